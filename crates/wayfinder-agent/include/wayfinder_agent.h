@@ -0,0 +1,34 @@
+/*
+ * Wayfinder debug agent for programs that embed Lua 5.4
+ *
+ * Link against the wayfinder_agent shared library. Each function returns 0
+ * on success and -1 on failure. Call them on the thread that runs the state.
+ */
+#ifndef WAYFINDER_AGENT_H
+#define WAYFINDER_AGENT_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct lua_State lua_State;
+
+/* Listens on the port in WAYFINDER_PORT, or 5678; WAYFINDER_WAIT=1 waits for the debugger */
+int wayfinder_attach(lua_State *L);
+
+/* Listens on `port` of 127.0.0.1; a nonzero `wait` waits for the debugger */
+int wayfinder_attach_port(lua_State *L, uint16_t port, int wait);
+
+/* Answers the debugger while no Lua code runs; call regularly, such as once a frame */
+int wayfinder_poll(lua_State *L);
+
+/* Disconnects the debugger and removes the debug hook; call before lua_close */
+int wayfinder_detach(lua_State *L);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
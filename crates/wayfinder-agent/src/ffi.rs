@@ -0,0 +1,119 @@
+//! C entry points for hosts that are not written in Rust
+//!
+//! The `cdylib` exports these, declared in `include/wayfinder_agent.h`. Each
+//! `lua_State` has at most one agent, kept here until `wayfinder_detach`.
+//! Functions return 0 on success and -1 on failure, after logging why.
+
+#![allow(non_snake_case)] // `L` as in the Lua C API
+
+use super::{Agent, AgentOptions};
+use libc::c_int;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use wayfinder_core::runtime::lua_ffi::LuaState;
+
+/// Environment variable naming the port `wayfinder_attach` listens on
+pub const PORT_VARIABLE: &str = "WAYFINDER_PORT";
+
+/// Environment variable that, set to 1, makes `wayfinder_attach` wait for the debugger
+pub const WAIT_VARIABLE: &str = "WAYFINDER_WAIT";
+
+/// Port `wayfinder_attach` listens on when `WAYFINDER_PORT` is not set
+const DEFAULT_PORT: u16 = 5678;
+
+/// Agents by the address of their state
+static AGENTS: Lazy<Mutex<HashMap<usize, Agent>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Starts an agent on the port in `WAYFINDER_PORT`, or 5678
+///
+/// With `WAYFINDER_WAIT=1` it returns only once a debugger has attached.
+///
+/// # Safety
+///
+/// `L` must be the main thread of a Lua 5.4 state that stays open until
+/// `wayfinder_detach`, run on the thread that calls these functions.
+#[no_mangle]
+pub unsafe extern "C" fn wayfinder_attach(L: LuaState) -> c_int {
+    let port = match std::env::var(PORT_VARIABLE) {
+        Ok(port) => match port.trim().parse() {
+            Ok(port) => port,
+            Err(_) => {
+                tracing::error!("{} is not a port: {}", PORT_VARIABLE, port);
+                return -1;
+            }
+        },
+        Err(_) => DEFAULT_PORT,
+    };
+    let wait = std::env::var(WAIT_VARIABLE).is_ok_and(|wait| wait.trim() == "1");
+    wayfinder_attach_port(L, port, c_int::from(wait))
+}
+
+/// Starts an agent listening on `port` of the loopback interface
+///
+/// A nonzero `wait` returns only once a debugger has attached.
+///
+/// # Safety
+///
+/// As for `wayfinder_attach`.
+#[no_mangle]
+pub unsafe extern "C" fn wayfinder_attach_port(L: LuaState, port: u16, wait: c_int) -> c_int {
+    if AGENTS.lock().unwrap_or_else(|e| e.into_inner()).contains_key(&(L as usize)) {
+        tracing::error!("A debug agent is already attached to this Lua state");
+        return -1;
+    }
+    let options = AgentOptions {
+        address: format!("127.0.0.1:{}", port),
+    };
+    let agent = match Agent::attach(L, &options) {
+        Ok(agent) => agent,
+        Err(e) => {
+            tracing::error!("Failed to start the debug agent on {}: {}", options.address, e);
+            return -1;
+        }
+    };
+    // Waited for before taking the lock, which other states' agents need
+    if wait != 0 {
+        agent.wait_for_debugger();
+    }
+    AGENTS.lock().unwrap_or_else(|e| e.into_inner()).insert(L as usize, agent);
+    0
+}
+
+/// Applies the debugger's requests while the host is not running Lua
+///
+/// Hosts call this regularly, such as once a frame.
+///
+/// # Safety
+///
+/// As for `wayfinder_attach`.
+#[no_mangle]
+pub unsafe extern "C" fn wayfinder_poll(L: LuaState) -> c_int {
+    let agents = AGENTS.lock().unwrap_or_else(|e| e.into_inner());
+    match agents.get(&(L as usize)) {
+        Some(agent) => {
+            agent.poll();
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Stops the agent of `L`, disconnecting the debugger and removing the debug hook
+///
+/// Call before closing the state.
+///
+/// # Safety
+///
+/// As for `wayfinder_attach`.
+#[no_mangle]
+pub unsafe extern "C" fn wayfinder_detach(L: LuaState) -> c_int {
+    let agent = AGENTS.lock().unwrap_or_else(|e| e.into_inner()).remove(&(L as usize));
+    match agent {
+        Some(agent) => {
+            drop(agent);
+            0
+        }
+        None => -1,
+    }
+}
@@ -0,0 +1,218 @@
+//! Debug agent for programs that embed Lua
+//!
+//! A game engine or other host that creates its own `lua_State` attaches an
+//! [`Agent`] to it, and Wayfinder debugs the host's Lua in-process:
+//! `wayfinder attach --port` connects to the agent like to any other. The
+//! agent listens on a thread of its own and serves one debugger at a time,
+//! taking the next once one detaches.
+//!
+//! Lua may only be touched on the thread that runs it, so the agent queues
+//! the debugger's requests for the host's thread. They are applied by the
+//! debug hook while Lua runs and by [`Agent::poll`], which hosts call
+//! regularly, such as once a frame, to get answers while no Lua code runs.
+//!
+//! C and C++ hosts use the functions in [`ffi`] through the `cdylib` and
+//! `include/wayfinder_agent.h`.
+
+pub mod ffi;
+
+use std::io;
+use std::net::SocketAddr;
+use std::thread::JoinHandle;
+use tokio::sync::oneshot;
+use wayfinder_core::agent::DebugAgent;
+use wayfinder_core::runtime::lua_ffi::LuaState;
+use wayfinder_core::runtime::lua_state::Lua;
+use wayfinder_core::runtime::puc_lua::{HostedProgram, PUCLuaRuntime};
+
+/// Address the agent listens on unless told otherwise
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:5678";
+
+/// How an agent is set up
+#[derive(Debug, Clone)]
+pub struct AgentOptions {
+    /// TCP address to listen on for debuggers; port 0 picks a free one
+    pub address: String,
+}
+
+impl Default for AgentOptions {
+    fn default() -> Self {
+        Self {
+            address: DEFAULT_ADDRESS.to_string(),
+        }
+    }
+}
+
+/// A debug agent serving a host's Lua state
+///
+/// Dropping the agent disconnects the debugger, if any, and removes the
+/// debug hook, leaving the state as the host set it up.
+pub struct Agent {
+    program: HostedProgram,
+    local_addr: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Agent {
+    /// Starts an agent for `state` listening on `options.address`
+    ///
+    /// Returns once the address is bound; the host goes on running and stops
+    /// at breakpoints once a debugger has attached.
+    ///
+    /// # Safety
+    ///
+    /// `state` must be the main thread of a Lua 5.4 state that outlives the
+    /// agent, and the host must run it and call the agent's methods on one
+    /// thread.
+    pub unsafe fn attach(state: LuaState, options: &AgentOptions) -> io::Result<Self> {
+        let listener = std::net::TcpListener::bind(&options.address)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        let (runtime, program) = PUCLuaRuntime::hosted(Lua::from_raw(state));
+        let (shutdown, stopped) = oneshot::channel();
+        let thread = std::thread::Builder::new()
+            .name("wayfinder-agent".to_string())
+            .spawn(move || serve(listener, runtime, stopped))?;
+
+        tracing::info!("Waiting for the debugger on {}", local_addr);
+        Ok(Self {
+            program,
+            local_addr,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    /// Address the agent listens on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Applies requests that came in while the host was not running Lua
+    pub fn poll(&self) {
+        self.program.poll();
+    }
+
+    /// Blocks until a debugger attaches and finishes configuring
+    ///
+    /// Hosts call this before running any Lua to stop at breakpoints in code
+    /// that runs only once, such as loading scripts.
+    pub fn wait_for_debugger(&self) {
+        self.program.wait_for_start();
+    }
+
+    /// Whether a debugger is attached and configured
+    pub fn is_debugging(&self) -> bool {
+        self.program.is_started()
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Serves debuggers one after another until the agent is dropped
+fn serve(listener: std::net::TcpListener, runtime: PUCLuaRuntime, mut stopped: oneshot::Receiver<()>) {
+    let executor = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(executor) => executor,
+        Err(e) => {
+            tracing::error!("Failed to start the debug agent: {}", e);
+            return;
+        }
+    };
+    executor.block_on(async move {
+        let listener = match tokio::net::TcpListener::from_std(listener) {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!("Failed to listen for the debugger: {}", e);
+                return;
+            }
+        };
+        let mut agent = DebugAgent::new(runtime);
+        loop {
+            tokio::select! {
+                _ = &mut stopped => return,
+                served = agent.accept_tcp(&listener) => {
+                    if let Err(e) = served {
+                        tracing::warn!("Debugger connection ended: {}", e);
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(all(test, feature = "static-lua"))]
+mod tests {
+    use super::*;
+    use wayfinder_core::runtime::remote::RemoteRuntime;
+    use wayfinder_core::runtime::{BreakpointType, DebugRuntime};
+
+    fn any_port() -> AgentOptions {
+        AgentOptions {
+            address: "127.0.0.1:0".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_a_debugger_stops_the_host_at_breakpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("engine.lua");
+        std::fs::write(&script, "local frame = 1\nframe = frame + 1\nreturn frame\n").unwrap();
+
+        let host = Lua::new();
+        let agent = unsafe { Agent::attach(host.state(), &any_port()) }.unwrap();
+        let address = agent.local_addr().to_string();
+        assert!(!agent.is_debugging());
+
+        let path = script.to_str().unwrap().to_string();
+        let host_thread = std::thread::spawn(move || {
+            let mut host = host;
+            agent.wait_for_debugger();
+            host.execute_file(&path).unwrap();
+            (host, agent)
+        });
+
+        let mut debugger = RemoteRuntime::connect_tcp(&address).await.unwrap();
+        let (sender, mut events) = wayfinder_core::dap::event_channel();
+        debugger.set_event_sender(sender);
+        debugger
+            .set_breakpoint(BreakpointType::Line {
+                source: script.to_str().unwrap().to_string(),
+                line: 2,
+            })
+            .await
+            .unwrap();
+        debugger.start_program(false).await.unwrap();
+
+        while events.recv().await.unwrap().event != "stopped" {}
+        let frames = debugger.stack_trace(None).await.unwrap();
+        assert_eq!(frames[0].line, 2);
+        debugger.continue_().await.unwrap();
+
+        let (host, agent) = tokio::task::spawn_blocking(move || host_thread.join().unwrap()).await.unwrap();
+        assert!(agent.is_debugging());
+        // The agent unhooks the state before the host closes it
+        drop(agent);
+        drop(host);
+    }
+
+    #[test]
+    fn test_the_address_must_be_free() {
+        let host = Lua::new();
+        let agent = unsafe { Agent::attach(host.state(), &any_port()) }.unwrap();
+        let taken = AgentOptions {
+            address: agent.local_addr().to_string(),
+        };
+        assert!(unsafe { Agent::attach(host.state(), &taken) }.is_err());
+    }
+}
@@ -0,0 +1,11 @@
+use std::env;
+
+fn main() {
+    // `embedded-lua` builds take Lua's functions from the executable's own
+    // symbols, which are only visible to dlsym when the executable exports them
+    let embedded = env::var("CARGO_FEATURE_EMBEDDED_LUA").is_ok();
+    let unix = env::var("CARGO_CFG_TARGET_FAMILY").as_deref() == Ok("unix");
+    if embedded && unix && env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("macos") {
+        println!("cargo:rustc-link-arg-bins=-Wl,--export-dynamic");
+    }
+}
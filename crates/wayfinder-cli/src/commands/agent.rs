@@ -0,0 +1,44 @@
+//! Agent command implementation
+//!
+//! Runs a script in-process under a debug agent listening on a TCP address,
+//! for a DAP server that asked its client to run the program in a terminal.
+//! The script waits for the server to connect and start it, so it keeps the
+//! terminal's stdio while the session goes through the agent.
+
+use std::path::Path;
+use wayfinder_core::agent::DebugAgent;
+use wayfinder_core::runtime::lua_paths::LuaPathConfig;
+
+use super::launch::program_launch;
+
+/// Agent configuration
+#[derive(Debug)]
+pub struct AgentConfig {
+    /// TCP address to listen on for the debugger
+    pub listen: String,
+    /// Runtime to use (e.g., "lua5.1")
+    pub runtime: Option<String>,
+    /// Script to run
+    pub script: String,
+    /// Arguments the script finds in `arg`
+    pub args: Vec<String>,
+    /// `package.path` and `package.cpath` templates and source roots
+    pub lua_paths: LuaPathConfig,
+}
+
+/// Loads the script and serves it to one debugger connection
+pub async fn run_agent(config: AgentConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let mut launch = program_launch(Path::new(&config.script), &config.lua_paths)?;
+    launch.args = config.args;
+    let mut runtime = match &config.runtime {
+        Some(runtime) => crate::create_puc_lua_runtime_for(runtime)?,
+        None => crate::create_puc_lua_runtime(None),
+    };
+    runtime
+        .load_launch(&launch)
+        .map_err(|e| format!("Failed to load {}: {}", config.script, e))?;
+
+    tracing::info!("Waiting for the debugger on {}", config.listen);
+    DebugAgent::new(runtime).listen_tcp(&config.listen).await?;
+    Ok(())
+}
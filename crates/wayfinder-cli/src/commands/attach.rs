@@ -1,14 +1,21 @@
 //! Attach command implementation
 //!
 //! This module handles attaching to running Lua processes for debugging.
+//! The target embeds a debug agent listening on a TCP port or, when attaching
+//! by PID, on a per-process Unix socket. The DAP session is served on stdio
+//! against a runtime that proxies every call to that agent.
 
-use std::net::SocketAddr;
+use std::future::Future;
+use std::path::PathBuf;
 use std::time::Duration;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::TcpStream;
-use serde_json::Value as JsonValue;
-use wayfinder_core::runtime::puc_lua::PUCLuaRuntime;
+use wayfinder_core::dap::transport::DapTransport;
+use wayfinder_core::runtime::remote::RemoteRuntime;
+use wayfinder_core::runtime::RuntimeError;
 use wayfinder_core::session::DapServer;
+use crate::diagnostics::Diagnostics;
+
+/// Delay between connection attempts while waiting for the target
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(200);
 
 /// Attach configuration
 #[derive(Debug)]
@@ -17,161 +24,102 @@ pub struct AttachConfig {
     pub port: Option<u16>,
     /// Process ID to attach to
     pub pid: Option<u32>,
+    /// Time allowed for the target to accept the connection
+    pub timeout: Duration,
+    /// JSONL file to record every DAP message to
+    pub trace_dap: Option<PathBuf>,
 }
 
 /// Attach to a running Lua process
 pub async fn attach_to_process(config: AttachConfig) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(port) = config.port {
-        // Connect via TCP
-        println!("Attaching to process on port {}", port);
-        attach_via_tcp(port).await?;
+    let runtime = if let Some(port) = config.port {
+        tracing::info!("Attaching to process on port {}", port);
+        attach_via_tcp(port, config.timeout).await?
     } else if let Some(pid) = config.pid {
-        // Attach via PID
-        println!("Attaching to process with PID {}", pid);
-        attach_via_pid(pid).await?;
+        tracing::info!("Attaching to process with PID {}", pid);
+        attach_via_pid(pid, config.timeout).await?
     } else {
         return Err("Either port or PID must be specified for attach".into());
-    }
-
-    Ok(())
-}
-
-/// Attach to a process via TCP connection
-async fn attach_via_tcp(port: u16) -> Result<(), Box<dyn std::error::Error>> {
-    let address: SocketAddr = format!("127.0.0.1:{}", port).parse()?;
-
-    eprintln!("Connecting to {}...", address);
-
-    // Attempt to connect with a timeout
-    let stream = match tokio::time::timeout(Duration::from_secs(10), TcpStream::connect(&address)).await {
-        Ok(Ok(stream)) => stream,
-        Ok(Err(e)) => {
-            return Err(format!("Failed to connect to {}: {}", address, e).into());
-        }
-        Err(_) => {
-            return Err(format!("Connection timeout after 10 seconds").into());
-        }
     };
 
-    eprintln!("✓ Connected to process on port {}", port);
-    eprintln!("Setting up DAP session...");
+    let version = wayfinder_core::runtime::DebugRuntime::version(&runtime).await;
+    tracing::info!("Connected to {:?} {:?} runtime", version.runtime, version.version);
 
-    // Create DAP server for this attachment
-    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
-
-    // Set up the runtime
-    let runtime = crate::create_puc_lua_runtime(None);
+    let mut server: DapServer<RemoteRuntime> = DapServer::new();
     server.set_runtime(runtime);
+    // The target keeps running after a shutdown, as after a disconnect
+    crate::shutdown::coordinator().register(&server.shutdown_trigger());
 
-    // Split the stream for reading and writing
-    let (read_half, write_half) = stream.into_split();
-    let mut reader = BufReader::new(read_half);
-    let mut writer = write_half;
+    let mut transport = DapTransport::stdio();
+    crate::commands::dap::record_trace(&mut transport, config.trace_dap.as_deref())?;
 
-    eprintln!("Starting DAP message loop...");
+    tracing::debug!("Starting DAP message loop");
+    server.run_event_loop(&mut transport).await?;
 
-    // DAP message loop
-    loop {
-        // Read the message from the TCP stream
-        match read_dap_message_tcp(&mut reader).await {
-            Ok(message) => {
-                eprintln!("Received DAP message: {}", message.get("method").and_then(|m| m.as_str()).unwrap_or("unknown"));
-
-                // Extract method, params, and id
-                let method = message.get("method").and_then(|m| m.as_str()).unwrap_or("");
-                let params = message.get("params").unwrap_or(&JsonValue::Null);
-                let id = message.get("id").and_then(|i| i.as_u64()).unwrap_or(0);
-
-                // Handle the request
-                if let Some(response) = server.handle_request(method, params, id).await {
-                    // Send the response
-                    write_dap_message_tcp(&mut writer, &response).await?;
-                }
-
-                // Check if we should exit
-                if method == "disconnect" || method == "terminate" {
-                    eprintln!("Received disconnect/terminate");
-                    break;
-                }
-            }
-            Err(e) => {
-                eprintln!("Error reading DAP message: {}", e);
-                break;
-            }
-        }
-    }
-
-    eprintln!("Connection closed");
+    tracing::info!("Connection closed");
     Ok(())
 }
 
-/// Read a DAP message from TCP stream using Content-Length headers
-async fn read_dap_message_tcp<R: tokio::io::AsyncBufRead + Unpin>(reader: &mut R) -> Result<JsonValue, Box<dyn std::error::Error>> {
-    let mut content_length: Option<usize> = None;
-
-    // Read headers
-    loop {
-        let mut header = String::new();
-        reader.read_line(&mut header).await?;
-        let header = header.trim();
-
-        // Empty line marks end of headers
-        if header.is_empty() {
-            break;
-        }
+/// Connects to an agent listening on a local TCP port
+async fn attach_via_tcp(port: u16, timeout: Duration) -> Result<RemoteRuntime, Box<dyn std::error::Error>> {
+    let address = format!("127.0.0.1:{}", port);
+    tracing::debug!("Connecting to {}", address);
 
-        // Parse Content-Length header
-        if let Some(length_str) = header.strip_prefix("Content-Length: ") {
-            content_length = Some(length_str.trim().parse()?);
-        }
-    }
-
-    // Read the message body
-    let content_length = content_length.ok_or("Missing Content-Length header")?;
-    let mut body = vec![0u8; content_length];
-    tokio::io::AsyncReadExt::read_exact(reader, &mut body).await?;
-
-    // Parse JSON
-    let message: JsonValue = serde_json::from_slice(&body)?;
-    Ok(message)
+    connect_with_retry(&address, timeout, || RemoteRuntime::connect_tcp(&address)).await
 }
 
-/// Write a DAP message to TCP stream with Content-Length header
-async fn write_dap_message_tcp<W: tokio::io::AsyncWrite + Unpin>(writer: &mut W, message: &JsonValue) -> Result<(), Box<dyn std::error::Error>> {
-    let body = serde_json::to_string(message)?;
-    let header = format!("Content-Length: {}\r\n\r\n", body.len());
+/// Connects to the agent socket of a local process
+#[cfg(unix)]
+async fn attach_via_pid(pid: u32, timeout: Duration) -> Result<RemoteRuntime, Box<dyn std::error::Error>> {
+    validate_pid(pid)?;
+    tracing::debug!("Process with PID {} exists", pid);
 
-    writer.write_all(header.as_bytes()).await?;
-    writer.write_all(body.as_bytes()).await?;
-    writer.flush().await?;
+    let path = wayfinder_core::agent::socket_path_for_pid(pid);
+    let label = path.display().to_string();
+    tracing::debug!("Connecting to {}", label);
 
-    Ok(())
+    connect_with_retry(&label, timeout, || RemoteRuntime::connect_unix(&path)).await
 }
 
-/// Attach to a process via PID
-async fn attach_via_pid(pid: u32) -> Result<(), Box<dyn std::error::Error>> {
-    // Validate the process exists
-    validate_pid(pid)?;
+/// Connects to the agent socket of a local process
+#[cfg(not(unix))]
+async fn attach_via_pid(_pid: u32, _timeout: Duration) -> Result<RemoteRuntime, Box<dyn std::error::Error>> {
+    Err("Attaching by PID requires Unix domain sockets; use --port instead".into())
+}
 
-    println!("✓ Process with PID {} exists", pid);
+/// Connects to the target, retrying until the timeout elapses
+///
+/// The target may still be starting its agent, so refused connections are
+/// retried. Every failure is recorded and reported if the deadline passes.
+pub(crate) async fn connect_with_retry<F, Fut>(
+    target: &str,
+    timeout: Duration,
+    mut connect: F,
+) -> Result<RemoteRuntime, Box<dyn std::error::Error>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<RemoteRuntime, RuntimeError>>,
+{
+    let mut diagnostics = Diagnostics::new();
+    let deadline = tokio::time::Instant::now() + timeout;
 
-    // In a real implementation, we would:
-    // 1. Use ptrace (Unix) or DebugActiveProcess (Windows) to attach
-    // 2. Inject the Lua debug library into the process
-    // 3. Establish communication with the injected debug adapter
-    // 4. Run the DAP event loop
+    loop {
+        match tokio::time::timeout_at(deadline, connect()).await {
+            Ok(Ok(runtime)) => return Ok(runtime),
+            Ok(Err(e)) => diagnostics.record_socket_error(format!("{}: {}", target, e)),
+            Err(_) => {
+                diagnostics.record_socket_error(format!("{}: connection attempt timed out", target));
+                break;
+            }
+        }
 
-    println!("\n--- Attached to Process ---");
-    println!("Note: PID-based attachment requires platform-specific");
-    println!("debugging APIs and Lua runtime injection.");
-    println!("This is a placeholder implementation.");
-    println!("\nIn a full implementation, this would:");
-    println!("  1. Attach to the process using OS debug APIs");
-    println!("  2. Inject Lua debugging hooks");
-    println!("  3. Establish DAP communication channel");
-    println!("  4. Start processing debug commands");
+        if tokio::time::Instant::now() + CONNECT_RETRY_INTERVAL >= deadline {
+            break;
+        }
+        tokio::time::sleep(CONNECT_RETRY_INTERVAL).await;
+    }
 
-    Ok(())
+    Err(diagnostics.summary("Attach", timeout).into())
 }
 
 /// Validate that a process with the given PID exists
@@ -180,7 +128,7 @@ fn validate_pid(pid: u32) -> Result<(), Box<dyn std::error::Error>> {
     // On Unix systems, we could check /proc/{pid}
     // On Windows, we could use OpenProcess
     // For cross-platform compatibility, we'll just return Ok for now
-    
+
     #[cfg(unix)]
     {
         let path = format!("/proc/{}", pid);
@@ -190,14 +138,14 @@ fn validate_pid(pid: u32) -> Result<(), Box<dyn std::error::Error>> {
             Err(format!("Process with PID {} not found", pid).into())
         }
     }
-    
+
     #[cfg(windows)]
     {
         // Windows implementation would use OpenProcess
         // For now, we'll just return Ok
         Ok(())
     }
-    
+
     #[cfg(not(any(unix, windows)))]
     {
         // For other platforms, we'll just return Ok
@@ -214,17 +162,32 @@ mod tests {
         let config_with_port = AttachConfig {
             port: Some(12345),
             pid: None,
+            timeout: Duration::from_secs(10),
+            trace_dap: None,
         };
-        
+
         assert_eq!(config_with_port.port, Some(12345));
         assert_eq!(config_with_port.pid, None);
-        
+
         let config_with_pid = AttachConfig {
             port: None,
             pid: Some(1234),
+            timeout: Duration::from_secs(10),
+            trace_dap: None,
         };
-        
+
         assert_eq!(config_with_pid.port, None);
         assert_eq!(config_with_pid.pid, Some(1234));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_attach_reports_refused_connections() {
+        // Nothing listens on the port once the listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let error = attach_via_tcp(port, Duration::from_millis(300)).await.err().unwrap();
+        assert!(error.to_string().contains("Attach did not complete"));
+    }
+}
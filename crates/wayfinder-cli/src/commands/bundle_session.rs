@@ -0,0 +1,92 @@
+//! Bundle-session command implementation
+//!
+//! Asks a running DAP server to write its session artifacts to a zip archive
+//! that can be attached to a bug report.
+
+use serde_json::{json, Value as JsonValue};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use wayfinder_core::dap::transport::DapTransport;
+use wayfinder_core::dap::Response;
+
+/// Sequence number of the request; the response carries it as its id
+const REQUEST_SEQ: u64 = 1;
+
+/// Bundle-session configuration
+#[derive(Debug)]
+pub struct BundleSessionConfig {
+    /// Archive to write, resolved against the current directory
+    pub out: PathBuf,
+    pub host: String,
+    pub port: u16,
+    /// Blank out source code in the archive
+    pub redact_sources: bool,
+}
+
+/// Builds the `wayfinder/bundleSession` request
+pub fn bundle_request(out: &Path, redact_sources: bool) -> JsonValue {
+    json!({
+        "seq": REQUEST_SEQ,
+        "type": "request",
+        "command": "wayfinder/bundleSession",
+        "arguments": {
+            "path": out.display().to_string(),
+            "redactSources": redact_sources,
+        }
+    })
+}
+
+/// Sends the request and waits for the server to write the archive
+pub async fn bundle_session(config: BundleSessionConfig) -> Result<(), Box<dyn std::error::Error>> {
+    // The server writes the file, so it needs a path that does not depend on its working directory
+    let out = std::env::current_dir()?.join(&config.out);
+    let address = format!("{}:{}", config.host, config.port);
+
+    let stream = match tokio::time::timeout(Duration::from_secs(5), TcpStream::connect(&address)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return Err(format!("Failed to connect to DAP server at {}: {}", address, e).into()),
+        Err(_) => return Err("Connection timeout - is the DAP server running?".into()),
+    };
+    let mut transport = DapTransport::tcp(stream);
+    transport.write_message(&bundle_request(&out, config.redact_sources)).await?;
+
+    let response = tokio::time::timeout(Duration::from_secs(30), async {
+        // Events may arrive before the response
+        loop {
+            match transport.read_message().await? {
+                Some(message) => match Response::from_json(&message) {
+                    Some(response) if response.request_seq == REQUEST_SEQ => {
+                        return Ok::<_, std::io::Error>(Some(response))
+                    }
+                    _ => continue,
+                },
+                None => return Ok(None),
+            }
+        }
+    })
+    .await
+    .map_err(|_| "Timeout waiting for response from DAP server")??
+    .ok_or("DAP server closed the connection")?;
+
+    let result = response.result.map_err(|error| error.message)?;
+    let files: Vec<&str> = result["files"]
+        .as_array()
+        .map(|files| files.iter().filter_map(|f| f.as_str()).collect())
+        .unwrap_or_default();
+    println!("Wrote {} ({})", out.display(), files.join(", "));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_request() {
+        let request = bundle_request(Path::new("/tmp/session.zip"), true);
+        assert_eq!(request["command"], "wayfinder/bundleSession");
+        assert_eq!(request["arguments"]["path"], "/tmp/session.zip");
+        assert_eq!(request["arguments"]["redactSources"], true);
+    }
+}
@@ -3,11 +3,21 @@
 //! This module handles running Wayfinder as a DAP (Debug Adapter Protocol) server.
 
 use std::net::TcpListener;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncWrite};
 use tokio::net::TcpStream;
-use serde_json::Value as JsonValue;
-use wayfinder_core::runtime::puc_lua::PUCLuaRuntime;
-use wayfinder_core::session::DapServer;
+use tokio::sync::Notify;
+use wayfinder_core::config::DebuggerSettings;
+use wayfinder_core::dap::transport::DapTransport;
+use wayfinder_core::hot_reload::WatcherConfig;
+use wayfinder_core::runtime::remote::RemoteRuntime;
+use wayfinder_core::runtime::DebugRuntime;
+use wayfinder_core::session::launch_arguments::LaunchArguments;
+use wayfinder_core::session::rewrite_rules::{RewriteRule, RewriteRules};
+use wayfinder_core::session::terminal::TerminalLauncher;
+use wayfinder_core::session::trace::TraceRecorder;
+use wayfinder_core::session::{DapServer, OnShutdown};
 
 /// DAP server configuration
 #[derive(Debug)]
@@ -16,23 +26,158 @@ pub struct DapConfig {
     pub port: Option<u16>,
     /// Whether to support multiple clients
     pub multi_client: bool,
+    /// Rewrites of DAP messages from the config file
+    pub rewrite_rules: Vec<RewriteRule>,
+    /// JSONL file to record every DAP message to
+    pub trace_dap: Option<PathBuf>,
+    /// Launch arguments from the config file, for launch requests that leave them unset
+    pub launch_defaults: LaunchArguments,
+    /// Files to hot reload into debugged programs when they change
+    pub hot_reload: Option<WatcherConfig>,
+    /// Debugger settings from the config file, applied to every session
+    pub settings: DebuggerSettings,
+    /// Config file the settings are read from again on SIGHUP or a `wayfinder/configure` reload
+    pub settings_file: Option<PathBuf>,
+    /// Whether `--log-level` set the log level, which reloads then leave alone
+    pub log_level_from_flag: bool,
+    /// Whether launched programs are killed or left running when the server is interrupted
+    pub on_shutdown: OnShutdown,
 }
 
 /// Run as a DAP server
 pub async fn run_dap_server(config: DapConfig) -> Result<(), Box<dyn std::error::Error>> {
     if let Some(port) = config.port {
         // Run in TCP server mode
-        run_tcp_server(port, config.multi_client).await
+        run_tcp_server(port, &config).await
     } else {
         // Run in stdio mode
-        run_stdio_server().await
+        run_stdio_server(&config).await
     }
 }
 
+/// Runtime of a server, in-process or the agent of a program run in the client's terminal
+type ServerRuntime = Box<dyn DebugRuntime>;
+
+/// Creates a server whose launch requests load their program in the runtime they ask for
+fn create_server(config: &DapConfig) -> DapServer<ServerRuntime> {
+    let mut server: DapServer<ServerRuntime> = DapServer::new();
+    server.set_runtime(Box::new(crate::create_puc_lua_runtime(None)));
+    server.set_runtime_factory(|arguments| {
+        let runtime = match (&arguments.lua_library_path, &arguments.runtime) {
+            (Some(library), runtime) => crate::create_puc_lua_runtime_from(Path::new(library), runtime.as_deref())?,
+            (None, Some(runtime)) => crate::create_puc_lua_runtime_for(runtime)?,
+            (None, None) => crate::create_puc_lua_runtime(None),
+        };
+        Ok(Box::new(runtime) as ServerRuntime)
+    });
+    server.set_terminal_launcher(AgentLauncher);
+    server.set_launch_defaults(config.launch_defaults.clone());
+    server.set_rewrite_rules(RewriteRules::new(config.rewrite_rules.clone()));
+    if let Some(hot_reload) = &config.hot_reload {
+        server.watch_for_hot_reload(hot_reload.clone());
+    }
+    server.set_log_level_handler(crate::logging::set_level);
+    server.set_on_shutdown(config.on_shutdown);
+    crate::shutdown::coordinator().register(&server.shutdown_trigger());
+    if let Err(e) = server.configure(&config.settings) {
+        tracing::warn!("Ignoring the debugger settings of the config file: {}", e);
+    }
+    if let Some(path) = config.settings_file.clone() {
+        let log_level_from_flag = config.log_level_from_flag;
+        server.set_settings_loader(move || {
+            let mut settings = crate::Config::load(&path)
+                .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?
+                .debugger_settings();
+            if log_level_from_flag {
+                settings.log_level = None;
+            }
+            Ok(settings)
+        });
+        reload_on_sighup(server.settings_reload_trigger());
+    }
+    server
+}
+
+/// Has the server reload its settings whenever the process receives SIGHUP
+#[cfg(unix)]
+fn reload_on_sighup(trigger: Arc<Notify>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::warn!("Cannot reload settings on SIGHUP: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            tracing::info!("Received SIGHUP, reloading the debugger settings");
+            trigger.notify_one();
+        }
+    });
+}
+
+/// Windows has no SIGHUP; settings are reloaded with a `wayfinder/configure` request there
+#[cfg(not(unix))]
+fn reload_on_sighup(_trigger: Arc<Notify>) {}
+
+/// Runs launched programs under `wayfinder agent` and attaches to it over TCP
+struct AgentLauncher;
+
+#[async_trait::async_trait]
+impl TerminalLauncher<ServerRuntime> for AgentLauncher {
+    fn command(&self, address: &str, arguments: &LaunchArguments) -> Result<Vec<String>, String> {
+        let program = arguments.program.clone().ok_or("no program to run")?;
+        let executable = std::env::current_exe().map_err(|e| format!("cannot find wayfinder itself: {}", e))?;
+        let mut command = vec![
+            executable.display().to_string(),
+            "agent".to_string(),
+            "--listen".to_string(),
+            address.to_string(),
+        ];
+        let options = [
+            ("--runtime", arguments.runtime.as_ref()),
+            ("--lua-library-path", arguments.lua_library_path.as_ref()),
+            ("--lua-path", arguments.lua_path.as_ref()),
+            ("--lua-cpath", arguments.lua_cpath.as_ref()),
+        ];
+        for (option, value) in options {
+            if let Some(value) = value {
+                command.extend([option.to_string(), value.clone()]);
+            }
+        }
+        for root in &arguments.source_roots {
+            command.extend(["--source-root".to_string(), root.clone()]);
+        }
+        command.extend(["--".to_string(), program]);
+        command.extend(arguments.args.iter().cloned());
+        Ok(command)
+    }
+
+    async fn connect(&self, address: &str) -> Result<ServerRuntime, String> {
+        let runtime = RemoteRuntime::connect_tcp(address).await.map_err(|e| e.to_string())?;
+        Ok(Box::new(runtime))
+    }
+}
+
+/// Records the messages crossing `transport` to `path`, if a trace was asked for
+pub fn record_trace<R, W>(transport: &mut DapTransport<R, W>, path: Option<&Path>) -> std::io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    if let Some(path) = path {
+        transport.set_recorder(TraceRecorder::create(path)?);
+        tracing::info!("Recording DAP messages to {}", path.display());
+    }
+    Ok(())
+}
+
 /// Run DAP server in TCP mode
-async fn run_tcp_server(port: u16, _multi_client: bool) -> Result<(), Box<dyn std::error::Error>> {
+async fn run_tcp_server(port: u16, config: &DapConfig) -> Result<(), Box<dyn std::error::Error>> {
     let address = format!("127.0.0.1:{}", port);
-    println!("Starting DAP server on {}", address);
+    tracing::info!("Starting DAP server on {}", address);
     
     // Create TCP listener
     let listener = TcpListener::bind(&address)?;
@@ -41,17 +186,17 @@ async fn run_tcp_server(port: u16, _multi_client: bool) -> Result<(), Box<dyn st
     // Convert to tokio listener
     let listener = tokio::net::TcpListener::from_std(listener)?;
     
-    println!("DAP server listening on {}", address);
+    tracing::info!("DAP server listening on {}", address);
     
     // Accept connections
     loop {
         match listener.accept().await {
             Ok((stream, addr)) => {
-                println!("Client connected from {}", addr);
+                tracing::info!("Client connected from {}", addr);
                 
                 // Handle the connection
-                if let Err(e) = handle_tcp_connection(stream).await {
-                    eprintln!("Error handling connection: {}", e);
+                if let Err(e) = handle_tcp_connection(stream, config).await {
+                    tracing::error!("Error handling connection: {}", e);
                 }
                 
                 // For now, we'll only handle one client
@@ -59,7 +204,7 @@ async fn run_tcp_server(port: u16, _multi_client: bool) -> Result<(), Box<dyn st
                 break;
             }
             Err(e) => {
-                eprintln!("Error accepting connection: {}", e);
+                tracing::error!("Error accepting connection: {}", e);
             }
         }
     }
@@ -68,196 +213,31 @@ async fn run_tcp_server(port: u16, _multi_client: bool) -> Result<(), Box<dyn st
 }
 
 /// Handle a TCP connection
-async fn handle_tcp_connection(stream: TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+async fn handle_tcp_connection(stream: TcpStream, config: &DapConfig) -> Result<(), Box<dyn std::error::Error>> {
     let peer_addr = stream.peer_addr()?;
-    eprintln!("Handling connection from {}", peer_addr);
-
-    // Create DAP server
-    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
-
-    // Set up the runtime
-    let runtime = crate::create_puc_lua_runtime(None);
-    server.set_runtime(runtime);
-
-    // Split the stream for reading and writing
-    let (read_half, write_half) = stream.into_split();
-    let mut reader = BufReader::new(read_half);
-    let mut writer = write_half;
-
-    eprintln!("Starting DAP event loop for {}", peer_addr);
-
-    // DAP message loop
-    loop {
-        // Read the message from the TCP stream
-        match read_dap_message_tcp(&mut reader).await {
-            Ok(message) => {
-                eprintln!("Received DAP message: {}", message.get("method").and_then(|m| m.as_str()).unwrap_or("unknown"));
-
-                // Extract method, params, and id
-                let method = message.get("method").and_then(|m| m.as_str()).unwrap_or("");
-                let params = message.get("params").unwrap_or(&JsonValue::Null);
-                let id = message.get("id").and_then(|i| i.as_u64()).unwrap_or(0);
-
-                // Handle the request
-                if let Some(response) = server.handle_request(method, params, id).await {
-                    // Send the response
-                    write_dap_message_tcp(&mut writer, &response).await?;
-                }
-
-                // Check if we should exit
-                if method == "disconnect" || method == "terminate" {
-                    eprintln!("Received disconnect/terminate from {}", peer_addr);
-                    break;
-                }
-            }
-            Err(e) => {
-                eprintln!("Error reading DAP message from {}: {}", peer_addr, e);
-                break;
-            }
-        }
-    }
-
-    eprintln!("Connection from {} closed", peer_addr);
-    Ok(())
-}
-
-/// Read a DAP message from TCP stream using Content-Length headers
-async fn read_dap_message_tcp<R: tokio::io::AsyncBufRead + Unpin>(reader: &mut R) -> Result<JsonValue, Box<dyn std::error::Error>> {
-    let mut content_length: Option<usize> = None;
-
-    // Read headers
-    loop {
-        let mut header = String::new();
-        reader.read_line(&mut header).await?;
-        let header = header.trim();
-
-        // Empty line marks end of headers
-        if header.is_empty() {
-            break;
-        }
-
-        // Parse Content-Length header
-        if let Some(length_str) = header.strip_prefix("Content-Length: ") {
-            content_length = Some(length_str.trim().parse()?);
-        }
-    }
+    tracing::debug!("Handling connection from {}", peer_addr);
 
-    // Read the message body
-    let content_length = content_length.ok_or("Missing Content-Length header")?;
-    let mut body = vec![0u8; content_length];
-    tokio::io::AsyncReadExt::read_exact(reader, &mut body).await?;
+    let mut server = create_server(config);
+    let mut transport = DapTransport::tcp(stream);
+    record_trace(&mut transport, config.trace_dap.as_deref())?;
 
-    // Parse JSON
-    let message: JsonValue = serde_json::from_slice(&body)?;
-    Ok(message)
-}
-
-/// Write a DAP message to TCP stream with Content-Length header
-async fn write_dap_message_tcp<W: tokio::io::AsyncWrite + Unpin>(writer: &mut W, message: &JsonValue) -> Result<(), Box<dyn std::error::Error>> {
-    let body = serde_json::to_string(message)?;
-    let header = format!("Content-Length: {}\r\n\r\n", body.len());
-
-    writer.write_all(header.as_bytes()).await?;
-    writer.write_all(body.as_bytes()).await?;
-    writer.flush().await?;
+    tracing::debug!("Starting DAP event loop for {}", peer_addr);
+    server.run_event_loop(&mut transport).await?;
 
+    tracing::info!("Connection from {} closed", peer_addr);
     Ok(())
 }
 
 /// Run DAP server in stdio mode
-async fn run_stdio_server() -> Result<(), Box<dyn std::error::Error>> {
-    eprintln!("Starting DAP server in stdio mode");
-    eprintln!("Reading from stdin, writing to stdout");
-    eprintln!("Waiting for DAP initialize request...");
-
-    // Create DAP server
-    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
-
-    // Set up the runtime
-    let runtime = crate::create_puc_lua_runtime(None);
-    server.set_runtime(runtime);
-
-    // Set up stdin/stdout for DAP communication
-    let stdin = tokio::io::stdin();
-    let mut stdout = tokio::io::stdout();
-    let mut reader = BufReader::new(stdin);
-
-    // DAP message loop
-    loop {
-        // Read the message from stdin
-        match read_dap_message(&mut reader).await {
-            Ok(message) => {
-                eprintln!("Received DAP message: {}", message.get("method").and_then(|m| m.as_str()).unwrap_or("unknown"));
-
-                // Extract method, params, and id
-                let method = message.get("method").and_then(|m| m.as_str()).unwrap_or("");
-                let params = message.get("params").unwrap_or(&JsonValue::Null);
-                let id = message.get("id").and_then(|i| i.as_u64()).unwrap_or(0);
-
-                // Handle the request
-                if let Some(response) = server.handle_request(method, params, id).await {
-                    // Send the response
-                    write_dap_message(&mut stdout, &response).await?;
-                }
-
-                // Check if we should exit
-                if method == "disconnect" || method == "terminate" {
-                    eprintln!("Received disconnect/terminate, shutting down");
-                    break;
-                }
-            }
-            Err(e) => {
-                eprintln!("Error reading DAP message: {}", e);
-                // On EOF or error, exit the loop
-                break;
-            }
-        }
-    }
-
-    eprintln!("DAP server shutting down");
-    Ok(())
-}
-
-/// Read a DAP message from stdin using Content-Length headers
-async fn read_dap_message(reader: &mut BufReader<tokio::io::Stdin>) -> Result<JsonValue, Box<dyn std::error::Error>> {
-    let mut content_length: Option<usize> = None;
+async fn run_stdio_server(config: &DapConfig) -> Result<(), Box<dyn std::error::Error>> {
+    tracing::info!("Starting DAP server in stdio mode, waiting for the initialize request");
 
-    // Read headers
-    loop {
-        let mut header = String::new();
-        reader.read_line(&mut header).await?;
-        let header = header.trim();
-
-        // Empty line marks end of headers
-        if header.is_empty() {
-            break;
-        }
-
-        // Parse Content-Length header
-        if let Some(length_str) = header.strip_prefix("Content-Length: ") {
-            content_length = Some(length_str.trim().parse()?);
-        }
-    }
-
-    // Read the message body
-    let content_length = content_length.ok_or("Missing Content-Length header")?;
-    let mut body = vec![0u8; content_length];
-    tokio::io::AsyncReadExt::read_exact(reader, &mut body).await?;
-
-    // Parse JSON
-    let message: JsonValue = serde_json::from_slice(&body)?;
-    Ok(message)
-}
-
-/// Write a DAP message to stdout with Content-Length header
-async fn write_dap_message(writer: &mut tokio::io::Stdout, message: &JsonValue) -> Result<(), Box<dyn std::error::Error>> {
-    let body = serde_json::to_string(message)?;
-    let header = format!("Content-Length: {}\r\n\r\n", body.len());
-
-    writer.write_all(header.as_bytes()).await?;
-    writer.write_all(body.as_bytes()).await?;
-    writer.flush().await?;
+    let mut server = create_server(config);
+    let mut transport = DapTransport::stdio();
+    record_trace(&mut transport, config.trace_dap.as_deref())?;
+    server.run_event_loop(&mut transport).await?;
 
+    tracing::info!("DAP server shutting down");
     Ok(())
 }
 
@@ -270,6 +250,14 @@ mod tests {
         let tcp_config = DapConfig {
             port: Some(12345),
             multi_client: true,
+            rewrite_rules: Vec::new(),
+            trace_dap: None,
+            launch_defaults: LaunchArguments::default(),
+            hot_reload: None,
+            settings: DebuggerSettings::default(),
+            settings_file: None,
+            log_level_from_flag: false,
+            on_shutdown: OnShutdown::Terminate,
         };
         
         assert_eq!(tcp_config.port, Some(12345));
@@ -278,9 +266,48 @@ mod tests {
         let stdio_config = DapConfig {
             port: None,
             multi_client: false,
+            rewrite_rules: Vec::new(),
+            trace_dap: Some(PathBuf::from("session.jsonl")),
+            launch_defaults: LaunchArguments::default(),
+            hot_reload: None,
+            settings: DebuggerSettings::default(),
+            settings_file: None,
+            log_level_from_flag: false,
+            on_shutdown: OnShutdown::Detach,
         };
         
         assert_eq!(stdio_config.port, None);
         assert_eq!(stdio_config.multi_client, false);
     }
+
+    #[cfg(feature = "hot-reload")]
+    #[tokio::test]
+    async fn test_server_hot_reloads_modules() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.lua");
+        let counter = dir.path().join("counter.lua");
+        std::fs::write(&counter, "return { hits = 0 }\n").unwrap();
+        std::fs::write(&main, "counter = require('counter')\n").unwrap();
+
+        let config = DapConfig {
+            port: None,
+            multi_client: false,
+            rewrite_rules: Vec::new(),
+            trace_dap: None,
+            launch_defaults: LaunchArguments::default(),
+            hot_reload: None,
+            settings: DebuggerSettings::default(),
+            settings_file: None,
+            log_level_from_flag: false,
+            on_shutdown: OnShutdown::Terminate,
+        };
+        let mut server = create_server(&config);
+        let launch = serde_json::json!({ "program": main.display().to_string() });
+        server.handle_request("launch", &launch, 1).await.unwrap();
+        server.handle_request("configurationDone", &serde_json::json!({}), 2).await.unwrap();
+
+        let reload = serde_json::json!({ "module": "counter" });
+        let response = server.handle_request("wayfinder/hotReload", &reload, 3).await.unwrap();
+        assert_eq!(response["result"]["success"], true, "{}", response);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,380 @@
+//! Debug command implementation
+//!
+//! A terminal debugger for those without a DAP client: the script is loaded
+//! in-process and a gdb-style prompt drives its `DebugSession` directly. See
+//! [`crate::repl`] for the commands.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use wayfinder_core::dap::{event_channel, Event, EventReceiver};
+use wayfinder_core::debug::side_effects::EvaluationContext;
+use wayfinder_core::runtime::lua_paths::LuaPathConfig;
+use wayfinder_core::runtime::{DebugRuntime, Frame, StepMode, VariablesPage};
+use wayfinder_core::session::DebugSession;
+
+use super::launch::{program_launch, resolve_script, resolve_source_path};
+use crate::repl::{self, BreakTarget, ReplCommand};
+
+/// Debug configuration
+#[derive(Debug)]
+pub struct DebugConfig {
+    /// Runtime to use (e.g., "lua5.1")
+    pub runtime: Option<String>,
+    /// Current working directory
+    pub cwd: Option<String>,
+    /// Environment variables
+    pub env: Option<HashMap<String, String>>,
+    /// Script to debug
+    pub script: String,
+    /// Stop before the first line instead of waiting for `run`
+    pub stop_on_entry: bool,
+    /// `package.path` and `package.cpath` templates and source roots
+    pub lua_paths: LuaPathConfig,
+}
+
+/// Debugs a script from the terminal until the user quits or input ends
+pub async fn debug_script(config: DebugConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let script = resolve_script(&config.script, config.cwd.as_deref())?;
+
+    if let Some(cwd) = &config.cwd {
+        tracing::debug!("Working directory: {}", cwd);
+        std::env::set_current_dir(cwd)?;
+    }
+    // The script runs in this process, so its environment is ours
+    if let Some(env_vars) = &config.env {
+        for (key, value) in env_vars {
+            tracing::debug!("Setting env: {}={}", key, value);
+            std::env::set_var(key, value);
+        }
+    }
+
+    let launch = program_launch(&script, &config.lua_paths)?;
+    let mut runtime = crate::create_puc_lua_runtime(config.runtime.as_deref());
+    runtime
+        .load_launch(&launch)
+        .map_err(|e| format!("Failed to load {}: {}", script.display(), e))?;
+
+    let mut repl = Repl::new(DebugSession::new(runtime), launch.program, std::io::stdout());
+    println!("Debugging {}. Type help for commands.", script.display());
+    if config.stop_on_entry {
+        repl.execute(ReplCommand::Step).await;
+    }
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("(wayfinder) ");
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next_line().await? else {
+            println!();
+            break;
+        };
+        if !repl.execute_line(&line).await {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Where the program is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProgramState {
+    NotStarted,
+    Stopped,
+    Exited,
+}
+
+/// The state of a terminal debugging session
+pub struct Repl<R: DebugRuntime, W: Write> {
+    session: DebugSession<R>,
+    events: EventReceiver,
+    /// Script being debugged, where breakpoints go before the program stops
+    program: String,
+    out: W,
+    state: ProgramState,
+    /// Stack of the current stop
+    frames: Vec<Frame>,
+    selected: usize,
+    last_command: Option<ReplCommand>,
+}
+
+impl<R: DebugRuntime, W: Write> Repl<R, W> {
+    /// Wraps a session whose program is loaded but not started
+    pub fn new(mut session: DebugSession<R>, program: String, out: W) -> Self {
+        let (event_tx, events) = event_channel();
+        session.set_event_sender(event_tx);
+        Self {
+            session,
+            events,
+            program,
+            out,
+            state: ProgramState::NotStarted,
+            frames: Vec::new(),
+            selected: 0,
+            last_command: None,
+        }
+    }
+
+    /// Runs a line typed at the prompt; false once the user quits
+    pub async fn execute_line(&mut self, line: &str) -> bool {
+        let command = if line.trim().is_empty() {
+            match self.last_command.clone() {
+                Some(command) => command,
+                None => return true,
+            }
+        } else {
+            match line.parse::<ReplCommand>() {
+                Ok(command) => command,
+                Err(e) => {
+                    self.say(&e);
+                    return true;
+                }
+            }
+        };
+        self.last_command = Some(command.clone());
+        self.execute(command).await
+    }
+
+    /// Runs a command; false once the user quits
+    pub async fn execute(&mut self, command: ReplCommand) -> bool {
+        let result = match command {
+            ReplCommand::Quit => return false,
+            ReplCommand::Help => {
+                self.say(repl::HELP);
+                Ok(())
+            }
+            ReplCommand::Break(target) => self.set_breakpoint(target).await,
+            ReplCommand::Delete(id) => self
+                .session
+                .remove_breakpoint(id)
+                .await
+                .map(|()| self.say(&format!("Deleted breakpoint {}", id)))
+                .map_err(|e| e.to_string()),
+            ReplCommand::Continue => self.resume(None).await,
+            ReplCommand::Next => self.resume(Some(StepMode::Over)).await,
+            ReplCommand::Step => self.resume(Some(StepMode::In)).await,
+            ReplCommand::Finish => self.resume(Some(StepMode::Out)).await,
+            ReplCommand::Backtrace => self.backtrace(),
+            ReplCommand::Frame(index) => self.select_frame(index),
+            ReplCommand::Print(expression) => self.print(&expression).await,
+            ReplCommand::Locals => self.locals().await,
+        };
+        if let Err(e) = result {
+            self.say(&e);
+        }
+        true
+    }
+
+    fn say(&mut self, text: &str) {
+        let _ = writeln!(self.out, "{}", text);
+        let _ = self.out.flush();
+    }
+
+    fn selected_frame(&self) -> Option<&Frame> {
+        self.frames.get(self.selected)
+    }
+
+    async fn set_breakpoint(&mut self, target: BreakTarget) -> Result<(), String> {
+        let (id, verified, message, location) = match target {
+            BreakTarget::Line { source, line } => {
+                let source = match source {
+                    Some(source) => resolve_source_path(Path::new(&source)).display().to_string(),
+                    None => self
+                        .selected_frame()
+                        .and_then(|frame| frame.source.as_ref())
+                        .map(|source| source.path.clone())
+                        .unwrap_or_else(|| self.program.clone()),
+                };
+                let bp = self.session.set_breakpoint(&source, line).await.map_err(|e| e.to_string())?;
+                (bp.id, bp.verified, bp.message, format!("{}:{}", source, line))
+            }
+            BreakTarget::Function(name) => {
+                let bp = self.session.set_function_breakpoint(&name).await.map_err(|e| e.to_string())?;
+                (bp.id, bp.verified, bp.message, format!("function {}", name))
+            }
+        };
+        match message.filter(|_| !verified) {
+            Some(message) => self.say(&format!("Breakpoint {} at {} (pending: {})", id, location, message)),
+            None => self.say(&format!("Breakpoint {} at {}", id, location)),
+        }
+        Ok(())
+    }
+
+    /// Starts or resumes the program, then waits until it stops or ends
+    async fn resume(&mut self, step: Option<StepMode>) -> Result<(), String> {
+        let resumed = match (self.state, step) {
+            (ProgramState::Exited, _) => return Err("The program has exited".to_string()),
+            (ProgramState::NotStarted, Some(StepMode::Out)) => return Err("The program is not running".to_string()),
+            // Stepping into a program that has not started stops on its first line
+            (ProgramState::NotStarted, step) => self.session.start_program(step.is_some()).await,
+            (ProgramState::Stopped, None) => self.session.run().await,
+            (ProgramState::Stopped, Some(mode)) => self.session.step(mode).await,
+        };
+        resumed.map_err(|e| e.to_string())?;
+        self.wait_for_stop().await;
+        Ok(())
+    }
+
+    /// Prints the program's output until it stops or ends; Ctrl-C pauses it
+    async fn wait_for_stop(&mut self) {
+        let mut exit_code = 0;
+        loop {
+            let event = tokio::select! {
+                event = self.events.recv() => event,
+                _ = tokio::signal::ctrl_c() => {
+                    if let Err(e) = self.session.pause().await {
+                        self.say(&format!("Cannot pause: {}", e));
+                    }
+                    continue;
+                }
+            };
+            let Some(Event { event, body }) = event else {
+                self.state = ProgramState::Exited;
+                return;
+            };
+            let body = body.unwrap_or_default();
+            match event.as_str() {
+                "output" => {
+                    let text = body["output"].as_str().unwrap_or_default();
+                    if body["category"] == "stderr" {
+                        eprint!("{}", text);
+                    } else {
+                        let _ = write!(self.out, "{}", text);
+                        let _ = self.out.flush();
+                    }
+                }
+                "stopped" => {
+                    self.state = ProgramState::Stopped;
+                    let thread_id = body["threadId"].as_u64();
+                    self.frames = self.session.stack_trace(thread_id).await.unwrap_or_default();
+                    self.selected = 0;
+                    let reason = body["description"].as_str().or_else(|| body["reason"].as_str()).unwrap_or("pause");
+                    let location = match self.selected_frame() {
+                        Some(frame) => repl::format_location(frame),
+                        None => "at an unknown location".to_string(),
+                    };
+                    self.say(&format!("Stopped ({}) {}", reason, location));
+                    self.show_source_line();
+                    return;
+                }
+                "exited" => exit_code = body["exitCode"].as_i64().unwrap_or_default(),
+                "terminated" => {
+                    self.state = ProgramState::Exited;
+                    self.frames.clear();
+                    self.say(&format!("Program exited with code {}", exit_code));
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn show_source_line(&mut self) {
+        let line = self
+            .selected_frame()
+            .and_then(|frame| repl::source_line(&frame.source.as_ref()?.path, frame.line));
+        if let Some(line) = line {
+            self.say(&line);
+        }
+    }
+
+    fn backtrace(&mut self) -> Result<(), String> {
+        if self.frames.is_empty() {
+            return Err("No stack: the program is not stopped".to_string());
+        }
+        let lines: Vec<String> = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| repl::format_frame(index, frame, index == self.selected))
+            .collect();
+        self.say(&lines.join("\n"));
+        Ok(())
+    }
+
+    fn select_frame(&mut self, index: usize) -> Result<(), String> {
+        let frame = self
+            .frames
+            .get(index)
+            .ok_or_else(|| format!("No frame {} (the stack has {})", index, self.frames.len()))?;
+        let line = repl::format_frame(index, frame, true);
+        self.selected = index;
+        self.say(&line);
+        self.show_source_line();
+        Ok(())
+    }
+
+    async fn print(&mut self, expression: &str) -> Result<(), String> {
+        // Without a stop the expression is evaluated against the globals
+        let value = match self.selected_frame().map(|frame| frame.id) {
+            Some(frame_id) => self.session.evaluate(frame_id, expression, EvaluationContext::Repl).await,
+            None => self.session.evaluate_global(expression).await,
+        };
+        let value = value.map_err(|e| e.to_string())?;
+        self.say(&format!("= {}", repl::format_value(&value)));
+        Ok(())
+    }
+
+    async fn locals(&mut self) -> Result<(), String> {
+        let frame_id = self
+            .selected_frame()
+            .map(|frame| frame.id)
+            .ok_or("No locals: the program is not stopped")?;
+        let scopes = self.session.scopes(frame_id).await.map_err(|e| e.to_string())?;
+        let Some(scope) = scopes.iter().find(|scope| scope.name == "Locals").or(scopes.first()) else {
+            return Err("No locals".to_string());
+        };
+        let variables = self
+            .session
+            .variables(scope.variables_reference, VariablesPage::default())
+            .await
+            .map_err(|e| e.to_string())?;
+        if variables.is_empty() {
+            self.say("No locals");
+        } else {
+            let lines: Vec<String> = variables.iter().map(repl::format_variable).collect();
+            self.say(&lines.join("\n"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "static-lua"))]
+mod tests {
+    use super::*;
+    use wayfinder_core::runtime::puc_lua::PUCLuaRuntime;
+    use wayfinder_core::runtime::ProgramLaunch;
+
+    #[tokio::test]
+    async fn test_repl_stops_prints_and_steps() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("sum.lua");
+        std::fs::write(&script, "local total = 0\nfor i = 1, 3 do\n  total = total + i\nend\nprint(total)\n").unwrap();
+        let program = script.display().to_string();
+
+        let mut runtime = PUCLuaRuntime::new();
+        runtime
+            .load_launch(&ProgramLaunch {
+                program: program.clone(),
+                args: Vec::new(),
+                lua_path: None,
+                lua_cpath: None,
+            })
+            .unwrap();
+        let mut repl = Repl::new(DebugSession::new(runtime), program, Vec::new());
+
+        for line in ["b 3", "c", "p total", "", "locals", "delete 1", "c", "c"] {
+            assert!(repl.execute_line(line).await, "{}", line);
+        }
+        assert!(!repl.execute_line("q").await);
+
+        let output = String::from_utf8(repl.out).unwrap();
+        assert!(output.contains("Breakpoint 1 at "), "{}", output);
+        assert!(output.contains("Stopped (breakpoint) main chunk at "), "{}", output);
+        assert!(output.contains("\n   3    total = total + i\n"), "{}", output);
+        assert!(output.contains("= 0\n= 0\n"), "{}", output);
+        assert!(output.contains("total = 0 (number)"), "{}", output);
+        assert!(output.contains("6\nProgram exited with code 0\nThe program has exited\n"), "{}", output);
+    }
+}
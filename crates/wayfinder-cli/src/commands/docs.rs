@@ -0,0 +1,61 @@
+//! Shell completion and manpage generation
+//!
+//! Both are generated from the clap definition in `lib.rs`, so they never
+//! drift from the flags the binary actually accepts.
+
+use clap::{Command, CommandFactory};
+use clap_complete::Shell;
+use std::io::Write;
+use std::path::Path;
+
+/// Writes the completion script for a shell
+pub fn write_completions(shell: Shell, out: &mut dyn Write) {
+    let mut command = crate::Args::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, out);
+}
+
+/// Writes the manpage for the top-level command
+pub fn write_manpage(out: &mut dyn Write) -> std::io::Result<()> {
+    clap_mangen::Man::new(crate::Args::command()).render(out)
+}
+
+/// Writes `wayfinder.1` and a `wayfinder-<subcommand>.1` page per subcommand
+pub fn write_manpages(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let command = crate::Args::command();
+    write_page(&command, command.get_name(), dir)?;
+
+    for subcommand in command.get_subcommands() {
+        let name = format!("{}-{}", command.get_name(), subcommand.get_name());
+        write_page(&subcommand.clone().name(name.clone()), &name, dir)?;
+    }
+    Ok(())
+}
+
+fn write_page(command: &Command, name: &str, dir: &Path) -> std::io::Result<()> {
+    let mut file = std::fs::File::create(dir.join(format!("{}.1", name)))?;
+    clap_mangen::Man::new(command.clone()).render(&mut file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completions_cover_subcommands() {
+        let mut out = Vec::new();
+        write_completions(Shell::Bash, &mut out);
+        let script = String::from_utf8(out).unwrap();
+        assert!(script.contains("hot-reload"));
+        assert!(script.contains("completions"));
+    }
+
+    #[test]
+    fn test_manpages_written_per_subcommand() {
+        let dir = tempfile::tempdir().unwrap();
+        write_manpages(dir.path()).unwrap();
+        assert!(dir.path().join("wayfinder.1").exists());
+        assert!(dir.path().join("wayfinder-attach.1").exists());
+    }
+}
@@ -54,11 +54,11 @@ async fn send_hot_reload_tcp(module: String, host: String, port: u16) -> Result<
     let mut reader = BufReader::new(read_half);
     let mut writer = write_half;
 
-    // Create the hotReload DAP request
+    // Create the wayfinder/hotReload DAP request
     let request = json!({
         "seq": 1,
         "type": "request",
-        "command": "hotReload",
+        "command": "wayfinder/hotReload",
         "arguments": {
             "module": module
         }
@@ -86,7 +86,7 @@ async fn send_hot_reload_tcp(module: String, host: String, port: u16) -> Result<
                             if !warnings.is_empty() {
                                 println!("\nWarnings:");
                                 for warning in warnings {
-                                    if let Some(msg) = warning.as_str() {
+                                    if let Some(msg) = warning.get("message").and_then(|m| m.as_str()) {
                                         println!("  ⚠ {}", msg);
                                     }
                                 }
@@ -124,11 +124,11 @@ async fn send_hot_reload_stdio(module: String) -> Result<(), Box<dyn std::error:
     let stdin = tokio::io::stdin();
     let mut stdout = tokio::io::stdout();
 
-    // Create the hotReload DAP request
+    // Create the wayfinder/hotReload DAP request
     let request = json!({
         "seq": 1,
         "type": "request",
-        "command": "hotReload",
+        "command": "wayfinder/hotReload",
         "arguments": {
             "module": module
         }
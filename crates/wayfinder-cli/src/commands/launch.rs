@@ -4,12 +4,20 @@
 
 use std::collections::HashMap;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::str::FromStr;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
+use wayfinder_core::dap::transport::DapTransport;
+use wayfinder_core::hot_reload::WatcherConfig;
+use wayfinder_core::profiling::export::{self, ExportFormat};
+use wayfinder_core::profiling::{ProfileData, ProfilingMode};
+use wayfinder_core::serializer::Serializer;
+use wayfinder_core::runtime::lua_paths::{self, LuaPathConfig, LuaPaths};
 use wayfinder_core::runtime::puc_lua::PUCLuaRuntime;
-use wayfinder_core::session::DapServer;
+use wayfinder_core::runtime::{DebugRuntime, ProgramLaunch};
+use wayfinder_core::session::{process_tree, DapServer, OnShutdown};
 
 /// Launch configuration
 #[derive(Debug)]
@@ -24,23 +32,181 @@ pub struct LaunchConfig {
     pub script: String,
     /// Enable DAP debugging
     pub debug: bool,
+    /// Stop before the first line when debugging
+    pub stop_on_entry: bool,
+    /// Profile the run and write the profile to this file
+    pub profile: Option<PathBuf>,
+    /// Format of the profile file
+    pub profile_format: ExportFormat,
+    /// Encoding of the profile file; implied by its extension when unset
+    pub profile_serializer: Option<Serializer>,
+    /// JSONL file to record every DAP message to when debugging
+    pub trace_dap: Option<PathBuf>,
+    /// `package.path` and `package.cpath` templates and source roots
+    pub lua_paths: LuaPathConfig,
+    /// Kind of project `script` names, when it is not a Lua script
+    pub preset: Option<Preset>,
+    /// Files to hot reload into the script when they change while debugging
+    pub hot_reload: Option<WatcherConfig>,
+    /// Whether the script's process is killed or left running when Wayfinder is interrupted
+    pub on_shutdown: OnShutdown,
+}
+
+/// Kinds of project the launch command knows how to start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// A LÖVE game directory, run with `love`
+    Love2d,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "love2d" | "love" => Ok(Preset::Love2d),
+            _ => Err(format!("Unknown preset: {} (expected love2d)", s)),
+        }
+    }
+}
+
+/// Most suggestions listed when the script is not found
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Resolves the script to launch to an absolute path
+///
+/// Relative paths are taken from `cwd` when one is given, since that is where
+/// the script runs. When the script does not exist the error names the
+/// closest files next to it, to catch typos.
+pub fn resolve_script(script: &str, cwd: Option<&str>) -> Result<PathBuf, String> {
+    let path = match cwd {
+        Some(cwd) if Path::new(script).is_relative() => Path::new(cwd).join(script),
+        _ => PathBuf::from(script),
+    };
+
+    if !path.exists() {
+        let suggestions = similar_files(&path);
+        return Err(if suggestions.is_empty() {
+            format!("Script not found: {}", path.display())
+        } else {
+            format!("Script not found: {} (did you mean {}?)", path.display(), suggestions.join(", "))
+        });
+    }
+    if !path.is_file() {
+        return Err(format!("Script is not a file: {}", path.display()));
+    }
+
+    let resolved = std::fs::canonicalize(&path).map_err(|e| format!("Cannot resolve {}: {}", path.display(), e))?;
+    Ok(strip_verbatim_prefix(resolved))
+}
+
+/// Makes a source path absolute, as Lua names the chunks of resolved scripts
+///
+/// Existing files are canonicalized like the script; others are taken against the working directory.
+pub fn resolve_source_path(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path)
+        .map(strip_verbatim_prefix)
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Names of the files next to `path` whose names are closest to its own
+fn similar_files(path: &Path) -> Vec<String> {
+    let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_lowercase()) else {
+        return Vec::new();
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    // Allow roughly one edit per four characters
+    let max_distance = (name.chars().count() / 4).max(1);
+    let mut candidates: Vec<(usize, String)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let candidate = entry.file_name().to_string_lossy().into_owned();
+            let distance = edit_distance(&name, &candidate.to_lowercase());
+            (distance <= max_distance).then_some((distance, candidate))
+        })
+        .collect();
+    candidates.sort();
+    candidates.into_iter().take(MAX_SUGGESTIONS).map(|(_, candidate)| candidate).collect()
+}
+
+/// Levenshtein distance between two strings, by character
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Drops the `\\?\` prefix Windows adds to canonical paths, which many Lua builds cannot open
+fn strip_verbatim_prefix(path: PathBuf) -> PathBuf {
+    if let Some(text) = path.to_str().filter(|_| cfg!(windows)) {
+        if let Some(share) = text.strip_prefix(r"\\?\UNC\") {
+            return PathBuf::from(format!(r"\\{}", share));
+        }
+        if let Some(local) = text.strip_prefix(r"\\?\") {
+            return PathBuf::from(local);
+        }
+    }
+    path
+}
+
+/// The launch of a script in-process, with search paths for its directory and source roots
+///
+/// Call once in the directory the script runs in, which relative source roots are taken against.
+pub fn program_launch(script: &Path, config: &LuaPathConfig) -> Result<ProgramLaunch, String> {
+    let program = script
+        .to_str()
+        .ok_or_else(|| format!("Script path is not valid Unicode: {}", script.display()))?;
+    let cwd = std::env::current_dir().map_err(|e| format!("Cannot read the working directory: {}", e))?;
+    let paths = LuaPaths::derive(config, script.parent().unwrap_or(&cwd), &cwd);
+    Ok(ProgramLaunch {
+        program: program.to_string(),
+        args: Vec::new(),
+        lua_path: Some(paths.path),
+        lua_cpath: Some(paths.cpath),
+    })
 }
 
 /// Launch a Lua script with debugging capabilities
 pub async fn launch_script(config: LaunchConfig) -> Result<(), Box<dyn std::error::Error>> {
-    // Determine the runtime executable
-    let runtime_executable = config.runtime.clone().unwrap_or_else(|| "lua".to_string());
+    if config.preset == Some(Preset::Love2d) {
+        return super::love2d::launch_game(config).await;
+    }
+
+    let script = resolve_script(&config.script, config.cwd.as_deref())?;
 
-    println!("Launching {} with {}", config.script, runtime_executable);
+    // Debugging runs the script in-process under the debug hook
     if config.debug {
-        println!("Debug mode enabled - injecting debug helpers");
+        tracing::info!("Launching {} under the debugger", script.display());
+        return launch_with_debugging(config, script).await;
     }
 
-    // Verify the script exists
-    if !Path::new(&config.script).exists() {
-        return Err(format!("Script not found: {}", config.script).into());
+    // Profiling also runs the script in-process, to install the profiler's hook
+    if let Some(profile) = config.profile.clone() {
+        tracing::info!("Profiling {}", script.display());
+        return launch_with_profiling(config, script, profile).await;
     }
 
+    // Determine the runtime executable
+    let runtime_executable = lua_executable(config.runtime.as_deref(), std::env::var_os("PATH").as_deref());
+
+    println!("Launching {} with {}", script.display(), runtime_executable);
+
     // Build the command
     let mut cmd = Command::new(&runtime_executable);
 
@@ -50,6 +216,18 @@ pub async fn launch_script(config: LaunchConfig) -> Result<(), Box<dyn std::erro
         cmd.current_dir(cwd);
     }
 
+    // Search the script's directory and source roots rather than whatever the adapter inherited
+    let cwd = match &config.cwd {
+        Some(cwd) => PathBuf::from(cwd),
+        None => std::env::current_dir()?,
+    };
+    let paths = LuaPaths::derive(&config.lua_paths, script.parent().unwrap_or(&cwd), &cwd);
+    for variable in lua_paths::VERSIONED_VARIABLES {
+        cmd.env_remove(variable);
+    }
+    cmd.env("LUA_PATH", &paths.path);
+    cmd.env("LUA_CPATH", &paths.cpath);
+
     // Set environment variables if provided
     if let Some(env_vars) = &config.env {
         for (key, value) in env_vars {
@@ -58,29 +236,18 @@ pub async fn launch_script(config: LaunchConfig) -> Result<(), Box<dyn std::erro
         }
     }
 
-    // If debug mode is enabled, prepend the debug initialization script
-    if config.debug {
-        // Create a wrapper script that loads debug helpers then the user script
-        let debug_init_path = get_debug_init_path()?;
+    // The resolved path is absolute, so the runtime cannot mistake it for an
+    // option, and it is passed as a single argument whatever it contains
+    cmd.arg(&script);
 
-        // Use -l flag to preload the debug module (Lua 5.2+)
-        // For Lua 5.1, we'll use a different approach
-        cmd.arg("-e");
-        cmd.arg(format!("dofile('{}')", debug_init_path.display()));
-        cmd.arg("-e");
-        cmd.arg("wayfinder.start()");
-    }
-
-    // Add the script as an argument
-    cmd.arg(&config.script);
-
-    // Configure stdio to allow communication with the debugger
+    // Configure stdio
     cmd.stdin(Stdio::piped());
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::inherit()); // Show stderr directly to user
 
-    // Spawn the process
+    // Spawn the process in a group of its own, so stopping it stops what it started
     println!("Spawning Lua process...");
+    process_tree::spawn_as_group(&mut cmd);
     let mut child = cmd.spawn()?;
 
     // Get the process ID
@@ -90,23 +257,42 @@ pub async fn launch_script(config: LaunchConfig) -> Result<(), Box<dyn std::erro
         println!("✓ Launched process (PID unavailable)");
     }
 
-    // If debug mode is enabled, set up DAP debugging
-    if config.debug {
-        println!("Starting DAP debugging session...");
-        return launch_with_debugging(child, config.runtime).await;
-    }
-
     // Normal execution without debugging
     // Forward stdout from the Lua process
-    if let Some(stdout) = child.stdout.take() {
-        let mut reader = BufReader::new(stdout);
-        let mut line = String::new();
-
-        println!("\n--- Script Output ---");
-        while reader.read_line(&mut line).await? > 0 {
-            print!("{}", line);
-            std::io::stdout().flush()?;
-            line.clear();
+    let stdout = child.stdout.take();
+    let forward = async {
+        if let Some(stdout) = stdout {
+            let mut reader = BufReader::new(stdout);
+            let mut line = String::new();
+
+            println!("\n--- Script Output ---");
+            while reader.read_line(&mut line).await? > 0 {
+                print!("{}", line);
+                std::io::stdout().flush()?;
+                line.clear();
+            }
+        }
+        Ok::<_, std::io::Error>(())
+    };
+
+    // On SIGINT or SIGTERM the script is killed, or left running, before Wayfinder exits
+    let shutdown = std::sync::Arc::new(tokio::sync::Notify::new());
+    crate::shutdown::coordinator().register(&shutdown);
+    tokio::select! {
+        forwarded = forward => forwarded?,
+        _ = shutdown.notified() => {
+            match config.on_shutdown {
+                OnShutdown::Terminate => {
+                    process_tree::kill_tree(&mut child).await?;
+                    println!("\n--- Script Stopped ---");
+                }
+                OnShutdown::Detach => {
+                    if let Some(pid) = child.id() {
+                        println!("\n--- Left process {} running ---", pid);
+                    }
+                }
+            }
+            return Ok(());
         }
     }
 
@@ -118,73 +304,160 @@ pub async fn launch_script(config: LaunchConfig) -> Result<(), Box<dyn std::erro
     Ok(())
 }
 
-/// Launch with DAP debugging enabled
-async fn launch_with_debugging(child: tokio::process::Child, runtime_version: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
-    eprintln!("DAP debugging enabled - starting debug session");
-    eprintln!("Note: Full DAP debugging requires IDE connection");
-    eprintln!("For now, the process will run with debug helpers injected");
+/// Lua executables looked for on PATH when no runtime is given, the unversioned `lua` first
+///
+/// Windows builds such as LuaBinaries name them without the dot, as `lua54.exe`.
+const LUA_EXECUTABLES: &[&str] = &[
+    "lua", "lua5.4", "lua54", "lua5.3", "lua53", "lua5.2", "lua52", "lua5.1", "lua51", "luajit",
+];
+
+/// The Lua executable: the runtime given, else the first of `LUA_EXECUTABLES` in `path`
+///
+/// Falls back to `lua`, so the error when it cannot be run names it.
+pub fn lua_executable(runtime: Option<&str>, path: Option<&std::ffi::OsStr>) -> String {
+    if let Some(runtime) = runtime {
+        return runtime.to_string();
+    }
+    let dirs: Vec<PathBuf> = path.map(|path| std::env::split_paths(path).collect()).unwrap_or_default();
+    LUA_EXECUTABLES
+        .iter()
+        .find(|name| {
+            let file = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+            dirs.iter().any(|dir| dir.join(&file).is_file())
+        })
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| "lua".to_string())
+}
 
-    // Create DAP server
-    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+/// Launch with DAP debugging enabled
+///
+/// The script is loaded into a runtime owned by the DAP server and starts once
+/// the client sends `configurationDone`. The DAP session is served over stdio,
+/// so `print` output is delivered as output events instead.
+async fn launch_with_debugging(config: LaunchConfig, script: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    // Resolve the trace before changing directory, so it is relative to where we were run
+    let trace_dap = config.trace_dap.as_deref().map(std::path::absolute).transpose()?;
 
-    // Set up the runtime with specified version
-    let runtime = crate::create_puc_lua_runtime(runtime_version.as_deref());
-    server.set_runtime(runtime);
+    if let Some(cwd) = &config.cwd {
+        tracing::debug!("Working directory: {}", cwd);
+        std::env::set_current_dir(cwd)?;
+    }
 
-    // Store the process handle
-    server.set_process(child);
+    // The script runs in this process, so its environment is ours
+    if let Some(env_vars) = &config.env {
+        for (key, value) in env_vars {
+            tracing::debug!("Setting env: {}={}", key, value);
+            std::env::set_var(key, value);
+        }
+    }
 
-    // In a full implementation, this would:
-    // 1. Start a DAP server (stdio or TCP) to accept debugger connections
-    // 2. Wait for IDE to connect
-    // 3. Handle DAP initialize/launch/attach requests
-    // 4. Communicate with the injected Lua debug helpers
-    // 5. Forward breakpoint hits, variable inspection, etc.
+    let launch = program_launch(&script, &config.lua_paths)?;
+    let mut runtime = crate::create_puc_lua_runtime(config.runtime.as_deref());
+    runtime
+        .load_launch(&launch)
+        .map_err(|e| format!("Failed to load {}: {}", script.display(), e))?;
 
-    // For now, we'll just wait for the process to complete
-    eprintln!("Process running with debug capabilities");
-    eprintln!("Connect a DAP client to begin debugging");
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(runtime);
+    server.set_stop_on_entry(config.stop_on_entry);
+    crate::shutdown::coordinator().register(&server.shutdown_trigger());
+    if let Some(hot_reload) = config.hot_reload.clone() {
+        server.watch_for_hot_reload(hot_reload);
+    }
 
-    // Run the basic event loop (placeholder)
-    server.run_event_loop().await?;
+    tracing::info!("Waiting for a DAP client on stdio");
+    let mut transport = DapTransport::stdio();
+    crate::commands::dap::record_trace(&mut transport, trace_dap.as_deref())?;
+    server.run_event_loop(&mut transport).await?;
 
     Ok(())
 }
 
-/// Get the path to the debug initialization script
-fn get_debug_init_path() -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
-    // Try to find debug_init.lua relative to the executable
-    if let Ok(exe_path) = std::env::current_exe() {
-        if let Some(exe_dir) = exe_path.parent() {
-            // Check in the same directory as the executable
-            let debug_init = exe_dir.join("debug_init.lua");
-            if debug_init.exists() {
-                return Ok(debug_init);
-            }
+/// Run the script in-process under the call profiler and write its profile
+///
+/// The profile is written when the script finishes, also when it fails.
+async fn launch_with_profiling(
+    config: LaunchConfig,
+    script: PathBuf,
+    profile: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Resolve the output before changing directory, so it is relative to where we were run
+    let profile = std::path::absolute(&profile)?;
 
-            // Check in parent directory (for development builds)
-            if let Some(parent_dir) = exe_dir.parent() {
-                let debug_init = parent_dir.join("debug_init.lua");
-                if debug_init.exists() {
-                    return Ok(debug_init);
-                }
-            }
+    if let Some(cwd) = &config.cwd {
+        tracing::debug!("Working directory: {}", cwd);
+        std::env::set_current_dir(cwd)?;
+    }
+    if let Some(env_vars) = &config.env {
+        for (key, value) in env_vars {
+            tracing::debug!("Setting env: {}={}", key, value);
+            std::env::set_var(key, value);
         }
     }
 
-    // Try current working directory
-    let cwd_debug_init = Path::new("debug_init.lua");
-    if cwd_debug_init.exists() {
-        return Ok(cwd_debug_init.to_path_buf());
-    }
+    let launch = program_launch(&script, &config.lua_paths)?;
+    let runtime = crate::create_puc_lua_runtime(config.runtime.as_deref());
+    let run = run_profiled(runtime, &launch, |category, output| {
+        if category == "stderr" {
+            eprint!("{}", output);
+        } else {
+            print!("{}", output);
+            let _ = std::io::stdout().flush();
+        }
+    })
+    .await?;
 
-    // Try the CLI crate directory (for development)
-    let cli_debug_init = Path::new("crates/wayfinder-cli/debug_init.lua");
-    if cli_debug_init.exists() {
-        return Ok(cli_debug_init.to_path_buf());
+    let serializer = config
+        .profile_serializer
+        .or_else(|| Serializer::for_path(&profile))
+        .unwrap_or_default();
+    export::write_profile(&run.profile, config.profile_format, serializer, &profile)?;
+    eprintln!("Wrote {} profile to {} ({})", config.profile_format, profile.display(), serializer);
+
+    Ok(())
+}
+
+/// Outcome of a script run under the call profiler
+pub struct ProfiledRun {
+    pub exit_code: i64,
+    pub profile: ProfileData,
+}
+
+/// Runs a script to completion in-process under the call profiler
+///
+/// Output is handed to `on_output` with its category as the script prints it.
+pub async fn run_profiled(
+    mut runtime: PUCLuaRuntime,
+    launch: &ProgramLaunch,
+    mut on_output: impl FnMut(&str, &str),
+) -> Result<ProfiledRun, Box<dyn std::error::Error>> {
+    let (event_tx, mut event_rx) = wayfinder_core::dap::event_channel();
+    runtime.set_event_sender(event_tx);
+    runtime
+        .load_launch(launch)
+        .map_err(|e| format!("Failed to load {}: {}", launch.program, e))?;
+    runtime.start_profiling(ProfilingMode::CallTrace).await?;
+    runtime.start_program(false).await?;
+
+    let mut exit_code = 0;
+    while let Some(event) = event_rx.recv().await {
+        let body = event.body.as_ref();
+        match event.event.as_str() {
+            "output" => {
+                let category = body.and_then(|b| b.get("category")).and_then(|v| v.as_str()).unwrap_or("stdout");
+                let output = body.and_then(|b| b.get("output")).and_then(|v| v.as_str()).unwrap_or_default();
+                on_output(category, output);
+            }
+            "exited" => {
+                exit_code = body.and_then(|b| b.get("exitCode")).and_then(|v| v.as_i64()).unwrap_or_default();
+            }
+            "terminated" => break,
+            _ => {}
+        }
     }
 
-    Err("Could not find debug_init.lua - make sure it's in the same directory as the wayfinder binary".into())
+    let profile = runtime.stop_profiling().await?;
+    Ok(ProfiledRun { exit_code, profile })
 }
 
 #[cfg(test)]
@@ -199,6 +472,15 @@ mod tests {
             env: None,
             script: "test.lua".to_string(),
             debug: false,
+            stop_on_entry: false,
+            profile: None,
+            profile_format: ExportFormat::default(),
+            profile_serializer: None,
+            trace_dap: None,
+            lua_paths: LuaPathConfig::default(),
+            preset: None,
+            hot_reload: None,
+            on_shutdown: OnShutdown::Terminate,
         };
 
         assert_eq!(config.runtime, Some("lua5.4".to_string()));
@@ -206,4 +488,52 @@ mod tests {
         assert_eq!(config.script, "test.lua".to_string());
         assert_eq!(config.debug, false);
     }
+
+    #[test]
+    fn test_resolve_script_with_spaces_and_unicode() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("projet été");
+        std::fs::create_dir(&project).unwrap();
+        let script = project.join("main script 日本.lua");
+        std::fs::write(&script, "print('hi')\n").unwrap();
+
+        let cwd = project.to_str().unwrap();
+        let resolved = resolve_script("main script 日本.lua", Some(cwd)).unwrap();
+        assert!(resolved.is_absolute());
+        assert_eq!(resolved, std::fs::canonicalize(&script).unwrap());
+
+        let absolute = resolve_script(script.to_str().unwrap(), Some("/nonexistent")).unwrap();
+        assert_eq!(absolute, resolved);
+    }
+
+    #[test]
+    fn test_resolve_script_suggests_similar_names() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.lua"), "").unwrap();
+        std::fs::write(dir.path().join("unrelated.txt"), "").unwrap();
+
+        let error = resolve_script("mian.lua", dir.path().to_str()).unwrap_err();
+        assert!(error.contains("did you mean main.lua?"), "{}", error);
+
+        let error = resolve_script(".", dir.path().to_str()).unwrap_err();
+        assert!(error.starts_with("Script is not a file"), "{}", error);
+    }
+
+    #[test]
+    fn test_lua_executable_takes_windows_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = std::env::join_paths([dir.path()]).unwrap();
+        assert_eq!(lua_executable(None, Some(&path)), "lua");
+
+        std::fs::write(dir.path().join(format!("lua53{}", std::env::consts::EXE_SUFFIX)), "").unwrap();
+        assert_eq!(lua_executable(None, Some(&path)), "lua53");
+        assert_eq!(lua_executable(Some("luajit"), Some(&path)), "luajit");
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("main.lua", "main.lua"), 0);
+        assert_eq!(edit_distance("mian.lua", "main.lua"), 2);
+        assert_eq!(edit_distance("été", "ete"), 2);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,306 @@
+//! LÖVE preset for the launch command
+//!
+//! `wayfinder launch --preset love2d <gamedir>` runs a game with `love`.
+//! LÖVE embeds LuaJIT, which cannot load the native agent, so under the
+//! debugger the game is started through a bootstrap game: a temporary
+//! directory whose `conf.lua` starts a Lua agent speaking the agent protocol
+//! over LuaSocket, and whose `main.lua` runs the game's own. The game's
+//! other files are linked in, so `require` and `love.filesystem` find them
+//! at their usual paths, and the agent reports them as the files in the game
+//! directory. Errors reaching LÖVE's error handler stop the game before its
+//! error screen shows.
+
+use super::launch::LaunchConfig;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio::process::Command;
+use wayfinder_core::dap::transport::DapTransport;
+use wayfinder_core::runtime::remote::RemoteRuntime;
+use wayfinder_core::runtime::{BreakpointType, DebugRuntime};
+use wayfinder_core::session::{process_tree, DapServer};
+
+/// The agent the bootstrap game loads
+const AGENT: &str = include_str!("love2d_agent.lua");
+
+/// Directory of the bootstrap game holding the agent, named to stay clear of the game's own
+const AGENT_DIR: &str = "__wayfinder";
+
+/// Files of the game the bootstrap game has its own of
+const REPLACED: &[&str] = &["main.lua", "conf.lua"];
+
+/// Time allowed for `love` to start and the agent to accept the debugger
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Bootstrap games created by this process, for unique directory names
+static BOOTSTRAPS: AtomicU32 = AtomicU32::new(0);
+
+/// Resolves the game directory, which holds the game's `main.lua`
+pub fn resolve_game_dir(game: &str, cwd: Option<&str>) -> Result<PathBuf, String> {
+    let path = match cwd {
+        Some(cwd) if Path::new(game).is_relative() => Path::new(cwd).join(game),
+        _ => PathBuf::from(game),
+    };
+    if !path.is_dir() {
+        return Err(format!("Game directory not found: {}", path.display()));
+    }
+    if !path.join("main.lua").is_file() {
+        return Err(format!("Not a LÖVE game, it has no main.lua: {}", path.display()));
+    }
+    Ok(super::launch::resolve_source_path(&path))
+}
+
+/// The `love` executable: the runtime given, else the one on PATH or in the macOS app bundle
+pub fn love_executable(runtime: Option<&str>) -> PathBuf {
+    if let Some(runtime) = runtime {
+        return PathBuf::from(runtime);
+    }
+    let bundled = Path::new("/Applications/love.app/Contents/MacOS/love");
+    if cfg!(target_os = "macos") && bundled.is_file() {
+        return bundled.to_path_buf();
+    }
+    PathBuf::from("love")
+}
+
+/// A bootstrap game, removed when dropped
+pub struct Bootstrap {
+    dir: PathBuf,
+}
+
+impl Bootstrap {
+    /// Creates the bootstrap game for `game_dir` in the temporary directory
+    ///
+    /// Its agent waits for the debugger on `host` and `port`.
+    pub fn create(game_dir: &Path, host: &str, port: u16) -> io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "wayfinder-love2d-{}-{}",
+            std::process::id(),
+            BOOTSTRAPS.fetch_add(1, Ordering::Relaxed)
+        ));
+        // A stale directory from an earlier run with the same pid
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join(AGENT_DIR))?;
+        let bootstrap = Self { dir };
+
+        for entry in std::fs::read_dir(game_dir)? {
+            let name = entry?.file_name();
+            if REPLACED.iter().any(|replaced| name == *replaced) || name == AGENT_DIR {
+                continue;
+            }
+            link(&game_dir.join(&name), &bootstrap.dir.join(&name))?;
+        }
+
+        // The agent and LÖVE's filesystem both use forward slashes
+        let game_path = game_dir.to_string_lossy().replace('\\', "/");
+        let identity = game_dir.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        std::fs::write(bootstrap.dir.join(AGENT_DIR).join("agent.lua"), AGENT)?;
+        std::fs::write(
+            bootstrap.dir.join("conf.lua"),
+            format!(
+                "-- Written by wayfinder: waits for the debugger, then runs the game's conf.lua\n\
+                 require(\"{}.agent\").start({{ host = {}, port = {}, game_dir = {}, identity = {} }}, ...)\n",
+                AGENT_DIR,
+                lua_quote(host),
+                port,
+                lua_quote(&game_path),
+                lua_quote(&identity)
+            ),
+        )?;
+        std::fs::write(
+            bootstrap.dir.join("main.lua"),
+            format!(
+                "-- Written by wayfinder: runs the game's main.lua under the debug agent\n\
+                 require(\"{}.agent\").run_file(\"main.lua\", ...)\n",
+                AGENT_DIR
+            ),
+        )?;
+        Ok(bootstrap)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for Bootstrap {
+    fn drop(&mut self) {
+        // Removes the links, not what they point to
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Links `target` into the bootstrap game at `link`
+#[cfg(unix)]
+fn link(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Links `target` into the bootstrap game at `link`
+///
+/// Symbolic links need Developer Mode or an elevated prompt on Windows.
+#[cfg(windows)]
+fn link(target: &Path, link: &Path) -> io::Result<()> {
+    if target.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+/// Quotes `text` as a Lua string literal
+fn lua_quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            // Three-digit decimal escapes, which every Lua version reads
+            c if c.is_control() && (c as u32) < 256 => quoted.push_str(&format!("\\{:03}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Runs the game in `config.script` with `love`, under the debugger when asked to
+pub async fn launch_game(config: LaunchConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let game_dir = resolve_game_dir(&config.script, config.cwd.as_deref())?;
+    let love = love_executable(config.runtime.as_deref());
+
+    if config.debug {
+        tracing::info!("Launching {} under the debugger", game_dir.display());
+        return launch_with_debugging(config, game_dir, love).await;
+    }
+
+    println!("Launching {} with {}", game_dir.display(), love.display());
+    let mut command = Command::new(&love);
+    command.arg(&game_dir);
+    if let Some(cwd) = &config.cwd {
+        command.current_dir(cwd);
+    }
+    command.envs(config.env.iter().flatten());
+    let status = command
+        .spawn()
+        .map_err(|e| format!("Cannot run {}: {}", love.display(), e))?
+        .wait()
+        .await?;
+    println!("Exit status: {}", status);
+    Ok(())
+}
+
+/// Runs the game through a bootstrap game and serves the DAP session on stdio
+///
+/// The game's output is piped and sent to the client as output events, as
+/// stdout carries the session.
+async fn launch_with_debugging(
+    config: LaunchConfig,
+    game_dir: PathBuf,
+    love: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let address = wayfinder_core::session::terminal::free_local_address()?;
+    let port = address
+        .rsplit_once(':')
+        .and_then(|(_, port)| port.parse().ok())
+        .ok_or_else(|| format!("Invalid agent address: {}", address))?;
+    // Lives until the server has stopped `love`
+    let bootstrap = Bootstrap::create(&game_dir, "127.0.0.1", port)
+        .map_err(|e| format!("Cannot create the bootstrap game for {}: {}", game_dir.display(), e))?;
+
+    let mut command = Command::new(&love);
+    command
+        .arg(bootstrap.path())
+        .current_dir(config.cwd.as_deref().map(Path::new).unwrap_or(game_dir.as_path()))
+        .envs(config.env.iter().flatten())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    process_tree::spawn_as_group(&mut command);
+    let process = command.spawn().map_err(|e| format!("Cannot run {}: {}", love.display(), e))?;
+
+    let mut runtime =
+        super::attach::connect_with_retry(&address, CONNECT_TIMEOUT, || RemoteRuntime::connect_tcp(&address)).await?;
+    // Errors reaching love.errorhandler stop the game whatever filters the client sets
+    runtime
+        .set_breakpoint(BreakpointType::Exception {
+            filter: "uncaught".to_string(),
+        })
+        .await?;
+
+    let mut server: DapServer<RemoteRuntime> = DapServer::new();
+    server.set_runtime(runtime);
+    server.set_stop_on_entry(config.stop_on_entry);
+    server.set_process(process);
+    // The bootstrap game goes away with this process, so the game is always stopped
+    crate::shutdown::coordinator().register(&server.shutdown_trigger());
+
+    tracing::info!("Waiting for a DAP client on stdio");
+    let mut transport = DapTransport::stdio();
+    crate::commands::dap::record_trace(&mut transport, config.trace_dap.as_deref())?;
+    server.run_event_loop(&mut transport).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lua_quote() {
+        assert_eq!(lua_quote("127.0.0.1"), "\"127.0.0.1\"");
+        assert_eq!(lua_quote(r#"C:\games\"mine""#), r#""C:\\games\\\"mine\"""#);
+        assert_eq!(lua_quote("a\nb\u{1}"), "\"a\\nb\\001\"");
+        assert_eq!(lua_quote("jeu été"), "\"jeu été\"");
+    }
+
+    #[test]
+    fn test_resolve_game_dir_needs_main_lua() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = resolve_game_dir("game", dir.path().to_str()).unwrap_err();
+        assert!(error.starts_with("Game directory not found"), "{}", error);
+
+        std::fs::create_dir(dir.path().join("game")).unwrap();
+        let error = resolve_game_dir("game", dir.path().to_str()).unwrap_err();
+        assert!(error.contains("no main.lua"), "{}", error);
+
+        std::fs::write(dir.path().join("game/main.lua"), "").unwrap();
+        let resolved = resolve_game_dir("game", dir.path().to_str()).unwrap();
+        assert!(resolved.is_absolute());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bootstrap_links_the_game_around_its_own_entry_points() {
+        let game = tempfile::tempdir().unwrap();
+        std::fs::write(game.path().join("main.lua"), "-- game main\n").unwrap();
+        std::fs::write(game.path().join("conf.lua"), "-- game conf\n").unwrap();
+        std::fs::write(game.path().join("player.lua"), "return {}\n").unwrap();
+        std::fs::create_dir(game.path().join("assets")).unwrap();
+        std::fs::write(game.path().join("assets/hero.png"), "png").unwrap();
+
+        let bootstrap = Bootstrap::create(game.path(), "127.0.0.1", 4711).unwrap();
+        let dir = bootstrap.path().to_path_buf();
+
+        let conf = std::fs::read_to_string(dir.join("conf.lua")).unwrap();
+        assert!(conf.contains("require(\"__wayfinder.agent\").start({ host = \"127.0.0.1\", port = 4711"), "{}", conf);
+        assert!(conf.contains(&lua_quote(&game.path().to_string_lossy())), "{}", conf);
+        let main = std::fs::read_to_string(dir.join("main.lua")).unwrap();
+        assert!(main.contains("run_file(\"main.lua\", ...)"), "{}", main);
+        assert_eq!(std::fs::read_to_string(dir.join("__wayfinder/agent.lua")).unwrap(), AGENT);
+
+        assert_eq!(std::fs::read_to_string(dir.join("player.lua")).unwrap(), "return {}\n");
+        assert_eq!(std::fs::read_to_string(dir.join("assets/hero.png")).unwrap(), "png");
+        assert!(std::fs::symlink_metadata(dir.join("assets")).unwrap().file_type().is_symlink());
+
+        drop(bootstrap);
+        assert!(!dir.exists());
+        // The game itself is untouched
+        assert_eq!(std::fs::read_to_string(game.path().join("assets/hero.png")).unwrap(), "png");
+    }
+}
@@ -0,0 +1,969 @@
+-- Wayfinder debug agent for LÖVE games
+--
+-- Written into the bootstrap game `wayfinder launch --preset love2d` runs.
+-- LÖVE embeds LuaJIT, which cannot load the native agent, so this one is
+-- plain Lua speaking the agent protocol over LuaSocket: Content-Length
+-- framed JSON, with requests answered in order and events sent as they
+-- happen. LÖVE names the chunks it loads after their path in the game's
+-- virtual filesystem, such as `@player.lua`; frames and breakpoints
+-- translate between those names and the files in the game directory.
+
+local socket = require("socket")
+
+local agent = {}
+
+-- Line events between checks for requests while the game runs
+local POLL_INTERVAL = 1000
+
+local NOT_IMPLEMENTED_CODE = -2
+local RUNTIME_ERROR_CODE = -1
+
+local own_source = debug.getinfo(1, "S").source
+
+-- JSON
+
+local null = setmetatable({}, { __tostring = function() return "null" end })
+local array_metatable = {}
+
+-- Marks a table to be encoded as a JSON array, also when empty
+local function array(items)
+    return setmetatable(items or {}, array_metatable)
+end
+
+-- Replaces bytes that are not UTF-8, which JSON cannot carry, with U+FFFD
+local function to_utf8(text)
+    if not text:find("[\128-\255]") then
+        return text
+    end
+    local parts, at = {}, 1
+    while at <= #text do
+        local byte = text:byte(at)
+        local length = byte < 0x80 and 1
+            or (byte >= 0xC2 and byte < 0xE0) and 2
+            or (byte >= 0xE0 and byte < 0xF0) and 3
+            or (byte >= 0xF0 and byte < 0xF5) and 4
+            or 0
+        local valid = length > 0 and at + length - 1 <= #text
+        for continuation = at + 1, at + length - 1 do
+            local next_byte = text:byte(continuation)
+            if not next_byte or next_byte < 0x80 or next_byte > 0xBF then
+                valid = false
+            end
+        end
+        if valid then
+            parts[#parts + 1] = text:sub(at, at + length - 1)
+            at = at + length
+        else
+            parts[#parts + 1] = "\239\191\189"
+            at = at + 1
+        end
+    end
+    return table.concat(parts)
+end
+
+local json_escapes = {
+    ['"'] = '\\"', ["\\"] = "\\\\", ["\b"] = "\\b", ["\f"] = "\\f", ["\n"] = "\\n", ["\r"] = "\\r", ["\t"] = "\\t",
+}
+
+local function encode_string(text)
+    local escaped = to_utf8(text):gsub('[%c"\\]', function(c)
+        return json_escapes[c] or string.format("\\u%04x", c:byte())
+    end)
+    return '"' .. escaped .. '"'
+end
+
+local function encode(value)
+    local kind = type(value)
+    if value == nil or value == null then
+        return "null"
+    elseif kind == "boolean" then
+        return tostring(value)
+    elseif kind == "number" then
+        if value ~= value or value == math.huge or value == -math.huge then
+            return "null"
+        elseif value == math.floor(value) and math.abs(value) < 2 ^ 53 then
+            return string.format("%d", value)
+        end
+        return string.format("%.17g", value)
+    elseif kind == "string" then
+        return encode_string(value)
+    elseif getmetatable(value) == array_metatable then
+        local items = {}
+        for index = 1, #value do
+            items[index] = encode(value[index])
+        end
+        return "[" .. table.concat(items, ",") .. "]"
+    end
+    local fields = {}
+    for key, field in pairs(value) do
+        fields[#fields + 1] = encode_string(tostring(key)) .. ":" .. encode(field)
+    end
+    return "{" .. table.concat(fields, ",") .. "}"
+end
+
+local function decode_error(at, problem)
+    error(string.format("Invalid JSON at %d: %s", at, problem), 0)
+end
+
+local function skip_space(text, at)
+    return text:find("[^ \t\r\n]", at) or #text + 1
+end
+
+local json_unescapes = {
+    ['"'] = '"', ["\\"] = "\\", ["/"] = "/", b = "\b", f = "\f", n = "\n", r = "\r", t = "\t",
+}
+
+local function utf8_char(code)
+    if code < 0x80 then
+        return string.char(code)
+    elseif code < 0x800 then
+        return string.char(0xC0 + math.floor(code / 0x40), 0x80 + code % 0x40)
+    elseif code < 0x10000 then
+        return string.char(0xE0 + math.floor(code / 0x1000), 0x80 + math.floor(code / 0x40) % 0x40, 0x80 + code % 0x40)
+    end
+    return string.char(
+        0xF0 + math.floor(code / 0x40000),
+        0x80 + math.floor(code / 0x1000) % 0x40,
+        0x80 + math.floor(code / 0x40) % 0x40,
+        0x80 + code % 0x40
+    )
+end
+
+-- Decodes the string whose opening quote is at `at`
+local function decode_string(text, at)
+    local parts, from = {}, at + 1
+    while true do
+        local special = text:find('["\\]', from)
+        if not special then
+            decode_error(at, "unterminated string")
+        end
+        parts[#parts + 1] = text:sub(from, special - 1)
+        if text:sub(special, special) == '"' then
+            return table.concat(parts), special + 1
+        end
+        local escape = text:sub(special + 1, special + 1)
+        if escape == "u" then
+            local code = tonumber(text:sub(special + 2, special + 5), 16)
+            if not code then
+                decode_error(special, "invalid \\u escape")
+            end
+            from = special + 6
+            if code >= 0xD800 and code < 0xDC00 and text:sub(from, from + 1) == "\\u" then
+                local low = tonumber(text:sub(from + 2, from + 5), 16)
+                if low and low >= 0xDC00 and low < 0xE000 then
+                    code = 0x10000 + (code - 0xD800) * 0x400 + (low - 0xDC00)
+                    from = from + 6
+                end
+            end
+            parts[#parts + 1] = utf8_char(code)
+        else
+            parts[#parts + 1] = json_unescapes[escape] or decode_error(special, "invalid escape")
+            from = special + 2
+        end
+    end
+end
+
+local decode_value
+
+local function decode_object(text, at)
+    local object = {}
+    at = skip_space(text, at + 1)
+    if text:sub(at, at) == "}" then
+        return object, at + 1
+    end
+    while true do
+        at = skip_space(text, at)
+        if text:sub(at, at) ~= '"' then
+            decode_error(at, "expected a key")
+        end
+        local key, value
+        key, at = decode_string(text, at)
+        at = skip_space(text, at)
+        if text:sub(at, at) ~= ":" then
+            decode_error(at, "expected ':'")
+        end
+        value, at = decode_value(text, at + 1)
+        object[key] = value
+        at = skip_space(text, at)
+        local delimiter = text:sub(at, at)
+        if delimiter == "}" then
+            return object, at + 1
+        elseif delimiter ~= "," then
+            decode_error(at, "expected ',' or '}'")
+        end
+        at = at + 1
+    end
+end
+
+local function decode_array(text, at)
+    local items, count = array(), 0
+    at = skip_space(text, at + 1)
+    if text:sub(at, at) == "]" then
+        return items, at + 1
+    end
+    while true do
+        local item
+        item, at = decode_value(text, at)
+        count = count + 1
+        items[count] = item
+        at = skip_space(text, at)
+        local delimiter = text:sub(at, at)
+        if delimiter == "]" then
+            return items, at + 1
+        elseif delimiter ~= "," then
+            decode_error(at, "expected ',' or ']'")
+        end
+        at = at + 1
+    end
+end
+
+local literals = { ["true"] = true, ["false"] = false }
+
+function decode_value(text, at)
+    at = skip_space(text, at)
+    local first = text:sub(at, at)
+    if first == "{" then
+        return decode_object(text, at)
+    elseif first == "[" then
+        return decode_array(text, at)
+    elseif first == '"' then
+        return decode_string(text, at)
+    end
+    for literal, value in pairs(literals) do
+        if text:sub(at, at + #literal - 1) == literal then
+            return value, at + #literal
+        end
+    end
+    if text:sub(at, at + 3) == "null" then
+        return nil, at + 4
+    end
+    local number_end = select(2, text:find("^-?%d+%.?%d*[eE]?[-+]?%d*", at))
+    local number = number_end and tonumber(text:sub(at, number_end))
+    if not number then
+        decode_error(at, "unexpected character")
+    end
+    return number, number_end + 1
+end
+
+local function decode(text)
+    return (decode_value(text, 1))
+end
+
+-- Connection
+
+local connection
+local buffer = ""
+
+-- Sends one message, waiting while the socket is full; false once the debugger has gone
+local function send(message)
+    if not connection then
+        return false
+    end
+    local body = encode(message)
+    local frame = "Content-Length: " .. #body .. "\r\n\r\n" .. body
+    local sent = 0
+    while sent < #frame do
+        local last, problem, partial_last = connection:send(frame, sent + 1)
+        if last then
+            sent = last
+        elseif problem == "timeout" then
+            sent = partial_last
+            socket.select(nil, { connection }, nil)
+        else
+            return false
+        end
+    end
+    return true
+end
+
+local function send_event(event, body)
+    return send({ type = "event", event = event, body = body or {} })
+end
+
+-- Reads whatever has arrived without waiting; false once the debugger has gone
+local function read_available()
+    local data, problem, partial = connection:receive(65536)
+    local received = data or partial
+    if received and #received > 0 then
+        buffer = buffer .. received
+    end
+    return problem ~= "closed"
+end
+
+-- Takes one message out of what has arrived, if a whole one has
+local function take_message()
+    local header_end = buffer:find("\r\n\r\n", 1, true)
+    if not header_end then
+        return nil
+    end
+    local length = tonumber(buffer:sub(1, header_end - 1):match("Content%-Length:%s*(%d+)"))
+    if not length then
+        error("Message without a Content-Length header", 0)
+    end
+    local body_start = header_end + 4
+    if #buffer < body_start + length - 1 then
+        return nil
+    end
+    local body = buffer:sub(body_start, body_start + length - 1)
+    buffer = buffer:sub(body_start + length)
+    return decode(body)
+end
+
+-- Debugger state
+
+local config
+local breakpoints = {}
+local lines = {}
+local next_breakpoint_id = 1
+local stop_on_exceptions = false
+local stop_on_entry = false
+local pause_requested = false
+local step
+local polls = 0
+local started = false
+local closing = false
+
+-- Set while stopped
+local serving = false
+local resumed = false
+local stopped_depth
+local frames = {}
+local handles = {}
+local exception
+
+-- Functions of the bootstrap game, hidden like the agent's own
+local bootstrap = {}
+
+local handlers = {}
+
+-- The file in the game directory a chunk was loaded from; nil for chunks that are not files
+local function chunk_path(source)
+    if source:sub(1, 1) ~= "@" then
+        return nil
+    end
+    local path = source:sub(2):gsub("\\", "/"):gsub("^%./", "")
+    if path:sub(1, 1) == "/" or path:match("^%a:") then
+        return path
+    end
+    return config.game_dir .. "/" .. path
+end
+
+-- The name LÖVE gives the chunk of the file at `path`
+local function chunk_name(path)
+    path = path:gsub("\\", "/")
+    local prefix = config.game_dir .. "/"
+    if path:sub(1, #prefix) == prefix then
+        return "@" .. path:sub(#prefix + 1)
+    end
+    return "@" .. path
+end
+
+local function fail(code, message)
+    error({ code = code, message = message }, 0)
+end
+
+local function hidden(info)
+    return info.source == own_source or bootstrap[info.func]
+end
+
+-- Frames above the caller of the function calling this, for comparing how deep steps are
+local function stack_depth()
+    local level = 3
+    while debug.getinfo(level, "l") do
+        level = level + 1
+    end
+    return level
+end
+
+-- Captures the stack below the agent with each frame's variables, as the client sees it while stopped
+--
+-- With `from_game`, frames before the first one running a file of the game,
+-- such as `error` and LÖVE's own error handling, are left out.
+local function capture_stack(from_game)
+    frames = {}
+    local level = 2
+    while true do
+        local info = debug.getinfo(level, "nSlf")
+        if not info then
+            break
+        end
+        local include = not hidden(info)
+        if include and from_game then
+            from_game = info.source:sub(1, 1) ~= "@"
+            include = not from_game
+        end
+        if include then
+            local frame = { info = info, locals = {}, upvalues = {} }
+            local index = 1
+            while true do
+                local name, value = debug.getlocal(level, index)
+                if not name then
+                    break
+                end
+                -- Locals such as `(for index)` are internal to Lua
+                if name:sub(1, 1) ~= "(" then
+                    frame.locals[#frame.locals + 1] = { name = name, value = value }
+                end
+                index = index + 1
+            end
+            index = 1
+            while true do
+                local name, value = debug.getupvalue(info.func, index)
+                if not name then
+                    break
+                end
+                frame.upvalues[#frame.upvalues + 1] = { name = name, value = value }
+                index = index + 1
+            end
+            frames[#frames + 1] = frame
+        end
+        level = level + 1
+    end
+end
+
+-- Stops the game and serves requests until the debugger resumes it
+local function stop(reason, details, from_game)
+    capture_stack(from_game)
+    handles = {}
+    resumed = false
+    serving = true
+    local body = { reason = reason, threadId = 1, allThreadsStopped = true }
+    for key, value in pairs(details or {}) do
+        body[key] = value
+    end
+    send_event("stopped", body)
+    while connection and not resumed do
+        local message = agent.wait_message()
+        if message then
+            agent.handle(message)
+        end
+    end
+    serving = false
+    frames = {}
+    handles = {}
+end
+
+local function resume()
+    resumed = true
+    exception = nil
+end
+
+-- Lets the game run on without the debugger
+local function disconnect()
+    debug.sethook()
+    breakpoints, lines, step = {}, {}, nil
+    stop_on_exceptions, pause_requested = false, false
+    resumed = true
+    if connection then
+        connection:close()
+        connection = nil
+    end
+end
+
+-- Waits for the next request; nil once the debugger has gone
+function agent.wait_message()
+    while connection do
+        local message = take_message()
+        if message then
+            return message
+        end
+        socket.select({ connection }, nil, nil)
+        if not read_available() then
+            disconnect()
+        end
+    end
+end
+
+-- Answers a request
+function agent.handle(message)
+    local handler = handlers[message.method]
+    local ok, result = pcall(function()
+        if not handler then
+            fail(NOT_IMPLEMENTED_CODE, message.method .. " is not supported by the LÖVE agent")
+        end
+        return handler(message.params or {})
+    end)
+    local response = { id = message.id }
+    if ok then
+        response.result = result == nil and null or result
+    elseif type(result) == "table" then
+        response.error = result
+    else
+        response.error = { code = RUNTIME_ERROR_CODE, message = tostring(result) }
+    end
+    if not send(response) or closing then
+        disconnect()
+    end
+end
+
+-- Answers the requests that have arrived while the game runs
+local function poll()
+    if not connection then
+        return
+    end
+    if not read_available() then
+        return disconnect()
+    end
+    while connection do
+        local message = take_message()
+        if not message then
+            break
+        end
+        agent.handle(message)
+    end
+end
+
+local function hook(_, line)
+    if serving then
+        return
+    end
+    polls = polls + 1
+    if polls >= POLL_INTERVAL then
+        polls = 0
+        poll()
+    end
+    local candidates = lines[line]
+    if not (candidates or step or pause_requested) then
+        return
+    end
+    local info = debug.getinfo(2, "Sf")
+    if hidden(info) then
+        return
+    end
+    local breakpoint = candidates and candidates[info.source]
+    if breakpoint then
+        step, pause_requested = nil, false
+        stopped_depth = stack_depth()
+        stop("breakpoint", { hitBreakpointIds = array({ breakpoint }) })
+    elseif pause_requested then
+        step, pause_requested = nil, false
+        stopped_depth = stack_depth()
+        stop("pause")
+    elseif step then
+        local depth = stack_depth()
+        if step.mode == "In" or depth <= step.depth - (step.mode == "Out" and 1 or 0) then
+            step = nil
+            stopped_depth = depth
+            stop("step")
+        end
+    end
+end
+
+-- Values
+
+-- Numbers a value for the client to list its contents, until the game resumes
+local function handle_for(target)
+    handles[#handles + 1] = target
+    return #handles
+end
+
+local string_escapes = { ['"'] = '\\"', ["\\"] = "\\\\", ["\n"] = "\\n", ["\r"] = "\\r", ["\t"] = "\\t" }
+
+local function describe(value)
+    if type(value) == "string" then
+        local escaped = value:gsub('[%c"\\]', function(c)
+            return string_escapes[c] or string.format("\\%03d", c:byte())
+        end)
+        return '"' .. escaped .. '"'
+    end
+    local ok, text = pcall(tostring, value)
+    return ok and tostring(text) or type(value)
+end
+
+local function variable(name, value)
+    local reference, indexed = null, null
+    if type(value) == "table" then
+        reference = handle_for({ kind = "table", value = value })
+        if #value > 0 then
+            indexed = #value
+        end
+    elseif type(value) == "function" then
+        reference = handle_for({ kind = "function", value = value })
+    end
+    return {
+        name = name,
+        value = describe(value),
+        type_ = type(value),
+        variables_reference = reference,
+        named_variables = null,
+        indexed_variables = indexed,
+    }
+end
+
+local function value_json(value)
+    local kind = type(value)
+    if kind == "nil" then
+        return "Nil"
+    elseif kind == "boolean" then
+        return { Boolean = value }
+    elseif kind == "number" then
+        if value ~= value or value == math.huge or value == -math.huge then
+            return { String = tostring(value) }
+        end
+        return { Number = value }
+    elseif kind == "string" then
+        return { String = value }
+    elseif kind == "table" then
+        return { Table = { reference = handle_for({ kind = "table", value = value }), length = #value } }
+    elseif kind == "function" then
+        return { Function = { reference = handle_for({ kind = "function", value = value }), name = null } }
+    elseif kind == "thread" then
+        return "Thread"
+    end
+    return "UserData"
+end
+
+local function frame_json(id, frame)
+    local info = frame.info
+    local path = chunk_path(info.source)
+    local source = null
+    if path then
+        source = { name = path:match("[^/]*$"), path = path, source_reference = null }
+    end
+    local name = info.name
+        or (info.what == "main" and "main chunk")
+        or string.format("function <%s:%d>", info.short_src, info.linedefined)
+    return {
+        id = id,
+        name = name,
+        source = source,
+        line = math.max(info.currentline, 0),
+        column = 1,
+        presentation_hint = null,
+    }
+end
+
+local function frame_at(id)
+    local frame = frames[id]
+    if not frame then
+        fail(RUNTIME_ERROR_CODE, "No frame " .. tostring(id))
+    end
+    return frame
+end
+
+-- The children of a table: array elements first, then the other fields by name
+local function table_children(target, filter)
+    local children = {}
+    local length = #target
+    if filter ~= "named" then
+        for index = 1, length do
+            children[#children + 1] = { name = "[" .. index .. "]", value = rawget(target, index) }
+        end
+    end
+    if filter ~= "indexed" then
+        local named = {}
+        for key, value in next, target do
+            local is_element = type(key) == "number" and key >= 1 and key <= length and key == math.floor(key)
+            if not is_element then
+                local name = type(key) == "string" and key or "[" .. describe(key) .. "]"
+                named[#named + 1] = { name = name, value = value }
+            end
+        end
+        table.sort(named, function(a, b) return a.name < b.name end)
+        for _, child in ipairs(named) do
+            children[#children + 1] = child
+        end
+    end
+    return children
+end
+
+-- Runs an expression, or a statement, with a stopped frame's variables in scope
+--
+-- Assignments to the frame's variables change only what the debugger shows
+-- of them, not the frame itself.
+local function evaluate(expression, frame)
+    local chunk = loadstring("return " .. expression, "=expression")
+    if not chunk then
+        local problem
+        chunk, problem = loadstring(expression, "=expression")
+        if not chunk then
+            fail(RUNTIME_ERROR_CODE, problem)
+        end
+    end
+    if frame then
+        local scope = {}
+        for _, upvalue in ipairs(frame.upvalues) do
+            scope[upvalue.name] = upvalue
+        end
+        -- The last local with a name is the one in scope
+        for _, local_variable in ipairs(frame.locals) do
+            scope[local_variable.name] = local_variable
+        end
+        setfenv(chunk, setmetatable({}, {
+            __index = function(_, name)
+                local found = scope[name]
+                if found then
+                    return found.value
+                end
+                return _G[name]
+            end,
+            __newindex = function(_, name, value)
+                local found = scope[name]
+                if found then
+                    found.value = value
+                else
+                    _G[name] = value
+                end
+            end,
+        }))
+    end
+    local ok, result = pcall(chunk)
+    if not ok then
+        fail(RUNTIME_ERROR_CODE, tostring(result))
+    end
+    return result
+end
+
+-- Requests
+
+function handlers.attach()
+    return { runtime = "PUC", version = "V51" }
+end
+
+function handlers.start(params)
+    started = true
+    stop_on_entry = params.stopOnEntry == true
+end
+
+handlers.setBreakpoint = function(params)
+    local breakpoint = params.breakpoint or {}
+    local id = next_breakpoint_id
+    if breakpoint.Line then
+        local chunk = chunk_name(breakpoint.Line.source)
+        local line = breakpoint.Line.line
+        next_breakpoint_id = id + 1
+        breakpoints[id] = { chunk = chunk, line = line }
+        lines[line] = lines[line] or {}
+        lines[line][chunk] = id
+        return { id = id, verified = true, line = line, message = null }
+    elseif breakpoint.Exception then
+        -- Only errors reaching LÖVE's error handler are seen, whatever the filter
+        next_breakpoint_id = id + 1
+        stop_on_exceptions = true
+        return { id = id, verified = true, line = 0, message = null }
+    end
+    fail(NOT_IMPLEMENTED_CODE, "The LÖVE agent supports line and exception breakpoints only")
+end
+
+handlers.removeBreakpoint = function(params)
+    local breakpoint = breakpoints[params.id]
+    if breakpoint then
+        breakpoints[params.id] = nil
+        local chunks = lines[breakpoint.line]
+        chunks[breakpoint.chunk] = nil
+        if next(chunks) == nil then
+            lines[breakpoint.line] = nil
+        end
+    end
+end
+
+handlers.step = function(params)
+    step = { mode = stopped_depth and params.mode or "In", depth = stopped_depth }
+    resume()
+end
+
+handlers.continue = function()
+    resume()
+end
+
+handlers.pause = function()
+    pause_requested = true
+end
+
+handlers.threads = function()
+    return array({ { id = 1, name = "main" } })
+end
+
+handlers.stackTrace = function()
+    local result = array()
+    for id, frame in ipairs(frames) do
+        result[id] = frame_json(id, frame)
+    end
+    return result
+end
+
+handlers.scopes = function(params)
+    local frame = frame_at(params.frameId)
+    return array({
+        { variables_reference = handle_for({ kind = "locals", frame = frame }), name = "Locals", expensive = false },
+        { variables_reference = handle_for({ kind = "upvalues", frame = frame }), name = "Upvalues", expensive = false },
+        { variables_reference = handle_for({ kind = "globals" }), name = "Globals", expensive = true },
+    })
+end
+
+handlers.variables = function(params)
+    local target = handles[params.variablesReference]
+    if not target then
+        fail(RUNTIME_ERROR_CODE, "No variables for reference " .. tostring(params.variablesReference))
+    end
+    local page = params.page or {}
+    local children
+    if target.kind == "locals" then
+        children = target.frame.locals
+    elseif target.kind == "upvalues" then
+        children = target.frame.upvalues
+    elseif target.kind == "globals" then
+        children = table_children(_G, page.filter)
+    elseif target.kind == "function" then
+        children = {}
+        local index = 1
+        while true do
+            local name, value = debug.getupvalue(target.value, index)
+            if not name then
+                break
+            end
+            children[index] = { name = name, value = value }
+            index = index + 1
+        end
+    else
+        children = table_children(target.value, page.filter)
+    end
+    local result = array()
+    local first = (page.start or 0) + 1
+    local last = page.count and first + page.count - 1 or #children
+    for index = first, math.min(last, #children) do
+        result[#result + 1] = variable(children[index].name, children[index].value)
+    end
+    return result
+end
+
+handlers.evaluate = function(params)
+    return value_json(evaluate(params.expression, frame_at(params.frameId)))
+end
+
+handlers.evaluateGlobal = function(params)
+    return value_json(evaluate(params.expression))
+end
+
+handlers.exceptionInfo = function()
+    if not exception then
+        fail(RUNTIME_ERROR_CODE, "The game is not stopped on an error")
+    end
+    return {
+        exception_type = "error",
+        message = exception.message,
+        stack_trace = handlers.stackTrace(),
+        inner_exception = null,
+        details = null,
+    }
+end
+
+handlers.detach = function()
+    -- Disconnects once answered
+    closing = true
+end
+
+-- Errors
+
+-- The error handlers LÖVE looks up, kept apart from `love` so the game can replace them
+local error_handlers = {}
+
+local function on_error(message)
+    if connection and stop_on_exceptions then
+        local text = tostring(message)
+        exception = { message = text }
+        stopped_depth = nil
+        stop("exception", { description = text, text = text, exceptionFilter = "uncaught" }, true)
+    end
+    local handler = error_handlers.errorhandler or error_handlers.errhand
+    return handler(message)
+end
+
+-- Routes errors reaching LÖVE through `on_error`
+--
+-- LÖVE 11 looks `love.errorhandler` up when an error reaches it, and older
+-- versions `love.errhand`. Keeping the fields out of `love` and serving
+-- them from its metatable sees every handler the game installs, whenever
+-- it does.
+local function install_error_handler()
+    for _, key in ipairs({ "errorhandler", "errhand" }) do
+        error_handlers[key] = rawget(love, key)
+        rawset(love, key, nil)
+    end
+    setmetatable(love, {
+        __index = function(_, key)
+            if error_handlers[key] ~= nil then
+                return on_error
+            end
+        end,
+        __newindex = function(target, key, value)
+            if key == "errorhandler" or key == "errhand" then
+                error_handlers[key] = value
+            else
+                rawset(target, key, value)
+            end
+        end,
+    })
+end
+
+-- Entry points for the bootstrap game
+
+-- Runs the game's own `name`, from the game directory and named as LÖVE would name it
+function agent.run_file(name, ...)
+    bootstrap[debug.getinfo(2, "f").func] = true
+    local file = io.open(config.game_dir .. "/" .. name, "rb")
+    if not file then
+        return
+    end
+    local code = file:read("*a")
+    file:close()
+    local chunk, problem = loadstring(code, "@" .. name)
+    if not chunk then
+        error(problem, 0)
+    end
+    if stop_on_entry then
+        stop_on_entry = false
+        step = { mode = "In" }
+    end
+    return chunk(...)
+end
+
+-- Waits for the debugger and its breakpoints, then runs the game's `conf.lua`
+--
+-- `options` holds the `host` and `port` to listen on, the `game_dir` and
+-- the `identity` LÖVE would give the game's save directory.
+function agent.start(options, ...)
+    config = options
+    bootstrap[debug.getinfo(2, "f").func] = true
+    io.stdout:setvbuf("line")
+    if love.filesystem.setSymlinksEnabled then
+        love.filesystem.setSymlinksEnabled(true)
+    end
+
+    local server = assert(socket.bind(config.host, config.port))
+    connection = assert(server:accept())
+    server:close()
+    connection:settimeout(0)
+    connection:setoption("tcp-nodelay", true)
+    while connection and not started do
+        local message = agent.wait_message()
+        if message then
+            agent.handle(message)
+        end
+    end
+
+    if connection then
+        -- Compiled code skips line hooks
+        if jit then
+            jit.off()
+            jit.flush()
+        end
+        install_error_handler()
+        debug.sethook(hook, "l")
+    end
+
+    local results = { agent.run_file("conf.lua", ...) }
+    -- Saves go where they would without the bootstrap game around it
+    local game_conf = love.conf
+    love.conf = function(t)
+        if game_conf then
+            game_conf(t)
+        end
+        if not t.identity then
+            t.identity = config.identity
+        end
+    end
+    return unpack(results)
+end
+
+return agent
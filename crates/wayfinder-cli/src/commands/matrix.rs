@@ -0,0 +1,277 @@
+//! Matrix command implementation
+//!
+//! Runs a script under several Lua versions one after another, each in a fresh
+//! runtime, and reports where their output, errors and exit codes diverge.
+//! Every run is profiled so the report also shows where each version spent
+//! its time.
+
+use super::launch::{program_launch, resolve_script, run_profiled};
+use std::collections::HashMap;
+use wayfinder_core::runtime::lua_paths::LuaPathConfig;
+use wayfinder_core::runtime::ProgramLaunch;
+
+/// Runtimes run when neither the command line nor the config names any
+pub const DEFAULT_RUNTIMES: [&str; 4] = ["lua5.1", "lua5.2", "lua5.3", "lua5.4"];
+
+/// Functions listed per run in the report
+const HOTTEST_FUNCTIONS: usize = 3;
+
+/// Matrix configuration
+#[derive(Debug)]
+pub struct MatrixConfig {
+    /// Runtimes to run the script under, e.g. "lua5.1"
+    pub runtimes: Vec<String>,
+    /// Current working directory
+    pub cwd: Option<String>,
+    /// Environment variables
+    pub env: Option<HashMap<String, String>>,
+    /// Script to run
+    pub script: String,
+    /// `package.path` and `package.cpath` templates and source roots
+    pub lua_paths: LuaPathConfig,
+}
+
+/// What one runtime did with the script
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuntimeRun {
+    pub runtime: String,
+    /// Why the script did not run, such as a Lua library that is not installed
+    pub skipped: Option<String>,
+    pub exit_code: i64,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: f64,
+    /// Functions with the most self time, with that time in milliseconds
+    pub hottest: Vec<(String, f64)>,
+}
+
+impl RuntimeRun {
+    fn summary(&self) -> String {
+        if let Some(reason) = &self.skipped {
+            return format!("{}: skipped ({})", self.runtime, reason);
+        }
+
+        let status = if self.exit_code == 0 {
+            "ok".to_string()
+        } else {
+            format!("failed with exit code {}", self.exit_code)
+        };
+        let mut summary = format!("{}: {} in {:.1} ms", self.runtime, status, self.duration_ms);
+        if !self.hottest.is_empty() {
+            let hottest: Vec<String> = self
+                .hottest
+                .iter()
+                .map(|(name, ms)| format!("{} {:.2} ms", name, ms))
+                .collect();
+            summary.push_str(&format!("; hottest: {}", hottest.join(", ")));
+        }
+        summary
+    }
+}
+
+/// Runs the script under each runtime and prints the report
+///
+/// Returns whether every runtime that ran agreed with the first.
+pub async fn run_matrix(config: MatrixConfig) -> Result<bool, Box<dyn std::error::Error>> {
+    let script = resolve_script(&config.script, config.cwd.as_deref())?;
+
+    // Runs share the process, so the directory and environment are set once for all
+    if let Some(cwd) = &config.cwd {
+        std::env::set_current_dir(cwd)?;
+    }
+    if let Some(env_vars) = &config.env {
+        for (key, value) in env_vars {
+            std::env::set_var(key, value);
+        }
+    }
+    let launch = program_launch(&script, &config.lua_paths)?;
+
+    let runtimes = if config.runtimes.is_empty() {
+        DEFAULT_RUNTIMES.iter().map(|r| r.to_string()).collect()
+    } else {
+        config.runtimes
+    };
+
+    let mut runs = Vec::new();
+    for runtime in &runtimes {
+        eprintln!("Running {} under {}", script.display(), runtime);
+        runs.push(run_one(runtime, &launch).await);
+    }
+
+    println!("{}", report(&runs));
+    let divergences = find_divergences(&runs);
+    Ok(divergences.is_empty())
+}
+
+async fn run_one(runtime: &str, launch: &ProgramLaunch) -> RuntimeRun {
+    let mut run = RuntimeRun {
+        runtime: runtime.to_string(),
+        ..RuntimeRun::default()
+    };
+    let lua = match crate::create_puc_lua_runtime_for(runtime) {
+        Ok(lua) => lua,
+        Err(e) => {
+            run.skipped = Some(e);
+            return run;
+        }
+    };
+
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    let result = run_profiled(lua, launch, |category, output| {
+        if category == "stderr" {
+            stderr.push_str(output);
+        } else {
+            stdout.push_str(output);
+        }
+    })
+    .await;
+    run.stdout = stdout;
+    run.stderr = stderr;
+
+    match result {
+        Ok(profiled) => {
+            run.exit_code = profiled.exit_code;
+            run.duration_ms = profiled.profile.duration_ms;
+
+            let mut functions: Vec<_> = profiled.profile.functions.into_values().collect();
+            functions.sort_by(|a, b| b.self_time_ms.total_cmp(&a.self_time_ms));
+            run.hottest = functions
+                .into_iter()
+                .take(HOTTEST_FUNCTIONS)
+                .map(|f| (f.name, f.self_time_ms))
+                .collect();
+        }
+        // Failing to load, such as on syntax another version lacks, is a result like any other
+        Err(e) => {
+            run.exit_code = 1;
+            run.stderr.push_str(&e.to_string());
+        }
+    }
+    run
+}
+
+/// Renders the per-runtime summaries followed by the divergences
+pub fn report(runs: &[RuntimeRun]) -> String {
+    let mut lines: Vec<String> = runs.iter().map(RuntimeRun::summary).collect();
+
+    let divergences = find_divergences(runs);
+    lines.push(String::new());
+    if divergences.is_empty() {
+        lines.push("No divergences".to_string());
+    } else {
+        lines.push(format!("{} divergence(s):", divergences.len()));
+        lines.extend(divergences.iter().map(|d| format!("  {}", d)));
+    }
+    lines.join("\n")
+}
+
+/// Differences between each run and the first run that was not skipped
+pub fn find_divergences(runs: &[RuntimeRun]) -> Vec<String> {
+    let mut ran = runs.iter().filter(|run| run.skipped.is_none());
+    let Some(baseline) = ran.next() else {
+        return Vec::new();
+    };
+
+    let mut divergences = Vec::new();
+    for run in ran {
+        if run.exit_code != baseline.exit_code {
+            divergences.push(format!(
+                "{} exited with {}, {} with {}",
+                baseline.runtime, baseline.exit_code, run.runtime, run.exit_code
+            ));
+        }
+        if let Some((line, expected, actual)) = first_difference(&baseline.stdout, &run.stdout) {
+            divergences.push(format!(
+                "output differs at line {}: {} printed {}, {} printed {}",
+                line,
+                baseline.runtime,
+                describe_line(expected),
+                run.runtime,
+                describe_line(actual)
+            ));
+        }
+        if let Some((line, expected, actual)) = first_difference(&baseline.stderr, &run.stderr) {
+            divergences.push(format!(
+                "errors differ at line {}: {} reported {}, {} reported {}",
+                line,
+                baseline.runtime,
+                describe_line(expected),
+                run.runtime,
+                describe_line(actual)
+            ));
+        }
+    }
+    divergences
+}
+
+/// First line, counted from 1, where two outputs differ
+fn first_difference<'a>(a: &'a str, b: &'a str) -> Option<(usize, Option<&'a str>, Option<&'a str>)> {
+    let mut a_lines = a.lines();
+    let mut b_lines = b.lines();
+    let mut line = 1;
+    loop {
+        match (a_lines.next(), b_lines.next()) {
+            (None, None) => return None,
+            (a, b) if a != b => return Some((line, a, b)),
+            _ => line += 1,
+        }
+    }
+}
+
+fn describe_line(line: Option<&str>) -> String {
+    match line {
+        Some(line) => format!("{:?}", line),
+        None => "nothing".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(runtime: &str, exit_code: i64, stdout: &str) -> RuntimeRun {
+        RuntimeRun {
+            runtime: runtime.to_string(),
+            exit_code,
+            stdout: stdout.to_string(),
+            ..RuntimeRun::default()
+        }
+    }
+
+    #[test]
+    fn test_first_difference() {
+        assert_eq!(first_difference("a\nb\n", "a\nb\n"), None);
+        assert_eq!(first_difference("a\nb\n", "a\nc\n"), Some((2, Some("b"), Some("c"))));
+        assert_eq!(first_difference("a\n", "a\nb\n"), Some((2, None, Some("b"))));
+    }
+
+    #[test]
+    fn test_find_divergences_against_first_run() {
+        let skipped = RuntimeRun {
+            runtime: "lua5.2".to_string(),
+            skipped: Some("not installed".to_string()),
+            ..RuntimeRun::default()
+        };
+        let runs = vec![
+            run("lua5.1", 0, "1\n2\n"),
+            skipped,
+            run("lua5.3", 0, "1\n2\n"),
+            run("lua5.4", 1, "1\n2.0\n"),
+        ];
+
+        let divergences = find_divergences(&runs);
+        assert_eq!(
+            divergences,
+            vec![
+                "lua5.1 exited with 0, lua5.4 with 1",
+                "output differs at line 2: lua5.1 printed \"2\", lua5.4 printed \"2.0\"",
+            ]
+        );
+
+        let text = report(&runs);
+        assert!(text.contains("lua5.2: skipped (not installed)"));
+        assert!(text.contains("2 divergence(s):"));
+        assert!(find_divergences(&runs[..3]).is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+//! Memory command implementation
+//!
+//! Compares heap snapshots written by the `heapSnapshot` request and reports
+//! which kinds of objects grew between them, to track down leaks.
+
+use std::path::Path;
+use wayfinder_core::memory::diff::{self, growth_by_type};
+use wayfinder_core::memory::{HeapSnapshot, SnapshotDiff};
+use wayfinder_core::serializer::Serializer;
+
+/// Reads a snapshot, decoding it as its file extension implies
+pub fn read_snapshot(path: &Path) -> Result<HeapSnapshot, Box<dyn std::error::Error>> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let serializer = Serializer::for_path(path).unwrap_or_default();
+    let snapshot = serializer
+        .from_slice(&bytes)
+        .map_err(|e| format!("{} is not a {} heap snapshot: {}", path.display(), serializer, e))?;
+    Ok(snapshot)
+}
+
+/// Prints the growth from the older snapshot to the newer one
+pub fn diff_snapshots(old: &Path, new: &Path, top: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let old = read_snapshot(old)?;
+    let new = read_snapshot(new)?;
+    println!("{}", report(&diff::diff(&old, &new), top));
+    Ok(())
+}
+
+/// Renders the growth per type, then the `top` largest new objects
+pub fn report(diff: &SnapshotDiff, top: usize) -> String {
+    let mut lines = vec![format!(
+        "Snapshot {} -> {}: memory {:+.1} KB",
+        diff.from_id, diff.to_id, diff.memory_delta_kb
+    )];
+
+    lines.push(String::new());
+    lines.push(format!(
+        "{:<10} {:>8} {:>8} {:>8} {:>12}",
+        "type", "count", "new", "freed", "net bytes"
+    ));
+    for growth in growth_by_type(diff) {
+        lines.push(format!(
+            "{:<10} {:>+8} {:>8} {:>8} {:>+12}",
+            growth.type_name,
+            growth.count_delta,
+            growth.new_count,
+            growth.deleted_count,
+            growth.net_bytes()
+        ));
+    }
+
+    let mut largest: Vec<_> = diff.new_objects.iter().collect();
+    largest.sort_by(|a, b| b.size_estimate.cmp(&a.size_estimate).then_with(|| a.address.cmp(&b.address)));
+    if top > 0 && !largest.is_empty() {
+        lines.push(String::new());
+        lines.push("Largest new objects:".to_string());
+        for object in largest.into_iter().take(top) {
+            lines.push(format!(
+                "  {} {} ~{} bytes",
+                object.type_name, object.address, object.size_estimate
+            ));
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use wayfinder_core::memory::ObjectInfo;
+
+    fn object(id: i64, type_name: &str, size_estimate: usize) -> ObjectInfo {
+        ObjectInfo {
+            id,
+            type_name: type_name.to_string(),
+            size_estimate,
+            address: format!("0x{:x}", id),
+        }
+    }
+
+    #[test]
+    fn test_report_lists_growth_and_largest_objects() {
+        let diff = SnapshotDiff {
+            from_id: 1,
+            to_id: 2,
+            memory_delta_kb: 12.5,
+            object_count_deltas: HashMap::from([("table".to_string(), 2), ("function".to_string(), -1)]),
+            new_objects: vec![object(0x30, "table", 56), object(0x40, "table", 2048)],
+            deleted_objects: vec![object(0x20, "function", 40)],
+        };
+
+        let text = report(&diff, 1);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "Snapshot 1 -> 2: memory +12.5 KB");
+        assert!(lines[3].starts_with("table"));
+        assert!(lines[3].ends_with("+2104"));
+        assert!(lines[4].starts_with("function"));
+        assert!(text.ends_with("Largest new objects:\n  table 0x40 ~2048 bytes"));
+    }
+}
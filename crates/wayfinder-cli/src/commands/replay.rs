@@ -0,0 +1,87 @@
+//! Replay command implementation
+//!
+//! Replays the requests of a session recorded with `--trace-dap` against a
+//! fresh in-process server and reports the requests it answered differently,
+//! to reproduce an adapter bug without the editor that ran into it.
+
+use std::path::PathBuf;
+use std::time::Duration;
+use wayfinder_core::runtime::puc_lua::PUCLuaRuntime;
+use wayfinder_core::session::replay::{self, ReplayReport};
+use wayfinder_core::session::trace;
+use wayfinder_core::session::DapServer;
+
+/// Replay configuration
+#[derive(Debug)]
+pub struct ReplayConfig {
+    /// Recording to replay
+    pub recording: PathBuf,
+    /// Runtime to use (e.g., "lua5.1")
+    pub runtime: Option<String>,
+    /// Write the replayed session to this file, in the format of a recording
+    pub transcript: Option<PathBuf>,
+    /// Time to wait for each response or event
+    pub timeout: Duration,
+}
+
+/// Replays a recording, returning whether every request was answered as recorded
+pub async fn replay_recording(config: ReplayConfig) -> Result<bool, Box<dyn std::error::Error>> {
+    let entries = trace::read_recording(&config.recording)?;
+
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(crate::create_puc_lua_runtime(config.runtime.as_deref()));
+    let report = replay::replay(&mut server, &entries, config.timeout).await?;
+
+    if let Some(path) = &config.transcript {
+        trace::write_recording(path, &report.transcript)?;
+    }
+    println!("{}", summary(&report));
+    Ok(report.matches())
+}
+
+/// Formats what the replay found, one line per difference
+fn summary(report: &ReplayReport) -> String {
+    let mut lines = vec![format!(
+        "Replayed {} requests: {}",
+        report.requests,
+        if report.matches() { "all answered as recorded" } else { "differences found" }
+    )];
+    for divergence in &report.divergences {
+        lines.push(format!(
+            "  #{} {}: recorded {}, replayed {}",
+            divergence.seq, divergence.command, divergence.recorded, divergence.replayed
+        ));
+    }
+    for (event, recorded, replayed) in &report.missing_events {
+        lines.push(format!("  {} event: recorded {}, replayed {}", event, recorded, replayed));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wayfinder_core::session::replay::Divergence;
+
+    #[test]
+    fn test_summary_lists_differences() {
+        let report = ReplayReport {
+            requests: 3,
+            divergences: vec![Divergence {
+                seq: 2,
+                command: "stackTrace".to_string(),
+                recorded: "success".to_string(),
+                replayed: "error: No active session".to_string(),
+            }],
+            missing_events: vec![("stopped".to_string(), 2, 1)],
+            transcript: Vec::new(),
+        };
+        assert_eq!(
+            summary(&report),
+            "Replayed 3 requests: differences found\n  \
+             #2 stackTrace: recorded success, replayed error: No active session\n  \
+             stopped event: recorded 2, replayed 1"
+        );
+        assert_eq!(summary(&ReplayReport::default()), "Replayed 0 requests: all answered as recorded");
+    }
+}
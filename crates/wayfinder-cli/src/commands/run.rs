@@ -0,0 +1,391 @@
+//! Run command implementation
+//!
+//! Runs a script in-process under the debugger with no client attached, for
+//! scripted debugging in CI. Breakpoints come from the command line; at each
+//! stop the location is printed and the given expressions are evaluated in
+//! the stopped frame. The command drives the DAP server over an in-memory
+//! connection, so it behaves as an editor session would.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde_json::{json, Value as JsonValue};
+use tokio::io::{AsyncBufRead, AsyncWrite, BufReader};
+use wayfinder_core::dap::transport::DapTransport;
+use wayfinder_core::dap::Response;
+use wayfinder_core::runtime::lua_paths::LuaPathConfig;
+use wayfinder_core::runtime::puc_lua::PUCLuaRuntime;
+use wayfinder_core::runtime::DebugRuntime;
+use wayfinder_core::session::DapServer;
+
+use super::launch::{program_launch, resolve_script, resolve_source_path};
+
+/// Run configuration
+#[derive(Debug)]
+pub struct RunConfig {
+    /// Runtime to use (e.g., "lua5.1")
+    pub runtime: Option<String>,
+    /// Current working directory
+    pub cwd: Option<String>,
+    /// Environment variables
+    pub env: Option<HashMap<String, String>>,
+    /// Script to run
+    pub script: String,
+    /// Line breakpoints to set before the script starts
+    pub breakpoints: Vec<BreakpointSpec>,
+    /// Expressions evaluated in the stopped frame at each stop
+    pub evaluations: Vec<String>,
+    /// Resume after each stop; otherwise the run ends at the first
+    pub continue_after_break: bool,
+    /// `package.path` and `package.cpath` templates and source roots
+    pub lua_paths: LuaPathConfig,
+    /// JSONL file to record every DAP message to
+    pub trace_dap: Option<PathBuf>,
+}
+
+/// A line breakpoint given as `FILE:LINE`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakpointSpec {
+    pub source: PathBuf,
+    pub line: u32,
+}
+
+impl FromStr for BreakpointSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        // The line follows the last colon, so Windows drive letters survive
+        let invalid = || format!("Invalid breakpoint `{}`: expected FILE:LINE", spec);
+        let (source, line) = spec.rsplit_once(':').ok_or_else(invalid)?;
+        let line = line.parse::<u32>().ok().filter(|&line| line > 0).ok_or_else(invalid)?;
+        if source.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            source: PathBuf::from(source),
+            line,
+        })
+    }
+}
+
+/// What a headless run does once the script is loaded
+#[derive(Debug, Clone, Default)]
+pub struct RunPlan {
+    /// Path the script was loaded from
+    pub program: String,
+    pub breakpoints: Vec<BreakpointSpec>,
+    pub evaluations: Vec<String>,
+    pub continue_after_break: bool,
+}
+
+/// Runs a script headlessly, returning its exit code
+pub async fn run_script(config: RunConfig) -> Result<i64, Box<dyn std::error::Error>> {
+    let script = resolve_script(&config.script, config.cwd.as_deref())?;
+    // Resolve the trace before changing directory, so it is relative to where we were run
+    let trace_dap = config.trace_dap.as_deref().map(std::path::absolute).transpose()?;
+
+    if let Some(cwd) = &config.cwd {
+        tracing::debug!("Working directory: {}", cwd);
+        std::env::set_current_dir(cwd)?;
+    }
+    // The script runs in this process, so its environment is ours
+    if let Some(env_vars) = &config.env {
+        for (key, value) in env_vars {
+            tracing::debug!("Setting env: {}={}", key, value);
+            std::env::set_var(key, value);
+        }
+    }
+
+    let launch = program_launch(&script, &config.lua_paths)?;
+    let mut runtime = crate::create_puc_lua_runtime(config.runtime.as_deref());
+    runtime
+        .load_launch(&launch)
+        .map_err(|e| format!("Failed to load {}: {}", script.display(), e))?;
+
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(runtime);
+
+    let plan = RunPlan {
+        program: launch.program,
+        breakpoints: config.breakpoints.into_iter().map(resolve_breakpoint).collect(),
+        evaluations: config.evaluations,
+        continue_after_break: config.continue_after_break,
+    };
+    run_headless(&mut server, &plan, trace_dap.as_deref(), |category, output| {
+        if category == "stderr" {
+            eprint!("{}", output);
+        } else {
+            print!("{}", output);
+            let _ = std::io::Write::flush(&mut std::io::stdout());
+        }
+    })
+    .await
+}
+
+fn resolve_breakpoint(breakpoint: BreakpointSpec) -> BreakpointSpec {
+    BreakpointSpec {
+        source: resolve_source_path(&breakpoint.source),
+        ..breakpoint
+    }
+}
+
+/// Drives `server`, which has the program loaded, through a run without a client
+///
+/// The script's output and a line per stop and evaluation are handed to
+/// `on_output` with their category (`stdout`, `stderr` or `console`). Returns
+/// the script's exit code, or 0 when the run ended at a stop.
+pub async fn run_headless<R: DebugRuntime>(
+    server: &mut DapServer<R>,
+    plan: &RunPlan,
+    trace_dap: Option<&Path>,
+    on_output: impl FnMut(&str, &str),
+) -> Result<i64, Box<dyn std::error::Error>> {
+    let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+    let (client_read, client_write) = tokio::io::split(client_end);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+    super::dap::record_trace(&mut transport, trace_dap)?;
+    let client = HeadlessClient {
+        transport: DapTransport::new(BufReader::new(client_read), client_write),
+        next_seq: 1,
+        events: VecDeque::new(),
+        on_output,
+    };
+
+    let served = async move {
+        // Owned here so the client reads the end of the stream when the loop finishes
+        let mut transport = transport;
+        server.run_event_loop(&mut transport).await.map_err(|e| e.to_string())
+    };
+    let (served, exit_code) = tokio::join!(served, drive(client, plan));
+    served?;
+    Ok(exit_code?)
+}
+
+async fn drive<Rd, Wr>(
+    mut client: HeadlessClient<Rd, Wr, impl FnMut(&str, &str)>,
+    plan: &RunPlan,
+) -> Result<i64, String>
+where
+    Rd: AsyncBufRead + Unpin,
+    Wr: AsyncWrite + Unpin,
+{
+    let exit_code = run_plan(&mut client, plan).await;
+    // Ends the server loop whatever happened, leaving nothing running
+    let disconnected = client.request("disconnect", json!({ "terminateDebuggee": true })).await;
+    let exit_code = exit_code?;
+    disconnected?;
+    Ok(exit_code)
+}
+
+async fn run_plan<Rd, Wr>(client: &mut HeadlessClient<Rd, Wr, impl FnMut(&str, &str)>, plan: &RunPlan) -> Result<i64, String>
+where
+    Rd: AsyncBufRead + Unpin,
+    Wr: AsyncWrite + Unpin,
+{
+    client.request("initialize", json!({ "clientID": "wayfinder-run", "adapterID": "wayfinder" })).await?;
+    client.request("launch", json!({ "program": plan.program })).await?;
+
+    let mut by_source: BTreeMap<&Path, Vec<u32>> = BTreeMap::new();
+    for breakpoint in &plan.breakpoints {
+        by_source.entry(breakpoint.source.as_path()).or_default().push(breakpoint.line);
+    }
+    for (source, lines) in by_source {
+        let breakpoints: Vec<JsonValue> = lines.iter().map(|line| json!({ "line": line })).collect();
+        let result = client
+            .request(
+                "setBreakpoints",
+                json!({ "source": { "path": source.display().to_string() }, "breakpoints": breakpoints }),
+            )
+            .await?;
+        for (line, breakpoint) in lines.iter().zip(result["breakpoints"].as_array().into_iter().flatten()) {
+            if breakpoint["verified"] == false {
+                let reason = breakpoint["message"].as_str().unwrap_or("not verified");
+                client.console(&format!("Breakpoint {}:{} may not be hit: {}", source.display(), line, reason));
+            }
+        }
+    }
+    client.request("configurationDone", json!({})).await?;
+
+    let mut exit_code = 0;
+    while let Some(event) = client.next_event().await {
+        let body = &event["body"];
+        match event["event"].as_str().unwrap_or_default() {
+            "output" => {
+                let category = body["category"].as_str().unwrap_or("stdout");
+                (client.on_output)(category, body["output"].as_str().unwrap_or_default());
+            }
+            "stopped" => {
+                client.report_stop(body, &plan.evaluations).await?;
+                if !plan.continue_after_break {
+                    client.console("Ending the run at the first stop (pass --continue to resume after stops)");
+                    return Ok(0);
+                }
+                client.request("continue", json!({ "threadId": body["threadId"] })).await?;
+            }
+            "exited" => exit_code = body["exitCode"].as_i64().unwrap_or_default(),
+            "terminated" => break,
+            _ => {}
+        }
+    }
+    Ok(exit_code)
+}
+
+/// The headless end of the connection
+struct HeadlessClient<Rd, Wr, F> {
+    transport: DapTransport<Rd, Wr>,
+    next_seq: u64,
+    /// Events that came while waiting for a response, handled after it
+    events: VecDeque<JsonValue>,
+    on_output: F,
+}
+
+impl<Rd, Wr, F> HeadlessClient<Rd, Wr, F>
+where
+    Rd: AsyncBufRead + Unpin,
+    Wr: AsyncWrite + Unpin,
+    F: FnMut(&str, &str),
+{
+    fn console(&mut self, line: &str) {
+        (self.on_output)("console", &format!("{}\n", line));
+    }
+
+    /// Sends a request and returns the result of its response
+    async fn request(&mut self, command: &str, arguments: JsonValue) -> Result<JsonValue, String> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let message = json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments });
+        self.transport
+            .write_message(&message)
+            .await
+            .map_err(|e| format!("Cannot send {}: {}", command, e))?;
+
+        loop {
+            let message = self
+                .receive()
+                .await?
+                .ok_or_else(|| format!("The debugger stopped before answering {}", command))?;
+            if message.get("event").is_some() {
+                self.events.push_back(message);
+            } else if let Some(response) = Response::from_json(&message).filter(|r| r.request_seq == seq) {
+                return response.result.map_err(|error| format!("{} failed: {}", command, error.message));
+            }
+        }
+    }
+
+    /// The next event, None once the server stops sending
+    async fn next_event(&mut self) -> Option<JsonValue> {
+        if let Some(event) = self.events.pop_front() {
+            return Some(event);
+        }
+        loop {
+            match self.receive().await {
+                Ok(Some(message)) if message.get("event").is_some() => return Some(message),
+                Ok(Some(_)) => continue,
+                Ok(None) => return None,
+                Err(e) => {
+                    tracing::warn!("{}", e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    async fn receive(&mut self) -> Result<Option<JsonValue>, String> {
+        self.transport
+            .read_message()
+            .await
+            .map_err(|e| format!("Cannot read from the debugger: {}", e))
+    }
+
+    /// Prints where the program stopped and evaluates the expressions in its top frame
+    async fn report_stop(&mut self, stopped: &JsonValue, evaluations: &[String]) -> Result<(), String> {
+        let reason = stopped["reason"].as_str().unwrap_or("pause");
+        let trace = self
+            .request("stackTrace", json!({ "threadId": stopped["threadId"], "levels": 1 }))
+            .await;
+        let frame = trace.ok().and_then(|trace| trace["stackFrames"].get(0).cloned());
+
+        let mut location = match &frame {
+            Some(frame) => {
+                let source = frame["source"]["path"].as_str().or_else(|| frame["source"]["name"].as_str());
+                format!("{}:{} in {}", source.unwrap_or("?"), frame["line"], frame["name"].as_str().unwrap_or("?"))
+            }
+            None => "an unknown location".to_string(),
+        };
+        if let Some(description) = stopped["description"].as_str() {
+            location = format!("{}: {}", location, description);
+        }
+        self.console(&format!("Stopped at {} ({})", location, reason));
+
+        // Without a frame the expressions are evaluated against the globals
+        let frame_id = frame.map(|frame| frame["id"].clone()).unwrap_or(JsonValue::Null);
+        for expression in evaluations {
+            let result = self
+                .request("evaluate", json!({ "expression": expression, "frameId": frame_id, "context": "repl" }))
+                .await;
+            match result {
+                Ok(result) => self.console(&format!("  {} = {}", expression, result["result"].as_str().unwrap_or(""))),
+                Err(e) => self.console(&format!("  {}: {}", expression, e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_breakpoint_spec() {
+        let spec: BreakpointSpec = "src/foo.lua:12".parse().unwrap();
+        assert_eq!(spec, BreakpointSpec { source: PathBuf::from("src/foo.lua"), line: 12 });
+        let spec: BreakpointSpec = r"C:\game\main.lua:3".parse().unwrap();
+        assert_eq!(spec.source, PathBuf::from(r"C:\game\main.lua"));
+
+        for invalid in ["foo.lua", "foo.lua:0", "foo.lua:x", ":4"] {
+            let error = invalid.parse::<BreakpointSpec>().unwrap_err();
+            assert!(error.contains("expected FILE:LINE"), "{}", error);
+        }
+    }
+
+    #[cfg(feature = "static-lua")]
+    #[tokio::test]
+    async fn test_headless_run_evaluates_at_each_stop() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("count.lua");
+        std::fs::write(&script, "local total = 0\nfor i = 1, 2 do\n  total = total + i\nend\nprint(total)\n").unwrap();
+        let program = script.display().to_string();
+
+        let mut runtime = PUCLuaRuntime::new();
+        runtime
+            .load_launch(&wayfinder_core::runtime::ProgramLaunch {
+                program: program.clone(),
+                args: Vec::new(),
+                lua_path: None,
+                lua_cpath: None,
+            })
+            .unwrap();
+        let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+        server.set_runtime(runtime);
+
+        let plan = RunPlan {
+            program: program.clone(),
+            breakpoints: vec![BreakpointSpec { source: script.clone(), line: 3 }],
+            evaluations: vec!["total".to_string()],
+            continue_after_break: true,
+        };
+        let mut output = String::new();
+        let exit_code = run_headless(&mut server, &plan, None, |_, text| output.push_str(text))
+            .await
+            .unwrap();
+
+        assert_eq!(exit_code, 0);
+        let stops: Vec<&str> = output.lines().filter(|line| line.starts_with("Stopped at")).collect();
+        assert_eq!(stops.len(), 2, "{}", output);
+        assert!(stops[0].starts_with(&format!("Stopped at {}:3", program)), "{}", output);
+        assert!(output.contains("  total = 0\n") && output.contains("  total = 1\n"), "{}", output);
+        assert!(output.ends_with("3\n"), "{}", output);
+    }
+}
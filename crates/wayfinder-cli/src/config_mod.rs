@@ -6,13 +6,26 @@
 use home::home_dir;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use wayfinder_core::config::DebuggerSettings;
+use wayfinder_core::hot_reload::WatcherConfig;
+use wayfinder_core::runtime::lua_paths::LuaPathConfig;
+use wayfinder_core::session::launch_arguments::LaunchArguments;
+use wayfinder_core::session::rewrite_rules::RewriteRule;
+use wayfinder_core::session::OnShutdown;
+
+/// Default time allowed for an attach target to accept the connection
+pub const DEFAULT_ATTACH_TIMEOUT_MS: u64 = 10_000;
 
 /// Main configuration structure
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     /// Runtime to use (e.g., "lua5.1", "lua5.2", "lua5.3", "lua5.4")
     pub runtime: Option<String>,
+    /// Lua library dynamic builds load instead of searching for one
+    #[serde(rename = "luaLibraryPath")]
+    pub lua_library_path: Option<String>,
     /// Whether to stop on entry
     #[serde(rename = "stopOnEntry")]
     pub stop_on_entry: bool,
@@ -20,15 +33,76 @@ pub struct Config {
     pub cwd: Option<String>,
     /// Environment variables
     pub env: Option<HashMap<String, String>>,
+    /// Milliseconds to wait for an attach target to respond
+    #[serde(rename = "attachTimeoutMs")]
+    pub attach_timeout_ms: u64,
+    /// Rewrites of DAP messages, for clients and engines that need paths or arguments adapted
+    #[serde(rename = "rewriteRules")]
+    pub rewrite_rules: Vec<RewriteRule>,
+    /// Runtimes `wayfinder matrix` runs a script under
+    pub matrix: Vec<String>,
+    /// `tracing` filter for the adapter's diagnostics, such as `debug`
+    #[serde(rename = "logLevel")]
+    pub log_level: Option<String>,
+    /// File diagnostics are appended to instead of stderr
+    #[serde(rename = "logFile")]
+    pub log_file: Option<String>,
+    /// Whether to debug TypeScript sources through source maps
+    #[serde(rename = "sourceMaps")]
+    pub source_maps: Option<bool>,
+    /// `package.path` for launched programs
+    #[serde(rename = "luaPath")]
+    pub lua_path: Option<String>,
+    /// `package.cpath` for launched programs
+    #[serde(rename = "luaCpath")]
+    pub lua_cpath: Option<String>,
+    /// Directories `require` searches after the script's own
+    #[serde(rename = "sourceRoots")]
+    pub source_roots: Vec<String>,
+    /// Reloading modules into debugged programs when their files change; off when unset
+    #[serde(rename = "hotReload")]
+    pub hot_reload: Option<HotReloadSettings>,
+    /// Evaluation, stepping and display settings of DAP sessions, read again on SIGHUP
+    pub debugger: DebuggerSettings,
+    /// Whether a launched program is killed or left running when Wayfinder is interrupted or terminated
+    #[serde(rename = "onShutdown")]
+    pub on_shutdown: OnShutdown,
+}
+
+/// The `hotReload` section
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HotReloadSettings {
+    /// Globs of the files to watch, relative to `cwd`; all Lua files when empty
+    #[serde(default)]
+    pub watch: Vec<String>,
+    /// Milliseconds a file must stay unchanged before it is reloaded
+    #[serde(rename = "debounceMs")]
+    pub debounce_ms: Option<u64>,
+    /// Shell command run when a watched file that is not Lua changes, such as `npx tstl`
+    #[serde(rename = "buildCommand")]
+    pub build_command: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             runtime: None,
+            lua_library_path: None,
             stop_on_entry: false,
             cwd: None,
             env: None,
+            attach_timeout_ms: DEFAULT_ATTACH_TIMEOUT_MS,
+            rewrite_rules: Vec::new(),
+            matrix: Vec::new(),
+            log_level: None,
+            log_file: None,
+            source_maps: None,
+            lua_path: None,
+            lua_cpath: None,
+            source_roots: Vec::new(),
+            hot_reload: None,
+            debugger: DebuggerSettings::default(),
+            on_shutdown: OnShutdown::default(),
         }
     }
 }
@@ -38,6 +112,9 @@ impl Default for Config {
 struct ConfigFile {
     /// Runtime to use (e.g., "lua5.1", "lua5.2", "lua5.3", "lua5.4")
     runtime: Option<String>,
+    /// Lua library to load instead of searching for one
+    #[serde(rename = "luaLibraryPath")]
+    lua_library_path: Option<String>,
     /// Whether to stop on entry
     #[serde(rename = "stopOnEntry")]
     stop_on_entry: Option<bool>,
@@ -45,6 +122,40 @@ struct ConfigFile {
     cwd: Option<String>,
     /// Environment variables
     env: Option<HashMap<String, String>>,
+    /// Milliseconds to wait for an attach target to respond
+    #[serde(rename = "attachTimeoutMs")]
+    attach_timeout_ms: Option<u64>,
+    /// Rewrites of DAP messages
+    #[serde(rename = "rewriteRules")]
+    rewrite_rules: Option<Vec<RewriteRule>>,
+    /// Runtimes `wayfinder matrix` runs a script under
+    matrix: Option<Vec<String>>,
+    /// `tracing` filter for the adapter's diagnostics
+    #[serde(rename = "logLevel")]
+    log_level: Option<String>,
+    /// File diagnostics are appended to
+    #[serde(rename = "logFile")]
+    log_file: Option<String>,
+    /// Whether to debug TypeScript sources through source maps
+    #[serde(rename = "sourceMaps")]
+    source_maps: Option<bool>,
+    /// `package.path` for launched programs
+    #[serde(rename = "luaPath")]
+    lua_path: Option<String>,
+    /// `package.cpath` for launched programs
+    #[serde(rename = "luaCpath")]
+    lua_cpath: Option<String>,
+    /// Directories `require` searches after the script's own
+    #[serde(rename = "sourceRoots")]
+    source_roots: Option<Vec<String>>,
+    /// Reloading modules when their files change
+    #[serde(rename = "hotReload")]
+    hot_reload: Option<HotReloadSettings>,
+    /// Settings of DAP sessions
+    debugger: Option<DebuggerSettings>,
+    /// What happens to a launched program on SIGINT or SIGTERM
+    #[serde(rename = "onShutdown")]
+    on_shutdown: Option<OnShutdown>,
 }
 
 impl Config {
@@ -59,12 +170,80 @@ impl Config {
 
         Ok(Self {
             runtime: config_file.runtime,
+            lua_library_path: config_file.lua_library_path,
             stop_on_entry: config_file.stop_on_entry.unwrap_or(false),
             cwd: config_file.cwd,
             env: config_file.env,
+            attach_timeout_ms: config_file
+                .attach_timeout_ms
+                .unwrap_or(DEFAULT_ATTACH_TIMEOUT_MS),
+            rewrite_rules: config_file.rewrite_rules.unwrap_or_default(),
+            matrix: config_file.matrix.unwrap_or_default(),
+            log_level: config_file.log_level,
+            log_file: config_file.log_file,
+            source_maps: config_file.source_maps,
+            lua_path: config_file.lua_path,
+            lua_cpath: config_file.lua_cpath,
+            source_roots: config_file.source_roots.unwrap_or_default(),
+            hot_reload: config_file.hot_reload,
+            debugger: config_file.debugger.unwrap_or_default(),
+            on_shutdown: config_file.on_shutdown.unwrap_or_default(),
         })
     }
 
+    /// Search path settings for scripts run in-process or spawned
+    pub fn lua_path_config(&self) -> LuaPathConfig {
+        LuaPathConfig {
+            lua_path: self.lua_path.clone(),
+            lua_cpath: self.lua_cpath.clone(),
+            source_roots: self.source_roots.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    /// Launch arguments for a DAP launch request to fall back on
+    pub fn launch_defaults(&self) -> LaunchArguments {
+        LaunchArguments {
+            cwd: self.cwd.clone(),
+            env: self.env.clone().unwrap_or_default(),
+            runtime: self.runtime.clone(),
+            stop_on_entry: self.stop_on_entry.then_some(true),
+            source_maps: self.source_maps,
+            lua_path: self.lua_path.clone(),
+            lua_cpath: self.lua_cpath.clone(),
+            source_roots: self.source_roots.clone(),
+            ..LaunchArguments::default()
+        }
+    }
+
+    /// What debug sessions watch to hot reload, None unless the file has a `hotReload` section
+    ///
+    /// Globs and module names are relative to `cwd`, or the current directory.
+    pub fn hot_reload_config(&self) -> Option<WatcherConfig> {
+        let settings = self.hot_reload.as_ref()?;
+        let root = match &self.cwd {
+            Some(cwd) => PathBuf::from(cwd),
+            None => std::env::current_dir().ok()?,
+        };
+        let mut config = WatcherConfig::new(root);
+        if !settings.watch.is_empty() {
+            config.watch = settings.watch.clone();
+        }
+        if let Some(debounce_ms) = settings.debounce_ms {
+            config.debounce = Duration::from_millis(debounce_ms);
+        }
+        config.build_command = settings.build_command.clone();
+        config.module_roots = self.source_roots.iter().map(PathBuf::from).collect();
+        Some(config)
+    }
+
+    /// Settings for DAP sessions: the `debugger` section, with `logLevel`
+    pub fn debugger_settings(&self) -> DebuggerSettings {
+        DebuggerSettings {
+            log_level: self.log_level.clone(),
+            ..self.debugger.clone()
+        }
+    }
+
     /// Find and load configuration from standard locations
     pub fn load_from_standard_locations() -> Result<Option<Self>, Box<dyn std::error::Error>> {
         // Try current directory first
@@ -97,9 +276,12 @@ mod tests {
     fn test_default_config() {
         let config = Config::default();
         assert_eq!(config.runtime, None);
+        assert_eq!(config.lua_library_path, None);
         assert_eq!(config.stop_on_entry, false);
         assert_eq!(config.cwd, None);
         assert_eq!(config.env, None);
+        assert_eq!(config.attach_timeout_ms, DEFAULT_ATTACH_TIMEOUT_MS);
+        assert_eq!(config.on_shutdown, OnShutdown::Terminate);
     }
 
     #[test]
@@ -109,8 +291,16 @@ mod tests {
 
         let config_content = r#"
 runtime: lua5.4
+luaLibraryPath: /opt/lua/lib/liblua5.4.so
 stopOnEntry: true
 cwd: /tmp
+matrix: [lua5.1, lua5.4]
+logLevel: debug
+logFile: /tmp/wayfinder.log
+sourceMaps: false
+luaPath: ./lib/?.lua
+sourceRoots: [src, vendor]
+onShutdown: detach
 env:
   DEBUG: true
   LUA_PATH: ./?.lua
@@ -121,13 +311,111 @@ env:
         let config = Config::load(&config_path)?;
 
         assert_eq!(config.runtime, Some("lua5.4".to_string()));
+        assert_eq!(config.lua_library_path.as_deref(), Some("/opt/lua/lib/liblua5.4.so"));
         assert_eq!(config.stop_on_entry, true);
         assert_eq!(config.cwd, Some("/tmp".to_string()));
+        assert_eq!(config.attach_timeout_ms, DEFAULT_ATTACH_TIMEOUT_MS);
+        assert_eq!(config.matrix, vec!["lua5.1", "lua5.4"]);
+        assert_eq!(config.log_level.as_deref(), Some("debug"));
+        assert_eq!(config.log_file.as_deref(), Some("/tmp/wayfinder.log"));
+        assert_eq!(config.on_shutdown, OnShutdown::Detach);
+
+        let defaults = config.launch_defaults();
+        assert_eq!(defaults.runtime.as_deref(), Some("lua5.4"));
+        assert_eq!(defaults.stop_on_entry, Some(true));
+        assert_eq!(defaults.source_maps, Some(false));
+        assert_eq!(defaults.lua_path.as_deref(), Some("./lib/?.lua"));
+        assert_eq!(defaults.source_roots, vec!["src", "vendor"]);
+        assert_eq!(config.lua_path_config().source_roots, vec![PathBuf::from("src"), PathBuf::from("vendor")]);
+        assert_eq!(defaults.env.get("LUA_PATH").map(String::as_str), Some("./?.lua"));
 
-        let env = config.env.unwrap();
+        let env = config.env.as_ref().unwrap();
         assert_eq!(env.get("DEBUG"), Some(&"true".to_string()));
         assert_eq!(env.get("LUA_PATH"), Some(&"./?.lua".to_string()));
+        assert!(config.hot_reload_config().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite_rules() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let config_path = temp_dir.path().join("wayfinder.yaml");
+        fs::write(
+            &config_path,
+            r#"
+rewriteRules:
+  - pathPrefixes:
+      - { from: /home/dev/game, to: /srv/game }
+  - command: initialize
+    maskCapabilities: [supportsStepBack]
+  - command: evaluate
+    defaultArguments:
+      context: repl
+"#,
+        )?;
+
+        let rules = Config::load(&config_path)?.rewrite_rules;
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].path_prefixes[0].to, "/srv/game");
+        assert_eq!(rules[1].command.as_deref(), Some("initialize"));
+        assert_eq!(rules[1].mask_capabilities, vec!["supportsStepBack"]);
+        assert_eq!(rules[2].default_arguments["context"], "repl");
+        Ok(())
+    }
+
+    #[test]
+    fn test_hot_reload_section() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let config_path = temp_dir.path().join("wayfinder.yaml");
+        fs::write(
+            &config_path,
+            r#"
+cwd: /game
+sourceRoots: [src]
+hotReload:
+  watch: ["src/**/*.ts", "dist/**/*.lua"]
+  debounceMs: 50
+  buildCommand: npx tstl
+"#,
+        )?;
+
+        let watcher = Config::load(&config_path)?.hot_reload_config().unwrap();
+        assert_eq!(watcher.root, PathBuf::from("/game"));
+        assert_eq!(watcher.watch, vec!["src/**/*.ts", "dist/**/*.lua"]);
+        assert_eq!(watcher.debounce, Duration::from_millis(50));
+        assert_eq!(watcher.build_command.as_deref(), Some("npx tstl"));
+        assert_eq!(watcher.module_roots, vec![PathBuf::from("src")]);
+
+        // An empty section watches every Lua file
+        fs::write(&config_path, "cwd: /game\nhotReload: {}\n")?;
+        let watcher = Config::load(&config_path)?.hot_reload_config().unwrap();
+        assert_eq!(watcher, WatcherConfig::new("/game"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_debugger_section() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = TempDir::new()?;
+        let config_path = temp_dir.path().join("wayfinder.yaml");
+        fs::write(
+            &config_path,
+            r#"
+logLevel: warn
+debugger:
+  evalSafety: strict
+  stepGranularity: instruction
+  maxStringLength: 80
+"#,
+        )?;
 
+        let settings = Config::load(&config_path)?.debugger_settings();
+        assert_eq!(settings.eval_safety.as_deref(), Some("strict"));
+        assert_eq!(settings.max_string_length, Some(80));
+        assert_eq!(settings.max_variable_children, None);
+        assert_eq!(settings.log_level.as_deref(), Some("warn"));
+        assert!(settings.step_granularity.is_some());
+        assert_eq!(Config::default().debugger_settings(), Default::default());
         Ok(())
     }
 
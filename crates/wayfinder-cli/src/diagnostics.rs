@@ -0,0 +1,122 @@
+//! Diagnostics for launch and attach failures
+//!
+//! When a debuggee never reaches its first line, or an attach target never
+//! answers, the request fails with a summary of what was observed instead of
+//! leaving the editor waiting forever.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::process::Child;
+
+/// Number of stderr lines kept for the failure summary
+pub const STDERR_TAIL_LINES: usize = 20;
+
+/// Observations collected while waiting on a debuggee
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    /// Most recent stderr lines from the debuggee
+    stderr_tail: VecDeque<String>,
+    /// Socket errors seen while trying to connect
+    socket_errors: Vec<String>,
+    /// Last known state of the debuggee process
+    process_state: Option<String>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a line of debuggee stderr, keeping only the tail
+    pub fn record_stderr(&mut self, line: impl Into<String>) {
+        if self.stderr_tail.len() == STDERR_TAIL_LINES {
+            self.stderr_tail.pop_front();
+        }
+        self.stderr_tail.push_back(line.into());
+    }
+
+    /// Records a socket error
+    pub fn record_socket_error(&mut self, error: impl Into<String>) {
+        self.socket_errors.push(error.into());
+    }
+
+    /// Records the state of the debuggee process
+    pub fn record_process_state(&mut self, child: &mut Child) {
+        self.process_state = Some(describe_process_state(child));
+    }
+
+    /// Builds a human-readable failure summary
+    pub fn summary(&self, what: &str, timeout: Duration) -> String {
+        let mut summary = format!("{} did not complete within {} ms", what, timeout.as_millis());
+
+        if let Some(state) = &self.process_state {
+            summary.push_str(&format!("\n  process: {}", state));
+        }
+
+        if !self.socket_errors.is_empty() {
+            summary.push_str(&format!(
+                "\n  socket errors ({} attempts):",
+                self.socket_errors.len()
+            ));
+            // Repeated identical errors are collapsed to keep the summary short
+            let mut last: Option<&String> = None;
+            for error in &self.socket_errors {
+                if last != Some(error) {
+                    summary.push_str(&format!("\n    {}", error));
+                }
+                last = Some(error);
+            }
+        }
+
+        if self.stderr_tail.is_empty() {
+            summary.push_str("\n  stderr: <empty>");
+        } else {
+            summary.push_str("\n  stderr (tail):");
+            for line in &self.stderr_tail {
+                summary.push_str(&format!("\n    {}", line));
+            }
+        }
+
+        summary
+    }
+}
+
+/// Describes whether a child process is still running or how it exited
+pub fn describe_process_state(child: &mut Child) -> String {
+    match child.try_wait() {
+        Ok(Some(status)) => format!("exited ({})", status),
+        Ok(None) => match child.id() {
+            Some(pid) => format!("running (pid {})", pid),
+            None => "running".to_string(),
+        },
+        Err(e) => format!("unknown ({})", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stderr_tail_is_bounded() {
+        let mut diagnostics = Diagnostics::new();
+        for i in 0..(STDERR_TAIL_LINES + 5) {
+            diagnostics.record_stderr(format!("line {}", i));
+        }
+
+        let summary = diagnostics.summary("Launch", Duration::from_millis(100));
+        assert!(!summary.contains("line 0\n"));
+        assert!(summary.contains(&format!("line {}", STDERR_TAIL_LINES + 4)));
+    }
+
+    #[test]
+    fn test_socket_errors_are_collapsed() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.record_socket_error("connection refused");
+        diagnostics.record_socket_error("connection refused");
+
+        let summary = diagnostics.summary("Attach", Duration::from_secs(1));
+        assert!(summary.contains("socket errors (2 attempts)"));
+        assert_eq!(summary.matches("connection refused").count(), 1);
+    }
+}
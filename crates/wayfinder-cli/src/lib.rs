@@ -1,11 +1,24 @@
 // Module declarations
 pub mod commands {
     pub mod launch;
+    pub mod love2d;
+    pub mod agent;
     pub mod attach;
     pub mod dap;
     pub mod hot_reload;
+    pub mod docs;
+    pub mod matrix;
+    pub mod memory;
+    pub mod bundle_session;
+    pub mod replay;
+    pub mod run;
+    pub mod debug;
 }
 pub mod config_mod;
+pub mod diagnostics;
+pub mod logging;
+pub mod repl;
+pub mod shutdown;
 
 // Re-exports for convenience
 pub use config_mod::Config;
@@ -29,37 +42,140 @@ pub fn create_puc_lua_runtime(runtime: Option<&str>) -> wayfinder_core::runtime:
     #[cfg(feature = "static-lua")]
     {
         if let Some(rt) = runtime {
-            eprintln!("Warning: Runtime version '{}' specified but wayfinder was built with static Lua 5.4. Ignoring runtime parameter.", rt);
+            tracing::warn!("Runtime version '{}' specified but wayfinder was built with static Lua 5.4. Ignoring runtime parameter.", rt);
         }
         wayfinder_core::runtime::puc_lua::PUCLuaRuntime::new()
     }
 
     #[cfg(feature = "dynamic-lua")]
     {
-        use wayfinder_core::runtime::lua_loader::LuaLibrary;
+        use wayfinder_core::runtime::lua_detect;
+        use wayfinder_core::runtime::lua_loader::{self, LuaLibrary};
         use wayfinder_core::runtime::LuaVersion;
 
-        // Parse runtime version from string or default to 5.4
-        let version = if let Some(rt_str) = runtime {
+        // A configured version wins; without one, or with one not understood,
+        // the version of the `lua` on PATH is used
+        let version = runtime.and_then(|rt_str| {
             parse_runtime_version(rt_str)
-                .unwrap_or_else(|e| {
-                    eprintln!("{}", e);
-                    eprintln!("Falling back to Lua 5.4");
-                    LuaVersion::V54
-                })
-        } else {
-            LuaVersion::V54
+                .map_err(|e| tracing::warn!("{}; detecting the Lua version instead", e))
+                .ok()
+        });
+        let lib = match (lua_loader::library_override(), version) {
+            // A library named by luaLibraryPath or LUA_WAYFINDER_LIB says the version itself
+            (Some(path), version) => LuaLibrary::load_override(&path, version)
+                .unwrap_or_else(|e| panic!("Failed to load the Lua library {}: {}", path.display(), e)),
+            (None, Some(version)) => or_embedded(LuaLibrary::load(version), version)
+                .unwrap_or_else(|e| panic!("Failed to load Lua library for version {}: {}", version, e)),
+            (None, None) => {
+                let mut candidates = lua_detect::detect_on_path();
+                // Without a `lua` on PATH, the library of the default version may still be installed
+                candidates.push(lua_detect::Candidate {
+                    version: LuaVersion::V54,
+                    evidence: "the default".to_string(),
+                    library: None,
+                });
+                or_embedded(LuaLibrary::load_detected(&candidates), LuaVersion::V54)
+                    .unwrap_or_else(|e| panic!("{}", e))
+            }
         };
 
-        let lib = LuaLibrary::load(version)
-            .unwrap_or_else(|e| {
-                panic!("Failed to load Lua library for version {:?}: {}", version, e);
-            });
-
         wayfinder_core::runtime::puc_lua::PUCLuaRuntime::new_with_library(lib)
     }
 }
 
+/// Falls back to the Lua 5.4 linked into Wayfinder when no library of `version` loads
+///
+/// Only builds with the `embedded-lua` feature have one, and a library named
+/// by `luaLibraryPath` or `LUA_WAYFINDER_LIB` is never replaced.
+#[cfg(feature = "dynamic-lua")]
+#[cfg_attr(not(feature = "embedded-lua"), allow(unused_variables))]
+fn or_embedded(
+    loaded: Result<wayfinder_core::runtime::lua_loader::LuaLibrary, wayfinder_core::runtime::lua_loader::LoaderError>,
+    version: wayfinder_core::runtime::LuaVersion,
+) -> Result<wayfinder_core::runtime::lua_loader::LuaLibrary, wayfinder_core::runtime::lua_loader::LoaderError> {
+    #[cfg(feature = "embedded-lua")]
+    {
+        use wayfinder_core::runtime::lua_loader::{self, LuaLibrary};
+
+        if let Err(e) = &loaded {
+            if version == wayfinder_core::runtime::LuaVersion::V54 && lua_loader::library_override().is_none() {
+                tracing::warn!("{}; using the Lua 5.4 linked into Wayfinder", e);
+                return LuaLibrary::load_embedded();
+            }
+        }
+    }
+    loaded
+}
+
+/// Sets the Lua library loaded in place of searching, from `--lua-library-path` or `luaLibraryPath`
+///
+/// Static builds have only the Lua linked in, so there it is ignored.
+pub fn set_lua_library_path(path: Option<PathBuf>) {
+    #[cfg(feature = "static-lua")]
+    {
+        let env = std::env::var_os(wayfinder_core::runtime::LUA_LIBRARY_ENV_VAR);
+        if let Some(path) = path.or(env.map(PathBuf::from)) {
+            tracing::warn!(
+                "Lua library {} specified but wayfinder was built with static Lua 5.4. Ignoring it.",
+                path.display()
+            );
+        }
+    }
+
+    #[cfg(feature = "dynamic-lua")]
+    wayfinder_core::runtime::lua_loader::set_library_path(path);
+}
+
+/// Creates a PUCLuaRuntime for exactly the given runtime, without falling back
+///
+/// Static builds only have Lua 5.4, so other versions are an error.
+pub fn create_puc_lua_runtime_for(runtime: &str) -> Result<wayfinder_core::runtime::puc_lua::PUCLuaRuntime, String> {
+    #[cfg(feature = "static-lua")]
+    {
+        match runtime.to_lowercase().as_str() {
+            "lua5.4" | "lua54" | "5.4" => Ok(wayfinder_core::runtime::puc_lua::PUCLuaRuntime::new()),
+            _ => Err(format!("{} is not available: wayfinder was built with static Lua 5.4", runtime)),
+        }
+    }
+
+    #[cfg(feature = "dynamic-lua")]
+    {
+        use wayfinder_core::runtime::lua_loader::LuaLibrary;
+
+        let version = parse_runtime_version(runtime)?;
+        let lib = or_embedded(LuaLibrary::load(version), version)
+            .map_err(|e| format!("Failed to load Lua library for version {}: {}", version, e))?;
+        Ok(wayfinder_core::runtime::puc_lua::PUCLuaRuntime::new_with_library(lib))
+    }
+}
+
+/// Creates a PUCLuaRuntime on the library at `library`, as a launch request's `luaLibraryPath` asks
+///
+/// A `runtime` the library is not the version of is an error.
+pub fn create_puc_lua_runtime_from(
+    library: &std::path::Path,
+    runtime: Option<&str>,
+) -> Result<wayfinder_core::runtime::puc_lua::PUCLuaRuntime, String> {
+    #[cfg(feature = "static-lua")]
+    {
+        let _ = runtime;
+        Err(format!(
+            "Cannot load {}: wayfinder was built with static Lua 5.4",
+            library.display()
+        ))
+    }
+
+    #[cfg(feature = "dynamic-lua")]
+    {
+        use wayfinder_core::runtime::lua_loader::LuaLibrary;
+
+        let version = runtime.map(parse_runtime_version).transpose()?;
+        let lib = LuaLibrary::load_override(library, version)
+            .map_err(|e| format!("Failed to load the Lua library {}: {}", library.display(), e))?;
+        Ok(wayfinder_core::runtime::puc_lua::PUCLuaRuntime::new_with_library(lib))
+    }
+}
+
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
@@ -69,6 +185,35 @@ use std::path::PathBuf;
 pub struct Args {
     #[command(subcommand)]
     pub command: Option<Commands>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILTER",
+        help = "Diagnostics to log, e.g. debug or wayfinder_core::session=trace (overrides logLevel from config)"
+    )]
+    pub log_level: Option<String>,
+    #[arg(long, global = true, value_name = "FILE", help = "Append diagnostics to FILE instead of stderr")]
+    pub log_file: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Record every DAP request, response and event to FILE as JSONL, for `wayfinder replay`"
+    )]
+    pub trace_dap: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Abort where a DAP request handler panics instead of answering with an error, for debugging Wayfinder"
+    )]
+    pub abort_on_panic: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Lua library to load instead of searching for one (overrides luaLibraryPath from config and LUA_WAYFINDER_LIB)"
+    )]
+    pub lua_library_path: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -86,14 +231,101 @@ pub enum Commands {
         cwd: Option<String>,
         #[arg(long, short = 'd', help = "Enable DAP debugging")]
         debug: bool,
+        #[arg(long, help = "Stop before the first line (overrides stopOnEntry from config)")]
+        stop_on_entry: bool,
+        #[arg(long, value_name = "FILE", help = "Profile the run and write the profile to FILE")]
+        profile: Option<PathBuf>,
+        #[arg(long, default_value = "speedscope", help = "Profile file format: speedscope or chrome")]
+        profile_format: wayfinder_core::profiling::export::ExportFormat,
+        #[arg(
+            long,
+            value_name = "SERIALIZER",
+            help = "Profile file encoding: json, json.gz or msgpack (defaults to the file extension's)"
+        )]
+        profile_serializer: Option<wayfinder_core::serializer::Serializer>,
+        #[arg(long, value_name = "TEMPLATE", help = "package.path after the script's directory; ;; is the default path")]
+        lua_path: Option<String>,
+        #[arg(long, value_name = "TEMPLATE", help = "package.cpath after the script's directory; ;; is the default path")]
+        lua_cpath: Option<String>,
+        #[arg(long = "source-root", value_name = "DIR", help = "Directory require searches after the script's own (repeatable)")]
+        source_roots: Vec<PathBuf>,
+        #[arg(
+            long,
+            value_name = "PRESET",
+            help = "Launch SCRIPT as a project of a known kind: love2d runs a LÖVE game directory with love"
+        )]
+        preset: Option<commands::launch::Preset>,
         script: Option<String>,
     },
+    #[command(about = "Debug a script from the terminal with gdb-style commands")]
+    Debug {
+        #[arg(long, short = 'r')]
+        runtime: Option<String>,
+        #[arg(long, short = 'c')]
+        cwd: Option<String>,
+        #[arg(long, help = "Stop before the first line (overrides stopOnEntry from config)")]
+        stop_on_entry: bool,
+        script: String,
+    },
+    #[command(
+        about = "Run a script under a debug agent, for a DAP server running it in the client's terminal",
+        hide = true
+    )]
+    Agent {
+        #[arg(long, value_name = "ADDRESS", help = "TCP address to wait for the debugger on")]
+        listen: String,
+        #[arg(long, short = 'r')]
+        runtime: Option<String>,
+        #[arg(long, value_name = "TEMPLATE")]
+        lua_path: Option<String>,
+        #[arg(long, value_name = "TEMPLATE")]
+        lua_cpath: Option<String>,
+        #[arg(long = "source-root", value_name = "DIR")]
+        source_roots: Vec<PathBuf>,
+        script: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, help = "Arguments the script finds in arg")]
+        args: Vec<String>,
+    },
+    #[command(about = "Run a script without a client, evaluating expressions at breakpoints, for CI")]
+    Run {
+        #[arg(long, short = 'r')]
+        runtime: Option<String>,
+        #[arg(long, short = 'c')]
+        cwd: Option<String>,
+        #[arg(long = "break", value_name = "FILE:LINE", help = "Set a line breakpoint (repeatable)")]
+        breakpoints: Vec<commands::run::BreakpointSpec>,
+        #[arg(long = "eval-on-break", value_name = "EXPR", help = "Evaluate EXPR in the stopped frame at each stop (repeatable)")]
+        evaluations: Vec<String>,
+        #[arg(long = "continue", help = "Resume after each stop instead of ending the run at the first")]
+        continue_after_break: bool,
+        script: String,
+    },
+    #[command(about = "Run a script under several Lua versions and report where they diverge")]
+    Matrix {
+        #[arg(
+            long,
+            short = 'r',
+            value_delimiter = ',',
+            help = "Runtimes to run, comma separated (default: matrix from config, else lua5.1 to lua5.4)"
+        )]
+        runtimes: Vec<String>,
+        #[arg(long, short = 'c')]
+        cwd: Option<String>,
+        script: String,
+    },
+    #[command(about = "Inspect heap snapshots")]
+    Memory {
+        #[command(subcommand)]
+        command: MemoryCommand,
+    },
     #[command(about = "Attach to a running process")]
     Attach {
         #[arg(long, short = 'p')]
         port: Option<u16>,
         #[arg(long)]
         pid: Option<u32>,
+        #[arg(long, help = "Milliseconds to wait for the target to respond")]
+        timeout_ms: Option<u64>,
     },
     #[command(about = "Hot reload a module")]
     HotReload {
@@ -104,6 +336,51 @@ pub enum Commands {
         #[arg(long, default_value = "127.0.0.1", help = "Host to connect to")]
         host: String,
     },
+    #[command(about = "Archive a running session's trace, output, breakpoints, config and profile for a bug report")]
+    BundleSession {
+        #[arg(help = "Zip archive to write")]
+        out: PathBuf,
+        #[arg(long, short = 'p', help = "Port of the DAP server")]
+        port: u16,
+        #[arg(long, default_value = "127.0.0.1", help = "Host of the DAP server")]
+        host: String,
+        #[arg(long, help = "Blank out source code, expressions and breakpoint conditions")]
+        redact_sources: bool,
+    },
+    #[command(about = "Replay a session recorded with --trace-dap and report requests answered differently")]
+    Replay {
+        #[arg(help = "Recording to replay")]
+        recording: PathBuf,
+        #[arg(long, short = 'r')]
+        runtime: Option<String>,
+        #[arg(long, value_name = "FILE", help = "Write the replayed session to FILE, in the format of a recording")]
+        transcript: Option<PathBuf>,
+        #[arg(long, default_value_t = 10000, help = "Milliseconds to wait for each response or event")]
+        timeout_ms: u64,
+    },
+    #[command(about = "Print a shell completion script")]
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    #[command(about = "Generate manpages")]
+    Man {
+        #[arg(long, short = 'o', help = "Write a page per subcommand to this directory instead of printing wayfinder.1")]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum MemoryCommand {
+    #[command(about = "Report which objects grew between two heap snapshots")]
+    Diff {
+        #[arg(help = "Older snapshot (.json, .json.gz or .msgpack)")]
+        old: PathBuf,
+        #[arg(help = "Newer snapshot")]
+        new: PathBuf,
+        #[arg(long, default_value_t = 10, help = "Number of largest new objects to list")]
+        top: usize,
+    },
 }
 
 fn find_config() -> Option<PathBuf> {
@@ -125,28 +402,59 @@ fn find_config() -> Option<PathBuf> {
 pub async fn run_cli() {
     let args = Args::parse();
 
-    let config = if let Some(config_path) = find_config() {
-        match Config::load(&config_path) {
-            Ok(cfg) => {
-                println!("Loaded config: {}", config_path.display());
-                Some(cfg)
-            }
-            Err(e) => {
-                println!("Error loading config: {}", e);
-                None
-            }
+    let loaded = find_config().map(|path| (Config::load(&path), path));
+    let config = loaded.as_ref().and_then(|(config, _)| config.as_ref().ok());
+
+    let log_level_from_flag = args.log_level.is_some();
+    let log_settings = logging::LogSettings {
+        level: args.log_level.clone().or_else(|| config.and_then(|c| c.log_level.clone())),
+        file: args.log_file.clone().or_else(|| config.and_then(|c| c.log_file.as_ref().map(PathBuf::from))),
+    };
+    if let Err(e) = logging::init(&log_settings) {
+        eprintln!("Error setting up logging: {}", e);
+    }
+    wayfinder_core::session::panics::set_abort_on_panic(args.abort_on_panic);
+    // The interactive debugger pauses the program on Ctrl+C instead
+    let interrupt = !matches!(args.command, Some(Commands::Debug { .. }));
+    shutdown::coordinator().install(interrupt);
+
+    let config_path = loaded.as_ref().map(|(_, path)| path.clone());
+    let config = match loaded {
+        Some((Ok(config), path)) => {
+            tracing::info!("Loaded config: {}", path.display());
+            Some(config)
+        }
+        Some((Err(e), path)) => {
+            tracing::error!("Error loading config {}: {}", path.display(), e);
+            None
         }
-    } else {
-        None
+        None => None,
     };
+    set_lua_library_path(
+        args.lua_library_path
+            .clone()
+            .or_else(|| config.as_ref().and_then(|c| c.lua_library_path.as_ref().map(PathBuf::from))),
+    );
 
     match args.command {
         Some(Commands::Dap { port }) => {
-            println!("DAP server mode");
+            tracing::debug!("DAP server mode");
 
             let dap_config = commands::dap::DapConfig {
                 port,
                 multi_client: false, // Could be made configurable
+                rewrite_rules: config.as_ref().map(|c| c.rewrite_rules.clone()).unwrap_or_default(),
+                trace_dap: args.trace_dap,
+                launch_defaults: config.as_ref().map(Config::launch_defaults).unwrap_or_default(),
+                hot_reload: config.as_ref().and_then(Config::hot_reload_config),
+                // The log level was set up with the rest of logging
+                settings: wayfinder_core::config::DebuggerSettings {
+                    log_level: None,
+                    ..config.as_ref().map(Config::debugger_settings).unwrap_or_default()
+                },
+                settings_file: config_path,
+                log_level_from_flag,
+                on_shutdown: config.as_ref().map(|c| c.on_shutdown).unwrap_or_default(),
             };
 
             if let Err(e) = commands::dap::run_dap_server(dap_config).await {
@@ -157,24 +465,39 @@ pub async fn run_cli() {
             runtime,
             cwd,
             debug,
+            stop_on_entry,
+            profile,
+            profile_format,
+            profile_serializer,
+            lua_path,
+            lua_cpath,
+            source_roots,
+            preset,
             script,
         }) => {
-            println!("Launch mode");
+            tracing::debug!("Launch mode");
 
             let effective_runtime = runtime.or(config.as_ref().and_then(|c| c.runtime.clone()));
             let effective_cwd = cwd.or(config.as_ref().and_then(|c| c.cwd.clone()));
 
             if let Some(r) = &effective_runtime {
-                println!("Runtime: {}", r);
+                tracing::debug!("Runtime: {}", r);
             }
             if let Some(c) = &effective_cwd {
-                println!("CWD: {}", c);
+                tracing::debug!("CWD: {}", c);
             }
             if debug {
-                println!("Debug mode: enabled");
+                tracing::debug!("Debug mode: enabled");
             }
             if let Some(s) = script {
-                println!("Script: {}", s);
+                tracing::debug!("Script: {}", s);
+
+                let configured = config.as_ref().map(Config::lua_path_config).unwrap_or_default();
+                let lua_paths = wayfinder_core::runtime::lua_paths::LuaPathConfig {
+                    lua_path: lua_path.or(configured.lua_path),
+                    lua_cpath: lua_cpath.or(configured.lua_cpath),
+                    source_roots: if source_roots.is_empty() { configured.source_roots } else { source_roots },
+                };
 
                 let launch_config = commands::launch::LaunchConfig {
                     runtime: effective_runtime,
@@ -182,6 +505,16 @@ pub async fn run_cli() {
                     env: config.as_ref().and_then(|c| c.env.clone()),
                     script: s,
                     debug,
+                    stop_on_entry: stop_on_entry
+                        || config.as_ref().map(|c| c.stop_on_entry).unwrap_or(false),
+                    profile,
+                    profile_format,
+                    profile_serializer,
+                    trace_dap: args.trace_dap,
+                    lua_paths,
+                    preset,
+                    hot_reload: config.as_ref().and_then(Config::hot_reload_config),
+                    on_shutdown: config.as_ref().map(|c| c.on_shutdown).unwrap_or_default(),
                 };
 
                 if let Err(e) = commands::launch::launch_script(launch_config).await {
@@ -189,18 +522,144 @@ pub async fn run_cli() {
                 }
             }
         }
-        Some(Commands::Attach { port, pid }) => {
-            println!("Attach mode");
+        Some(Commands::Debug {
+            runtime,
+            cwd,
+            stop_on_entry,
+            script,
+        }) => {
+            let debug_config = commands::debug::DebugConfig {
+                runtime: runtime.or(config.as_ref().and_then(|c| c.runtime.clone())),
+                cwd: cwd.or(config.as_ref().and_then(|c| c.cwd.clone())),
+                env: config.as_ref().and_then(|c| c.env.clone()),
+                script,
+                stop_on_entry: stop_on_entry || config.as_ref().map(|c| c.stop_on_entry).unwrap_or(false),
+                lua_paths: config.as_ref().map(Config::lua_path_config).unwrap_or_default(),
+            };
+
+            if let Err(e) = commands::debug::debug_script(debug_config).await {
+                eprintln!("Error debugging script: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Agent {
+            listen,
+            runtime,
+            lua_path,
+            lua_cpath,
+            source_roots,
+            script,
+            args,
+        }) => {
+            // The server passes everything the launch asked for on the command line
+            let agent_config = commands::agent::AgentConfig {
+                listen,
+                runtime,
+                script,
+                args,
+                lua_paths: wayfinder_core::runtime::lua_paths::LuaPathConfig {
+                    lua_path,
+                    lua_cpath,
+                    source_roots,
+                },
+            };
+
+            if let Err(e) = commands::agent::run_agent(agent_config).await {
+                eprintln!("Error running script under the agent: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Run {
+            runtime,
+            cwd,
+            breakpoints,
+            evaluations,
+            continue_after_break,
+            script,
+        }) => {
+            let run_config = commands::run::RunConfig {
+                runtime: runtime.or(config.as_ref().and_then(|c| c.runtime.clone())),
+                cwd: cwd.or(config.as_ref().and_then(|c| c.cwd.clone())),
+                env: config.as_ref().and_then(|c| c.env.clone()),
+                script,
+                breakpoints,
+                evaluations,
+                continue_after_break,
+                lua_paths: config.as_ref().map(Config::lua_path_config).unwrap_or_default(),
+                trace_dap: args.trace_dap,
+            };
+
+            match commands::run::run_script(run_config).await {
+                Ok(exit_code) => std::process::exit(exit_code as i32),
+                Err(e) => {
+                    eprintln!("Error running script: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Matrix { runtimes, cwd, script }) => {
+            let runtimes = if !runtimes.is_empty() {
+                runtimes
+            } else {
+                config.as_ref().map(|c| c.matrix.clone()).unwrap_or_default()
+            };
+
+            let matrix_config = commands::matrix::MatrixConfig {
+                runtimes,
+                cwd: cwd.or(config.as_ref().and_then(|c| c.cwd.clone())),
+                env: config.as_ref().and_then(|c| c.env.clone()),
+                script,
+                lua_paths: config.as_ref().map(Config::lua_path_config).unwrap_or_default(),
+            };
+
+            match commands::matrix::run_matrix(matrix_config).await {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("Error running matrix: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Memory { command }) => match command {
+            MemoryCommand::Diff { old, new, top } => {
+                if let Err(e) = commands::memory::diff_snapshots(&old, &new, top) {
+                    eprintln!("Error comparing snapshots: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        },
+        Some(Commands::BundleSession { out, port, host, redact_sources }) => {
+            let config = commands::bundle_session::BundleSessionConfig {
+                out,
+                host,
+                port,
+                redact_sources,
+            };
+            if let Err(e) = commands::bundle_session::bundle_session(config).await {
+                eprintln!("Error bundling the session: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Attach { port, pid, timeout_ms }) => {
+            tracing::debug!("Attach mode");
             if let Some(p) = port {
-                println!("Port: {}", p);
+                tracing::debug!("Port: {}", p);
             }
             if let Some(p) = pid {
-                println!("PID: {}", p);
+                tracing::debug!("PID: {}", p);
             }
 
             let attach_config = commands::attach::AttachConfig {
                 port,
                 pid,
+                timeout: std::time::Duration::from_millis(timeout_ms.unwrap_or_else(|| {
+                    config
+                        .as_ref()
+                        .map(|c| c.attach_timeout_ms)
+                        .unwrap_or(config_mod::DEFAULT_ATTACH_TIMEOUT_MS)
+                })),
+                trace_dap: args.trace_dap,
             };
 
             if let Err(e) = commands::attach::attach_to_process(attach_config).await {
@@ -222,8 +681,43 @@ pub async fn run_cli() {
                 std::process::exit(1);
             }
         }
+        Some(Commands::Replay { recording, runtime, transcript, timeout_ms }) => {
+            let replay_config = commands::replay::ReplayConfig {
+                recording,
+                runtime: runtime.or(config.as_ref().and_then(|c| c.runtime.clone())),
+                transcript,
+                timeout: std::time::Duration::from_millis(timeout_ms),
+            };
+
+            match commands::replay::replay_recording(replay_config).await {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("Error replaying the recording: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Completions { shell }) => {
+            commands::docs::write_completions(shell, &mut std::io::stdout());
+        }
+        Some(Commands::Man { out_dir }) => {
+            let result = match out_dir {
+                Some(dir) => commands::docs::write_manpages(&dir),
+                None => commands::docs::write_manpage(&mut std::io::stdout()),
+            };
+            if let Err(e) = result {
+                eprintln!("Error generating manpages: {}", e);
+                std::process::exit(1);
+            }
+        }
         None => {
             println!("No command specified. Use --help for usage.");
         }
     }
+
+    // A command that wound down for a signal exits as the signal says
+    if let Some(signal) = shutdown::coordinator().signal() {
+        std::process::exit(signal.exit_code());
+    }
 }
\ No newline at end of file
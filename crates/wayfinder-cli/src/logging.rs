@@ -0,0 +1,106 @@
+//! Logging setup for the adapter's own diagnostics
+//!
+//! In stdio mode stdout carries the DAP framing, so nothing but protocol
+//! messages may be written there. Diagnostics are `tracing` events, written
+//! to stderr or to a log file. The level is a `tracing` filter such as
+//! `debug` or `wayfinder_core::session=trace,info`, taken from `--log-level`,
+//! then `logLevel` in wayfinder.yaml, then the `WAYFINDER_LOG` environment
+//! variable. The filter can be swapped while the adapter runs, for the
+//! `wayfinder/configure` request and settings reloads.
+
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use tracing_subscriber::{reload, EnvFilter};
+
+/// Filter used when nothing sets one
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Environment variable read for the filter when neither flag nor config set one
+pub const LOG_LEVEL_ENV: &str = "WAYFINDER_LOG";
+
+/// Where and how much to log
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogSettings {
+    /// `tracing` filter directives; `DEFAULT_LOG_LEVEL` when unset
+    pub level: Option<String>,
+    /// File to append to instead of stderr
+    pub file: Option<PathBuf>,
+}
+
+impl LogSettings {
+    /// The filter to log with, falling back to `WAYFINDER_LOG` and then the default
+    pub fn filter(&self) -> Result<EnvFilter, String> {
+        let level = self
+            .level
+            .clone()
+            .or_else(|| std::env::var(LOG_LEVEL_ENV).ok())
+            .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string());
+        EnvFilter::try_new(&level).map_err(|e| format!("Invalid log level `{}`: {}", level, e))
+    }
+}
+
+/// Swaps the filter of the subscriber `init` installed
+type FilterSetter = Box<dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync>;
+
+static SET_FILTER: OnceLock<FilterSetter> = OnceLock::new();
+
+/// Installs the global subscriber
+///
+/// Fails if the filter does not parse or the log file cannot be opened, in
+/// which case nothing is installed.
+pub fn init(settings: &LogSettings) -> Result<(), String> {
+    let filter = settings.filter()?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let installed = match &settings.file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Cannot open log file {}: {}", path.display(), e))?;
+            let builder = builder.with_ansi(false).with_writer(Mutex::new(file)).with_filter_reloading();
+            keep_reload_handle(builder.reload_handle());
+            builder.try_init()
+        }
+        None => {
+            let builder = builder.with_writer(std::io::stderr).with_filter_reloading();
+            keep_reload_handle(builder.reload_handle());
+            builder.try_init()
+        }
+    };
+    installed.map_err(|e| e.to_string())
+}
+
+fn keep_reload_handle<S: 'static>(handle: reload::Handle<EnvFilter, S>) {
+    let _ = SET_FILTER.set(Box::new(move |filter| handle.reload(filter).map_err(|e| e.to_string())));
+}
+
+/// Changes the filter of the installed subscriber to `level`
+pub fn set_level(level: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(level).map_err(|e| format!("Invalid log level `{}`: {}", level, e))?;
+    let set_filter = SET_FILTER.get().ok_or_else(|| "Logging is not set up".to_string())?;
+    set_filter(filter)?;
+    tracing::info!("Log level set to {}", level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_from_settings() {
+        let settings = LogSettings {
+            level: Some("wayfinder_core::session=trace,warn".to_string()),
+            file: None,
+        };
+        assert!(settings.filter().is_ok());
+
+        let settings = LogSettings {
+            level: Some("session=verbose".to_string()),
+            file: None,
+        };
+        assert!(settings.filter().unwrap_err().contains("session=verbose"));
+        assert!(set_level("session=verbose").unwrap_err().contains("session=verbose"));
+    }
+}
@@ -0,0 +1,200 @@
+//! Commands and output of the terminal debugger
+//!
+//! `wayfinder debug` reads gdb-style commands such as `b main.lua:12`, `n`
+//! and `p player.health`. This module parses them and formats the frames and
+//! variables it prints; the command itself lives in `commands::debug`.
+
+use std::str::FromStr;
+use wayfinder_core::runtime::{Frame, Value, Variable};
+
+/// Help printed by the `help` command
+pub const HELP: &str = "\
+Commands:
+  b, break FILE:LINE | LINE | FUNCTION   set a breakpoint
+  d, delete ID                           remove a breakpoint
+  c, continue (r, run)                   start or resume the program
+  n, next                                step over
+  s, step                                step into
+  finish                                 step out of the current function
+  bt, backtrace                          show the stack
+  f, frame N                             select frame N
+  p, print EXPR                          evaluate an expression in the selected frame
+  locals                                 show the selected frame's local variables
+  h, help                                show this help
+  q, quit                                end the session
+An empty line repeats the last command; Ctrl-C pauses a running program.";
+
+/// Where a breakpoint goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakTarget {
+    /// A line, in the current frame's file when no file is given
+    Line { source: Option<String>, line: u32 },
+    Function(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplCommand {
+    Break(BreakTarget),
+    Delete(i64),
+    Continue,
+    Next,
+    Step,
+    Finish,
+    Backtrace,
+    Frame(usize),
+    Print(String),
+    Locals,
+    Help,
+    Quit,
+}
+
+impl FromStr for ReplCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (name, argument) = match line.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (line, ""),
+        };
+        let required = |what: &str| {
+            if argument.is_empty() {
+                Err(format!("{} needs {}", name, what))
+            } else {
+                Ok(argument)
+            }
+        };
+
+        let command = match name {
+            "b" | "break" => ReplCommand::Break(parse_break_target(required("a location")?)),
+            "d" | "delete" => ReplCommand::Delete(
+                required("a breakpoint id")?
+                    .parse()
+                    .map_err(|_| format!("Not a breakpoint id: {}", argument))?,
+            ),
+            "c" | "continue" | "r" | "run" => ReplCommand::Continue,
+            "n" | "next" => ReplCommand::Next,
+            "s" | "step" => ReplCommand::Step,
+            "finish" => ReplCommand::Finish,
+            "bt" | "backtrace" | "where" => ReplCommand::Backtrace,
+            "f" | "frame" => ReplCommand::Frame(
+                required("a frame number")?
+                    .parse()
+                    .map_err(|_| format!("Not a frame number: {}", argument))?,
+            ),
+            "p" | "print" => ReplCommand::Print(required("an expression")?.to_string()),
+            "locals" => ReplCommand::Locals,
+            "info" if argument == "locals" => ReplCommand::Locals,
+            "h" | "help" | "?" => ReplCommand::Help,
+            "q" | "quit" | "exit" => ReplCommand::Quit,
+            _ => return Err(format!("Unknown command: {} (try help)", name)),
+        };
+        Ok(command)
+    }
+}
+
+/// Reads `FILE:LINE`, `LINE` or a function name
+fn parse_break_target(argument: &str) -> BreakTarget {
+    if let Ok(line) = argument.parse() {
+        return BreakTarget::Line { source: None, line };
+    }
+    // The line follows the last colon, so Windows drive letters survive
+    if let Some((source, line)) = argument.rsplit_once(':') {
+        if let Ok(line) = line.parse() {
+            return BreakTarget::Line {
+                source: Some(source.to_string()),
+                line,
+            };
+        }
+    }
+    BreakTarget::Function(argument.to_string())
+}
+
+/// A frame as a backtrace line, marking the selected one
+pub fn format_frame(index: usize, frame: &Frame, selected: bool) -> String {
+    let marker = if selected { "*" } else { " " };
+    format!("{}#{:<2} {}", marker, index, format_location(frame))
+}
+
+/// The function a frame runs and where it is
+pub fn format_location(frame: &Frame) -> String {
+    match &frame.source {
+        Some(source) => format!("{} at {}:{}", frame.name, source.name, frame.line),
+        None => frame.name.clone(),
+    }
+}
+
+pub fn format_variable(variable: &Variable) -> String {
+    format!("{} = {} ({})", variable.name, variable.value, variable.type_)
+}
+
+pub fn format_value(value: &Value) -> String {
+    match value {
+        Value::Nil => "nil".to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => format!("{:?}", s),
+        Value::Table { length, .. } if *length > 0 => format!("table (length {})", length),
+        Value::Table { .. } => "table".to_string(),
+        Value::Function { name: Some(name), .. } => format!("function {}", name),
+        Value::Function { .. } => "function".to_string(),
+        Value::UserData => "userdata".to_string(),
+        Value::Thread => "thread".to_string(),
+    }
+}
+
+/// The line the program stopped on, numbered, if its file can be read
+pub fn source_line(path: &str, line: u32) -> Option<String> {
+    let text = std::fs::read_to_string(path).ok()?;
+    let code = text.lines().nth(line.checked_sub(1)? as usize)?;
+    Some(format!("{:>4}  {}", line, code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wayfinder_core::runtime::Source;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            "b src/main.lua:12".parse::<ReplCommand>(),
+            Ok(ReplCommand::Break(BreakTarget::Line { source: Some("src/main.lua".to_string()), line: 12 }))
+        );
+        assert_eq!(
+            "break 7".parse::<ReplCommand>(),
+            Ok(ReplCommand::Break(BreakTarget::Line { source: None, line: 7 }))
+        );
+        assert_eq!(
+            "b Player:update".parse::<ReplCommand>(),
+            Ok(ReplCommand::Break(BreakTarget::Function("Player:update".to_string())))
+        );
+        assert_eq!("  p  player.hp + 1 ".parse::<ReplCommand>(), Ok(ReplCommand::Print("player.hp + 1".to_string())));
+        assert_eq!("info locals".parse::<ReplCommand>(), Ok(ReplCommand::Locals));
+        assert_eq!("f 2".parse::<ReplCommand>(), Ok(ReplCommand::Frame(2)));
+        assert_eq!("r".parse::<ReplCommand>(), Ok(ReplCommand::Continue));
+
+        assert!("p".parse::<ReplCommand>().unwrap_err().contains("needs an expression"));
+        assert!("d one".parse::<ReplCommand>().unwrap_err().contains("Not a breakpoint id"));
+        assert!("jump 3".parse::<ReplCommand>().unwrap_err().starts_with("Unknown command"));
+    }
+
+    #[test]
+    fn test_format_frame_and_values() {
+        let frame = Frame {
+            id: 1,
+            name: "update".to_string(),
+            source: Some(Source {
+                name: "game.lua".to_string(),
+                path: "/src/game.lua".to_string(),
+                source_reference: None,
+            }),
+            line: 12,
+            column: 0,
+            presentation_hint: None,
+        };
+        assert_eq!(format_frame(0, &frame, true), "*#0  update at game.lua:12");
+        assert_eq!(format_value(&Value::String("hi\n".to_string())), "\"hi\\n\"");
+        assert_eq!(format_value(&Value::Table { reference: 3, length: 2 }), "table (length 2)");
+    }
+}
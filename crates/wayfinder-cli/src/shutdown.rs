@@ -0,0 +1,149 @@
+//! Shutting down on SIGINT and SIGTERM, or console control events on Windows
+//!
+//! Commands that own a debuggee register a trigger, which the first signal
+//! notifies so they can end their session: a DAP server sends its pending
+//! events and `terminated`, and the debuggee is killed or left running as
+//! `onShutdown` says. When nothing is registered, or a second signal arrives,
+//! or the commands take longer than [`GRACE_PERIOD`], the process exits
+//! straight away. Either way the exit code tells which signal ended it.
+
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Time registered commands get to finish after the first signal
+pub const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// A request to shut down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGINT, or Ctrl+C or Ctrl+Break on Windows
+    Interrupt,
+    /// SIGTERM, or the console window closing on Windows
+    Terminate,
+}
+
+impl Signal {
+    /// The shell's code for a process ended by the signal: 128 plus its number
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Signal::Interrupt => 130,
+            Signal::Terminate => 143,
+        }
+    }
+}
+
+/// Hands signals to the commands that want them
+#[derive(Default)]
+pub struct Coordinator {
+    triggers: Mutex<Vec<Weak<Notify>>>,
+    signal: OnceLock<Signal>,
+}
+
+/// The process's coordinator
+pub fn coordinator() -> &'static Coordinator {
+    static COORDINATOR: OnceLock<Coordinator> = OnceLock::new();
+    COORDINATOR.get_or_init(Coordinator::default)
+}
+
+impl Coordinator {
+    /// Starts listening for signals; with `interrupt` false, SIGINT is left to the command
+    ///
+    /// The interactive debugger pauses the program on Ctrl+C, so it only
+    /// shuts down on SIGTERM.
+    pub fn install(&'static self, interrupt: bool) {
+        tokio::spawn(async move {
+            let Some(signal) = next_signal(interrupt).await else {
+                return;
+            };
+            let _ = self.signal.set(signal);
+            if self.notify_all() == 0 {
+                std::process::exit(signal.exit_code());
+            }
+            tracing::info!("Received {:?}, shutting down", signal);
+            let _ = tokio::time::timeout(GRACE_PERIOD, next_signal(interrupt)).await;
+            std::process::exit(signal.exit_code());
+        });
+    }
+
+    /// Has the first signal notify `trigger`, for as long as something else holds it
+    pub fn register(&self, trigger: &Arc<Notify>) {
+        let mut triggers = self.triggers.lock().unwrap();
+        triggers.retain(|trigger| trigger.strong_count() > 0);
+        triggers.push(Arc::downgrade(trigger));
+    }
+
+    /// The signal received, if any
+    pub fn signal(&self) -> Option<Signal> {
+        self.signal.get().copied()
+    }
+
+    /// Notifies the live triggers, returning how many there were
+    fn notify_all(&self) -> usize {
+        let triggers = self.triggers.lock().unwrap();
+        let live: Vec<Arc<Notify>> = triggers.iter().filter_map(Weak::upgrade).collect();
+        // A permit is stored for a trigger nobody is waiting on yet
+        live.iter().for_each(|trigger| trigger.notify_one());
+        live.len()
+    }
+}
+
+/// Waits for the next shutdown signal; None when signals cannot be listened for
+#[cfg(unix)]
+async fn next_signal(interrupt: bool) -> Option<Signal> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).ok()?;
+    if !interrupt {
+        terminate.recv().await;
+        return Some(Signal::Terminate);
+    }
+    let mut interrupts = signal(SignalKind::interrupt()).ok()?;
+    tokio::select! {
+        _ = interrupts.recv() => Some(Signal::Interrupt),
+        _ = terminate.recv() => Some(Signal::Terminate),
+    }
+}
+
+/// Waits for the next console control event that asks the process to end
+#[cfg(windows)]
+async fn next_signal(interrupt: bool) -> Option<Signal> {
+    use tokio::signal::windows::{ctrl_break, ctrl_c, ctrl_close, ctrl_shutdown};
+
+    let mut close = ctrl_close().ok()?;
+    let mut shutdown = ctrl_shutdown().ok()?;
+    if !interrupt {
+        tokio::select! {
+            _ = close.recv() => {},
+            _ = shutdown.recv() => {},
+        }
+        return Some(Signal::Terminate);
+    }
+    let mut interrupts = ctrl_c().ok()?;
+    let mut breaks = ctrl_break().ok()?;
+    tokio::select! {
+        _ = interrupts.recv() => Some(Signal::Interrupt),
+        _ = breaks.recv() => Some(Signal::Interrupt),
+        _ = close.recv() => Some(Signal::Terminate),
+        _ = shutdown.recv() => Some(Signal::Terminate),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_held_triggers_are_notified() {
+        let coordinator = Coordinator::default();
+        let held = Arc::new(Notify::new());
+        coordinator.register(&held);
+        coordinator.register(&Arc::new(Notify::new()));
+        assert_eq!(coordinator.notify_all(), 1);
+
+        drop(held);
+        assert_eq!(coordinator.notify_all(), 0);
+        assert_eq!(Signal::Interrupt.exit_code(), 130);
+        assert_eq!(Signal::Terminate.exit_code(), 143);
+    }
+}
@@ -4,13 +4,23 @@ fn main() {
     // Check if we're using the dynamic-lua feature
     let use_dynamic = env::var("CARGO_FEATURE_DYNAMIC_LUA").is_ok();
 
-    if use_dynamic {
+    // Embedded mode links Lua 5.4 as static builds do, for when no library is found at runtime
+    let use_embedded = env::var("CARGO_FEATURE_EMBEDDED_LUA").is_ok();
+
+    if use_dynamic && !use_embedded {
         // Dynamic loading mode - no build-time Lua dependency required
         println!("cargo:warning=Building with dynamic Lua loading support (runtime dependency only)");
         println!("cargo:warning=Lua 5.1-5.4 libraries will be loaded at runtime");
         return;
     }
 
+    if use_embedded {
+        println!("cargo:warning=Building with dynamic Lua loading and Lua 5.4 linked in as the fallback");
+        #[cfg(feature = "embedded-lua")]
+        build_embedded_lua();
+        return;
+    }
+
     // Static linking mode - for backwards compatibility
     println!("cargo:warning=Building with static Lua linking (WAYFINDER_STATIC_LUA is set)");
 
@@ -78,3 +88,34 @@ fn main() {
     println!("cargo:warning=Could not automatically find Lua 5.4 library");
     println!("cargo:warning=You may need to set PKG_CONFIG_PATH or LUA_LIB_DIR/LUA_INCLUDE_DIR environment variables");
 }
+
+/// Builds the bundled Lua 5.4 sources and links them in whole
+///
+/// The loader takes the embedded Lua's functions from the process's own
+/// symbols, so every object is kept and the executable exports them.
+#[cfg(feature = "embedded-lua")]
+fn build_embedded_lua() {
+    let artifacts = lua_src::Build::new().build(lua_src::Lua54);
+
+    cc::Build::new()
+        .include(artifacts.include_dir())
+        .file("lua/api_functions.c")
+        .cargo_metadata(false)
+        .out_dir(artifacts.lib_dir())
+        .compile("wayfinder_lua_api");
+    println!("cargo:rerun-if-changed=lua/api_functions.c");
+
+    println!("cargo:rustc-link-search=native={}", artifacts.lib_dir().display());
+    for lib in artifacts.libs().iter().map(String::as_str).chain(["wayfinder_lua_api"]) {
+        println!("cargo:rustc-link-lib=static:+whole-archive={}", lib);
+    }
+
+    if env::var("CARGO_CFG_TARGET_FAMILY").as_deref() == Ok("unix") {
+        println!("cargo:rustc-link-lib=dylib=m");
+        // Executables only export their symbols to dlsym with this; macOS ones always do.
+        // It covers this crate's tests, and wayfinder-cli passes it to its binary
+        if env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("macos") {
+            println!("cargo:rustc-link-arg=-Wl,--export-dynamic");
+        }
+    }
+}
@@ -0,0 +1,45 @@
+/*
+ * Functions for the parts of the Lua 5.4 C API that lua.h defines as macros
+ *
+ * Wayfinder declares these as functions, and the dynamic loader looks them
+ * up by name, so the embedded Lua provides them as real, exported symbols.
+ */
+
+#include "lua.h"
+
+#undef lua_insert
+#undef lua_remove
+#undef lua_replace
+#undef lua_tonumber
+#undef lua_tointeger
+#undef lua_newuserdata
+#undef lua_pop
+#undef lua_pcall
+#undef lua_pushglobaltable
+
+void lua_insert(lua_State *L, int idx) { lua_rotate(L, idx, 1); }
+
+int lua_remove(lua_State *L, int idx) {
+    lua_rotate(L, idx, -1);
+    lua_settop(L, -2);
+    return 0;
+}
+
+void lua_replace(lua_State *L, int idx) {
+    lua_copy(L, -1, idx);
+    lua_settop(L, -2);
+}
+
+lua_Number lua_tonumber(lua_State *L, int idx) { return lua_tonumberx(L, idx, NULL); }
+
+lua_Integer lua_tointeger(lua_State *L, int idx) { return lua_tointegerx(L, idx, NULL); }
+
+void *lua_newuserdata(lua_State *L, size_t size) { return lua_newuserdatauv(L, size, 1); }
+
+void lua_pop(lua_State *L, int n) { lua_settop(L, -n - 1); }
+
+int lua_pcall(lua_State *L, int nargs, int nresults, int errfunc) {
+    return lua_pcallk(L, nargs, nresults, errfunc, 0, NULL);
+}
+
+void lua_pushglobaltable(lua_State *L) { lua_rawgeti(L, LUA_REGISTRYINDEX, LUA_RIDX_GLOBALS); }
@@ -0,0 +1,312 @@
+//! Debug agent wire protocol
+//!
+//! A process embedding Wayfinder runs a [`DebugAgent`] next to its runtime and
+//! listens on a TCP port or a Unix socket. The `wayfinder attach` command then
+//! connects with a [`RemoteRuntime`](crate::runtime::remote::RemoteRuntime),
+//! which proxies every `DebugRuntime` call over the connection.
+//!
+//! Messages are DAP messages, framed and shaped as the debugger's client
+//! sees them: requests name an agent method as their `command`, responses
+//! answer them by `request_seq`, and runtime events are forwarded as they are.
+
+use crate::dap::transport::DapTransport;
+use crate::dap::{event_channel, EventReceiver, Message, ProtocolMessage, Response};
+use crate::runtime::{
+    error_code, BreakpointType, DebugRuntime, FrameStepTarget, RuntimeError, StepMode, VariableScope, VariablesPage,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use std::io;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufRead, AsyncWrite};
+
+/// Method names understood by the agent
+pub mod method {
+    pub const ATTACH: &str = "attach";
+    pub const START: &str = "start";
+    pub const SET_BREAKPOINT: &str = "setBreakpoint";
+    pub const REMOVE_BREAKPOINT: &str = "removeBreakpoint";
+    pub const STEP: &str = "step";
+    pub const STEP_INSTRUCTION: &str = "stepInstruction";
+    pub const CONTINUE: &str = "continue";
+    pub const STEP_FRAME: &str = "stepFrame";
+    pub const PAUSE: &str = "pause";
+    pub const PAUSE_THREAD: &str = "pauseThread";
+    pub const CONTINUE_THREAD: &str = "continueThread";
+    pub const THREADS: &str = "threads";
+    pub const STACK_TRACE: &str = "stackTrace";
+    pub const SCOPES: &str = "scopes";
+    pub const VARIABLES: &str = "variables";
+    pub const EVALUATE: &str = "evaluate";
+    pub const SET_VARIABLE: &str = "setVariable";
+    pub const EVALUATE_GLOBAL: &str = "evaluateGlobal";
+    pub const EVALUATE_LITERAL: &str = "evaluateLiteral";
+    pub const RUN_TO_LOCATION: &str = "runToLocation";
+    pub const GOTO_LINES: &str = "gotoLines";
+    pub const READ_MEMORY: &str = "readMemory";
+    pub const WRITE_MEMORY: &str = "writeMemory";
+    pub const SOURCE: &str = "source";
+    pub const CHECK_DATA_BREAKPOINTS: &str = "checkDataBreakpoints";
+    pub const EXCEPTION_INFO: &str = "exceptionInfo";
+    pub const MEMORY_STATISTICS: &str = "memoryStatistics";
+    pub const FORCE_GC: &str = "forceGC";
+    pub const GC_STEP: &str = "gcStep";
+    pub const TUNE_GC: &str = "tuneGC";
+    pub const FLIGHT_RECORDS: &str = "flightRecords";
+    pub const MODULES: &str = "modules";
+    pub const SEARCH_HEAP: &str = "searchHeap";
+    pub const HEAP_SNAPSHOT: &str = "heapSnapshot";
+    pub const SET_ALLOCATION_TRACKING: &str = "setAllocationTracking";
+    pub const ALLOCATION_PROFILE: &str = "allocationProfile";
+    pub const SET_STRING_ENCODING: &str = "setStringEncoding";
+    pub const SET_MEMORY_LIMIT: &str = "setMemoryLimit";
+    pub const DETACH: &str = "detach";
+}
+
+/// Version an agent reports on attach; the debugger only talks to agents of its own version
+pub const AGENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Socket path an agent uses when attached to by process id
+pub fn socket_path_for_pid(pid: u32) -> PathBuf {
+    std::env::temp_dir().join(format!("wayfinder-{}.sock", pid))
+}
+
+/// Serves a local runtime to a remote debugger
+pub struct DebugAgent<R: DebugRuntime> {
+    runtime: R,
+    events: EventReceiver,
+}
+
+impl<R: DebugRuntime> DebugAgent<R> {
+    pub fn new(mut runtime: R) -> Self {
+        let (sender, events) = event_channel();
+        runtime.set_event_sender(sender);
+        Self { runtime, events }
+    }
+
+    /// Accepts one debugger connection on a TCP address and serves it
+    pub async fn listen_tcp(&mut self, address: &str) -> io::Result<()> {
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        self.accept_tcp(&listener).await
+    }
+
+    /// Accepts one debugger connection on a bound listener and serves it
+    ///
+    /// Hosts that outlive their debugging sessions call this again for the
+    /// next debugger once one detaches.
+    pub async fn accept_tcp(&mut self, listener: &tokio::net::TcpListener) -> io::Result<()> {
+        let (stream, _) = listener.accept().await?;
+        self.serve(&mut DapTransport::tcp(stream)).await
+    }
+
+    /// Accepts one debugger connection on this process's Unix socket and serves it
+    #[cfg(unix)]
+    pub async fn listen_unix(&mut self) -> io::Result<()> {
+        let path = socket_path_for_pid(std::process::id());
+        // A stale socket from an earlier run with the same pid blocks binding
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path)?;
+        let accepted = listener.accept().await;
+        let _ = std::fs::remove_file(&path);
+
+        let (stream, _) = accepted?;
+        self.serve(&mut DapTransport::unix(stream)).await
+    }
+
+    /// Handles requests until the debugger disconnects
+    pub async fn serve<Rd, Wr>(&mut self, transport: &mut DapTransport<Rd, Wr>) -> io::Result<()>
+    where
+        Rd: AsyncBufRead + Unpin,
+        Wr: AsyncWrite + Unpin,
+    {
+        loop {
+            tokio::select! {
+                message = transport.read_protocol_message() => match message? {
+                    Some(ProtocolMessage::Request(request)) => {
+                        // Errors keep their code, so the debugger reports them as it would its own
+                        let response = match self.dispatch(&request).await {
+                            Ok(result) => Response::new_ok(request.id, &request.method, result),
+                            Err(RuntimeError::NotImplemented(message)) => {
+                                Response::new_error(request.id, &request.method, error_code::NOT_IMPLEMENTED, message)
+                            }
+                            Err(e) => Response::new_error(request.id, &request.method, e.code(), e.to_string()),
+                        };
+                        transport.write_protocol_message(&ProtocolMessage::Response(response)).await?;
+                    }
+                    // The debugger only sends requests
+                    Some(_) => {}
+                    // The host keeps running, so undo the debugger's changes even without a detach request
+                    None => {
+                        if let Err(e) = self.runtime.detach().await {
+                            tracing::error!("error detaching from the program: {}", e);
+                        }
+                        return Ok(());
+                    }
+                },
+                Some(event) = self.events.recv() => {
+                    transport.write_protocol_message(&ProtocolMessage::Event(event)).await?;
+                }
+            }
+        }
+    }
+
+    async fn dispatch(&mut self, request: &Message) -> Result<JsonValue, RuntimeError> {
+        let params = &request.params;
+        match request.method.as_str() {
+            method::ATTACH => {
+                let mut version = to_json(self.runtime.version().await)?;
+                version["agentVersion"] = json!(AGENT_VERSION);
+                Ok(version)
+            }
+            method::START => {
+                let stop_on_entry = param(params, "stopOnEntry")?;
+                to_json(self.runtime.start_program(stop_on_entry).await?)
+            }
+            method::SET_BREAKPOINT => {
+                let breakpoint: BreakpointType = param(params, "breakpoint")?;
+                to_json(self.runtime.set_breakpoint(breakpoint).await?)
+            }
+            method::REMOVE_BREAKPOINT => to_json(self.runtime.remove_breakpoint(param(params, "id")?).await?),
+            method::STEP => {
+                let mode: StepMode = param(params, "mode")?;
+                to_json(self.runtime.step(mode).await?)
+            }
+            method::STEP_INSTRUCTION => {
+                let mode: StepMode = param(params, "mode")?;
+                to_json(self.runtime.step_instruction(mode).await?)
+            }
+            method::CONTINUE => to_json(self.runtime.continue_().await?),
+            method::STEP_FRAME => {
+                let function: String = param(params, "function")?;
+                let until: FrameStepTarget = param(params, "until")?;
+                to_json(self.runtime.step_frame(&function, until).await?)
+            }
+            method::PAUSE => to_json(self.runtime.pause().await?),
+            method::PAUSE_THREAD => to_json(self.runtime.pause_thread(param(params, "threadId")?).await?),
+            method::CONTINUE_THREAD => to_json(self.runtime.continue_thread(param(params, "threadId")?).await?),
+            method::THREADS => to_json(self.runtime.threads().await?),
+            method::STACK_TRACE => to_json(self.runtime.stack_trace(param(params, "threadId")?).await?),
+            method::SCOPES => to_json(self.runtime.scopes(param(params, "frameId")?).await?),
+            method::VARIABLES => {
+                let reference = param(params, "variablesReference")?;
+                let page: Option<VariablesPage> = param(params, "page")?;
+                match page {
+                    Some(page) => to_json(self.runtime.variables_page(reference, page).await?),
+                    None => {
+                        let filter: Option<VariableScope> = param(params, "filter")?;
+                        to_json(self.runtime.variables(reference, filter).await?)
+                    }
+                }
+            }
+            method::EVALUATE => {
+                let expression: String = param(params, "expression")?;
+                to_json(self.runtime.evaluate(param(params, "frameId")?, &expression).await?)
+            }
+            method::SET_VARIABLE => {
+                let name: String = param(params, "name")?;
+                let value: String = param(params, "value")?;
+                to_json(
+                    self.runtime
+                        .set_variable(param(params, "variablesReference")?, &name, &value)
+                        .await?,
+                )
+            }
+            method::EVALUATE_GLOBAL => {
+                let expression: String = param(params, "expression")?;
+                to_json(self.runtime.evaluate_global(&expression).await?)
+            }
+            method::EVALUATE_LITERAL => {
+                let expression: String = param(params, "expression")?;
+                to_json(self.runtime.evaluate_literal(param(params, "frameId")?, &expression).await?)
+            }
+            method::GOTO_LINES => {
+                let source: String = param(params, "source")?;
+                to_json(self.runtime.goto_lines(&source).await?)
+            }
+            method::READ_MEMORY => {
+                let reference: String = param(params, "memoryReference")?;
+                to_json(self.runtime.read_memory(&reference, param(params, "offset")?, param(params, "count")?).await?)
+            }
+            method::WRITE_MEMORY => {
+                let reference: String = param(params, "memoryReference")?;
+                let data = param(params, "data")?;
+                to_json(
+                    self.runtime
+                        .write_memory(&reference, param(params, "offset")?, data, param(params, "allowPartial")?)
+                        .await?,
+                )
+            }
+            method::RUN_TO_LOCATION => {
+                let source: String = param(params, "source")?;
+                to_json(self.runtime.run_to_location(&source, param(params, "line")?).await?)
+            }
+            method::SOURCE => to_json(self.runtime.source(param(params, "sourceReference")?).await?),
+            method::CHECK_DATA_BREAKPOINTS => {
+                to_json(self.runtime.check_data_breakpoints(param(params, "frameId")?).await?)
+            }
+            method::EXCEPTION_INFO => to_json(self.runtime.get_exception_info(param(params, "threadId")?).await?),
+            method::MEMORY_STATISTICS => to_json(self.runtime.get_memory_statistics().await?),
+            method::FORCE_GC => to_json(self.runtime.force_gc().await?),
+            method::GC_STEP => to_json(self.runtime.gc_step(param(params, "kb")?).await?),
+            method::TUNE_GC => {
+                let pause = param(params, "pause")?;
+                to_json(self.runtime.tune_gc(pause, param(params, "stepMul")?).await?)
+            }
+            method::DETACH => to_json(self.runtime.detach().await?),
+            method::FLIGHT_RECORDS => to_json(self.runtime.flight_records().await?),
+            method::MODULES => to_json(self.runtime.modules().await?),
+            method::HEAP_SNAPSHOT => to_json(self.runtime.take_heap_snapshot().await?),
+            method::SET_ALLOCATION_TRACKING => {
+                to_json(self.runtime.set_allocation_tracking(param(params, "enabled")?).await?)
+            }
+            method::ALLOCATION_PROFILE => {
+                let limit = param(params, "limit")?;
+                to_json(self.runtime.allocation_profile(limit, param(params, "reset")?).await?)
+            }
+            method::SEARCH_HEAP => {
+                let predicate: String = param(params, "predicate")?;
+                let max_tables = param(params, "maxTables")?;
+                to_json(
+                    self.runtime
+                        .search_heap(&predicate, max_tables, param(params, "maxResults")?)
+                        .await?,
+                )
+            }
+            method::SET_STRING_ENCODING => {
+                let label: Option<String> = param(params, "encoding")?;
+                let encoding = match label {
+                    Some(label) => crate::debug::encoding::lookup(&label).map_err(RuntimeError::Communication)?,
+                    None => None,
+                };
+                self.runtime.set_string_encoding(encoding);
+                Ok(JsonValue::Null)
+            }
+            method::SET_MEMORY_LIMIT => {
+                self.runtime.set_memory_limit(param(params, "limitKb")?);
+                Ok(JsonValue::Null)
+            }
+            other => Err(RuntimeError::NotImplemented(format!("Unknown agent method: {}", other))),
+        }
+    }
+}
+
+/// Reads a named parameter, treating a missing one as `null`
+fn param<T: DeserializeOwned>(params: &JsonValue, name: &str) -> Result<T, RuntimeError> {
+    let value = params.get(name).cloned().unwrap_or(JsonValue::Null);
+    serde_json::from_value(value)
+        .map_err(|e| RuntimeError::Communication(format!("Invalid parameter '{}': {}", name, e)))
+}
+
+fn to_json<T: Serialize>(value: T) -> Result<JsonValue, RuntimeError> {
+    serde_json::to_value(value).map_err(|e| RuntimeError::Communication(e.to_string()))
+}
+
+/// Builds the parameters object for a request
+pub(crate) fn params(pairs: &[(&str, JsonValue)]) -> JsonValue {
+    let mut object = json!({});
+    for (name, value) in pairs {
+        object[*name] = value.clone();
+    }
+    object
+}
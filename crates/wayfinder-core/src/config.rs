@@ -3,6 +3,7 @@
 //! This module provides configuration options for the debugger,
 //! including evaluate mutation settings.
 
+use crate::runtime::{DisplayLimits, StepGranularity, ValueFormat};
 use serde::{Deserialize, Serialize};
 
 /// Configuration for the Wayfinder debugger
@@ -19,10 +20,115 @@ pub struct DebuggerConfig {
     /// Safety level for evaluation
     #[serde(default)]
     pub eval_safety: EvalSafety,
+
+    /// Whether to collapse TypeScriptToLua runtime frames in stack traces
+    #[serde(default = "default_collapse_lualib_frames")]
+    pub collapse_lualib_frames: bool,
+
+    /// Whether the stack of a stopped coroutine continues into the threads that resumed it
+    #[serde(default = "default_stitch_coroutine_stacks")]
+    pub stitch_coroutine_stacks: bool,
+
+    /// Encoding label for source files and Lua strings, e.g. `shift_jis`
+    ///
+    /// Auto-detected when unset.
+    #[serde(default)]
+    pub source_encoding: Option<String>,
+
+    /// Memory the program may use, in kilobytes, before it is stopped
+    ///
+    /// Unlimited when unset.
+    #[serde(default)]
+    pub memory_limit_kb: Option<u64>,
+
+    /// Instructions an evaluated expression may run before it is stopped; 0 for no limit
+    #[serde(default = "default_evaluation_instruction_limit")]
+    pub evaluation_instruction_limit: u64,
+
+    /// Milliseconds an evaluated expression may run before it is stopped; 0 for no limit
+    #[serde(default = "default_evaluation_timeout_ms")]
+    pub evaluation_timeout_ms: u64,
+
+    /// Whether launched programs are recorded so the client can step backwards
+    #[serde(default)]
+    pub record_replay: bool,
+
+    /// Executed lines kept for the execution trace; 0 turns it off
+    #[serde(default = "default_execution_trace_lines")]
+    pub execution_trace_lines: usize,
+
+    /// Granularity of steps whose request does not give one
+    #[serde(default)]
+    pub step_granularity: StepGranularity,
+
+    /// Named children a table lists in the Variables pane before the rest are left out
+    #[serde(default = "default_max_variable_children")]
+    pub max_variable_children: usize,
+
+    /// Characters of a string shown in values before it is cut
+    #[serde(default = "default_max_string_length")]
+    pub max_string_length: usize,
+
+    /// Digits shown after the decimal point of fractional numbers
+    ///
+    /// The shortest exact form when unset; a request's `format` can ask for another.
+    #[serde(default)]
+    pub float_precision: Option<usize>,
+
+    /// Whether `writeMemory` may change the block of a full userdata
+    ///
+    /// Off by default, as nothing stops a write from breaking what the host keeps there.
+    #[serde(default)]
+    pub unsafe_memory_writes: bool,
+}
+
+fn default_collapse_lualib_frames() -> bool {
+    true
+}
+
+fn default_stitch_coroutine_stacks() -> bool {
+    true
+}
+
+fn default_evaluation_instruction_limit() -> u64 {
+    10_000_000
+}
+
+fn default_evaluation_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_execution_trace_lines() -> usize {
+    crate::debug::execution_trace::DEFAULT_CAPACITY
+}
+
+fn default_max_variable_children() -> usize {
+    DisplayLimits::default().max_children
+}
+
+fn default_max_string_length() -> usize {
+    DisplayLimits::default().max_string_length
+}
+
+impl DebuggerConfig {
+    pub fn display_limits(&self) -> DisplayLimits {
+        DisplayLimits {
+            max_children: self.max_variable_children,
+            max_string_length: self.max_string_length,
+        }
+    }
+
+    /// How numbers are shown when a request's `format` does not say
+    pub fn value_format(&self) -> ValueFormat {
+        ValueFormat {
+            hex: false,
+            precision: self.float_precision,
+        }
+    }
 }
 
 /// Safety levels for expression evaluation
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EvalSafety {
     /// No safety checks - allow all operations
     None,
@@ -38,12 +144,138 @@ impl Default for EvalSafety {
     }
 }
 
+impl EvalSafety {
+    /// Reads a level as settings write it: `none`, `basic` or `strict`
+    pub fn parse(label: &str) -> Result<Self, String> {
+        match label.to_ascii_lowercase().as_str() {
+            "none" => Ok(EvalSafety::None),
+            "basic" => Ok(EvalSafety::Basic),
+            "strict" => Ok(EvalSafety::Strict),
+            _ => Err(format!("Unknown evaluation safety `{}`: expected none, basic or strict", label)),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EvalSafety::None => "none",
+            EvalSafety::Basic => "basic",
+            EvalSafety::Strict => "strict",
+        }
+    }
+}
+
+/// Changes to a `DebuggerConfig` made while a session runs
+///
+/// The arguments of the `wayfinder/configure` request, and the `debugger`
+/// section of wayfinder.yaml. Settings left unset keep their value.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebuggerSettings {
+    /// `none`, `basic` or `strict`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eval_safety: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evaluate_mutation: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evaluation_instruction_limit: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evaluation_timeout_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step_granularity: Option<StepGranularity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_variable_children: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_string_length: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub float_precision: Option<usize>,
+    /// `tracing` filter for the adapter's diagnostics; applied by the embedder, not the config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+}
+
+impl DebuggerSettings {
+    /// The settings `config` has, with no log level
+    pub fn of(config: &DebuggerConfig) -> Self {
+        Self {
+            eval_safety: Some(config.eval_safety.label().to_string()),
+            evaluate_mutation: Some(config.evaluate_mutation),
+            evaluation_instruction_limit: Some(config.evaluation_instruction_limit),
+            evaluation_timeout_ms: Some(config.evaluation_timeout_ms),
+            step_granularity: Some(config.step_granularity),
+            max_variable_children: Some(config.max_variable_children),
+            max_string_length: Some(config.max_string_length),
+            float_precision: config.float_precision,
+            log_level: None,
+        }
+    }
+
+    /// These settings, with the ones they leave unset taken from `fallback`
+    pub fn or(self, fallback: DebuggerSettings) -> Self {
+        Self {
+            eval_safety: self.eval_safety.or(fallback.eval_safety),
+            evaluate_mutation: self.evaluate_mutation.or(fallback.evaluate_mutation),
+            evaluation_instruction_limit: self.evaluation_instruction_limit.or(fallback.evaluation_instruction_limit),
+            evaluation_timeout_ms: self.evaluation_timeout_ms.or(fallback.evaluation_timeout_ms),
+            step_granularity: self.step_granularity.or(fallback.step_granularity),
+            max_variable_children: self.max_variable_children.or(fallback.max_variable_children),
+            max_string_length: self.max_string_length.or(fallback.max_string_length),
+            float_precision: self.float_precision.or(fallback.float_precision),
+            log_level: self.log_level.or(fallback.log_level),
+        }
+    }
+
+    /// Writes the settings that are set into `config`
+    ///
+    /// Nothing is written if one of them is invalid.
+    pub fn apply(&self, config: &mut DebuggerConfig) -> Result<(), String> {
+        let eval_safety = self.eval_safety.as_deref().map(EvalSafety::parse).transpose()?;
+        if let Some(eval_safety) = eval_safety {
+            config.eval_safety = eval_safety;
+        }
+        if let Some(evaluate_mutation) = self.evaluate_mutation {
+            config.evaluate_mutation = evaluate_mutation;
+        }
+        if let Some(limit) = self.evaluation_instruction_limit {
+            config.evaluation_instruction_limit = limit;
+        }
+        if let Some(timeout_ms) = self.evaluation_timeout_ms {
+            config.evaluation_timeout_ms = timeout_ms;
+        }
+        if let Some(granularity) = self.step_granularity {
+            config.step_granularity = granularity;
+        }
+        if let Some(children) = self.max_variable_children {
+            config.max_variable_children = children;
+        }
+        if let Some(length) = self.max_string_length {
+            config.max_string_length = length;
+        }
+        if let Some(precision) = self.float_precision {
+            config.float_precision = Some(precision);
+        }
+        Ok(())
+    }
+}
+
 impl Default for DebuggerConfig {
     fn default() -> Self {
         Self {
             evaluate_mutation: false,
             show_modifications: true,
             eval_safety: EvalSafety::default(),
+            collapse_lualib_frames: default_collapse_lualib_frames(),
+            stitch_coroutine_stacks: default_stitch_coroutine_stacks(),
+            source_encoding: None,
+            memory_limit_kb: None,
+            evaluation_instruction_limit: default_evaluation_instruction_limit(),
+            evaluation_timeout_ms: default_evaluation_timeout_ms(),
+            record_replay: false,
+            execution_trace_lines: default_execution_trace_lines(),
+            step_granularity: StepGranularity::default(),
+            max_variable_children: default_max_variable_children(),
+            max_string_length: default_max_string_length(),
+            float_precision: None,
+            unsafe_memory_writes: false,
         }
     }
 }
@@ -58,6 +290,19 @@ mod tests {
         assert!(!config.evaluate_mutation);
         assert!(config.show_modifications);
         assert_eq!(config.eval_safety, EvalSafety::Basic);
+        assert!(config.collapse_lualib_frames);
+        assert!(config.stitch_coroutine_stacks);
+        assert!(config.source_encoding.is_none());
+        assert!(config.memory_limit_kb.is_none());
+        assert_eq!(config.evaluation_instruction_limit, 10_000_000);
+        assert_eq!(config.evaluation_timeout_ms, 1000);
+        assert!(!config.record_replay);
+        assert_eq!(config.execution_trace_lines, 64);
+        assert_eq!(config.step_granularity, StepGranularity::Line);
+        assert_eq!(config.max_variable_children, 100);
+        assert_eq!(config.max_string_length, 200);
+        assert_eq!(config.float_precision, None);
+        assert!(!config.unsafe_memory_writes);
     }
 
     #[test]
@@ -66,10 +311,35 @@ mod tests {
             evaluate_mutation: true,
             show_modifications: false,
             eval_safety: EvalSafety::Strict,
+            ..Default::default()
         };
 
         assert!(config.evaluate_mutation);
         assert!(!config.show_modifications);
         assert_eq!(config.eval_safety, EvalSafety::Strict);
     }
+
+    #[test]
+    fn test_settings_change_only_what_they_set() {
+        let mut config = DebuggerConfig::default();
+        let settings: DebuggerSettings =
+            serde_json::from_value(serde_json::json!({ "evalSafety": "Strict", "maxStringLength": 40 })).unwrap();
+        settings.apply(&mut config).unwrap();
+        assert_eq!(config.eval_safety, EvalSafety::Strict);
+        assert_eq!(config.max_string_length, 40);
+        assert_eq!(config.max_variable_children, 100);
+
+        let invalid = DebuggerSettings {
+            eval_safety: Some("paranoid".to_string()),
+            max_string_length: Some(10),
+            ..Default::default()
+        };
+        assert!(invalid.apply(&mut config).unwrap_err().contains("paranoid"));
+        assert_eq!(config.max_string_length, 40);
+
+        let merged = DebuggerSettings { max_string_length: Some(10), ..Default::default() }.or(settings);
+        assert_eq!(merged.max_string_length, Some(10));
+        assert_eq!(merged.eval_safety.as_deref(), Some("Strict"));
+        assert_eq!(DebuggerSettings::of(&config).eval_safety.as_deref(), Some("strict"));
+    }
 }
@@ -0,0 +1,512 @@
+//! Arguments of the DAP requests the server handles
+//!
+//! Each request's `arguments` object is deserialized into its struct before
+//! the handler runs, so a malformed request is answered with an error naming
+//! the field at fault instead of going unanswered. Fields follow the DAP
+//! specification's names and optionality; the custom `wayfinder` requests
+//! that share a shape reuse these structs.
+
+use crate::config::DebuggerSettings;
+use crate::debug::inventory::InventoryScope;
+use crate::runtime::{FrameStepTarget, StepGranularity, ValueFormat, VariablesFilter};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+/// Error code of a response to a request whose arguments do not deserialize
+pub const INVALID_ARGUMENTS: i32 = -32602;
+
+/// Deserializes the arguments of a `command` request
+///
+/// Requests without arguments send none, which reads as an empty object so
+/// that structs with only optional fields accept them.
+pub fn parse<T: DeserializeOwned>(command: &str, params: &JsonValue) -> Result<T, String> {
+    let parsed = if params.is_null() {
+        T::deserialize(&JsonValue::Object(Default::default()))
+    } else {
+        T::deserialize(params)
+    };
+    parsed.map_err(|e| format!("Invalid {} arguments: {}", command, e))
+}
+
+/// A DAP `Source` as requests refer to one
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestSource {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub source_reference: Option<i64>,
+}
+
+impl RequestSource {
+    /// The source's path, for requests that only work on files
+    pub fn path(&self) -> Result<&str, String> {
+        self.path.as_deref().ok_or_else(|| "The source has no path".to_string())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceBreakpoint {
+    pub line: u32,
+    #[serde(default)]
+    pub column: Option<u32>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub hit_condition: Option<String>,
+    #[serde(default)]
+    pub log_message: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetBreakpointsArguments {
+    pub source: RequestSource,
+    /// The source's complete set; missing clears it
+    #[serde(default)]
+    pub breakpoints: Vec<SourceBreakpoint>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionBreakpoint {
+    pub name: String,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub hit_condition: Option<String>,
+    #[serde(default)]
+    pub log_message: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetFunctionBreakpointsArguments {
+    #[serde(default)]
+    pub breakpoints: Vec<FunctionBreakpoint>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExceptionFilterOptions {
+    pub filter_id: String,
+    /// Lua expression that must be truthy for the exception to stop; it sees the error as `message`
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub hit_condition: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetExceptionBreakpointsArguments {
+    #[serde(default)]
+    pub filters: Vec<String>,
+    #[serde(default)]
+    pub filter_options: Vec<ExceptionFilterOptions>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataBreakpoint {
+    pub data_id: String,
+    /// `read`, `write` or `readWrite`; checked against the access types the runtime supports
+    #[serde(default)]
+    pub access_type: Option<String>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub hit_condition: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetDataBreakpointsArguments {
+    #[serde(default)]
+    pub breakpoints: Vec<DataBreakpoint>,
+}
+
+/// A breakpoint on a metamethod event of the metatable an expression evaluates to
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetamethodBreakpoint {
+    pub target: String,
+    pub event: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetMetamethodBreakpointsArguments {
+    pub breakpoints: Vec<MetamethodBreakpoint>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BreakpointLocationsArguments {
+    pub source: RequestSource,
+    pub line: u32,
+    /// Last line of the range; the range is `line` alone without it
+    #[serde(default)]
+    pub end_line: Option<u32>,
+}
+
+/// Arguments of `continue`, `next`, `stepIn`, `stepOut` and `pause`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionArguments {
+    #[serde(default)]
+    pub thread_id: Option<u64>,
+    /// Acts on `thread_id` alone instead of the whole program
+    #[serde(default)]
+    pub single_thread: bool,
+    /// How far a step goes; the session's configured granularity without it
+    #[serde(default)]
+    pub granularity: Option<StepGranularity>,
+}
+
+impl ExecutionArguments {
+    /// The thread the request is limited to, when it sets `singleThread`
+    pub fn single_thread(&self) -> Option<u64> {
+        self.thread_id.filter(|_| self.single_thread)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StepFrameArguments {
+    /// The per-frame function, as an expression such as `love.update`
+    pub function: String,
+    #[serde(default = "default_frame_step_target")]
+    pub until: FrameStepTarget,
+}
+
+fn default_frame_step_target() -> FrameStepTarget {
+    FrameStepTarget::NextCall
+}
+
+/// Arguments of `gotoTargets` and `runToLocation`, which both name a line of a file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocationArguments {
+    pub source: RequestSource,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GotoArguments {
+    #[serde(default)]
+    pub thread_id: Option<u64>,
+    pub target_id: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StackTraceArguments {
+    #[serde(default)]
+    pub thread_id: Option<u64>,
+    #[serde(default)]
+    pub start_frame: usize,
+    /// Frames to return; 0 for all
+    #[serde(default)]
+    pub levels: usize,
+    #[serde(default)]
+    pub format: StackFrameFormat,
+}
+
+/// A DAP `StackFrameFormat`; frames show no parameters, so only `line` and `module` apply
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StackFrameFormat {
+    /// Adds the line to the frame's name
+    #[serde(default)]
+    pub line: bool,
+    /// Adds the file the frame runs in to its name
+    #[serde(default)]
+    pub module: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScopesArguments {
+    pub frame_id: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VariablesArguments {
+    pub variables_reference: i64,
+    #[serde(default)]
+    pub filter: Option<VariablesFilter>,
+    #[serde(default)]
+    pub start: usize,
+    /// Children to return; 0 for all
+    #[serde(default)]
+    pub count: usize,
+    /// DAP `ValueFormat`, plus the `precision` of fractional numbers
+    #[serde(default)]
+    pub format: ValueFormat,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetVariableArguments {
+    pub variables_reference: i64,
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluateArguments {
+    pub expression: String,
+    #[serde(default)]
+    pub frame_id: Option<i64>,
+    /// Where the expression comes from: `watch`, `repl`, `hover`, `clipboard` and so on
+    #[serde(default)]
+    pub context: Option<String>,
+    #[serde(default)]
+    pub format: ValueFormat,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionsArguments {
+    #[serde(default)]
+    pub text: String,
+    /// Cursor position in `text`, starting at 1; the end of the text without it
+    #[serde(default)]
+    pub column: Option<usize>,
+    #[serde(default)]
+    pub frame_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceArguments {
+    #[serde(default)]
+    pub source: Option<RequestSource>,
+    /// The same as `source.sourceReference`, for clients that predate `source`
+    #[serde(default)]
+    pub source_reference: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModulesArguments {
+    #[serde(default)]
+    pub start_module: usize,
+    /// Modules to return; 0 for all
+    #[serde(default)]
+    pub module_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadMemoryArguments {
+    pub memory_reference: String,
+    #[serde(default)]
+    pub offset: i64,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteMemoryArguments {
+    pub memory_reference: String,
+    #[serde(default)]
+    pub offset: i64,
+    /// Writes the bytes that fit rather than refusing a write past the end
+    #[serde(default)]
+    pub allow_partial: bool,
+    /// The bytes to write, base64 encoded
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigureArguments {
+    /// Reads the settings file again first; the settings given here win over it
+    #[serde(default)]
+    pub reload: bool,
+    #[serde(flatten)]
+    pub settings: DebuggerSettings,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExceptionInfoArguments {
+    #[serde(default)]
+    pub thread_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchesArguments {
+    /// Expressions to start watching
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BreakpointInventoryArguments {
+    #[serde(default)]
+    pub scope: InventoryScope,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfilingStartArguments {
+    /// `sampling`, `wallClock`, `callTrace` or `lineLevel`; `sampling` without it
+    #[serde(default)]
+    pub mode: Option<String>,
+    /// Interval of the sampling modes, 10 ms without it
+    #[serde(default)]
+    pub interval_ms: Option<u32>,
+}
+
+/// Arguments of `wayfinder/profile/export` and `heapSnapshot`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportArguments {
+    /// Profile format, for profiles: `speedscope` or `chrome`
+    #[serde(default)]
+    pub format: Option<String>,
+    /// File to write; the result carries the document without it
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub serializer: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryGcArguments {
+    /// `collect` or `step`; `collect` without it
+    #[serde(default)]
+    pub mode: Option<String>,
+    /// Kilobytes an incremental step collects
+    #[serde(default)]
+    pub step_kb: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryTuneArguments {
+    #[serde(default)]
+    pub pause: Option<i32>,
+    #[serde(default)]
+    pub step_mul: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryAllocationsArguments {
+    /// Starts or stops tracking before the lines are read
+    #[serde(default)]
+    pub track: Option<bool>,
+    /// Lines to return
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// Clears the counts once they are returned
+    #[serde(default)]
+    pub reset: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleSessionArguments {
+    pub path: String,
+    #[serde(default)]
+    pub redact_sources: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeapSearchArguments {
+    /// Lua expression run on each table as `t`
+    pub predicate: String,
+    #[serde(default)]
+    pub max_tables: Option<usize>,
+    #[serde(default)]
+    pub max_results: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HotReloadArguments {
+    /// `name` as the original `hotReload` request called it
+    #[serde(default, alias = "name")]
+    pub module: Option<String>,
+    /// File the module was loaded from
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Unsaved text to load instead of the file
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_missing_arguments_read_as_empty() {
+        let args: StackTraceArguments = parse("stackTrace", &JsonValue::Null).unwrap();
+        assert_eq!(args, StackTraceArguments::default());
+        let args: SetBreakpointsArguments =
+            parse("setBreakpoints", &json!({ "source": { "path": "main.lua" } })).unwrap();
+        assert!(args.breakpoints.is_empty());
+        assert_eq!(args.source.path(), Ok("main.lua"));
+    }
+
+    #[test]
+    fn test_malformed_arguments_name_the_field() {
+        let error = parse::<ScopesArguments>("scopes", &json!({})).unwrap_err();
+        assert!(error.contains("scopes") && error.contains("frameId"), "{}", error);
+
+        let error = parse::<SetBreakpointsArguments>(
+            "setBreakpoints",
+            &json!({ "source": { "path": "main.lua" }, "breakpoints": [{ "column": 2 }] }),
+        )
+        .unwrap_err();
+        assert!(error.contains("line"), "{}", error);
+
+        let error = parse::<StepFrameArguments>("stepFrame", &json!({ "function": "f", "until": "later" }))
+            .unwrap_err();
+        assert!(error.contains("later"), "{}", error);
+    }
+
+    #[test]
+    fn test_single_thread_needs_the_flag() {
+        let args: ExecutionArguments = parse("continue", &json!({ "threadId": 3 })).unwrap();
+        assert_eq!(args.single_thread(), None);
+        let args: ExecutionArguments =
+            parse("continue", &json!({ "threadId": 3, "singleThread": true, "granularity": "instruction" })).unwrap();
+        assert_eq!(args.single_thread(), Some(3));
+        assert_eq!(args.granularity, Some(StepGranularity::Instruction));
+    }
+
+    #[test]
+    fn test_custom_requests_check_their_types() {
+        let args: HotReloadArguments = parse("wayfinder/hotReload", &json!({ "name": "player" })).unwrap();
+        assert_eq!(args.module.as_deref(), Some("player"));
+        let args: BreakpointInventoryArguments = parse("breakpointInventory", &JsonValue::Null).unwrap();
+        assert_eq!(args.scope, InventoryScope::All);
+
+        let error = parse::<MemoryAllocationsArguments>("wayfinder/memory/allocations", &json!({ "track": "yes" }))
+            .unwrap_err();
+        assert!(error.contains("wayfinder/memory/allocations"), "{}", error);
+        assert!(parse::<MemoryTuneArguments>("wayfinder/memory/tune", &json!({ "pause": 1.5 })).is_err());
+        assert!(parse::<BundleSessionArguments>("wayfinder/bundleSession", &json!({})).is_err());
+        assert!(parse::<ExceptionInfoArguments>("exceptionInfo", &json!({ "threadId": "main" })).is_err());
+    }
+}
@@ -1,21 +1,43 @@
+pub mod arguments;
+pub mod transport;
+
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use tokio::sync::mpsc;
+
+/// Sending half of the channel used to deliver events to the DAP client
+pub type EventSender = mpsc::UnboundedSender<Event>;
+
+/// Receiving half of the channel used to deliver events to the DAP client
+pub type EventReceiver = mpsc::UnboundedReceiver<Event>;
+
+/// Creates a channel for events raised outside of request handling
+pub fn event_channel() -> (EventSender, EventReceiver) {
+    mpsc::unbounded_channel()
+}
 
 fn default_null() -> serde_json::Value {
     serde_json::Value::Null
 }
 
+/// A request, sent on the wire as `{"seq", "type": "request", "command", "arguments"}`
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Message {
+    /// The request's `seq`, which its response echoes as `request_seq`
     pub id: u64,
     pub method: String,
     #[serde(default = "default_null")]
     pub params: serde_json::Value,
 }
 
+/// A response, sent on the wire as `{"seq", "type": "response", "request_seq", "success", "command", ...}`
+///
+/// A success carries its result as `body`. A failure carries its message as
+/// `message` and as a DAP `Message` in `body.error`, whose `id` is the code.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Response {
-    pub id: u64,
+    pub request_seq: u64,
+    pub command: String,
     pub result: Result<serde_json::Value, ResponseError>,
 }
 
@@ -23,6 +45,9 @@ pub struct Response {
 pub struct ResponseError {
     pub code: i32,
     pub message: String,
+    /// Whether the client should show the message to the user; left to the client when None
+    #[serde(default)]
+    pub show_user: Option<bool>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -72,6 +97,26 @@ impl Event {
         Self::new("stopped", Some(body))
     }
 
+    /// Stopped event with a description the client shows for the stop
+    pub fn stopped_with_description(reason: &str, description: &str, thread_id: Option<u64>) -> Self {
+        let mut event = Self::stopped(reason, thread_id, true);
+        if let Some(body) = event.body.as_mut() {
+            body["description"] = serde_json::json!(description);
+            body["text"] = serde_json::json!(description);
+        }
+        event
+    }
+
+    /// Stopped event for a data breakpoint, with the values it saw
+    pub fn data_breakpoint_stopped(hit: &crate::debug::watchpoints::DataBreakpointHit, thread_id: Option<u64>) -> Self {
+        let mut event = Self::stopped_with_description("data breakpoint", &hit.description(), thread_id);
+        if let Some(body) = event.body.as_mut() {
+            body["hitBreakpointIds"] = serde_json::json!([hit.id]);
+            body["dataBreakpoint"] = hit.to_json();
+        }
+        event
+    }
+
     pub fn continued(thread_id: Option<u64>, all_threads_continued: bool) -> Self {
         let mut body = serde_json::json!({
             "allThreadsContinued": all_threads_continued,
@@ -101,6 +146,51 @@ impl Event {
         Self::new("output", Some(body))
     }
 
+    /// Output attributed to a line of a source file
+    pub fn output_at(category: &str, text: &str, path: &str, line: u32) -> Self {
+        let body = serde_json::json!({
+            "category": category,
+            "output": text,
+            "source": { "path": path },
+            "line": line,
+        });
+        Self::new("output", Some(body))
+    }
+
+    /// Tells the client a breakpoint changed, such as becoming verified
+    pub fn breakpoint(reason: &str, breakpoint: crate::runtime::Breakpoint) -> Self {
+        let mut body = serde_json::json!({
+            "reason": reason,
+            "breakpoint": {
+                "id": breakpoint.id,
+                "verified": breakpoint.verified,
+                "line": breakpoint.line,
+            },
+        });
+        if let Some(message) = breakpoint.message {
+            body["breakpoint"]["message"] = serde_json::json!(message);
+        }
+        Self::new("breakpoint", Some(body))
+    }
+
+    /// A breakpoint event that also names the breakpoint's source, for breakpoints the client did not set
+    pub fn breakpoint_in(reason: &str, breakpoint: crate::runtime::Breakpoint, path: &str) -> Self {
+        let mut event = Self::breakpoint(reason, breakpoint);
+        if let Some(body) = event.body.as_mut() {
+            body["breakpoint"]["source"] = serde_json::json!({ "path": path });
+        }
+        event
+    }
+
+    /// Tells the client a module was loaded (`new`), changed or unloaded
+    pub fn module(reason: &str, module: &crate::debug::modules::Module) -> Self {
+        let body = serde_json::json!({
+            "reason": reason,
+            "module": module,
+        });
+        Self::new("module", Some(body))
+    }
+
     pub fn thread(thread_id: u64, reason: &str) -> Self {
         let body = serde_json::json!({
             "threadId": thread_id,
@@ -126,23 +216,154 @@ impl Message {
             params,
         }
     }
+
+    /// The request as it is sent
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "seq": self.id,
+            "type": "request",
+            "command": self.method,
+            "arguments": self.params,
+        })
+    }
+
+    /// Reads a request, None for other messages
+    ///
+    /// The `id`, `method` and `params` names of older clients and recordings are accepted as well.
+    pub fn from_json(value: &serde_json::Value) -> Option<Self> {
+        match value.get("type").and_then(|t| t.as_str()) {
+            Some("request") | None => {}
+            Some(_) => return None,
+        }
+        let method = value.get("command").or_else(|| value.get("method"))?.as_str()?;
+        let id = value.get("seq").or_else(|| value.get("id"))?.as_u64()?;
+        let params = value.get("arguments").or_else(|| value.get("params")).cloned().unwrap_or_default();
+        Some(Self::new(id, method, params))
+    }
 }
 
 impl Response {
-    pub fn new_ok(id: u64, result: serde_json::Value) -> Self {
+    pub fn new_ok(request_seq: u64, command: impl Into<String>, result: serde_json::Value) -> Self {
         Self {
-            id,
+            request_seq,
+            command: command.into(),
             result: Ok(result),
         }
     }
 
-    pub fn new_error(id: u64, code: i32, message: impl Into<String>) -> Self {
+    pub fn new_error(request_seq: u64, command: impl Into<String>, code: i32, message: impl Into<String>) -> Self {
         Self {
-            id,
+            request_seq,
+            command: command.into(),
             result: Err(ResponseError {
                 code,
                 message: message.into(),
+                show_user: None,
             }),
         }
     }
+
+    /// Converts what a request handler returned, `{"id", "result"}` or `{"id", "error"}`
+    pub fn from_handler(command: &str, answer: &serde_json::Value) -> Self {
+        let request_seq = answer.get("id").and_then(|v| v.as_u64()).unwrap_or(0);
+        let Some(error) = answer.get("error") else {
+            let result = answer.get("result").cloned().unwrap_or_default();
+            return Self::new_ok(request_seq, command, result);
+        };
+        let mut response = Self::new_error(
+            request_seq,
+            command,
+            error.get("code").and_then(|v| v.as_i64()).unwrap_or(-1) as i32,
+            error.get("message").and_then(|v| v.as_str()).unwrap_or("Unknown error"),
+        );
+        if let Err(e) = &mut response.result {
+            e.show_user = error.get("showUser").and_then(|v| v.as_bool());
+        }
+        response
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// The response as it is sent, numbered `seq`
+    pub fn to_json(&self, seq: u64) -> serde_json::Value {
+        let mut value = serde_json::json!({
+            "seq": seq,
+            "type": "response",
+            "request_seq": self.request_seq,
+            "success": self.is_success(),
+            "command": self.command,
+        });
+        match &self.result {
+            // An empty result is sent without a body, which DAP allows for every response
+            Ok(result) if result.is_null() => {}
+            Ok(result) => value["body"] = result.clone(),
+            Err(error) => {
+                value["message"] = serde_json::json!(error.message);
+                let mut body = serde_json::json!({ "id": error.code, "format": error.message });
+                if let Some(show_user) = error.show_user {
+                    body["showUser"] = serde_json::json!(show_user);
+                }
+                value["body"] = serde_json::json!({ "error": body });
+            }
+        }
+        value
+    }
+
+    /// Reads a response, None for other messages
+    ///
+    /// Responses in the `{"id", "result"}` shape of recordings made by older
+    /// versions are accepted as well.
+    pub fn from_json(value: &serde_json::Value) -> Option<Self> {
+        if value.get("type").and_then(|t| t.as_str()) != Some("response") {
+            value.get("id")?.as_u64()?;
+            if value.get("result").is_none() && value.get("error").is_none() {
+                return None;
+            }
+            return Some(Self::from_handler("", value));
+        }
+
+        let request_seq = value.get("request_seq")?.as_u64()?;
+        let command = value.get("command").and_then(|c| c.as_str()).unwrap_or_default();
+        if value.get("success").and_then(|s| s.as_bool()).unwrap_or(false) {
+            return Some(Self::new_ok(request_seq, command, value.get("body").cloned().unwrap_or_default()));
+        }
+        let error = &value["body"]["error"];
+        let message = error
+            .get("format")
+            .or_else(|| value.get("message"))
+            .and_then(|m| m.as_str())
+            .unwrap_or("Unknown error");
+        let mut response = Self::new_error(request_seq, command, error["id"].as_i64().unwrap_or(-1) as i32, message);
+        if let Err(e) = &mut response.result {
+            e.show_user = error.get("showUser").and_then(|v| v.as_bool());
+        }
+        Some(response)
+    }
+}
+
+impl Event {
+    /// The event as it is sent, numbered `seq`
+    pub fn to_json(&self, seq: u64) -> serde_json::Value {
+        let mut value = serde_json::json!({
+            "seq": seq,
+            "type": "event",
+            "event": self.event,
+        });
+        if let Some(body) = &self.body {
+            value["body"] = body.clone();
+        }
+        value
+    }
+
+    /// Reads an event, None for other messages
+    pub fn from_json(value: &serde_json::Value) -> Option<Self> {
+        match value.get("type").and_then(|t| t.as_str()) {
+            Some("event") | None => {}
+            Some(_) => return None,
+        }
+        let event = value.get("event")?.as_str()?;
+        Some(Self::new(event, value.get("body").cloned()))
+    }
 }
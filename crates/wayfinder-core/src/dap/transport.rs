@@ -1,151 +1,404 @@
+//! Content-Length framed DAP transport
+//!
+//! This module implements the wire framing used by the Debug Adapter Protocol:
+//! every message is a JSON body preceded by a `Content-Length` header and a
+//! blank line. The transport is generic over any async reader/writer pair so the
+//! same code serves stdio and TCP connections.
+//!
+//! Everything the transport writes is numbered from one counter: requests,
+//! responses and events each take the next `seq`, as DAP wants of every
+//! message one side sends. A response names the request it answers by that
+//! request's own `seq`, in `request_seq`.
+//!
+//! A [`TraceRecorder`] can be attached to see every message that crosses the
+//! transport, requests as they are read and responses and events as they are
+//! written.
+
 use super::{Event, Message, ProtocolMessage, Response};
-use std::io::{self, BufRead, Write};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::{Child, Command};
+use crate::session::trace::{Direction, TraceRecorder};
+use serde_json::Value as JsonValue;
+use std::io;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
 
 const CONTENT_LENGTH_HEADER: &str = "Content-Length: ";
 
-pub struct StdioTransport {
-    stdin: tokio::process::ChildStdin,
-    stdout: BufReader<tokio::process::ChildStdout>,
-    buffer: String,
+/// Largest message body accepted, so a bad header cannot make the reader allocate without bound
+pub const MAX_CONTENT_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Error code of the response to a message whose body is not JSON
+pub const INVALID_JSON: i32 = -32700;
+
+/// Whether a `read_message` error is a well-framed body that is not JSON
+///
+/// The stream is still in step after such an error, so reading can go on.
+pub fn is_invalid_json(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<serde_json::Error>())
+}
+
+/// A DAP transport over an arbitrary reader/writer pair
+pub struct DapTransport<R, W> {
+    reader: R,
+    writer: W,
+    /// Sequence number for the next outgoing message, shared by requests, responses and events
+    next_seq: u64,
+    /// Partially read incoming message
+    pending: PendingMessage,
+    /// Records every message read or written
+    recorder: Option<TraceRecorder>,
 }
 
+/// Read progress for one incoming message
+///
+/// Kept on the transport rather than on the stack so that `read_message` can
+/// be cancelled (e.g. in `tokio::select!`) and resumed without losing bytes.
+#[derive(Default)]
+struct PendingMessage {
+    /// Current header line, possibly incomplete
+    line: Vec<u8>,
+    /// Whether any bytes of this message have been read
+    started: bool,
+    /// Whether a non-empty header line has been seen
+    saw_header: bool,
+    content_length: Option<usize>,
+    /// Body buffer, allocated once the headers are complete
+    body: Option<Vec<u8>>,
+    /// Number of body bytes read so far
+    body_read: usize,
+}
+
+/// Transport reading from stdin and writing to stdout
+pub type StdioTransport = DapTransport<BufReader<tokio::io::Stdin>, tokio::io::Stdout>;
+
+/// Transport over an accepted or connected TCP stream
+pub type TcpTransport = DapTransport<BufReader<OwnedReadHalf>, OwnedWriteHalf>;
+
+/// Transport over a Unix domain socket
+#[cfg(unix)]
+pub type UnixTransport = DapTransport<
+    BufReader<tokio::net::unix::OwnedReadHalf>,
+    tokio::net::unix::OwnedWriteHalf,
+>;
+
 impl StdioTransport {
-    pub fn new(child: Child) -> Self {
-        let stdin = child.stdin.take().unwrap();
-        let stdout = child.stdout.take().unwrap();
-        let stdout = BufReader::new(stdout);
+    /// Creates a transport bound to the process stdin/stdout
+    pub fn stdio() -> Self {
+        DapTransport::new(BufReader::new(tokio::io::stdin()), tokio::io::stdout())
+    }
+}
+
+impl TcpTransport {
+    /// Creates a transport from a TCP stream
+    pub fn tcp(stream: TcpStream) -> Self {
+        let (read_half, write_half) = stream.into_split();
+        DapTransport::new(BufReader::new(read_half), write_half)
+    }
+}
 
+#[cfg(unix)]
+impl UnixTransport {
+    /// Creates a transport from a Unix domain socket stream
+    pub fn unix(stream: tokio::net::UnixStream) -> Self {
+        let (read_half, write_half) = stream.into_split();
+        DapTransport::new(BufReader::new(read_half), write_half)
+    }
+}
+
+impl<R, W> DapTransport<R, W>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(reader: R, writer: W) -> Self {
         Self {
-            stdin,
-            stdout,
-            buffer: String::new(),
+            reader,
+            writer,
+            next_seq: 1,
+            pending: PendingMessage::default(),
+            recorder: None,
         }
     }
 
-    pub async fn read_message(&mut self) -> io::Result<ProtocolMessage> {
-        let mut headers = Vec::new();
-        let mut content_length: Option<usize> = None;
+    /// Records every message read or written from now on
+    pub fn set_recorder(&mut self, recorder: TraceRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Reads one framed message
+    ///
+    /// Returns `Ok(None)` when the peer closed the stream before sending any
+    /// header bytes, which is the normal way for a client to go away.
+    ///
+    /// This method is cancel-safe: if the future is dropped before completing,
+    /// the next call picks up where it left off.
+    pub async fn read_message(&mut self) -> io::Result<Option<JsonValue>> {
+        while self.pending.body.is_none() {
+            let bytes_read = self.reader.read_until(b'\n', &mut self.pending.line).await?;
+            if bytes_read == 0 {
+                if self.pending.started {
+                    self.pending = PendingMessage::default();
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Stream closed in the middle of a message header",
+                    ));
+                }
+                return Ok(None);
+            }
+            self.pending.started = true;
 
-        loop {
-            let mut line = String::new();
-            self.stdout.read_line(&mut line).await?;
-            let line = line.trim_end();
+            if !self.pending.line.ends_with(b"\n") {
+                continue;
+            }
 
+            let line = std::mem::take(&mut self.pending.line);
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end();
             if line.is_empty() {
-                break;
+                match self.pending.content_length {
+                    Some(length) => self.pending.body = Some(vec![0u8; length]),
+                    None if self.pending.saw_header => {
+                        self.pending = PendingMessage::default();
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Missing Content-Length header",
+                        ));
+                    }
+                    // Tolerate stray blank lines between messages
+                    None => {}
+                }
+                continue;
             }
-            headers.push(line.to_string());
+            self.pending.saw_header = true;
 
             if let Some(content_length_str) = line.strip_prefix(CONTENT_LENGTH_HEADER) {
-                content_length = Some(content_length_str.trim().parse().map_err(|_| {
-                    io::Error::new(io::ErrorKind::InvalidData, "Invalid Content-Length")
-                })?);
+                match content_length_str.trim().parse::<usize>() {
+                    Ok(length) if length > MAX_CONTENT_LENGTH => {
+                        self.pending = PendingMessage::default();
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Content-Length {} exceeds the maximum of {} bytes", length, MAX_CONTENT_LENGTH),
+                        ));
+                    }
+                    Ok(length) => self.pending.content_length = Some(length),
+                    Err(_) => {
+                        self.pending = PendingMessage::default();
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid Content-Length"));
+                    }
+                }
             }
         }
 
-        let content_length = content_length.ok_or_else(|| {
-            io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header")
-        })?;
+        if let Some(body) = self.pending.body.as_mut() {
+            while self.pending.body_read < body.len() {
+                let bytes_read = self.reader.read(&mut body[self.pending.body_read..]).await?;
+                if bytes_read == 0 {
+                    self.pending = PendingMessage::default();
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Stream closed in the middle of a message body",
+                    ));
+                }
+                self.pending.body_read += bytes_read;
+            }
+        }
 
-        let mut body = vec![0u8; content_length];
-        self.stdout.read_exact(&mut body).await?;
+        let body = std::mem::take(&mut self.pending).body.unwrap_or_default();
+        let value = serde_json::from_slice::<JsonValue>(&body)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
-        let body_str = String::from_utf8(body).map_err(|_| {
-            io::Error::new(io::ErrorKind::InvalidData, "Invalid UTF-8 in message body")
-        })?;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(Direction::Received, &value);
+        }
+        Ok(Some(value))
+    }
 
-        serde_json::from_str::<serde_json::Value>(&body_str).map_err(|e| {
-            io::Error::new(io::ErrorKind::InvalidData, format!("Invalid JSON: {}", e))
+    /// Writes one framed message
+    pub async fn write_message(&mut self, message: &JsonValue) -> io::Result<()> {
+        let body = serde_json::to_string(message).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Failed to serialize: {}", e))
         })?;
 
-        let value: serde_json::Value = serde_json::from_str(&body_str).map_err(|e| {
-            io::Error::new(io::ErrorKind::InvalidData, format!("Invalid JSON-RPC message: {}", e))
-        })?;
+        let header = format!("{}{}\r\n\r\n", CONTENT_LENGTH_HEADER, body.len());
+        self.writer.write_all(header.as_bytes()).await?;
+        self.writer.write_all(body.as_bytes()).await?;
+        self.writer.flush().await?;
+        self.next_seq += 1;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(Direction::Sent, message);
+        }
+        Ok(())
+    }
 
-        parse_message(value)
+    /// Writes a request to the client, such as `runInTerminal`, returning its sequence number
+    pub async fn write_request(&mut self, command: &str, arguments: &JsonValue) -> io::Result<u64> {
+        let seq = self.next_seq;
+        let request = serde_json::json!({
+            "seq": seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        });
+        self.write_message(&request).await?;
+        Ok(seq)
     }
 
-    pub async fn write_message(&mut self, message: &ProtocolMessage) -> io::Result<()> {
-        let body = serde_json::to_string(&serialize_message(message)).map_err(|e| {
-            io::Error::new(io::ErrorKind::InvalidData, format!("Failed to serialize: {}", e))
-        })?;
+    /// Writes a DAP event, stamping it with the next outgoing sequence number
+    pub async fn write_event(&mut self, event: &Event) -> io::Result<()> {
+        self.write_message(&event.to_json(self.next_seq)).await
+    }
 
-        let header = format!("Content-Length: {}\r\n\r\n", body.len());
-        self.stdin.write_all(header.as_bytes()).await?;
-        self.stdin.write_all(body.as_bytes()).await?;
-        self.stdin.flush().await?;
-        Ok(())
+    /// Writes a DAP response, stamping it with the next outgoing sequence number
+    pub async fn write_response(&mut self, response: &Response) -> io::Result<()> {
+        self.write_message(&response.to_json(self.next_seq)).await
     }
-}
 
-fn parse_message(value: serde_json::Value) -> io::Result<ProtocolMessage> {
-    if let Some(id) = value.get("id").and_then(|v| v.as_u64()) {
-        if value.get("result").is_some() || value.get("error").is_some() {
-            let result = if let Some(error) = value.get("error") {
-                let code = error.get("code").and_then(|v| v.as_i32()).unwrap_or(-1);
-                let message = error
-                    .get("message")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("Unknown error")
-                    .to_string();
-                Err(super::ResponseError { code, message })
-            } else {
-                Ok(value.get("result").cloned().unwrap_or(serde_json::Value::Null))
-            };
-            Ok(ProtocolMessage::Response(Response { id, result }))
-        } else {
-            let method = value
-                .get("method")
-                .and_then(|v| v.as_str())
-                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing method"))?
-                .to_string();
-            let params = value.get("params").cloned().unwrap_or(serde_json::Value::Null);
-            Ok(ProtocolMessage::Request(Message { id, method, params }))
+    /// Reads one framed message and decodes it into a `ProtocolMessage`
+    pub async fn read_protocol_message(&mut self) -> io::Result<Option<ProtocolMessage>> {
+        match self.read_message().await? {
+            Some(value) => parse_message(value).map(Some),
+            None => Ok(None),
         }
-    } else if let Some(event_type) = value.get("event").and_then(|v| v.as_str()) {
-        let body = value.get("body").cloned();
-        Ok(ProtocolMessage::Event(Event {
-            event: event_type.to_string(),
-            body,
-        }))
+    }
+
+    /// Encodes and writes a `ProtocolMessage`
+    ///
+    /// A request keeps its own id as its `seq`, so that the response can be matched to it.
+    pub async fn write_protocol_message(&mut self, message: &ProtocolMessage) -> io::Result<()> {
+        match message {
+            ProtocolMessage::Request(request) => self.write_message(&request.to_json()).await,
+            ProtocolMessage::Response(response) => self.write_response(response).await,
+            ProtocolMessage::Event(event) => self.write_event(event).await,
+        }
+    }
+}
+
+fn parse_message(value: JsonValue) -> io::Result<ProtocolMessage> {
+    if let Some(response) = Response::from_json(&value) {
+        Ok(ProtocolMessage::Response(response))
+    } else if let Some(request) = Message::from_json(&value) {
+        Ok(ProtocolMessage::Request(request))
+    } else if let Some(event) = Event::from_json(&value) {
+        Ok(ProtocolMessage::Event(event))
     } else {
         Err(io::Error::new(
             io::ErrorKind::InvalidData,
-            "Invalid message: must have id or event",
+            "Invalid message: must be a request, response or event",
         ))
     }
 }
 
-fn serialize_message(message: &ProtocolMessage) -> serde_json::Value {
-    match message {
-        ProtocolMessage::Request(msg) => serde_json::json!({
-            "id": msg.id,
-            "method": msg.method,
-            "params": msg.params
-        }),
-        ProtocolMessage::Response(resp) => match &resp.result {
-            Ok(result) => serde_json::json!({
-                "id": resp.id,
-                "result": result
-            }),
-            Err(error) => serde_json::json!({
-                "id": resp.id,
-                "error": {
-                    "code": error.code,
-                    "message": error.message
-                }
-            }),
-        },
-        ProtocolMessage::Event(evt) => {
-            let mut value = serde_json::json!({
-                "event": evt.event
-            });
-            if let Some(body) = &evt.body {
-                value["body"] = body.clone();
-            }
-            value
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    #[tokio::test]
+    async fn test_read_framed_messages() {
+        let input = format!(
+            "{}{}",
+            frame(r#"{"id":1,"method":"initialize"}"#),
+            frame(r#"{"id":2,"method":"launch"}"#)
+        );
+        let mut transport = DapTransport::new(input.as_bytes(), Vec::new());
+
+        let first = transport.read_message().await.unwrap().unwrap();
+        assert_eq!(first["method"], "initialize");
+        let second = transport.read_message().await.unwrap().unwrap();
+        assert_eq!(second["id"], 2);
+        assert!(transport.read_message().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_missing_content_length() {
+        let input = "X-Other: 1\r\n\r\n{}";
+        let mut transport = DapTransport::new(input.as_bytes(), Vec::new());
+        assert!(transport.read_message().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_content_length() {
+        let input = format!("Content-Length: {}\r\n\r\n", MAX_CONTENT_LENGTH + 1);
+        let mut transport = DapTransport::new(input.as_bytes(), Vec::new());
+        let error = transport.read_message().await.unwrap_err();
+        assert!(error.to_string().contains("exceeds the maximum"), "{}", error);
+        assert!(!is_invalid_json(&error));
+    }
+
+    #[tokio::test]
+    async fn test_read_goes_on_after_invalid_json() {
+        let input = format!("{}{}", frame("{not json"), frame(r#"{"id":2,"method":"threads"}"#));
+        let mut transport = DapTransport::new(input.as_bytes(), Vec::new());
+        assert!(is_invalid_json(&transport.read_message().await.unwrap_err()));
+        let value = transport.read_message().await.unwrap().unwrap();
+        assert_eq!(value["id"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_read_resumes_after_cancellation() {
+        let (client, server) = tokio::io::duplex(64);
+        let (_, mut client_write) = tokio::io::split(client);
+        let (server_read, _) = tokio::io::split(server);
+        let mut transport = DapTransport::new(BufReader::new(server_read), Vec::new());
+
+        let message = frame(r#"{"id":1,"method":"threads"}"#);
+        let (head, tail) = message.split_at(10);
+        client_write.write_all(head.as_bytes()).await.unwrap();
+
+        // Give up before the message is complete, then finish sending it
+        let timed_out = tokio::time::timeout(
+            std::time::Duration::from_millis(20),
+            transport.read_message(),
+        )
+        .await;
+        assert!(timed_out.is_err());
+        client_write.write_all(tail.as_bytes()).await.unwrap();
+
+        let value = transport.read_message().await.unwrap().unwrap();
+        assert_eq!(value["method"], "threads");
+    }
+
+    #[tokio::test]
+    async fn test_write_event_is_framed() {
+        let mut transport = DapTransport::new(&b""[..], Vec::new());
+        transport.write_event(&Event::initialized()).await.unwrap();
+
+        let written = String::from_utf8(transport.writer.clone()).unwrap();
+        let (header, body) = written.split_once("\r\n\r\n").unwrap();
+        assert_eq!(header, format!("Content-Length: {}", body.len()));
+        let value: JsonValue = serde_json::from_str(body).unwrap();
+        assert_eq!(value["type"], "event");
+        assert_eq!(value["event"], "initialized");
+    }
+
+    #[tokio::test]
+    async fn test_responses_and_events_share_seq() {
+        let mut transport = DapTransport::new(&b""[..], Vec::new());
+        transport.write_response(&Response::new_ok(7, "threads", serde_json::json!({ "threads": [] }))).await.unwrap();
+        transport.write_event(&Event::initialized()).await.unwrap();
+        transport.write_response(&Response::new_error(8, "scopes", 2002, "Unknown frame 3")).await.unwrap();
+
+        let mut reader = DapTransport::new(&transport.writer[..], Vec::new());
+        let mut messages = Vec::new();
+        while let Some(message) = reader.read_message().await.unwrap() {
+            messages.push(message);
         }
+        let seqs: Vec<&JsonValue> = messages.iter().map(|m| &m["seq"]).collect();
+        assert_eq!(seqs, [1, 2, 3]);
+
+        assert_eq!(messages[0]["type"], "response");
+        assert_eq!(messages[0]["request_seq"], 7);
+        assert_eq!(messages[0]["success"], true);
+        assert_eq!(messages[0]["command"], "threads");
+        assert_eq!(messages[0]["body"]["threads"], serde_json::json!([]));
+
+        assert_eq!(messages[2]["success"], false);
+        assert_eq!(messages[2]["message"], "Unknown frame 3");
+        assert_eq!(messages[2]["body"]["error"]["id"], 2002);
+        let parsed = parse_message(messages[2].clone()).unwrap();
+        assert_eq!(parsed, ProtocolMessage::Response(Response::new_error(8, "scopes", 2002, "Unknown frame 3")));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,132 @@
+//! Breakpoints kept in a file that tools other than the client can edit
+//!
+//! The server watches `.wayfinder-breakpoints.json` and applies it whenever it
+//! changes, so an editor plugin or a script that cannot speak DAP can still set
+//! breakpoints. The file maps sources to breakpoints in the shape of a
+//! `setBreakpoints` request:
+//!
+//! ```json
+//! { "breakpoints": { "src/game.lua": [{ "line": 12 }, { "line": 40, "condition": "hp <= 0" }] } }
+//! ```
+//!
+//! Like a `setBreakpoints` request, each listed source gets exactly the
+//! breakpoints the file gives it. A source dropped from the file, or the file
+//! itself being deleted, clears the breakpoints the file had set there.
+
+use crate::dap::arguments::SourceBreakpoint;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Name of the file looked for in the working directory
+pub const DEFAULT_FILE_NAME: &str = ".wayfinder-breakpoints.json";
+
+/// How often the server checks the file for changes
+pub const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct BreakpointFile {
+    /// DAP `SourceBreakpoint` objects by source path
+    #[serde(default)]
+    pub breakpoints: BTreeMap<String, Vec<SourceBreakpoint>>,
+}
+
+impl BreakpointFile {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        serde_json::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// Breakpoints by source, with relative sources resolved against `base`
+    pub fn resolve(self, base: &Path) -> BTreeMap<String, Vec<SourceBreakpoint>> {
+        self.breakpoints
+            .into_iter()
+            .map(|(source, breakpoints)| (base.join(source).display().to_string(), breakpoints))
+            .collect()
+    }
+}
+
+/// Notices changes to a breakpoint file by comparing its contents
+///
+/// Contents rather than modification times are compared, since editors that
+/// save twice within the timestamp granularity would otherwise be missed.
+#[derive(Debug)]
+pub struct BreakpointFileWatcher {
+    path: PathBuf,
+    /// Contents as of the last poll, `None` while the file does not exist
+    contents: Option<String>,
+    /// Sources the file set breakpoints in when it was last applied
+    sources: BTreeSet<String>,
+}
+
+impl BreakpointFileWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            contents: None,
+            sources: BTreeSet::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Checks the file and returns the breakpoints to apply if it changed
+    ///
+    /// The result holds every source the file lists, and an empty list for
+    /// each source it listed before but no longer does. A file that does not
+    /// parse is reported once and leaves the breakpoints as they were.
+    pub fn poll(&mut self) -> Option<Result<BTreeMap<String, Vec<SourceBreakpoint>>, String>> {
+        let contents = std::fs::read_to_string(&self.path).ok();
+        if contents == self.contents {
+            return None;
+        }
+        self.contents = contents;
+
+        let file = match self.contents.as_deref().map(BreakpointFile::parse) {
+            Some(Ok(file)) => file,
+            Some(Err(e)) => return Some(Err(format!("{}: {}", self.path.display(), e))),
+            None => BreakpointFile::default(),
+        };
+        let base = self.path.parent().unwrap_or_else(|| Path::new("."));
+        let mut breakpoints = file.resolve(base);
+
+        let sources: BTreeSet<String> = breakpoints.keys().cloned().collect();
+        for dropped in self.sources.difference(&sources) {
+            breakpoints.insert(dropped.clone(), Vec::new());
+        }
+        self.sources = sources;
+        Some(Ok(breakpoints))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_reports_changes_and_dropped_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_FILE_NAME);
+        let game = dir.path().join("game.lua").display().to_string();
+        let mut watcher = BreakpointFileWatcher::new(&path);
+        assert!(watcher.poll().is_none());
+
+        std::fs::write(&path, r#"{ "breakpoints": { "game.lua": [{ "line": 3 }, { "line": 7, "condition": "x" }] } }"#)
+            .unwrap();
+        let applied = watcher.poll().unwrap().unwrap();
+        assert_eq!(applied[&game].len(), 2);
+        assert_eq!(applied[&game][1].condition.as_deref(), Some("x"));
+        assert!(watcher.poll().is_none());
+
+        std::fs::write(&path, "{ \"breakpoints\": ").unwrap();
+        assert!(watcher.poll().unwrap().is_err());
+        std::fs::write(&path, r#"{ "breakpoints": { "game.lua": [{ "condition": "x" }] } }"#).unwrap();
+        assert!(watcher.poll().unwrap().is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        let applied = watcher.poll().unwrap().unwrap();
+        assert_eq!(applied.len(), 1);
+        assert!(applied[&game].is_empty());
+    }
+}
@@ -19,6 +19,9 @@ pub struct LineBreakpoint {
     pub source: String,
     /// The line number in the source file
     pub line: u32,
+    /// Column on the line, for sources with several statements on one line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<u32>,
     /// Optional condition that must be true for the breakpoint to trigger
     pub condition: Option<String>,
     /// Optional log message to output instead of pausing execution
@@ -80,6 +83,12 @@ pub struct BreakpointManager {
     function_breakpoints: Vec<FunctionBreakpoint>,
     /// Active exception breakpoint filters
     exception_filters: Vec<String>,
+    /// Conditions of exception filters, by filter
+    exception_conditions: HashMap<String, String>,
+    /// Hit conditions of exception filters, by filter
+    exception_hit_conditions: HashMap<String, String>,
+    /// Exceptions each filter has stopped for, by filter
+    exception_hit_counts: HashMap<String, usize>,
     /// Next ID to assign to a breakpoint
     next_id: i64,
 }
@@ -91,6 +100,9 @@ impl BreakpointManager {
             line_breakpoints: HashMap::new(),
             function_breakpoints: Vec::new(),
             exception_filters: Vec::new(),
+            exception_conditions: HashMap::new(),
+            exception_hit_conditions: HashMap::new(),
+            exception_hit_counts: HashMap::new(),
             next_id: 1,
         }
     }
@@ -114,8 +126,12 @@ impl BreakpointManager {
         }
 
         // Replace all breakpoints for this source
-        self.line_breakpoints
-            .insert(source, breakpoints_with_ids.clone());
+        if breakpoints_with_ids.is_empty() {
+            self.line_breakpoints.remove(&source);
+        } else {
+            self.line_breakpoints
+                .insert(source, breakpoints_with_ids.clone());
+        }
 
         breakpoints_with_ids
     }
@@ -173,6 +189,34 @@ impl BreakpointManager {
         &self.exception_filters
     }
 
+    /// Replaces the conditions of the exception filters
+    pub fn set_exception_conditions(&mut self, conditions: HashMap<String, String>) {
+        self.exception_conditions = conditions;
+    }
+
+    /// Gets the condition of an exception filter
+    pub fn get_exception_condition(&self, filter: &str) -> Option<&String> {
+        self.exception_conditions.get(filter)
+    }
+
+    /// Replaces the hit conditions of the exception filters, starting their counts over
+    pub fn set_exception_hit_conditions(&mut self, hit_conditions: HashMap<String, String>) {
+        self.exception_hit_conditions = hit_conditions;
+        self.exception_hit_counts.clear();
+    }
+
+    /// Gets the hit condition of an exception filter
+    pub fn get_exception_hit_condition(&self, filter: &str) -> Option<&String> {
+        self.exception_hit_conditions.get(filter)
+    }
+
+    /// Counts an exception against a filter, returning the new hit count
+    pub fn increment_exception_hit_count(&mut self, filter: &str) -> usize {
+        let count = self.exception_hit_counts.entry(filter.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
     /// Checks if a line breakpoint exists at the specified source and line
     pub fn has_line_breakpoint(&self, source: &str, line: u32) -> bool {
         if let Some(breakpoints) = self.line_breakpoints.get(source) {
@@ -191,11 +235,86 @@ impl BreakpointManager {
         }
     }
 
+    /// The source a line breakpoint was set in, given the path the runtime reports for a location
+    ///
+    /// Runtimes may report paths relative to where the program was started,
+    /// while clients set breakpoints with absolute ones.
+    pub fn line_breakpoint_source(&self, path: &str, line: u32) -> Option<String> {
+        self.line_breakpoints
+            .iter()
+            .find(|(source, breakpoints)| {
+                crate::runtime::hook_state::source_matches(path, source) && breakpoints.iter().any(|bp| bp.line == line)
+            })
+            .map(|(source, _)| source.clone())
+    }
+
     /// Finds a function breakpoint by name
     pub fn find_function_breakpoint(&self, name: &str) -> Option<&FunctionBreakpoint> {
         self.function_breakpoints.iter().find(|bp| bp.name == name)
     }
 
+    /// Records the ID and verification state the runtime gave a stored line breakpoint
+    pub fn update_line_breakpoint(
+        &mut self,
+        source: &str,
+        line: u32,
+        id: i64,
+        verified: bool,
+        message: Option<String>,
+    ) -> bool {
+        if let Some(bp) = self
+            .line_breakpoints
+            .get_mut(source)
+            .and_then(|breakpoints| breakpoints.iter_mut().find(|bp| bp.line == line))
+        {
+            bp.id = id;
+            bp.verified = verified;
+            bp.message = message;
+            return true;
+        }
+        false
+    }
+
+    /// Records the ID and verification state the runtime gave a stored function breakpoint
+    pub fn update_function_breakpoint(
+        &mut self,
+        name: &str,
+        id: i64,
+        verified: bool,
+        message: Option<String>,
+    ) -> bool {
+        if let Some(bp) = self
+            .function_breakpoints
+            .iter_mut()
+            .find(|bp| bp.name == name)
+        {
+            bp.id = id;
+            bp.verified = verified;
+            bp.message = message;
+            return true;
+        }
+        false
+    }
+
+    /// Updates the verification state of a breakpoint by ID, as reported by a `breakpoint` event
+    pub fn set_verified(&mut self, id: i64, verified: bool, message: Option<String>) -> bool {
+        for breakpoints in self.line_breakpoints.values_mut() {
+            if let Some(bp) = breakpoints.iter_mut().find(|bp| bp.id == id) {
+                bp.verified = verified;
+                bp.message = message;
+                return true;
+            }
+        }
+
+        if let Some(bp) = self.function_breakpoints.iter_mut().find(|bp| bp.id == id) {
+            bp.verified = verified;
+            bp.message = message;
+            return true;
+        }
+
+        false
+    }
+
     /// Increments the hit count for a line breakpoint
     pub fn increment_line_breakpoint_hit_count(&mut self, source: &str, line: u32) -> bool {
         if let Some(breakpoints) = self.line_breakpoints.get_mut(source) {
@@ -240,6 +359,16 @@ impl BreakpointManager {
             .map(|bp| bp.hit_count)
     }
 
+    /// Starts every hit count over, for a program run again from the start
+    pub fn reset_hit_counts(&mut self) {
+        for bp in self.line_breakpoints.values_mut().flatten() {
+            bp.hit_count = 0;
+        }
+        for bp in &mut self.function_breakpoints {
+            bp.hit_count = 0;
+        }
+    }
+
     /// Removes a breakpoint by ID
     pub fn remove_breakpoint(&mut self, id: i64) -> bool {
         // Try to remove from line breakpoints
@@ -264,6 +393,9 @@ impl BreakpointManager {
         self.line_breakpoints.clear();
         self.function_breakpoints.clear();
         self.exception_filters.clear();
+        self.exception_conditions.clear();
+        self.exception_hit_conditions.clear();
+        self.exception_hit_counts.clear();
     }
 
     /// Gets the total count of all breakpoints
@@ -300,6 +432,7 @@ mod tests {
             id: 0,
             source: "test.lua".to_string(),
             line: 10,
+            column: None,
             condition: None,
             log_message: None,
             hit_condition: None,
@@ -320,6 +453,28 @@ mod tests {
         assert!(!manager.has_line_breakpoint("other.lua", 10));
     }
 
+    #[test]
+    fn test_line_breakpoint_source_matches_relative_paths() {
+        let mut manager = BreakpointManager::new();
+        let breakpoint = LineBreakpoint {
+            id: 0,
+            source: "/game/src/player.lua".to_string(),
+            line: 4,
+            column: None,
+            condition: None,
+            log_message: Some("hp={hp}".to_string()),
+            hit_condition: None,
+            verified: true,
+            message: None,
+            hit_count: 0,
+        };
+        manager.set_line_breakpoints("/game/src/player.lua".to_string(), vec![breakpoint]);
+
+        assert_eq!(manager.line_breakpoint_source("src/player.lua", 4).as_deref(), Some("/game/src/player.lua"));
+        assert_eq!(manager.line_breakpoint_source("src/player.lua", 5), None);
+        assert_eq!(manager.line_breakpoint_source("src/enemy.lua", 4), None);
+    }
+
     #[test]
     fn test_function_breakpoints() {
         let mut manager = BreakpointManager::new();
@@ -357,6 +512,26 @@ mod tests {
         assert_eq!(retrieved[1], "uncaught");
     }
 
+    #[test]
+    fn test_exception_hit_counts() {
+        let mut manager = BreakpointManager::new();
+        manager.set_exception_hit_conditions(HashMap::from([("all".to_string(), "% 2".to_string())]));
+        assert_eq!(manager.get_exception_hit_condition("all").map(String::as_str), Some("% 2"));
+        assert_eq!(manager.get_exception_hit_condition("uncaught"), None);
+
+        assert_eq!(manager.increment_exception_hit_count("all"), 1);
+        assert_eq!(manager.increment_exception_hit_count("all"), 2);
+        assert_eq!(manager.increment_exception_hit_count("uncaught"), 1);
+
+        manager.set_exception_hit_conditions(HashMap::new());
+        assert_eq!(manager.increment_exception_hit_count("all"), 1);
+
+        manager.set_exception_conditions(HashMap::from([("all".to_string(), "message ~= nil".to_string())]));
+        assert_eq!(manager.get_exception_condition("all").map(String::as_str), Some("message ~= nil"));
+        manager.clear_all_breakpoints();
+        assert_eq!(manager.get_exception_condition("all"), None);
+    }
+
     #[test]
     fn test_breakpoint_removal() {
         let mut manager = BreakpointManager::new();
@@ -366,6 +541,7 @@ mod tests {
             id: 0,
             source: "test.lua".to_string(),
             line: 10,
+            column: None,
             condition: None,
             log_message: None,
             hit_condition: None,
@@ -407,6 +583,7 @@ mod tests {
             id: 0,
             source: "test.lua".to_string(),
             line: 10,
+            column: None,
             condition: None,
             log_message: None,
             hit_condition: None,
@@ -0,0 +1,266 @@
+//! Lines with code in a compiled Lua 5.4 chunk
+//!
+//! A breakpoint only stops on a line that has instructions. `lua_getinfo`
+//! lists those lines with its `L` option, but only for a function value, and
+//! the functions nested in a chunk have none until the chunk runs. So the
+//! chunk is compiled and written out with `lua_dump` instead, and the line
+//! info of every function in the dump is read here.
+
+use std::collections::BTreeSet;
+
+const SIGNATURE: &[u8] = b"\x1bLua";
+const VERSION: u8 = 0x54;
+const FORMAT: u8 = 0;
+/// Bytes that catch a dump mangled by text conversion
+const DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
+/// Line delta of an instruction whose line is stored as an absolute line
+const ABSOLUTE_LINE: i8 = -0x80;
+
+// Constant types, with their variant bits
+const NIL: u8 = 0x00;
+const FALSE: u8 = 0x01;
+const TRUE: u8 = 0x11;
+const INTEGER: u8 = 0x03;
+const FLOAT: u8 = 0x13;
+const SHORT_STRING: u8 = 0x04;
+const LONG_STRING: u8 = 0x14;
+
+/// The lines of a dumped chunk that have code, in any of its functions
+pub fn code_lines(dump: &[u8]) -> Result<BTreeSet<u32>, String> {
+    let mut reader = Reader {
+        bytes: dump,
+        position: 0,
+        instruction_size: 4,
+        integer_size: 8,
+        number_size: 8,
+    };
+    reader.header()?;
+    reader.byte()?; // upvalue count of the main function
+    let mut lines = BTreeSet::new();
+    reader.function(&mut lines)?;
+    Ok(lines)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    instruction_size: usize,
+    integer_size: usize,
+    number_size: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or("Truncated chunk")?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// A size or count: 7 bits a byte, most significant first, the last byte flagged with 0x80
+    fn size(&mut self) -> Result<usize, String> {
+        let mut size: usize = 0;
+        loop {
+            let byte = self.byte()?;
+            size = size.checked_mul(0x80).ok_or("Size out of range")? | (byte & 0x7f) as usize;
+            if byte & 0x80 != 0 {
+                return Ok(size);
+            }
+        }
+    }
+
+    /// Skips `count` items of `item_size` bytes
+    fn skip(&mut self, count: usize, item_size: usize) -> Result<(), String> {
+        self.take(count.checked_mul(item_size).ok_or("Size out of range")?)?;
+        Ok(())
+    }
+
+    fn skip_string(&mut self) -> Result<(), String> {
+        // The length is stored plus one, so 0 is a missing string
+        let size = self.size()?;
+        self.skip(size.saturating_sub(1), 1)
+    }
+
+    fn header(&mut self) -> Result<(), String> {
+        if self.take(SIGNATURE.len())? != SIGNATURE {
+            return Err("Not a compiled Lua chunk".to_string());
+        }
+        let version = self.byte()?;
+        if version != VERSION || self.byte()? != FORMAT {
+            return Err(format!("Unsupported chunk version {:#x}", version));
+        }
+        if self.take(DATA.len())? != DATA {
+            return Err("Corrupted chunk header".to_string());
+        }
+        self.instruction_size = self.byte()? as usize;
+        self.integer_size = self.byte()? as usize;
+        self.number_size = self.byte()? as usize;
+        // An integer and a number that check the dumping machine's formats
+        self.skip(1, self.integer_size + self.number_size)
+    }
+
+    /// Reads a function and the functions nested in it, adding the lines they have code on
+    fn function(&mut self, lines: &mut BTreeSet<u32>) -> Result<(), String> {
+        self.skip_string()?; // source
+        let line_defined = self.size()?;
+        self.size()?; // last line defined
+        self.byte()?; // parameter count
+        let is_vararg = self.byte()? != 0;
+        self.byte()?; // stack size
+
+        let code_size = self.size()?;
+        self.skip(code_size, self.instruction_size)?;
+
+        for _ in 0..self.size()? {
+            match self.byte()? {
+                NIL | FALSE | TRUE => {}
+                INTEGER => self.skip(1, self.integer_size)?,
+                FLOAT => self.skip(1, self.number_size)?,
+                SHORT_STRING | LONG_STRING => self.skip_string()?,
+                tag => return Err(format!("Unknown constant type {:#x}", tag)),
+            }
+        }
+        let upvalues = self.size()?;
+        self.skip(upvalues, 3)?; // in stack, index and kind
+        for _ in 0..self.size()? {
+            self.function(lines)?;
+        }
+
+        // A line delta per instruction, where a delta that does not fit in a
+        // byte is replaced by an absolute line
+        let line_count = self.size()?;
+        let deltas = self.take(line_count)?;
+        let mut absolute_lines = Vec::new();
+        for _ in 0..self.size()? {
+            self.size()?; // instruction
+            absolute_lines.push(self.size()?);
+        }
+        for _ in 0..self.size()? {
+            self.skip_string()?; // local name
+            self.size()?; // start
+            self.size()?; // end
+        }
+        for _ in 0..self.size()? {
+            self.skip_string()?; // upvalue name
+        }
+
+        let mut absolute_lines = absolute_lines.into_iter();
+        let mut line = line_defined as i64;
+        for (instruction, &delta) in deltas.iter().enumerate() {
+            if delta as i8 == ABSOLUTE_LINE {
+                line = absolute_lines.next().ok_or("Missing absolute line")? as i64;
+            } else {
+                line += delta as i8 as i64;
+            }
+            // The first instruction of a vararg function only collects its
+            // arguments, on the line the function is defined
+            if instruction == 0 && is_vararg {
+                continue;
+            }
+            if let Ok(line) = u32::try_from(line) {
+                lines.insert(line);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(mut value: usize) -> Vec<u8> {
+        let mut bytes = vec![(value & 0x7f) as u8 | 0x80];
+        value >>= 7;
+        while value != 0 {
+            bytes.insert(0, (value & 0x7f) as u8);
+            value >>= 7;
+        }
+        bytes
+    }
+
+    fn string(text: &str) -> Vec<u8> {
+        let mut bytes = size(text.len() + 1);
+        bytes.extend_from_slice(text.as_bytes());
+        bytes
+    }
+
+    /// A vararg main chunk on lines 1, 200 and 202, with a function on lines 6 and 7
+    fn dump() -> Vec<u8> {
+        let mut chunk = Vec::new();
+        chunk.extend_from_slice(SIGNATURE);
+        chunk.extend_from_slice(&[VERSION, FORMAT]);
+        chunk.extend_from_slice(DATA);
+        chunk.extend_from_slice(&[4, 8, 8]);
+        chunk.extend_from_slice(&[0; 16]);
+        chunk.push(1);
+
+        // Main chunk
+        chunk.extend(string("@main.lua"));
+        chunk.extend(size(0));
+        chunk.extend(size(0));
+        chunk.extend_from_slice(&[0, 1, 2]);
+        chunk.extend(size(3));
+        chunk.extend_from_slice(&[0; 12]);
+        chunk.extend(size(2));
+        chunk.push(SHORT_STRING);
+        chunk.extend(string("abc"));
+        chunk.push(FLOAT);
+        chunk.extend_from_slice(&[0; 8]);
+        chunk.extend(size(1));
+        chunk.extend_from_slice(&[1, 0, 0]);
+        chunk.extend(size(1));
+
+        // Nested function, on lines 5 to 7
+        chunk.extend(size(0));
+        chunk.extend(size(5));
+        chunk.extend(size(7));
+        chunk.extend_from_slice(&[1, 0, 3]);
+        chunk.extend(size(2));
+        chunk.extend_from_slice(&[0; 8]);
+        chunk.extend(size(0));
+        chunk.extend(size(0));
+        chunk.extend(size(0));
+        chunk.extend(size(2));
+        chunk.extend_from_slice(&[1, 1]);
+        chunk.extend(size(0));
+        chunk.extend(size(1));
+        chunk.extend(string("x"));
+        chunk.extend(size(0));
+        chunk.extend(size(2));
+        chunk.extend(size(0));
+
+        // Main chunk's debug info, with line 200 too far for a delta
+        chunk.extend(size(3));
+        chunk.extend_from_slice(&[1, ABSOLUTE_LINE as u8, 2]);
+        chunk.extend(size(1));
+        chunk.extend(size(1));
+        chunk.extend(size(200));
+        chunk.extend(size(0));
+        chunk.extend(size(1));
+        chunk.extend(string("_ENV"));
+        chunk
+    }
+
+    #[test]
+    fn test_code_lines() {
+        let lines: Vec<u32> = code_lines(&dump()).unwrap().into_iter().collect();
+        // The vararg setup on line 1 does not count
+        assert_eq!(lines, [6, 7, 200, 202]);
+    }
+
+    #[test]
+    fn test_invalid_dumps() {
+        let chunk = dump();
+        assert_eq!(code_lines(&chunk[..chunk.len() - 3]), Err("Truncated chunk".to_string()));
+        assert_eq!(code_lines(b"print('hi')"), Err("Not a compiled Lua chunk".to_string()));
+    }
+}
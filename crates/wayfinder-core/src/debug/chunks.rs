@@ -0,0 +1,178 @@
+//! Chunks the VM has loaded, for verifying line breakpoints
+//!
+//! A line breakpoint is verified once a chunk loaded from its file has been
+//! seen and the line exists in that file. Runtimes register chunks when they
+//! load a program and when the hook first fires in a new source, so
+//! breakpoints set in modules that are `require`d later start out unverified
+//! and are verified when the module loads.
+
+use crate::runtime::hook_state::source_matches;
+use std::collections::HashMap;
+
+/// Outcome of checking a breakpoint location against the loaded chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    Verified,
+    /// No chunk from the file has been loaded yet
+    NotLoaded,
+    /// The file is loaded but ends before the line
+    PastEnd { line_count: u32 },
+}
+
+impl Verification {
+    pub fn is_verified(&self) -> bool {
+        *self == Verification::Verified
+    }
+
+    /// Explanation shown next to an unverified breakpoint
+    pub fn message(&self, line: u32) -> Option<String> {
+        match self {
+            Verification::Verified => None,
+            Verification::NotLoaded => Some("The source has not been loaded yet".to_string()),
+            Verification::PastEnd { line_count } => {
+                Some(format!("Line {} is past the end of the source ({} lines)", line, line_count))
+            }
+        }
+    }
+}
+
+/// Loaded file chunks, by normalized path
+#[derive(Debug, Default)]
+pub struct ChunkRegistry {
+    /// Line count of each chunk, when its file could be read
+    chunks: HashMap<String, Option<u32>>,
+}
+
+impl ChunkRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, source: &str) -> bool {
+        self.chunks.contains_key(&normalize(source))
+    }
+
+    /// Records a chunk source such as `@scripts/main.lua`
+    ///
+    /// Returns false if the chunk was already known.
+    pub fn register(&mut self, source: &str, line_count: Option<u32>) -> bool {
+        self.chunks.insert(normalize(source), line_count).is_none()
+    }
+
+    /// Records a chunk the VM loaded from a file, reading the file for its length
+    pub fn register_file(&mut self, source: &str) -> bool {
+        if self.contains(source) {
+            return false;
+        }
+        let path = source.strip_prefix('@').unwrap_or(source);
+        let line_count = std::fs::read(path).ok().map(|bytes| count_lines(&bytes));
+        self.register(source, line_count)
+    }
+
+    pub fn verify(&self, path: &str, line: u32) -> Verification {
+        let path = normalize(path);
+        let Some((_, line_count)) = self.chunks.iter().find(|(source, _)| source_matches(source, &path)) else {
+            return Verification::NotLoaded;
+        };
+
+        match line_count {
+            Some(line_count) if line == 0 || line > *line_count => Verification::PastEnd { line_count: *line_count },
+            _ => Verification::Verified,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+}
+
+/// Code of chunks that have no file, handed to the client as `sourceReference`s
+///
+/// A reference is the chunk's index plus one and stays valid for the whole
+/// session, so the editor can reopen the code after the program resumes.
+#[derive(Debug, Default)]
+pub struct SourceReferences {
+    /// Display name and code of each chunk
+    chunks: Vec<(String, String)>,
+}
+
+impl SourceReferences {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the reference of a chunk's code, recording it the first time it is seen
+    pub fn reference(&mut self, name: &str, code: &str) -> i64 {
+        let index = match self.chunks.iter().position(|(n, c)| n == name && c == code) {
+            Some(index) => index,
+            None => {
+                self.chunks.push((name.to_string(), code.to_string()));
+                self.chunks.len() - 1
+            }
+        };
+        index as i64 + 1
+    }
+
+    pub fn code(&self, reference: i64) -> Option<&str> {
+        let index = usize::try_from(reference).ok()?.checked_sub(1)?;
+        self.chunks.get(index).map(|(_, code)| code.as_str())
+    }
+}
+
+/// Normalizes a chunk source or client path so the two can be compared
+///
+/// Drops Lua's `@` prefix and a leading `./`, and uses forward slashes.
+pub fn normalize(path: &str) -> String {
+    let path = path.strip_prefix('@').unwrap_or(path).replace('\\', "/");
+    match path.strip_prefix("./") {
+        Some(relative) => relative.to_string(),
+        None => path,
+    }
+}
+
+/// Number of lines in a source file, counting a last line without a newline
+pub fn count_lines(bytes: &[u8]) -> u32 {
+    let newlines = bytes.iter().filter(|&&b| b == b'\n').count();
+    let unterminated = !bytes.is_empty() && !bytes.ends_with(b"\n");
+    (newlines + unterminated as usize) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_against_loaded_chunks() {
+        let mut chunks = ChunkRegistry::new();
+        assert_eq!(chunks.verify("/game/main.lua", 3), Verification::NotLoaded);
+
+        assert!(chunks.register("@./main.lua", Some(10)));
+        assert!(!chunks.register("@main.lua", Some(10)));
+        assert!(chunks.verify("/game/main.lua", 3).is_verified());
+        assert_eq!(chunks.verify("/game/main.lua", 11), Verification::PastEnd { line_count: 10 });
+
+        // Unreadable files accept any line
+        chunks.register("@lib\\util.lua", None);
+        assert!(chunks.verify("C:/game/lib/util.lua", 500).is_verified());
+    }
+
+    #[test]
+    fn test_source_references() {
+        let mut sources = SourceReferences::new();
+        let generated = sources.reference("generated", "return 1");
+        assert_eq!(sources.reference("generated", "return 1"), generated);
+        let reloaded = sources.reference("generated", "return 2");
+        assert_ne!(reloaded, generated);
+
+        assert_eq!(sources.code(generated), Some("return 1"));
+        assert_eq!(sources.code(reloaded), Some("return 2"));
+        assert_eq!(sources.code(0), None);
+    }
+
+    #[test]
+    fn test_count_lines() {
+        assert_eq!(count_lines(b""), 0);
+        assert_eq!(count_lines(b"a = 1\n"), 1);
+        assert_eq!(count_lines(b"a = 1\nb = 2"), 2);
+    }
+}
@@ -0,0 +1,205 @@
+//! Completions for the debug console
+//!
+//! The identifier being typed is completed from what is in scope: with
+//! nothing before it, from the frame's locals, upvalues and the globals; after
+//! `a.b.` or `a.b:`, from the fields of the table the path leads to. The
+//! session gathers the candidates through the variables machinery and this
+//! module decides what to look up and which candidates match.
+
+use crate::runtime::Variable;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Lua 5.4's reserved words, offered where a new expression can start
+pub const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in", "local", "nil",
+    "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// What the text before the cursor asks to complete
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionContext {
+    /// Path to the table whose fields complete the prefix, such as `player.inventory`
+    pub base: Option<String>,
+    /// Whether the prefix follows a `:`, which only methods can
+    pub method_call: bool,
+    /// Start of the identifier being typed
+    pub prefix: String,
+    /// Offset in characters of the prefix within the text
+    pub start: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompletionItem {
+    pub label: String,
+    /// DAP `CompletionItemType`: variable, field, function or keyword
+    #[serde(rename = "type")]
+    pub item_type: String,
+}
+
+/// Works out what to complete in `text` with the cursor `cursor` characters in
+///
+/// Returns None where no identifier can be typed, such as inside a string or
+/// after a number, or after an expression that is not a plain dotted path.
+pub fn context_at(text: &str, cursor: usize) -> Option<CompletionContext> {
+    let before: Vec<char> = text.chars().take(cursor).collect();
+    if in_string(&before) {
+        return None;
+    }
+
+    let mut start = before.len();
+    while start > 0 && is_identifier_char(before[start - 1]) {
+        start -= 1;
+    }
+    let prefix: String = before[start..].iter().collect();
+    if prefix.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let separator = start.checked_sub(1).map(|i| before[i]);
+    let (base, method_call) = match separator {
+        Some(separator @ ('.' | ':')) => {
+            let mut base_start = start - 1;
+            while base_start > 0 && (is_identifier_char(before[base_start - 1]) || before[base_start - 1] == '.') {
+                base_start -= 1;
+            }
+            let base: String = before[base_start..start - 1].iter().collect();
+            if base.is_empty() || !base.split('.').all(is_identifier) {
+                return None;
+            }
+            (Some(base), separator == ':')
+        }
+        _ => (None, false),
+    };
+
+    Some(CompletionContext {
+        base,
+        method_call,
+        prefix,
+        start,
+    })
+}
+
+/// Candidates matching the context, each name once with the first group that has it winning
+///
+/// Groups are given innermost scope first, each with the item type its
+/// non-function variables get. Keywords are added where a new expression can start.
+pub fn complete(context: &CompletionContext, groups: &[(Vec<Variable>, &str)]) -> Vec<CompletionItem> {
+    let mut seen = HashSet::new();
+    let mut items = Vec::new();
+    for (variables, item_type) in groups {
+        let mut group: Vec<CompletionItem> = variables
+            .iter()
+            .filter(|variable| is_identifier(&variable.name) && variable.name.starts_with(&context.prefix))
+            .filter(|variable| !context.method_call || variable.type_ == "function")
+            .filter(|variable| seen.insert(variable.name.clone()))
+            .map(|variable| CompletionItem {
+                label: variable.name.clone(),
+                item_type: if variable.type_ == "function" { "function" } else { item_type }.to_string(),
+            })
+            .collect();
+        group.sort_by(|a, b| a.label.cmp(&b.label));
+        items.extend(group);
+    }
+
+    if context.base.is_none() {
+        items.extend(
+            KEYWORDS
+                .iter()
+                .filter(|keyword| keyword.starts_with(&context.prefix) && seen.insert(keyword.to_string()))
+                .map(|keyword| CompletionItem {
+                    label: keyword.to_string(),
+                    item_type: "keyword".to_string(),
+                }),
+        );
+    }
+    items
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Whether a name can be written bare in Lua source
+pub fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(is_identifier_char)
+        && !KEYWORDS.contains(&name)
+}
+
+/// Whether the text ends inside a quoted string
+fn in_string(text: &[char]) -> bool {
+    let mut quote = None;
+    let mut escaped = false;
+    for &c in text {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None => {}
+        }
+    }
+    quote.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable(name: &str, type_: &str) -> Variable {
+        Variable {
+            name: name.to_string(),
+            value: String::new(),
+            type_: type_.to_string(),
+            variables_reference: None,
+            named_variables: None,
+            indexed_variables: None,
+            memory_reference: None,
+        }
+    }
+
+    #[test]
+    fn test_context_at() {
+        let context = context_at("print(player.inv", 16).unwrap();
+        assert_eq!(context.base.as_deref(), Some("player"));
+        assert_eq!((context.prefix.as_str(), context.start), ("inv", 13));
+
+        let context = context_at("world.player:", 13).unwrap();
+        assert_eq!(context.base.as_deref(), Some("world.player"));
+        assert!(context.method_call);
+        assert_eq!(context.prefix, "");
+
+        assert_eq!(context_at("x + pl", 6).unwrap().base, None);
+        assert!(context_at("'player.", 8).is_none());
+        assert!(context_at("1.5", 3).is_none());
+        assert!(context_at("f().x", 5).is_none());
+    }
+
+    #[test]
+    fn test_complete_prefers_inner_scopes_and_filters_methods() {
+        let context = context_at("pl", 2).unwrap();
+        let locals = vec![variable("player", "table"), variable("(temporary)", "number")];
+        let globals = vec![variable("player", "number"), variable("pairs", "function"), variable("print", "function")];
+        let items = complete(&context, &[(locals, "variable"), (globals, "variable")]);
+        let labels: Vec<_> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, vec!["player"]);
+        assert_eq!(items[0].item_type, "variable");
+
+        let context = context_at("p", 1).unwrap();
+        let globals = vec![variable("print", "function"), variable("pairs", "function")];
+        let items = complete(&context, &[(globals, "variable")]);
+        assert_eq!(items[0].label, "pairs");
+        assert_eq!(items[0].item_type, "function");
+
+        let context = context_at("player:", 7).unwrap();
+        let fields = vec![variable("hp", "number"), variable("heal", "function")];
+        let items = complete(&context, &[(fields, "field")]);
+        assert_eq!(items, vec![CompletionItem { label: "heal".to_string(), item_type: "function".to_string() }]);
+
+        let items = complete(&context_at("re", 2).unwrap(), &[]);
+        assert_eq!(items.iter().map(|item| item.label.as_str()).collect::<Vec<_>>(), vec!["repeat", "return"]);
+    }
+}
@@ -53,18 +53,53 @@ impl ConditionEvaluator {
             Ok(result) => Ok(result),
             Err(e) => {
                 // If condition evaluation fails, we still break but log the error
-                eprintln!("Warning: Condition evaluation failed: {}", e);
+                tracing::warn!("condition evaluation failed: {}", e);
                 Ok(true)
             }
         }
     }
 }
 
+/// The expression that evaluates an exception filter's `condition` for an error
+///
+/// The condition runs in a function taking the error message as `message`,
+/// so it can test the error while still seeing the frame's variables.
+pub fn exception_condition(condition: &str, message: &str) -> String {
+    format!("(function(message) return ({}) end)({})", condition, lua_string_literal(message))
+}
+
+/// A quoted Lua string that reads back as `text`, in a form every Lua version accepts
+fn lua_string_literal(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            c if c.is_control() && (c as u32) < 256 => literal.push_str(&format!("\\{:03}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::runtime::{mock::MockRuntime, Value};
 
+    #[test]
+    fn test_exception_condition_sees_the_message() {
+        assert_eq!(
+            exception_condition("message:find('nil')", "main.lua:3: attempt to index a nil value\n\"x\""),
+            r#"(function(message) return (message:find('nil')) end)("main.lua:3: attempt to index a nil value\n\"x\"")"#
+        );
+        assert_eq!(lua_string_literal("a\\b\0"), r#""a\\b\000""#);
+    }
+
     #[tokio::test]
     async fn test_should_break_without_condition() {
         let mut runtime = MockRuntime::new();
@@ -0,0 +1,84 @@
+//! Text encoding of debuggee sources and strings
+//!
+//! Lua strings are byte strings and scripts are often saved in a legacy code
+//! page such as Latin-1 or Shift-JIS. DAP carries JSON, so everything sent to
+//! the client is decoded to UTF-8 here first.
+//!
+//! Encodings are named with WHATWG labels (`utf-8`, `latin1`, `shift_jis`,
+//! ...). Without a configured encoding the bytes are auto-detected: a byte
+//! order mark wins, valid UTF-8 is taken as is, and anything else is decoded
+//! as Windows-1252, which accepts every byte.
+
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+
+/// Label that requests auto-detection explicitly
+pub const AUTO_LABEL: &str = "auto";
+
+/// Resolves an encoding label
+///
+/// Returns `Ok(None)` for auto-detection and an error for unknown labels.
+pub fn lookup(label: &str) -> Result<Option<&'static Encoding>, String> {
+    let label = label.trim();
+    if label.is_empty() || label.eq_ignore_ascii_case(AUTO_LABEL) {
+        return Ok(None);
+    }
+
+    Encoding::for_label(label.as_bytes())
+        .map(Some)
+        .ok_or_else(|| format!("Unknown encoding: {}", label))
+}
+
+/// Decodes bytes with the given encoding, or auto-detects it
+///
+/// Malformed sequences are replaced rather than rejected so a client always
+/// gets something to display.
+pub fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> String {
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => detect(bytes),
+    };
+
+    let (text, _, _) = encoding.decode(bytes);
+    text.into_owned()
+}
+
+fn detect(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+
+    if std::str::from_utf8(bytes).is_ok() {
+        UTF_8
+    } else {
+        WINDOWS_1252
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_labels() {
+        assert_eq!(lookup("auto").unwrap(), None);
+        assert_eq!(lookup("").unwrap(), None);
+        assert_eq!(lookup("Shift_JIS").unwrap().unwrap().name(), "Shift_JIS");
+        assert_eq!(lookup("latin1").unwrap().unwrap(), WINDOWS_1252);
+        assert!(lookup("klingon").is_err());
+    }
+
+    #[test]
+    fn test_auto_detection_falls_back_to_latin1() {
+        assert_eq!(decode("caf\u{e9}".as_bytes(), None), "caf\u{e9}");
+        assert_eq!(decode(b"caf\xe9", None), "caf\u{e9}");
+        assert_eq!(decode(b"\xef\xbb\xbf-- bom", None), "-- bom");
+    }
+
+    #[test]
+    fn test_configured_encoding() {
+        // "日本" in Shift-JIS
+        let bytes = b"-- \x93\xfa\x96\x7b";
+        let shift_jis = lookup("shift_jis").unwrap();
+        assert_eq!(decode(bytes, shift_jis), "-- \u{65e5}\u{672c}");
+    }
+}
@@ -0,0 +1,186 @@
+//! Trail of the lines the program executed last
+//!
+//! A stack trace only shows the calls still active, so it cannot tell how the
+//! program got to an error through functions that already returned. The hook
+//! appends every line it runs to a small ring buffer, which the client reads
+//! with the `wayfinder/trace` request and which is printed when the program
+//! fails with an error.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Number of lines kept before the oldest is dropped
+pub const DEFAULT_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceEntry {
+    /// Chunk source as Lua reports it, `@path` for files
+    pub source: Option<String>,
+    pub line: u32,
+    /// Name of the running function, as the stack trace shows it
+    pub function: String,
+    /// Line the running function is defined on, 0 for a main chunk
+    #[serde(skip)]
+    pub line_defined: i32,
+}
+
+impl TraceEntry {
+    /// `path:line in function`, with the `@` of file chunks dropped
+    pub fn location(&self) -> String {
+        let source = self.source.as_deref().map_or("?", |source| source.strip_prefix('@').unwrap_or(source));
+        format!("{}:{} in {}", source, self.line, self.function)
+    }
+}
+
+/// Ring buffer of the most recently executed lines
+#[derive(Debug)]
+pub struct ExecutionTrace {
+    capacity: usize,
+    entries: VecDeque<TraceEntry>,
+}
+
+impl ExecutionTrace {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes how many lines are kept, dropping the oldest ones over the new capacity
+    pub fn set_capacity(&mut self, capacity: usize) {
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+        self.capacity = capacity;
+    }
+
+    /// Appends a line, dropping the oldest one when full
+    ///
+    /// `function` names the running function. It is only asked for when the
+    /// line is in another function than the previous one, since looking the
+    /// name up costs more than the rest of the entry.
+    pub fn record(&mut self, source: Option<&str>, line: u32, line_defined: i32, function: impl FnOnce() -> String) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let function = match self.entries.back() {
+            Some(last) if last.line_defined == line_defined && last.source.as_deref() == source => last.function.clone(),
+            _ => function(),
+        };
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TraceEntry {
+            source: source.map(str::to_string),
+            line,
+            function,
+            line_defined,
+        });
+    }
+
+    /// Returns the kept lines, oldest first
+    pub fn entries(&self) -> Vec<TraceEntry> {
+        self.entries.iter().cloned().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl Default for ExecutionTrace {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// Explains how the program got to where it is, for the console
+///
+/// Lists the lines oldest first, one per line; a line run several times in a
+/// row, as by a tight loop, is listed once with its count. None without lines.
+pub fn describe(entries: &[TraceEntry]) -> Option<String> {
+    if entries.is_empty() {
+        return None;
+    }
+
+    let mut described = "How the program got here, oldest line first:\n".to_string();
+    let mut entries = entries.iter().peekable();
+    while let Some(entry) = entries.next() {
+        let mut repeats = 1;
+        while entries.peek().is_some_and(|next| next.source == entry.source && next.line == entry.line) {
+            entries.next();
+            repeats += 1;
+        }
+        described.push_str("  ");
+        described.push_str(&entry.location());
+        if repeats > 1 {
+            described.push_str(&format!(" (x{})", repeats));
+        }
+        described.push('\n');
+    }
+    Some(described)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_lines_are_dropped() {
+        let mut trace = ExecutionTrace::new(2);
+        for line in 1..=3 {
+            trace.record(Some("@main.lua"), line, 0, || "main chunk".to_string());
+        }
+
+        let entries = trace.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].line, 2);
+        assert_eq!(entries[1].location(), "main.lua:3 in main chunk");
+
+        trace.set_capacity(1);
+        assert_eq!(trace.entries()[0].line, 3);
+        trace.set_capacity(0);
+        trace.record(Some("@main.lua"), 4, 0, || "main chunk".to_string());
+        assert!(trace.entries().is_empty());
+    }
+
+    #[test]
+    fn test_function_name_looked_up_once_per_function() {
+        let mut trace = ExecutionTrace::default();
+        let mut lookups = 0;
+        for (line, line_defined) in [(1, 0), (5, 4), (6, 4), (2, 0)] {
+            trace.record(Some("@main.lua"), line, line_defined, || {
+                lookups += 1;
+                if line_defined == 0 { "main chunk".to_string() } else { "update".to_string() }
+            });
+        }
+
+        assert_eq!(lookups, 3);
+        let functions: Vec<String> = trace.entries().into_iter().map(|e| e.function).collect();
+        assert_eq!(functions, vec!["main chunk", "update", "update", "main chunk"]);
+    }
+
+    #[test]
+    fn test_describe_collapses_repeated_lines() {
+        let mut trace = ExecutionTrace::default();
+        for line in [3, 4, 4, 4, 5] {
+            trace.record(Some("@loop.lua"), line, 0, || "main chunk".to_string());
+        }
+
+        assert_eq!(
+            describe(&trace.entries()).unwrap(),
+            "How the program got here, oldest line first:\n  loop.lua:3 in main chunk\n  loop.lua:4 in main chunk (x3)\n  loop.lua:5 in main chunk\n"
+        );
+        let value = serde_json::to_value(&trace.entries()[0]).unwrap();
+        assert_eq!(value["source"], "@loop.lua");
+        assert!(value.get("lineDefined").is_none());
+        assert!(describe(&[]).is_none());
+    }
+}
@@ -0,0 +1,162 @@
+//! Flight recorder of recent stops and uncaught errors
+//!
+//! Glitches that are hard to reproduce are usually noticed only after the
+//! program has moved on. The runtime snapshots every stop and every uncaught
+//! error into a small ring buffer, whether or not the client breaks on them, so
+//! the client can look back with the `flightRecorder` request.
+//!
+//! A snapshot holds frame summaries and the locals of the innermost Lua frame.
+//! Values are rendered to strings when captured since the state they point into
+//! is gone by the time anyone asks.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of snapshots kept before the oldest is dropped
+pub const DEFAULT_CAPACITY: usize = 16;
+
+/// What caused a snapshot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum RecordKind {
+    /// The program stopped on a breakpoint, step, pause or entry
+    Stop { reason: String },
+    /// An error propagated out of the program
+    Error { message: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameSummary {
+    pub name: String,
+    pub source: Option<String>,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocalSnapshot {
+    pub name: String,
+    pub value: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlightRecord {
+    /// Increases by one per snapshot, so gaps show how many were dropped
+    pub sequence: u64,
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub thread_id: u64,
+    #[serde(flatten)]
+    pub kind: RecordKind,
+    /// Innermost frame first
+    pub frames: Vec<FrameSummary>,
+    /// Locals of the innermost Lua frame
+    pub locals: Vec<LocalSnapshot>,
+}
+
+/// Ring buffer of the most recent snapshots
+#[derive(Debug)]
+pub struct FlightRecorder {
+    capacity: usize,
+    next_sequence: u64,
+    records: VecDeque<FlightRecord>,
+}
+
+impl FlightRecorder {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            next_sequence: 1,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Stores a snapshot, dropping the oldest one when full
+    pub fn record(&mut self, thread_id: u64, kind: RecordKind, frames: Vec<FrameSummary>, locals: Vec<LocalSnapshot>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        self.records.push_back(FlightRecord {
+            sequence: self.next_sequence,
+            timestamp_ms,
+            thread_id,
+            kind,
+            frames,
+            locals,
+        });
+        self.next_sequence += 1;
+    }
+
+    /// Returns the kept snapshots, oldest first
+    pub fn records(&self) -> Vec<FlightRecord> {
+        self.records.iter().cloned().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}
+
+impl Default for FlightRecorder {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(recorder: &mut FlightRecorder, reason: &str) {
+        recorder.record(1, RecordKind::Stop { reason: reason.to_string() }, Vec::new(), Vec::new());
+    }
+
+    #[test]
+    fn test_oldest_records_are_dropped() {
+        let mut recorder = FlightRecorder::new(2);
+        stop(&mut recorder, "entry");
+        stop(&mut recorder, "breakpoint");
+        stop(&mut recorder, "step");
+
+        let records = recorder.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].sequence, 2);
+        assert_eq!(records[1].kind, RecordKind::Stop { reason: "step".to_string() });
+    }
+
+    #[test]
+    fn test_zero_capacity_disables_recording() {
+        let mut recorder = FlightRecorder::new(0);
+        stop(&mut recorder, "entry");
+        assert!(recorder.records().is_empty());
+    }
+
+    #[test]
+    fn test_record_serializes_kind_inline() {
+        let mut recorder = FlightRecorder::default();
+        recorder.record(
+            1,
+            RecordKind::Error { message: "boom".to_string() },
+            vec![FrameSummary { name: "main".to_string(), source: None, line: 3 }],
+            vec![LocalSnapshot { name: "x".to_string(), value: "1".to_string(), type_: "number".to_string() }],
+        );
+
+        let value = serde_json::to_value(&recorder.records()[0]).unwrap();
+        assert_eq!(value["kind"], "error");
+        assert_eq!(value["message"], "boom");
+        assert_eq!(value["threadId"], 1);
+        assert_eq!(value["locals"][0]["type"], "number");
+    }
+}
@@ -0,0 +1,252 @@
+//! Listing of everything the session is set to stop on
+//!
+//! Answers "what does the server think is set?" for the `breakpointInventory`
+//! request and the debug console's `.breakpoints`, `.watchpoints`,
+//! `.exceptions` and `.all` commands, including verification states and hit
+//! counts.
+
+use super::breakpoints::BreakpointManager;
+use super::watchpoints::WatchpointManager;
+use serde::{Deserialize, Serialize};
+
+/// Which part of the inventory to list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InventoryScope {
+    #[default]
+    All,
+    Breakpoints,
+    Watchpoints,
+    Exceptions,
+}
+
+impl InventoryScope {
+    /// Debug console commands, for help and error messages
+    pub const COMMANDS: &'static str = ".breakpoints, .watchpoints, .exceptions, .all";
+
+    /// Parses a debug console command such as `.breakpoints`
+    pub fn from_command(command: &str) -> Option<Self> {
+        match command.trim() {
+            ".all" => Some(InventoryScope::All),
+            ".breakpoints" | ".bp" => Some(InventoryScope::Breakpoints),
+            ".watchpoints" | ".wp" => Some(InventoryScope::Watchpoints),
+            ".exceptions" => Some(InventoryScope::Exceptions),
+            _ => None,
+        }
+    }
+
+    fn includes(&self, scope: InventoryScope) -> bool {
+        *self == InventoryScope::All || *self == scope
+    }
+}
+
+/// One breakpoint or watchpoint as listed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryEntry {
+    pub id: i64,
+    /// `line`, `function` or `data`
+    pub kind: String,
+    /// `path:line`, the function name or the watched variable
+    pub location: String,
+    pub verified: bool,
+    pub hit_count: usize,
+    pub condition: Option<String>,
+    pub hit_condition: Option<String>,
+    pub log_message: Option<String>,
+    pub message: Option<String>,
+}
+
+impl InventoryEntry {
+    fn details(&self) -> String {
+        let mut details = Vec::new();
+        if let Some(condition) = &self.condition {
+            details.push(format!("if {}", condition));
+        }
+        if let Some(hit_condition) = &self.hit_condition {
+            details.push(format!("hits {}", hit_condition));
+        }
+        if let Some(log_message) = &self.log_message {
+            details.push(format!("log \"{}\"", log_message));
+        }
+        if let Some(message) = &self.message {
+            details.push(format!("({})", message));
+        }
+        details.join(", ")
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BreakpointInventory {
+    pub breakpoints: Vec<InventoryEntry>,
+    pub watchpoints: Vec<InventoryEntry>,
+    pub exception_filters: Vec<String>,
+}
+
+impl BreakpointInventory {
+    pub fn collect(breakpoints: &BreakpointManager, watchpoints: &WatchpointManager, scope: InventoryScope) -> Self {
+        let mut inventory = Self::default();
+
+        if scope.includes(InventoryScope::Breakpoints) {
+            let mut lines = breakpoints.get_all_line_breakpoints();
+            lines.sort_by(|a, b| (&a.source, a.line).cmp(&(&b.source, b.line)));
+            inventory.breakpoints.extend(lines.into_iter().map(|bp| InventoryEntry {
+                id: bp.id,
+                kind: "line".to_string(),
+                location: format!("{}:{}", bp.source, bp.line),
+                verified: bp.verified,
+                hit_count: bp.hit_count,
+                condition: bp.condition.clone(),
+                hit_condition: bp.hit_condition.clone(),
+                log_message: bp.log_message.clone(),
+                message: bp.message.clone(),
+            }));
+            inventory.breakpoints.extend(breakpoints.get_function_breakpoints().iter().map(|bp| InventoryEntry {
+                id: bp.id,
+                kind: "function".to_string(),
+                location: bp.name.clone(),
+                verified: bp.verified,
+                hit_count: bp.hit_count,
+                condition: bp.condition.clone(),
+                hit_condition: bp.hit_condition.clone(),
+                log_message: bp.log_message.clone(),
+                message: bp.message.clone(),
+            }));
+        }
+
+        if scope.includes(InventoryScope::Watchpoints) {
+            let mut data = watchpoints.get_data_breakpoints();
+            data.sort_by_key(|wp| wp.id);
+            inventory.watchpoints.extend(data.into_iter().map(|wp| InventoryEntry {
+                id: wp.id,
+                kind: "data".to_string(),
+                location: wp.name.clone(),
+                verified: wp.verified,
+                hit_count: wp.hit_count,
+                condition: wp.condition.clone(),
+                hit_condition: wp.hit_condition.clone(),
+                log_message: None,
+                message: wp.message.clone(),
+            }));
+        }
+
+        if scope.includes(InventoryScope::Exceptions) {
+            inventory.exception_filters = breakpoints.get_exception_breakpoints().clone();
+        }
+
+        inventory
+    }
+
+    /// Renders the inventory as plain-text tables for the debug console
+    pub fn render(&self, scope: InventoryScope) -> String {
+        let mut sections = Vec::new();
+        if scope.includes(InventoryScope::Breakpoints) {
+            sections.push(render_table("Breakpoints", &self.breakpoints));
+        }
+        if scope.includes(InventoryScope::Watchpoints) {
+            sections.push(render_table("Watchpoints", &self.watchpoints));
+        }
+        if scope.includes(InventoryScope::Exceptions) {
+            let filters = if self.exception_filters.is_empty() {
+                "none".to_string()
+            } else {
+                self.exception_filters.join(", ")
+            };
+            sections.push(format!("Exception filters: {}", filters));
+        }
+        sections.join("\n\n")
+    }
+}
+
+fn render_table(title: &str, entries: &[InventoryEntry]) -> String {
+    if entries.is_empty() {
+        return format!("{}: none", title);
+    }
+
+    let header = ["ID", "KIND", "LOCATION", "VERIFIED", "HITS", "DETAILS"];
+    let rows: Vec<[String; 6]> = entries
+        .iter()
+        .map(|entry| {
+            [
+                entry.id.to_string(),
+                entry.kind.clone(),
+                entry.location.clone(),
+                if entry.verified { "yes" } else { "no" }.to_string(),
+                entry.hit_count.to_string(),
+                entry.details(),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+
+    let mut lines = vec![format!("{}:", title), format_row(header.to_vec())];
+    lines.extend(rows.iter().map(|row| format_row(row.iter().map(String::as_str).collect())));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::breakpoints::LineBreakpoint;
+
+    fn line_breakpoint(line: u32, condition: Option<&str>) -> LineBreakpoint {
+        LineBreakpoint {
+            id: 0,
+            source: "/game/main.lua".to_string(),
+            line,
+            column: None,
+            condition: condition.map(str::to_string),
+            log_message: None,
+            hit_condition: None,
+            verified: true,
+            message: None,
+            hit_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_scope_commands() {
+        assert_eq!(InventoryScope::from_command(" .bp "), Some(InventoryScope::Breakpoints));
+        assert_eq!(InventoryScope::from_command(".exceptions"), Some(InventoryScope::Exceptions));
+        assert_eq!(InventoryScope::from_command(".frames"), None);
+    }
+
+    #[test]
+    fn test_render_breakpoints_table() {
+        let mut breakpoints = BreakpointManager::new();
+        breakpoints.set_line_breakpoints(
+            "/game/main.lua".to_string(),
+            vec![line_breakpoint(12, Some("hp < 0")), line_breakpoint(3, None)],
+        );
+        breakpoints.set_exception_breakpoints(vec!["uncaught".to_string()]);
+        let watchpoints = WatchpointManager::new();
+
+        let inventory = BreakpointInventory::collect(&breakpoints, &watchpoints, InventoryScope::All);
+        assert_eq!(inventory.breakpoints[0].location, "/game/main.lua:3");
+
+        let text = inventory.render(InventoryScope::All);
+        assert!(text.contains("/game/main.lua:12  yes       0     if hp < 0"));
+        assert!(text.contains("Watchpoints: none"));
+        assert!(text.contains("Exception filters: uncaught"));
+
+        let scoped = BreakpointInventory::collect(&breakpoints, &watchpoints, InventoryScope::Exceptions);
+        assert!(scoped.breakpoints.is_empty());
+        assert_eq!(scoped.render(InventoryScope::Exceptions), "Exception filters: uncaught");
+    }
+}
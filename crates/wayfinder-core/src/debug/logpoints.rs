@@ -1,63 +1,107 @@
 //! Logpoint handling for breakpoints
 //!
 //! This module provides functionality to evaluate logpoint messages
-//! and output them without pausing execution.
+//! and output them without pausing execution. Expressions in `{}` are
+//! evaluated in the breakpoint's frame; they may hold braces of their own,
+//! such as table constructors, as long as those are balanced.
 
 use crate::runtime::{DebugRuntime, Value};
-use regex::Regex;
+
+/// A piece of a logpoint message template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    Expression(&'a str),
+}
+
+/// Splits a template into text and `{expression}` placeholders
+///
+/// Braces inside string literals do not count, and a placeholder that is
+/// never closed is kept as text.
+pub fn parse_template(template: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut text_start = 0;
+    let mut chars = template.char_indices();
+    while let Some((open, c)) = chars.next() {
+        if c != '{' {
+            continue;
+        }
+        let mut depth = 0;
+        let mut quote = None;
+        let mut close = None;
+        while let Some((i, c)) = chars.next() {
+            match (quote, c) {
+                (Some(_), '\\') => {
+                    chars.next();
+                }
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '{') => depth += 1,
+                (None, '}') if depth == 0 => {
+                    close = Some(i);
+                    break;
+                }
+                (None, '}') => depth -= 1,
+                (None, _) => {}
+            }
+        }
+        let Some(close) = close else { break };
+        if open > text_start {
+            segments.push(Segment::Text(&template[text_start..open]));
+        }
+        segments.push(Segment::Expression(&template[open + 1..close]));
+        text_start = close + 1;
+    }
+    if text_start < template.len() {
+        segments.push(Segment::Text(&template[text_start..]));
+    }
+    segments
+}
+
+/// Renders a value the way Lua's `tostring` would, as far as the debugger can tell
+fn display_value(value: Value) -> String {
+    match value {
+        Value::Nil => "nil".to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s,
+        Value::Table { reference, .. } => format!("table:0x{:x}", reference as usize),
+        Value::Function { reference, name } => {
+            if let Some(n) = name {
+                format!("function:{}:0x{:x}", n, reference as usize)
+            } else {
+                format!("function:0x{:x}", reference as usize)
+            }
+        }
+        Value::UserData => "userdata".to_string(),
+        Value::Thread => "thread".to_string(),
+    }
+}
 
 /// Evaluates a logpoint message template with variable substitution
 pub struct LogpointEvaluator;
 
 impl LogpointEvaluator {
     /// Evaluates a logpoint message template and substitutes variables
+    ///
+    /// An expression that fails to evaluate is replaced by its error, so the
+    /// message still shows where it went wrong.
     pub async fn evaluate_log_message<R: DebugRuntime>(
         runtime: &mut R,
         frame_id: i64,
         template: &str,
     ) -> Result<String, Box<dyn std::error::Error>> {
-        // Parse the template for {expression} placeholders
-        let re = Regex::new(r"\{([^}]+)\}").unwrap();
-        
-        let mut result = template.to_string();
-        let _last_match = 0;
-        
-        // Find all placeholders and evaluate them
-        for cap in re.captures_iter(template) {
-            let full_match = &cap[0]; // e.g., "{x}"
-            let expression = &cap[1]; // e.g., "x"
-            
-            // Evaluate the expression
-            match runtime.evaluate(frame_id, expression).await {
-                Ok(value) => {
-                    // Convert the value to a string representation
-                    let value_str = match value {
-                        Value::Nil => "nil".to_string(),
-                        Value::Boolean(b) => b.to_string(),
-                        Value::Number(n) => n.to_string(),
-                        Value::String(s) => s,
-                        Value::Table { reference, .. } => format!("table:0x{:x}", reference as usize),
-                        Value::Function { reference, name } => {
-                            if let Some(n) = name {
-                                format!("function:{}:0x{:x}", n, reference as usize)
-                            } else {
-                                format!("function:0x{:x}", reference as usize)
-                            }
-                        },
-                        Value::UserData => "userdata".to_string(),
-                        Value::Thread => "thread".to_string(),
-                    };
-                    
-                    // Replace the placeholder with the evaluated value
-                    result = result.replacen(full_match, &value_str, 1);
-                }
-                Err(e) => {
-                    // If evaluation fails, leave the placeholder and log an error
-                    eprintln!("Warning: Failed to evaluate logpoint expression '{}': {}", expression, e);
-                }
+        let mut result = String::new();
+        for segment in parse_template(template) {
+            match segment {
+                Segment::Text(text) => result.push_str(text),
+                Segment::Expression(expression) => match runtime.evaluate(frame_id, expression.trim()).await {
+                    Ok(value) => result.push_str(&display_value(value)),
+                    Err(e) => result.push_str(&format!("<error: {}>", e)),
+                },
             }
         }
-        
         Ok(result)
     }
 
@@ -89,6 +133,22 @@ mod tests {
         let mut runtime = MockRuntime::new();
         let result = LogpointEvaluator::process_logpoint(&mut runtime, 0, "Value is {x}").await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "Value is {x}");
+        assert_eq!(result.unwrap(), "Value is 10");
+    }
+
+    #[test]
+    fn test_parse_template() {
+        assert_eq!(
+            parse_template("hp={player.hp} t={ {1, 2} } s={\"}\"}"),
+            vec![
+                Segment::Text("hp="),
+                Segment::Expression("player.hp"),
+                Segment::Text(" t="),
+                Segment::Expression(" {1, 2} "),
+                Segment::Text(" s="),
+                Segment::Expression("\"}\""),
+            ]
+        );
+        assert_eq!(parse_template("open {x"), vec![Segment::Text("open {x")]);
     }
 }
\ No newline at end of file
@@ -0,0 +1,113 @@
+//! TypeScriptToLua runtime frame handling
+//!
+//! Code compiled with TypeScriptToLua calls into `lualib_bundle.lua` helpers
+//! such as `__TS__ArrayPush` for many built-in operations. Those frames carry no
+//! meaning for the TypeScript author, so consecutive runtime frames are
+//! collapsed into a single labeled frame.
+
+use crate::runtime::Frame;
+
+/// Prefix shared by all TSTL runtime helper functions
+const LUALIB_FUNCTION_PREFIX: &str = "__TS__";
+
+/// File stem of the bundled TSTL runtime
+const LUALIB_BUNDLE_NAME: &str = "lualib_bundle";
+
+/// Returns true if the frame belongs to the TSTL runtime
+pub fn is_lualib_frame(frame: &Frame) -> bool {
+    if frame.name.starts_with(LUALIB_FUNCTION_PREFIX) {
+        return true;
+    }
+
+    frame
+        .source
+        .as_ref()
+        .map(|source| source.path.contains(LUALIB_BUNDLE_NAME))
+        .unwrap_or(false)
+}
+
+/// Collapses each run of consecutive TSTL runtime frames into one frame
+///
+/// The replacement frame keeps the id and location of the innermost runtime
+/// frame so selecting it still shows something useful.
+pub fn collapse_lualib_frames(frames: Vec<Frame>) -> Vec<Frame> {
+    let mut collapsed = Vec::with_capacity(frames.len());
+    let mut run: Vec<Frame> = Vec::new();
+
+    for frame in frames {
+        if is_lualib_frame(&frame) {
+            run.push(frame);
+        } else {
+            flush_run(&mut run, &mut collapsed);
+            collapsed.push(frame);
+        }
+    }
+    flush_run(&mut run, &mut collapsed);
+
+    collapsed
+}
+
+fn flush_run(run: &mut Vec<Frame>, out: &mut Vec<Frame>) {
+    if run.is_empty() {
+        return;
+    }
+
+    let names: Vec<&str> = run.iter().map(|f| f.name.as_str()).collect();
+    let mut frame = run[0].clone();
+    frame.name = if run.len() == 1 {
+        format!("[lualib] {}", names[0])
+    } else {
+        format!("[lualib: {} frames] {}", run.len(), names.join(" > "))
+    };
+
+    out.push(frame);
+    run.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Source;
+
+    fn frame(id: i64, name: &str, path: &str) -> Frame {
+        Frame {
+            id,
+            name: name.to_string(),
+            source: Some(Source {
+                name: path.to_string(),
+                path: path.to_string(),
+                source_reference: None,
+            }),
+            line: 1,
+            column: 1,
+            presentation_hint: None,
+        }
+    }
+
+    #[test]
+    fn test_detects_runtime_frames() {
+        assert!(is_lualib_frame(&frame(0, "__TS__ArrayPush", "main.lua")));
+        assert!(is_lualib_frame(&frame(0, "anonymous", "out/lualib_bundle.lua")));
+        assert!(!is_lualib_frame(&frame(0, "update", "main.lua")));
+    }
+
+    #[test]
+    fn test_collapses_consecutive_runtime_frames() {
+        let frames = vec![
+            frame(0, "callback", "main.lua"),
+            frame(1, "__TS__ArrayForEach", "lualib_bundle.lua"),
+            frame(2, "__TS__ArrayPush", "lualib_bundle.lua"),
+            frame(3, "main", "main.lua"),
+            frame(4, "__TS__New", "lualib_bundle.lua"),
+        ];
+
+        let collapsed = collapse_lualib_frames(frames);
+        assert_eq!(collapsed.len(), 4);
+        assert_eq!(collapsed[1].id, 1);
+        assert_eq!(
+            collapsed[1].name,
+            "[lualib: 2 frames] __TS__ArrayForEach > __TS__ArrayPush"
+        );
+        assert_eq!(collapsed[3].name, "[lualib] __TS__New");
+    }
+}
@@ -0,0 +1,99 @@
+//! Bytes behind the `memoryReference` of strings and userdata
+//!
+//! Variables holding a string or userdata carry a memory reference, so a
+//! client's hex view can show binary blobs passed through Lua. Strings are
+//! read-only, since Lua shares and interns them. The block of a full userdata
+//! can be written too, but only with `unsafe_memory_writes` set, as nothing
+//! stops a write from breaking what the host keeps in it. A light userdata is
+//! only an address: its extent is unknown, so none of its bytes are readable.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// The answer to a `readMemory` request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryRead {
+    /// Address of the first byte of `data`, or of the request's first byte when nothing was read
+    pub address: u64,
+    pub data: Vec<u8>,
+    /// Bytes after the last one read that could not be read
+    pub unreadable_bytes: usize,
+}
+
+/// Reads `count` bytes at `offset` into `bytes`, a block starting at `address`
+///
+/// Bytes before the block are skipped; those past its end are unreadable.
+pub fn read(bytes: &[u8], address: u64, offset: i64, count: usize) -> MemoryRead {
+    let len = bytes.len() as i64;
+    let end = offset.saturating_add(count as i64);
+    let start = offset.clamp(0, len);
+    let stop = end.clamp(start, len);
+    if stop == start {
+        return unreadable(address, offset, count);
+    }
+    MemoryRead {
+        address: address + start as u64,
+        data: bytes[start as usize..stop as usize].to_vec(),
+        unreadable_bytes: (end - stop) as usize,
+    }
+}
+
+/// A read of `count` bytes at `offset` from `address` that finds nothing readable
+pub fn unreadable(address: u64, offset: i64, count: usize) -> MemoryRead {
+    MemoryRead {
+        address: address.wrapping_add_signed(offset),
+        data: Vec::new(),
+        unreadable_bytes: count,
+    }
+}
+
+/// The bytes of a `len`-byte block that a `count`-byte write at `offset` covers
+///
+/// A write that does not fit in the block is refused, unless `allow_partial`
+/// lets it write the bytes that do.
+pub fn write_range(len: usize, offset: i64, count: usize, allow_partial: bool) -> Result<Range<usize>, String> {
+    let start = usize::try_from(offset)
+        .ok()
+        .filter(|&start| start < len || count == 0)
+        .ok_or_else(|| format!("Offset {} is outside the {}-byte block", offset, len))?;
+    let stop = start.saturating_add(count).min(len);
+    if stop - start < count && !allow_partial {
+        return Err(format!(
+            "Writing {} bytes at offset {} would run past the end of the {}-byte block",
+            count, offset, len
+        ));
+    }
+    Ok(start..stop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_stop_at_the_block() {
+        let bytes = b"PNG\r\n";
+        let whole = read(bytes, 0x1000, 0, 5);
+        assert_eq!((whole.address, whole.data.as_slice(), whole.unreadable_bytes), (0x1000, &bytes[..], 0));
+
+        let tail = read(bytes, 0x1000, 3, 8);
+        assert_eq!((tail.address, tail.data.as_slice(), tail.unreadable_bytes), (0x1003, &b"\r\n"[..], 6));
+
+        // Bytes before the block are skipped rather than reported
+        let before = read(bytes, 0x1000, -2, 4);
+        assert_eq!((before.address, before.data.as_slice(), before.unreadable_bytes), (0x1000, &b"PN"[..], 0));
+
+        let past = read(bytes, 0x1000, 10, 4);
+        assert_eq!((past.address, past.data.len(), past.unreadable_bytes), (0x100a, 0, 4));
+    }
+
+    #[test]
+    fn test_writes_must_fit_unless_partial() {
+        assert_eq!(write_range(8, 2, 4, false), Ok(2..6));
+        assert!(write_range(8, 6, 4, false).unwrap_err().contains("past the end"));
+        assert_eq!(write_range(8, 6, 4, true), Ok(6..8));
+        assert!(write_range(8, -1, 1, true).is_err());
+        assert!(write_range(8, 8, 1, true).is_err());
+    }
+}
@@ -1,7 +1,24 @@
+pub mod breakpoint_file;
 pub mod breakpoints;
+pub mod chunk_lines;
+pub mod chunks;
+pub mod completions;
 pub mod conditions;
+pub mod encoding;
+pub mod execution_trace;
+pub mod flight_recorder;
 pub mod hit_conditions;
+pub mod inventory;
 pub mod logpoints;
+pub mod memory_references;
+pub mod lualib;
+pub mod modules;
+pub mod side_effects;
+pub mod source_maps;
+pub mod source_paths;
+pub mod time_travel;
+pub mod ts_expressions;
+pub mod watches;
 pub mod watchpoints;
 
 pub struct Debug;
@@ -0,0 +1,122 @@
+//! Lua modules the program has loaded, for the `modules` request
+//!
+//! The modules are the string keys of `package.loaded`, read when the client
+//! asks. The `require` hook notes when each module was found and the file it
+//! came from, so a module loaded through it carries its load time and keeps a
+//! path on Lua versions without `package.searchpath`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A DAP `Module`: one entry of `package.loaded`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Module {
+    /// The name `require` takes, which is unique among loaded modules
+    pub id: String,
+    pub name: String,
+    /// File the module was loaded from; none for built-in and C modules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Milliseconds since the Unix epoch; none for modules loaded before the hook was set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_time_ms: Option<u64>,
+}
+
+/// What the `require` hook saw of a module
+#[derive(Debug, Clone)]
+struct LoadRecord {
+    path: Option<String>,
+    load_time_ms: u64,
+}
+
+/// Modules the `require` hook saw loaded, by name
+#[derive(Debug, Default)]
+pub struct ModuleRegistry {
+    loaded: HashMap<String, LoadRecord>,
+}
+
+impl ModuleRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Notes that `require` found `name` in the chunk `source`
+    ///
+    /// Returns the module the first time it is seen, for the `module` event.
+    pub fn record(&mut self, name: &str, source: &str) -> Option<Module> {
+        if self.loaded.contains_key(name) {
+            return None;
+        }
+        let load_time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let record = LoadRecord {
+            path: source.strip_prefix('@').map(str::to_string),
+            load_time_ms,
+        };
+        self.loaded.insert(name.to_string(), record);
+        Some(self.module(name, None))
+    }
+
+    /// The module `name` of `package.loaded`, found at `searched_path` by `package.searchpath`
+    pub fn module(&self, name: &str, searched_path: Option<String>) -> Module {
+        let record = self.loaded.get(name);
+        Module {
+            id: name.to_string(),
+            name: name.to_string(),
+            path: searched_path.or_else(|| record.and_then(|r| r.path.clone())),
+            load_time_ms: record.map(|r| r.load_time_ms),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.loaded.clear();
+    }
+}
+
+/// The modules of a `modules` request's page, sorted by name
+///
+/// A `count` of 0 asks for every module from `start` on.
+pub fn page(mut modules: Vec<Module>, start: usize, count: usize) -> Vec<Module> {
+    modules.sort_by(|a, b| a.name.cmp(&b.name));
+    let count = if count > 0 { count } else { usize::MAX };
+    modules.into_iter().skip(start).take(count).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hooked_modules_keep_their_file_and_load_time() {
+        let mut registry = ModuleRegistry::new();
+        let module = registry.record("ui.menu", "@/game/ui/menu.lua").unwrap();
+        assert_eq!(module.path.as_deref(), Some("/game/ui/menu.lua"));
+        assert!(module.load_time_ms.is_some());
+        assert!(registry.record("ui.menu", "@/game/ui/menu.lua").is_none());
+
+        // The searched path wins over the hooked chunk
+        let module = registry.module("ui.menu", Some("./ui/menu.lua".to_string()));
+        assert_eq!(module.path.as_deref(), Some("./ui/menu.lua"));
+        let module = registry.module("string", None);
+        assert_eq!((module.path, module.load_time_ms), (None, None));
+
+        let value = serde_json::to_value(registry.module("ui.menu", None)).unwrap();
+        assert_eq!(value["id"], "ui.menu");
+        assert!(value["loadTimeMs"].is_u64());
+    }
+
+    #[test]
+    fn test_page_sorts_by_name() {
+        let registry = ModuleRegistry::new();
+        let modules: Vec<Module> = ["string", "main", "_G", "ui"].iter().map(|n| registry.module(n, None)).collect();
+
+        let names = |modules: Vec<Module>| modules.into_iter().map(|m| m.name).collect::<Vec<_>>();
+        assert_eq!(names(page(modules.clone(), 0, 0)), ["_G", "main", "string", "ui"]);
+        assert_eq!(names(page(modules.clone(), 1, 2)), ["main", "string"]);
+        assert!(page(modules, 4, 0).is_empty());
+    }
+}
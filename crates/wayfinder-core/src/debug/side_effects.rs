@@ -0,0 +1,251 @@
+//! Keeps hover and watch evaluation from changing the program
+//!
+//! Hovering an identifier and refreshing the watch pane evaluate expressions
+//! without the user asking to run anything, so they must only read state.
+//! Whatever the evaluation safety setting, expressions in those contexts are
+//! scanned for function calls and assignments and rejected if they have any;
+//! the debug console keeps the configured safety level. Metamethods such as
+//! `__index` still run, since only the syntax is checked.
+
+use crate::runtime::{EvalErrorKind, RuntimeError};
+
+/// Where an expression comes from, as the `context` of an evaluate request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvaluationContext {
+    Hover,
+    Watch,
+    Repl,
+    Clipboard,
+    /// A context this debugger does not know, treated as the console
+    Other,
+}
+
+impl EvaluationContext {
+    /// Reads an evaluate request's `context`; the console without one
+    pub fn from_request(context: Option<&str>) -> Self {
+        match context {
+            Some("hover") => EvaluationContext::Hover,
+            Some("watch") => EvaluationContext::Watch,
+            Some("repl") | None => EvaluationContext::Repl,
+            Some("clipboard") => EvaluationContext::Clipboard,
+            Some(_) => EvaluationContext::Other,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            EvaluationContext::Hover => "hover",
+            EvaluationContext::Watch => "watch",
+            EvaluationContext::Repl => "repl",
+            EvaluationContext::Clipboard => "clipboard",
+            EvaluationContext::Other => "other",
+        }
+    }
+
+    /// Whether expressions in this context must not have side effects
+    pub fn is_read_only(self) -> bool {
+        matches!(self, EvaluationContext::Hover | EvaluationContext::Watch)
+    }
+
+    /// Rejects an expression with side effects in a read-only context
+    pub fn check(self, expression: &str) -> Result<(), RuntimeError> {
+        if !self.is_read_only() {
+            return Ok(());
+        }
+        match side_effect(expression) {
+            Some(effect) => Err(RuntimeError::Evaluation {
+                kind: EvalErrorKind::Rejected,
+                message: format!("{} are not evaluated in {} expressions, which must not change the program", effect, self.label()),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Keywords that can come right before a parenthesis or table without calling anything
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in", "local", "nil",
+    "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// What came before the current token, enough to tell a call from a grouping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Previous {
+    /// Start of the expression, an operator or a keyword
+    Operator,
+    /// A name, closing bracket or string: something a call can follow
+    Callable,
+}
+
+/// The first kind of side effect an expression has: function calls or assignments
+///
+/// A call is a parenthesis, string or table right after a name, a closing
+/// bracket or a string, as in `f()`, `obj:m"x"` or `(g)()`. An assignment is
+/// an `=` that is not part of a comparison or a table constructor's field.
+/// Strings and comments are skipped.
+pub fn side_effect(expression: &str) -> Option<&'static str> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut previous = Previous::Operator;
+    let mut braces = 0usize;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                i = skip_comment(&chars, i + 2);
+                continue;
+            }
+            '"' | '\'' => {
+                if previous == Previous::Callable {
+                    return Some("Function calls");
+                }
+                i = skip_quoted(&chars, i);
+                previous = Previous::Callable;
+                continue;
+            }
+            '[' if long_bracket_level(&chars, i).is_some() => {
+                if previous == Previous::Callable {
+                    return Some("Function calls");
+                }
+                i = skip_long_bracket(&chars, i);
+                previous = Previous::Callable;
+                continue;
+            }
+            '(' if previous == Previous::Callable => return Some("Function calls"),
+            '{' if previous == Previous::Callable => return Some("Function calls"),
+            '{' => braces += 1,
+            '}' => braces = braces.saturating_sub(1),
+            '=' => {
+                if chars.get(i + 1) == Some(&'=') {
+                    i += 2;
+                    previous = Previous::Operator;
+                    continue;
+                }
+                let comparison = i > 0 && matches!(chars[i - 1], '~' | '<' | '>' | '=');
+                if !comparison && braces == 0 {
+                    return Some("Assignments");
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                previous = if KEYWORDS.contains(&word.as_str()) { Previous::Operator } else { Previous::Callable };
+                continue;
+            }
+            c if c.is_ascii_digit() => {
+                // Numbers, hexadecimal and exponents included, cannot be called
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '.') {
+                    i += 1;
+                }
+                previous = Previous::Operator;
+                continue;
+            }
+            ')' | ']' => {
+                i += 1;
+                previous = Previous::Callable;
+                continue;
+            }
+            _ => {}
+        }
+        previous = Previous::Operator;
+        i += 1;
+    }
+    None
+}
+
+/// The index after a comment whose `--` ends before `start`
+fn skip_comment(chars: &[char], start: usize) -> usize {
+    if long_bracket_level(chars, start).is_some() {
+        return skip_long_bracket(chars, start);
+    }
+    chars[start..].iter().position(|&c| c == '\n').map_or(chars.len(), |end| start + end + 1)
+}
+
+/// The index after the quoted string starting at `start`
+fn skip_quoted(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+/// The level of the long bracket `[==[` opening at `start`, if one does
+fn long_bracket_level(chars: &[char], start: usize) -> Option<usize> {
+    if chars.get(start) != Some(&'[') {
+        return None;
+    }
+    let level = chars[start + 1..].iter().take_while(|&&c| c == '=').count();
+    (chars.get(start + 1 + level) == Some(&'[')).then_some(level)
+}
+
+/// The index after the long string or comment opening at `start`
+fn skip_long_bracket(chars: &[char], start: usize) -> usize {
+    let level = long_bracket_level(chars, start).unwrap_or(0);
+    let close: Vec<char> = std::iter::once(']').chain(std::iter::repeat_n('=', level)).chain(std::iter::once(']')).collect();
+    let body = start + level + 2;
+    chars[body..]
+        .windows(close.len())
+        .position(|window| window == close.as_slice())
+        .map_or(chars.len(), |end| body + end + close.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_have_no_side_effects() {
+        for expression in [
+            "player.hp",
+            "enemies[i].pos.x * 2",
+            "(a + b) / 2",
+            "not (x == y) and (z ~= 1)",
+            "a <= b or a >= c",
+            "#items",
+            "{ x = 1, y = { z = 2 } }",
+            "\"call(me)\" .. 'x = 1'",
+            "[[f()]] -- g()",
+            "0x1F + 1e3",
+            "function(a) return a end",
+        ] {
+            assert_eq!(side_effect(expression), None, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_calls_and_assignments_are_found() {
+        for expression in ["f()", "obj:update(dt)", "print 'x'", "require\"mod\"", "t{1}", "(f)()", "list[1]()", "s:upper()"] {
+            assert_eq!(side_effect(expression), Some("Function calls"), "{}", expression);
+        }
+        for expression in ["x = 1", "player.hp = 0", "a, b = b, a"] {
+            assert_eq!(side_effect(expression), Some("Assignments"), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_only_hover_and_watch_are_read_only() {
+        let hover = EvaluationContext::from_request(Some("hover"));
+        let error = hover.check("save()").unwrap_err();
+        assert!(matches!(error, RuntimeError::Evaluation { kind: EvalErrorKind::Rejected, .. }));
+        assert!(error.to_string().contains("hover"), "{}", error);
+        assert!(EvaluationContext::from_request(Some("watch")).check("hp = 0").is_err());
+        assert!(hover.check("hp").is_ok());
+
+        for context in [None, Some("repl"), Some("clipboard"), Some("variables")] {
+            assert!(EvaluationContext::from_request(context).check("save()").is_ok());
+        }
+    }
+}
@@ -0,0 +1,596 @@
+//! Source maps of TypeScriptToLua output
+//!
+//! TSTL writes a version 3 source map next to each Lua file, or inlines it as
+//! a base64 data URI in a trailing `--# sourceMappingURL=` comment. A
+//! [`SourceMap`] translates positions both ways with a binary search over its
+//! mappings, and a [`SourceMapStore`] finds and caches the map of each Lua
+//! file, loading it again once the file is rebuilt.
+//!
+//! Lines and columns are 1-based throughout, as DAP clients send them by
+//! default; the 0-based positions of the map format stay inside this module.
+
+use super::source_paths::PathResolver;
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Directories never searched for Lua files
+const SKIPPED_DIRECTORIES: &[&str] = &["node_modules", ".git"];
+
+/// Comment prefixes a Lua file can name its source map with
+const SOURCE_MAPPING_URL_PREFIXES: &[&str] = &["--# sourceMappingURL=", "--@ sourceMappingURL="];
+
+/// Position in a TypeScript source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginalPosition {
+    pub source: PathBuf,
+    pub line: u32,
+    pub column: u32,
+    /// Name the mapping gives the identifier at the position
+    pub name: Option<String>,
+}
+
+/// Position in generated Lua
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeneratedPosition {
+    pub line: u32,
+    pub column: u32,
+}
+
+/// One mapping segment, with 0-based positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mapping {
+    generated_line: u32,
+    generated_column: u32,
+    source: usize,
+    original_line: u32,
+    original_column: u32,
+    name: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawSourceMap {
+    version: u32,
+    #[serde(default)]
+    source_root: Option<String>,
+    sources: Vec<Option<String>>,
+    #[serde(default)]
+    names: Vec<String>,
+    mappings: String,
+}
+
+/// A parsed source map
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    /// Sources resolved against the map's directory and `sourceRoot`
+    sources: Vec<PathBuf>,
+    names: Vec<String>,
+    /// Mappings ordered by generated position
+    by_generated: Vec<Mapping>,
+    /// Mappings ordered by source and original position
+    by_original: Vec<Mapping>,
+}
+
+impl SourceMap {
+    /// Parses a source map, resolving its sources against `base`
+    ///
+    /// Segments that map to no source are dropped, so the mapping before one
+    /// extends over the unmapped code.
+    pub fn parse(text: &str, base: &Path) -> Result<Self, String> {
+        Self::parse_with(text, base, &PathResolver::default())
+    }
+
+    /// Parses a source map, resolving its sources with a project's path settings
+    pub fn parse_with(text: &str, base: &Path, resolver: &PathResolver) -> Result<Self, String> {
+        let raw: RawSourceMap = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        if raw.version != 3 {
+            return Err(format!("unsupported source map version {}", raw.version));
+        }
+
+        let sources = raw
+            .sources
+            .iter()
+            .map(|source| {
+                normalize(&resolver.resolve_source(source.as_deref().unwrap_or(""), raw.source_root.as_deref(), base))
+            })
+            .collect();
+        let by_generated = decode_mappings(&raw.mappings, raw.sources.len(), raw.names.len())?;
+        let mut by_original = by_generated.clone();
+        by_original.sort_by_key(|m| (m.source, m.original_line, m.original_column, m.generated_line, m.generated_column));
+
+        Ok(Self {
+            sources,
+            names: raw.names,
+            by_generated,
+            by_original,
+        })
+    }
+
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    /// Where the code at a generated position came from
+    ///
+    /// Uses the last mapping at or before the column on that line, or the
+    /// line's first mapping when the column is before all of them, so a
+    /// line-only position such as a hook's maps to the start of the line.
+    pub fn original_position(&self, line: u32, column: u32) -> Option<OriginalPosition> {
+        let (line, column) = (line.checked_sub(1)?, column.saturating_sub(1));
+        let after = self
+            .by_generated
+            .partition_point(|m| (m.generated_line, m.generated_column) <= (line, column));
+        let mapping = match after.checked_sub(1).map(|i| &self.by_generated[i]) {
+            Some(mapping) if mapping.generated_line == line => mapping,
+            _ => self.by_generated.get(after).filter(|m| m.generated_line == line)?,
+        };
+
+        Some(OriginalPosition {
+            source: self.sources[mapping.source].clone(),
+            line: mapping.original_line + 1,
+            column: mapping.original_column + 1,
+            name: mapping.name.map(|name| self.names[name].clone()),
+        })
+    }
+
+    /// Where the code for an original position ended up
+    ///
+    /// Uses the first mapping at or after the column on that line of the
+    /// source, so a breakpoint on a line lands on the code for its first
+    /// statement.
+    pub fn generated_position(&self, source: &Path, line: u32, column: u32) -> Option<GeneratedPosition> {
+        let source = normalize(source);
+        let index = self.sources.iter().position(|s| *s == source)?;
+        let (line, column) = (line.checked_sub(1)?, column.saturating_sub(1));
+        let at = self
+            .by_original
+            .partition_point(|m| (m.source, m.original_line, m.original_column) < (index, line, column));
+        let mapping = self.by_original.get(at).filter(|m| m.source == index && m.original_line == line)?;
+
+        Some(GeneratedPosition {
+            line: mapping.generated_line + 1,
+            column: mapping.generated_column + 1,
+        })
+    }
+
+    /// Every mapping from lines `line` to `end_line` of a source, in source order
+    pub fn mappings_in(&self, source: &Path, line: u32, end_line: u32) -> Vec<(OriginalPosition, GeneratedPosition)> {
+        let source = normalize(source);
+        let Some(index) = self.sources.iter().position(|s| *s == source) else { return Vec::new() };
+        let (line, end_line) = (line.saturating_sub(1), end_line.saturating_sub(1));
+        let start = self.by_original.partition_point(|m| (m.source, m.original_line) < (index, line));
+        self.by_original[start..]
+            .iter()
+            .take_while(|m| m.source == index && m.original_line <= end_line)
+            .map(|m| {
+                let original = OriginalPosition {
+                    source: source.clone(),
+                    line: m.original_line + 1,
+                    column: m.original_column + 1,
+                    name: m.name.map(|name| self.names[name].clone()),
+                };
+                let generated = GeneratedPosition {
+                    line: m.generated_line + 1,
+                    column: m.generated_column + 1,
+                };
+                (original, generated)
+            })
+            .collect()
+    }
+
+    /// TypeScript names TSTL gave another identifier in the generated code
+    ///
+    /// `code` is the Lua the map belongs to. The identifier at each named
+    /// mapping counts as a rename only if it is one TSTL makes: a `____`
+    /// prefix for names Lua reserves, or `_XX` hex escapes for characters
+    /// such as `$`, so mappings that merely start at a keyword are ignored.
+    pub fn renamed_identifiers(&self, code: &str) -> HashMap<String, String> {
+        let lines: Vec<&str> = code.lines().collect();
+        let mut renames = HashMap::new();
+        for mapping in &self.by_generated {
+            let Some(name) = mapping.name.map(|name| &self.names[name]) else { continue };
+            let Some(line) = lines.get(mapping.generated_line as usize) else { continue };
+            let generated: String = line
+                .chars()
+                .skip(mapping.generated_column as usize)
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                .collect();
+            if generated != *name && is_tstl_rename(name, &generated) {
+                renames.insert(name.clone(), generated);
+            }
+        }
+        renames
+    }
+}
+
+/// Whether `generated` is a name TSTL could have made from the TypeScript `name`
+fn is_tstl_rename(name: &str, generated: &str) -> bool {
+    let escaped: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c.to_string() } else { format!("_{:X}", c as u32) })
+        .collect();
+    generated == escaped || generated.strip_prefix("____") == Some(escaped.as_str())
+}
+
+/// Decodes the `mappings` field into segments ordered by generated position
+fn decode_mappings(mappings: &str, source_count: usize, name_count: usize) -> Result<Vec<Mapping>, String> {
+    let mut decoded = Vec::new();
+    // Every field but the generated column is relative to the previous segment anywhere in the map
+    let (mut source, mut original_line, mut original_column, mut name) = (0i64, 0i64, 0i64, 0i64);
+
+    for (generated_line, line) in mappings.split(';').enumerate() {
+        let mut generated_column = 0i64;
+        for segment in line.split(',').filter(|segment| !segment.is_empty()) {
+            let fields = decode_vlq(segment)?;
+            generated_column += fields[0];
+            match fields.len() {
+                1 => continue,
+                4 | 5 => {}
+                n => return Err(format!("mapping segment `{}` has {} fields", segment, n)),
+            }
+            source += fields[1];
+            original_line += fields[2];
+            original_column += fields[3];
+            let name = fields.get(4).map(|delta| {
+                name += delta;
+                name
+            });
+
+            let in_range = |value: i64, len: usize| usize::try_from(value).is_ok_and(|value| value < len);
+            if !in_range(source, source_count) || name.is_some_and(|name| !in_range(name, name_count)) {
+                return Err(format!("mapping segment `{}` refers past the sources or names", segment));
+            }
+            if generated_column < 0 || original_line < 0 || original_column < 0 {
+                return Err(format!("mapping segment `{}` has a negative position", segment));
+            }
+            decoded.push(Mapping {
+                generated_line: generated_line as u32,
+                generated_column: generated_column as u32,
+                source: source as usize,
+                original_line: original_line as u32,
+                original_column: original_column as u32,
+                name: name.map(|name| name as usize),
+            });
+        }
+    }
+
+    // Segments within a line are usually ordered already, but the format does not promise it
+    decoded.sort_by_key(|m| (m.generated_line, m.generated_column));
+    Ok(decoded)
+}
+
+/// Decodes a segment's base64 VLQ fields
+fn decode_vlq(segment: &str) -> Result<Vec<i64>, String> {
+    let mut fields = Vec::new();
+    let (mut value, mut shift) = (0i64, 0u32);
+    for c in segment.bytes() {
+        let digit = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(format!("invalid character `{}` in mapping segment `{}`", c as char, segment)),
+        } as i64;
+        if shift > 60 {
+            return Err(format!("mapping segment `{}` has a field that is too large", segment));
+        }
+        value |= (digit & 31) << shift;
+        if digit & 32 != 0 {
+            shift += 5;
+            continue;
+        }
+        fields.push(if value & 1 == 1 { -(value >> 1) } else { value >> 1 });
+        (value, shift) = (0, 0);
+    }
+    if shift != 0 {
+        return Err(format!("mapping segment `{}` ends in the middle of a field", segment));
+    }
+    Ok(fields)
+}
+
+/// Resolves `.` and `..` without touching the filesystem, as sources may not exist here
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Where a Lua file's source map lives
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MapLocation {
+    /// Inlined into the Lua file as a data URI
+    Inline(String),
+    File(PathBuf),
+}
+
+/// Finds the source map a Lua file names in its last `sourceMappingURL` comment
+///
+/// Without a comment, a `.map` file next to the Lua file is used if there is one.
+fn locate_map(lua_file: &Path, code: &str) -> Result<Option<MapLocation>, String> {
+    let url = code.lines().rev().map(str::trim).find_map(|line| {
+        SOURCE_MAPPING_URL_PREFIXES.iter().find_map(|prefix| line.strip_prefix(prefix))
+    });
+    let dir = lua_file.parent().unwrap_or_else(|| Path::new(""));
+
+    match url {
+        Some(url) if url.starts_with("data:") => {
+            let (header, data) = url.split_once(',').ok_or("source map data URI has no data")?;
+            if !header.ends_with(";base64") {
+                return Err("only base64 source map data URIs are supported".to_string());
+            }
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(data.trim())
+                .map_err(|e| format!("inline source map is not valid base64: {}", e))?;
+            let text = String::from_utf8(bytes).map_err(|_| "inline source map is not UTF-8".to_string())?;
+            Ok(Some(MapLocation::Inline(text)))
+        }
+        Some(url) => Ok(Some(MapLocation::File(dir.join(url)))),
+        None => {
+            let mut sibling = lua_file.as_os_str().to_owned();
+            sibling.push(".map");
+            let sibling = PathBuf::from(sibling);
+            Ok(sibling.is_file().then_some(MapLocation::File(sibling)))
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[derive(Debug)]
+struct CachedMap {
+    /// Modification times of the Lua file and a separate map file when loaded
+    stamp: (Option<SystemTime>, Option<SystemTime>),
+    map_file: Option<PathBuf>,
+    /// None for a Lua file without a source map, so it is not looked at again
+    map: Option<Arc<SourceMap>>,
+}
+
+/// Source maps of Lua files, loaded on first use and cached per file
+///
+/// A cached map is loaded again when the Lua file or its map file changes.
+#[derive(Debug, Default)]
+pub struct SourceMapStore {
+    maps: HashMap<PathBuf, CachedMap>,
+    resolver: PathResolver,
+}
+
+impl SourceMapStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store that resolves map sources with a project's path settings
+    pub fn with_resolver(resolver: PathResolver) -> Self {
+        Self {
+            maps: HashMap::new(),
+            resolver,
+        }
+    }
+
+    /// The source map of a Lua file, None if it has none
+    pub fn for_lua_file(&mut self, lua_file: &Path) -> Result<Option<Arc<SourceMap>>, String> {
+        let lua_file = normalize(lua_file);
+        if let Some(cached) = self.maps.get(&lua_file) {
+            let map_modified = cached.map_file.as_deref().and_then(modified);
+            if cached.stamp == (modified(&lua_file), map_modified) {
+                return Ok(cached.map.clone());
+            }
+        }
+
+        let lua_modified = modified(&lua_file);
+        let code = std::fs::read_to_string(&lua_file).map_err(|e| format!("{}: {}", lua_file.display(), e))?;
+        let dir = lua_file.parent().unwrap_or_else(|| Path::new(""));
+        let (map, map_file) = match locate_map(&lua_file, &code).map_err(|e| format!("{}: {}", lua_file.display(), e))? {
+            Some(MapLocation::Inline(text)) => {
+                let map = SourceMap::parse_with(&text, dir, &self.resolver)
+                    .map_err(|e| format!("{}: {}", lua_file.display(), e))?;
+                (Some(map), None)
+            }
+            Some(MapLocation::File(path)) => {
+                let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                let base = path.parent().unwrap_or_else(|| Path::new(""));
+                let map = SourceMap::parse_with(&text, base, &self.resolver)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                (Some(map), Some(path))
+            }
+            None => (None, None),
+        };
+
+        let map = map.map(Arc::new);
+        let stamp = (lua_modified, map_file.as_deref().and_then(modified));
+        self.maps.insert(lua_file, CachedMap { stamp, map_file, map: map.clone() });
+        Ok(map)
+    }
+
+    /// Translates a position in a Lua file to its TypeScript source
+    pub fn to_original(&mut self, lua_file: &Path, line: u32, column: u32) -> Option<OriginalPosition> {
+        self.for_lua_file(lua_file).ok()??.original_position(line, column)
+    }
+
+    /// Translates a position in a TypeScript source to the Lua file generated from it
+    ///
+    /// Only maps already loaded are searched, since nothing names the Lua
+    /// file a source compiles to; load the program's files first.
+    pub fn to_generated(&self, source: &Path, line: u32, column: u32) -> Option<(PathBuf, GeneratedPosition)> {
+        self.maps.iter().find_map(|(lua_file, cached)| {
+            let position = cached.map.as_ref()?.generated_position(source, line, column)?;
+            Some((lua_file.clone(), position))
+        })
+    }
+
+    /// The mappings from lines `line` to `end_line` of a source in a Lua file's map
+    pub fn mappings_in(&mut self, lua_file: &Path, source: &Path, line: u32, end_line: u32) -> Vec<(OriginalPosition, GeneratedPosition)> {
+        match self.for_lua_file(lua_file) {
+            Ok(Some(map)) => map.mappings_in(source, line, end_line),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The Lua file whose map covers a source
+    ///
+    /// Looks through the maps loaded so far, then at the file the project's
+    /// `outDir` puts the source's Lua in.
+    pub fn lua_file_for(&mut self, source: &Path) -> Option<PathBuf> {
+        let source = normalize(source);
+        let loaded = self.maps.iter().find_map(|(lua_file, cached)| {
+            cached.map.as_ref()?.sources().contains(&source).then(|| lua_file.clone())
+        });
+        if loaded.is_some() {
+            return loaded;
+        }
+
+        let predicted = normalize(&self.resolver.lua_file_for(&source)?);
+        let map = self.for_lua_file(&predicted).ok()??;
+        map.sources().contains(&source).then_some(predicted)
+    }
+
+    /// Loads the maps of every Lua file under a directory
+    ///
+    /// Files whose map does not load are skipped, as are `node_modules` and
+    /// hidden directories.
+    pub fn load_dir(&mut self, dir: &Path) {
+        let Ok(entries) = std::fs::read_dir(dir) else { return };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if path.is_dir() {
+                if !name.starts_with('.') && !SKIPPED_DIRECTORIES.contains(&name.as_ref()) {
+                    self.load_dir(&path);
+                }
+            } else if name.ends_with(".lua") {
+                let _ = self.for_lua_file(&path);
+            }
+        }
+    }
+
+    /// TypeScript names renamed in a Lua file, empty if it has no map
+    pub fn renamed_identifiers(&mut self, lua_file: &Path) -> HashMap<String, String> {
+        let Some(map) = self.for_lua_file(lua_file).ok().flatten() else { return HashMap::new() };
+        std::fs::read_to_string(lua_file)
+            .map(|code| map.renamed_identifiers(&code))
+            .unwrap_or_default()
+    }
+
+    /// Drops the cached map of a Lua file
+    pub fn invalidate(&mut self, lua_file: &Path) {
+        self.maps.remove(&normalize(lua_file));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Map of `src/main.ts` compiled to `main.lua`:
+    /// line 1 `local x = 1` from 1:7 (`x`), line 2 from 2:1 and, at column 7, from 4:3
+    const MAP: &str = r#"{
+        "version": 3,
+        "sourceRoot": "",
+        "sources": ["src/main.ts"],
+        "names": ["x"],
+        "mappings": "AAAMA;AACN,MAEE"
+    }"#;
+
+    #[test]
+    fn test_renamed_identifiers() {
+        // `local ____end = 1` from `let end = 1`, `local _24count = ____end` from `let $count = end`
+        let map = r#"{ "version": 3, "sources": ["main.ts"], "names": ["end", "$count", "end"],
+                       "mappings": "AAAA,MAAIA;AACJ,MAAIC,WAAQC" }"#;
+        let map = SourceMap::parse(map, Path::new("/project")).unwrap();
+        let renames = map.renamed_identifiers("local ____end = 1\nlocal _24count = ____end\n");
+        assert_eq!(renames.len(), 2);
+        assert_eq!(renames["end"], "____end");
+        assert_eq!(renames["$count"], "_24count");
+        // The mapping of `x` in MAP starts at `local`, which is no rename of it
+        assert!(SourceMap::parse(MAP, Path::new("/")).unwrap().renamed_identifiers("local x = 1\n").is_empty());
+    }
+
+    #[test]
+    fn test_decode_vlq() {
+        assert_eq!(decode_vlq("AAgBC").unwrap(), vec![0, 0, 16, 1]);
+        assert_eq!(decode_vlq("D").unwrap(), vec![-1]);
+        assert_eq!(decode_vlq("2H").unwrap(), vec![123]);
+        assert!(decode_vlq("g").is_err());
+        assert!(decode_vlq("A!").is_err());
+    }
+
+    #[test]
+    fn test_translates_both_ways() {
+        let map = SourceMap::parse(MAP, Path::new("/project/out")).unwrap();
+        let source = PathBuf::from("/project/out/src/main.ts");
+        assert_eq!(map.sources(), std::slice::from_ref(&source));
+
+        let position = map.original_position(1, 1).unwrap();
+        assert_eq!((position.line, position.column, position.name.as_deref()), (1, 7, Some("x")));
+        assert_eq!(map.original_position(2, 3).map(|p| (p.line, p.column)), Some((2, 1)));
+        assert_eq!(map.original_position(2, 20).map(|p| (p.line, p.column)), Some((4, 3)));
+        assert_eq!(map.original_position(3, 1), None);
+
+        assert_eq!(map.generated_position(&source, 4, 1), Some(GeneratedPosition { line: 2, column: 7 }));
+        assert_eq!(map.generated_position(Path::new("/project/out/src/../src/main.ts"), 2, 1).map(|p| p.line), Some(2));
+        assert_eq!(map.generated_position(&source, 3, 1), None);
+        assert_eq!(map.generated_position(Path::new("/project/other.ts"), 1, 1), None);
+
+        let mapped: Vec<(u32, u32, u32)> = map
+            .mappings_in(&source, 2, 4)
+            .into_iter()
+            .map(|(original, generated)| (original.line, original.column, generated.line))
+            .collect();
+        assert_eq!(mapped, [(2, 1, 2), (4, 3, 2)]);
+    }
+
+    #[test]
+    fn test_store_loads_inline_and_sibling_maps() {
+        let dir = tempfile::tempdir().unwrap();
+        let inline = dir.path().join("inline.lua");
+        let encoded = base64::engine::general_purpose::STANDARD.encode(MAP);
+        std::fs::write(&inline, format!("local x = 1\n--# sourceMappingURL=data:application/json;base64,{}\n", encoded))
+            .unwrap();
+        let sibling = dir.path().join("sibling.lua");
+        std::fs::write(&sibling, "local x = 1\n").unwrap();
+        std::fs::write(dir.path().join("sibling.lua.map"), MAP).unwrap();
+        let plain = dir.path().join("plain.lua");
+        std::fs::write(&plain, "print(1)\n").unwrap();
+
+        let mut store = SourceMapStore::new();
+        let original = store.to_original(&inline, 1, 1).unwrap();
+        assert_eq!(original.source, dir.path().join("src/main.ts"));
+        assert_eq!(store.to_original(&sibling, 2, 7).map(|p| p.line), Some(4));
+        assert!(store.for_lua_file(&plain).unwrap().is_none());
+
+        let (lua_file, position) = store.to_generated(&dir.path().join("src/main.ts"), 1, 1).unwrap();
+        assert!(lua_file == inline || lua_file == sibling);
+        assert_eq!(position, GeneratedPosition { line: 1, column: 1 });
+    }
+
+    #[test]
+    fn test_load_dir_finds_maps_for_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        std::fs::create_dir_all(out.join("node_modules")).unwrap();
+        std::fs::write(out.join("main.lua"), "local x = 1\n--# sourceMappingURL=main.lua.map\n").unwrap();
+        std::fs::write(out.join("main.lua.map"), MAP).unwrap();
+        std::fs::write(out.join("node_modules/dep.lua"), "--# sourceMappingURL=missing.map\n").unwrap();
+
+        let mut store = SourceMapStore::new();
+        assert_eq!(store.lua_file_for(&out.join("src/main.ts")), None);
+        store.load_dir(dir.path());
+        assert_eq!(store.lua_file_for(&out.join("src/main.ts")), Some(out.join("main.lua")));
+    }
+}
@@ -0,0 +1,311 @@
+//! Resolving the source paths of TSTL projects
+//!
+//! The paths in a source map rarely match the ones the editor sends as they
+//! are: they are relative to wherever the map was written, may carry a
+//! `sourceRoot` from another machine, or use a bundler's URL scheme. A
+//! [`PathResolver`] turns a map's source into a local path, trying in order
+//! the `sourceMapPathOverrides` globs, a `sourceRoot` override, and the map's
+//! own `sourceRoot`. It also knows from the `rootDir` and `outDir` of
+//! `tsconfig.json` which Lua file a source compiles to, so breakpoints can be
+//! placed before anything has searched for maps.
+//!
+//! Override patterns use `*` for any run of characters within a path segment
+//! and `**` for any run across segments; what they match is substituted for
+//! the wildcards of the replacement in order:
+//!
+//! ```json
+//! { "sourceMapPathOverrides": { "webpack:///src/**": "/home/me/game/src/**" } }
+//! ```
+
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Source path settings from the launch or attach arguments
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourcePathConfig {
+    /// Replaces the `sourceRoot` of every map
+    #[serde(default)]
+    pub source_root: Option<String>,
+    /// Glob rules rewriting map sources, as pattern and replacement
+    #[serde(default, deserialize_with = "longest_pattern_first")]
+    pub source_map_path_overrides: Vec<(String, String)>,
+    /// `tsconfig.json` to read `rootDir` and `outDir` from
+    #[serde(default)]
+    pub tsconfig: Option<PathBuf>,
+}
+
+/// Reads `sourceMapPathOverrides`, the longest, most specific, pattern first
+///
+/// It is an object, as in other debug adapters, so its rules have no order
+/// of their own.
+fn longest_pattern_first<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(String, String)>, D::Error> {
+    let mut overrides: Vec<(String, String)> = HashMap::<String, String>::deserialize(deserializer)?.into_iter().collect();
+    overrides.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    Ok(overrides)
+}
+
+/// A `rootDir` and the `outDir` its sources compile into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutDir {
+    pub root_dir: PathBuf,
+    pub out_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PathResolver {
+    source_root: Option<PathBuf>,
+    overrides: Vec<(String, String)>,
+    out_dirs: Vec<OutDir>,
+}
+
+impl PathResolver {
+    /// Builds a resolver, with relative paths in the config taken against `base`
+    ///
+    /// Without a `tsconfig` setting, `tsconfig.json` in `base` is read if
+    /// there is one. A tsconfig that cannot be read or parsed leaves the
+    /// resolver without an `outDir`, and the error is returned alongside.
+    pub fn new(config: &SourcePathConfig, base: &Path) -> (Self, Option<String>) {
+        let mut resolver = Self {
+            source_root: config.source_root.as_ref().map(|root| base.join(root)),
+            overrides: config.source_map_path_overrides.clone(),
+            out_dirs: Vec::new(),
+        };
+
+        let tsconfig = match &config.tsconfig {
+            Some(path) => Some(base.join(path)),
+            None => Some(base.join("tsconfig.json")).filter(|path| path.is_file()),
+        };
+        let error = tsconfig.and_then(|tsconfig| {
+            let out_dir = std::fs::read_to_string(&tsconfig)
+                .map_err(|e| e.to_string())
+                .and_then(|text| out_dir_from_tsconfig(&text, tsconfig.parent().unwrap_or(base)));
+            match out_dir {
+                Ok(out_dir) => {
+                    resolver.out_dirs.extend(out_dir);
+                    None
+                }
+                Err(e) => Some(format!("{}: {}", tsconfig.display(), e)),
+            }
+        });
+        (resolver, error)
+    }
+
+    pub fn out_dirs(&self) -> &[OutDir] {
+        &self.out_dirs
+    }
+
+    /// Turns a source as written in a map into a local path
+    ///
+    /// `map_dir` is the directory of the map, or of the Lua file it is inlined in.
+    pub fn resolve_source(&self, source: &str, source_root: Option<&str>, map_dir: &Path) -> PathBuf {
+        if let Some(rewritten) = self.overrides.iter().find_map(|(pattern, replacement)| substitute(pattern, replacement, source)) {
+            return map_dir.join(rewritten);
+        }
+        match &self.source_root {
+            Some(root) => root.join(source),
+            None => map_dir.join(source_root.unwrap_or("")).join(source),
+        }
+    }
+
+    /// The Lua file TSTL writes for a source, by its place under a `rootDir`
+    pub fn lua_file_for(&self, source: &Path) -> Option<PathBuf> {
+        self.out_dirs.iter().find_map(|dirs| {
+            let relative = source.strip_prefix(&dirs.root_dir).ok()?;
+            Some(dirs.out_dir.join(relative).with_extension("lua"))
+        })
+    }
+}
+
+/// Reads `compilerOptions.rootDir` and `outDir` from a tsconfig, resolved against its directory
+///
+/// None unless `outDir` is set; `rootDir` defaults to the tsconfig's directory.
+fn out_dir_from_tsconfig(text: &str, dir: &Path) -> Result<Option<OutDir>, String> {
+    let tsconfig: serde_json::Value = serde_json::from_str(&strip_jsonc(text)).map_err(|e| e.to_string())?;
+    let options = &tsconfig["compilerOptions"];
+    let Some(out_dir) = options["outDir"].as_str() else { return Ok(None) };
+    Ok(Some(OutDir {
+        root_dir: dir.join(options["rootDir"].as_str().unwrap_or(".")),
+        out_dir: dir.join(out_dir),
+    }))
+}
+
+/// Removes the comments and trailing commas tsconfig files allow but JSON does not
+fn strip_jsonc(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    // Position after the last comma outside a string, dropped if a closing bracket comes next
+    let mut pending_comma = None;
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            stripped.push(c);
+            match c {
+                '\\' => stripped.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('/', Some('/')) => while chars.next_if(|&c| c != '\n').is_some() {},
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = '\0';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            _ if c.is_whitespace() => stripped.push(c),
+            _ => {
+                if let Some(at) = pending_comma.take() {
+                    if c == '}' || c == ']' {
+                        stripped.remove(at - 1);
+                    }
+                }
+                stripped.push(c);
+                match c {
+                    '"' => in_string = true,
+                    ',' => pending_comma = Some(stripped.len()),
+                    _ => {}
+                }
+            }
+        }
+    }
+    stripped
+}
+
+/// Rewrites `text` with `replacement` if it matches `pattern`, wildcards carrying what they matched
+fn substitute(pattern: &str, replacement: &str, text: &str) -> Option<String> {
+    let mut captures = Vec::new();
+    if !glob_match(pattern, text, &mut captures) {
+        return None;
+    }
+
+    let mut rewritten = String::new();
+    let mut captures = captures.into_iter();
+    let mut rest = replacement;
+    while let Some(at) = rest.find('*') {
+        rewritten.push_str(&rest[..at]);
+        let wildcard = if rest[at..].starts_with("**") { 2 } else { 1 };
+        rewritten.push_str(&captures.next().unwrap_or_default());
+        rest = &rest[at + wildcard..];
+    }
+    rewritten.push_str(rest);
+    Some(rewritten)
+}
+
+/// Matches `text` against a glob, collecting what each wildcard matched
+pub(crate) fn glob_match(pattern: &str, text: &str, captures: &mut Vec<String>) -> bool {
+    let Some(at) = pattern.find('*') else { return pattern == text };
+    let Some(text_rest) = text.strip_prefix(&pattern[..at]) else { return false };
+    let across_segments = pattern[at..].starts_with("**");
+    let pattern_rest = &pattern[at + if across_segments { 2 } else { 1 }..];
+
+    // Shortest match first, so a later literal anchors as early as it can
+    for (end, _) in text_rest.char_indices().chain([(text_rest.len(), ' ')]) {
+        let candidate = &text_rest[..end];
+        if !across_segments && candidate.contains('/') {
+            break;
+        }
+        let mut rest_captures = Vec::new();
+        if glob_match(pattern_rest, &text_rest[end..], &mut rest_captures) {
+            captures.push(candidate.to_string());
+            captures.extend(rest_captures);
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_substitute_wildcards() {
+        assert_eq!(
+            substitute("webpack:///src/**", "/game/src/**", "webpack:///src/ui/menu.ts").as_deref(),
+            Some("/game/src/ui/menu.ts")
+        );
+        assert_eq!(substitute("*/lib/*.ts", "lib/*/*.ts", "pkg/lib/util.ts").as_deref(), Some("lib/pkg/util.ts"));
+        assert_eq!(substitute("*.ts", "*.ts", "ui/menu.ts"), None);
+        assert_eq!(substitute("exact.ts", "other.ts", "exact.ts").as_deref(), Some("other.ts"));
+    }
+
+    #[test]
+    fn test_overrides_are_read_longest_first() {
+        let arguments = serde_json::json!({
+            "sourceRoot": "src",
+            "sourceMapPathOverrides": { "webpack:///*": "/a/*", "webpack:///src/**": "/b/**" }
+        });
+        let config = SourcePathConfig::deserialize(&arguments).unwrap();
+        assert_eq!(config.source_root.as_deref(), Some("src"));
+        assert_eq!(config.source_map_path_overrides[0].0, "webpack:///src/**");
+
+        let arguments = serde_json::json!({ "sourceMapPathOverrides": { "webpack:///*": 3 } });
+        assert!(SourcePathConfig::deserialize(&arguments).is_err());
+    }
+
+    #[test]
+    fn test_resolve_source_order() {
+        let map_dir = Path::new("/game/dist");
+        let config = SourcePathConfig {
+            source_map_path_overrides: vec![("webpack:///**".to_string(), "/game/src/**".to_string())],
+            ..Default::default()
+        };
+        let (resolver, error) = PathResolver::new(&config, Path::new("/nowhere"));
+        assert_eq!(error, None);
+        assert_eq!(resolver.resolve_source("webpack:///a.ts", None, map_dir), PathBuf::from("/game/src/a.ts"));
+        assert_eq!(resolver.resolve_source("a.ts", Some("../src"), map_dir), PathBuf::from("/game/dist/../src/a.ts"));
+
+        let config = SourcePathConfig {
+            source_root: Some("src".to_string()),
+            ..Default::default()
+        };
+        let (resolver, _) = PathResolver::new(&config, Path::new("/game"));
+        assert_eq!(resolver.resolve_source("a.ts", Some("/elsewhere"), map_dir), PathBuf::from("/game/src/a.ts"));
+    }
+
+    #[test]
+    fn test_tsconfig_out_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("tsconfig.json"),
+            r#"{
+                // TSTL settings
+                "compilerOptions": { "rootDir": "src", "outDir": "dist", /* built here */ },
+                "tstl": { "luaTarget": "5.4" },
+            }"#,
+        )
+        .unwrap();
+
+        let (resolver, error) = PathResolver::new(&SourcePathConfig::default(), dir.path());
+        assert_eq!(error, None);
+        assert_eq!(
+            resolver.lua_file_for(&dir.path().join("src/ui/menu.ts")),
+            Some(dir.path().join("dist/ui/menu.lua"))
+        );
+        assert_eq!(resolver.lua_file_for(&dir.path().join("other/menu.ts")), None);
+
+        let config = SourcePathConfig {
+            tsconfig: Some(PathBuf::from("missing.json")),
+            ..Default::default()
+        };
+        let (resolver, error) = PathResolver::new(&config, dir.path());
+        assert!(error.unwrap().contains("missing.json"));
+        assert!(resolver.out_dirs().is_empty());
+    }
+
+    #[test]
+    fn test_strip_jsonc_keeps_strings() {
+        let text = r#"{ "url": "http://x/*y*/", "a": [1, 2,], }"#;
+        let value: serde_json::Value = serde_json::from_str(&strip_jsonc(text)).unwrap();
+        assert_eq!(value["url"], "http://x/*y*/");
+        assert_eq!(value["a"], serde_json::json!([1, 2]));
+    }
+}
@@ -0,0 +1,295 @@
+//! Record and replay of a launched program, for stepping backwards
+//!
+//! Lua cannot undo what a program did, so going back means running it again.
+//! While recording, the runtime numbers every line the program executes and
+//! logs what the program read from outside: the results of `os.time`,
+//! `os.clock`, `math.random` and `io.read`. To go back, it starts the program
+//! over in a fresh state, hands it the logged inputs instead of calling the
+//! real functions, and stops when the line count reaches the wanted position.
+//!
+//! Every so often the recording keeps a checkpoint: a rendering of the
+//! globals at that position. A replay compares the globals against it on its
+//! way past, so a program that does not run the same way twice, for instance
+//! because it reads a file that changed, is reported instead of silently
+//! showing a different past.
+
+use std::collections::VecDeque;
+
+/// Executed lines kept for finding breakpoints to reverse continue to
+pub const DEFAULT_LINE_CAPACITY: usize = 100_000;
+
+/// Lines between checkpoints
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 10_000;
+
+/// A value the program read from outside, as one of the logged functions returned it
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Vec<u8>),
+}
+
+/// A line the program executed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineRecord {
+    /// Lines executed before this one, plus one
+    pub position: u64,
+    pub source: String,
+    pub line: u32,
+}
+
+/// The globals at a position, each rendered so that separate runs render them alike
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub position: u64,
+    pub globals: Vec<(String, String)>,
+}
+
+/// Where the program is relative to the position a replay runs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// Not replaying; the program runs and stops as usual
+    Live,
+    /// Replaying lines that were already recorded; nothing may stop the program
+    Replaying,
+    /// The replay reached its position; the program stops with this reason
+    Arrived { reason: &'static str },
+}
+
+/// Line history, input log and checkpoints of one program run
+#[derive(Debug)]
+pub struct Recording {
+    line_capacity: usize,
+    checkpoint_interval: u64,
+    /// Lines executed so far in this run
+    position: u64,
+    lines: VecDeque<LineRecord>,
+    inputs: Vec<Vec<InputValue>>,
+    /// Logged inputs handed back so far in this run
+    inputs_used: usize,
+    checkpoints: Vec<Checkpoint>,
+    /// Position a replay stops at, and the reason it reports
+    target: Option<(u64, &'static str)>,
+}
+
+impl Recording {
+    pub fn new(line_capacity: usize, checkpoint_interval: u64) -> Self {
+        Self {
+            line_capacity,
+            checkpoint_interval,
+            position: 0,
+            lines: VecDeque::new(),
+            inputs: Vec::new(),
+            inputs_used: 0,
+            checkpoints: Vec::new(),
+            target: None,
+        }
+    }
+
+    /// Lines executed so far in this run
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Whether the program is re-running lines it already ran
+    pub fn is_replaying(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Counts a line the program is about to execute
+    ///
+    /// Lines past the end of the history are added to it. Reaching the
+    /// position a replay runs to ends the replay: the inputs logged after it
+    /// belong to a future the program may now take differently, so they are
+    /// dropped and the functions are called for real again.
+    pub fn advance(&mut self, source: &str, line: u32) -> Progress {
+        self.position += 1;
+        if self.lines.back().is_none_or(|last| last.position < self.position) {
+            if self.lines.len() == self.line_capacity {
+                self.lines.pop_front();
+            }
+            if self.line_capacity > 0 {
+                self.lines.push_back(LineRecord {
+                    position: self.position,
+                    source: source.to_string(),
+                    line,
+                });
+            }
+        }
+
+        match self.target {
+            Some((target, reason)) if target == self.position => {
+                self.target = None;
+                self.inputs.truncate(self.inputs_used);
+                Progress::Arrived { reason }
+            }
+            Some(_) => Progress::Replaying,
+            None => Progress::Live,
+        }
+    }
+
+    /// Whether a checkpoint belongs at the current position
+    pub fn checkpoint_due(&self) -> bool {
+        self.checkpoint_interval > 0 && self.position > 0 && self.position.is_multiple_of(self.checkpoint_interval)
+    }
+
+    /// Keeps the globals as a checkpoint, or compares them with the one kept here before
+    ///
+    /// Returns the names of the globals that differ from an earlier run.
+    pub fn checkpoint(&mut self, globals: Vec<(String, String)>) -> Vec<String> {
+        let Some(earlier) = self.checkpoints.iter().find(|c| c.position == self.position) else {
+            self.checkpoints.push(Checkpoint {
+                position: self.position,
+                globals,
+            });
+            return Vec::new();
+        };
+
+        let mut changed: Vec<String> = globals
+            .iter()
+            .filter(|entry| !earlier.globals.contains(entry))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for (name, _) in &earlier.globals {
+            if !globals.iter().any(|(other, _)| other == name) {
+                changed.push(name.clone());
+            }
+        }
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+
+    /// The next logged input, while the run has not used them all up
+    pub fn replay_input(&mut self) -> Option<Vec<InputValue>> {
+        let input = self.inputs.get(self.inputs_used)?.clone();
+        self.inputs_used += 1;
+        Some(input)
+    }
+
+    /// Logs what a function reading from outside returned
+    pub fn record_input(&mut self, values: Vec<InputValue>) {
+        self.inputs.push(values);
+        self.inputs_used = self.inputs.len();
+    }
+
+    /// Whether logged inputs remain for the run to use
+    pub fn has_logged_input(&self) -> bool {
+        self.inputs_used < self.inputs.len()
+    }
+
+    /// Position of the line executed before the one at `current`
+    pub fn step_back_target(&self, current: u64) -> Result<u64, String> {
+        if current <= 1 {
+            return Err("The program is at the start of the recording".to_string());
+        }
+        Ok(current - 1)
+    }
+
+    /// Position of the last line before `current` that `is_breakpoint` accepts
+    ///
+    /// Without one, the oldest line still in the history, which is the first
+    /// line of the program unless the history has overflowed.
+    pub fn reverse_continue_target(
+        &self,
+        current: u64,
+        is_breakpoint: impl Fn(&str, u32) -> bool,
+    ) -> Result<(u64, &'static str), String> {
+        let mut earlier = self.lines.iter().rev().filter(|record| record.position < current);
+        if let Some(record) = earlier.clone().find(|record| is_breakpoint(&record.source, record.line)) {
+            return Ok((record.position, "breakpoint"));
+        }
+        match earlier.next_back() {
+            Some(first) => Ok((first.position, if first.position == 1 { "entry" } else { "step" })),
+            None => Err("The program is at the start of the recording".to_string()),
+        }
+    }
+
+    /// Prepares the recording for a new run that replays up to `target`
+    ///
+    /// What was recorded after `target` is forgotten, as the program may go
+    /// elsewhere from there.
+    pub fn rewind(&mut self, target: u64, reason: &'static str) {
+        self.lines.retain(|record| record.position <= target);
+        self.checkpoints.retain(|checkpoint| checkpoint.position <= target);
+        self.position = 0;
+        self.inputs_used = 0;
+        self.target = Some((target, reason));
+    }
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Self::new(DEFAULT_LINE_CAPACITY, DEFAULT_CHECKPOINT_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(recording: &mut Recording, lines: &[u32]) -> Vec<Progress> {
+        lines.iter().map(|&line| recording.advance("@main.lua", line)).collect()
+    }
+
+    #[test]
+    fn test_replay_stops_at_target_and_forks_inputs() {
+        let mut recording = Recording::default();
+        run(&mut recording, &[1, 2]);
+        recording.record_input(vec![InputValue::Integer(4)]);
+        run(&mut recording, &[3]);
+        recording.record_input(vec![InputValue::Number(0.5)]);
+        run(&mut recording, &[4]);
+
+        let target = recording.step_back_target(recording.position()).unwrap();
+        assert_eq!(target, 3);
+        recording.rewind(target, "step");
+        assert_eq!(run(&mut recording, &[1, 2]), vec![Progress::Replaying, Progress::Replaying]);
+        assert_eq!(recording.replay_input(), Some(vec![InputValue::Integer(4)]));
+        assert_eq!(run(&mut recording, &[3]), vec![Progress::Arrived { reason: "step" }]);
+
+        // The input read after line 3 is for the program to read again
+        assert!(!recording.has_logged_input());
+        assert_eq!(run(&mut recording, &[4]), vec![Progress::Live]);
+    }
+
+    #[test]
+    fn test_reverse_continue_finds_previous_breakpoint() {
+        let mut recording = Recording::default();
+        run(&mut recording, &[1, 5, 6, 5, 6, 7]);
+
+        let target = recording.reverse_continue_target(6, |_, line| line == 5).unwrap();
+        assert_eq!(target, (4, "breakpoint"));
+        let target = recording.reverse_continue_target(2, |_, line| line == 5).unwrap();
+        assert_eq!(target, (1, "entry"));
+        assert!(recording.reverse_continue_target(1, |_, _| true).is_err());
+        assert!(recording.step_back_target(1).is_err());
+    }
+
+    #[test]
+    fn test_history_keeps_most_recent_lines() {
+        let mut recording = Recording::new(2, 0);
+        run(&mut recording, &[1, 2, 3]);
+
+        let target = recording.reverse_continue_target(3, |_, _| false).unwrap();
+        assert_eq!(target, (2, "step"));
+        assert!(!recording.checkpoint_due());
+    }
+
+    #[test]
+    fn test_checkpoint_reports_diverged_globals() {
+        let mut recording = Recording::new(10, 2);
+        run(&mut recording, &[1, 2]);
+        assert!(recording.checkpoint_due());
+        let globals = vec![("score".to_string(), "1".to_string()), ("name".to_string(), "\"a\"".to_string())];
+        assert!(recording.checkpoint(globals.clone()).is_empty());
+
+        recording.rewind(2, "step");
+        run(&mut recording, &[1, 2]);
+        assert!(recording.checkpoint(globals).is_empty());
+        let changed = recording.checkpoint(vec![("score".to_string(), "2".to_string())]);
+        assert_eq!(changed, vec!["name".to_string(), "score".to_string()]);
+    }
+}
@@ -0,0 +1,279 @@
+//! Rewriting TypeScript expressions typed in the debug console as Lua
+//!
+//! In code compiled with TypeScriptToLua, the console is evaluated as Lua but
+//! users type what they see in the editor. Common TypeScript syntax is
+//! rewritten to what TSTL would have generated for it:
+//!
+//! - `this` becomes `self`, and `null` and `undefined` become `nil`
+//! - `a?.b` becomes `(a and a.b)`, for any chain of `?.` accesses and calls
+//! - `===`, `!==`, `&&`, `||` and `!` become `==`, `~=`, `and`, `or` and `not`
+//! - `list[0]` becomes `list[1]`, as TSTL arrays start at 1
+//! - `a.end` becomes `a["end"]` for properties named like Lua keywords
+//! - identifiers TSTL renamed, such as `end` to `____end`, take their Lua name
+//!
+//! Anything else is left as written; an expression that then fails to
+//! evaluate is evaluated again as typed.
+
+use super::completions::KEYWORDS;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Identifier(String),
+    /// Numbers, strings and whitespace, copied as they are
+    Verbatim(String),
+    Punctuation(&'static str),
+    /// A character the tokenizer does not know, copied as it is
+    Other(char),
+}
+
+/// Operators and punctuation, longer ones first so each matches whole
+const OPERATORS: &[&str] = &[
+    "===", "!==", "...", "?.", "??", "&&", "||", "==", "!=", "<=", ">=", "..", "!", ".", "(", ")", "[", "]", "{",
+    "}", ",", ":", ";", "+", "-", "*", "/", "%", "^", "<", ">", "=", "#", "~", "?", "&", "|",
+];
+
+/// Rewrites a TypeScript expression as Lua, None if nothing needed rewriting
+///
+/// `renames` maps TypeScript identifiers to the names TSTL gave them, see
+/// [`SourceMap::renamed_identifiers`](super::source_maps::SourceMap::renamed_identifiers).
+pub fn translate(expression: &str, renames: &HashMap<String, String>) -> Option<String> {
+    let tokens = tokenize(expression)?;
+    let translated = Translator { renames }.sequence(&tokens);
+    (translated != expression).then_some(translated)
+}
+
+/// Splits an expression into tokens, None if a string is left open
+fn tokenize(expression: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = expression;
+    while let Some(c) = rest.chars().next() {
+        let length = if is_identifier_start(c) {
+            let length = rest.find(|c: char| !is_identifier_char(c)).unwrap_or(rest.len());
+            tokens.push(Token::Identifier(rest[..length].to_string()));
+            length
+        } else if c.is_ascii_digit() || (c == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) {
+            let length = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            // TypeScript allows `_` between digits, Lua does not
+            tokens.push(Token::Verbatim(rest[..length].replace('_', "")));
+            length
+        } else if c == '"' || c == '\'' || c == '`' {
+            let length = string_length(rest, c)?;
+            tokens.push(Token::Verbatim(rest[..length].to_string()));
+            length
+        } else if c.is_whitespace() {
+            let length = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+            tokens.push(Token::Verbatim(rest[..length].to_string()));
+            length
+        } else if let Some(operator) = OPERATORS.iter().find(|operator| rest.starts_with(*operator)) {
+            // `a?.5:b` is a conditional, not an optional access
+            if *operator == "?." && rest[2..].starts_with(|c: char| c.is_ascii_digit()) {
+                tokens.push(Token::Punctuation("?"));
+                1
+            } else {
+                tokens.push(Token::Punctuation(operator));
+                operator.len()
+            }
+        } else {
+            tokens.push(Token::Other(c));
+            c.len_utf8()
+        };
+        rest = &rest[length..];
+    }
+    Some(tokens)
+}
+
+/// Length in bytes of the string literal `text` starts with, quotes included
+fn string_length(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (at, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == quote => return Some(at + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '$'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '$'
+}
+
+struct Translator<'a> {
+    renames: &'a HashMap<String, String>,
+}
+
+impl Translator<'_> {
+    fn sequence(&self, tokens: &[Token]) -> String {
+        let mut out = String::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            match &tokens[i] {
+                Token::Identifier(_) | Token::Punctuation("(") => {
+                    let (chain, next) = self.chain(tokens, i);
+                    out.push_str(&chain);
+                    i = next;
+                    continue;
+                }
+                Token::Punctuation(operator) => match *operator {
+                    "===" => out.push_str("=="),
+                    "!==" | "!=" => out.push_str("~="),
+                    "&&" => push_word(&mut out, "and", tokens.get(i + 1)),
+                    "||" => push_word(&mut out, "or", tokens.get(i + 1)),
+                    "!" => push_word(&mut out, "not", tokens.get(i + 1)),
+                    operator => out.push_str(operator),
+                },
+                Token::Verbatim(text) => out.push_str(text),
+                Token::Other(c) => out.push(*c),
+            }
+            i += 1;
+        }
+        out
+    }
+
+    /// Rewrites the access chain starting at `start`, returning it and the index after it
+    fn chain(&self, tokens: &[Token], start: usize) -> (String, usize) {
+        let (mut current, mut i) = match &tokens[start] {
+            Token::Identifier(name) => (self.identifier(name), start + 1),
+            _ => match self.group(tokens, start) {
+                Some((group, next)) => (group, next),
+                None => return ("(".to_string(), start + 1),
+            },
+        };
+
+        // What comes before each `?.`, all of which must be set for the chain to go on
+        let mut guards = Vec::new();
+        loop {
+            match (tokens.get(i), tokens.get(i + 1)) {
+                (Some(Token::Punctuation(access @ ("." | "?."))), Some(Token::Identifier(property))) => {
+                    if *access == "?." {
+                        guards.push(current.clone());
+                    }
+                    if KEYWORDS.contains(&property.as_str()) {
+                        current.push_str(&format!("[\"{}\"]", property));
+                    } else {
+                        current.push('.');
+                        current.push_str(property);
+                    }
+                    i += 2;
+                }
+                (Some(Token::Punctuation("?.")), Some(Token::Punctuation("[" | "("))) => {
+                    guards.push(current.clone());
+                    i += 1;
+                }
+                (Some(Token::Punctuation("[")), _) => {
+                    let Some(end) = closing(tokens, i) else { break };
+                    let index = self.sequence(&tokens[i + 1..end]);
+                    match index.trim().parse::<u64>() {
+                        Ok(index) => current.push_str(&format!("[{}]", index + 1)),
+                        Err(_) => current.push_str(&format!("[{}]", index)),
+                    }
+                    i = end + 1;
+                }
+                (Some(Token::Punctuation("(")), _) => {
+                    let Some((group, next)) = self.group(tokens, i) else { break };
+                    current.push_str(&group);
+                    i = next;
+                }
+                _ => break,
+            }
+        }
+
+        if guards.is_empty() {
+            (current, i)
+        } else {
+            guards.push(current);
+            (format!("({})", guards.join(" and ")), i)
+        }
+    }
+
+    /// Rewrites the parenthesized tokens at `start`, returning them and the index after them
+    fn group(&self, tokens: &[Token], start: usize) -> Option<(String, usize)> {
+        let end = closing(tokens, start)?;
+        Some((format!("({})", self.sequence(&tokens[start + 1..end])), end + 1))
+    }
+
+    fn identifier(&self, name: &str) -> String {
+        match name {
+            "this" => "self".to_string(),
+            "null" | "undefined" => "nil".to_string(),
+            _ => self.renames.get(name).cloned().unwrap_or_else(|| name.to_string()),
+        }
+    }
+}
+
+/// Index of the bracket closing the one at `open`
+fn closing(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token {
+            Token::Punctuation("(" | "[" | "{") => depth += 1,
+            Token::Punctuation(")" | "]" | "}") => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Appends a word operator, spacing it from the tokens around it
+fn push_word(out: &mut String, word: &str, next: Option<&Token>) {
+    if !out.is_empty() && !out.ends_with(char::is_whitespace) {
+        out.push(' ');
+    }
+    out.push_str(word);
+    if !matches!(next, Some(Token::Verbatim(text)) if text.starts_with(char::is_whitespace)) {
+        out.push(' ');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(expression: &str) -> String {
+        translate(expression, &HashMap::new()).unwrap_or_else(|| expression.to_string())
+    }
+
+    #[test]
+    fn test_rewrites_typescript_syntax() {
+        assert_eq!(rewrite("this.health"), "self.health");
+        assert_eq!(rewrite("x === null || !done"), "x == nil or not done");
+        assert_eq!(rewrite("a!==b&&c"), "a~=b and c");
+        assert_eq!(rewrite("items[0].name"), "items[1].name");
+        assert_eq!(rewrite("items[i]"), "items[i]");
+        assert_eq!(rewrite("state.end"), "state[\"end\"]");
+        assert_eq!(rewrite("1_000 + .5"), "1000 + .5");
+        assert_eq!(rewrite("ok ? 1 : 2"), "ok ? 1 : 2");
+    }
+
+    #[test]
+    fn test_rewrites_optional_chains() {
+        assert_eq!(rewrite("player?.pos"), "(player and player.pos)");
+        assert_eq!(rewrite("this.target?.pos.x"), "(self.target and self.target.pos.x)");
+        assert_eq!(rewrite("a?.b?.c + 1"), "(a and a.b and a.b.c) + 1");
+        assert_eq!(rewrite("list?.[0]"), "(list and list[1])");
+        assert_eq!(rewrite("f(a?.b)"), "f((a and a.b))");
+    }
+
+    #[test]
+    fn test_renames_and_untouched_text() {
+        let renames = HashMap::from([("end".to_string(), "____end".to_string()), ("$el".to_string(), "_24el".to_string())]);
+        assert_eq!(translate("end + $el.end", &renames).as_deref(), Some("____end + _24el[\"end\"]"));
+        assert_eq!(translate("'this === that'", &renames), None);
+        assert_eq!(translate("player.hp + 1", &renames), None);
+        assert_eq!(translate("'open", &renames), None);
+    }
+}
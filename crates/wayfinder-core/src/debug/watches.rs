@@ -0,0 +1,189 @@
+//! Watch expressions re-evaluated at every stop
+//!
+//! Unlike data breakpoints, watches never stop the program. The session
+//! evaluates every watch when the program stops and keeps the value from the
+//! stop before, so evaluate responses in the `watch` context can tell the
+//! client which watches changed and the watch pane can highlight them.
+
+use serde::{Deserialize, Serialize};
+
+/// How a watch's value compares with its value at the previous stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WatchStatus {
+    /// First evaluated at this stop
+    New,
+    Changed,
+    Unchanged,
+}
+
+/// A watch expression and its values at the current and previous stops
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Watch {
+    pub id: i64,
+    pub expression: String,
+    /// Rendered value at the stop it was last evaluated at, or the error
+    pub value: Option<String>,
+    /// Rendered value at the stop before that
+    pub previous_value: Option<String>,
+    /// Stop the value was evaluated at
+    #[serde(skip)]
+    evaluated_at: u64,
+}
+
+impl Watch {
+    pub fn status(&self) -> WatchStatus {
+        match &self.previous_value {
+            None => WatchStatus::New,
+            Some(previous) if Some(previous) == self.value.as_ref() => WatchStatus::Unchanged,
+            Some(_) => WatchStatus::Changed,
+        }
+    }
+}
+
+/// Manages the watch expressions of a debugging session
+#[derive(Debug, Clone)]
+pub struct WatchManager {
+    /// Watches in the order they were added
+    watches: Vec<Watch>,
+    /// Next ID to assign to a watch
+    next_id: i64,
+    /// Number of stops seen; 0 until the program first stops
+    stop: u64,
+    /// Thread of the latest stop, whose top frame watches are evaluated in
+    stopped_thread: Option<u64>,
+}
+
+impl WatchManager {
+    pub fn new() -> Self {
+        Self {
+            watches: Vec::new(),
+            next_id: 1,
+            stop: 0,
+            stopped_thread: None,
+        }
+    }
+
+    /// Adds a watch, or returns the id of the existing one for the same expression
+    pub fn add(&mut self, expression: &str) -> i64 {
+        if let Some(watch) = self.find(expression) {
+            return watch.id;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.watches.push(Watch {
+            id,
+            expression: expression.to_string(),
+            value: None,
+            previous_value: None,
+            evaluated_at: 0,
+        });
+        id
+    }
+
+    /// Removes a watch by expression
+    pub fn remove(&mut self, expression: &str) -> bool {
+        let count = self.watches.len();
+        self.watches.retain(|watch| watch.expression != expression);
+        self.watches.len() != count
+    }
+
+    pub fn find(&self, expression: &str) -> Option<&Watch> {
+        self.watches.iter().find(|watch| watch.expression == expression)
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    /// Notes that the program stopped, making every watch due for evaluation
+    pub fn on_stop(&mut self, thread_id: Option<u64>) {
+        self.stop += 1;
+        self.stopped_thread = thread_id;
+    }
+
+    pub fn stopped_thread(&self) -> Option<u64> {
+        self.stopped_thread
+    }
+
+    /// Expressions not yet evaluated at the current stop
+    pub fn stale(&self) -> Vec<String> {
+        if self.stop == 0 {
+            return Vec::new();
+        }
+        self.watches
+            .iter()
+            .filter(|watch| watch.evaluated_at != self.stop)
+            .map(|watch| watch.expression.clone())
+            .collect()
+    }
+
+    /// Records a watch's value at the current stop, adding the watch if needed
+    ///
+    /// The first value recorded at a stop moves the one from the previous stop
+    /// aside; later ones at the same stop, say for another frame, replace it.
+    pub fn record(&mut self, expression: &str, value: String) -> &Watch {
+        self.add(expression);
+        let stop = self.stop;
+        let watch = self
+            .watches
+            .iter_mut()
+            .find(|watch| watch.expression == expression)
+            .expect("watch was just added");
+        if watch.evaluated_at != stop {
+            watch.previous_value = watch.value.take();
+            watch.evaluated_at = stop;
+        }
+        watch.value = Some(value);
+        watch
+    }
+
+    pub fn clear(&mut self) {
+        self.watches.clear();
+    }
+}
+
+impl Default for WatchManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_compares_with_previous_stop() {
+        let mut watches = WatchManager::new();
+        assert_eq!(watches.add("player.hp"), watches.add("player.hp"));
+
+        watches.on_stop(Some(1));
+        assert_eq!(watches.stale(), vec!["player.hp"]);
+        assert_eq!(watches.record("player.hp", "10".to_string()).status(), WatchStatus::New);
+        assert!(watches.stale().is_empty());
+
+        watches.on_stop(Some(1));
+        assert_eq!(watches.record("player.hp", "10".to_string()).status(), WatchStatus::Unchanged);
+
+        watches.on_stop(Some(1));
+        let watch = watches.record("player.hp", "7".to_string());
+        assert_eq!(watch.status(), WatchStatus::Changed);
+        assert_eq!(watch.previous_value.as_deref(), Some("10"));
+
+        // Evaluating again at the same stop keeps comparing with the stop before
+        let watch = watches.record("player.hp", "8".to_string());
+        assert_eq!((watch.status(), watch.previous_value.as_deref()), (WatchStatus::Changed, Some("10")));
+    }
+
+    #[test]
+    fn test_remove_watch() {
+        let mut watches = WatchManager::new();
+        watches.add("a");
+        watches.add("b");
+        assert!(watches.remove("a"));
+        assert!(!watches.remove("a"));
+        assert_eq!(watches.watches().len(), 1);
+    }
+}
@@ -63,6 +63,235 @@ pub enum AccessType {
     ReadWrite,
 }
 
+impl DataType {
+    /// Reads what a DAP `dataId` watches, and the variable's name
+    ///
+    /// `global:NAME` and `upvalue:NAME` watch a global, or an upvalue of the
+    /// running function; `local:NAME` or a plain name a local of it.
+    pub fn from_data_id(data_id: &str) -> (Self, &str) {
+        match data_id.split_once(':') {
+            Some(("global", name)) => (DataType::Global, name),
+            Some(("upvalue", name)) => (DataType::Upvalue, name),
+            Some(("local", name)) => (DataType::Local, name),
+            _ => (DataType::Local, data_id),
+        }
+    }
+}
+
+impl AccessType {
+    /// Reads a DAP `accessType`, which defaults to watching writes
+    pub fn from_dap(access_type: Option<&str>) -> Option<Self> {
+        match access_type {
+            None | Some("write") => Some(AccessType::Write),
+            Some("read") => Some(AccessType::Read),
+            Some("readWrite") => Some(AccessType::ReadWrite),
+            Some(_) => None,
+        }
+    }
+
+    pub fn as_dap(&self) -> &'static str {
+        match self {
+            AccessType::Read => "read",
+            AccessType::Write => "write",
+            AccessType::ReadWrite => "readWrite",
+        }
+    }
+
+    /// Whether a watch for this access type triggers on `access`
+    pub fn includes(&self, access: &AccessType) -> bool {
+        self == access || *self == AccessType::ReadWrite
+    }
+}
+
+/// A data breakpoint the running program triggered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataBreakpointHit {
+    pub id: i64,
+    pub name: String,
+    /// `Write` when the value changed, `Read` when the next line reads it
+    pub access: AccessType,
+    /// Value before the change; for a read, the value read
+    pub old_value: String,
+    pub new_value: String,
+}
+
+impl DataBreakpointHit {
+    /// What happened, as shown to the user
+    pub fn description(&self) -> String {
+        match self.access {
+            AccessType::Read => format!("{} is read ({})", self.name, self.new_value),
+            _ => format!("{} changed from {} to {}", self.name, self.old_value, self.new_value),
+        }
+    }
+
+    /// The hit as reported in the `dataBreakpoint` field of a stopped event
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "name": self.name,
+            "accessType": self.access.as_dap(),
+            "oldValue": self.old_value,
+            "newValue": self.new_value,
+        })
+    }
+}
+
+/// How a line of Lua source uses a variable
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineAccess {
+    pub reads: bool,
+    pub writes: bool,
+}
+
+/// Finds the reads and writes of a variable on one line of Lua source
+///
+/// A scan of the line rather than a parse: strings and comments are
+/// skipped, fields such as `t.name` and table constructor keys are not the
+/// variable, and an occurrence is a write when it is a target of the line's
+/// assignment, declaration or `for` loop. Only the first statement of a line
+/// is looked at for targets.
+pub fn line_accesses(code: &str, name: &str) -> LineAccess {
+    let tokens = tokenize(code);
+
+    // Nesting inside brackets at each token, and the end of the targets at the top level
+    let mut depths = Vec::with_capacity(tokens.len());
+    let mut depth = 0usize;
+    let mut assignment = None;
+    let mut loop_in = None;
+    for (index, token) in tokens.iter().enumerate() {
+        if matches!(token, Token::Symbol(")" | "]" | "}")) {
+            depth = depth.saturating_sub(1);
+        }
+        depths.push(depth);
+        match token {
+            Token::Symbol("(" | "[" | "{") => depth += 1,
+            Token::Assign if depth == 0 && assignment.is_none() => assignment = Some(index),
+            Token::Name("in") if depth == 0 && loop_in.is_none() => loop_in = Some(index),
+            _ => {}
+        }
+    }
+    let targets_end = match tokens.first() {
+        Some(Token::Name("local" | "for")) => Some(assignment.or(loop_in).unwrap_or(tokens.len())),
+        _ => assignment,
+    };
+
+    let mut access = LineAccess::default();
+    for (index, token) in tokens.iter().enumerate() {
+        if *token != Token::Name(name) {
+            continue;
+        }
+        let previous = index.checked_sub(1).map(|i| tokens[i]);
+        let next = tokens.get(index + 1).copied();
+        if matches!(previous, Some(Token::Symbol("." | ":" | "::"))) {
+            continue;
+        }
+        // A key in a table constructor, `{ name = 1 }`
+        if depths[index] > 0 && next == Some(Token::Assign) {
+            continue;
+        }
+
+        let whole_variable = !matches!(next, Some(Token::Symbol("." | ":" | "[" | "(")));
+        let is_target = depths[index] == 0 && targets_end.is_some_and(|end| index < end);
+        let is_function_name = previous == Some(Token::Name("function"));
+        if whole_variable && (is_target || is_function_name) {
+            access.writes = true;
+        } else {
+            access.reads = true;
+        }
+    }
+    access
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Name(&'a str),
+    /// A lone `=`
+    Assign,
+    /// Any other operator or punctuation, such as `==` or `.`
+    Symbol(&'a str),
+}
+
+/// Splits a line of Lua into names and symbols, dropping literals and comments
+fn tokenize(code: &str) -> Vec<Token<'_>> {
+    let bytes = code.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'_' || c.is_ascii_alphabetic() {
+            let start = i;
+            while i < bytes.len() && (bytes[i] == b'_' || bytes[i].is_ascii_alphanumeric()) {
+                i += 1;
+            }
+            tokens.push(Token::Name(&code[start..i]));
+            continue;
+        }
+        if c.is_ascii_digit() {
+            // Hex digits and exponents are part of the number, never names
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                i += 1;
+            }
+            continue;
+        }
+
+        match c {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => match long_bracket_end(bytes, i + 2) {
+                Some(end) => i = end,
+                None => break,
+            },
+            b'"' | b'\'' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != c {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i += 1;
+            }
+            b'[' => match long_bracket_end(bytes, i) {
+                Some(end) => i = end,
+                None => {
+                    tokens.push(Token::Symbol("["));
+                    i += 1;
+                }
+            },
+            _ if !c.is_ascii() || c.is_ascii_whitespace() => i += 1,
+            _ => {
+                let length = ["...", "==", "~=", "<=", ">=", "..", "::", "//", "<<", ">>"]
+                    .iter()
+                    .find(|op| code[i..].starts_with(**op))
+                    .map_or(1, |op| op.len());
+                let symbol = &code[i..i + length];
+                tokens.push(if symbol == "=" { Token::Assign } else { Token::Symbol(symbol) });
+                i += length;
+            }
+        }
+    }
+    tokens
+}
+
+/// Where a long bracket such as `[[` or `[==[` opening at `start` closes
+///
+/// The end of the line if it does not close on it; None if no long bracket opens there.
+fn long_bracket_end(bytes: &[u8], start: usize) -> Option<usize> {
+    if bytes.get(start) != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[start + 1..].iter().take_while(|&&b| b == b'=').count();
+    if bytes.get(start + 1 + level) != Some(&b'[') {
+        return None;
+    }
+
+    let mut close = vec![b']'];
+    close.extend(std::iter::repeat_n(b'=', level));
+    close.push(b']');
+    let body = start + level + 2;
+    Some(
+        bytes[body..]
+            .windows(close.len())
+            .position(|window| window == close.as_slice())
+            .map_or(bytes.len(), |position| body + position + close.len()),
+    )
+}
+
 /// Manages all watchpoints for a debugging session
 #[derive(Debug, Clone)]
 pub struct WatchpointManager {
@@ -159,6 +388,13 @@ impl WatchpointManager {
         }
     }
 
+    /// Forgets the previous value of a data breakpoint, so the next one seen is not a change
+    pub fn forget_data_breakpoint_previous_value(&mut self, id: i64) {
+        if let Some(bp) = self.data_breakpoints.get_mut(&id) {
+            bp.previous_value = None;
+        }
+    }
+
     /// Gets the previous value for a data breakpoint
     pub fn get_data_breakpoint_previous_value(&self, id: i64) -> Option<&String> {
         self.data_breakpoints
@@ -205,6 +441,7 @@ mod tests {
             hit_count: 0,
             data_type: DataType::Local,
             access_type: AccessType::ReadWrite,
+            previous_value: None,
         }];
 
         let result = manager.set_data_breakpoints(breakpoints);
@@ -233,6 +470,7 @@ mod tests {
             hit_count: 0,
             data_type: DataType::Local,
             access_type: AccessType::ReadWrite,
+            previous_value: None,
         }];
 
         let result = manager.set_data_breakpoints(breakpoints);
@@ -259,6 +497,7 @@ mod tests {
             hit_count: 0,
             data_type: DataType::Local,
             access_type: AccessType::ReadWrite,
+            previous_value: None,
         }];
 
         manager.set_data_breakpoints(breakpoints);
@@ -282,6 +521,7 @@ mod tests {
             hit_count: 0,
             data_type: DataType::Local,
             access_type: AccessType::ReadWrite,
+            previous_value: None,
         }];
 
         let result = manager.set_data_breakpoints(breakpoints);
@@ -301,4 +541,33 @@ mod tests {
         // Try to increment non-existent breakpoint
         assert!(!manager.increment_data_breakpoint_hit_count(999));
     }
+
+    #[test]
+    fn test_line_accesses() {
+        let access = |code| {
+            let access = line_accesses(code, "x");
+            (access.reads, access.writes)
+        };
+        assert_eq!(access("x = x + 1"), (true, true));
+        assert_eq!(access("local y = x * 2 -- x again"), (true, false));
+        assert_eq!(access("x, y = 1, 2"), (false, true));
+        assert_eq!(access("local x"), (false, true));
+        assert_eq!(access("for _, x in ipairs(list) do"), (false, true));
+        assert_eq!(access("if x == 1 then"), (true, false));
+        assert_eq!(access("t[x] = 1"), (true, false));
+        assert_eq!(access("x.count = 2"), (true, false));
+        assert_eq!(access("print('x', t.x, obj:x(), { x = 1 })"), (false, false));
+        assert_eq!(access("s = [[x]] .. \"x\" --[[ x ]] .. y"), (false, false));
+    }
+
+    #[test]
+    fn test_dap_names() {
+        assert_eq!(DataType::from_data_id("global:score"), (DataType::Global, "score"));
+        assert_eq!(DataType::from_data_id("hp"), (DataType::Local, "hp"));
+        assert_eq!(AccessType::from_dap(None), Some(AccessType::Write));
+        assert_eq!(AccessType::from_dap(Some("readWrite")), Some(AccessType::ReadWrite));
+        assert_eq!(AccessType::from_dap(Some("execute")), None);
+        assert!(AccessType::ReadWrite.includes(&AccessType::Read));
+        assert!(!AccessType::Write.includes(&AccessType::Read));
+    }
 }
@@ -230,18 +230,18 @@ impl HotReload {
         &self.warnings
     }
 
-    /// Output warnings to console/DAP output
+    /// Logs the warnings
     pub fn output_warnings(&self) {
         for warning in &self.warnings {
             match warning.severity {
                 WarningSeverity::Info => {
-                    println!("[INFO] Hot reload: {}", warning.message);
+                    tracing::info!(target: "wayfinder::hot_reload", "{}", warning.message);
                 }
                 WarningSeverity::Warning => {
-                    println!("[WARN] Hot reload: {}", warning.message);
+                    tracing::warn!(target: "wayfinder::hot_reload", "{}", warning.message);
                 }
                 WarningSeverity::Error => {
-                    println!("[ERROR] Hot reload: {}", warning.message);
+                    tracing::error!(target: "wayfinder::hot_reload", "{}", warning.message);
                 }
             }
         }
@@ -329,10 +329,7 @@ impl HotReload {
         for closure_ref in referencing_closures {
             // Update each closure's reference to the new module
             // This is a simplified example
-            println!(
-                "Would update closure {} to reference new module",
-                closure_ref
-            );
+            tracing::debug!(closure = closure_ref, "would update closure to reference the new module");
         }
 
         self.preserve_function_identity()?;
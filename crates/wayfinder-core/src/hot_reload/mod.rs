@@ -7,11 +7,16 @@
 pub mod hot_reload;
 pub mod state_capture;
 pub mod service;
+pub mod watcher;
 
 // Re-export the main types for convenience
 pub use hot_reload::{HotReload, HotReloadError, HotReloadWarning, WarningSeverity};
-pub use state_capture::{CapturedGlobal, CapturedTable, CapturedValue, StateCapture};
+pub use state_capture::{
+    reload_module, CapturedGlobal, CapturedTable, CapturedValue, FieldOutcome, FieldPreservation, ModuleTransfer,
+    StateCapture,
+};
 pub use service::{HotReloadService, HotReloadResult};
+pub use watcher::{FileWatcher, WatcherConfig};
 
 #[cfg(test)]
 mod tests {
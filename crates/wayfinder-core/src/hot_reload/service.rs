@@ -4,7 +4,8 @@
 //! Each runtime implementation provides its own hot reload service that has direct
 //! access to the underlying Lua state.
 
-use crate::hot_reload::{HotReloadError, HotReloadWarning};
+use crate::hot_reload::state_capture::{FieldOutcome, FieldPreservation, ModuleTransfer};
+use crate::hot_reload::{HotReloadError, HotReloadWarning, WarningSeverity};
 use async_trait::async_trait;
 
 /// Result of a hot reload operation
@@ -18,6 +19,47 @@ pub struct HotReloadResult {
     
     /// Optional message describing the result
     pub message: Option<String>,
+
+    /// What became of each field of the reloaded module
+    pub fields: Vec<FieldPreservation>,
+}
+
+impl HotReloadResult {
+    /// Describes a reload that carried `transfer` over to the module's new code
+    pub fn from_transfer(module_name: Option<&str>, transfer: ModuleTransfer) -> Self {
+        let name = module_name.unwrap_or("unnamed");
+        let mut warnings = Vec::new();
+        if module_name.is_none() {
+            warnings.push(HotReloadWarning {
+                message: "No module name given, so no state was carried over".to_string(),
+                severity: WarningSeverity::Info,
+            });
+        } else if !transfer.had_state {
+            warnings.push(HotReloadWarning {
+                message: format!("Module '{}' was not loaded before, so there was no state to carry over", name),
+                severity: WarningSeverity::Info,
+            });
+        }
+        for field in &transfer.fields {
+            if let FieldOutcome::Lost(reason) = &field.outcome {
+                warnings.push(HotReloadWarning {
+                    message: format!("Field '{}' was reset: {}", field.field, reason),
+                    severity: WarningSeverity::Warning,
+                });
+            }
+        }
+
+        let preserved = transfer.fields.iter().filter(|f| f.outcome == FieldOutcome::Preserved).count();
+        Self {
+            success: true,
+            warnings,
+            message: Some(format!(
+                "Module '{}' reloaded, keeping {} fields, updating {} closures and rebinding {} functions",
+                name, preserved, transfer.patched_upvalues, transfer.rebound_functions
+            )),
+            fields: transfer.fields,
+        }
+    }
 }
 
 /// Hot reload service trait
@@ -326,6 +326,338 @@ impl StateCapture {
     }
 }
 
+/// What became of one field of a reloaded module
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FieldOutcome {
+    /// The running program's value was kept; tables keep their data merged into the new table
+    Preserved,
+    /// The new code's function replaced the old one
+    Replaced,
+    /// Only the new code has the field
+    Added,
+    /// The new code no longer defines the function
+    Removed,
+    /// The old value was dropped for the new code's, with why
+    Lost(String),
+}
+
+/// A field of a reloaded module and what became of it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldPreservation {
+    pub field: String,
+    pub outcome: FieldOutcome,
+}
+
+/// How a reload carried the module's state over to the new code
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleTransfer {
+    /// Whether the module was loaded before, so there was state to carry over
+    pub had_state: bool,
+    /// The old module's fields, and those the new code adds, by name
+    pub fields: Vec<FieldPreservation>,
+    /// Upvalues of functions that held the old module table and now hold the new one
+    pub patched_upvalues: usize,
+    /// References to the old module's functions, in tables and upvalues, now to their new versions
+    pub rebound_functions: usize,
+}
+
+/// Moves the state of an old module table into the table its new code returned
+///
+/// Called with the old and new tables. Data fields of the old table are
+/// deep-copied into the new one, merging into tables the new code also
+/// creates, so the program keeps its counters, caches and settings while
+/// functions come from the new code. Fields whose type the new code changes
+/// take the new value. Userdata and threads are kept as they are, since they
+/// cannot be copied. Closures anywhere in the state that captured the old
+/// table get the new one instead.
+///
+/// Functions of the old code that the program still holds, stored as
+/// callbacks or captured by other closures, are rebound to their new
+/// versions: those under the same key of the module table, and, given the
+/// chunk's source name, those of the chunk defined on the same line, such as
+/// local functions and handlers in nested tables. Both must take the same
+/// parameters. Returns the fields, sorted, the number of upvalues patched,
+/// and the number of function references rebound.
+const MODULE_STATE_TRANSFER: &str = r#"
+local old, new, source = ...
+local getmetatable, setmetatable = debug.getmetatable, debug.setmetatable
+local getupvalue, setupvalue = debug.getupvalue, debug.setupvalue
+local getinfo = debug.getinfo
+
+local copies = {}
+local function copy(value)
+    if type(value) ~= "table" then return value end
+    if copies[value] then return copies[value] end
+    local result = {}
+    copies[value] = result
+    for k, v in next, value do
+        if type(v) ~= "function" then rawset(result, copy(k), copy(v)) end
+    end
+    setmetatable(result, getmetatable(value))
+    return result
+end
+local function merge(from, into)
+    for k, v in next, from do
+        local current = rawget(into, k)
+        if type(v) == "table" and type(current) == "table" then
+            merge(v, current)
+        elseif type(v) ~= "function" and (current == nil or type(current) == type(v)) then
+            rawset(into, k, copy(v))
+        end
+    end
+end
+
+local fields = {}
+local function report(key, outcome, detail)
+    fields[#fields + 1] = { field = tostring(key), outcome = outcome, detail = detail }
+end
+for k, v in next, old do
+    local current = rawget(new, k)
+    if type(v) == "function" then
+        report(k, current == nil and "removed" or "replaced")
+    elseif current ~= nil and type(current) ~= type(v) then
+        report(k, "lost", "the new code makes it a " .. type(current))
+    elseif type(v) == "table" and type(current) == "table" then
+        merge(v, current)
+        report(k, "preserved")
+    else
+        rawset(new, k, copy(v))
+        report(k, "preserved")
+    end
+end
+for k in next, new do
+    if rawget(old, k) == nil then report(k, "added") end
+end
+table.sort(fields, function(a, b) return a.field < b.field end)
+
+-- The new code's functions, which are never replaced, and those of its chunk by line
+local fresh, by_line = {}, {}
+local function collect(value)
+    if fresh[value] then return end
+    fresh[value] = true
+    if type(value) == "table" then
+        for _, v in next, value do
+            if type(v) == "table" or type(v) == "function" then collect(v) end
+        end
+        return
+    end
+    local info = getinfo(value, "S")
+    if source and info.source == source then by_line[info.linedefined] = value end
+    local i = 1
+    while true do
+        local name, v = getupvalue(value, i)
+        if name == nil then break end
+        -- Only functions: tables reached this way include the globals
+        if type(v) == "function" then collect(v) end
+        i = i + 1
+    end
+end
+collect(new)
+
+-- Parameter counts are unknown before Lua 5.2, where any function matches
+local function signature(f)
+    local info = getinfo(f, "u")
+    return tostring(info.nparams) .. tostring(info.isvararg)
+end
+local replacements = {}
+for k, v in next, old do
+    local current = rawget(new, k)
+    if type(v) == "function" and type(current) == "function" and v ~= current
+        and getinfo(v, "S").what ~= "C" and signature(v) == signature(current) then
+        replacements[v] = current
+    end
+end
+local function replacement(f)
+    local found = replacements[f]
+    if found == nil and source and not fresh[f] then
+        local info = getinfo(f, "S")
+        local candidate = info.source == source and by_line[info.linedefined]
+        found = candidate and signature(candidate) == signature(f) and candidate or false
+        replacements[f] = found
+    end
+    return found
+end
+
+local patched, rebound = 0, 0
+local seen = { [fields] = true, [copies] = true, [fresh] = true, [by_line] = true, [replacements] = true }
+local pending = { debug.getregistry() }
+while #pending > 0 do
+    local value = table.remove(pending)
+    if not seen[value] then
+        seen[value] = true
+        if type(value) == "table" then
+            for k, v in next, value do
+                if type(k) == "table" or type(k) == "function" then pending[#pending + 1] = k end
+                local current = type(v) == "function" and replacement(v)
+                if current then
+                    rawset(value, k, current)
+                    rebound = rebound + 1
+                elseif type(v) == "table" or type(v) == "function" then
+                    pending[#pending + 1] = v
+                end
+            end
+            local mt = getmetatable(value)
+            if mt then pending[#pending + 1] = mt end
+        else
+            local i = 1
+            while true do
+                local name, v = getupvalue(value, i)
+                if name == nil then break end
+                local current = type(v) == "function" and replacement(v)
+                if v == old then
+                    setupvalue(value, i, new)
+                    patched = patched + 1
+                elseif current then
+                    setupvalue(value, i, current)
+                    rebound = rebound + 1
+                elseif type(v) == "table" or type(v) == "function" then
+                    pending[#pending + 1] = v
+                end
+                i = i + 1
+            end
+        end
+    end
+end
+return fields, patched, rebound
+"#;
+
+/// The source name of the chunk a loaded module table came from
+///
+/// Taken from the first Lua function among its fields; None for modules
+/// without one.
+const MODULE_CHUNK_SOURCE: &str = r#"
+for _, v in next, ... do
+    if type(v) == "function" then
+        local info = debug.getinfo(v, "S")
+        if info.what ~= "C" then return info.source end
+    end
+end
+"#;
+
+/// Compiles the code given under the chunk name given
+const LOAD_NAMED_CHUNK: &str = "local code, name = ... return (loadstring or load)(code, name)";
+
+/// Runs a module's new code and carries the loaded module's state over to it
+///
+/// With a `module_name` that `package.loaded` has a table for, the table the
+/// new code returns takes over the old one's state, see
+/// `MODULE_STATE_TRANSFER`, and replaces it in `package.loaded`. Without one
+/// the code just runs. Leaves the stack as it was.
+pub fn reload_module(lua: &mut Lua, source: &str, module_name: Option<&str>) -> Result<ModuleTransfer, String> {
+    let top = lua.get_top();
+    let result = (|| {
+        // Compiled under the old code's name, so its functions can be matched and breakpoints hit
+        let chunk_source = module_name.and_then(|name| loaded_chunk_source(lua, name));
+        match &chunk_source {
+            Some(chunk) => load_named(lua, source, chunk),
+            None => lua.load_string(source).map(|_| ()),
+        }
+        .map_err(|e| format!("Compilation failed: {}", e))?;
+        lua.pcall(0, 1).map_err(|e| format!("Execution failed: {}", e))?;
+        let new = lua.get_top();
+        let Some(name) = module_name else {
+            return Ok(ModuleTransfer::default());
+        };
+
+        lua.get_global("package");
+        if !lua.is_table(-1) || lua.get_field(-1, "loaded") != LUA_TTABLE {
+            return Ok(ModuleTransfer::default());
+        }
+        let loaded = lua.get_top();
+        lua.get_field(loaded, name);
+        let old = lua.get_top();
+
+        let mut transfer = ModuleTransfer::default();
+        if lua.is_table(old) && lua.is_table(new) {
+            transfer.had_state = true;
+            lua.load_string(MODULE_STATE_TRANSFER)?;
+            lua.lua_pushvalue(old);
+            lua.lua_pushvalue(new);
+            match &chunk_source {
+                Some(chunk) => lua.push_string(chunk),
+                None => lua.push_nil(),
+            }
+            lua.pcall(3, 3)?;
+            transfer.rebound_functions = lua.pop_integer() as usize;
+            lua.lua_pop(1);
+            transfer.patched_upvalues = lua.pop_integer() as usize;
+            lua.lua_pop(1);
+            let count = lua.raw_len(-1);
+            for i in 1..=count {
+                lua.raw_get_i(-1, i as c_int);
+                transfer.fields.push(read_field(lua));
+                lua.lua_pop(1);
+            }
+            lua.lua_pop(1);
+        }
+
+        if !lua.is_nil(new) {
+            lua.lua_pushvalue(new);
+            lua.set_field(loaded, name);
+        }
+        Ok(transfer)
+    })();
+    lua.set_top(top);
+    result
+}
+
+/// The source name of the chunk `package.loaded[module_name]` came from, see `MODULE_CHUNK_SOURCE`
+fn loaded_chunk_source(lua: &mut Lua, module_name: &str) -> Option<String> {
+    let top = lua.get_top();
+    let source = (|| {
+        lua.get_global("package");
+        if !lua.is_table(-1) || lua.get_field(-1, "loaded") != LUA_TTABLE {
+            return None;
+        }
+        if lua.get_field(-1, module_name) != LUA_TTABLE {
+            return None;
+        }
+        let module = lua.get_top();
+        lua.load_string(MODULE_CHUNK_SOURCE).ok()?;
+        lua.lua_pushvalue(module);
+        lua.pcall(1, 1).ok()?;
+        lua.is_string(-1).then(|| lua.pop_string())
+    })();
+    lua.set_top(top);
+    source
+}
+
+/// Compiles `code` as the chunk `name`, pushing the function
+fn load_named(lua: &mut Lua, code: &str, name: &str) -> Result<(), String> {
+    lua.load_string(LOAD_NAMED_CHUNK)?;
+    lua.push_string(code);
+    lua.push_string(name);
+    lua.pcall(2, 2)?;
+    if lua.is_nil(-2) {
+        let error = lua.pop_string();
+        lua.lua_pop(2);
+        return Err(error);
+    }
+    lua.lua_pop(1);
+    Ok(())
+}
+
+/// Reads the `{field, outcome, detail}` entry on top of the stack
+fn read_field(lua: &mut Lua) -> FieldPreservation {
+    let field = string_field(lua, "field").unwrap_or_default();
+    let outcome = match string_field(lua, "outcome").as_deref() {
+        Some("preserved") => FieldOutcome::Preserved,
+        Some("replaced") => FieldOutcome::Replaced,
+        Some("added") => FieldOutcome::Added,
+        Some("removed") => FieldOutcome::Removed,
+        _ => FieldOutcome::Lost(string_field(lua, "detail").unwrap_or_default()),
+    };
+    FieldPreservation { field, outcome }
+}
+
+/// The string field `key` of the table on top of the stack
+fn string_field(lua: &mut Lua, key: &str) -> Option<String> {
+    lua.get_field(-1, key);
+    let value = lua.is_string(-1).then(|| lua.pop_string());
+    lua.lua_pop(1);
+    value
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -376,6 +708,87 @@ mod tests {
         // This test mainly verifies the method exists and doesn't panic
     }
 
+    #[test]
+    fn test_reload_keeps_module_state() {
+        let mut lua = Lua::new();
+        lua.execute(
+            "package.loaded.counter = { count = 5, name = 'old', config = { speed = 2, debug = true }, \
+                                        helper = function() end, out = io.stdout }\n\
+             local counter = package.loaded.counter\n\
+             read_count = function() return counter.count end",
+        )
+        .unwrap();
+
+        let source = "local M = { count = 0, name = 0, config = { speed = 1, scale = 3 } }\n\
+                      function M.get() return M.count end\n\
+                      return M";
+        let transfer = reload_module(&mut lua, source, Some("counter")).unwrap();
+        assert!(transfer.had_state);
+        assert_eq!(transfer.patched_upvalues, 1);
+        let outcome = |field: &str| {
+            transfer.fields.iter().find(|f| f.field == field).map(|f| f.outcome.clone()).unwrap()
+        };
+        assert_eq!(outcome("count"), FieldOutcome::Preserved);
+        assert_eq!(outcome("config"), FieldOutcome::Preserved);
+        assert_eq!(outcome("out"), FieldOutcome::Preserved);
+        assert_eq!(outcome("helper"), FieldOutcome::Removed);
+        assert_eq!(outcome("get"), FieldOutcome::Added);
+        assert_eq!(outcome("name"), FieldOutcome::Lost("the new code makes it a number".to_string()));
+
+        lua.execute(
+            "local M = package.loaded.counter\n\
+             assert(M.get() == 5 and M.name == 0 and M.out == io.stdout)\n\
+             assert(M.config.speed == 2 and M.config.debug == true and M.config.scale == 3)\n\
+             M.count = 7\n\
+             assert(read_count() == 7)",
+        )
+        .unwrap();
+        assert_eq!(lua.get_top(), 0);
+    }
+
+    #[test]
+    fn test_reload_rebinds_functions_the_program_holds() {
+        let mut lua = Lua::new();
+        let old = "local M = {}\n\
+                   function M.greet(name) return 'hello ' .. name end\n\
+                   local function shout(s) return s:upper() end\n\
+                   M.handlers = { on_join = function(name) return shout(M.greet(name)) end }\n\
+                   return M\n";
+        lua.execute(&format!(
+            "package.loaded.greeter = assert((loadstring or load)({:?}, '@greeter.lua'))()\n\
+             local greeter = package.loaded.greeter\n\
+             greet, on_join = greeter.greet, greeter.handlers.on_join\n\
+             local saved = greeter.greet\n\
+             call_saved = function(name) return saved(name) end",
+            old
+        ))
+        .unwrap();
+
+        let source = "local M = {}\n\
+                      function M.greet(name) return 'hi ' .. name end\n\
+                      local function shout(s) return s:upper() .. '!' end\n\
+                      M.handlers = { on_join = function(name) return shout(M.greet(name)) end }\n\
+                      return M\n";
+        let transfer = reload_module(&mut lua, source, Some("greeter")).unwrap();
+        assert!(transfer.rebound_functions >= 3, "{:?}", transfer);
+        lua.execute(
+            "assert(greet('a') == 'hi a')\n\
+             assert(call_saved('a') == 'hi a')\n\
+             assert(on_join('a') == 'HI A!')\n\
+             assert(debug.getinfo(package.loaded.greeter.greet, 'S').source == '@greeter.lua')",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_reload_without_a_loaded_module() {
+        let mut lua = Lua::new();
+        let transfer = reload_module(&mut lua, "return { fresh = true }", Some("fresh")).unwrap();
+        assert!(!transfer.had_state);
+        lua.execute("assert(package.loaded.fresh.fresh)").unwrap();
+        assert!(reload_module(&mut lua, "return {", Some("fresh")).unwrap_err().starts_with("Compilation failed"));
+    }
+
     #[test]
     fn test_captured_table_struct() {
         let table = CapturedTable {
@@ -0,0 +1,249 @@
+//! Reloading modules when their files change
+//!
+//! A [`FileWatcher`] looks over the files of a project matching the `watch`
+//! globs and reports those that changed once they have stayed unchanged for
+//! the debounce time, so an editor writing a file in several steps, or a
+//! build rewriting many, causes one reload each. The server reloads the
+//! modules of changed Lua files that the program has already required. Other
+//! watched files, such as TypeScript sources, run the build command instead,
+//! and the Lua it writes is reloaded in turn when it too is watched.
+//!
+//! Like the breakpoint file, the files are polled and their contents compared,
+//! which works the same on every platform and filesystem, network mounts and
+//! containers included.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often the server looks for changed files
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long a file must stay unchanged before it is reloaded, unless configured
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Files watched unless configured
+pub const DEFAULT_WATCH: &[&str] = &["**/*.lua"];
+
+/// Directories never looked into
+const SKIPPED_DIRECTORIES: &[&str] = &["node_modules"];
+
+/// What to watch and how to turn changes into reloads
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatcherConfig {
+    /// Directory the globs and module names are relative to
+    pub root: PathBuf,
+    /// Globs of the files to watch, `*` within a path segment and `**` across them
+    pub watch: Vec<String>,
+    /// How long a file must stay unchanged before it is reloaded
+    pub debounce: Duration,
+    /// Shell command run when a watched file that is not Lua changes
+    pub build_command: Option<String>,
+    /// Directories modules are required relative to, besides `root`
+    pub module_roots: Vec<PathBuf>,
+}
+
+impl WatcherConfig {
+    /// Watches the Lua files under `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            watch: DEFAULT_WATCH.iter().map(|glob| glob.to_string()).collect(),
+            debounce: DEFAULT_DEBOUNCE,
+            build_command: None,
+            module_roots: Vec::new(),
+        }
+    }
+
+    /// The name `require` loads a Lua file under, such as `ui.menu` for `ui/menu.lua`
+    ///
+    /// Taken relative to the innermost root holding the file; `init.lua`
+    /// names its directory. None for files outside every root.
+    pub fn module_name(&self, path: &Path) -> Option<String> {
+        let relative = self
+            .module_roots
+            .iter()
+            .map(|root| self.root.join(root))
+            .chain([self.root.clone()])
+            .filter_map(|root| path.strip_prefix(root).ok())
+            .min_by_key(|relative| relative.components().count())?;
+        if relative.extension()? != "lua" {
+            return None;
+        }
+
+        let mut segments = Vec::new();
+        for component in relative.with_extension("").components() {
+            match component {
+                Component::Normal(segment) => segments.push(segment.to_str()?.to_string()),
+                _ => return None,
+            }
+        }
+        if segments.len() > 1 && segments.last().is_some_and(|last| last == "init") {
+            segments.pop();
+        }
+        Some(segments.join("."))
+    }
+
+    /// Whether the file at `relative`, with `/` separators, is watched
+    fn is_watched(&self, relative: &str) -> bool {
+        self.watch.iter().any(|glob| {
+            let mut captures = Vec::new();
+            crate::debug::source_paths::glob_match(glob, relative, &mut captures)
+                // `**/` also matches no directory at all
+                || glob
+                    .strip_prefix("**/")
+                    .is_some_and(|rest| crate::debug::source_paths::glob_match(rest, relative, &mut captures))
+        })
+    }
+}
+
+/// Notices changes to the watched files of a project
+#[derive(Debug)]
+pub struct FileWatcher {
+    config: WatcherConfig,
+    /// Hash of each watched file's contents as of the last poll
+    files: BTreeMap<PathBuf, u64>,
+    /// Files changed but not yet reported, with when they last changed
+    settling: BTreeMap<PathBuf, Instant>,
+}
+
+impl FileWatcher {
+    /// Starts watching, taking the files as they are now as unchanged
+    pub fn new(config: WatcherConfig) -> Self {
+        let mut watcher = Self {
+            config,
+            files: BTreeMap::new(),
+            settling: BTreeMap::new(),
+        };
+        watcher.files = watcher.scan();
+        watcher
+    }
+
+    pub fn config(&self) -> &WatcherConfig {
+        &self.config
+    }
+
+    /// Checks the files and returns those whose changes have settled by `now`
+    ///
+    /// Deleted files are not reported; there is nothing to reload them with.
+    pub fn poll(&mut self, now: Instant) -> Vec<PathBuf> {
+        let files = self.scan();
+        for (path, hash) in &files {
+            if self.files.get(path) != Some(hash) {
+                self.settling.insert(path.clone(), now);
+            }
+        }
+        self.settling.retain(|path, _| files.contains_key(path));
+        self.files = files;
+
+        let debounce = self.config.debounce;
+        let settled: Vec<PathBuf> = self
+            .settling
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= debounce)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &settled {
+            self.settling.remove(path);
+        }
+        settled
+    }
+
+    /// Hashes of the contents of every watched file
+    fn scan(&self) -> BTreeMap<PathBuf, u64> {
+        let mut files = BTreeMap::new();
+        self.scan_dir(&self.config.root, &mut files);
+        files
+    }
+
+    fn scan_dir(&self, dir: &Path, files: &mut BTreeMap<PathBuf, u64>) {
+        let Ok(entries) = std::fs::read_dir(dir) else { return };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if path.is_dir() {
+                if !name.starts_with('.') && !SKIPPED_DIRECTORIES.contains(&name.as_ref()) {
+                    self.scan_dir(&path, files);
+                }
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(&self.config.root) else { continue };
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            if !self.config.is_watched(&relative) {
+                continue;
+            }
+            if let Ok(contents) = std::fs::read(&path) {
+                let mut hasher = DefaultHasher::new();
+                contents.hash(&mut hasher);
+                files.insert(path, hasher.finish());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_names() {
+        let config = WatcherConfig {
+            module_roots: vec![PathBuf::from("src")],
+            ..WatcherConfig::new("/game")
+        };
+        assert_eq!(config.module_name(Path::new("/game/main.lua")).as_deref(), Some("main"));
+        assert_eq!(config.module_name(Path::new("/game/src/ui/menu.lua")).as_deref(), Some("ui.menu"));
+        assert_eq!(config.module_name(Path::new("/game/lib/ui/init.lua")).as_deref(), Some("lib.ui"));
+        assert_eq!(config.module_name(Path::new("/game/init.lua")).as_deref(), Some("init"));
+        assert_eq!(config.module_name(Path::new("/game/src/menu.ts")), None);
+        assert_eq!(config.module_name(Path::new("/elsewhere/menu.lua")), None);
+    }
+
+    #[test]
+    fn test_watch_globs() {
+        let config = WatcherConfig {
+            watch: vec!["**/*.lua".to_string(), "src/*.ts".to_string()],
+            ..WatcherConfig::new("/game")
+        };
+        assert!(config.is_watched("main.lua"));
+        assert!(config.is_watched("ui/menu.lua"));
+        assert!(config.is_watched("src/menu.ts"));
+        assert!(!config.is_watched("src/ui/menu.ts"));
+        assert!(!config.is_watched("readme.md"));
+    }
+
+    #[test]
+    fn test_poll_reports_changes_once_settled() {
+        let dir = tempfile::tempdir().unwrap();
+        let player = dir.path().join("player.lua");
+        std::fs::write(&player, "return { hp = 10 }\n").unwrap();
+        std::fs::create_dir(dir.path().join("node_modules")).unwrap();
+        std::fs::write(dir.path().join("node_modules/dep.lua"), "return {}\n").unwrap();
+
+        let mut watcher = FileWatcher::new(WatcherConfig::new(dir.path()));
+        let start = Instant::now();
+        assert!(watcher.poll(start).is_empty());
+
+        std::fs::write(&player, "return { hp = 20 }\n").unwrap();
+        std::fs::write(dir.path().join("node_modules/dep.lua"), "return { changed = true }\n").unwrap();
+        assert!(watcher.poll(start).is_empty());
+        // A second write restarts the wait
+        std::fs::write(&player, "return { hp = 30 }\n").unwrap();
+        let later = start + DEFAULT_DEBOUNCE / 2;
+        assert!(watcher.poll(later).is_empty());
+        assert!(watcher.poll(later + DEFAULT_DEBOUNCE / 2).is_empty());
+        assert_eq!(watcher.poll(later + DEFAULT_DEBOUNCE), vec![player.clone()]);
+        assert!(watcher.poll(later + DEFAULT_DEBOUNCE * 2).is_empty());
+
+        // Rewriting the same contents is no change, and deleted files are dropped
+        std::fs::write(&player, "return { hp = 30 }\n").unwrap();
+        assert!(watcher.poll(later + DEFAULT_DEBOUNCE * 3).is_empty());
+        std::fs::write(&player, "return { hp = 40 }\n").unwrap();
+        watcher.poll(later + DEFAULT_DEBOUNCE * 4);
+        std::fs::remove_file(&player).unwrap();
+        assert!(watcher.poll(later + DEFAULT_DEBOUNCE * 6).is_empty());
+    }
+}
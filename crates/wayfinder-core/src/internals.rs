@@ -0,0 +1,303 @@
+//! Introspection of the adapter's own concurrency
+//!
+//! When the adapter hangs or drops events in the field, the first questions
+//! are what is still running and who holds the Lua state. Tasks and threads
+//! register here for as long as they live, and the Lua mutex is a
+//! [`TrackedMutex`] that remembers where it was locked. The
+//! `wayfinder/internals` request reports both without taking any lock a stuck
+//! task could be holding, other than the registry's own.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{LockResult, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::{Duration, Instant};
+
+/// What a task or thread is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskRole {
+    /// Reads requests, forwards runtime events and polls the breakpoint file
+    Transport,
+    /// Runs the debugged Lua program
+    Program,
+    /// Samples the call stack for wall-clock profiling
+    ProfilerTimer,
+    /// Carries requests and events between the adapter and a debug agent
+    RemoteConnection,
+}
+
+/// Whether a unit of work is a tokio task or an OS thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskKind {
+    Task,
+    Thread,
+}
+
+/// A live task as reported by `wayfinder/internals`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskInfo {
+    pub id: u64,
+    pub role: TaskRole,
+    pub kind: TaskKind,
+    /// What the task is doing, such as the request being handled
+    pub activity: String,
+    pub running_for_ms: u64,
+    /// How long ago the activity last changed
+    pub activity_for_ms: u64,
+}
+
+#[derive(Debug)]
+struct TaskEntry {
+    role: TaskRole,
+    kind: TaskKind,
+    activity: String,
+    started: Instant,
+    activity_since: Instant,
+}
+
+/// Tasks and threads currently alive
+#[derive(Debug, Default)]
+pub struct TaskRegistry {
+    next_id: AtomicU64,
+    tasks: Mutex<BTreeMap<u64, TaskEntry>>,
+}
+
+impl TaskRegistry {
+    /// Registers a task until the returned handle is dropped
+    pub fn register(&'static self, role: TaskRole, kind: TaskKind, activity: &str) -> TaskHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = Instant::now();
+        self.tasks.lock().unwrap_or_else(PoisonError::into_inner).insert(
+            id,
+            TaskEntry {
+                role,
+                kind,
+                activity: activity.to_string(),
+                started: now,
+                activity_since: now,
+            },
+        );
+        TaskHandle { registry: self, id }
+    }
+
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        let now = Instant::now();
+        self.tasks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(&id, task)| TaskInfo {
+                id,
+                role: task.role,
+                kind: task.kind,
+                activity: task.activity.clone(),
+                running_for_ms: millis(now - task.started),
+                activity_for_ms: millis(now - task.activity_since),
+            })
+            .collect()
+    }
+}
+
+/// Keeps a task registered; dropping it removes the task
+#[derive(Debug)]
+pub struct TaskHandle {
+    registry: &'static TaskRegistry,
+    id: u64,
+}
+
+impl TaskHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Records what the task is now doing
+    pub fn set_activity(&self, activity: &str) {
+        if let Some(task) = self.registry.tasks.lock().unwrap_or_else(PoisonError::into_inner).get_mut(&self.id) {
+            if task.activity != activity {
+                task.activity = activity.to_string();
+                task.activity_since = Instant::now();
+            }
+        }
+    }
+}
+
+impl Drop for TaskHandle {
+    fn drop(&mut self) {
+        self.registry.tasks.lock().unwrap_or_else(PoisonError::into_inner).remove(&self.id);
+    }
+}
+
+static TASKS: Lazy<TaskRegistry> = Lazy::new(TaskRegistry::default);
+
+/// The process-wide task registry
+pub fn tasks() -> &'static TaskRegistry {
+    &TASKS
+}
+
+/// Hold state of a tracked lock as reported by `wayfinder/internals`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockState {
+    pub name: String,
+    /// Source location that holds the lock, if it is held
+    pub holder: Option<String>,
+    pub held_for_ms: Option<u64>,
+    /// Threads blocked waiting for the lock
+    pub waiters: usize,
+    pub acquisitions: u64,
+    /// Acquisitions that had to wait for another holder
+    pub contended: u64,
+    pub longest_hold_ms: u64,
+}
+
+#[derive(Debug, Default)]
+struct LockMonitor {
+    holder: Mutex<Option<(&'static Location<'static>, Instant)>>,
+    waiters: AtomicUsize,
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    longest_hold_us: AtomicU64,
+}
+
+/// A mutex that records who holds it, for `wayfinder/internals`
+///
+/// Locking works like [`Mutex::lock`]; the caller's source location is
+/// recorded as the holder until the guard is dropped.
+#[derive(Debug)]
+pub struct TrackedMutex<T> {
+    name: &'static str,
+    inner: Mutex<T>,
+    monitor: LockMonitor,
+}
+
+impl<T> TrackedMutex<T> {
+    pub fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            inner: Mutex::new(value),
+            monitor: LockMonitor::default(),
+        }
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> LockResult<TrackedMutexGuard<'_, T>> {
+        let caller = Location::caller();
+        let result = match self.inner.try_lock() {
+            Ok(guard) => Ok(guard),
+            Err(TryLockError::Poisoned(poisoned)) => Err(poisoned),
+            Err(TryLockError::WouldBlock) => {
+                self.monitor.contended.fetch_add(1, Ordering::Relaxed);
+                self.monitor.waiters.fetch_add(1, Ordering::SeqCst);
+                let result = self.inner.lock();
+                self.monitor.waiters.fetch_sub(1, Ordering::SeqCst);
+                result
+            }
+        };
+
+        self.monitor.acquisitions.fetch_add(1, Ordering::Relaxed);
+        *self.monitor.holder.lock().unwrap_or_else(PoisonError::into_inner) = Some((caller, Instant::now()));
+        match result {
+            Ok(guard) => Ok(TrackedMutexGuard { guard, monitor: &self.monitor }),
+            Err(poisoned) => Err(PoisonError::new(TrackedMutexGuard {
+                guard: poisoned.into_inner(),
+                monitor: &self.monitor,
+            })),
+        }
+    }
+
+    /// Reads the hold state without touching the protected value
+    pub fn state(&self) -> LockState {
+        let holder = *self.monitor.holder.lock().unwrap_or_else(PoisonError::into_inner);
+        LockState {
+            name: self.name.to_string(),
+            holder: holder.map(|(location, _)| location.to_string()),
+            held_for_ms: holder.map(|(_, since)| millis(since.elapsed())),
+            waiters: self.monitor.waiters.load(Ordering::SeqCst),
+            acquisitions: self.monitor.acquisitions.load(Ordering::Relaxed),
+            contended: self.monitor.contended.load(Ordering::Relaxed),
+            longest_hold_ms: self.monitor.longest_hold_us.load(Ordering::Relaxed) / 1000,
+        }
+    }
+}
+
+pub struct TrackedMutexGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    monitor: &'a LockMonitor,
+}
+
+impl<T> Deref for TrackedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for TrackedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for TrackedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Cleared before the inner guard is released, so a new holder is never overwritten
+        if let Some((_, since)) = self.monitor.holder.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            let held = since.elapsed().as_micros() as u64;
+            self.monitor.longest_hold_us.fetch_max(held, Ordering::Relaxed);
+        }
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_tasks_are_listed_while_registered() {
+        let handle = tasks().register(TaskRole::Program, TaskKind::Thread, "starting");
+        handle.set_activity("running");
+        let task = tasks().tasks().into_iter().find(|task| task.id == handle.id()).unwrap();
+        assert_eq!((task.role, task.activity.as_str()), (TaskRole::Program, "running"));
+
+        let id = handle.id();
+        drop(handle);
+        assert!(tasks().tasks().iter().all(|task| task.id != id));
+    }
+
+    #[test]
+    fn test_tracked_mutex_reports_holder_and_contention() {
+        let mutex = Arc::new(TrackedMutex::new("lua", 0));
+        assert_eq!(mutex.state().holder, None);
+
+        let guard = mutex.lock().unwrap();
+        let state = mutex.state();
+        assert!(state.holder.unwrap().contains("internals.rs"));
+        assert_eq!(state.acquisitions, 1);
+
+        let waiter = {
+            let mutex = mutex.clone();
+            std::thread::spawn(move || *mutex.lock().unwrap() += 1)
+        };
+        while mutex.state().waiters == 0 {
+            std::thread::yield_now();
+        }
+        drop(guard);
+        waiter.join().unwrap();
+
+        let state = mutex.state();
+        assert_eq!((state.holder, state.waiters, state.contended), (None, 0, 1));
+        assert_eq!(*mutex.lock().unwrap(), 1);
+    }
+}
@@ -6,13 +6,16 @@
 #![allow(non_snake_case)] // Lua C API uses mixed case (_L, luaL_newstate, etc.)
 #![allow(static_mut_refs)] // Required for Lua FFI interaction
 
+pub mod agent;
 pub mod config;
 pub mod dap;
 pub mod debug;
 pub mod hot_reload;
+pub mod internals;
 pub mod memory;
 pub mod profiling;
 pub mod runtime;
+pub mod serializer;
 pub mod session;
 
 pub use config::{DebuggerConfig, EvalSafety};
@@ -22,6 +25,6 @@ pub use memory::MemoryStatistics;
 pub use profiling::{ProfileData, ProfilingMode, FunctionProfile};
 pub use runtime::{
     Breakpoint, BreakpointType, Frame, RuntimeError, RuntimeType, RuntimeVersion, Scope, Source,
-    StepMode, Variable, VariableScope, Value,
+    StepGranularity, StepMode, Variable, VariableScope, Value,
 };
 pub use session::{DapServer, DebugSession};
\ No newline at end of file
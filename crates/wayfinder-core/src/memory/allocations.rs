@@ -0,0 +1,226 @@
+//! Allocation tracking for Lua states created with a tracking allocator
+//!
+//! The allocator reports every block Lua allocates, grows or frees, and the
+//! debug hook keeps the tracker told which source line is running, so each
+//! allocation is charged to that line. A block grown by `realloc` counts as a
+//! free at the site that allocated it and a new allocation at the current
+//! line. Only the line is known, not the whole stack, so memory allocated by a
+//! helper is charged to the helper, and allocations made by the collector or
+//! while no hook runs go to the last line seen.
+
+use super::{AllocationProfile, AllocationSite};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Source index and line an allocation is charged to
+type SiteKey = (u32, u32);
+
+#[derive(Debug, Default, Clone, Copy)]
+struct SiteCounts {
+    allocations: u64,
+    allocated_bytes: u64,
+    frees: u64,
+    freed_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    sites: HashMap<SiteKey, SiteCounts>,
+    /// Blocks allocated while tracking, by address
+    live: HashMap<usize, (SiteKey, usize)>,
+}
+
+/// Counts allocations per source line
+///
+/// Tracking starts disabled; until it is enabled the allocator only pays for
+/// an atomic load per call.
+#[derive(Debug)]
+pub struct AllocationTracker {
+    enabled: AtomicBool,
+    /// Index into `sources` of the running source
+    source: AtomicU32,
+    line: AtomicU32,
+    /// Interned source names; index 0 is the empty name used before any line runs
+    sources: Mutex<Vec<String>>,
+    counts: Mutex<Counts>,
+    /// Number of entries in `counts.live`, so frees skip the lock once it is empty
+    live_blocks: AtomicUsize,
+}
+
+impl Default for AllocationTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AllocationTracker {
+    pub fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            source: AtomicU32::new(0),
+            line: AtomicU32::new(0),
+            sources: Mutex::new(vec![String::new()]),
+            counts: Mutex::new(Counts::default()),
+            live_blocks: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Starts or stops recording; what was recorded is kept
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Charges allocations from now on to `source`
+    pub fn set_source(&self, source: &str) {
+        let mut sources = self.sources.lock().unwrap();
+        let index = match sources.iter().position(|known| known == source) {
+            Some(index) => index,
+            None => {
+                sources.push(source.to_string());
+                sources.len() - 1
+            }
+        };
+        self.source.store(index as u32, Ordering::Relaxed);
+    }
+
+    /// Charges allocations from now on to `line` of the current source
+    pub fn set_line(&self, line: u32) {
+        self.line.store(line, Ordering::Relaxed);
+    }
+
+    /// Records a block of `size` bytes allocated at `address`
+    pub fn on_alloc(&self, address: usize, size: usize) {
+        if !self.is_enabled() {
+            return;
+        }
+        let site = (self.source.load(Ordering::Relaxed), self.line.load(Ordering::Relaxed));
+        let mut counts = self.counts.lock().unwrap();
+        let entry = counts.sites.entry(site).or_default();
+        entry.allocations += 1;
+        entry.allocated_bytes += size as u64;
+        if counts.live.insert(address, (site, size)).is_none() {
+            self.live_blocks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records that the block at `address` was freed or moved
+    ///
+    /// Blocks allocated while tracking was off are ignored. Frees of tracked
+    /// blocks are still recorded after tracking stops, so live bytes stay right.
+    pub fn on_free(&self, address: usize) {
+        if self.live_blocks.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut counts = self.counts.lock().unwrap();
+        if let Some((site, size)) = counts.live.remove(&address) {
+            self.live_blocks.fetch_sub(1, Ordering::Relaxed);
+            if let Some(entry) = counts.sites.get_mut(&site) {
+                entry.frees += 1;
+                entry.freed_bytes += size as u64;
+            }
+        }
+    }
+
+    /// Forgets everything recorded so far
+    pub fn reset(&self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.sites.clear();
+        counts.live.clear();
+        self.live_blocks.store(0, Ordering::Relaxed);
+    }
+
+    /// The `limit` sites holding the most live bytes, then allocating the most
+    pub fn profile(&self, limit: usize) -> AllocationProfile {
+        let counts = self.counts.lock().unwrap();
+        let sources = self.sources.lock().unwrap();
+
+        let mut sites: Vec<AllocationSite> = counts
+            .sites
+            .iter()
+            .map(|(&(source, line), site)| AllocationSite {
+                source: sources.get(source as usize).cloned().unwrap_or_default(),
+                line,
+                allocations: site.allocations,
+                allocated_bytes: site.allocated_bytes,
+                frees: site.frees,
+                freed_bytes: site.freed_bytes,
+                live_bytes: site.allocated_bytes - site.freed_bytes,
+            })
+            .collect();
+        sites.sort_by(|a, b| {
+            b.live_bytes
+                .cmp(&a.live_bytes)
+                .then(b.allocated_bytes.cmp(&a.allocated_bytes))
+                .then_with(|| a.source.cmp(&b.source))
+                .then(a.line.cmp(&b.line))
+        });
+
+        let total_allocated_bytes = sites.iter().map(|site| site.allocated_bytes).sum();
+        let total_freed_bytes = sites.iter().map(|site| site.freed_bytes).sum();
+        let omitted_sites = sites.len().saturating_sub(limit);
+        sites.truncate(limit);
+        AllocationProfile {
+            sites,
+            omitted_sites,
+            total_allocated_bytes,
+            total_freed_bytes,
+            live_bytes: total_allocated_bytes - total_freed_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocations_are_charged_to_the_current_line() {
+        let tracker = AllocationTracker::new();
+        tracker.on_alloc(0x10, 64);
+        assert_eq!(tracker.profile(10), AllocationProfile::default());
+
+        tracker.set_enabled(true);
+        tracker.set_source("@main.lua");
+        tracker.set_line(3);
+        tracker.on_alloc(0x10, 64);
+        tracker.on_alloc(0x20, 32);
+        tracker.set_line(7);
+        tracker.on_alloc(0x30, 16);
+        // Growing a block from line 3 charges the new size to line 7
+        tracker.on_free(0x20);
+        tracker.on_alloc(0x40, 128);
+        tracker.on_free(0x99);
+
+        let profile = tracker.profile(10);
+        assert_eq!((profile.total_allocated_bytes, profile.total_freed_bytes), (240, 32));
+        assert_eq!(profile.live_bytes, 208);
+        assert_eq!((profile.sites[0].line, profile.sites[0].live_bytes), (7, 144));
+        assert_eq!(profile.sites[0].allocations, 2);
+        assert_eq!(profile.sites[1].source, "@main.lua");
+        assert_eq!((profile.sites[1].line, profile.sites[1].frees), (3, 1));
+        assert_eq!(profile.sites[1].live_bytes, 64);
+
+        let top = tracker.profile(1);
+        assert_eq!((top.sites.len(), top.omitted_sites), (1, 1));
+        assert_eq!(top.total_allocated_bytes, 240);
+    }
+
+    #[test]
+    fn test_frees_after_disabling_still_count() {
+        let tracker = AllocationTracker::new();
+        tracker.set_enabled(true);
+        tracker.set_line(1);
+        tracker.on_alloc(0x10, 64);
+        tracker.set_enabled(false);
+        tracker.on_free(0x10);
+        assert_eq!(tracker.profile(10).live_bytes, 0);
+
+        tracker.reset();
+        assert!(tracker.profile(10).sites.is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+//! Differences between heap snapshots, for finding leaks
+//!
+//! Objects are matched across snapshots by address and type. An address the
+//! collector freed and reused for an object of the same type looks like the
+//! same object, so a diff can undercount churn, but everything it reports as
+//! new really was allocated between the snapshots and is still reachable.
+
+use super::{HeapSnapshot, ObjectCounts, ObjectInfo, SnapshotDiff};
+use std::collections::{HashMap, HashSet};
+
+/// Computes what changed from `from` to `to`
+pub fn diff(from: &HeapSnapshot, to: &HeapSnapshot) -> SnapshotDiff {
+    SnapshotDiff {
+        from_id: from.id,
+        to_id: to.id,
+        memory_delta_kb: to.statistics.total_kb - from.statistics.total_kb,
+        object_count_deltas: count_deltas(&from.object_counts, &to.object_counts),
+        new_objects: unmatched(&to.objects, &from.objects),
+        deleted_objects: unmatched(&from.objects, &to.objects),
+    }
+}
+
+/// Objects of `objects` that have no match in `others`
+fn unmatched(objects: &[ObjectInfo], others: &[ObjectInfo]) -> Vec<ObjectInfo> {
+    let known: HashSet<(i64, &str)> = others
+        .iter()
+        .filter(|object| object.id != 0)
+        .map(|object| (object.id, object.type_name.as_str()))
+        .collect();
+    objects
+        .iter()
+        .filter(|object| object.id == 0 || !known.contains(&(object.id, object.type_name.as_str())))
+        .cloned()
+        .collect()
+}
+
+fn count_deltas(from: &ObjectCounts, to: &ObjectCounts) -> HashMap<String, i64> {
+    [
+        ("table", from.tables, to.tables),
+        ("function", from.functions, to.functions),
+        ("userdata", from.userdata, to.userdata),
+        ("thread", from.threads, to.threads),
+        ("string", from.strings, to.strings),
+    ]
+    .into_iter()
+    .map(|(name, from, to)| (name.to_string(), to as i64 - from as i64))
+    .collect()
+}
+
+/// How one type of object changed between two snapshots
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeGrowth {
+    pub type_name: String,
+    pub count_delta: i64,
+    pub new_count: usize,
+    pub new_bytes: usize,
+    pub deleted_count: usize,
+    pub deleted_bytes: usize,
+}
+
+impl TypeGrowth {
+    /// Estimated bytes added, less those freed
+    pub fn net_bytes(&self) -> i64 {
+        self.new_bytes as i64 - self.deleted_bytes as i64
+    }
+}
+
+/// Growth per type, the types that grew most first
+pub fn growth_by_type(diff: &SnapshotDiff) -> Vec<TypeGrowth> {
+    fn entry<'a>(growth: &'a mut HashMap<String, TypeGrowth>, name: &str) -> &'a mut TypeGrowth {
+        growth.entry(name.to_string()).or_insert_with(|| TypeGrowth {
+            type_name: name.to_string(),
+            ..TypeGrowth::default()
+        })
+    }
+
+    let mut growth = HashMap::new();
+    for (name, delta) in &diff.object_count_deltas {
+        entry(&mut growth, name).count_delta = *delta;
+    }
+    for object in &diff.new_objects {
+        let type_growth = entry(&mut growth, &object.type_name);
+        type_growth.new_count += 1;
+        type_growth.new_bytes += object.size_estimate;
+    }
+    for object in &diff.deleted_objects {
+        let type_growth = entry(&mut growth, &object.type_name);
+        type_growth.deleted_count += 1;
+        type_growth.deleted_bytes += object.size_estimate;
+    }
+
+    let mut growth: Vec<TypeGrowth> = growth.into_values().collect();
+    growth.sort_by(|a, b| {
+        b.net_bytes()
+            .cmp(&a.net_bytes())
+            .then(b.count_delta.cmp(&a.count_delta))
+            .then_with(|| a.type_name.cmp(&b.type_name))
+    });
+    growth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryStatistics;
+    use std::time::SystemTime;
+
+    fn object(id: i64, type_name: &str, size_estimate: usize) -> ObjectInfo {
+        ObjectInfo {
+            id,
+            type_name: type_name.to_string(),
+            size_estimate,
+            address: format!("0x{:x}", id),
+        }
+    }
+
+    fn snapshot(id: u64, total_kb: f64, objects: Vec<ObjectInfo>, strings: usize) -> HeapSnapshot {
+        let count = |name: &str| objects.iter().filter(|o| o.type_name == name).count();
+        HeapSnapshot {
+            id,
+            timestamp: SystemTime::now(),
+            statistics: MemoryStatistics {
+                total_kb,
+                total_bytes: (total_kb * 1024.0) as usize,
+                gc_pause: 200,
+                gc_step_mul: 100,
+                gc_running: true,
+                timestamp: SystemTime::now(),
+            },
+            object_counts: ObjectCounts {
+                tables: count("table"),
+                functions: count("function"),
+                userdata: count("userdata"),
+                threads: count("thread"),
+                strings,
+            },
+            objects,
+            size_estimates: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_diff_matches_objects_by_address_and_type() {
+        let from = snapshot(1, 100.0, vec![object(0x10, "table", 88), object(0x20, "function", 40)], 5);
+        let to = snapshot(
+            2,
+            150.0,
+            vec![object(0x10, "table", 120), object(0x20, "table", 56), object(0x30, "table", 56)],
+            7,
+        );
+
+        let diff = diff(&from, &to);
+        assert_eq!((diff.from_id, diff.to_id), (1, 2));
+        assert_eq!(diff.memory_delta_kb, 50.0);
+        // A grown table is the same table; a reused address of another type is not
+        let new: Vec<_> = diff.new_objects.iter().map(|o| o.id).collect();
+        assert_eq!(new, vec![0x20, 0x30]);
+        assert_eq!(diff.deleted_objects.len(), 1);
+        assert_eq!(diff.deleted_objects[0].type_name, "function");
+        assert_eq!(diff.object_count_deltas["table"], 2);
+        assert_eq!(diff.object_count_deltas["function"], -1);
+        assert_eq!(diff.object_count_deltas["string"], 2);
+    }
+
+    #[test]
+    fn test_growth_by_type_lists_largest_growth_first() {
+        let from = snapshot(1, 100.0, vec![object(0x20, "function", 40)], 0);
+        let to = snapshot(2, 150.0, vec![object(0x30, "table", 56), object(0x40, "table", 56)], 0);
+
+        let growth = growth_by_type(&diff(&from, &to));
+        assert_eq!(growth[0].type_name, "table");
+        assert_eq!((growth[0].new_count, growth[0].new_bytes, growth[0].count_delta), (2, 112, 2));
+        assert_eq!(growth.last().unwrap().type_name, "function");
+        assert_eq!(growth.last().unwrap().net_bytes(), -40);
+    }
+}
@@ -1,3 +1,6 @@
+pub mod allocations;
+pub mod diff;
+
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::SystemTime;
@@ -60,6 +63,9 @@ pub struct HeapSnapshot {
     pub object_counts: ObjectCounts,
     /// List of objects in the heap
     pub objects: Vec<ObjectInfo>,
+    /// Estimated bytes held by each type of object, by type name
+    #[serde(default)]
+    pub size_estimates: HashMap<String, usize>,
 }
 
 /// Difference between two heap snapshots
@@ -78,3 +84,64 @@ pub struct SnapshotDiff {
     /// Objects that disappeared
     pub deleted_objects: Vec<ObjectInfo>,
 }
+
+/// Table visits a heap search makes when the client sets no limit
+pub const DEFAULT_SEARCH_MAX_TABLES: usize = 10_000;
+
+/// Matches a heap search returns when the client sets no limit
+pub const DEFAULT_SEARCH_MAX_RESULTS: usize = 100;
+
+/// Source lines an allocation profile lists when the client sets no limit
+pub const DEFAULT_ALLOCATION_SITES: usize = 20;
+
+/// A value that satisfied a heap search predicate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeapMatch {
+    /// Access path from the globals table, such as `_G.world.enemies[3]`
+    pub path: String,
+    /// Display value
+    pub value: String,
+    /// Type name of the value
+    #[serde(rename = "type")]
+    pub type_name: String,
+}
+
+/// Outcome of a heap search
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeapSearchResult {
+    pub matches: Vec<HeapMatch>,
+    /// Number of distinct tables walked
+    pub tables_visited: usize,
+    /// Set when a limit stopped the walk before every reachable table was seen
+    pub truncated: bool,
+}
+
+/// Allocations attributed to one source line
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocationSite {
+    /// Chunk name of the source, or empty before any line has run
+    pub source: String,
+    pub line: u32,
+    /// Blocks allocated or grown while this line ran
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+    /// How many of those blocks were freed since, wherever that happened
+    pub frees: u64,
+    pub freed_bytes: u64,
+    /// Bytes allocated here that are still in use
+    pub live_bytes: u64,
+}
+
+/// Allocations recorded by a tracking allocator, heaviest sites first
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllocationProfile {
+    pub sites: Vec<AllocationSite>,
+    /// Sites left out of `sites` by the limit
+    pub omitted_sites: usize,
+    pub total_allocated_bytes: u64,
+    pub total_freed_bytes: u64,
+    pub live_bytes: u64,
+}
@@ -0,0 +1,223 @@
+//! Profile export to formats standard flame graph viewers open
+//!
+//! Speedscope files hold one sampled profile whose samples are the distinct
+//! call stacks, weighted by their exclusive time. Chrome trace files hold the
+//! call tree laid out as complete events, callees in name order, so they read
+//! as a flame chart rather than a timeline of individual calls.
+
+use super::{CallTreeNode, ProfileData};
+use crate::serializer::Serializer;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+const SPEEDSCOPE_SCHEMA: &str = "https://www.speedscope.app/file-format-schema.json";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportFormat {
+    #[default]
+    Speedscope,
+    ChromeTrace,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "speedscope" => Ok(ExportFormat::Speedscope),
+            "chrome" | "chrome-trace" | "chromeTrace" => Ok(ExportFormat::ChromeTrace),
+            _ => Err(format!("Unknown profile format: {} (expected speedscope or chrome)", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportFormat::Speedscope => write!(f, "speedscope"),
+            ExportFormat::ChromeTrace => write!(f, "chrome"),
+        }
+    }
+}
+
+/// Serializes a profile in the given format
+pub fn export(data: &ProfileData, format: ExportFormat, name: &str) -> JsonValue {
+    match format {
+        ExportFormat::Speedscope => to_speedscope(data, name),
+        ExportFormat::ChromeTrace => to_chrome_trace(data),
+    }
+}
+
+/// Writes a profile to a file in the given format, encoded by `serializer`
+pub fn write_profile(
+    data: &ProfileData,
+    format: ExportFormat,
+    serializer: Serializer,
+    path: &Path,
+) -> std::io::Result<()> {
+    let name = path.file_stem().map_or("profile".into(), |stem| stem.to_string_lossy());
+    let document = export(data, format, &name);
+    serializer.write(&document, path)
+}
+
+/// Speedscope frame for a function, with its location when known
+fn frame_json(data: &ProfileData, name: &str) -> JsonValue {
+    let mut frame = json!({ "name": name });
+    if let Some(profile) = data.functions.get(name) {
+        if let Some(source) = &profile.source {
+            frame["file"] = json!(source.strip_prefix('@').unwrap_or(source));
+            frame["line"] = json!(profile.line_defined);
+        }
+    }
+    frame
+}
+
+pub fn to_speedscope(data: &ProfileData, name: &str) -> JsonValue {
+    let mut frames = Vec::new();
+    let mut frame_index: HashMap<&str, usize> = HashMap::new();
+    let mut samples = Vec::new();
+    let mut weights = Vec::new();
+
+    for stack in &data.stacks {
+        let sample: Vec<usize> = stack
+            .frames
+            .iter()
+            .map(|frame| {
+                *frame_index.entry(frame.as_str()).or_insert_with(|| {
+                    frames.push(frame_json(data, frame));
+                    frames.len() - 1
+                })
+            })
+            .collect();
+        samples.push(sample);
+        weights.push(data.weight_ms(stack.weight));
+    }
+    let total: f64 = weights.iter().sum();
+
+    json!({
+        "$schema": SPEEDSCOPE_SCHEMA,
+        "name": name,
+        "exporter": concat!("wayfinder ", env!("CARGO_PKG_VERSION")),
+        "activeProfileIndex": 0,
+        "shared": { "frames": frames },
+        "profiles": [{
+            "type": "sampled",
+            "name": name,
+            "unit": "milliseconds",
+            "startValue": 0,
+            "endValue": total,
+            "samples": samples,
+            "weights": weights,
+        }],
+    })
+}
+
+/// Emits a call tree node and its children as complete events starting at `start_ms`
+///
+/// `scale` converts the tree's weights to milliseconds.
+fn emit(node: &CallTreeNode, data: &ProfileData, scale: f64, start_ms: f64, events: &mut Vec<JsonValue>) {
+    let mut event = json!({
+        "name": node.name,
+        "ph": "X",
+        "ts": start_ms * 1000.0,
+        "dur": node.total * scale * 1000.0,
+        "pid": 1,
+        "tid": 1,
+    });
+    if let Some(source) = data.functions.get(&node.name).and_then(|p| p.source.as_ref()) {
+        event["args"] = json!({ "source": source.strip_prefix('@').unwrap_or(source) });
+    }
+    events.push(event);
+
+    let mut child_start = start_ms;
+    for child in &node.children {
+        emit(child, data, scale, child_start, events);
+        child_start += child.total * scale;
+    }
+}
+
+pub fn to_chrome_trace(data: &ProfileData) -> JsonValue {
+    let scale = data.weight_ms(1.0);
+    let mut events = Vec::new();
+    let mut start = 0.0;
+    for child in &data.call_tree().children {
+        emit(child, data, scale, start, &mut events);
+        start += child.total * scale;
+    }
+
+    json!({
+        "traceEvents": events,
+        "displayTimeUnit": "ms",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiling::{FunctionProfile, ProfilingMode, StackProfile};
+
+    fn profile() -> ProfileData {
+        let mut functions = HashMap::new();
+        functions.insert(
+            "update".to_string(),
+            FunctionProfile {
+                name: "update".to_string(),
+                source: Some("@game/main.lua".to_string()),
+                line_defined: 12,
+                call_count: 2,
+                total_time_ms: 3.0,
+                self_time_ms: 0.0,
+                children: HashMap::new(),
+                callees: HashMap::new(),
+            },
+        );
+        let stack = |frames: &[&str], weight: f64| StackProfile {
+            frames: frames.iter().map(|f| f.to_string()).collect(),
+            weight,
+        };
+
+        ProfileData {
+            mode: ProfilingMode::CallTrace,
+            duration_ms: 10.0,
+            functions,
+            total_samples: 0,
+            stacks: vec![stack(&["main"], 1.0), stack(&["main", "update"], 3.0), stack(&["main", "draw"], 2.0)],
+        }
+    }
+
+    #[test]
+    fn test_speedscope_shares_frames() {
+        let document = to_speedscope(&profile(), "game");
+        let frames = document["shared"]["frames"].as_array().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1]["file"], "game/main.lua");
+        assert_eq!(frames[1]["line"], 12);
+
+        let profile = &document["profiles"][0];
+        assert_eq!(profile["samples"], json!([[0], [0, 1], [0, 2]]));
+        assert_eq!(profile["endValue"], 6.0);
+    }
+
+    #[test]
+    fn test_chrome_trace_nests_children_within_parents() {
+        let document = to_chrome_trace(&profile());
+        let events = document["traceEvents"].as_array().unwrap();
+        let summary: Vec<_> = events
+            .iter()
+            .map(|e| (e["name"].as_str().unwrap(), e["ts"].as_f64().unwrap(), e["dur"].as_f64().unwrap()))
+            .collect();
+        assert_eq!(summary, vec![("main", 0.0, 6000.0), ("draw", 0.0, 2000.0), ("update", 2000.0, 3000.0)]);
+    }
+
+    #[test]
+    fn test_sampling_weights_scale_by_interval() {
+        let mut data = profile();
+        data.mode = ProfilingMode::Sampling { interval_ms: 5 };
+        assert_eq!(to_speedscope(&data, "game")["profiles"][0]["endValue"], 30.0);
+        assert_eq!("chrome".parse::<ExportFormat>(), Ok(ExportFormat::ChromeTrace));
+    }
+}
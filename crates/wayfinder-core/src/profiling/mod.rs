@@ -1,3 +1,5 @@
+pub mod export;
+
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::{Instant, Duration};
@@ -9,6 +11,11 @@ pub enum ProfilingMode {
     Disabled,
     /// Low overhead sampling at specified interval in milliseconds
     Sampling { interval_ms: u32 },
+    /// Sampling of the whole call stack on a wall-clock timer, every `interval_ms`
+    ///
+    /// Unlike `Sampling`, whose interval counts VM instructions, time spent
+    /// in C functions and waiting on I/O is weighed too.
+    WallClock { interval_ms: u32 },
     /// Medium overhead call/return tracing
     CallTrace,
     /// High overhead line-level profiling
@@ -38,6 +45,61 @@ pub struct FunctionProfile {
     pub self_time_ms: f64,
     /// Count of calls to child functions
     pub children: HashMap<String, u64>,
+    /// Timing of the calls this function made, by callee
+    #[serde(default)]
+    pub callees: HashMap<String, CallEdge>,
+}
+
+/// Calls from one function to another
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CallEdge {
+    pub call_count: u64,
+    /// Time in the callee including its own callees (milliseconds)
+    pub total_time_ms: f64,
+    /// Time in the callee excluding its callees (milliseconds)
+    pub self_time_ms: f64,
+}
+
+/// Node of the call tree rebuilt from the recorded stacks
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CallTreeNode {
+    pub name: String,
+    /// Weight of this node and everything it called
+    pub total: f64,
+    /// Weight of this node alone
+    pub self_weight: f64,
+    /// Callees, sorted by name
+    pub children: Vec<CallTreeNode>,
+}
+
+impl CallTreeNode {
+    fn insert(&mut self, frames: &[String], weight: f64) {
+        self.total += weight;
+        let Some((first, rest)) = frames.split_first() else {
+            self.self_weight += weight;
+            return;
+        };
+        let index = match self.children.binary_search_by(|child| child.name.as_str().cmp(first)) {
+            Ok(index) => index,
+            Err(index) => {
+                self.children.insert(index, CallTreeNode {
+                    name: first.clone(),
+                    ..CallTreeNode::default()
+                });
+                index
+            }
+        };
+        self.children[index].insert(rest, weight);
+    }
+}
+
+/// Weight attributed to one call stack, as drawn in a flame graph
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StackProfile {
+    /// Function names from the outermost call inward
+    pub frames: Vec<String>,
+    /// Exclusive time in milliseconds, or the number of samples in sampling mode
+    pub weight: f64,
 }
 
 /// Complete profiling data for a session
@@ -51,6 +113,84 @@ pub struct ProfileData {
     pub functions: HashMap<String, FunctionProfile>,
     /// Total number of samples (for sampling mode)
     pub total_samples: u64,
+    /// Weight of each distinct call stack, sorted by frames
+    #[serde(default)]
+    pub stacks: Vec<StackProfile>,
+}
+
+/// Time one function took over part of a profile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hotspot {
+    pub name: String,
+    pub source: Option<String>,
+    pub line: u32,
+    /// Time in the function excluding its callees (milliseconds)
+    pub self_time_ms: f64,
+    pub call_count: u64,
+}
+
+impl ProfileData {
+    /// Milliseconds a self time or stack weight stands for; sampling mode counts samples
+    pub fn weight_ms(&self, weight: f64) -> f64 {
+        match self.mode {
+            ProfilingMode::Sampling { interval_ms } => weight * interval_ms as f64,
+            _ => weight,
+        }
+    }
+
+    /// Functions with the most self time since `earlier`, an older snapshot of the same profile
+    ///
+    /// Without an earlier snapshot this covers the whole profile. Functions
+    /// that took no time in between are left out.
+    pub fn hottest_since(&self, earlier: Option<&ProfileData>, limit: usize) -> Vec<Hotspot> {
+        let mut hotspots: Vec<Hotspot> = self
+            .functions
+            .values()
+            .filter_map(|profile| {
+                let before = earlier.and_then(|e| e.functions.get(&profile.name));
+                let self_time = profile.self_time_ms - before.map_or(0.0, |b| b.self_time_ms);
+                (self_time > 0.0).then(|| Hotspot {
+                    name: profile.name.clone(),
+                    source: profile.source.clone(),
+                    line: profile.line_defined,
+                    self_time_ms: self.weight_ms(self_time),
+                    call_count: profile.call_count - before.map_or(0, |b| b.call_count),
+                })
+            })
+            .collect();
+        hotspots.sort_by(|a, b| b.self_time_ms.total_cmp(&a.self_time_ms).then_with(|| a.name.cmp(&b.name)));
+        hotspots.truncate(limit);
+        hotspots
+    }
+
+    /// Rebuilds the call tree from the stacks, for flame graphs
+    ///
+    /// The root is unnamed and holds the outermost calls. Weights are in the
+    /// stacks' unit: milliseconds, or samples in sampling mode.
+    pub fn call_tree(&self) -> CallTreeNode {
+        let mut root = CallTreeNode::default();
+        for stack in &self.stacks {
+            root.insert(&stack.frames, stack.weight);
+        }
+        root
+    }
+}
+
+/// Function on a stack captured by a wall-clock sample
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampledFrame {
+    pub name: String,
+    pub source: Option<String>,
+    pub line_defined: u32,
+}
+
+/// A call the profiler has seen enter but not yet return
+struct ActiveCall {
+    name: String,
+    start: Instant,
+    /// Time spent in callees that have returned, in milliseconds
+    child_time_ms: f64,
 }
 
 /// Runtime profiler that tracks function calls and timing
@@ -58,9 +198,11 @@ pub struct Profiler {
     mode: ProfilingMode,
     start_time: Instant,
     /// Stack of currently executing functions with start times
-    current_stack: Vec<(String, Instant)>,
+    current_stack: Vec<ActiveCall>,
     /// Accumulated profile data
     functions: HashMap<String, FunctionProfile>,
+    /// Weight of each call stack seen, keyed by its frames
+    stacks: HashMap<Vec<String>, f64>,
     /// Sample counter (incremented on each hook event for sampling mode)
     sample_count: u64,
 }
@@ -73,13 +215,18 @@ impl Profiler {
             start_time: Instant::now(),
             current_stack: Vec::new(),
             functions: HashMap::new(),
+            stacks: HashMap::new(),
             sample_count: 0,
         }
     }
 
     /// Record a function call
     pub fn on_call(&mut self, name: String, source: Option<String>, line: u32) {
-        self.current_stack.push((name.clone(), Instant::now()));
+        self.current_stack.push(ActiveCall {
+            name: name.clone(),
+            start: Instant::now(),
+            child_time_ms: 0.0,
+        });
 
         let profile = self.functions.entry(name.clone()).or_insert(FunctionProfile {
             name,
@@ -89,23 +236,47 @@ impl Profiler {
             total_time_ms: 0.0,
             self_time_ms: 0.0,
             children: HashMap::new(),
+            callees: HashMap::new(),
         });
         profile.call_count += 1;
     }
 
     /// Record a function return
     pub fn on_return(&mut self) {
-        if let Some((name, start)) = self.current_stack.pop() {
-            let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+        if let Some(call) = self.current_stack.pop() {
+            let elapsed = call.start.elapsed().as_secs_f64() * 1000.0;
+            let self_time = (elapsed - call.child_time_ms).max(0.0);
+            // Only call tracing times calls; samples weigh self time in sampling mode
+            let timed = !matches!(self.mode, ProfilingMode::Sampling { .. });
+            // A recursive call's time is already inside the outer call's total
+            let recursive = self.current_stack.iter().any(|active| active.name == call.name);
+
+            if let Some(profile) = self.functions.get_mut(&call.name) {
+                if !recursive {
+                    profile.total_time_ms += elapsed;
+                }
+                if timed {
+                    profile.self_time_ms += self_time;
+                }
+            }
 
-            if let Some(profile) = self.functions.get_mut(&name) {
-                profile.total_time_ms += elapsed;
+            if timed {
+                let mut stack = self.stack_names();
+                stack.push(call.name.clone());
+                *self.stacks.entry(stack).or_insert(0.0) += self_time;
             }
 
             // Track parent-child relationship
-            if let Some((parent_name, _)) = self.current_stack.last() {
-                if let Some(parent) = self.functions.get_mut(parent_name) {
-                    *parent.children.entry(name).or_insert(0) += 1;
+            if let Some(parent_call) = self.current_stack.last_mut() {
+                parent_call.child_time_ms += elapsed;
+                if let Some(parent) = self.functions.get_mut(&parent_call.name) {
+                    *parent.children.entry(call.name.clone()).or_insert(0) += 1;
+                    let edge = parent.callees.entry(call.name).or_default();
+                    edge.call_count += 1;
+                    edge.total_time_ms += elapsed;
+                    if timed {
+                        edge.self_time_ms += self_time;
+                    }
                 }
             }
         }
@@ -116,18 +287,84 @@ impl Profiler {
         self.sample_count += 1;
 
         // Record current stack for sampling mode
-        if let Some((name, _)) = self.current_stack.last() {
-            if let Some(profile) = self.functions.get_mut(name) {
+        if let Some(call) = self.current_stack.last() {
+            if let Some(profile) = self.functions.get_mut(&call.name) {
                 profile.self_time_ms += 1.0; // Sample weight
             }
+            let stack = self.stack_names();
+            *self.stacks.entry(stack).or_insert(0.0) += 1.0;
         }
     }
 
+    /// Record a captured call stack, outermost frame first (for wall-clock mode)
+    ///
+    /// `weight_ms` is the time the sample stands for. It is added to the
+    /// total time of every function on the stack, once even if the function
+    /// recurses, and to the self time of the innermost one.
+    pub fn on_stack_sample(&mut self, frames: &[SampledFrame], weight_ms: f64) {
+        self.sample_count += 1;
+        let Some(innermost) = frames.last() else {
+            return;
+        };
+
+        let mut counted: Vec<&str> = Vec::new();
+        for frame in frames {
+            let profile = self.functions.entry(frame.name.clone()).or_insert_with(|| FunctionProfile {
+                name: frame.name.clone(),
+                source: frame.source.clone(),
+                line_defined: frame.line_defined,
+                call_count: 0,
+                total_time_ms: 0.0,
+                self_time_ms: 0.0,
+                children: HashMap::new(),
+                callees: HashMap::new(),
+            });
+            if !counted.contains(&frame.name.as_str()) {
+                profile.total_time_ms += weight_ms;
+                counted.push(&frame.name);
+            }
+        }
+        if let Some(profile) = self.functions.get_mut(&innermost.name) {
+            profile.self_time_ms += weight_ms;
+        }
+
+        for (index, pair) in frames.windows(2).enumerate() {
+            if let Some(parent) = self.functions.get_mut(&pair[0].name) {
+                let edge = parent.callees.entry(pair[1].name.clone()).or_default();
+                edge.total_time_ms += weight_ms;
+                if index + 2 == frames.len() {
+                    edge.self_time_ms += weight_ms;
+                }
+            }
+        }
+
+        let stack = frames.iter().map(|frame| frame.name.clone()).collect();
+        *self.stacks.entry(stack).or_insert(0.0) += weight_ms;
+    }
+
+    fn stack_names(&self) -> Vec<String> {
+        self.current_stack.iter().map(|call| call.name.clone()).collect()
+    }
+
+    fn stack_profiles(&self) -> Vec<StackProfile> {
+        let mut stacks: Vec<StackProfile> = self
+            .stacks
+            .iter()
+            .map(|(frames, weight)| StackProfile {
+                frames: frames.clone(),
+                weight: *weight,
+            })
+            .collect();
+        stacks.sort_by(|a, b| a.frames.cmp(&b.frames));
+        stacks
+    }
+
     /// Finish profiling and return the collected data (consumes self)
     pub fn finish(self) -> ProfileData {
         ProfileData {
             mode: self.mode,
             duration_ms: self.start_time.elapsed().as_secs_f64() * 1000.0,
+            stacks: self.stack_profiles(),
             functions: self.functions,
             total_samples: self.sample_count,
         }
@@ -140,6 +377,7 @@ impl Profiler {
             duration_ms: self.start_time.elapsed().as_secs_f64() * 1000.0,
             functions: self.functions.clone(),
             total_samples: self.sample_count,
+            stacks: self.stack_profiles(),
         }
     }
 
@@ -216,4 +454,123 @@ mod tests {
         assert!(data.duration_ms >= 0.0);
         assert!(data.functions.contains_key("foo"));
     }
+
+    #[test]
+    fn test_stacks_are_recorded() {
+        let mut profiler = Profiler::new(ProfilingMode::CallTrace);
+
+        profiler.on_call("main".to_string(), None, 1);
+        profiler.on_call("update".to_string(), None, 10);
+        profiler.on_return();
+        profiler.on_return();
+
+        let data = profiler.finish();
+        let frames: Vec<_> = data.stacks.iter().map(|s| s.frames.join(";")).collect();
+        assert_eq!(frames, vec!["main", "main;update"]);
+        assert!(data.stacks.iter().all(|s| s.weight >= 0.0));
+    }
+
+    #[test]
+    fn test_self_time_excludes_callees() {
+        let mut profiler = Profiler::new(ProfilingMode::CallTrace);
+
+        profiler.on_call("main".to_string(), None, 1);
+        profiler.on_call("update".to_string(), None, 10);
+        std::thread::sleep(Duration::from_millis(5));
+        profiler.on_return();
+        profiler.on_return();
+
+        let data = profiler.finish();
+        let main = &data.functions["main"];
+        let update = &data.functions["update"];
+        assert!(update.self_time_ms >= 5.0);
+        assert!(main.self_time_ms < update.self_time_ms);
+        assert!((main.self_time_ms + update.total_time_ms - main.total_time_ms).abs() < 1e-9);
+
+        let edge = &main.callees["update"];
+        assert_eq!(edge.call_count, 1);
+        assert_eq!(edge.total_time_ms, update.total_time_ms);
+    }
+
+    #[test]
+    fn test_wall_clock_samples_weigh_whole_stack() {
+        let mut profiler = Profiler::new(ProfilingMode::WallClock { interval_ms: 5 });
+        let frame = |name: &str| SampledFrame {
+            name: name.to_string(),
+            source: Some("@main.lua".to_string()),
+            line_defined: 1,
+        };
+
+        profiler.on_stack_sample(&[frame("main"), frame("walk"), frame("walk")], 10.0);
+        profiler.on_stack_sample(&[frame("main"), frame("draw")], 5.0);
+
+        let data = profiler.finish();
+        assert_eq!(data.total_samples, 2);
+        assert_eq!(data.functions["main"].total_time_ms, 15.0);
+        assert_eq!(data.functions["main"].self_time_ms, 0.0);
+        assert_eq!(data.functions["walk"].total_time_ms, 10.0);
+        assert_eq!(data.functions["walk"].self_time_ms, 10.0);
+        assert_eq!(data.functions["main"].callees["draw"].self_time_ms, 5.0);
+        assert_eq!(data.call_tree().total, 15.0);
+    }
+
+    #[test]
+    fn test_hottest_since_earlier_snapshot() {
+        let mut profiler = Profiler::new(ProfilingMode::Sampling { interval_ms: 2 });
+        profiler.on_call("update".to_string(), None, 10);
+        profiler.on_sample();
+        let earlier = profiler.to_profile_data();
+
+        profiler.on_call("draw".to_string(), None, 20);
+        profiler.on_sample();
+        profiler.on_sample();
+        profiler.on_return();
+        profiler.on_sample();
+        let data = profiler.to_profile_data();
+
+        let hottest = data.hottest_since(Some(&earlier), 5);
+        let summary: Vec<_> = hottest.iter().map(|h| (h.name.as_str(), h.self_time_ms, h.call_count)).collect();
+        assert_eq!(summary, vec![("draw", 4.0, 1), ("update", 2.0, 0)]);
+        let whole = data.hottest_since(None, 1);
+        assert_eq!((whole.len(), whole[0].self_time_ms), (1, 4.0));
+    }
+
+    #[test]
+    fn test_recursive_calls_count_total_time_once() {
+        let mut profiler = Profiler::new(ProfilingMode::CallTrace);
+
+        profiler.on_call("walk".to_string(), None, 1);
+        profiler.on_call("walk".to_string(), None, 1);
+        std::thread::sleep(Duration::from_millis(2));
+        profiler.on_return();
+        profiler.on_return();
+
+        let data = profiler.finish();
+        let walk = &data.functions["walk"];
+        assert_eq!(walk.call_count, 2);
+        assert!(walk.total_time_ms <= data.duration_ms);
+        assert!((walk.self_time_ms - walk.total_time_ms).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_call_tree_from_stacks() {
+        let stack = |frames: &[&str], weight: f64| StackProfile {
+            frames: frames.iter().map(|f| f.to_string()).collect(),
+            weight,
+        };
+        let data = ProfileData {
+            mode: ProfilingMode::CallTrace,
+            duration_ms: 10.0,
+            functions: HashMap::new(),
+            total_samples: 0,
+            stacks: vec![stack(&["main"], 1.0), stack(&["main", "update"], 3.0), stack(&["main", "draw"], 2.0)],
+        };
+
+        let tree = data.call_tree();
+        assert_eq!(tree.total, 6.0);
+        let main = &tree.children[0];
+        assert_eq!((main.total, main.self_weight), (6.0, 1.0));
+        let children: Vec<_> = main.children.iter().map(|c| (c.name.as_str(), c.total)).collect();
+        assert_eq!(children, vec![("draw", 2.0), ("update", 3.0)]);
+    }
 }
@@ -0,0 +1,291 @@
+//! Runtimes chosen while the server runs
+//!
+//! A `DapServer` is generic over its runtime, but a server that launches
+//! programs in-process and attaches to the agents of programs run in the
+//! client's terminal needs both `PUCLuaRuntime` and `RemoteRuntime`. It uses
+//! `Box<dyn DebugRuntime>`, which forwards every call to the boxed runtime.
+//! Methods with a default in the trait must be forwarded here too, or the
+//! box answers with the default instead of the runtime it holds.
+
+use super::*;
+use crate::debug::execution_trace::TraceEntry;
+use crate::debug::flight_recorder::FlightRecord;
+use crate::debug::memory_references::MemoryRead;
+use crate::debug::modules::Module;
+use crate::debug::watchpoints::DataBreakpoint;
+
+#[async_trait::async_trait]
+impl<T: DebugRuntime + ?Sized> DebugRuntime for Box<T> {
+    async fn version(&self) -> RuntimeVersion {
+        (**self).version().await
+    }
+
+    async fn set_breakpoint(&mut self, breakpoint: BreakpointType) -> Result<Breakpoint> {
+        (**self).set_breakpoint(breakpoint).await
+    }
+
+    async fn remove_breakpoint(&mut self, id: i64) -> Result<()> {
+        (**self).remove_breakpoint(id).await
+    }
+
+    async fn step(&mut self, mode: StepMode) -> Result<()> {
+        (**self).step(mode).await
+    }
+
+    async fn step_instruction(&mut self, mode: StepMode) -> Result<()> {
+        (**self).step_instruction(mode).await
+    }
+
+    async fn continue_(&mut self) -> Result<()> {
+        (**self).continue_().await
+    }
+
+    async fn step_back(&mut self) -> Result<()> {
+        (**self).step_back().await
+    }
+
+    async fn reverse_continue(&mut self) -> Result<()> {
+        (**self).reverse_continue().await
+    }
+
+    async fn step_frame(&mut self, function: &str, until: FrameStepTarget) -> Result<()> {
+        (**self).step_frame(function, until).await
+    }
+
+    async fn pause(&mut self) -> Result<()> {
+        (**self).pause().await
+    }
+
+    async fn pause_thread(&mut self, thread_id: u64) -> Result<()> {
+        (**self).pause_thread(thread_id).await
+    }
+
+    async fn continue_thread(&mut self, thread_id: u64) -> Result<bool> {
+        (**self).continue_thread(thread_id).await
+    }
+
+    async fn threads(&mut self) -> Result<Vec<Thread>> {
+        (**self).threads().await
+    }
+
+    async fn stack_trace(&mut self, thread_id: Option<u64>) -> Result<Vec<Frame>> {
+        (**self).stack_trace(thread_id).await
+    }
+
+    async fn scopes(&mut self, frame_id: i64) -> Result<Vec<Scope>> {
+        (**self).scopes(frame_id).await
+    }
+
+    async fn variables(&mut self, variables_reference: i64, filter: Option<VariableScope>) -> Result<Vec<Variable>> {
+        (**self).variables(variables_reference, filter).await
+    }
+
+    async fn variables_page(&mut self, variables_reference: i64, page: VariablesPage) -> Result<Vec<Variable>> {
+        (**self).variables_page(variables_reference, page).await
+    }
+
+    async fn evaluate(&mut self, frame_id: i64, expression: &str) -> Result<Value> {
+        (**self).evaluate(frame_id, expression).await
+    }
+
+    async fn set_variable(&mut self, variables_reference: i64, name: &str, value: &str) -> Result<Variable> {
+        (**self).set_variable(variables_reference, name, value).await
+    }
+
+    async fn evaluate_global(&mut self, expression: &str) -> Result<Value> {
+        (**self).evaluate_global(expression).await
+    }
+
+    async fn evaluate_variable(&mut self, frame_id: Option<i64>, expression: &str, format: ValueFormat) -> Result<Variable> {
+        (**self).evaluate_variable(frame_id, expression, format).await
+    }
+
+    async fn evaluate_literal(&mut self, frame_id: Option<i64>, expression: &str) -> Result<String> {
+        (**self).evaluate_literal(frame_id, expression).await
+    }
+
+    async fn run_to_location(&mut self, source: &str, line: u32) -> Result<()> {
+        (**self).run_to_location(source, line).await
+    }
+
+    async fn source(&mut self, source_reference: i64) -> Result<String> {
+        (**self).source(source_reference).await
+    }
+
+    async fn code_lines(&mut self, source: &str) -> Result<Vec<u32>> {
+        (**self).code_lines(source).await
+    }
+
+    async fn goto_lines(&mut self, source: &str) -> Result<Vec<u32>> {
+        (**self).goto_lines(source).await
+    }
+
+    async fn read_memory(&mut self, memory_reference: &str, offset: i64, count: usize) -> Result<MemoryRead> {
+        (**self).read_memory(memory_reference, offset, count).await
+    }
+
+    async fn write_memory(&mut self, memory_reference: &str, offset: i64, data: Vec<u8>, allow_partial: bool) -> Result<usize> {
+        (**self).write_memory(memory_reference, offset, data, allow_partial).await
+    }
+
+    async fn check_data_breakpoints(&mut self, frame_id: i64) -> Result<bool> {
+        (**self).check_data_breakpoints(frame_id).await
+    }
+
+    async fn set_data_breakpoints(&mut self, breakpoints: Vec<DataBreakpoint>) -> Result<Vec<DataBreakpoint>> {
+        (**self).set_data_breakpoints(breakpoints).await
+    }
+
+    async fn get_exception_info(&mut self, thread_id: u64) -> Result<ExceptionInfo> {
+        (**self).get_exception_info(thread_id).await
+    }
+
+    async fn flight_records(&self) -> Result<Vec<FlightRecord>> {
+        (**self).flight_records().await
+    }
+
+    async fn execution_trace(&self) -> Result<Vec<TraceEntry>> {
+        (**self).execution_trace().await
+    }
+
+    async fn modules(&mut self) -> Result<Vec<Module>> {
+        (**self).modules().await
+    }
+
+    async fn search_heap(
+        &mut self,
+        predicate: &str,
+        max_tables: usize,
+        max_results: usize,
+    ) -> Result<crate::memory::HeapSearchResult> {
+        (**self).search_heap(predicate, max_tables, max_results).await
+    }
+
+    async fn hot_reload(
+        &mut self,
+        module_source: &str,
+        module_name: Option<&str>,
+    ) -> Result<crate::hot_reload::HotReloadResult> {
+        (**self).hot_reload(module_source, module_name).await
+    }
+
+    async fn get_memory_statistics(&self) -> Result<crate::memory::MemoryStatistics> {
+        (**self).get_memory_statistics().await
+    }
+
+    async fn gc_step(&mut self, kb: u32) -> Result<bool> {
+        (**self).gc_step(kb).await
+    }
+
+    async fn tune_gc(&mut self, pause: Option<i32>, step_mul: Option<i32>) -> Result<crate::memory::MemoryStatistics> {
+        (**self).tune_gc(pause, step_mul).await
+    }
+
+    async fn set_allocation_tracking(&mut self, enabled: bool) -> Result<()> {
+        (**self).set_allocation_tracking(enabled).await
+    }
+
+    async fn allocation_profile(&mut self, limit: usize, reset: bool) -> Result<crate::memory::AllocationProfile> {
+        (**self).allocation_profile(limit, reset).await
+    }
+
+    async fn take_heap_snapshot(&mut self) -> Result<crate::memory::HeapSnapshot> {
+        (**self).take_heap_snapshot().await
+    }
+
+    async fn force_gc(&mut self) -> Result<()> {
+        (**self).force_gc().await
+    }
+
+    async fn start_profiling(&mut self, mode: crate::profiling::ProfilingMode) -> Result<()> {
+        (**self).start_profiling(mode).await
+    }
+
+    async fn stop_profiling(&mut self) -> Result<crate::profiling::ProfileData> {
+        (**self).stop_profiling().await
+    }
+
+    async fn get_profile_snapshot(&self) -> Result<Option<crate::profiling::ProfileData>> {
+        (**self).get_profile_snapshot().await
+    }
+
+    async fn launch_program(&mut self, launch: &ProgramLaunch) -> Result<()> {
+        (**self).launch_program(launch).await
+    }
+
+    fn has_program(&self) -> bool {
+        (**self).has_program()
+    }
+
+    fn lua_backend(&self) -> Option<LuaBackend> {
+        (**self).lua_backend()
+    }
+
+    async fn start_program(&mut self, stop_on_entry: bool) -> Result<()> {
+        (**self).start_program(stop_on_entry).await
+    }
+
+    async fn restart(&mut self) -> Result<()> {
+        (**self).restart().await
+    }
+
+    async fn detach(&mut self) -> Result<()> {
+        (**self).detach().await
+    }
+
+    fn set_event_sender(&mut self, sender: crate::dap::EventSender) {
+        (**self).set_event_sender(sender)
+    }
+
+    fn set_string_encoding(&mut self, encoding: Option<&'static encoding_rs::Encoding>) {
+        (**self).set_string_encoding(encoding)
+    }
+
+    fn set_memory_limit(&mut self, limit_kb: Option<u64>) {
+        (**self).set_memory_limit(limit_kb)
+    }
+
+    fn set_stitch_coroutine_stacks(&mut self, enabled: bool) {
+        (**self).set_stitch_coroutine_stacks(enabled)
+    }
+
+    fn set_record_replay(&mut self, enabled: bool) {
+        (**self).set_record_replay(enabled)
+    }
+
+    fn set_execution_trace_lines(&mut self, lines: usize) {
+        (**self).set_execution_trace_lines(lines)
+    }
+
+    fn set_evaluation_config(&mut self, config: &crate::config::DebuggerConfig) {
+        (**self).set_evaluation_config(config)
+    }
+
+    fn set_display_limits(&mut self, limits: super::DisplayLimits) {
+        (**self).set_display_limits(limits)
+    }
+
+    fn lock_states(&self) -> Vec<crate::internals::LockState> {
+        (**self).lock_states()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::mock::{self, MockRuntime, MockStop};
+
+    #[tokio::test]
+    async fn test_box_forwards_to_its_runtime() {
+        let mock = MockRuntime::new();
+        mock.queue_stop(MockStop::new("breakpoint", vec![mock::frame(3, "update", "/game/main.lua", 7)]));
+        let mut runtime: Box<dyn DebugRuntime> = Box::new(mock.clone());
+
+        runtime.start_program(false).await.unwrap();
+        let frames = runtime.stack_trace(None).await.unwrap();
+        assert_eq!((frames[0].name.as_str(), frames[0].line), ("update", 7));
+        // A defaulted method reaches the mock's own version
+        let watched = runtime.set_data_breakpoints(Vec::new()).await;
+        assert!(watched.is_ok());
+    }
+}
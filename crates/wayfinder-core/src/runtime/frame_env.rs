@@ -0,0 +1,180 @@
+//! Evaluating code with a stack frame's variables in scope
+//!
+//! Code typed in the debug console is compiled as a chunk of its own, so on
+//! its own it only sees globals. To see the frame's locals and upvalues, the
+//! chunk runs with an environment that reads them first and falls back to
+//! the globals: `_ENV` is replaced from Lua 5.2 on and `setfenv` is used on
+//! Lua 5.1. Locals that are nil still hide a global of the same name, since
+//! the environment knows which names the frame declares rather than which
+//! have values. With write-back, what the code assigns to those names is
+//! stored back into the frame afterwards.
+
+use super::lua_ffi::*;
+use super::lua_state::Lua;
+use super::sandbox::{self, EvaluationError, EvaluationLimits};
+use libc::c_int;
+
+/// Builds the environment's `__index` and `__newindex` from the globals,
+/// the frame's values and the set of names it declares
+const ENV_ACCESSORS: &str = r#"
+local globals, values, declared = ...
+local function index(_, name)
+    if declared[name] then
+        return values[name]
+    end
+    return globals[name]
+end
+local function newindex(_, name, value)
+    if declared[name] then
+        values[name] = value
+    else
+        globals[name] = value
+    end
+end
+return index, newindex
+"#;
+
+/// Where each name the frame declares lives
+enum Slot {
+    Local(c_int),
+    Upvalue(c_int),
+}
+
+/// Whether `thread` has a frame at `level`
+pub fn has_frame(thread: &Lua, level: c_int) -> bool {
+    let mut ar = unsafe { std::mem::zeroed::<lua_Debug>() };
+    thread.get_stack(level, &mut ar) != 0
+}
+
+/// Runs `code` with the variables of the frame at `level` of `thread` in scope, within `limits`
+///
+/// Leaves the code's first result on top of `lua`'s stack, along with the
+/// values used to run it; callers restore the stack top. Code and values run
+/// on `lua`, which may be another thread than the frame's.
+pub fn evaluate_in_frame(
+    lua: &mut Lua,
+    thread: &mut Lua,
+    level: c_int,
+    code: &str,
+    write_back: bool,
+    limits: &EvaluationLimits,
+) -> Result<(), EvaluationError> {
+    let mut ar = unsafe { std::mem::zeroed::<lua_Debug>() };
+    if thread.get_stack(level, &mut ar) == 0 {
+        return Err(EvaluationError::Failed(format!("No frame at level {}", level)));
+    }
+
+    lua.create_table(0, 0);
+    let values = lua.get_top();
+    lua.create_table(0, 0);
+    let declared = lua.get_top();
+    let slots = collect_variables(lua, thread, &mut ar, values, declared);
+
+    lua.create_table(0, 0);
+    let env = lua.get_top();
+    lua.create_table(0, 2);
+    lua.load_string(ENV_ACCESSORS).map_err(EvaluationError::Failed)?;
+    lua.lua_pushglobaltable();
+    lua.lua_pushvalue(values);
+    lua.lua_pushvalue(declared);
+    lua.pcall(3, 2).map_err(EvaluationError::Failed)?;
+    lua.set_field(-3, "__newindex");
+    lua.set_field(-2, "__index");
+    lua.set_metatable(env);
+
+    lua.load_string(code).map_err(EvaluationError::Syntax)?;
+    set_environment(lua, env).map_err(EvaluationError::Failed)?;
+    sandbox::call(lua, limits)?;
+
+    if write_back {
+        let result = lua.get_top();
+        store_variables(lua, thread, &mut ar, values, &slots);
+        lua.set_top(result);
+    }
+    Ok(())
+}
+
+/// Fills `values` and `declared` with the frame's locals and upvalues, locals hiding upvalues
+///
+/// The last local with a name is the one in scope; locals such as
+/// `(temporary)` are internal to Lua and left out.
+fn collect_variables(lua: &mut Lua, thread: &mut Lua, ar: &mut lua_Debug, values: c_int, declared: c_int) -> Vec<(String, Slot)> {
+    let mut slots: Vec<(String, Slot)> = Vec::new();
+    let mut index = 1;
+    while let Some(name) = thread.get_local(ar, index) {
+        if name.starts_with('(') {
+            thread.set_top(-2);
+        } else {
+            declare(lua, thread, values, declared, &name);
+            slots.retain(|(declared_name, _)| *declared_name != name);
+            slots.push((name, Slot::Local(index)));
+        }
+        index += 1;
+    }
+
+    if thread.get_info("f", ar) != 0 {
+        let mut index = 1;
+        while let Some(name) = thread.get_upvalue(-1, index) {
+            if slots.iter().any(|(declared_name, _)| *declared_name == name) {
+                thread.set_top(-2);
+            } else {
+                declare(lua, thread, values, declared, &name);
+                slots.push((name, Slot::Upvalue(index)));
+            }
+            index += 1;
+        }
+        // The frame's function
+        thread.set_top(-2);
+    }
+    slots
+}
+
+/// Moves the value on top of `thread` into `values[name]` and marks `name` declared
+fn declare(lua: &mut Lua, thread: &mut Lua, values: c_int, declared: c_int, name: &str) {
+    // Moving within one thread leaves the value where it is
+    thread.xmove(lua, 1);
+    lua.set_field(values, name);
+    lua.push_boolean(true);
+    lua.set_field(declared, name);
+}
+
+/// Stores `values` back into the frame's locals and upvalues
+fn store_variables(lua: &mut Lua, thread: &mut Lua, ar: &mut lua_Debug, values: c_int, slots: &[(String, Slot)]) {
+    let has_function = thread.get_info("f", ar) != 0;
+    for (name, slot) in slots {
+        match *slot {
+            Slot::Local(index) => {
+                lua.get_field(values, name);
+                lua.xmove(thread, 1);
+                thread.set_local(ar, index);
+            }
+            Slot::Upvalue(index) if has_function => {
+                lua.get_field(values, name);
+                lua.xmove(thread, 1);
+                thread.set_upvalue(-2, index);
+            }
+            Slot::Upvalue(_) => {}
+        }
+    }
+    if has_function {
+        thread.set_top(-2);
+    }
+}
+
+/// Makes the table at `env` the environment of the function on top of the stack
+fn set_environment(lua: &mut Lua, env: c_int) -> Result<(), String> {
+    lua.lua_pushvalue(env);
+    // From Lua 5.2 on, a chunk's only upvalue is `_ENV`
+    if lua.set_upvalue(-2, 1).is_some() {
+        return Ok(());
+    }
+    lua.set_top(-2);
+    lua.get_global("setfenv");
+    if !lua.is_function(-1) {
+        return Err("Cannot give the expression the frame's variables: setfenv is missing".to_string());
+    }
+    lua.lua_pushvalue(-2);
+    lua.lua_pushvalue(env);
+    lua.pcall(2, 0)?;
+    Ok(())
+}
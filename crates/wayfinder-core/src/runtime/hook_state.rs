@@ -0,0 +1,220 @@
+//! Debugger state shared between a runtime and its hook callback
+//!
+//! Lua hooks are plain C callbacks that only receive the `lua_State` they fire
+//! on, so the state they act on is looked up in a registry keyed by the address
+//! of the main thread. Every runtime registers its own entry, which keeps two
+//! sessions in one process from pausing or stepping each other. Hooks firing
+//! inside a coroutine resolve to the entry of the coroutine's main thread, in
+//! builds that link Lua statically.
+
+use super::lua_ffi::*;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Pause and stepping flags of one Lua state
+#[derive(Debug)]
+pub struct HookState {
+    pub paused: AtomicBool,
+    pub should_step: AtomicBool,
+    /// Set by the hook when a step request completed
+    pub step_triggered: AtomicBool,
+    pub step_mode: AtomicUsize,
+    pub step_depth: AtomicUsize,
+    /// Whether the pending step stops at the next instruction rather than the next line
+    pub instruction_step: AtomicBool,
+    /// Line an instruction step last stopped at, 0 once the hook has moved on
+    ///
+    /// The line event for an instruction that starts a line follows its count
+    /// event, and must not end a line step requested while stopped there.
+    pub instruction_stop_line: AtomicUsize,
+    pub current_line: AtomicUsize,
+    pub current_source: Mutex<Option<String>>,
+    /// One-shot stop location set by run to cursor, as (path, line)
+    pub run_to: Mutex<Option<(String, u32)>>,
+}
+
+impl HookState {
+    pub fn new() -> Self {
+        Self {
+            paused: AtomicBool::new(false),
+            should_step: AtomicBool::new(false),
+            step_triggered: AtomicBool::new(false),
+            step_mode: AtomicUsize::new(0),
+            step_depth: AtomicUsize::new(0),
+            instruction_step: AtomicBool::new(false),
+            instruction_stop_line: AtomicUsize::new(0),
+            current_line: AtomicUsize::new(1),
+            current_source: Mutex::new(None),
+            run_to: Mutex::new(None),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Clears the pause along with any pending or completed step
+    pub fn clear_pause(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.should_step.store(false, Ordering::SeqCst);
+        self.step_triggered.store(false, Ordering::SeqCst);
+        self.instruction_step.store(false, Ordering::SeqCst);
+    }
+
+    /// Records the line the hook is executing
+    pub fn set_location(&self, source: Option<String>, line: u32) {
+        self.current_line.store(line as usize, Ordering::SeqCst);
+        *self.current_source.lock().unwrap() = source;
+    }
+
+    pub fn current_line(&self) -> u32 {
+        self.current_line.load(Ordering::SeqCst) as u32
+    }
+
+    pub fn current_source(&self) -> Option<String> {
+        self.current_source.lock().unwrap().clone()
+    }
+
+    /// Returns true and clears the run to cursor location if it is the given one
+    pub fn take_run_to(&self, source: Option<&str>, line: u32) -> bool {
+        let mut run_to = self.run_to.lock().unwrap();
+        let hit = match (run_to.as_ref(), source) {
+            (Some((path, target)), Some(source)) => *target == line && source_matches(source, path),
+            _ => false,
+        };
+        if hit {
+            *run_to = None;
+        }
+        hit
+    }
+
+    /// Forgets the run to cursor location, for stops with another reason
+    pub fn cancel_run_to(&self) {
+        *self.run_to.lock().unwrap() = None;
+    }
+}
+
+/// Returns true if a chunk source names the file at `path`
+///
+/// Lua reports file chunks as `@path`, which may be relative while clients
+/// send absolute paths, so either side being a suffix of the other matches.
+pub fn source_matches(source: &str, path: &str) -> bool {
+    let source = source.strip_prefix('@').unwrap_or(source);
+    path.ends_with(source) || source.ends_with(path)
+}
+
+impl Default for HookState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hook state of every live runtime, keyed by main `lua_State` address
+pub struct HookRegistry<T> {
+    states: RwLock<HashMap<usize, Arc<T>>>,
+}
+
+impl<T> HookRegistry<T> {
+    pub fn new() -> Self {
+        Self {
+            states: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn register(&self, main: LuaState, state: Arc<T>) {
+        self.states.write().unwrap().insert(main as usize, state);
+    }
+
+    pub fn unregister(&self, main: LuaState) {
+        self.states.write().unwrap().remove(&(main as usize));
+    }
+
+    /// Finds the state for any thread of a registered Lua state
+    ///
+    /// Coroutines are resolved through the registry of the state they belong
+    /// to, which needs the Lua linked into Wayfinder. Builds without
+    /// `static-lua` only find the main threads that were registered, so hooks
+    /// firing inside a coroutine of a dynamically loaded Lua get `None`.
+    ///
+    /// # Safety
+    ///
+    /// `thread` must be a registered main thread or a live thread of a Lua
+    /// state, and no other OS thread may be running that state.
+    pub unsafe fn get(&self, thread: LuaState) -> Option<Arc<T>> {
+        let states = self.states.read().ok()?;
+        if let Some(state) = states.get(&(thread as usize)) {
+            return Some(state.clone());
+        }
+
+        #[cfg(feature = "static-lua")]
+        {
+            let main = main_thread(thread);
+            states.get(&(main as usize)).cloned()
+        }
+
+        #[cfg(not(feature = "static-lua"))]
+        None
+    }
+}
+
+impl<T> Default for HookRegistry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the main thread of the Lua state `thread` belongs to
+#[cfg(feature = "static-lua")]
+unsafe fn main_thread(L: LuaState) -> LuaState {
+    lua_rawgeti(L, LUA_REGISTRYINDEX, LUA_RIDX_MAINTHREAD);
+    let main = lua_tothread(L, -1);
+    lua_settop(L, -2);
+    main
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_states_are_independent() {
+        let registry = HookRegistry::new();
+        let first = Arc::new(HookState::new());
+        let second = Arc::new(HookState::new());
+        registry.register(0x10 as LuaState, first.clone());
+        registry.register(0x20 as LuaState, second.clone());
+
+        unsafe { registry.get(0x10 as LuaState) }.unwrap().paused.store(true, Ordering::SeqCst);
+        assert!(first.is_paused());
+        assert!(!second.is_paused());
+
+        registry.unregister(0x10 as LuaState);
+        assert!(unsafe { registry.get(0x20 as LuaState) }.is_some());
+    }
+
+    #[test]
+    fn test_run_to_location_is_one_shot() {
+        let state = HookState::new();
+        *state.run_to.lock().unwrap() = Some(("/game/main.lua".to_string(), 7));
+
+        assert!(!state.take_run_to(Some("@main.lua"), 6));
+        assert!(state.take_run_to(Some("@main.lua"), 7));
+        assert!(!state.take_run_to(Some("@main.lua"), 7));
+    }
+
+    #[test]
+    fn test_clear_pause_resets_stepping() {
+        let state = HookState::new();
+        state.paused.store(true, Ordering::SeqCst);
+        state.should_step.store(true, Ordering::SeqCst);
+        state.step_triggered.store(true, Ordering::SeqCst);
+        state.instruction_step.store(true, Ordering::SeqCst);
+
+        state.clear_pause();
+        assert!(!state.is_paused());
+        assert!(!state.should_step.load(Ordering::SeqCst));
+        assert!(!state.step_triggered.load(Ordering::SeqCst));
+        assert!(!state.instruction_step.load(Ordering::SeqCst));
+    }
+}
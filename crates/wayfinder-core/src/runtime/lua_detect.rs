@@ -0,0 +1,251 @@
+//! Finding which Lua version a program runs on
+//!
+//! Dynamic builds load the Lua library of the program's version, which users
+//! otherwise have to name. Without one configured, the version is detected:
+//! from the Lua libraries an attach target has loaded, from the banner `-v`
+//! prints for the `lua` executables on PATH, or, for a library whose name
+//! carries no version, from the functions it exports. Each finding is a
+//! `Candidate`; the loader tries them in order and lists them all if none of
+//! their libraries can be loaded.
+
+use super::LuaVersion;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Executables looked for on PATH, the unversioned `lua` first
+///
+/// The Windows builds of LuaBinaries name them without the dot, as `lua54.exe`.
+const EXECUTABLES: &[&str] = &[
+    "lua", "lua5.4", "lua5.3", "lua5.2", "lua5.1", "lua54", "lua53", "lua52", "lua51", "luajit",
+];
+
+/// A Lua version a program may run on, and how it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub version: LuaVersion,
+    /// Where the version was seen, such as "`lua -v`"
+    pub evidence: String,
+    /// The library the version was seen in, to be loaded instead of searching for one
+    pub library: Option<PathBuf>,
+}
+
+impl std::fmt::Display for Candidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Lua {} from {}", self.version, self.evidence)
+    }
+}
+
+/// The versions of the Lua executables on PATH, in the order of `EXECUTABLES`
+pub fn detect_on_path() -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for executable in EXECUTABLES {
+        if let Some(version) = executable_version(Path::new(executable)) {
+            candidates.push(Candidate {
+                version,
+                evidence: format!("`{} -v`", executable),
+                library: None,
+            });
+        }
+    }
+    candidates
+}
+
+/// The versions of Lua a running process has loaded
+///
+/// Reads the libraries the process maps, which needs `/proc` and so only
+/// finds anything on Linux. A process with Lua linked in statically shows
+/// no library; its executable is asked for `-v` only when it is named `lua`.
+pub fn detect_for_process(pid: u32) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    if let Ok(maps) = std::fs::read_to_string(format!("/proc/{}/maps", pid)) {
+        for library in mapped_libraries(&maps) {
+            let Some(version) = library_version(&library).or_else(|| probe_library(&library)) else {
+                continue;
+            };
+            if !candidates.iter().any(|candidate| candidate.library.as_ref() == Some(&library)) {
+                candidates.push(Candidate {
+                    version,
+                    evidence: format!("{} loaded by process {}", library.display(), pid),
+                    library: Some(library),
+                });
+            }
+        }
+    }
+    if candidates.is_empty() {
+        if let Ok(executable) = std::fs::read_link(format!("/proc/{}/exe", pid)) {
+            let is_lua = executable
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("lua"));
+            if let Some(version) = is_lua.then(|| executable_version(&executable)).flatten() {
+                candidates.push(Candidate {
+                    version,
+                    evidence: format!("`{} -v` of process {}", executable.display(), pid),
+                    library: None,
+                });
+            }
+        }
+    }
+    candidates
+}
+
+/// Runs `executable -v` and reads the version from its banner
+fn executable_version(executable: &Path) -> Option<LuaVersion> {
+    let output = Command::new(executable).arg("-v").output().ok()?;
+    // Lua 5.1 prints the banner to stderr
+    parse_banner(&String::from_utf8_lossy(&output.stdout))
+        .or_else(|| parse_banner(&String::from_utf8_lossy(&output.stderr)))
+}
+
+/// Reads the version from a banner such as `Lua 5.4.6  Copyright (C) 1994-2023 Lua.org, PUC-Rio`
+///
+/// LuaJIT implements the API of Lua 5.1.
+pub fn parse_banner(banner: &str) -> Option<LuaVersion> {
+    let banner = banner.trim_start();
+    if banner.starts_with("LuaJIT") {
+        return Some(LuaVersion::V51);
+    }
+    let version = banner.strip_prefix("Lua ")?;
+    parse_version(version.split_whitespace().next()?)
+}
+
+/// Reads `5.x` or `5.x.y`
+fn parse_version(version: &str) -> Option<LuaVersion> {
+    let mut parts = version.split('.');
+    if parts.next()? != "5" {
+        return None;
+    }
+    match parts.next()? {
+        "1" => Some(LuaVersion::V51),
+        "2" => Some(LuaVersion::V52),
+        "3" => Some(LuaVersion::V53),
+        "4" => Some(LuaVersion::V54),
+        _ => None,
+    }
+}
+
+/// The Lua libraries in the lines of a `/proc/<pid>/maps` file
+fn mapped_libraries(maps: &str) -> Vec<PathBuf> {
+    let mut libraries: Vec<PathBuf> = Vec::new();
+    for line in maps.lines() {
+        // The path is the sixth field and may contain spaces
+        let Some(path) = line.splitn(6, char::is_whitespace).nth(5).map(str::trim) else {
+            continue;
+        };
+        let path = PathBuf::from(path);
+        let is_lua = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("liblua") || name.starts_with("lua5"));
+        if is_lua && !libraries.contains(&path) {
+            libraries.push(path);
+        }
+    }
+    libraries
+}
+
+/// The version in a Lua library's file name, such as `liblua5.3.so.0`, `liblua54.so` or `lua51.dll`
+pub fn library_version(library: &Path) -> Option<LuaVersion> {
+    let name = library.file_name()?.to_str()?;
+    if name.starts_with("libluajit") {
+        return Some(LuaVersion::V51);
+    }
+    let rest = name.strip_prefix("lib").unwrap_or(name).strip_prefix("lua")?;
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+    match digits.trim_end_matches('.') {
+        "51" | "5.1" => Some(LuaVersion::V51),
+        "52" | "5.2" => Some(LuaVersion::V52),
+        "53" | "5.3" => Some(LuaVersion::V53),
+        "54" | "5.4" => Some(LuaVersion::V54),
+        // `liblua.so.5.3` puts the version after the extension
+        "" => name.split_once(".so.").and_then(|(_, version)| parse_version(version)),
+        _ => None,
+    }
+}
+
+/// The version of a library whose name does not tell, from the functions it exports
+#[cfg(feature = "dynamic-lua")]
+pub(crate) fn probe_library(library: &Path) -> Option<LuaVersion> {
+    // Lua libraries have no initializers to run as they load
+    let library = unsafe { libloading::Library::new(library) }.ok()?;
+    version_from_symbols(|name| unsafe { library.get::<*const ()>(format!("{}\0", name).as_bytes()) }.is_ok())
+}
+
+/// Static builds cannot open libraries to look at their exports
+#[cfg(not(feature = "dynamic-lua"))]
+pub(crate) fn probe_library(_library: &Path) -> Option<LuaVersion> {
+    None
+}
+
+/// The version of a library, from the functions it exports
+///
+/// Each version adds functions the one before lacks: `lua_pcallk` in 5.2,
+/// `lua_rotate` in 5.3 and `lua_newuserdatauv` in 5.4.
+pub fn version_from_symbols(exports: impl Fn(&str) -> bool) -> Option<LuaVersion> {
+    if !exports("lua_gettop") {
+        return None;
+    }
+    Some(if exports("lua_newuserdatauv") {
+        LuaVersion::V54
+    } else if exports("lua_rotate") {
+        LuaVersion::V53
+    } else if exports("lua_pcallk") {
+        LuaVersion::V52
+    } else {
+        LuaVersion::V51
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions_are_read_from_banners_and_names() {
+        assert_eq!(
+            parse_banner("Lua 5.4.6  Copyright (C) 1994-2023 Lua.org, PUC-Rio\n"),
+            Some(LuaVersion::V54)
+        );
+        assert_eq!(parse_banner("Lua 5.1.5  Copyright (C) 1994-2012 Lua.org, PUC-Rio"), Some(LuaVersion::V51));
+        assert_eq!(parse_banner("LuaJIT 2.1.0-beta3 -- Copyright (C) 2005-2017 Mike Pall."), Some(LuaVersion::V51));
+        assert_eq!(parse_banner("Lua 6.0"), None);
+        assert_eq!(parse_banner("bash: lua: command not found"), None);
+
+        let version = |name: &str| library_version(Path::new(name));
+        assert_eq!(version("/usr/lib/x86_64-linux-gnu/liblua5.3.so.0.0.0"), Some(LuaVersion::V53));
+        assert_eq!(version("/opt/homebrew/lib/liblua54.dylib"), Some(LuaVersion::V54));
+        assert_eq!(version("lua51.dll"), Some(LuaVersion::V51));
+        assert_eq!(version("/usr/lib/liblua.so.5.2"), Some(LuaVersion::V52));
+        assert_eq!(version("/usr/lib/liblua.so"), None);
+    }
+
+    #[test]
+    fn test_process_maps_list_lua_libraries_once() {
+        let maps = "\
+7f1c2a000000-7f1c2a020000 r--p 00000000 08:01 1234                       /usr/lib/x86_64-linux-gnu/liblua5.3.so.0.0.0
+7f1c2a020000-7f1c2a040000 r-xp 00020000 08:01 1234 /usr/lib/x86_64-linux-gnu/liblua5.3.so.0.0.0
+7f1c2b000000-7f1c2b020000 r-xp 00000000 08:01 5678 /usr/lib/x86_64-linux-gnu/libc.so.6
+7ffd5e000000-7ffd5e021000 rw-p 00000000 00:00 0 [stack]
+";
+        assert_eq!(
+            mapped_libraries(maps),
+            [PathBuf::from("/usr/lib/x86_64-linux-gnu/liblua5.3.so.0.0.0")]
+        );
+    }
+
+    #[test]
+    fn test_versions_are_told_apart_by_their_exports() {
+        let exporting = |names: &'static [&'static str]| move |name: &str| names.contains(&name);
+        assert_eq!(version_from_symbols(exporting(&["lua_gettop"])), Some(LuaVersion::V51));
+        assert_eq!(version_from_symbols(exporting(&["lua_gettop", "lua_pcallk"])), Some(LuaVersion::V52));
+        assert_eq!(
+            version_from_symbols(exporting(&["lua_gettop", "lua_pcallk", "lua_rotate"])),
+            Some(LuaVersion::V53)
+        );
+        assert_eq!(
+            version_from_symbols(exporting(&["lua_gettop", "lua_pcallk", "lua_rotate", "lua_newuserdatauv"])),
+            Some(LuaVersion::V54)
+        );
+        assert_eq!(version_from_symbols(exporting(&["malloc"])), None);
+    }
+}
@@ -6,6 +6,9 @@ pub use libc::{c_char, c_int, c_long, c_void, size_t};
 pub type LuaState = *mut c_void;
 pub type LuaCFunction = extern "C" fn(*mut c_void) -> c_int;
 pub type LuaHook = extern "C" fn(*mut c_void, *mut lua_Debug);
+pub type LuaWarnFunction = extern "C" fn(*mut c_void, *const c_char, c_int);
+/// Memory allocator of a state: (ud, ptr, osize, nsize) -> new block
+pub type LuaAlloc = unsafe extern "C" fn(*mut c_void, *mut c_void, size_t, size_t) -> *mut c_void;
 
 // These types follow Lua's official C API naming conventions
 #[allow(non_camel_case_types)]
@@ -13,6 +16,7 @@ pub type lua_Integer = i64;
 #[allow(non_camel_case_types)]
 pub type lua_Number = f64;
 
+/// Mirrors `lua_Debug` from Lua 5.4's lua.h, field for field
 #[repr(C)]
 pub struct lua_Debug {
     pub event: c_int,
@@ -20,22 +24,30 @@ pub struct lua_Debug {
     pub namewhat: *const c_char,
     pub what: *const c_char,
     pub source: *const c_char,
+    pub srclen: size_t,
     pub currentline: c_int,
     pub linedefined: c_int,
     pub lastlinedefined: c_int,
-    pub nups: c_int,
-    pub nparams: c_int,
-    pub isvararg: c_int,
+    pub nups: u8,
+    pub nparams: u8,
+    pub isvararg: c_char,
     pub istailcall: c_char,
-    pub short_src: [c_char; 60],
+    pub ftransfer: u16,
+    pub ntransfer: u16,
+    pub short_src: [c_char; LUA_IDSIZE],
     pub i_ci: *mut c_void,
 }
 
+/// Size of `lua_Debug::short_src`
+pub const LUA_IDSIZE: usize = 60;
+
 // Static linking mode: link against Lua library at build time
 #[cfg(feature = "static-lua")]
 #[link(name = "lua5.4")]
 extern "C" {
+    pub fn lua_newstate(f: LuaAlloc, ud: *mut c_void) -> LuaState;
     pub fn lua_close(L: LuaState);
+    pub fn lua_atpanic(L: LuaState, panicf: LuaCFunction) -> Option<LuaCFunction>;
     pub fn lua_newthread(L: LuaState) -> LuaState;
     pub fn lua_resetthread(L: LuaState) -> c_int;
 
@@ -52,6 +64,7 @@ extern "C" {
     pub fn lua_xmove(from: LuaState, to: LuaState, n: c_int);
 
     pub fn lua_isnumber(L: LuaState, idx: c_int) -> c_int;
+    pub fn lua_isinteger(L: LuaState, idx: c_int) -> c_int;
     pub fn lua_isstring(L: LuaState, idx: c_int) -> c_int;
     pub fn lua_iscfunction(L: LuaState, idx: c_int) -> c_int;
     pub fn lua_isuserdata(L: LuaState, idx: c_int) -> c_int;
@@ -77,6 +90,7 @@ extern "C" {
 
     pub fn lua_arith(L: LuaState, op: c_int);
     pub fn lua_len(L: LuaState, idx: c_int);
+    pub fn lua_rawlen(L: LuaState, idx: c_int) -> size_t;
     pub fn lua_concat(L: LuaState, n: c_int);
     pub fn lua_rawequal(L: LuaState, idx1: c_int, idx2: c_int) -> c_int;
     pub fn lua_compare(L: LuaState, idx1: c_int, idx2: c_int, op: c_int) -> c_int;
@@ -129,9 +143,7 @@ extern "C" {
     ) -> c_int;
     pub fn lua_dump(
         L: LuaState,
-        writer: Option<
-            unsafe extern "C" fn(*mut c_void, *const *const c_char, size_t, *mut c_void),
-        >,
+        writer: Option<unsafe extern "C" fn(LuaState, *const c_void, size_t, *mut c_void) -> c_int>,
         data: *mut c_void,
         strip: c_int,
     ) -> c_int;
@@ -141,7 +153,7 @@ extern "C" {
     pub fn lua_gc(L: LuaState, what: c_int, data: c_long, arg: c_int) -> c_int;
     pub fn lua_status(L: LuaState) -> c_int;
     pub fn lua_isyieldable(L: LuaState, idx: c_int) -> c_int;
-    pub fn lua_resume(L: LuaState, from: LuaState, narg: c_int) -> c_int;
+    pub fn lua_resume(L: LuaState, from: LuaState, narg: c_int, nres: *mut c_int) -> c_int;
     pub fn lua_yieldk(
         L: LuaState,
         nresults: c_int,
@@ -150,6 +162,7 @@ extern "C" {
     );
 
     pub fn lua_sethook(L: LuaState, f: LuaHook, mask: c_int, count: c_int);
+    pub fn lua_setwarnf(L: LuaState, f: Option<LuaWarnFunction>, ud: *mut c_void);
     pub fn lua_gethook(L: LuaState) -> LuaHook;
     pub fn lua_gethookmask(L: LuaState) -> c_int;
     pub fn lua_gethookcount(L: LuaState) -> c_int;
@@ -221,6 +234,7 @@ extern "C" {
     pub fn luaL_getmetafield(L: LuaState, obj: c_int, event: *const c_char) -> c_int;
     pub fn luaL_callmeta(L: LuaState, obj: c_int, event: *const c_char) -> c_int;
     pub fn luaL_len(L: LuaState, idx: c_int) -> i64;
+    pub fn luaL_tolstring(L: LuaState, idx: c_int, len: *mut size_t) -> *const c_char;
     pub fn luaL_gsub(
         L: LuaState,
         s: *const c_char,
@@ -271,7 +285,10 @@ pub const LUA_TFUNCTION: c_int = 6;
 pub const LUA_TUSERDATA: c_int = 7;
 pub const LUA_TTHREAD: c_int = 8;
 
-pub const LUA_REGISTRYINDEX: c_int = -1000000;
+/// `-LUAI_MAXSTACK - 1000` with the default stack limit of a million slots
+pub const LUA_REGISTRYINDEX: c_int = -1_001_000;
+pub const LUA_RIDX_MAINTHREAD: c_int = 1;
+pub const LUA_RIDX_GLOBALS: c_int = 2;
 
 pub const LUA_HOOKCALL: c_int = 0;
 pub const LUA_HOOKRET: c_int = 1;
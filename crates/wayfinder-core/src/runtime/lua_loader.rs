@@ -5,9 +5,10 @@
 
 #![allow(hidden_glob_reexports)]
 
+use super::lua_detect::Candidate;
 use super::LuaVersion;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
 use libloading::{Library, Symbol};
 
@@ -38,6 +39,30 @@ pub enum LoaderError {
 
     #[error("Unsupported Lua version: {0}")]
     UnsupportedVersion(String),
+
+    #[error("Could not find a usable Lua library: {0}")]
+    NotDetected(String),
+}
+
+static LIBRARY_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Sets the Lua library loaded in place of searching for one, from `luaLibraryPath`
+pub fn set_library_path(path: Option<PathBuf>) {
+    *LIBRARY_PATH.lock().unwrap() = path;
+}
+
+/// The library to load in place of searching: the `luaLibraryPath` setting, else `LUA_WAYFINDER_LIB`
+pub fn library_override() -> Option<PathBuf> {
+    let setting = LIBRARY_PATH.lock().unwrap().clone();
+    setting.or_else(|| std::env::var_os(super::LUA_LIBRARY_ENV_VAR).filter(|path| !path.is_empty()).map(PathBuf::from))
+}
+
+/// Lua C API functions of the Lua linked in by the `embedded-lua` feature
+#[cfg(feature = "embedded-lua")]
+mod embedded {
+    extern "C" {
+        pub fn luaL_newstate() -> super::LuaState;
+    }
 }
 
 /// Dynamically loaded Lua library
@@ -53,11 +78,16 @@ pub struct LuaLibrary {
 struct LuaLibraryInner {
     _lib: Library,
     version: LuaVersion,
+    /// File the library was loaded from; None for the Lua linked into Wayfinder
+    path: Option<PathBuf>,
 
     // Core API functions - required in all versions
     lua_close: Symbol<'static, unsafe extern "C" fn(LuaState)>,
     lua_newthread: Symbol<'static, unsafe extern "C" fn(LuaState) -> LuaState>,
     lua_gettop: Symbol<'static, unsafe extern "C" fn(LuaState) -> c_int>,
+    lua_xmove: Symbol<'static, unsafe extern "C" fn(LuaState, LuaState, c_int)>,
+    lua_status: Symbol<'static, unsafe extern "C" fn(LuaState) -> c_int>,
+    lua_tothread: Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> LuaState>,
     lua_settop: Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> c_int>,
     lua_pushvalue: Symbol<'static, unsafe extern "C" fn(LuaState, c_int)>,
     lua_type: Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> c_int>,
@@ -85,6 +115,7 @@ struct LuaLibraryInner {
     lua_getmetatable: Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> c_int>,
     lua_setmetatable: Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> c_int>,
     lua_next: Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> c_int>,
+    lua_gc: Symbol<'static, unsafe extern "C" fn(LuaState, c_int, c_long, c_int) -> c_int>,
 
     // Debug API - required in all versions
     lua_sethook: Symbol<'static, unsafe extern "C" fn(LuaState, LuaHook, c_int, c_int)>,
@@ -109,6 +140,7 @@ struct LuaLibraryInner {
     lua_isnumber: Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> c_int>,
     lua_isstring: Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> c_int>,
     lua_topointer: Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> *const c_void>,
+    lua_touserdata: Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> *mut c_void>,
     lua_error: Symbol<'static, unsafe extern "C" fn(LuaState) -> !>,
     lua_newuserdata: Symbol<'static, unsafe extern "C" fn(LuaState, size_t) -> *mut c_void>,
     lua_checkstack: Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> c_int>,
@@ -119,6 +151,7 @@ struct LuaLibraryInner {
     // Version-specific optional functions (5.2+)
     lua_pcallk: Option<Symbol<'static, unsafe extern "C" fn(LuaState, c_int, c_int, c_int, c_long, Option<unsafe extern "C" fn(*mut c_void, c_int)>) -> c_int>>,
     lua_pushglobaltable: Option<Symbol<'static, unsafe extern "C" fn(LuaState)>>,
+    lua_rawlen: Option<Symbol<'static, unsafe extern "C" fn(LuaState, c_int) -> size_t>>,
     lual_loadbufferx: Option<Symbol<'static, unsafe extern "C" fn(LuaState, *const c_char, size_t, *const c_char, *const c_char) -> c_int>>,
 
     // Lua 5.1-specific functions (deprecated in 5.2+)
@@ -131,13 +164,91 @@ struct LuaLibraryInner {
 #[allow(non_snake_case)]
 impl LuaLibrary {
     /// Load a Lua library for the specified version
+    ///
+    /// A library set with [`set_library_path`] or `LUA_WAYFINDER_LIB` is
+    /// loaded instead of searching the usual places.
     pub fn load(version: LuaVersion) -> Result<Self, LoaderError> {
+        if let Some(lib_path) = library_override() {
+            return Self::load_override(&lib_path, Some(version));
+        }
         let lib_path = Self::find_library(version)?;
+        Self::load_path(&lib_path, version)
+    }
+
+    /// Loads the first library of the detected candidates that loads
+    ///
+    /// A candidate seen in a library loads that library; otherwise the
+    /// library of its version is searched for. The error names every
+    /// candidate and why it could not be used.
+    pub fn load_detected(candidates: &[Candidate]) -> Result<Self, LoaderError> {
+        if candidates.is_empty() {
+            return Err(LoaderError::NotDetected(
+                "no Lua was detected; set the runtime, e.g. `--runtime lua5.4`".to_string(),
+            ));
+        }
+        let mut failures = Vec::new();
+        for candidate in candidates {
+            let loaded = match &candidate.library {
+                Some(library) => Self::load_path(library, candidate.version),
+                None => Self::load(candidate.version),
+            };
+            match loaded {
+                Ok(library) => {
+                    tracing::info!("using {}", candidate);
+                    return Ok(library);
+                }
+                Err(e) => failures.push(format!("{} ({})", candidate, e)),
+            }
+        }
+        Err(LoaderError::NotDetected(format!("detected {}", failures.join("; "))))
+    }
+
+    /// Loads the library at `lib_path`, which implements `version`
+    pub fn load_path(lib_path: &Path, version: LuaVersion) -> Result<Self, LoaderError> {
+        let lib = unsafe { Library::new(lib_path) }
+            .map_err(|e| LoaderError::LoadFailed(format!("{}: {}", lib_path.display(), e)))?;
+        Self::from_library(lib, version, Some(lib_path.to_path_buf()))
+    }
+
+    /// Loads the library a `luaLibraryPath` setting or `LUA_WAYFINDER_LIB` names
+    ///
+    /// Its version is read from its name or exports; `version` is assumed
+    /// when neither tells, and is an error when they tell another.
+    pub fn load_override(lib_path: &Path, version: Option<LuaVersion>) -> Result<Self, LoaderError> {
+        let found = super::lua_detect::library_version(lib_path).or_else(|| super::lua_detect::probe_library(lib_path));
+        match (found, version) {
+            (Some(found), Some(version)) if found != version => Err(LoaderError::UnsupportedVersion(format!(
+                "{} is Lua {}, not Lua {}",
+                lib_path.display(),
+                found,
+                version
+            ))),
+            (found, version) => Self::load_path(lib_path, found.or(version).unwrap_or(LuaVersion::V54)),
+        }
+    }
 
-        unsafe {
-            let lib = Library::new(&lib_path)
-                .map_err(|e| LoaderError::LoadFailed(format!("{}: {}", lib_path.display(), e)))?;
+    /// The Lua 5.4 linked into Wayfinder, for when no library can be found
+    ///
+    /// Builds with the `embedded-lua` feature link Lua 5.4 as static builds
+    /// do, and take its functions from the process's own symbols.
+    #[cfg(feature = "embedded-lua")]
+    pub fn load_embedded() -> Result<Self, LoaderError> {
+        // A use of the linked Lua, so the linker keeps it
+        std::hint::black_box(embedded::luaL_newstate as unsafe extern "C" fn() -> LuaState);
 
+        #[cfg(unix)]
+        let lib: Library = libloading::os::unix::Library::this().into();
+        #[cfg(windows)]
+        let lib: Library = libloading::os::windows::Library::open_already_loaded("lua54.dll")
+            .or_else(|_| libloading::os::windows::Library::this())
+            .map_err(|e| LoaderError::LoadFailed(format!("the Lua linked into Wayfinder: {}", e)))?
+            .into();
+        Self::from_library(lib, LuaVersion::V54, None)
+    }
+
+    /// Takes the Lua C API functions of `lib`, which implements `version`
+    fn from_library(lib: Library, version: LuaVersion, path: Option<PathBuf>) -> Result<Self, LoaderError> {
+        unsafe {
             // Leak the library to get 'static lifetime
             let lib_static = Box::leak(Box::new(lib));
 
@@ -148,15 +259,20 @@ impl LuaLibrary {
             let lual_loadbufferx_opt = Self::load_symbol_optional(lib_static, b"luaL_loadbufferx\0");
             let lual_loadbuffer_opt = Self::load_symbol_optional(lib_static, b"luaL_loadbuffer\0");
             let lua_objlen_opt = Self::load_symbol_optional(lib_static, b"lua_objlen\0");
+            let lua_rawlen_opt = Self::load_symbol_optional(lib_static, b"lua_rawlen\0");
 
             let inner = LuaLibraryInner {
                 _lib: std::ptr::read(lib_static as *const Library),
                 version,
+                path,
 
                 // Load all required function pointers (available in all Lua versions 5.1-5.4)
                 lua_close: Self::load_symbol(lib_static, b"lua_close\0")?,
                 lua_newthread: Self::load_symbol(lib_static, b"lua_newthread\0")?,
                 lua_gettop: Self::load_symbol(lib_static, b"lua_gettop\0")?,
+                lua_xmove: Self::load_symbol(lib_static, b"lua_xmove\0")?,
+                lua_status: Self::load_symbol(lib_static, b"lua_status\0")?,
+                lua_tothread: Self::load_symbol(lib_static, b"lua_tothread\0")?,
                 lua_settop: Self::load_symbol(lib_static, b"lua_settop\0")?,
                 lua_pushvalue: Self::load_symbol(lib_static, b"lua_pushvalue\0")?,
                 lua_type: Self::load_symbol(lib_static, b"lua_type\0")?,
@@ -184,6 +300,7 @@ impl LuaLibrary {
                 lua_getmetatable: Self::load_symbol(lib_static, b"lua_getmetatable\0")?,
                 lua_setmetatable: Self::load_symbol(lib_static, b"lua_setmetatable\0")?,
                 lua_next: Self::load_symbol(lib_static, b"lua_next\0")?,
+                lua_gc: Self::load_symbol(lib_static, b"lua_gc\0")?,
                 lua_sethook: Self::load_symbol(lib_static, b"lua_sethook\0")?,
                 lua_getinfo: Self::load_symbol(lib_static, b"lua_getinfo\0")?,
                 lua_getlocal: Self::load_symbol(lib_static, b"lua_getlocal\0")?,
@@ -204,6 +321,7 @@ impl LuaLibrary {
                 lua_isnumber: Self::load_symbol(lib_static, b"lua_isnumber\0")?,
                 lua_isstring: Self::load_symbol(lib_static, b"lua_isstring\0")?,
                 lua_topointer: Self::load_symbol(lib_static, b"lua_topointer\0")?,
+                lua_touserdata: Self::load_symbol(lib_static, b"lua_touserdata\0")?,
                 lua_error: Self::load_symbol(lib_static, b"lua_error\0")?,
                 lua_newuserdata: Self::load_symbol(lib_static, b"lua_newuserdata\0")?,
                 lua_checkstack: Self::load_symbol(lib_static, b"lua_checkstack\0")?,
@@ -217,6 +335,7 @@ impl LuaLibrary {
                 lual_loadbufferx: lual_loadbufferx_opt,
                 lua_pcall: lua_pcall_opt,
                 lua_objlen: lua_objlen_opt,
+                lua_rawlen: lua_rawlen_opt,
                 lual_loadbuffer: lual_loadbuffer_opt,
             };
 
@@ -285,21 +404,36 @@ impl LuaLibrary {
         let mut candidates = vec![];
 
         #[cfg(target_os = "windows")]
-        if let Some(ref lua_libs) = project_lua_libs {
-            candidates.push(format!("{}\\lua{}.dll", lua_libs.display(), version_str.replace(".", "")));
-        }
+        {
+            use super::windows_libraries;
 
-        #[cfg(target_os = "windows")]
-        candidates.extend(vec![
-            format!("lua{}.dll", version_str.replace(".", "")),
-            format!("lua{}.dll", version_str),
-        ]);
+            let mut dirs: Vec<PathBuf> = project_lua_libs.into_iter().collect();
+            dirs.extend(windows_libraries::search_dirs(
+                version,
+                |name| std::env::var(name).ok(),
+                &windows_libraries::registry_install_dirs(),
+            ));
+            let mingw = cfg!(target_env = "gnu");
+            candidates.extend(
+                windows_libraries::candidates(version, &dirs, mingw)
+                    .into_iter()
+                    .map(|path| path.display().to_string()),
+            );
+        }
 
         for candidate in &candidates {
             let path = PathBuf::from(candidate);
-            if path.exists() {
-                return Ok(path);
+            if !path.exists() {
+                continue;
+            }
+            // `lua.dll` carries no version, so only its exports can tell
+            #[cfg(target_os = "windows")]
+            if super::lua_detect::library_version(&path).is_none()
+                && super::lua_detect::probe_library(&path) != Some(version)
+            {
+                continue;
             }
+            return Ok(path);
         }
 
         Err(LoaderError::LoadFailed(format!(
@@ -327,6 +461,11 @@ impl LuaLibrary {
         self.inner.version
     }
 
+    /// File the library was loaded from; None for the Lua linked into Wayfinder
+    pub fn path(&self) -> Option<&Path> {
+        self.inner.path.as_deref()
+    }
+
     // Provide safe wrappers for all Lua C API functions
     pub unsafe fn lua_close(&self, l: LuaState) {
         (self.inner.lua_close)(l)
@@ -384,6 +523,27 @@ impl LuaLibrary {
         (self.inner.lua_gettop)(l)
     }
 
+    /// # Safety
+    ///
+    /// `from` and `to` must be threads of the same Lua state, with `n` values on `from`'s stack
+    pub unsafe fn lua_xmove(&self, from: LuaState, to: LuaState, n: c_int) {
+        (self.inner.lua_xmove)(from, to, n)
+    }
+
+    /// # Safety
+    ///
+    /// `l` must be a valid Lua state
+    pub unsafe fn lua_status(&self, l: LuaState) -> c_int {
+        (self.inner.lua_status)(l)
+    }
+
+    /// # Safety
+    ///
+    /// `l` must be a valid Lua state and `idx` an acceptable index
+    pub unsafe fn lua_tothread(&self, l: LuaState, idx: c_int) -> LuaState {
+        (self.inner.lua_tothread)(l, idx)
+    }
+
     pub unsafe fn lua_settop(&self, l: LuaState, idx: c_int) -> c_int {
         (self.inner.lua_settop)(l, idx)
     }
@@ -440,6 +600,13 @@ impl LuaLibrary {
         (self.inner.lua_next)(l, idx)
     }
 
+    /// # Safety
+    ///
+    /// `l` must be a valid Lua state and `what` an option the loaded Lua version knows
+    pub unsafe fn lua_gc(&self, l: LuaState, what: c_int, data: c_long, arg: c_int) -> c_int {
+        (self.inner.lua_gc)(l, what, data, arg)
+    }
+
     pub unsafe fn lua_pcallk(&self, l: LuaState, nargs: c_int, nresults: c_int, msgh: c_int, ctx: c_long, k: Option<unsafe extern "C" fn(*mut c_void, c_int)>) -> c_int {
         if let Some(ref f) = self.inner.lua_pcallk {
             // Lua 5.2+: use native lua_pcallk with continuation support
@@ -447,7 +614,7 @@ impl LuaLibrary {
         } else if let Some(ref f) = self.inner.lua_pcall {
             // Lua 5.1: continuations not supported, ignore ctx and k parameters
             if k.is_some() {
-                eprintln!("Warning: Continuation functions are not supported in Lua 5.1");
+                tracing::warn!("continuation functions are not supported in Lua 5.1");
             }
             f(l, nargs, nresults, msgh)
         } else {
@@ -483,6 +650,13 @@ impl LuaLibrary {
         (self.inner.lua_gettable)(l, idx)
     }
 
+    /// # Safety
+    ///
+    /// `l` must be a valid Lua state with a table at `idx` and the key on top of its stack
+    pub unsafe fn lua_rawget(&self, l: LuaState, idx: c_int) -> c_int {
+        (self.inner.lua_rawget)(l, idx)
+    }
+
     pub unsafe fn lua_getmetatable(&self, l: LuaState, idx: c_int) -> c_int {
         (self.inner.lua_getmetatable)(l, idx)
     }
@@ -506,6 +680,20 @@ impl LuaLibrary {
         }
     }
 
+    /// # Safety
+    ///
+    /// `l` must be a valid Lua state and `idx` an acceptable index
+    pub unsafe fn lua_rawlen(&self, l: LuaState, idx: c_int) -> size_t {
+        if let Some(ref f) = self.inner.lua_rawlen {
+            f(l, idx)
+        } else if let Some(ref f) = self.inner.lua_objlen {
+            // Lua 5.1: lua_objlen does not invoke __len either
+            f(l, idx)
+        } else {
+            panic!("Neither lua_rawlen nor lua_objlen available in Lua library")
+        }
+    }
+
     pub unsafe fn luaL_ref(&self, l: LuaState, t: c_int) -> c_int {
         (self.inner.lual_ref)(l, t)
     }
@@ -537,7 +725,7 @@ impl LuaLibrary {
         } else if let Some(ref f) = self.inner.lual_loadbuffer {
             // Lua 5.1: use luaL_loadbuffer (ignores mode parameter)
             if !mode.is_null() {
-                eprintln!("Warning: Mode parameter ignored in Lua 5.1");
+                tracing::warn!("mode parameter ignored in Lua 5.1");
             }
             f(l, filename, 0, filename)
         } else {
@@ -577,6 +765,14 @@ impl LuaLibrary {
         (self.inner.lua_topointer)(l, idx)
     }
 
+    /// # Safety
+    ///
+    /// `l` must be a valid Lua state and `idx` an acceptable index; the block
+    /// pointed to lives only as long as the userdata is reachable
+    pub unsafe fn lua_touserdata(&self, l: LuaState, idx: c_int) -> *mut c_void {
+        (self.inner.lua_touserdata)(l, idx)
+    }
+
     pub unsafe fn lua_error(&self, l: LuaState) -> ! {
         (self.inner.lua_error)(l)
     }
@@ -0,0 +1,191 @@
+//! Module search paths for launched scripts
+//!
+//! A script that `require`s its project's modules needs `package.path` to
+//! reach them, whatever `LUA_PATH` the adapter was started with. The paths
+//! built here search the script's directory first, then the configured
+//! source roots, then a template from the command line, `wayfinder.yaml` or
+//! the launch request. In a template `;;` stands for Lua's default path, as
+//! in `LUA_PATH`, and `${scriptDir}` and `${cwd}` are replaced by those
+//! directories.
+//!
+//! Spawned interpreters get the paths as `LUA_PATH` and `LUA_CPATH`; the
+//! embedded state has `package.path` and `package.cpath` set, with `;;`
+//! replaced by the path it had.
+
+use std::path::{Path, PathBuf};
+
+/// Template used when none is configured: only the default path
+pub const DEFAULT_TEMPLATE: &str = ";;";
+
+/// Versioned variables Lua 5.2 and later read instead of `LUA_PATH` and `LUA_CPATH`
+pub const VERSIONED_VARIABLES: &[&str] = &[
+    "LUA_PATH_5_2",
+    "LUA_PATH_5_3",
+    "LUA_PATH_5_4",
+    "LUA_CPATH_5_2",
+    "LUA_CPATH_5_3",
+    "LUA_CPATH_5_4",
+];
+
+#[cfg(windows)]
+const NATIVE_MODULE_EXTENSION: &str = "dll";
+#[cfg(not(windows))]
+const NATIVE_MODULE_EXTENSION: &str = "so";
+
+/// Search path settings
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LuaPathConfig {
+    /// Template for `package.path`
+    pub lua_path: Option<String>,
+    /// Template for `package.cpath`
+    pub lua_cpath: Option<String>,
+    /// Directories of the project's modules, searched after the script's own
+    pub source_roots: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LuaPaths {
+    pub path: String,
+    pub cpath: String,
+}
+
+impl LuaPaths {
+    /// Builds the search paths for a script in `script_dir`
+    ///
+    /// Relative source roots are taken against `cwd`.
+    pub fn derive(config: &LuaPathConfig, script_dir: &Path, cwd: &Path) -> Self {
+        let mut dirs = vec![script_dir.to_path_buf()];
+        for root in &config.source_roots {
+            let root = cwd.join(root);
+            if !dirs.contains(&root) {
+                dirs.push(root);
+            }
+        }
+
+        let path_entries: Vec<String> = dirs
+            .iter()
+            .flat_map(|dir| [dir.join("?.lua"), dir.join("?").join("init.lua")])
+            .map(|entry| entry.display().to_string())
+            .collect();
+        let cpath_entries: Vec<String> = dirs
+            .iter()
+            .map(|dir| dir.join(format!("?.{}", NATIVE_MODULE_EXTENSION)).display().to_string())
+            .collect();
+
+        let expand = |template: &Option<String>| {
+            template
+                .as_deref()
+                .unwrap_or(DEFAULT_TEMPLATE)
+                .replace("${scriptDir}", &script_dir.display().to_string())
+                .replace("${cwd}", &cwd.display().to_string())
+        };
+        Self {
+            path: join_template(&path_entries, &expand(&config.lua_path)),
+            cpath: join_template(&cpath_entries, &expand(&config.lua_cpath)),
+        }
+    }
+}
+
+/// Puts the derived entries before a template, keeping its `;;` where it was
+fn join_template(entries: &[String], template: &str) -> String {
+    let mut path = entries.join(";");
+    if !template.starts_with(";;") {
+        path.push(';');
+    }
+    path.push_str(template);
+    path
+}
+
+/// Replaces the first `;;` of a path with `default`, as Lua does with `LUA_PATH`
+pub fn expand_default(path: &str, default: &str) -> String {
+    match path.split_once(";;") {
+        Some((before, after)) => [before, default, after]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(";"),
+        None => path.to_string(),
+    }
+}
+
+/// The file `require(module)` loads through the search path `path`
+///
+/// Templates are tried in order, relative ones against `cwd`, as
+/// `package.searchpath` does.
+pub fn search_module(path: &str, module: &str, cwd: &Path) -> Option<PathBuf> {
+    let name = module.replace('.', std::path::MAIN_SEPARATOR_STR);
+    path.split(';')
+        .filter(|template| !template.is_empty())
+        .map(|template| cwd.join(template.replace('?', &name)))
+        .find(|file| file.is_file())
+}
+
+/// The module `require` loads `file` as through the search path `path`
+///
+/// Of the templates that lead to the file, the shortest name wins, so
+/// `ui/init.lua` is `ui` rather than `ui.init`.
+pub fn module_for_file(path: &str, file: &Path, cwd: &Path) -> Option<String> {
+    let file = file.to_string_lossy();
+    path.split(';')
+        .filter_map(|template| {
+            let (prefix, suffix) = template.split_once('?')?;
+            let stem = file.strip_suffix(suffix)?;
+            let relative = Path::new(stem).strip_prefix(cwd.join(prefix)).ok()?;
+            let segments: Option<Vec<&str>> = relative
+                .components()
+                .map(|component| match component {
+                    std::path::Component::Normal(segment) => segment.to_str(),
+                    _ => None,
+                })
+                .collect();
+            segments.filter(|segments| !segments.is_empty()).map(|segments| segments.join("."))
+        })
+        .min_by_key(|name| name.split('.').count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_derive_puts_script_dir_and_roots_first() {
+        let config = LuaPathConfig {
+            lua_path: Some("${cwd}/vendor/?.lua;;".to_string()),
+            source_roots: vec![PathBuf::from("lib"), PathBuf::from("/game/src")],
+            ..Default::default()
+        };
+        let paths = LuaPaths::derive(&config, Path::new("/game/src"), Path::new("/game"));
+        assert_eq!(
+            paths.path,
+            "/game/src/?.lua;/game/src/?/init.lua;/game/lib/?.lua;/game/lib/?/init.lua;/game/vendor/?.lua;;"
+        );
+        assert_eq!(paths.cpath, "/game/src/?.so;/game/lib/?.so;;");
+    }
+
+    #[test]
+    fn test_modules_and_their_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = dir.path();
+        std::fs::create_dir_all(cwd.join("src/ui")).unwrap();
+        std::fs::write(cwd.join("src/ui/init.lua"), "").unwrap();
+        std::fs::write(cwd.join("src/ui/menu.lua"), "").unwrap();
+        let path = "./src/?.lua;./src/?/init.lua;/usr/share/lua/?.lua";
+
+        assert_eq!(search_module(path, "ui.menu", cwd), Some(cwd.join("./src/ui/menu.lua")));
+        assert_eq!(search_module(path, "ui", cwd), Some(cwd.join("./src/ui/init.lua")));
+        assert_eq!(search_module(path, "missing", cwd), None);
+
+        let menu = cwd.join("src/ui/menu.lua");
+        assert_eq!(module_for_file(path, &menu, cwd).as_deref(), Some("ui.menu"));
+        assert_eq!(module_for_file(path, &cwd.join("src/ui/init.lua"), cwd).as_deref(), Some("ui"));
+        assert_eq!(module_for_file(path, &cwd.join("other/menu.lua"), cwd), None);
+    }
+
+    #[test]
+    fn test_expand_default() {
+        assert_eq!(expand_default("/a/?.lua;;", "./?.lua"), "/a/?.lua;./?.lua");
+        assert_eq!(expand_default(";;/b/?.lua", "./?.lua"), "./?.lua;/b/?.lua");
+        assert_eq!(expand_default("/a/?.lua", "./?.lua"), "/a/?.lua");
+    }
+}
@@ -4,6 +4,9 @@ use super::lua_ffi::*;
 use std::ffi::{CStr, CString};
 use std::marker::PhantomData;
 use std::ptr;
+use std::sync::Arc;
+
+use crate::memory::allocations::AllocationTracker;
 
 #[cfg(feature = "dynamic-lua")]
 use super::lua_loader::LuaLibrary;
@@ -13,6 +16,10 @@ pub struct Lua {
     state: LuaState,
     #[cfg(feature = "dynamic-lua")]
     lib: LuaLibrary,
+    /// Whether dropping this handle closes the state
+    owned: bool,
+    /// Tracker the state's allocator reports to, kept alive until the state is closed
+    allocations: Option<Arc<AllocationTracker>>,
 }
 
 unsafe impl Send for Lua {}
@@ -28,10 +35,51 @@ impl Lua {
                 panic!("Failed to create Lua state");
             }
             luaL_openlibs(state);
-            Self { state }
+            Self {
+                state,
+                owned: true,
+                allocations: None,
+            }
         }
     }
 
+    /// Creates a state whose allocations are reported to a new `AllocationTracker`
+    ///
+    /// Tracking starts disabled; see `allocation_tracker`.
+    #[cfg(feature = "static-lua")]
+    pub fn new_tracked() -> Self {
+        let tracker = Arc::new(AllocationTracker::new());
+        unsafe {
+            let state = lua_newstate(tracking_alloc, Arc::as_ptr(&tracker) as *mut c_void);
+            if state.is_null() {
+                panic!("Failed to create Lua state");
+            }
+            // What luaL_newstate would have set up
+            lua_atpanic(state, panic_handler);
+            luaL_openlibs(state);
+            Self {
+                state,
+                owned: true,
+                allocations: Some(tracker),
+            }
+        }
+    }
+
+    /// Creates a fresh state of the same kind: from the same library, and tracked if this one is
+    pub fn new_like(&self) -> Self {
+        #[cfg(feature = "static-lua")]
+        {
+            if self.allocations.is_some() {
+                Self::new_tracked()
+            } else {
+                Self::new()
+            }
+        }
+
+        #[cfg(feature = "dynamic-lua")]
+        Self::new_with_library(self.lib.clone())
+    }
+
     #[cfg(feature = "dynamic-lua")]
     pub fn new_with_library(lib: LuaLibrary) -> Self {
         unsafe {
@@ -40,7 +88,45 @@ impl Lua {
                 panic!("Failed to create Lua state");
             }
             lib.lual_openlibs(state);
-            Self { state, lib }
+            Self {
+                state,
+                lib,
+                owned: true,
+                allocations: None,
+            }
+        }
+    }
+
+    /// Wraps a state created elsewhere, such as by a host program embedding Lua
+    ///
+    /// The handle does not own the state, so dropping it closes nothing.
+    ///
+    /// # Safety
+    ///
+    /// `state` must be a valid Lua 5.4 state that outlives the handle.
+    #[cfg(feature = "static-lua")]
+    pub unsafe fn from_raw(state: LuaState) -> Self {
+        Self {
+            state,
+            owned: false,
+            allocations: None,
+        }
+    }
+
+    /// Wraps a state created elsewhere with the library it was created by
+    ///
+    /// The handle does not own the state, so dropping it closes nothing.
+    ///
+    /// # Safety
+    ///
+    /// `state` must be a valid state of `lib`'s version that outlives the handle.
+    #[cfg(feature = "dynamic-lua")]
+    pub unsafe fn from_raw_with_library(state: LuaState, lib: LuaLibrary) -> Self {
+        Self {
+            state,
+            lib,
+            owned: false,
+            allocations: None,
         }
     }
 
@@ -48,6 +134,74 @@ impl Lua {
         self.state
     }
 
+    /// Returns a handle to another thread (coroutine) of the same state
+    ///
+    /// The handle does not own the thread, so dropping it closes nothing.
+    pub fn thread_view(&self, thread: LuaState) -> Lua {
+        Lua {
+            state: thread,
+            #[cfg(feature = "dynamic-lua")]
+            lib: self.lib.clone(),
+            owned: false,
+            allocations: None,
+        }
+    }
+
+    /// Tracker of a state created with `new_tracked`
+    pub fn allocation_tracker(&self) -> Option<Arc<AllocationTracker>> {
+        self.allocations.clone()
+    }
+
+    /// The Lua this state runs on
+    pub fn backend(&self) -> super::LuaBackend {
+        #[cfg(feature = "static-lua")]
+        return super::LuaBackend {
+            mode: super::LuaMode::Static,
+            version: super::LuaVersion::V54,
+            library: None,
+        };
+
+        #[cfg(feature = "dynamic-lua")]
+        return super::LuaBackend {
+            mode: if self.lib.path().is_some() { super::LuaMode::Dynamic } else { super::LuaMode::Static },
+            version: self.lib.version(),
+            library: self.lib.path().map(std::path::Path::to_path_buf),
+        };
+    }
+
+    /// Status of this thread: `LUA_OK`, `LUA_YIELD` or an error code
+    pub fn status(&self) -> c_int {
+        unsafe {
+            #[cfg(feature = "static-lua")]
+            return lua_status(self.state);
+
+            #[cfg(feature = "dynamic-lua")]
+            return self.lib.lua_status(self.state);
+        }
+    }
+
+    /// Returns the thread at the given index, or null if it is not a thread
+    pub fn to_thread(&self, idx: c_int) -> LuaState {
+        unsafe {
+            #[cfg(feature = "static-lua")]
+            return lua_tothread(self.state, idx);
+
+            #[cfg(feature = "dynamic-lua")]
+            return self.lib.lua_tothread(self.state, idx);
+        }
+    }
+
+    /// Pops `n` values from this thread and pushes them onto another
+    pub fn xmove(&self, to: &Lua, n: c_int) {
+        unsafe {
+            #[cfg(feature = "static-lua")]
+            lua_xmove(self.state, to.state, n);
+
+            #[cfg(feature = "dynamic-lua")]
+            self.lib.lua_xmove(self.state, to.state, n);
+        }
+    }
+
     pub fn get_stack(&self, level: c_int, ar: &mut lua_Debug) -> c_int {
         unsafe {
             #[cfg(feature = "static-lua")]
@@ -197,6 +351,17 @@ impl Lua {
         }
     }
 
+    /// Length of a table's array part or a string, ignoring `__len`
+    pub fn raw_len(&self, idx: c_int) -> usize {
+        unsafe {
+            #[cfg(feature = "static-lua")]
+            return lua_rawlen(self.state, idx);
+
+            #[cfg(feature = "dynamic-lua")]
+            return self.lib.lua_rawlen(self.state, idx);
+        }
+    }
+
     pub fn set_metatable(&self, idx: c_int) -> c_int {
         unsafe {
             #[cfg(feature = "static-lua")]
@@ -419,6 +584,11 @@ impl Lua {
     }
 
     pub fn pop_string(&mut self) -> String {
+        String::from_utf8_lossy(&self.pop_bytes()).to_string()
+    }
+
+    /// Reads the string at the top of the stack as raw bytes
+    pub fn pop_bytes(&mut self) -> Vec<u8> {
         unsafe {
             let mut len: usize = 0;
             #[cfg(feature = "static-lua")]
@@ -428,10 +598,9 @@ impl Lua {
             let ptr = self.lib.lua_tolstring(self.state, -1, &mut len);
 
             if ptr.is_null() {
-                String::new()
+                Vec::new()
             } else {
-                let slice = std::slice::from_raw_parts(ptr as *const u8, len);
-                String::from_utf8_lossy(slice).to_string()
+                std::slice::from_raw_parts(ptr as *const u8, len).to_vec()
             }
         }
     }
@@ -476,6 +645,17 @@ impl Lua {
         }
     }
 
+    /// Pushes `t[k]` for the table `t` at `idx` and the key `k` on top, without `__index`
+    pub fn raw_get(&mut self, idx: c_int) -> c_int {
+        unsafe {
+            #[cfg(feature = "static-lua")]
+            return lua_rawget(self.state, idx);
+
+            #[cfg(feature = "dynamic-lua")]
+            return self.lib.lua_rawget(self.state, idx);
+        }
+    }
+
     pub fn get_field(&mut self, idx: c_int, key: &str) -> c_int {
         unsafe {
             let key_ptr = CString::new(key).unwrap();
@@ -620,6 +800,17 @@ impl Lua {
         }
     }
 
+    /// The block of a full userdata or the pointer of a light one; null for other values
+    pub fn touserdata(&self, idx: c_int) -> *mut c_void {
+        unsafe {
+            #[cfg(feature = "static-lua")]
+            return lua_touserdata(self.state, idx);
+
+            #[cfg(feature = "dynamic-lua")]
+            return self.lib.lua_touserdata(self.state, idx);
+        }
+    }
+
     pub fn error(&mut self, msg: &str) {
         unsafe {
             let _msg_ptr = CString::new(msg).unwrap();
@@ -764,6 +955,17 @@ impl Lua {
         }
     }
 
+    /// Runs a garbage collector command, as `lua_gc` with the `LUA_GC*` options
+    pub fn gc(&self, what: c_int, data: c_long, arg: c_int) -> c_int {
+        unsafe {
+            #[cfg(feature = "static-lua")]
+            return lua_gc(self.state, what, data, arg);
+
+            #[cfg(feature = "dynamic-lua")]
+            return self.lib.lua_gc(self.state, what, data, arg);
+        }
+    }
+
     pub fn lua_sethook(&self, f: LuaHook, mask: c_int, count: c_int) {
         unsafe {
             #[cfg(feature = "static-lua")]
@@ -924,12 +1126,50 @@ impl Lua {
 
 impl Drop for Lua {
     fn drop(&mut self) {
-        if !self.state.is_null() {
+        if self.owned && !self.state.is_null() {
             self.close();
         }
     }
 }
 
+/// Allocator of states created with `new_tracked`; `ud` points to their tracker
+#[cfg(feature = "static-lua")]
+unsafe extern "C" fn tracking_alloc(ud: *mut c_void, block: *mut c_void, _osize: size_t, nsize: size_t) -> *mut c_void {
+    let tracker = &*(ud as *const AllocationTracker);
+    if nsize == 0 {
+        if !block.is_null() {
+            tracker.on_free(block as usize);
+        }
+        libc::free(block);
+        return ptr::null_mut();
+    }
+
+    let resized = libc::realloc(block, nsize);
+    // A failed realloc leaves the old block in place
+    if !resized.is_null() {
+        if !block.is_null() {
+            tracker.on_free(block as usize);
+        }
+        tracker.on_alloc(resized as usize, nsize);
+    }
+    resized
+}
+
+/// Reports an error raised outside any protected call, as luaL_newstate's handler does
+#[cfg(feature = "static-lua")]
+extern "C" fn panic_handler(state: LuaState) -> c_int {
+    let message = unsafe {
+        let message = lua_tolstring(state, -1, ptr::null_mut());
+        if message.is_null() {
+            "error object is not a string".into()
+        } else {
+            CStr::from_ptr(message).to_string_lossy()
+        }
+    };
+    tracing::error!("PANIC: unprotected error in call to Lua API ({})", message);
+    0
+}
+
 pub trait LuaPop: Sized {
     fn pop(lua: &mut Lua) -> Self;
 }
@@ -1057,11 +1297,11 @@ impl<'a> DebugInfo<'a> {
     }
 
     pub fn nups(&self) -> c_int {
-        self.ar.nups
+        self.ar.nups as c_int
     }
 
     pub fn nparams(&self) -> c_int {
-        self.ar.nparams
+        self.ar.nparams as c_int
     }
 
     pub fn is_vararg(&self) -> bool {
@@ -6,28 +6,28 @@ use libc::c_int;
 use std::collections::HashMap;
 use std::ffi::CStr;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use luanext_sourcemap::{PositionTranslator, SourceMapSource};
+use once_cell::sync::Lazy;
+use crate::runtime::hook_state::{HookRegistry, HookState};
+use crate::runtime::variable_refs::{VariableReference, VariableRefs};
 
-static mut PAUSED: AtomicBool = AtomicBool::new(false);
-static mut SHOULD_STEP: AtomicBool = AtomicBool::new(false);
-static mut CURRENT_LINE: AtomicUsize = AtomicUsize::new(1);
-static mut CURRENT_SOURCE: Option<String> = None;
-static mut STEP_MODE: AtomicUsize = AtomicUsize::new(0);
-static mut STEP_DEPTH: AtomicUsize = AtomicUsize::new(0);
-static mut STEP_TRIGGERED: AtomicBool = AtomicBool::new(false);
+// Hook state of every LuaNext runtime in the process
+static HOOK_STATES: Lazy<HookRegistry<HookState>> = Lazy::new(HookRegistry::new);
 
 extern "C" fn lua_hook_callback(_L: LuaState, ar: *mut lua_Debug) {
+    // SAFETY: hooks are called with the thread they fire on
+    let Some(state) = (unsafe { HOOK_STATES.get(_L) }) else { return };
+
     unsafe {
         if lua_getinfo(_L, b"lS\0".as_ptr() as *const i8, ar) == 0 {
             return;
         }
 
         let line = (*ar).currentline as u32;
-        CURRENT_LINE.store(line as usize, Ordering::SeqCst);
 
         let source = {
             let source_ptr = (*ar).source;
@@ -38,21 +38,18 @@ extern "C" fn lua_hook_callback(_L: LuaState, ar: *mut lua_Debug) {
                 None
             }
         };
-        CURRENT_SOURCE = source;
+        let at_run_to = (*ar).event == LUA_HOOKLINE && state.take_run_to(source.as_deref(), line);
+        state.set_location(source, line);
 
-        let step_mode = StepMode::from_u32(STEP_MODE.load(Ordering::SeqCst) as u32);
-        let should_step = SHOULD_STEP.load(Ordering::SeqCst);
+        let step_mode = StepMode::from_u32(state.step_mode.load(Ordering::SeqCst) as u32);
+        let should_step = state.should_step.load(Ordering::SeqCst);
 
         let triggered_for_step = if should_step {
             match step_mode {
                 StepMode::In => true,
                 StepMode::Over => {
                     let depth = (*ar).linedefined as usize;
-                    if depth <= STEP_DEPTH.load(Ordering::SeqCst) {
-                        true
-                    } else {
-                        false
-                    }
+                    depth <= state.step_depth.load(Ordering::SeqCst)
                 }
                 StepMode::Out => {
                     false
@@ -63,56 +60,122 @@ extern "C" fn lua_hook_callback(_L: LuaState, ar: *mut lua_Debug) {
         };
 
         if triggered_for_step {
-            STEP_TRIGGERED.store(true, Ordering::SeqCst);
-            PAUSED.store(true, Ordering::SeqCst);
+            state.step_triggered.store(true, Ordering::SeqCst);
         }
+        if triggered_for_step || at_run_to {
+            state.cancel_run_to();
+            state.paused.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Most children listed for one table, to keep huge tables responsive
+const TABLE_CHILDREN_LIMIT: usize = 100;
+
+/// Describes the value on top of the stack, pinning tables so they can be expanded
+fn top_variable(lua: &mut Lua, refs: &mut VariableRefs, name: String) -> super::Variable {
+    let value_type = lua.type_of(-1);
+    let value = match value_type {
+        0 => "nil".to_string(),
+        1 => format!("{}", lua.pop_boolean()),
+        3 => format!("{}", lua.pop_number()),
+        4 => lua.pop_string(),
+        5 => format!("table: 0x{:x}", lua.topointer(-1) as usize),
+        6 => format!("function: 0x{:x}", lua.topointer(-1) as usize),
+        7 => format!("userdata: 0x{:x}", lua.topointer(-1) as usize),
+        8 => format!("thread: 0x{:x}", lua.topointer(-1) as usize),
+        _ => lua.type_name(value_type).to_string(),
+    };
+
+    super::Variable {
+        name,
+        value,
+        type_: lua.type_name(value_type).to_string(),
+        variables_reference: (value_type == LUA_TTABLE).then(|| refs.pin_top(lua)),
+        named_variables: None,
+        indexed_variables: None,
+        memory_reference: None,
     }
 }
 
+/// Lists the fields of the table on top of the stack
+fn list_table(lua: &mut Lua, refs: &mut VariableRefs) -> Vec<super::Variable> {
+    let table = lua.get_top();
+    let mut variables = Vec::new();
+
+    lua.push_nil(); // First key
+    while variables.len() < TABLE_CHILDREN_LIMIT && lua.next(table) != 0 {
+        // Converting a number key in place would confuse lua_next
+        let name = match lua.type_of(-2) {
+            LUA_TSTRING => {
+                lua.lua_pushvalue(-2);
+                let name = lua.pop_string();
+                lua.lua_settop(-2);
+                Some(name)
+            }
+            LUA_TNUMBER => Some(format!("[{}]", lua.lua_tonumber(-2))),
+            _ => None,
+        };
+        if let Some(name) = name {
+            variables.push(top_variable(lua, refs, name));
+        }
+
+        // Remove value, keep key for next iteration
+        lua.lua_settop(-2);
+    }
+
+    lua.set_top(table);
+    variables
+}
+
 pub struct LuaNextRuntime {
     lua: Arc<Mutex<Lua>>,
     breakpoints: Arc<Mutex<HashMap<String, Vec<u32>>>>,
     step_mode: Arc<Mutex<StepMode>>,
     source_map_translator: Arc<Mutex<PositionTranslator>>,
+    /// Id handed to the next breakpoint
+    next_breakpoint_id: i64,
+    /// Compiled location of each line breakpoint, by id
+    line_breakpoints: HashMap<i64, (String, u32)>,
+    /// Expandable variables handed out since the program last resumed
+    variable_refs: Arc<Mutex<VariableRefs>>,
+    /// State shared with the hook, registered under this runtime's Lua state
+    hook_state: Arc<HookState>,
 }
 
 impl LuaNextRuntime {
     #[cfg(feature = "static-lua")]
     pub fn new() -> Self {
-        unsafe {
-            PAUSED.store(false, Ordering::SeqCst);
-            SHOULD_STEP.store(false, Ordering::SeqCst);
-            CURRENT_LINE.store(1, Ordering::SeqCst);
-        }
-
-        let lua = Arc::new(Mutex::new(Lua::new()));
-
-        Self {
-            lua,
-            breakpoints: Arc::new(Mutex::new(HashMap::new())),
-            step_mode: Arc::new(Mutex::new(StepMode::Over)),
-            source_map_translator: Arc::new(Mutex::new(PositionTranslator::new())),
-        }
+        Self::with_lua(Lua::new())
     }
 
     #[cfg(feature = "dynamic-lua")]
     pub fn new_with_library(lib: crate::runtime::lua_loader::LuaLibrary) -> Self {
-        unsafe {
-            PAUSED.store(false, Ordering::SeqCst);
-            SHOULD_STEP.store(false, Ordering::SeqCst);
-            CURRENT_LINE.store(1, Ordering::SeqCst);
-        }
+        Self::with_lua(Lua::new_with_library(lib))
+    }
 
-        let lua = Arc::new(Mutex::new(Lua::new_with_library(lib)));
+    fn with_lua(lua: Lua) -> Self {
+        let hook_state = Arc::new(HookState::new());
+        HOOK_STATES.register(lua.state(), hook_state.clone());
 
         Self {
-            lua,
+            lua: Arc::new(Mutex::new(lua)),
             breakpoints: Arc::new(Mutex::new(HashMap::new())),
             step_mode: Arc::new(Mutex::new(StepMode::Over)),
             source_map_translator: Arc::new(Mutex::new(PositionTranslator::new())),
+            next_breakpoint_id: 1,
+            line_breakpoints: HashMap::new(),
+            variable_refs: Arc::new(Mutex::new(VariableRefs::new())),
+            hook_state,
         }
     }
 
+    fn next_breakpoint_id(&mut self) -> i64 {
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        id
+    }
+
     fn lua_to_value(lua: &mut Lua, index: c_int) -> Value {
         let lua_type = lua.type_of(index);
 
@@ -187,6 +250,20 @@ impl LuaNextRuntime {
             .map(|loc| (loc.file, loc.position.line, loc.position.column))
     }
 
+    /// Location in compiled Lua that a client-side location refers to
+    ///
+    /// `.luax` positions are translated through the source map. Without one the
+    /// original position is kept and the compiled code is debugged as is.
+    fn compiled_location(&self, source: &str, line: u32) -> (String, u32) {
+        if source.ends_with(".luax") {
+            let source_path = PathBuf::from(source);
+            if let Some((lua_file, lua_line, _)) = self.translate_to_compiled(&source_path, line, 1) {
+                return (lua_file.to_string_lossy().to_string(), lua_line);
+            }
+        }
+        (source.to_string(), line)
+    }
+
     pub fn get_global(&mut self, name: &str) -> c_int {
         let mut lua = self.lua.lock().unwrap();
         lua.get_global(name)
@@ -274,8 +351,7 @@ impl LuaNextRuntime {
     }
 
     pub fn is_breakpoint_hit_at_current_location(&self) -> bool {
-        let source = unsafe { CURRENT_SOURCE.clone() };
-        let line = unsafe { CURRENT_LINE.load(Ordering::SeqCst) as u32 };
+        let (source, line) = self.get_current_location();
 
         if let Some(ref s) = source {
             self.is_breakpoint_hit(s, line)
@@ -288,13 +364,11 @@ impl LuaNextRuntime {
         if self.is_breakpoint_hit_at_current_location() {
             return true;
         }
-        unsafe { STEP_TRIGGERED.load(Ordering::SeqCst) }
+        self.hook_state.step_triggered.load(Ordering::SeqCst)
     }
 
     pub fn clear_step_triggered(&self) {
-        unsafe {
-            STEP_TRIGGERED.store(false, Ordering::SeqCst);
-        }
+        self.hook_state.step_triggered.store(false, Ordering::SeqCst);
     }
 
     pub fn install_hook(&self) {
@@ -305,7 +379,7 @@ impl LuaNextRuntime {
     }
 
     pub fn is_paused(&self) -> bool {
-        unsafe { PAUSED.load(Ordering::SeqCst) }
+        self.hook_state.is_paused()
     }
 
     pub fn wait_for_pause(&self, timeout_ms: u64) -> bool {
@@ -321,7 +395,7 @@ impl LuaNextRuntime {
 
     pub fn handle_pause(&self) -> bool {
         let is_breakpoint = self.is_breakpoint_hit_at_current_location();
-        let step_triggered = unsafe { STEP_TRIGGERED.load(Ordering::SeqCst) };
+        let step_triggered = self.hook_state.step_triggered.load(Ordering::SeqCst);
 
         if is_breakpoint || step_triggered {
             self.clear_step_triggered();
@@ -334,26 +408,24 @@ impl LuaNextRuntime {
     }
 
     pub fn clear_pause(&self) {
-        unsafe {
-            PAUSED.store(false, Ordering::SeqCst);
-            SHOULD_STEP.store(false, Ordering::SeqCst);
-            STEP_TRIGGERED.store(false, Ordering::SeqCst);
-        }
+        self.hook_state.clear_pause();
     }
 
     pub fn set_step(&self, mode: StepMode) {
+        let hook = &self.hook_state;
+        hook.should_step.store(true, Ordering::SeqCst);
+        hook.step_mode.store(mode.to_u32() as usize, Ordering::SeqCst);
+
         unsafe {
-            SHOULD_STEP.store(true, Ordering::SeqCst);
-            STEP_MODE.store(mode.to_u32() as usize, Ordering::SeqCst);
 
             let lua = self.lua.lock().unwrap();
             let mut ar = DebugInfo::new();
             if lua.lua_getinfo( b"n\0".as_ptr() as *const i8, ar.ptr()) != 0 {
                 let depth = ar.linedefined() as usize;
                 if depth == 0 {
-                    STEP_DEPTH.store(0, Ordering::SeqCst);
+                    hook.step_depth.store(0, Ordering::SeqCst);
                 } else {
-                    STEP_DEPTH.store(depth + 1, Ordering::SeqCst);
+                    hook.step_depth.store(depth + 1, Ordering::SeqCst);
                 }
             }
         }
@@ -366,18 +438,22 @@ impl LuaNextRuntime {
     }
 
     pub fn get_current_location(&self) -> (Option<String>, u32) {
-        unsafe {
-            let line = CURRENT_LINE.load(Ordering::SeqCst) as u32;
-            (CURRENT_SOURCE.clone(), line)
-        }
+        (self.get_current_source(), self.get_current_line())
     }
 
     pub fn get_current_line(&self) -> u32 {
-        unsafe { CURRENT_LINE.load(Ordering::SeqCst) as u32 }
+        self.hook_state.current_line()
     }
 
     pub fn get_current_source(&self) -> Option<String> {
-        unsafe { CURRENT_SOURCE.clone() }
+        self.hook_state.current_source()
+    }
+}
+
+impl Drop for LuaNextRuntime {
+    fn drop(&mut self) {
+        let lua = self.lua.lock().unwrap_or_else(|e| e.into_inner());
+        HOOK_STATES.unregister(lua.state());
     }
 }
 
@@ -397,93 +473,11 @@ impl DebugRuntime for LuaNextRuntime {
     ) -> Result<crate::hot_reload::HotReloadResult, RuntimeError> {
         #[cfg(feature = "hot-reload")]
         {
-            use crate::hot_reload::{HotReloadResult, HotReloadWarning, WarningSeverity};
-            use crate::runtime::lua_ffi::*;
-
-            // Compile the module source
-            let compile_result: Result<(), RuntimeError> = {
-                let lua_guard = self.lua.lock().unwrap();
-                let lua_state = lua_guard.state();
-
-                unsafe {
-                    let source_cstr = std::ffi::CString::new(module_source)
-                        .map_err(|_| RuntimeError::Communication("Invalid source string".to_string()))?;
-
-                    if luaL_loadstring(lua_state, source_cstr.as_ptr()) != LUA_OK as i32 {
-                        // Get the error message
-                        let error_msg = if lua_type(lua_state, -1) == LUA_TSTRING as i32 {
-                            let c_str = lua_tolstring(lua_state, -1, std::ptr::null_mut());
-                            if !c_str.is_null() {
-                                std::ffi::CStr::from_ptr(c_str)
-                                    .to_string_lossy()
-                                    .to_string()
-                            } else {
-                                "Unknown compilation error".to_string()
-                            }
-                        } else {
-                            "Unknown compilation error".to_string()
-                        };
-
-                        lua_pop(lua_state, 1); // Remove error message
-                        return Err(RuntimeError::Communication(format!("Compilation failed: {}", error_msg)));
-                    }
-                    Ok(())
-                }
-            };
-
-            compile_result?;
-
-            // Execute the compiled module
-            let execute_result: Result<(), RuntimeError> = {
-                let lua_guard = self.lua.lock().unwrap();
-                let lua_state = lua_guard.state();
-
-                unsafe {
-                    if lua_pcall(lua_state, 0, 1, 0) != LUA_OK as i32 {
-                        // Get the error message
-                        let error_msg = if lua_type(lua_state, -1) == LUA_TSTRING as i32 {
-                            let c_str = lua_tolstring(lua_state, -1, std::ptr::null_mut());
-                            if !c_str.is_null() {
-                                std::ffi::CStr::from_ptr(c_str)
-                                    .to_string_lossy()
-                                    .to_string()
-                            } else {
-                                "Unknown execution error".to_string()
-                            }
-                        } else {
-                            "Unknown execution error".to_string()
-                        };
-
-                        lua_pop(lua_state, 1); // Remove error message
-                        return Err(RuntimeError::Communication(format!("Execution failed: {}", error_msg)));
-                    }
+            use crate::hot_reload::{reload_module, HotReloadResult};
 
-                    // Pop the result
-                    lua_pop(lua_state, 1);
-                    Ok(())
-                }
-            };
-
-            execute_result?;
-
-            // Create warnings about limitations
-            let warnings = vec![
-                HotReloadWarning {
-                    message: "State preservation not yet implemented - local variables and upvalues will be reset".to_string(),
-                    severity: WarningSeverity::Warning,
-                },
-                HotReloadWarning {
-                    message: "Module references in existing closures will not be updated".to_string(),
-                    severity: WarningSeverity::Warning,
-                }
-            ];
-
-            Ok(HotReloadResult {
-                success: true,
-                warnings,
-                message: Some(format!("Module '{}' reloaded successfully",
-                                    module_name.unwrap_or("unnamed"))),
-            })
+            let mut lua = self.lua.lock().unwrap();
+            let transfer = reload_module(&mut lua, module_source, module_name).map_err(RuntimeError::Communication)?;
+            Ok(HotReloadResult::from_transfer(module_name, transfer))
         }
 
         #[cfg(not(feature = "hot-reload"))]
@@ -496,64 +490,68 @@ impl DebugRuntime for LuaNextRuntime {
     async fn set_breakpoint(&mut self, breakpoint: BreakpointType) -> Result<Breakpoint, RuntimeError> {
         match breakpoint {
             BreakpointType::Line { source, line } => {
-                // If this is a .luax file, translate the position to compiled .lua
-                let (actual_source, actual_line) = if source.ends_with(".luax") {
-                    let source_path = PathBuf::from(&source);
-                    if let Some((lua_file, lua_line, _)) = self.translate_to_compiled(&source_path, line, 1) {
-                        (lua_file.to_string_lossy().to_string(), lua_line)
-                    } else {
-                        // No source map found, use original (will debug compiled code)
-                        (source.clone(), line)
-                    }
-                } else {
-                    (source.clone(), line)
-                };
+                let (actual_source, actual_line) = self.compiled_location(&source, line);
+                let id = self.next_breakpoint_id();
 
-                let mut breakpoints = self.breakpoints.lock().unwrap();
-                breakpoints.entry(actual_source.clone()).or_default().push(actual_line);
+                self.breakpoints.lock().unwrap().entry(actual_source.clone()).or_default().push(actual_line);
+                self.line_breakpoints.insert(id, (actual_source, actual_line));
 
                 self.install_hook();
 
                 Ok(Breakpoint {
-                    id: 1,
+                    id,
                     verified: true,
                     line: actual_line,
                     message: None,
                 })
             }
             BreakpointType::Function { name } => Ok(Breakpoint {
-                id: 1,
+                id: self.next_breakpoint_id(),
                 verified: true,
                 line: 1,
                 message: Some(format!("Function breakpoint: {}", name)),
             }),
             BreakpointType::Exception { filter } => Ok(Breakpoint {
-                id: 1,
+                id: self.next_breakpoint_id(),
                 verified: true,
                 line: 0,
                 message: Some(format!("Exception breakpoint: {}", filter)),
             }),
+            BreakpointType::Metamethod { .. } => Err(RuntimeError::NotImplemented(
+                "Metamethod breakpoints not supported".to_string(),
+            )),
         }
     }
 
-    async fn remove_breakpoint(&mut self, _id: i64) -> Result<(), RuntimeError> {
+    async fn remove_breakpoint(&mut self, id: i64) -> Result<(), RuntimeError> {
+        if let Some((source, line)) = self.line_breakpoints.remove(&id) {
+            let mut breakpoints = self.breakpoints.lock().unwrap();
+            if let Some(lines) = breakpoints.get_mut(&source) {
+                // Another breakpoint may sit on the same line
+                if let Some(index) = lines.iter().position(|&l| l == line) {
+                    lines.remove(index);
+                }
+            }
+        }
         Ok(())
     }
 
     async fn step(&mut self, mode: StepMode) -> Result<(), RuntimeError> {
+        // Variable references are only valid while the program is stopped
+        self.variable_refs.lock().unwrap().clear();
         self.set_step(mode);
         Ok(())
     }
 
     async fn continue_(&mut self) -> Result<(), RuntimeError> {
+        self.variable_refs.lock().unwrap().clear();
         self.resume();
         Ok(())
     }
 
     async fn pause(&mut self) -> Result<(), RuntimeError> {
-        unsafe {
-            PAUSED.store(true, Ordering::SeqCst);
-        }
+        self.hook_state.cancel_run_to();
+        self.hook_state.paused.store(true, Ordering::SeqCst);
         Ok(())
     }
 
@@ -600,6 +598,7 @@ impl DebugRuntime for LuaNextRuntime {
                     }),
                     line: final_line,
                     column: final_column,
+                    presentation_hint: None,
                 });
             }
         }
@@ -608,14 +607,20 @@ impl DebugRuntime for LuaNextRuntime {
     }
 
     async fn scopes(&mut self, frame_id: i64) -> Result<Vec<Scope>, RuntimeError> {
+        let mut refs = self.variable_refs.lock().unwrap();
         Ok(vec![
             Scope {
-                variables_reference: frame_id,
+                variables_reference: refs.register(VariableReference::Locals { frame: frame_id }),
                 name: "Locals".to_string(),
                 expensive: false,
             },
             Scope {
-                variables_reference: -1,
+                variables_reference: refs.register(VariableReference::Upvalues { frame: frame_id }),
+                name: "Upvalues".to_string(),
+                expensive: false,
+            },
+            Scope {
+                variables_reference: refs.register(VariableReference::Globals),
                 name: "Globals".to_string(),
                 expensive: true,
             },
@@ -629,200 +634,51 @@ impl DebugRuntime for LuaNextRuntime {
     ) -> Result<Vec<super::Variable>, RuntimeError> {
         let mut variables = Vec::new();
         let mut lua = self.lua.lock().unwrap();
-
-        if variables_reference >= 0 {
-            // Handle local variables using debug.getlocal
-            unsafe {
-                // For local variables, variables_reference represents the frame ID
-                let frame_id = variables_reference as c_int;
-                
-                // Create a debug info structure for the specified frame
-                let mut ar = std::mem::zeroed::<lua_Debug>();
-                // Get stack info for the frame
-                if lua.lua_getstack( frame_id, &mut ar) != 0 {
-                    // Enumerate local variables using lua_getlocal
-                    let mut index = 1i32;
-                    loop {
-                        // Get local variable name and value
-                        let name_ptr = lua.lua_getlocal( &mut ar, index);
-                        
-                        if name_ptr.is_null() {
-                            break; // No more local variables
-                        }
-                        
-                        // Get the local variable name
-                        let name_cstr = CStr::from_ptr(name_ptr);
-                        let name = name_cstr.to_string_lossy().to_string();
-                        
+        let mut refs = self.variable_refs.lock().unwrap();
+        let top = lua.get_top();
+
+        match refs.get(variables_reference) {
+            Some(VariableReference::Locals { frame }) => {
+                let mut ar = unsafe { std::mem::zeroed::<lua_Debug>() };
+                if lua.get_stack(frame as c_int, &mut ar) != 0 {
+                    let mut index = 1;
+                    while let Some(name) = lua.get_local(&mut ar, index) {
                         // Skip special variables that start with "(" like "(temporary)"
-                        if !name.starts_with("(") {
-                            // Get the local variable value (it's on top of the stack)
-                            let value_type = lua.type_of(-1);
-                            let value_str = match value_type {
-                                0 => "nil".to_string(),
-                                1 => format!("{}", lua.pop_boolean()),
-                                3 => format!("{}", lua.pop_number()),
-                                4 => lua.pop_string(),
-                                5 => format!("table: 0x{:x}", lua.topointer(-1) as usize),
-                                6 => format!("function: 0x{:x}", lua.topointer(-1) as usize),
-                                7 => format!("userdata: 0x{:x}", lua.topointer(-1) as usize),
-                                8 => format!("thread: 0x{:x}", lua.topointer(-1) as usize),
-                                _ => format!("{}", lua.type_name(value_type)),
-                            };
-
-                            variables.push(super::Variable {
-                                name,
-                                value: value_str,
-                                type_: lua.type_name(value_type).to_string(),
-                                variables_reference: if value_type == 5 { Some(-(variables_reference * 1000 + index as i64)) } else { None },
-                                named_variables: None,
-                                indexed_variables: None,
-                            });
+                        if !name.starts_with('(') {
+                            variables.push(top_variable(&mut lua, &mut refs, name));
                         }
-                        
+
                         // Remove the value from the stack
-                        lua.lua_settop( -2);
-                        
+                        lua.lua_settop(-2);
                         index += 1;
                     }
                 }
             }
-        } else if variables_reference == -1 {
-            // Handle global variables by accessing _G
-            unsafe {
-                // Push "_G" string and get the global table
-                let g_name = b"_G\0".as_ptr() as *const i8;
-                if lua.lua_getglobal( g_name) == 0 {
-                    // _G doesn't exist or is nil, remove it from stack
-                    lua.lua_settop( -2);
-                } else {
-                    // Successfully got _G table, iterate it
-                    lua.push_nil(); // First key
-                    let mut count = 0;
-                    while lua.lua_next( -2) != 0 && count < 100 {
-                        let key = lua.pop_string();
-                        let value_type = lua.type_of(-1);
-                        let value_str = match value_type {
-                            0 => "nil".to_string(),
-                            1 => format!("{}", lua.pop_boolean()),
-                            3 => format!("{}", lua.pop_number()),
-                            4 => lua.pop_string(),
-                            5 => format!("table: 0x{:x}", lua.topointer(-1) as usize),
-                            6 => format!("function: 0x{:x}", lua.topointer(-1) as usize),
-                            7 => format!("userdata: 0x{:x}", lua.topointer(-1) as usize),
-                            8 => format!("thread: 0x{:x}", lua.topointer(-1) as usize),
-                            _ => format!("{}", lua.type_name(value_type)),
-                        };
-
-                        variables.push(super::Variable {
-                            name: key,
-                            value: value_str,
-                            type_: lua.type_name(value_type).to_string(),
-                            variables_reference: if value_type == 5 { Some(-2) } else { None },
-                            named_variables: None,
-                            indexed_variables: None,
-                        });
-                        
-                        // Remove value, keep key for next iteration
-                        lua.lua_settop( -2);
-                        count += 1;
-                    }
-                    
-                    // Remove _G table from stack
-                    lua.lua_settop( -2);
-                }
-            }
-        } else if variables_reference < -1000 {
-            // Handle upvalues - negative values less than -1000 represent upvalues
-            // Format: -(frame_id * 1000 + local_index)
-            let abs_ref = -variables_reference;
-            let frame_id = (abs_ref / 1000) as c_int;
-            // let local_index = (abs_ref % 1000) as c_int;
-            
-            unsafe {
-                let mut ar = std::mem::zeroed::<lua_Debug>();
-                if lua.lua_getstack( frame_id, &mut ar) != 0 {
-                    // Get upvalues using lua_getupvalue
-                    let mut index = 1i32;
-                    loop {
-                        let name_ptr = lua_getupvalue(lua.state(), -1, index);
-                        
-                        if name_ptr.is_null() {
-                            break; // No more upvalues
-                        }
-                        
-                        // Get the upvalue name
-                        let name_cstr = CStr::from_ptr(name_ptr);
-                        let name = name_cstr.to_string_lossy().to_string();
-                        
-                        // Get the upvalue value (it's on top of the stack)
-                        let value_type = lua.type_of(-1);
-                        let value_str = match value_type {
-                            0 => "nil".to_string(),
-                            1 => format!("{}", lua.pop_boolean()),
-                            3 => format!("{}", lua.pop_number()),
-                            4 => lua.pop_string(),
-                            5 => format!("table: 0x{:x}", lua.topointer(-1) as usize),
-                            6 => format!("function: 0x{:x}", lua.topointer(-1) as usize),
-                            7 => format!("userdata: 0x{:x}", lua.topointer(-1) as usize),
-                            8 => format!("thread: 0x{:x}", lua.topointer(-1) as usize),
-                            _ => format!("{}", lua.type_name(value_type)),
-                        };
-
-                        variables.push(super::Variable {
-                            name,
-                            value: value_str,
-                            type_: lua.type_name(value_type).to_string(),
-                            variables_reference: if value_type == 5 { Some(-(variables_reference * 100 + index as i64)) } else { None },
-                            named_variables: None,
-                            indexed_variables: None,
-                        });
-                        
+            Some(VariableReference::Upvalues { frame }) => {
+                let mut ar = unsafe { std::mem::zeroed::<lua_Debug>() };
+                // Push the frame's function so its upvalues can be read
+                if lua.get_stack(frame as c_int, &mut ar) != 0 && lua.get_info("f", &mut ar) != 0 {
+                    let mut index = 1;
+                    while let Some(name) = lua.get_upvalue(-1, index) {
+                        variables.push(top_variable(&mut lua, &mut refs, name));
+
                         // Remove the value from the stack
-                        lua.lua_settop( -2);
-                        
+                        lua.lua_settop(-2);
                         index += 1;
                     }
                 }
             }
-        } else if variables_reference == -2 {
-            // Handle table expansion with depth limits
-            unsafe {
-                // The table is already on the stack (placed there by the caller)
-                // Limit the number of elements we show to prevent huge expansions
-                lua.push_nil(); // First key
-                let mut count = 0;
-                while lua.lua_next( -2) != 0 && count < 50 {
-                    let key = lua.pop_string();
-                    let value_type = lua.type_of(-1);
-                    let value_str = match value_type {
-                        0 => "nil".to_string(),
-                        1 => format!("{}", lua.pop_boolean()),
-                        3 => format!("{}", lua.pop_number()),
-                        4 => lua.pop_string(),
-                        5 => format!("table: 0x{:x}", lua.topointer(-1) as usize),
-                        6 => format!("function: 0x{:x}", lua.topointer(-1) as usize),
-                        7 => format!("userdata: 0x{:x}", lua.topointer(-1) as usize),
-                        8 => format!("thread: 0x{:x}", lua.topointer(-1) as usize),
-                        _ => format!("{}", lua.type_name(value_type)),
-                    };
-
-                    variables.push(super::Variable {
-                        name: key,
-                        value: value_str,
-                        type_: lua.type_name(value_type).to_string(),
-                        variables_reference: if value_type == 5 { Some(-2) } else { None },
-                        named_variables: None,
-                        indexed_variables: None,
-                    });
-                    
-                    // Remove value, keep key for next iteration
-                    lua.lua_settop( -2);
-                    count += 1;
-                }
+            Some(VariableReference::Globals) => {
+                lua.lua_pushglobaltable();
+                variables = list_table(&mut lua, &mut refs);
             }
+            Some(VariableReference::Value { slot }) if refs.push_pinned(&mut lua, slot) => {
+                variables = list_table(&mut lua, &mut refs);
+            }
+            Some(VariableReference::Value { .. }) | None => {}
         }
 
+        lua.set_top(top);
         Ok(variables)
     }
 
@@ -842,11 +698,11 @@ impl DebugRuntime for LuaNextRuntime {
             // This is a simplified approach - in a real implementation we'd want
             // to check if the assignment is to a local variable or global
             // For now, we'll allow it but log that it's happening
-            println!("Warning: Assignment detected in expression evaluation: {}", trimmed);
+            tracing::warn!(expression = trimmed, "assignment in evaluated expression");
         }
         
         if is_dangerous_function {
-            println!("Warning: Potentially dangerous function call detected: {}", trimmed);
+            tracing::warn!(expression = trimmed, "potentially dangerous function call in evaluated expression");
         }
 
         // Use safer evaluation method
@@ -866,8 +722,10 @@ impl DebugRuntime for LuaNextRuntime {
         }
     }
 
-    async fn run_to_location(&mut self, _source: &str, _line: u32) -> Result<(), RuntimeError> {
-        Ok(())
+    async fn run_to_location(&mut self, source: &str, line: u32) -> Result<(), RuntimeError> {
+        let location = self.compiled_location(source, line);
+        *self.hook_state.run_to.lock().unwrap() = Some(location);
+        self.continue_().await
     }
 
     async fn source(&mut self, _source_reference: i64) -> Result<String, RuntimeError> {
@@ -941,6 +799,27 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_remove_breakpoint_by_id() {
+        block_on(async {
+            let mut runtime = LuaNextRuntime::new();
+
+            let first = runtime.set_breakpoint(BreakpointType::Line {
+                source: "test.lua".to_string(),
+                line: 10,
+            }).await.unwrap();
+            let second = runtime.set_breakpoint(BreakpointType::Line {
+                source: "test.lua".to_string(),
+                line: 20,
+            }).await.unwrap();
+            assert_ne!(first.id, second.id);
+
+            runtime.remove_breakpoint(first.id).await.unwrap();
+            assert!(!runtime.is_breakpoint_hit("test.lua", 10));
+            assert!(runtime.is_breakpoint_hit("test.lua", 20));
+        });
+    }
+
     #[test]
     fn test_step_mode_conversion() {
         assert_eq!(StepMode::Over.to_u32(), 0);
@@ -1,7 +1,80 @@
-use super::{Frame, RuntimeError, RuntimeVersion, Scope, StepMode, Value, Variable, VariableScope};
-use std::collections::HashMap;
+//! A scriptable runtime for tests
+//!
+//! Out of the box the mock answers with a small fixed program. Tests that
+//! drive the DAP server end to end script it instead: they queue the stops
+//! the program makes each time it is resumed, the outcome of the next
+//! breakpoints set, and the frames, variables and values it reports. Clones
+//! share their state, so a test keeps a clone to script and inspect the
+//! runtime after handing it to a server.
+
+use super::{Frame, ProgramLaunch, RuntimeError, RuntimeVersion, Scope, StepMode, Value, Variable, VariableScope};
+use crate::dap::{Event, EventSender};
+use crate::debug::memory_references::{self, MemoryRead};
+use crate::debug::modules::Module;
+use crate::debug::watchpoints::DataBreakpoint;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
 
+/// Thread id the mock's stops are reported on
+pub const MOCK_THREAD_ID: u64 = 1;
+
+/// A stop the program makes when it is next resumed
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockStop {
+    /// Reason of the stopped event, such as `breakpoint` or `step`
+    pub reason: String,
+    /// Stack reported while stopped; empty keeps the current one
+    pub frames: Vec<Frame>,
+}
+
+impl MockStop {
+    pub fn new(reason: &str, frames: Vec<Frame>) -> Self {
+        Self {
+            reason: reason.to_string(),
+            frames,
+        }
+    }
+}
+
+/// How the runtime answers the next line breakpoint set
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MockBreakpointOutcome {
+    pub verified: bool,
+    /// Line the breakpoint moves to, if not the requested one
+    pub line: Option<u32>,
+    pub message: Option<String>,
+}
+
+/// A frame in `path`, for scripting stacks
+pub fn frame(id: i64, name: &str, path: &str, line: u32) -> Frame {
+    let file_name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    Frame {
+        id,
+        name: name.to_string(),
+        source: Some(super::Source {
+            name: file_name.to_string(),
+            path: path.to_string(),
+            source_reference: None,
+        }),
+        line,
+        column: 1,
+        presentation_hint: None,
+    }
+}
+
+/// A variable with no children, for scripting scopes
+pub fn variable(name: &str, value: &str, type_: &str) -> Variable {
+    Variable {
+        name: name.to_string(),
+        value: value.to_string(),
+        type_: type_.to_string(),
+        variables_reference: None,
+        named_variables: None,
+        indexed_variables: None,
+        memory_reference: None,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MockRuntime {
     state: Arc<Mutex<MockState>>,
@@ -14,6 +87,33 @@ struct MockState {
     paused: bool,
     current_frame: Option<Frame>,
     variables: HashMap<i64, Vec<Variable>>,
+    /// Ids handed out so far
+    last_breakpoint_id: i64,
+    /// Location of each line breakpoint, by id
+    line_breakpoints: HashMap<i64, (String, u32)>,
+    /// Stack set by a test, reported instead of `current_frame`
+    frames: Option<Vec<Frame>>,
+    /// Stops to make, one each time the program is resumed
+    stops: VecDeque<MockStop>,
+    /// Outcomes of the next line breakpoints set
+    breakpoint_outcomes: VecDeque<MockBreakpointOutcome>,
+    /// Values of expressions set by a test
+    evaluations: HashMap<String, Value>,
+    /// Program handed to `launch_program`
+    launch: Option<ProgramLaunch>,
+    /// Number of times the program was started or resumed
+    resumes: usize,
+    /// Data breakpoints last set, all verified
+    data_breakpoints: Vec<DataBreakpoint>,
+    /// Modules listed by `modules`, set by a test
+    modules: Vec<Module>,
+    /// Later lines of the stopped function; unset, the mock cannot tell
+    goto_lines: Option<Vec<u32>>,
+    /// Bytes behind each memory reference
+    memory: HashMap<String, Vec<u8>>,
+    /// Message the next `modules` call panics with
+    modules_panic: Option<String>,
+    events: Option<EventSender>,
 }
 
 impl MockRuntime {
@@ -32,6 +132,7 @@ impl MockRuntime {
                     variables_reference: None,
                     named_variables: None,
                     indexed_variables: None,
+                    memory_reference: None,
                 },
                 Variable {
                     name: "y".to_string(),
@@ -40,12 +141,150 @@ impl MockRuntime {
                     variables_reference: None,
                     named_variables: None,
                     indexed_variables: None,
+                    memory_reference: None,
+                },
+                Variable {
+                    name: "player".to_string(),
+                    value: "table".to_string(),
+                    type_: "table".to_string(),
+                    variables_reference: Some(1),
+                    named_variables: Some(2),
+                    indexed_variables: None,
+                    memory_reference: None,
+                },
+            ],
+        );
+        variables.insert(
+            1,
+            vec![
+                Variable {
+                    name: "hp".to_string(),
+                    value: "10".to_string(),
+                    type_: "number".to_string(),
+                    variables_reference: None,
+                    named_variables: None,
+                    indexed_variables: None,
+                    memory_reference: None,
+                },
+                Variable {
+                    name: "heal".to_string(),
+                    value: "function".to_string(),
+                    type_: "function".to_string(),
+                    variables_reference: None,
+                    named_variables: None,
+                    indexed_variables: None,
+                    memory_reference: None,
                 },
             ],
         );
+        state.lock().unwrap().variables = variables;
 
         Self { state, breakpoints }
     }
+
+    /// Lines with a breakpoint in a source, for tests to check what was set
+    pub fn breakpoint_lines(&self, source: &str) -> Vec<u32> {
+        self.breakpoints.lock().unwrap().get(source).cloned().unwrap_or_default()
+    }
+
+    /// Sets the stack reported from now on
+    pub fn set_frames(&self, frames: Vec<Frame>) {
+        self.state.lock().unwrap().frames = Some(frames);
+    }
+
+    /// Sets the children of a variables reference; a frame's locals use its id
+    pub fn set_variables(&self, variables_reference: i64, variables: Vec<Variable>) {
+        self.state.lock().unwrap().variables.insert(variables_reference, variables);
+    }
+
+    /// Sets the value an expression evaluates to
+    pub fn set_evaluation(&self, expression: &str, value: Value) {
+        self.state.lock().unwrap().evaluations.insert(expression.to_string(), value);
+    }
+
+    /// Sets the modules the program has loaded
+    pub fn set_modules(&self, modules: Vec<Module>) {
+        self.state.lock().unwrap().modules = modules;
+    }
+
+    /// Makes the next `modules` call panic with `message`, standing in for a bug in a handler
+    pub fn panic_on_next_modules(&self, message: &str) {
+        self.state.lock().unwrap().modules_panic = Some(message.to_string());
+    }
+
+    /// Sets the lines after the current one of the function the program is stopped in
+    pub fn set_goto_lines(&self, lines: Vec<u32>) {
+        self.state.lock().unwrap().goto_lines = Some(lines);
+    }
+
+    /// Sets the bytes behind a memory reference, which reads and writes then use
+    pub fn set_memory(&self, memory_reference: &str, bytes: Vec<u8>) {
+        self.state.lock().unwrap().memory.insert(memory_reference.to_string(), bytes);
+    }
+
+    /// The bytes behind a memory reference, as writes left them
+    pub fn memory(&self, memory_reference: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().memory.get(memory_reference).cloned()
+    }
+
+    /// Queues a stop, made the next time the program starts or resumes without one queued before it
+    ///
+    /// A program resumed with no stop queued runs to its end, sending
+    /// `exited` and `terminated`.
+    pub fn queue_stop(&self, stop: MockStop) {
+        self.state.lock().unwrap().stops.push_back(stop);
+    }
+
+    /// Queues how the next line breakpoint set is answered; unqueued ones are verified
+    pub fn queue_breakpoint_outcome(&self, outcome: MockBreakpointOutcome) {
+        self.state.lock().unwrap().breakpoint_outcomes.push_back(outcome);
+    }
+
+    /// The program the runtime was launched with
+    pub fn launched_program(&self) -> Option<ProgramLaunch> {
+        self.state.lock().unwrap().launch.clone()
+    }
+
+    /// Number of times the program was started or resumed
+    pub fn resumes(&self) -> usize {
+        self.state.lock().unwrap().resumes
+    }
+
+    /// Data breakpoints last set
+    pub fn data_breakpoints(&self) -> Vec<DataBreakpoint> {
+        self.state.lock().unwrap().data_breakpoints.clone()
+    }
+
+    /// Makes the next queued stop, or ends the program when none is left
+    fn resume(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.resumes += 1;
+        let events = match state.stops.pop_front() {
+            Some(stop) => {
+                state.paused = true;
+                if !stop.frames.is_empty() {
+                    state.frames = Some(stop.frames);
+                }
+                vec![Event::stopped(&stop.reason, Some(MOCK_THREAD_ID), true)]
+            }
+            None => {
+                state.running = false;
+                state.paused = false;
+                vec![Event::exited(0), Event::terminated()]
+            }
+        };
+        if let Some(sender) = &state.events {
+            for event in events {
+                let _ = sender.send(event);
+            }
+        }
+    }
+
+    fn next_breakpoint_id(&self) -> i64 {
+        let mut state = self.state.lock().unwrap();
+        state.last_breakpoint_id += 1;
+        state.last_breakpoint_id
+    }
 }
 
 #[async_trait::async_trait]
@@ -63,35 +302,64 @@ impl super::DebugRuntime for MockRuntime {
     ) -> Result<super::Breakpoint, RuntimeError> {
         match breakpoint {
             super::BreakpointType::Line { source, line } => {
+                let id = self.next_breakpoint_id();
+                let outcome = {
+                    let mut state = self.state.lock().unwrap();
+                    state.line_breakpoints.insert(id, (source.clone(), line));
+                    state.breakpoint_outcomes.pop_front()
+                };
                 let mut breakpoints = self.breakpoints.lock().unwrap();
                 breakpoints.entry(source).or_default().push(line);
-                Ok(super::Breakpoint {
-                    id: 1,
+                let outcome = outcome.unwrap_or(MockBreakpointOutcome {
                     verified: true,
-                    line,
-                    message: None,
+                    ..Default::default()
+                });
+                Ok(super::Breakpoint {
+                    id,
+                    verified: outcome.verified,
+                    line: outcome.line.unwrap_or(line),
+                    message: outcome.message,
                 })
             }
             super::BreakpointType::Function { name } => Ok(super::Breakpoint {
-                id: 1,
+                id: self.next_breakpoint_id(),
                 verified: true,
                 line: 1,
                 message: Some(format!("Function breakpoint: {}", name)),
             }),
             super::BreakpointType::Exception { filter } => Ok(super::Breakpoint {
-                id: 1,
+                id: self.next_breakpoint_id(),
                 verified: true,
                 line: 0,
                 message: Some(format!("Exception breakpoint: {}", filter)),
             }),
+            super::BreakpointType::Metamethod { target, event } => Ok(super::Breakpoint {
+                id: self.next_breakpoint_id(),
+                verified: true,
+                line: 0,
+                message: Some(format!("Metamethod breakpoint: {} {}", target, event)),
+            }),
         }
     }
 
-    async fn remove_breakpoint(&mut self, _id: i64) -> Result<(), RuntimeError> {
+    async fn remove_breakpoint(&mut self, id: i64) -> Result<(), RuntimeError> {
+        let removed = self.state.lock().unwrap().line_breakpoints.remove(&id);
+        if let Some((source, line)) = removed {
+            let mut breakpoints = self.breakpoints.lock().unwrap();
+            if let Some(lines) = breakpoints.get_mut(&source) {
+                if let Some(index) = lines.iter().position(|&l| l == line) {
+                    lines.remove(index);
+                }
+            }
+        }
         Ok(())
     }
 
     async fn step(&mut self, _mode: StepMode) -> Result<(), RuntimeError> {
+        if !self.state.lock().unwrap().stops.is_empty() {
+            self.resume();
+            return Ok(());
+        }
         let mut state = self.state.lock().unwrap();
         state.paused = true;
         state.current_frame = Some(Frame {
@@ -104,17 +372,44 @@ impl super::DebugRuntime for MockRuntime {
             }),
             line: 5,
             column: 1,
+            presentation_hint: None,
         });
         Ok(())
     }
 
     async fn continue_(&mut self) -> Result<(), RuntimeError> {
-        let mut state = self.state.lock().unwrap();
-        state.running = true;
-        state.paused = false;
+        {
+            let mut state = self.state.lock().unwrap();
+            state.running = true;
+            state.paused = false;
+            // Without a client listening the program just keeps running
+            if state.events.is_none() {
+                return Ok(());
+            }
+        }
+        self.resume();
+        Ok(())
+    }
+
+    async fn launch_program(&mut self, launch: &ProgramLaunch) -> Result<(), RuntimeError> {
+        self.state.lock().unwrap().launch = Some(launch.clone());
+        Ok(())
+    }
+
+    fn has_program(&self) -> bool {
+        self.state.lock().unwrap().launch.is_some()
+    }
+
+    async fn start_program(&mut self, _stop_on_entry: bool) -> Result<(), RuntimeError> {
+        self.state.lock().unwrap().running = true;
+        self.resume();
         Ok(())
     }
 
+    fn set_event_sender(&mut self, sender: EventSender) {
+        self.state.lock().unwrap().events = Some(sender);
+    }
+
     async fn pause(&mut self) -> Result<(), RuntimeError> {
         let mut state = self.state.lock().unwrap();
         state.paused = true;
@@ -123,7 +418,9 @@ impl super::DebugRuntime for MockRuntime {
 
     async fn stack_trace(&mut self, _thread_id: Option<u64>) -> Result<Vec<Frame>, RuntimeError> {
         let state = self.state.lock().unwrap();
-        if let Some(frame) = &state.current_frame {
+        if let Some(frames) = &state.frames {
+            Ok(frames.clone())
+        } else if let Some(frame) = &state.current_frame {
             Ok(vec![frame.clone()])
         } else {
             Ok(vec![Frame {
@@ -136,6 +433,7 @@ impl super::DebugRuntime for MockRuntime {
                 }),
                 line: 1,
                 column: 1,
+                presentation_hint: None,
             }])
         }
     }
@@ -169,6 +467,9 @@ impl super::DebugRuntime for MockRuntime {
     }
 
     async fn evaluate(&mut self, _frame_id: i64, expression: &str) -> Result<Value, RuntimeError> {
+        if let Some(value) = self.state.lock().unwrap().evaluations.get(expression.trim()) {
+            return Ok(value.clone());
+        }
         match expression.trim() {
             "x" => Ok(Value::Number(10.0)),
             "y" => Ok(Value::Number(20.0)),
@@ -205,6 +506,7 @@ impl super::DebugRuntime for MockRuntime {
                 }),
                 line: 10,
                 column: 5,
+                presentation_hint: None,
             }],
             inner_exception: None,
             details: None,
@@ -214,4 +516,58 @@ impl super::DebugRuntime for MockRuntime {
     async fn check_data_breakpoints(&mut self, _frame_id: i64) -> Result<bool, RuntimeError> {
         Ok(false)
     }
+
+    async fn goto_lines(&mut self, _source: &str) -> Result<Vec<u32>, RuntimeError> {
+        self.state
+            .lock()
+            .unwrap()
+            .goto_lines
+            .clone()
+            .ok_or_else(|| RuntimeError::NotImplemented("Goto targets not supported".to_string()))
+    }
+
+    async fn read_memory(&mut self, memory_reference: &str, offset: i64, count: usize) -> Result<MemoryRead, RuntimeError> {
+        let state = self.state.lock().unwrap();
+        let bytes = state.memory.get(memory_reference).ok_or_else(|| {
+            RuntimeError::VariableNotFound(format!("Memory reference {} is no longer valid", memory_reference))
+        })?;
+        Ok(memory_references::read(bytes, 0, offset, count))
+    }
+
+    async fn write_memory(
+        &mut self,
+        memory_reference: &str,
+        offset: i64,
+        data: Vec<u8>,
+        allow_partial: bool,
+    ) -> Result<usize, RuntimeError> {
+        let mut state = self.state.lock().unwrap();
+        let bytes = state.memory.get_mut(memory_reference).ok_or_else(|| {
+            RuntimeError::VariableNotFound(format!("Memory reference {} is no longer valid", memory_reference))
+        })?;
+        let range = memory_references::write_range(bytes.len(), offset, data.len(), allow_partial)
+            .map_err(RuntimeError::MemoryAccess)?;
+        let written = range.len();
+        bytes[range].copy_from_slice(&data[..written]);
+        Ok(written)
+    }
+
+    async fn modules(&mut self) -> Result<Vec<Module>, RuntimeError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(message) = state.modules_panic.take() {
+            // Unlocked first, so the panic does not poison the state
+            drop(state);
+            panic!("{}", message);
+        }
+        Ok(state.modules.clone())
+    }
+
+    async fn set_data_breakpoints(&mut self, breakpoints: Vec<DataBreakpoint>) -> Result<Vec<DataBreakpoint>, RuntimeError> {
+        let breakpoints: Vec<DataBreakpoint> = breakpoints
+            .into_iter()
+            .map(|bp| DataBreakpoint { verified: true, ..bp })
+            .collect();
+        self.state.lock().unwrap().data_breakpoints = breakpoints.clone();
+        Ok(breakpoints)
+    }
 }
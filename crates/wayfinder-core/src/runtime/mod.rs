@@ -26,7 +26,7 @@ pub enum RuntimeType {
     LuaNext,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct RuntimeVersion {
     pub runtime: RuntimeType,
     pub version: LuaVersion,
@@ -41,6 +41,49 @@ impl fmt::Display for RuntimeVersion {
     }
 }
 
+/// Environment variable naming the Lua library dynamic builds load, when no setting does
+pub const LUA_LIBRARY_ENV_VAR: &str = "LUA_WAYFINDER_LIB";
+
+/// How a runtime got its Lua: linked into Wayfinder or loaded from a library
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LuaMode {
+    /// Lua 5.4 linked into Wayfinder, as in static builds or when no library was found
+    Static,
+    /// A Lua library loaded at runtime
+    Dynamic,
+}
+
+/// The Lua a runtime runs programs on, as reported to clients in `initialize`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LuaBackend {
+    pub mode: LuaMode,
+    pub version: LuaVersion,
+    /// The library loaded, in the dynamic mode
+    pub library: Option<std::path::PathBuf>,
+}
+
+impl fmt::Display for LuaBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.library {
+            Some(library) => write!(f, "Lua {} from {}", self.version, library.display()),
+            None => write!(f, "Lua {} linked into Wayfinder", self.version),
+        }
+    }
+}
+
+/// A program as a launch request asks for it to be loaded
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgramLaunch {
+    pub program: String,
+    /// Arguments the program finds in `arg`, after the script name at `arg[0]`
+    pub args: Vec<String>,
+    /// Replaces `package.path`; `;;` stands for the path it had
+    pub lua_path: Option<String>,
+    /// Replaces `package.cpath`; `;;` stands for the path it had
+    pub lua_cpath: Option<String>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum StepMode {
     Over,
@@ -66,6 +109,32 @@ impl StepMode {
     }
 }
 
+/// How far a step goes, from a step request's `granularity`
+///
+/// Lua has no statements apart from lines, so a statement step is a line step.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StepGranularity {
+    Statement,
+    #[default]
+    Line,
+    /// One bytecode instruction, for stepping through compiled output such as TSTL's
+    Instruction,
+}
+
+/// Where a frame step stops, relative to the function it watches
+///
+/// Games drive everything from a per-frame function such as `love.update`, so
+/// stepping to its next call or its return steps one game frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FrameStepTarget {
+    /// First line of the function's next invocation
+    NextCall,
+    /// First line run after the function returns
+    Return,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum VariableScope {
     Local,
@@ -74,6 +143,121 @@ pub enum VariableScope {
     Table { reference: i64 },
 }
 
+/// Which children of a container the `variables` request lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VariablesFilter {
+    /// Array elements, as counted by `indexed_variables`
+    Indexed,
+    /// Everything else
+    Named,
+}
+
+impl VariablesFilter {
+    /// Whether a child belongs to this filter, telling array elements apart by their `[n]` names
+    pub fn matches(self, variable: &Variable) -> bool {
+        let indexed = variable
+            .name
+            .strip_prefix('[')
+            .and_then(|name| name.strip_suffix(']'))
+            .is_some_and(|index| index.parse::<i64>().is_ok());
+        indexed == (self == VariablesFilter::Indexed)
+    }
+}
+
+/// A slice of a container's children, from the `variables` request's paging arguments
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VariablesPage {
+    pub filter: Option<VariablesFilter>,
+    /// Index of the first child to return
+    pub start: usize,
+    /// Number of children to return, or all of them
+    pub count: Option<usize>,
+    /// How the children's numbers are shown
+    #[serde(default)]
+    pub format: ValueFormat,
+}
+
+impl VariablesPage {
+    /// Cuts the page out of a complete listing, keeping only the children the filter asks for
+    pub fn apply(&self, variables: Vec<Variable>) -> Vec<Variable> {
+        let filter = self.filter;
+        let variables = variables.into_iter().filter(|v| filter.is_none_or(|f| f.matches(v))).collect();
+        self.slice(variables)
+    }
+
+    /// Cuts the page out of a listing already limited to the filter's children
+    pub fn slice(&self, variables: Vec<Variable>) -> Vec<Variable> {
+        variables
+            .into_iter()
+            .skip(self.start)
+            .take(self.count.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+/// How numbers are shown, from a request's DAP `format` and the configured precision
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValueFormat {
+    /// Whole numbers in hexadecimal, as `0x1f`
+    #[serde(default)]
+    pub hex: bool,
+    /// Digits after the decimal point of fractional numbers; the shortest exact form without it
+    #[serde(default)]
+    pub precision: Option<usize>,
+}
+
+impl ValueFormat {
+    /// This format, with the precision `fallback` has when it sets none
+    pub fn or(self, fallback: ValueFormat) -> Self {
+        Self {
+            hex: self.hex || fallback.hex,
+            precision: self.precision.or(fallback.precision),
+        }
+    }
+
+    pub fn format_number(&self, number: f64) -> String {
+        // Beyond 2^63 a whole float no longer fits an integer
+        if self.hex && number.fract() == 0.0 && number.abs() < 9.2e18 {
+            let whole = number as i64;
+            let sign = if whole < 0 { "-" } else { "" };
+            return format!("{}0x{:x}", sign, whole.unsigned_abs());
+        }
+        match self.precision {
+            Some(precision) if number.is_finite() && number.fract() != 0.0 => format!("{:.*}", precision, number),
+            _ => number.to_string(),
+        }
+    }
+}
+
+/// How much of a value the Variables pane and debug console show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisplayLimits {
+    /// Named children a table lists before the rest are left out, to keep huge tables responsive
+    pub max_children: usize,
+    /// Characters of a string shown before it is cut
+    pub max_string_length: usize,
+}
+
+impl Default for DisplayLimits {
+    fn default() -> Self {
+        Self {
+            max_children: 100,
+            max_string_length: 200,
+        }
+    }
+}
+
+/// A thread of execution the client can inspect: the main state or a coroutine
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Thread {
+    pub id: u64,
+    pub name: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Frame {
     pub id: i64,
@@ -81,6 +265,9 @@ pub struct Frame {
     pub source: Option<Source>,
     pub line: u32,
     pub column: u32,
+    /// DAP `presentationHint`, such as `label` for a frame that only separates others
+    #[serde(default)]
+    pub presentation_hint: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -98,6 +285,9 @@ pub struct Variable {
     pub variables_reference: Option<i64>,
     pub named_variables: Option<u32>,
     pub indexed_variables: Option<u32>,
+    /// Handle for `read_memory` of a string's or userdata's bytes
+    #[serde(default)]
+    pub memory_reference: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -118,6 +308,30 @@ pub enum Value {
     Thread,
 }
 
+impl Value {
+    /// The value as the debug console shows it, and its type name
+    pub fn render(&self) -> (String, String) {
+        self.render_formatted(ValueFormat::default())
+    }
+
+    /// The value as `render` shows it, with numbers in `format`
+    pub fn render_formatted(&self, format: ValueFormat) -> (String, String) {
+        match self {
+            Value::Nil => ("nil".to_string(), "nil".to_string()),
+            Value::Boolean(b) => (b.to_string(), "boolean".to_string()),
+            Value::Number(n) => (format.format_number(*n), "number".to_string()),
+            Value::String(s) => (format!("\"{}\"", s), "string".to_string()),
+            Value::Table { reference, length } => (format!("table (ref={}, len={})", reference, length), "table".to_string()),
+            Value::Function { reference, name } => (
+                format!("function (ref={}, name={})", reference, name.as_deref().unwrap_or_default()),
+                "function".to_string(),
+            ),
+            Value::UserData => ("userdata".to_string(), "userdata".to_string()),
+            Value::Thread => ("thread".to_string(), "thread".to_string()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Breakpoint {
     pub id: i64,
@@ -126,11 +340,73 @@ pub struct Breakpoint {
     pub message: Option<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BreakpointType {
     Line { source: String, line: u32 },
     Function { name: String },
     Exception { filter: String },
+    /// Stops whenever the `event` metamethod (`__index`, `__call`, ...) of the
+    /// metatable `target` evaluates to fires
+    Metamethod { target: String, event: String },
+}
+
+/// Stable error codes of failed requests, sent as the DAP error message id
+///
+/// Clients may match on these, so a code keeps its meaning once released.
+/// Codes are grouped by what went wrong: the debugger or its connection
+/// (1xxx), a request naming something that does not exist or cannot be done
+/// in the program's current state (2xxx), an evaluated expression (3xxx) and
+/// incompatible components (4xxx).
+pub mod error_code {
+    pub const IO: i32 = 1001;
+    pub const PROCESS_EXITED: i32 = 1002;
+    pub const PROCESS_KILLED: i32 = 1003;
+    pub const COMMUNICATION: i32 = 1004;
+    pub const NOT_IMPLEMENTED: i32 = 1005;
+    pub const NO_SESSION: i32 = 1006;
+    pub const LAUNCH_FAILED: i32 = 1007;
+
+    pub const BREAKPOINT_INVALID: i32 = 2001;
+    pub const FRAME_NOT_FOUND: i32 = 2002;
+    pub const THREAD_NOT_FOUND: i32 = 2003;
+    pub const VARIABLE_NOT_FOUND: i32 = 2004;
+    pub const SOURCE_NOT_FOUND: i32 = 2005;
+    pub const NOT_STOPPED: i32 = 2006;
+    pub const MEMORY_ACCESS: i32 = 2007;
+    pub const GOTO_TARGET_NOT_FOUND: i32 = 2008;
+    pub const MODULE_NOT_FOUND: i32 = 2009;
+    pub const NOT_AVAILABLE: i32 = 2010;
+
+    pub const EVAL_SYNTAX: i32 = 3001;
+    pub const EVAL_RUNTIME: i32 = 3002;
+    pub const EVAL_REJECTED: i32 = 3003;
+    pub const EVAL_TIMEOUT: i32 = 3004;
+
+    pub const VERSION_MISMATCH: i32 = 4001;
+
+    /// Whether the client should show errors with this code to the user
+    ///
+    /// Stale frame, thread, variable, source and goto target ids are a
+    /// client racing the program and not worth a notification, and
+    /// evaluation errors show up where the expression was typed.
+    pub fn shows_user(code: i32) -> bool {
+        code / 1000 != 3
+            && !matches!(
+                code,
+                FRAME_NOT_FOUND | THREAD_NOT_FOUND | VARIABLE_NOT_FOUND | SOURCE_NOT_FOUND | GOTO_TARGET_NOT_FOUND
+            )
+    }
+}
+
+/// Why an evaluated expression failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvalErrorKind {
+    /// It does not compile
+    Syntax,
+    /// It raised an error
+    Runtime,
+    /// The evaluation safety setting does not allow it
+    Rejected,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -149,6 +425,80 @@ pub enum RuntimeError {
 
     #[error("Not implemented: {0}")]
     NotImplemented(String),
+
+    /// An evaluated expression ran past the configured limits
+    #[error("Evaluation timed out: {0}")]
+    EvaluationTimeout(String),
+
+    /// A breakpoint the runtime cannot set
+    #[error("{0}")]
+    BreakpointInvalid(String),
+
+    #[error("No frame {0}")]
+    FrameNotFound(i64),
+
+    #[error("Unknown thread {0}")]
+    ThreadNotFound(u64),
+
+    /// A variable, or variable reference, that is not in scope
+    #[error("{0}")]
+    VariableNotFound(String),
+
+    #[error("Unknown source reference {0}")]
+    SourceNotFound(i64),
+
+    /// The request needs the program stopped, and it is running
+    #[error("{0}")]
+    NotStopped(String),
+
+    /// Memory behind a memory reference that cannot be read or written as asked
+    #[error("{0}")]
+    MemoryAccess(String),
+
+    /// An evaluated expression failed
+    #[error("{message}")]
+    Evaluation { kind: EvalErrorKind, message: String },
+
+    /// A debug agent from another version of the debugger
+    #[error("The debug agent is version {found}, the debugger version {expected}; use the same version for both")]
+    VersionMismatch { expected: String, found: String },
+
+    /// An error a debug agent reported, with the code it gave it
+    #[error("{message}")]
+    Remote { code: i32, message: String },
+}
+
+impl RuntimeError {
+    /// Stable code identifying the kind of error, see `error_code`
+    pub fn code(&self) -> i32 {
+        match self {
+            RuntimeError::Io(_) => error_code::IO,
+            RuntimeError::ProcessExited(_) => error_code::PROCESS_EXITED,
+            RuntimeError::ProcessKilled => error_code::PROCESS_KILLED,
+            RuntimeError::Communication(_) => error_code::COMMUNICATION,
+            RuntimeError::NotImplemented(_) => error_code::NOT_IMPLEMENTED,
+            RuntimeError::EvaluationTimeout(_) => error_code::EVAL_TIMEOUT,
+            RuntimeError::BreakpointInvalid(_) => error_code::BREAKPOINT_INVALID,
+            RuntimeError::FrameNotFound(_) => error_code::FRAME_NOT_FOUND,
+            RuntimeError::ThreadNotFound(_) => error_code::THREAD_NOT_FOUND,
+            RuntimeError::VariableNotFound(_) => error_code::VARIABLE_NOT_FOUND,
+            RuntimeError::SourceNotFound(_) => error_code::SOURCE_NOT_FOUND,
+            RuntimeError::NotStopped(_) => error_code::NOT_STOPPED,
+            RuntimeError::MemoryAccess(_) => error_code::MEMORY_ACCESS,
+            RuntimeError::Evaluation { kind, .. } => match kind {
+                EvalErrorKind::Syntax => error_code::EVAL_SYNTAX,
+                EvalErrorKind::Runtime => error_code::EVAL_RUNTIME,
+                EvalErrorKind::Rejected => error_code::EVAL_REJECTED,
+            },
+            RuntimeError::VersionMismatch { .. } => error_code::VERSION_MISMATCH,
+            RuntimeError::Remote { code, .. } => *code,
+        }
+    }
+
+    /// Whether the client should show the error to the user, as DAP `showUser`
+    pub fn show_user(&self) -> bool {
+        error_code::shows_user(self.code())
+    }
 }
 
 pub type Result<T> = std::result::Result<T, RuntimeError>;
@@ -163,10 +513,60 @@ pub trait DebugRuntime: Send + Sync {
 
     async fn step(&mut self, mode: StepMode) -> Result<()>;
 
+    /// Steps like `step`, but stops at the next bytecode instruction instead of the next line
+    async fn step_instruction(&mut self, mode: StepMode) -> Result<()> {
+        let _ = mode;
+        Err(RuntimeError::NotImplemented("Instruction stepping not supported".to_string()))
+    }
+
     async fn continue_(&mut self) -> Result<()>;
 
+    /// Goes back to the line the program executed before the one it is stopped at
+    async fn step_back(&mut self) -> Result<()> {
+        Err(RuntimeError::NotImplemented("Stepping back not supported".to_string()))
+    }
+
+    /// Goes back to the last breakpoint the program passed before the current stop
+    ///
+    /// Without one, goes back as far as the recording reaches.
+    async fn reverse_continue(&mut self) -> Result<()> {
+        Err(RuntimeError::NotImplemented("Reverse continue not supported".to_string()))
+    }
+
+    /// Resumes until the function `function` evaluates to is next called or returns
+    async fn step_frame(&mut self, function: &str, until: FrameStepTarget) -> Result<()> {
+        let _ = (function, until);
+        Err(RuntimeError::NotImplemented("Frame stepping not supported".to_string()))
+    }
+
     async fn pause(&mut self) -> Result<()>;
 
+    /// Stops the program the next time the thread `thread_id` runs
+    async fn pause_thread(&mut self, thread_id: u64) -> Result<()> {
+        let _ = thread_id;
+        Err(RuntimeError::NotImplemented("Thread-targeted execution not supported".to_string()))
+    }
+
+    /// Resumes only the thread `thread_id`, stopping the program again as soon as another thread runs
+    ///
+    /// Returns whether every thread was continued after all, as when the
+    /// runtime cannot tell its threads apart.
+    async fn continue_thread(&mut self, thread_id: u64) -> Result<bool> {
+        let _ = thread_id;
+        Err(RuntimeError::NotImplemented("Thread-targeted execution not supported".to_string()))
+    }
+
+    /// Lists the main thread and live coroutines
+    ///
+    /// Ids are stable for the life of a thread and are accepted by
+    /// `stack_trace`. Runtimes without coroutine tracking report only main.
+    async fn threads(&mut self) -> Result<Vec<Thread>> {
+        Ok(vec![Thread {
+            id: 1,
+            name: "main".to_string(),
+        }])
+    }
+
     async fn stack_trace(&mut self, thread_id: Option<u64>) -> Result<Vec<Frame>>;
 
     async fn scopes(&mut self, frame_id: i64) -> Result<Vec<Scope>>;
@@ -177,18 +577,153 @@ pub trait DebugRuntime: Send + Sync {
         filter: Option<VariableScope>,
     ) -> Result<Vec<Variable>>;
 
+    /// Lists one page of a scope's or value's children
+    ///
+    /// Runtimes that report `indexed_variables` override this to read array
+    /// slices directly instead of listing everything first.
+    async fn variables_page(&mut self, variables_reference: i64, page: VariablesPage) -> Result<Vec<Variable>> {
+        let variables = self.variables(variables_reference, None).await?;
+        Ok(page.apply(variables))
+    }
+
     async fn evaluate(&mut self, frame_id: i64, expression: &str) -> Result<Value>;
 
+    /// Assigns the result of evaluating `value` to a variable
+    ///
+    /// `variables_reference` identifies the scope or table holding `name`, as
+    /// returned by `scopes` and `variables`.
+    async fn set_variable(&mut self, variables_reference: i64, name: &str, value: &str) -> Result<Variable> {
+        let _ = (variables_reference, name, value);
+        Err(RuntimeError::NotImplemented("Setting variables not supported".to_string()))
+    }
+
+    /// Evaluates an expression against the global environment
+    ///
+    /// Used when the client supplies no frame, e.g. for watches while the
+    /// program is running. Runtimes without a safe-point mechanism fall back
+    /// to evaluating in the top frame.
+    async fn evaluate_global(&mut self, expression: &str) -> Result<Value> {
+        self.evaluate(0, expression).await
+    }
+
+    /// Evaluates an expression into a variable the client can show and expand
+    ///
+    /// `frame_id` None evaluates against the globals. The default describes
+    /// the value `evaluate` or `evaluate_global` returns; runtimes that can
+    /// preview tables and keep them for drill-down override it. Numbers are
+    /// shown in `format`.
+    async fn evaluate_variable(&mut self, frame_id: Option<i64>, expression: &str, format: ValueFormat) -> Result<Variable> {
+        let value = match frame_id {
+            Some(frame_id) => self.evaluate(frame_id, expression).await?,
+            None => self.evaluate_global(expression).await?,
+        };
+        let (value, type_) = value.render_formatted(format);
+        Ok(Variable {
+            name: expression.trim().to_string(),
+            value,
+            type_,
+            variables_reference: None,
+            named_variables: None,
+            indexed_variables: None,
+            memory_reference: None,
+        })
+    }
+
+    /// Evaluates an expression into a Lua literal of its whole value, for the clipboard
+    ///
+    /// The default copies what `evaluate_variable` shows; runtimes that can
+    /// read whole tables override it.
+    async fn evaluate_literal(&mut self, frame_id: Option<i64>, expression: &str) -> Result<String> {
+        Ok(self.evaluate_variable(frame_id, expression, ValueFormat::default()).await?.value)
+    }
+
     async fn run_to_location(&mut self, source: &str, line: u32) -> Result<()>;
 
     async fn source(&mut self, source_reference: i64) -> Result<String>;
 
+    /// The lines of a source file a breakpoint can stop on, in order
+    async fn code_lines(&mut self, source: &str) -> Result<Vec<u32>> {
+        let _ = source;
+        Err(RuntimeError::NotImplemented("Breakpoint locations not supported".to_string()))
+    }
+
+    /// The lines after the current one of the function the program is stopped in, if it is in `source`, in order
+    ///
+    /// These are the lines `goto` can target, since it runs the program on
+    /// to them; none when the stopped function is in another file or is not
+    /// a Lua function.
+    async fn goto_lines(&mut self, source: &str) -> Result<Vec<u32>> {
+        let _ = source;
+        Err(RuntimeError::NotImplemented("Goto targets not supported".to_string()))
+    }
+
+    /// Reads `count` bytes at `offset` into the string or userdata behind a variable's `memory_reference`
+    async fn read_memory(
+        &mut self,
+        memory_reference: &str,
+        offset: i64,
+        count: usize,
+    ) -> Result<crate::debug::memory_references::MemoryRead> {
+        let _ = (memory_reference, offset, count);
+        Err(RuntimeError::NotImplemented("Reading memory not supported".to_string()))
+    }
+
+    /// Writes `data` at `offset` into the userdata behind a `memory_reference`, returning the bytes written
+    ///
+    /// With `allow_partial`, a write running past the end of the userdata
+    /// writes what fits instead of failing.
+    async fn write_memory(&mut self, memory_reference: &str, offset: i64, data: Vec<u8>, allow_partial: bool) -> Result<usize> {
+        let _ = (memory_reference, offset, data, allow_partial);
+        Err(RuntimeError::NotImplemented("Writing memory not supported".to_string()))
+    }
+
     /// Check if any data breakpoints (watchpoints) have been triggered
     async fn check_data_breakpoints(&mut self, frame_id: i64) -> Result<bool>;
 
+    /// Replaces the data breakpoints the program stops at
+    ///
+    /// Returns them with `verified` and `message` set for each.
+    async fn set_data_breakpoints(
+        &mut self,
+        breakpoints: Vec<crate::debug::watchpoints::DataBreakpoint>,
+    ) -> Result<Vec<crate::debug::watchpoints::DataBreakpoint>> {
+        let _ = breakpoints;
+        Err(RuntimeError::NotImplemented("Data breakpoints not supported".to_string()))
+    }
+
     /// Gets detailed information about the current exception
     async fn get_exception_info(&mut self, thread_id: u64) -> Result<ExceptionInfo>;
 
+    /// Returns snapshots of the most recent stops and uncaught errors, oldest first
+    async fn flight_records(&self) -> Result<Vec<crate::debug::flight_recorder::FlightRecord>> {
+        Err(RuntimeError::NotImplemented("Flight recorder not supported".to_string()))
+    }
+
+    /// Returns the lines the program executed last, oldest first
+    async fn execution_trace(&self) -> Result<Vec<crate::debug::execution_trace::TraceEntry>> {
+        Err(RuntimeError::NotImplemented("Execution trace not supported".to_string()))
+    }
+
+    /// Lists the modules in `package.loaded`, in no particular order
+    async fn modules(&mut self) -> Result<Vec<crate::debug::modules::Module>> {
+        Err(RuntimeError::NotImplemented("Modules not supported".to_string()))
+    }
+
+    /// Walks the tables reachable from the globals and returns the values a predicate accepts
+    ///
+    /// The predicate is a Lua expression over `k` and `v`, the key and value of
+    /// each table entry. The walk stops after `max_tables` tables or
+    /// `max_results` matches.
+    async fn search_heap(
+        &mut self,
+        predicate: &str,
+        max_tables: usize,
+        max_results: usize,
+    ) -> Result<crate::memory::HeapSearchResult> {
+        let _ = (predicate, max_tables, max_results);
+        Err(RuntimeError::NotImplemented("Heap search not supported".to_string()))
+    }
+
     /// Perform a hot reload of a module
     ///
     /// This method compiles and executes new module source code,
@@ -214,6 +749,45 @@ pub trait DebugRuntime: Send + Sync {
         Err(RuntimeError::NotImplemented("Memory statistics not supported".to_string()))
     }
 
+    /// Performs an incremental collection step of about `kb` kilobytes
+    ///
+    /// Returns whether the step finished a collection cycle.
+    async fn gc_step(&mut self, kb: u32) -> Result<bool> {
+        let _ = kb;
+        Err(RuntimeError::NotImplemented("GC step not supported".to_string()))
+    }
+
+    /// Sets the collector's pause and step multiplier, leaving those that are `None`
+    ///
+    /// Returns the statistics with the settings as the collector stored them,
+    /// which Lua 5.4 rounds to its own steps.
+    async fn tune_gc(&mut self, pause: Option<i32>, step_mul: Option<i32>) -> Result<crate::memory::MemoryStatistics> {
+        let _ = (pause, step_mul);
+        Err(RuntimeError::NotImplemented("GC tuning not supported".to_string()))
+    }
+
+    /// Starts or stops charging the state's allocations to the running source line
+    async fn set_allocation_tracking(&mut self, enabled: bool) -> Result<()> {
+        let _ = enabled;
+        Err(RuntimeError::NotImplemented("Allocation tracking not supported".to_string()))
+    }
+
+    /// The `limit` source lines holding the most memory since tracking started
+    ///
+    /// With `reset`, what was recorded so far is forgotten once it is returned.
+    async fn allocation_profile(&mut self, limit: usize, reset: bool) -> Result<crate::memory::AllocationProfile> {
+        let _ = (limit, reset);
+        Err(RuntimeError::NotImplemented("Allocation tracking not supported".to_string()))
+    }
+
+    /// Walks the objects reachable from the globals and the registry
+    ///
+    /// Returns every table, function, userdata and thread found with an
+    /// estimate of its size, and the number of strings.
+    async fn take_heap_snapshot(&mut self) -> Result<crate::memory::HeapSnapshot> {
+        Err(RuntimeError::NotImplemented("Heap snapshots not supported".to_string()))
+    }
+
     /// Force garbage collection
     async fn force_gc(&mut self) -> Result<()> {
         Err(RuntimeError::NotImplemented("Force GC not supported".to_string()))
@@ -233,6 +807,77 @@ pub trait DebugRuntime: Send + Sync {
     async fn get_profile_snapshot(&self) -> Result<Option<crate::profiling::ProfileData>> {
         Ok(None)
     }
+
+    /// Loads the program a launch request names, ready for `start_program`
+    async fn launch_program(&mut self, _launch: &ProgramLaunch) -> Result<()> {
+        Err(RuntimeError::NotImplemented("This runtime cannot load programs".to_string()))
+    }
+
+    /// Whether a program is loaded and waiting for `start_program`
+    fn has_program(&self) -> bool {
+        false
+    }
+
+    /// The Lua this runtime runs on, when it runs one in this process
+    fn lua_backend(&self) -> Option<LuaBackend> {
+        None
+    }
+
+    /// Starts the loaded program, if the runtime has one
+    ///
+    /// Called once the client has finished configuration. When
+    /// `stop_on_entry` is set the program stops before its first line.
+    async fn start_program(&mut self, _stop_on_entry: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /// Stops the program and loads it again, ready for `start_program`
+    ///
+    /// The runtime is left as a fresh launch would leave it: the program's
+    /// state is discarded and no breakpoints are set.
+    async fn restart(&mut self) -> Result<()> {
+        Err(RuntimeError::NotImplemented("Restart not supported".to_string()))
+    }
+
+    /// Removes the debugger from the program and lets it run on
+    ///
+    /// Clears breakpoints and the hook and undoes every change the debugger
+    /// made to the program's state, so the program behaves as if it had never
+    /// been debugged.
+    async fn detach(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Registers the channel used to report state changes such as stops
+    ///
+    /// Runtimes that never change state on their own can ignore this.
+    fn set_event_sender(&mut self, _sender: crate::dap::EventSender) {}
+
+    /// Sets the encoding Lua strings are decoded with, `None` to auto-detect
+    fn set_string_encoding(&mut self, _encoding: Option<&'static encoding_rs::Encoding>) {}
+
+    /// Sets the memory, in kilobytes, past which the running program stops; `None` removes the limit
+    fn set_memory_limit(&mut self, _limit_kb: Option<u64>) {}
+
+    /// Sets whether a stopped coroutine's stack trace continues into the threads that resumed it
+    fn set_stitch_coroutine_stacks(&mut self, _enabled: bool) {}
+
+    /// Sets whether the program's run is recorded, so that `step_back` and `reverse_continue` can go back
+    fn set_record_replay(&mut self, _enabled: bool) {}
+
+    /// Sets how many executed lines the execution trace keeps; 0 turns it off
+    fn set_execution_trace_lines(&mut self, _lines: usize) {}
+
+    /// Sets how expressions are evaluated: the safety level, mutation and the limits they run within
+    fn set_evaluation_config(&mut self, _config: &crate::config::DebuggerConfig) {}
+
+    /// Sets how much of a value variables and evaluation results show
+    fn set_display_limits(&mut self, _limits: DisplayLimits) {}
+
+    /// Hold states of the locks guarding the Lua state, read without taking them
+    fn lock_states(&self) -> Vec<crate::internals::LockState> {
+        Vec::new()
+    }
 }
 
 /// Information about an exception
@@ -257,8 +902,19 @@ pub struct Scope {
     pub expensive: bool,
 }
 
+pub mod boxed;
+pub mod frame_env;
+pub mod hook_state;
+pub mod lua_detect;
+pub mod lua_paths;
 pub mod mock;
+pub mod patches;
 pub mod puc_lua;
+pub mod remote;
+pub mod sandbox;
+pub mod value_preview;
+pub mod variable_refs;
+pub mod windows_libraries;
 pub mod luanext;
 pub mod lua_ffi;
 pub mod lua_state;
@@ -0,0 +1,100 @@
+//! Record of the changes the debugger makes to the program's Lua state
+//!
+//! Everything the debugger replaces in the state, such as `print`, `load` or
+//! a metamethod wrapped by a breakpoint, is set through the patch table so
+//! that detaching puts every original value back. Patches are undone newest
+//! first, and a value the program has replaced since is left alone, so the
+//! program keeps its own changes.
+
+use super::lua_ffi::*;
+use super::lua_state::Lua;
+
+/// Registry key of the patch table
+pub const PATCH_REGISTRY_KEY: &str = "wayfinder.patches";
+
+/// Sets table fields while remembering what they held
+///
+/// `set` returns the patch, which `undo` takes to revert just that one.
+/// Fields are read and written raw so no metamethods run.
+const PATCH_API: &str = r#"
+local patches = {}
+local api = {}
+function api.set(t, k, v)
+    local patch = { t = t, k = k, original = rawget(t, k), value = v }
+    patches[#patches + 1] = patch
+    rawset(t, k, v)
+    return patch
+end
+function api.undo(patch)
+    for i = #patches, 1, -1 do
+        if patches[i] == patch then
+            table.remove(patches, i)
+            if rawget(patch.t, patch.k) == patch.value then
+                rawset(patch.t, patch.k, patch.original)
+            end
+            return
+        end
+    end
+end
+function api.restore()
+    local count = #patches
+    while #patches > 0 do
+        api.undo(patches[#patches])
+    end
+    return count
+end
+return api
+"#;
+
+/// Pushes the patch API, loading it on first use
+pub fn push_patch_api(lua: &mut Lua) -> Result<(), String> {
+    if lua.get_field(LUA_REGISTRYINDEX, PATCH_REGISTRY_KEY) == LUA_TTABLE {
+        return Ok(());
+    }
+    lua.lua_settop(-2);
+
+    lua.load_string(PATCH_API)?;
+    lua.pcall(0, 1)?;
+    lua.lua_pushvalue(-1);
+    lua.set_field(LUA_REGISTRYINDEX, PATCH_REGISTRY_KEY);
+    Ok(())
+}
+
+/// Replaces the global `name` with the value on top of the stack, popping the value
+pub fn patch_global(lua: &mut Lua, name: &str) -> Result<(), String> {
+    let value = lua.get_top();
+    let result = (|| {
+        push_patch_api(lua)?;
+        lua.get_field(-1, "set");
+        lua.lua_pushglobaltable();
+        lua.push_string(name);
+        lua.lua_pushvalue(value);
+        lua.pcall(3, 0)?;
+        Ok(())
+    })();
+    lua.set_top(value - 1);
+    result
+}
+
+/// Undoes every patch, newest first, and forgets the patch table
+///
+/// Returns how many patches were undone.
+pub fn restore_all(lua: &mut Lua) -> Result<usize, String> {
+    let top = lua.get_top();
+    if lua.get_field(LUA_REGISTRYINDEX, PATCH_REGISTRY_KEY) != LUA_TTABLE {
+        lua.set_top(top);
+        return Ok(0);
+    }
+
+    let result = (|| {
+        lua.get_field(-1, "restore");
+        lua.pcall(0, 1)?;
+        Ok(lua.pop_integer() as usize)
+    })();
+    lua.set_top(top);
+    if result.is_ok() {
+        lua.push_nil();
+        lua.set_field(LUA_REGISTRYINDEX, PATCH_REGISTRY_KEY);
+    }
+    result
+}
@@ -1,29 +1,29 @@
-use super::{super::*, BreakpointType, DebugRuntime, ExceptionInfo, LuaVersion, RuntimeError, RuntimeType, Scope, StepMode, Value};
+use super::{super::*, BreakpointType, DebugRuntime, DisplayLimits, EvalErrorKind, ExceptionInfo, FrameStepTarget, LuaBackend, ProgramLaunch, RuntimeError, RuntimeType, Scope, StepMode, Value, ValueFormat, VariablesFilter, VariablesPage};
 use super::super::config::DebuggerConfig;
 use super::super::debug::breakpoints::LineBreakpoint;
-use super::super::debug::watchpoints::{DataBreakpoint, WatchpointManager, DataType};
+use super::super::debug::watchpoints::{line_accesses, AccessType, DataBreakpoint, DataBreakpointHit, DataType, WatchpointManager};
 use super::lua_state::Lua;
-use std::sync::RwLock;
-
-/// Check if any watchpoints have been triggered
-#[allow(dead_code)]
-unsafe fn check_watchpoints(_L: LuaState, _ar: *mut lua_Debug) -> bool {
-    // In a complete implementation, this would:
-    // 1. Access the watchpoint manager (probably through a static or passed parameter)
-    // 2. Iterate through all active data breakpoints
-    // 3. For each watchpoint:
-    //    - Determine the variable type (local, global, upvalue, table field)
-    //    - Get the current value using appropriate Lua debug API functions
-    //    - Compare with the previous value
-    //    - If changed and access type matches, trigger the watchpoint
-    // 4. Return true if any watchpoint was triggered
-    
-    // For now, we'll return false as this is a complex feature that requires
-    // significant implementation work
-    false
-}
 use crate::runtime::lua_state::DebugInfo;
 use crate::runtime::lua_ffi::*;
+use crate::debug::chunk_lines;
+use crate::debug::chunks::{ChunkRegistry, SourceReferences, Verification};
+use crate::internals::{self, LockState, TaskKind, TaskRole, TrackedMutex};
+use crate::memory::allocations::AllocationTracker;
+use crate::debug::execution_trace::{self, ExecutionTrace, TraceEntry};
+use crate::debug::flight_recorder::{FlightRecord, FlightRecorder, RecordKind};
+#[cfg(feature = "static-lua")]
+use crate::debug::flight_recorder::{FrameSummary, LocalSnapshot};
+use crate::debug::memory_references::{self, MemoryRead};
+use crate::debug::modules::{Module, ModuleRegistry};
+use crate::debug::time_travel::{Progress, Recording};
+#[cfg(feature = "static-lua")]
+use crate::debug::time_travel::InputValue;
+use crate::runtime::frame_env;
+use crate::runtime::hook_state::{source_matches, HookRegistry, HookState};
+use crate::runtime::patches;
+use crate::runtime::sandbox::{self, EvaluationError, EvaluationLimits};
+use crate::runtime::value_preview;
+use crate::runtime::variable_refs::{VariableReference, VariableRefs};
 
 // In dynamic mode, FFI functions don't exist so we need to use wrapper methods
 // Define module-level helpers that dispatch through the Lua wrapper
@@ -35,1828 +35,5885 @@ mod ffi_compat {
 }
 use async_trait::async_trait;
 use libc::c_int;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CStr;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::Duration;
 use once_cell::sync::Lazy;
 
-static mut PAUSED: AtomicBool = AtomicBool::new(false);
-static mut SHOULD_STEP: AtomicBool = AtomicBool::new(false);
-static mut CURRENT_LINE: AtomicUsize = AtomicUsize::new(1);
-static mut CURRENT_SOURCE: Option<String> = None;
-static mut STEP_MODE: AtomicUsize = AtomicUsize::new(0);
-static mut STEP_DEPTH: AtomicUsize = AtomicUsize::new(0);
-static mut STEP_TRIGGERED: AtomicBool = AtomicBool::new(false);
-// Note: Storing runtime references in static variables is not thread-safe
-// This is a simplification for the prototype
-
-// Profiler registry: maps runtime ID to active profiler
-static PROFILER_REGISTRY: Lazy<Mutex<HashMap<usize, Arc<Mutex<crate::profiling::Profiler>>>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
-
-// Thread-local to track current runtime ID (used in hook callback)
-thread_local! {
-    static CURRENT_RUNTIME_ID: std::cell::Cell<usize> = std::cell::Cell::new(0);
+/// Thread id reported for the main Lua state
+const MAIN_THREAD_ID: u64 = 1;
+
+/// Work that must touch the Lua state from the program thread
+type SafePointAction = Box<dyn FnOnce() + Send>;
+
+/// Everything the hook of one PUC Lua state works with
+struct PucHookState {
+    hook: HookState,
+    /// Address of the main thread, reported as `MAIN_THREAD_ID`
+    main_state: usize,
+    /// Set while a launched program is executing on its own thread
+    program_running: AtomicBool,
+    /// Set to stop on the first line the launched program executes
+    stop_on_entry: AtomicBool,
+    /// Channel for reporting state changes from the hook to the DAP server
+    events: Mutex<Option<crate::dap::EventSender>>,
+    /// Encoding Lua strings are decoded with when shown to the client; None auto-detects
+    string_encoding: Mutex<Option<&'static encoding_rs::Encoding>>,
+    /// Actions queued while a launched program is running. Touching the state
+    /// from another thread while the interpreter executes is undefined
+    /// behavior, so the hook applies these between lines or while stopped.
+    safe_point_queue: Mutex<Vec<SafePointAction>>,
+    /// Parks the program thread while it is stopped, see `wait_while_paused`
+    wakeup_lock: Mutex<()>,
+    wakeup: Condvar,
+    /// Line breakpoints of the runtime, checked by the hook
+    breakpoints: Arc<Mutex<HashMap<String, Vec<u32>>>>,
+    /// File chunks loaded so far, for verifying line breakpoints
+    chunks: Mutex<ChunkRegistry>,
+    /// Line breakpoints waiting for their file to load, by id
+    pending_breakpoints: Mutex<HashMap<i64, (String, u32)>>,
+    /// Code of chunks loaded from strings, shown through `source` requests
+    source_references: Mutex<SourceReferences>,
+    /// Whether Lua 5.4 warnings are reported; the program toggles it with `warn("@on")` and `warn("@off")`
+    warnings_enabled: AtomicBool,
+    /// Pieces of a warning the runtime is still emitting
+    pending_warning: Mutex<String>,
+    /// Set once the debugger detached; the warning function then writes to stderr like Lua's own
+    detached: AtomicBool,
+    /// Set while a hosted program has no debugger configured, see `HostedProgram::wait_for_start`
+    awaiting_start: AtomicBool,
+    /// Set to unwind the running program for a restart; the hook raises an error at every event
+    aborting: AtomicBool,
+    /// Memory the program may use, in kilobytes, before it stops; 0 for no limit
+    memory_limit_kb: AtomicU64,
+    /// Set once the program stopped for going over the memory limit, until usage drops back under it
+    over_memory_limit: AtomicBool,
+    /// Data breakpoints, with the value each saw at the previous line
+    data_breakpoints: Mutex<WatchpointManager>,
+    /// Lines of the files scanned for reads of watched variables, by path
+    source_lines: Mutex<HashMap<String, Arc<Vec<String>>>>,
+    /// Whether the program is stopped at a data breakpoint
+    stopped_at_data_breakpoint: AtomicBool,
+    /// Function breakpoints, by id; call events are only hooked while there are any
+    function_breakpoints: Mutex<HashMap<i64, String>>,
+    /// Set when a function breakpoint matched a call, to stop on the function's first line
+    function_entry: AtomicBool,
+    /// Debugger ids of coroutines seen so far, keyed by lua_State address
+    thread_ids: Mutex<HashMap<usize, u64>>,
+    /// Thread the program last stopped on, as (thread id, lua_State address)
+    stopped_thread: Mutex<(u64, usize)>,
+    /// Threads that stop the program as soon as they run, set by thread-targeted
+    /// pause and continue requests and cleared at every stop
+    held_threads: Mutex<HashSet<u64>>,
+    /// Function a frame step waits for, by `lua_topointer` address, and where it stops
+    frame_step: Mutex<Option<(usize, FrameStepTarget)>>,
+    /// Snapshots of recent stops and uncaught errors
+    flight_recorder: Mutex<FlightRecorder>,
+    /// Most recently executed lines, printed when the program fails
+    execution_trace: Mutex<ExecutionTrace>,
+    /// Modules `require` loaded, with when and from where
+    modules: Mutex<ModuleRegistry>,
+    /// Line history and input log of the run, kept to step backwards; None unless asked for
+    recording: Mutex<Option<Recording>>,
+    profiler: Mutex<Option<Arc<Mutex<crate::profiling::Profiler>>>>,
+    /// Wall-clock sampler timer ticks since the hook last took a sample
+    wall_clock_ticks: AtomicU64,
+    /// Profile as of the previous stop, to report what ran in between
+    profile_at_last_stop: Mutex<Option<crate::profiling::ProfileData>>,
+    /// Tracker of the state's allocator, told the running line by the hook
+    allocations: Option<Arc<AllocationTracker>>,
 }
 
-extern "C" fn lua_hook_callback(_L: LuaState, ar: *mut lua_Debug) {
-    unsafe {
-        if lua_getinfo(_L, b"lS\0".as_ptr() as *const i8, ar) == 0 {
-            return;
+impl PucHookState {
+    fn new(
+        main_state: LuaState,
+        breakpoints: Arc<Mutex<HashMap<String, Vec<u32>>>>,
+        allocations: Option<Arc<AllocationTracker>>,
+    ) -> Self {
+        Self {
+            hook: HookState::new(),
+            main_state: main_state as usize,
+            program_running: AtomicBool::new(false),
+            stop_on_entry: AtomicBool::new(false),
+            events: Mutex::new(None),
+            string_encoding: Mutex::new(None),
+            safe_point_queue: Mutex::new(Vec::new()),
+            wakeup_lock: Mutex::new(()),
+            wakeup: Condvar::new(),
+            breakpoints,
+            chunks: Mutex::new(ChunkRegistry::new()),
+            pending_breakpoints: Mutex::new(HashMap::new()),
+            source_references: Mutex::new(SourceReferences::new()),
+            warnings_enabled: AtomicBool::new(true),
+            pending_warning: Mutex::new(String::new()),
+            detached: AtomicBool::new(false),
+            awaiting_start: AtomicBool::new(false),
+            aborting: AtomicBool::new(false),
+            memory_limit_kb: AtomicU64::new(0),
+            over_memory_limit: AtomicBool::new(false),
+            data_breakpoints: Mutex::new(WatchpointManager::new()),
+            source_lines: Mutex::new(HashMap::new()),
+            stopped_at_data_breakpoint: AtomicBool::new(false),
+            function_breakpoints: Mutex::new(HashMap::new()),
+            function_entry: AtomicBool::new(false),
+            thread_ids: Mutex::new(HashMap::new()),
+            stopped_thread: Mutex::new((MAIN_THREAD_ID, 0)),
+            held_threads: Mutex::new(HashSet::new()),
+            frame_step: Mutex::new(None),
+            flight_recorder: Mutex::new(FlightRecorder::default()),
+            execution_trace: Mutex::new(ExecutionTrace::default()),
+            modules: Mutex::new(ModuleRegistry::new()),
+            recording: Mutex::new(None),
+            profiler: Mutex::new(None),
+            wall_clock_ticks: AtomicU64::new(0),
+            profile_at_last_stop: Mutex::new(None),
+            allocations,
         }
+    }
 
-        let line = (*ar).currentline as u32;
-        CURRENT_LINE.store(line as usize, Ordering::SeqCst);
-
-        let source = {
-            let source_ptr = (*ar).source;
-            if !source_ptr.is_null() {
-                let c_str = CStr::from_ptr(source_ptr);
-                Some(c_str.to_string_lossy().to_string())
-            } else {
-                None
+    /// Sends an event to the DAP server if one is listening
+    fn emit(&self, mut event: crate::dap::Event) {
+        if event.event == "stopped" {
+            self.annotate_with_profile(&mut event);
+        }
+        if let Ok(sender) = self.events.lock() {
+            if let Some(sender) = sender.as_ref() {
+                // The server may already be gone during shutdown
+                let _ = sender.send(event);
             }
-        };
-        CURRENT_SOURCE = source;
+        }
+    }
 
-        let step_mode = StepMode::from_u32(STEP_MODE.load(Ordering::SeqCst) as u32);
-        let should_step = SHOULD_STEP.load(Ordering::SeqCst);
+    /// Adds the functions that took the most time since the previous stop to a stopped event
+    ///
+    /// Only while profiling; the event gets a `profile` field with the time
+    /// profiled since the previous stop and the hottest functions in it.
+    fn annotate_with_profile(&self, event: &mut crate::dap::Event) {
+        let Some(profiler) = self.profiler.lock().unwrap().clone() else { return };
+        let current = profiler.lock().unwrap().to_profile_data();
+        let mut previous = self.profile_at_last_stop.lock().unwrap();
+
+        let hottest = current.hottest_since(previous.as_ref(), STOP_HOTTEST_FUNCTIONS);
+        let elapsed_ms = current.duration_ms - previous.as_ref().map_or(0.0, |p| p.duration_ms);
+        if let Some(body) = event.body.as_mut() {
+            body["profile"] = serde_json::json!({
+                "sinceLastStopMs": elapsed_ms,
+                "hottestFunctions": hottest,
+            });
+        }
+        *previous = Some(current);
+    }
 
-        let triggered_for_step = if should_step {
-            match step_mode {
-                StepMode::In => true,
-                StepMode::Over => {
-                    let depth = (*ar).linedefined as usize;
-                    if depth <= STEP_DEPTH.load(Ordering::SeqCst) {
-                        true
-                    } else {
-                        false
-                    }
-                },
-                StepMode::Out => {
-                    let depth = (*ar).linedefined as usize;
-                    depth < STEP_DEPTH.load(Ordering::SeqCst)
-                }
-            }
-        } else {
-            false
+    /// Applies all queued actions
+    ///
+    /// Called from the hook, where the interpreter is between instructions and
+    /// hooks are disabled, so actions running Lua code cannot re-enter the hook.
+    /// Also called once the program finishes, for actions queued at the very end.
+    fn drain_safe_point_actions(&self) {
+        let pending = match self.safe_point_queue.lock() {
+            Ok(mut queue) if !queue.is_empty() => std::mem::take(&mut *queue),
+            _ => return,
         };
 
-        // Check for watchpoint triggers
-        // Note: This is a simplified approach as we can't easily pass the runtime instance
-        // to the static hook callback. In a full implementation, we would need a more
-        // sophisticated approach, possibly using thread-local storage or a global registry.
-        let watchpoint_triggered = false; // Placeholder - would need access to runtime instance
-
-        if triggered_for_step || watchpoint_triggered {
-            STEP_TRIGGERED.store(true, Ordering::SeqCst);
-            PAUSED.store(true, Ordering::SeqCst);
+        for action in pending {
+            action();
         }
+    }
 
-        // Handle profiling events
-        let event = (*ar).event;
-        if event == LUA_HOOKCALL || event == LUA_HOOKRET || event == LUA_HOOKCOUNT {
-            let runtime_id = CURRENT_RUNTIME_ID.with(|id| id.get());
+    /// Blocks the program thread until the client resumes it
+    ///
+    /// Queued actions are applied while waiting, so requests keep working
+    /// against the stopped program.
+    fn wait_while_paused(&self) {
+        loop {
+            self.drain_safe_point_actions();
 
-            if let Ok(registry) = PROFILER_REGISTRY.lock() {
-                if let Some(profiler_arc) = registry.get(&runtime_id) {
-                    if let Ok(mut profiler) = profiler_arc.lock() {
-                        match event {
-                            LUA_HOOKCALL => {
-                                // Get function information for the call event
-                                let _ = lua_getinfo(_L, b"nS\0".as_ptr() as *const i8, ar);
-                                let name = get_hook_function_name(ar);
-                                let source = get_hook_source(ar);
-                                let line = (*ar).linedefined as u32;
-                                profiler.on_call(name, source, line);
-                            }
-                            LUA_HOOKRET => {
-                                profiler.on_return();
-                            }
-                            LUA_HOOKCOUNT => {
-                                profiler.on_sample();
-                            }
-                            _ => {}
-                        }
-                    }
-                }
+            let guard = self.wakeup_lock.lock().unwrap();
+            if !self.hook.is_paused() {
+                return;
+            }
+            if !self.safe_point_queue.lock().unwrap().is_empty() {
+                continue;
             }
+            // Wakers take the lock before notifying, so nothing is lost
+            // between the checks above and the wait
+            drop(self.wakeup.wait(guard));
         }
     }
-}
 
-// Helper functions for profiling hook
-unsafe fn get_hook_function_name(ar: *mut lua_Debug) -> String {
-    if !(*ar).name.is_null() {
-        if let Ok(c_str) = CStr::from_ptr((*ar).name).to_str() {
-            return c_str.to_string();
-        }
-    }
-    format!("<?:{}?>", (*ar).linedefined)
-}
+    /// Blocks a hosted program's thread until a debugger has started it
+    ///
+    /// Like `wait_while_paused`, queued actions are applied while waiting, so
+    /// the debugger can set breakpoints before the host runs any Lua.
+    fn wait_for_start(&self) {
+        loop {
+            self.drain_safe_point_actions();
 
-unsafe fn get_hook_source(ar: *mut lua_Debug) -> Option<String> {
-    if !(*ar).source.is_null() {
-        if let Ok(c_str) = CStr::from_ptr((*ar).source).to_str() {
-            return Some(c_str.to_string());
+            let guard = self.wakeup_lock.lock().unwrap();
+            if !self.awaiting_start.load(Ordering::SeqCst) {
+                return;
+            }
+            if !self.safe_point_queue.lock().unwrap().is_empty() {
+                continue;
+            }
+            drop(self.wakeup.wait(guard));
         }
     }
-    None
-}
 
-pub struct PUCLuaRuntime {
-    lua: Arc<Mutex<Lua>>,
-    breakpoints: Arc<Mutex<HashMap<String, Vec<u32>>>>,
-    detailed_breakpoints: Arc<Mutex<HashMap<String, Vec<LineBreakpoint>>>>,
-    watchpoint_manager: Arc<RwLock<WatchpointManager>>,
-    watched_variable_values: Arc<Mutex<HashMap<String, String>>>,
-    config: DebuggerConfig,
-    step_mode: Arc<Mutex<StepMode>>,
-}
+    /// Wakes a program thread parked in `wait_while_paused` or `wait_for_start`
+    ///
+    /// Call after resuming or queueing an action.
+    fn wake(&self) {
+        let _guard = self.wakeup_lock.lock().unwrap();
+        self.wakeup.notify_all();
+    }
 
-impl PUCLuaRuntime {
-    #[cfg(feature = "static-lua")]
-    pub fn new() -> Self {
-        unsafe {
-            PAUSED.store(false, Ordering::SeqCst);
-            SHOULD_STEP.store(false, Ordering::SeqCst);
-            CURRENT_LINE.store(1, Ordering::SeqCst);
+    /// Records a file chunk the first time the hook runs in it
+    ///
+    /// Breakpoints that were waiting for the file are verified, or told why
+    /// they cannot be, with `breakpoint` events.
+    fn note_chunk(&self, source: &str) {
+        if !source.starts_with('@') {
+            return;
+        }
+        let mut chunks = self.chunks.lock().unwrap();
+        if !chunks.register_file(source) {
+            return;
         }
 
-        let lua = Arc::new(Mutex::new(Lua::new()));
-
-        Self {
-            lua,
-            breakpoints: Arc::new(Mutex::new(HashMap::new())),
-            detailed_breakpoints: Arc::new(Mutex::new(HashMap::new())),
-            watchpoint_manager: Arc::new(RwLock::new(WatchpointManager::new())),
-            watched_variable_values: Arc::new(Mutex::new(HashMap::new())),
-            config: DebuggerConfig::default(),
-            step_mode: Arc::new(Mutex::new(StepMode::Over)),
+        let mut pending = self.pending_breakpoints.lock().unwrap();
+        let mut resolved = Vec::new();
+        pending.retain(|id, (path, line)| {
+            let verification = chunks.verify(path, *line);
+            if verification == Verification::NotLoaded {
+                return true;
+            }
+            resolved.push((*id, *line, verification));
+            false
+        });
+        drop(pending);
+        drop(chunks);
+
+        for (id, line, verification) in resolved {
+            self.emit(crate::dap::Event::breakpoint(
+                "changed",
+                Breakpoint {
+                    id,
+                    verified: verification.is_verified(),
+                    line,
+                    message: verification.message(line),
+                },
+            ));
         }
     }
 
-    #[cfg(feature = "dynamic-lua")]
-    pub fn new_with_library(lib: crate::runtime::lua_loader::LuaLibrary) -> Self {
-        unsafe {
-            PAUSED.store(false, Ordering::SeqCst);
-            SHOULD_STEP.store(false, Ordering::SeqCst);
-            CURRENT_LINE.store(1, Ordering::SeqCst);
-        }
+    /// Returns true if a breakpoint is set at the given chunk source and line
+    fn is_active_breakpoint(&self, source: &str, line: u32) -> bool {
+        let breakpoints = match self.breakpoints.lock() {
+            Ok(breakpoints) => breakpoints,
+            Err(_) => return false,
+        };
 
-        let lua = Arc::new(Mutex::new(Lua::new_with_library(lib)));
+        breakpoints
+            .iter()
+            .any(|(file, lines)| source_matches(source, file) && lines.contains(&line))
+    }
 
-        Self {
-            lua,
-            breakpoints: Arc::new(Mutex::new(HashMap::new())),
-            detailed_breakpoints: Arc::new(Mutex::new(HashMap::new())),
-            watchpoint_manager: Arc::new(RwLock::new(WatchpointManager::new())),
-            watched_variable_values: Arc::new(Mutex::new(HashMap::new())),
-            config: DebuggerConfig::default(),
-            step_mode: Arc::new(Mutex::new(StepMode::Over)),
+    /// Returns the stable debugger id of a Lua thread
+    fn thread_id_for(&self, thread: LuaState) -> u64 {
+        if thread as usize == self.main_state {
+            return MAIN_THREAD_ID;
         }
+
+        let mut ids = self.thread_ids.lock().unwrap();
+        let next = MAIN_THREAD_ID + 1 + ids.len() as u64;
+        *ids.entry(thread as usize).or_insert(next)
     }
 
-    fn lua_to_value(lua: &mut Lua, index: c_int) -> Value {
-        let lua_type = lua.type_of(index);
+    /// Turns a pending frame step into a step in once its function is called or returns
+    ///
+    /// Call and return events carry no line, so the stop itself happens on the
+    /// next line event.
+    #[cfg(feature = "static-lua")]
+    unsafe fn check_frame_step(&self, L: LuaState, ar: *mut lua_Debug) {
+        let mut frame_step = self.frame_step.lock().unwrap();
+        let Some((function, until)) = *frame_step else { return };
 
-        match lua_type {
-            0 => Value::Nil,
-            1 => Value::Boolean(lua.pop_boolean()),
-            2 => Value::UserData,
-            3 => Value::Number(lua.pop_number()),
-            4 => Value::String(lua.pop_string()),
-            5 => {
-                let len = lua.len(index);
-                Value::Table {
-                    reference: 0,
-                    length: len as u32,
-                }
-            }
-            6 => Value::Function {
-                reference: 0,
-                name: None,
-            },
-            7 => Value::UserData,
-            8 => Value::Thread,
-            _ => Value::Nil,
+        let event = (*ar).event;
+        let wanted = match until {
+            FrameStepTarget::NextCall => event == LUA_HOOKCALL || event == LUA_HOOKTAILCALL,
+            FrameStepTarget::Return => event == LUA_HOOKRET,
+        };
+        if !wanted || lua_getinfo(L, c"f".as_ptr(), ar) == 0 {
+            return;
+        }
+        let current = lua_topointer(L, -1) as usize;
+        lua_settop(L, -2);
+        if current != function {
+            return;
         }
-    }
 
-    pub fn execute_code(&self, code: &str) -> Result<Value, String> {
-        let mut lua = self.lua.lock().unwrap();
-        lua.execute(code)?;
-        Ok(Self::lua_to_value(&mut lua, -1))
+        *frame_step = None;
+        self.hook.step_mode.store(StepMode::In.to_u32() as usize, Ordering::SeqCst);
+        self.hook.should_step.store(true, Ordering::SeqCst);
+        if self.profiler.lock().map_or(true, |p| p.is_none()) {
+            lua_sethook(L, lua_hook_callback, self.base_hook_mask(), 0);
+        }
     }
 
-    pub fn load_file(&self, filename: &str) -> Result<c_int, String> {
-        let mut lua = self.lua.lock().unwrap();
-        lua.load_file(filename)
+    #[cfg(feature = "dynamic-lua")]
+    unsafe fn check_frame_step(&self, _L: LuaState, _ar: *mut lua_Debug) {}
+
+    /// Records the current stack with the wall-clock profiler, weighted by the timer ticks it covers
+    unsafe fn take_wall_clock_sample(&self, L: LuaState, ticks: u64) {
+        let Some(profiler) = self.profiler.lock().unwrap().clone() else { return };
+        let mut profiler = profiler.lock().unwrap();
+        let crate::profiling::ProfilingMode::WallClock { interval_ms } = profiler.mode() else { return };
+        let frames = sample_stack(L);
+        profiler.on_stack_sample(&frames, (ticks * interval_ms as u64) as f64);
     }
 
-    pub fn load_string(&self, code: &str) -> Result<c_int, String> {
-        let mut lua = self.lua.lock().unwrap();
-        lua.load_string(code)
-    }
+    /// Checks the state's memory usage against the limit
+    ///
+    /// Returns a description of the usage when it has just gone over the
+    /// limit. The program stops once per excursion: usage has to fall back
+    /// under the limit, such as after a collection, before it stops again.
+    #[cfg(feature = "static-lua")]
+    unsafe fn check_memory_limit(&self, L: LuaState) -> Option<String> {
+        let limit_kb = self.memory_limit_kb.load(Ordering::SeqCst);
+        if limit_kb == 0 {
+            return None;
+        }
 
-    pub fn pcall(&self, nargs: c_int, nresults: c_int) -> Result<c_int, String> {
-        let mut lua = self.lua.lock().unwrap();
-        lua.pcall(nargs, nresults)
+        let used_kb = lua_gc(L, LUA_GCCOUNT, 0, 0).max(0) as u64;
+        if used_kb < limit_kb {
+            self.over_memory_limit.store(false, Ordering::SeqCst);
+            return None;
+        }
+        if self.over_memory_limit.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        Some(format!("Memory usage of {} KB exceeds the limit of {} KB", used_kb, limit_kb))
     }
 
-    pub fn get_global(&mut self, name: &str) -> c_int {
-        let mut lua = self.lua.lock().unwrap();
-        lua.get_global(name)
+    #[cfg(feature = "dynamic-lua")]
+    unsafe fn check_memory_limit(&self, _L: LuaState) -> Option<String> {
+        None
     }
 
-    pub fn set_top(&mut self, idx: c_int) {
-        let mut lua = self.lua.lock().unwrap();
-        lua.set_top(idx);
+    /// Applies a warning control message such as `@on`, returning false for an ordinary warning
+    fn warning_control(&self, message: &str) -> bool {
+        match message {
+            "@on" => self.warnings_enabled.store(true, Ordering::SeqCst),
+            "@off" => self.warnings_enabled.store(false, Ordering::SeqCst),
+            // Unknown control messages are ignored, as by the standard warning function
+            _ => return message.starts_with('@'),
+        }
+        true
     }
 
-    pub fn get_top(&self) -> c_int {
-        let lua = self.lua.lock().unwrap();
-        lua.get_top()
+    /// Events the hook needs when neither profiling nor a frame step asks for more
+    fn base_hook_mask(&self) -> c_int {
+        if self.function_breakpoints.lock().unwrap().is_empty() {
+            LUA_MASKLINE
+        } else {
+            LUA_MASKLINE | LUA_MASKCALL
+        }
     }
 
-    pub fn type_of(&self, idx: c_int) -> c_int {
-        let lua = self.lua.lock().unwrap();
-        lua.type_of(idx)
-    }
+    /// Checks a call event against the function breakpoints
+    ///
+    /// A call into a Lua function stops on the function's first line so the
+    /// stop shows its source; returns true for a call into a C function,
+    /// which has no lines and stops at the call.
+    #[cfg(feature = "static-lua")]
+    unsafe fn check_function_breakpoint(&self, L: LuaState, ar: *mut lua_Debug) -> bool {
+        let names: Vec<String> = self.function_breakpoints.lock().unwrap().values().cloned().collect();
+        if names.is_empty() || lua_getinfo(L, c"nf".as_ptr(), ar) == 0 {
+            return false;
+        }
+        let called = lua_topointer(L, -1) as usize;
+        let called_name = (!(*ar).name.is_null()).then(|| CStr::from_ptr((*ar).name).to_string_lossy().into_owned());
+        let matched = names
+            .iter()
+            .any(|name| function_breakpoint_matches(L, name, called_name.as_deref(), called));
+        lua_settop(L, -2);
+        if !matched {
+            return false;
+        }
 
-    pub fn is_nil(&self, idx: c_int) -> bool {
-        let lua = self.lua.lock().unwrap();
-        lua.is_nil(idx)
+        let is_c_function = !(*ar).what.is_null() && CStr::from_ptr((*ar).what).to_bytes() == b"C";
+        if !is_c_function {
+            self.function_entry.store(true, Ordering::SeqCst);
+        }
+        is_c_function
     }
 
-    pub fn pop_string(&mut self) -> String {
-        let mut lua = self.lua.lock().unwrap();
-        lua.pop_string()
+    #[cfg(feature = "dynamic-lua")]
+    unsafe fn check_function_breakpoint(&self, _L: LuaState, _ar: *mut lua_Debug) -> bool {
+        false
     }
 
-    pub fn get_local_variable(&mut self, ar: &mut DebugInfo, n: c_int) -> Option<(String, Value)> {
-        let mut lua = self.lua.lock().unwrap();
+    /// Checks the data breakpoints at a line event, remembering the values they see
+    ///
+    /// A value that changed since the previous line is a write. Lua has no
+    /// hook for variable accesses, so a read is found by scanning the line
+    /// about to run for the variable, and stops before the read happens.
+    /// Variables are looked up in the running function; one not visible there
+    /// forgets its value, so that another function's variable of the same name
+    /// is not taken for a change.
+    unsafe fn check_data_breakpoints(&self, L: LuaState, ar: *mut lua_Debug, source: Option<&str>, line: u32) -> Option<DataBreakpointHit> {
+        let mut manager = self.data_breakpoints.lock().unwrap();
+        if manager.data_breakpoint_count() == 0 {
+            return None;
+        }
+        let mut watched: Vec<DataBreakpoint> =
+            manager.get_data_breakpoints().into_iter().filter(|bp| bp.verified).cloned().collect();
+        watched.sort_by_key(|bp| bp.id);
+
+        let mut code = None;
+        let mut hit = None;
+        for bp in watched {
+            let Some(value) = read_watched_value(L, ar, &bp.data_type, &bp.name) else {
+                manager.forget_data_breakpoint_previous_value(bp.id);
+                continue;
+            };
+            // Every value is remembered, even once a breakpoint triggered
+            manager.update_data_breakpoint_previous_value(bp.id, value.clone());
+            if hit.is_some() {
+                continue;
+            }
 
-        let name = unsafe {
-            let ptr = lua.lua_getlocal(ar.ptr(), n);
-            if ptr.is_null() {
+            let written = bp.previous_value.as_ref().is_some_and(|previous| *previous != value);
+            let access = if written && bp.access_type.includes(&AccessType::Write) {
+                Some(AccessType::Write)
+            } else if bp.access_type.includes(&AccessType::Read)
+                && code
+                    .get_or_insert_with(|| self.line_text(source, line))
+                    .as_deref()
+                    .is_some_and(|code| line_accesses(code, &bp.name).reads)
+            {
+                Some(AccessType::Read)
+            } else {
+                None
+            };
+            if let Some(access) = access {
+                hit = Some(DataBreakpointHit {
+                    id: bp.id,
+                    name: bp.name,
+                    access,
+                    old_value: bp.previous_value.unwrap_or_else(|| value.clone()),
+                    new_value: value,
+                });
+            }
+        }
+        hit
+    }
+
+    /// Text of a line of a chunk
+    ///
+    /// File chunks are read from disk once; chunks loaded from strings carry
+    /// their code as their source.
+    fn line_text(&self, source: Option<&str>, line: u32) -> Option<String> {
+        let source = source?;
+        let index = line.checked_sub(1)? as usize;
+        let Some(path) = source.strip_prefix('@') else {
+            if source.starts_with('=') {
                 return None;
             }
-            let name = CStr::from_ptr(ptr).to_string_lossy().to_string();
-            lua.set_top(-2);
-            name
+            return source.lines().nth(index).map(str::to_string);
         };
 
-        let value = Self::lua_to_value(&mut lua, -1);
-        Some((name, value))
+        let lines = self
+            .source_lines
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                let text = std::fs::read(path).map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+                Arc::new(text.unwrap_or_default().lines().map(str::to_string).collect())
+            })
+            .clone();
+        lines.get(index).cloned()
     }
 
-    pub fn get_upvalue(&mut self, func_index: c_int, n: c_int) -> Option<(String, Value)> {
-        let mut lua = self.lua.lock().unwrap();
+    /// Ids of the main thread and every coroutine seen so far
+    fn known_thread_ids(&self) -> HashSet<u64> {
+        let mut ids: HashSet<u64> = self.thread_ids.lock().unwrap().values().copied().collect();
+        ids.insert(MAIN_THREAD_ID);
+        ids
+    }
 
-        unsafe {
-            let ptr = lua.lua_getupvalue(func_index, n);
-            if ptr.is_null() {
-                return None;
-            }
-            let name = CStr::from_ptr(ptr).to_string_lossy().to_string();
-            let value = Self::lua_to_value(&mut lua, -1);
-            lua.set_top(-2);
-            Some((name, value))
-        }
+    /// Whether the hook is running in a thread held by a thread-targeted request
+    fn is_held_thread(&self, thread: LuaState) -> bool {
+        let held = self.held_threads.lock().unwrap();
+        !held.is_empty() && held.contains(&self.thread_id_for(thread))
     }
 
-    pub fn get_stack_info(&mut self, _level: i32) -> Option<DebugInfo<'static>> {
-        let lua = self.lua.lock().unwrap();
+    /// Records the thread the hook is stopping on and returns its id
+    fn stop_on_thread(&self, thread: LuaState) -> u64 {
+        let id = self.thread_id_for(thread);
+        *self.stopped_thread.lock().unwrap() = (id, thread as usize);
+        id
+    }
 
-        unsafe {
-            let mut ar = DebugInfo::new();
-            let result = lua.lua_getinfo(b"SnSluf\0".as_ptr() as *const i8, ar.ptr());
+    /// Whether a replay is running lines the program already ran; it prints nothing meanwhile
+    fn is_replaying(&self) -> bool {
+        self.recording.lock().unwrap().as_ref().is_some_and(Recording::is_replaying)
+    }
 
-            if result == 0 {
-                return None;
-            }
+    /// Counts a line event in the recording, when the run is recorded
+    ///
+    /// Other events only learn whether a replay is under way. At a checkpoint
+    /// the globals are kept, or compared with those of the earlier run, and a
+    /// difference is reported: the replay no longer shows what happened.
+    unsafe fn advance_recording(&self, L: LuaState, event: c_int, source: Option<&str>, line: u32) -> Progress {
+        let mut recording = self.recording.lock().unwrap();
+        let Some(recording) = recording.as_mut() else { return Progress::Live };
+        if event != LUA_HOOKLINE {
+            return if recording.is_replaying() { Progress::Replaying } else { Progress::Live };
+        }
 
-            Some(ar)
+        let progress = recording.advance(source.unwrap_or("?"), line);
+        if recording.checkpoint_due() {
+            let changed = recording.checkpoint(render_globals(L));
+            if !changed.is_empty() {
+                self.emit(crate::dap::Event::output(
+                    "important",
+                    &format!(
+                        "The replay differs from the recording after {} lines, in {}; the program may read input that is not logged\n",
+                        recording.position(),
+                        changed.join(", "),
+                    ),
+                ));
+            }
         }
+        progress
     }
 
-    fn is_breakpoint_hit(&self, source: &str, line: u32) -> bool {
-        let breakpoints = self.breakpoints.lock().unwrap();
-        if let Some(lines) = breakpoints.get(source) {
-            lines.contains(&line)
-        } else {
-            false
+    /// Adds a line event to the execution trace
+    ///
+    /// The function is only named when the line is in another function than
+    /// the one before, so most lines cost no more than the `lS` lookup the
+    /// hook already did.
+    unsafe fn trace_line(&self, L: LuaState, ar: *mut lua_Debug, source: Option<&str>, line: u32) {
+        let mut trace = self.execution_trace.lock().unwrap();
+        if trace.capacity() == 0 {
+            return;
         }
+        trace.record(source, line, (*ar).linedefined, || {
+            if lua_getinfo(L, c"n".as_ptr(), ar) != 0 && !(*ar).name.is_null() {
+                return CStr::from_ptr((*ar).name).to_string_lossy().into_owned();
+            }
+            if (*ar).linedefined == 0 {
+                "main chunk".to_string()
+            } else {
+                let short_src = CStr::from_ptr((*ar).short_src.as_ptr()).to_string_lossy();
+                format!("function <{}:{}>", short_src, (*ar).linedefined)
+            }
+        });
     }
+}
 
-    pub fn is_breakpoint_hit_at_current_location(&self) -> bool {
-        let source = unsafe { CURRENT_SOURCE.clone() };
-        let line = unsafe { CURRENT_LINE.load(Ordering::SeqCst) as u32 };
-
-        if let Some(ref s) = source {
-            self.is_breakpoint_hit(s, line)
-        } else {
-            false
+/// Renders the globals for a checkpoint of the recording
+///
+/// Only plain data is rendered by value; tables, functions and the like are
+/// rendered by type, as their addresses differ from run to run. Entries are
+/// read raw so no metamethods run.
+#[cfg(feature = "static-lua")]
+unsafe fn render_globals(L: LuaState) -> Vec<(String, String)> {
+    let top = lua_gettop(L);
+    let mut globals = Vec::new();
+    lua_rawgeti(L, LUA_REGISTRYINDEX, LUA_RIDX_GLOBALS);
+    lua_pushnil(L);
+    while lua_next(L, -2) != 0 {
+        if lua_type(L, -2) == LUA_TSTRING {
+            let mut len = 0;
+            let ptr = lua_tolstring(L, -2, &mut len);
+            let name = String::from_utf8_lossy(std::slice::from_raw_parts(ptr as *const u8, len)).into_owned();
+            let value = match lua_type(L, -1) {
+                LUA_TNIL | LUA_TBOOLEAN | LUA_TNUMBER | LUA_TSTRING => describe_watched_value(L, -1),
+                type_ => CStr::from_ptr(lua_typename(L, type_)).to_string_lossy().into_owned(),
+            };
+            globals.push((name, value));
         }
+        lua_settop(L, -2);
     }
+    lua_settop(L, top);
+    globals
+}
 
-    pub fn should_remain_paused(&self) -> bool {
-        if self.is_breakpoint_hit_at_current_location() {
-            return true;
+#[cfg(feature = "dynamic-lua")]
+unsafe fn render_globals(_L: LuaState) -> Vec<(String, String)> {
+    Vec::new()
+}
+
+/// Describes the current value of a watched variable of the running function
+///
+/// None when the variable is not visible there. The descriptions are what
+/// data breakpoints compare: scalars by value, everything else by identity.
+#[cfg(feature = "static-lua")]
+unsafe fn read_watched_value(L: LuaState, ar: *mut lua_Debug, data_type: &DataType, name: &str) -> Option<String> {
+    let top = lua_gettop(L);
+    let found = match data_type {
+        DataType::Local => {
+            // The last active local of that name shadows the others
+            let mut last = None;
+            let mut index = 1;
+            loop {
+                let local = lua_getlocal(L, ar, index);
+                if local.is_null() {
+                    break;
+                }
+                if CStr::from_ptr(local).to_bytes() == name.as_bytes() {
+                    last = Some(index);
+                }
+                lua_settop(L, -2);
+                index += 1;
+            }
+            last.is_some_and(|index| !lua_getlocal(L, ar, index).is_null())
         }
-        unsafe { STEP_TRIGGERED.load(Ordering::SeqCst) }
-    }
+        DataType::Global => {
+            lua_rawgeti(L, LUA_REGISTRYINDEX, LUA_RIDX_GLOBALS);
+            // An unset global is nil, which is still a value to watch
+            push_raw_field(L, name);
+            lua_gettop(L) > top + 1
+        }
+        DataType::Upvalue => {
+            let mut found = false;
+            if lua_getinfo(L, c"f".as_ptr(), ar) != 0 {
+                let function = lua_gettop(L);
+                let mut index = 1;
+                loop {
+                    let upvalue = lua_getupvalue(L, function, index);
+                    if upvalue.is_null() {
+                        break;
+                    }
+                    if CStr::from_ptr(upvalue).to_bytes() == name.as_bytes() {
+                        found = true;
+                        break;
+                    }
+                    lua_settop(L, -2);
+                    index += 1;
+                }
+            }
+            found
+        }
+        DataType::UpvalueId { .. } | DataType::TableField { .. } => false,
+    };
 
-    pub fn clear_step_triggered(&self) {
-        unsafe {
-            STEP_TRIGGERED.store(false, Ordering::SeqCst);
+    let value = found.then(|| describe_watched_value(L, -1));
+    lua_settop(L, top);
+    value
+}
+
+#[cfg(feature = "dynamic-lua")]
+unsafe fn read_watched_value(_L: LuaState, _ar: *mut lua_Debug, _data_type: &DataType, _name: &str) -> Option<String> {
+    None
+}
+
+/// Renders a value for data breakpoints to compare and report
+#[cfg(feature = "static-lua")]
+unsafe fn describe_watched_value(L: LuaState, index: c_int) -> String {
+    match lua_type(L, index) {
+        LUA_TNIL => "nil".to_string(),
+        LUA_TBOOLEAN => (lua_toboolean(L, index) != 0).to_string(),
+        LUA_TNUMBER => lua_tonumber(L, index).to_string(),
+        LUA_TSTRING => {
+            let mut len = 0;
+            let ptr = lua_tolstring(L, index, &mut len);
+            format!("{:?}", decode_lua_string(L, std::slice::from_raw_parts(ptr as *const u8, len)))
+        }
+        type_ => {
+            let type_name = CStr::from_ptr(lua_typename(L, type_)).to_string_lossy();
+            format!("{}: 0x{:x}", type_name, lua_topointer(L, index) as usize)
         }
     }
+}
 
-    pub fn install_hook(&self) {
-        let lua = self.lua.lock().unwrap();
-        unsafe {
-            lua.lua_sethook(lua_hook_callback, LUA_MASKLINE, 0);
-        }
+/// Whether a called function is the one a function breakpoint names
+///
+/// Plain names match the name Lua reports for the call. Dotted names such as
+/// `module.function` or `Class:method` are looked up from the globals, or from
+/// `package.loaded` for modules kept in locals, without running metamethods.
+#[cfg(feature = "static-lua")]
+unsafe fn function_breakpoint_matches(L: LuaState, breakpoint: &str, called_name: Option<&str>, called: usize) -> bool {
+    let path: Vec<&str> = breakpoint.split(['.', ':']).collect();
+    if path.len() == 1 {
+        return called_name == Some(breakpoint);
     }
 
-    pub fn is_paused(&self) -> bool {
-        unsafe { PAUSED.load(Ordering::SeqCst) }
+    let top = lua_gettop(L);
+    let matches = push_global_path(L, &path) && lua_topointer(L, -1) as usize == called;
+    lua_settop(L, top);
+    matches
+}
+
+/// Pushes the value at a path of table fields, leaving the tables walked through below it
+#[cfg(feature = "static-lua")]
+unsafe fn push_global_path(L: LuaState, path: &[&str]) -> bool {
+    lua_rawgeti(L, LUA_REGISTRYINDEX, LUA_RIDX_GLOBALS);
+    let mut found = push_raw_field(L, path[0]) && lua_type(L, -1) == LUA_TTABLE;
+    if !found {
+        lua_rawgeti(L, LUA_REGISTRYINDEX, LUA_RIDX_GLOBALS);
+        found = push_raw_field(L, "package") && push_raw_field(L, "loaded") && push_raw_field(L, path[0]);
     }
+    found && path[1..].iter().all(|key| push_raw_field(L, key))
+}
 
-    pub fn wait_for_pause(&self, timeout_ms: u64) -> bool {
-        let start = std::time::Instant::now();
-        while !self.is_paused() {
-            if start.elapsed() > Duration::from_millis(timeout_ms) {
-                return false;
-            }
-            thread::sleep(Duration::from_millis(10));
-        }
-        true
+/// Pushes a field of the table on top of the stack without invoking `__index`
+///
+/// Returns false, leaving the stack as it was, if the top is not a table, and
+/// false after pushing nil if the field is unset.
+#[cfg(feature = "static-lua")]
+unsafe fn push_raw_field(L: LuaState, key: &str) -> bool {
+    if lua_type(L, -1) != LUA_TTABLE {
+        return false;
     }
+    lua_pushlstring(L, key.as_ptr() as *const libc::c_char, key.len());
+    lua_rawget(L, -2) != LUA_TNIL
+}
 
-    pub fn handle_pause(&self) -> bool {
-        let is_breakpoint = self.is_breakpoint_hit_at_current_location();
-        let step_triggered = unsafe { STEP_TRIGGERED.load(Ordering::SeqCst) };
+// Hook state of every PUC Lua runtime in the process
+static HOOK_STATES: Lazy<HookRegistry<PucHookState>> = Lazy::new(HookRegistry::new);
 
-        if is_breakpoint || step_triggered {
-            self.clear_step_triggered();
-            true
-        } else {
-            self.clear_pause();
-            self.install_hook();
-            false
+/// Decodes the bytes of a Lua string for display, with the encoding configured for its state
+pub(super) fn decode_lua_string(state: LuaState, bytes: &[u8]) -> String {
+    // SAFETY: strings are decoded on the thread running `state`
+    let encoding = unsafe { HOOK_STATES.get(state) }
+        .and_then(|state| state.string_encoding.lock().map(|e| *e).unwrap_or(None));
+    crate::debug::encoding::decode(bytes, encoding)
+}
+
+/// How long a frame-less evaluation waits for the hook to reach a safe point
+const GLOBAL_EVAL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a restart waits for the running program to unwind
+const RESTART_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Error the hook raises to unwind a program being restarted
+const RESTART_ERROR: &[u8] = b"program stopped by the debugger for a restart\0";
+
+/// The value on top of the stack, without running metamethods where the build allows
+fn value_on_top(lua: &mut Lua) -> Value {
+    #[cfg(feature = "static-lua")]
+    return unsafe { raw_to_value(lua.state(), -1) };
+
+    #[cfg(feature = "dynamic-lua")]
+    PUCLuaRuntime::lua_to_value(lua, -1)
+}
+
+#[cfg(feature = "static-lua")]
+unsafe fn raw_to_value(L: LuaState, index: c_int) -> Value {
+    match lua_type(L, index) {
+        LUA_TNIL => Value::Nil,
+        LUA_TBOOLEAN => Value::Boolean(lua_toboolean(L, index) != 0),
+        LUA_TNUMBER => Value::Number(lua_tonumber(L, index)),
+        LUA_TSTRING => {
+            let mut len = 0;
+            let ptr = lua_tolstring(L, index, &mut len);
+            Value::String(decode_lua_string(L, std::slice::from_raw_parts(ptr as *const u8, len)))
         }
+        // Raw length so a __len metamethod cannot raise an error inside the hook
+        LUA_TTABLE => Value::Table {
+            reference: 0,
+            length: lua_rawlen(L, index) as u32,
+        },
+        LUA_TFUNCTION => Value::Function {
+            reference: 0,
+            name: None,
+        },
+        8 => Value::Thread,
+        _ => Value::UserData,
     }
+}
 
-    pub fn clear_pause(&self) {
-        unsafe {
-            PAUSED.store(false, Ordering::SeqCst);
-            SHOULD_STEP.store(false, Ordering::SeqCst);
-            STEP_TRIGGERED.store(false, Ordering::SeqCst);
+/// Replacement for `print` that reports output as DAP output events
+///
+/// Needed because a launched program shares the process stdout with the DAP
+/// stdio transport.
+#[cfg(feature = "static-lua")]
+extern "C" fn print_to_output(L: LuaState) -> c_int {
+    unsafe {
+        let count = lua_gettop(L);
+        let mut parts = Vec::with_capacity(count as usize);
+        for index in 1..=count {
+            let mut len = 0;
+            let ptr = luaL_tolstring(L, index, &mut len);
+            parts.push(decode_lua_string(L, std::slice::from_raw_parts(ptr as *const u8, len)));
+            lua_settop(L, -2);
+        }
+        // The output was shown when the recorded run printed it
+        if let Some(state) = HOOK_STATES.get(L).filter(|state| !state.is_replaying()) {
+            state.emit(crate::dap::Event::output("stdout", &format!("{}\n", parts.join("\t"))));
         }
     }
+    0
+}
 
-    pub fn set_step(&self, mode: StepMode) {
-        unsafe {
-            SHOULD_STEP.store(true, Ordering::SeqCst);
-            STEP_MODE.store(mode.to_u32() as usize, Ordering::SeqCst);
+/// Told by the `MODULE_TRACKING` searchers the source and name of each module `require` loads
+///
+/// A module seen for the first time is announced with a `module` event.
+#[cfg(feature = "static-lua")]
+extern "C" fn module_loaded(L: LuaState) -> c_int {
+    unsafe {
+        let mut len = 0;
+        let ptr = lua_tolstring(L, 1, &mut len);
+        if ptr.is_null() {
+            return 0;
+        }
+        let source = String::from_utf8_lossy(std::slice::from_raw_parts(ptr as *const u8, len)).into_owned();
+        let Some(state) = HOOK_STATES.get(L) else { return 0 };
+        state.note_chunk(&source);
 
-            let lua = self.lua.lock().unwrap();
-            let mut ar = DebugInfo::new();
-            if lua.lua_getinfo(b"n\0".as_ptr() as *const i8, ar.ptr()) != 0 {
-                let depth = ar.linedefined() as usize;
-                if depth == 0 {
-                    STEP_DEPTH.store(0, Ordering::SeqCst);
-                } else {
-                    STEP_DEPTH.store(depth + 1, Ordering::SeqCst);
-                }
-            }
+        let ptr = lua_tolstring(L, 2, &mut len);
+        if ptr.is_null() {
+            return 0;
+        }
+        let name = String::from_utf8_lossy(std::slice::from_raw_parts(ptr as *const u8, len));
+        let module = state.modules.lock().unwrap().record(&name, &source);
+        if let Some(mut module) = module {
+            module.path = module.path.map(|path| absolute_path(&path));
+            state.emit(crate::dap::Event::module("new", &module));
         }
-        self.install_hook();
     }
+    0
+}
 
-    pub fn resume(&self) {
-        self.clear_pause();
-        self.install_hook();
+/// A path Lua gave relative to the working directory, made absolute for the client
+fn absolute_path(path: &str) -> String {
+    let relative = std::path::Path::new(path);
+    if relative.is_absolute() {
+        return path.to_string();
     }
+    let relative = relative.strip_prefix(".").unwrap_or(relative);
+    match std::env::current_dir() {
+        Ok(cwd) => cwd.join(relative).display().to_string(),
+        Err(_) => path.to_string(),
+    }
+}
 
-    pub fn get_current_location(&self) -> (Option<String>, u32) {
-        unsafe {
-            let line = CURRENT_LINE.load(Ordering::SeqCst) as u32;
-            (CURRENT_SOURCE.clone(), line)
+/// Replacement for Lua 5.4's `warn` that reports warnings as output events
+///
+/// Unlike the warning function, it sees the calling thread, so the event
+/// carries the location of the call.
+#[cfg(feature = "static-lua")]
+extern "C" fn warn_to_output(L: LuaState) -> c_int {
+    unsafe {
+        let count = lua_gettop(L);
+        // Raises the usual argument errors before anything is allocated
+        luaL_checklstring(L, 1, std::ptr::null_mut());
+        for index in 2..=count {
+            luaL_checklstring(L, index, std::ptr::null_mut());
+        }
+
+        let mut bytes = Vec::new();
+        for index in 1..=count {
+            let mut len = 0;
+            let ptr = lua_tolstring(L, index, &mut len);
+            bytes.extend_from_slice(std::slice::from_raw_parts(ptr as *const u8, len));
+        }
+        let message = decode_lua_string(L, &bytes);
+
+        let Some(state) = HOOK_STATES.get(L) else { return 0 };
+        if (count == 1 && state.warning_control(&message))
+            || !state.warnings_enabled.load(Ordering::SeqCst)
+            || state.is_replaying()
+        {
+            return 0;
         }
+
+        let text = format!("Lua warning: {}\n", message);
+        let mut info = DebugInfo::new();
+        // Level 0 is `warn` itself
+        let location = if lua_getstack(L, 1, info.ptr()) != 0 && lua_getinfo(L, c"Sl".as_ptr(), info.ptr()) != 0 {
+            info.source()
+                .and_then(|source| source.strip_prefix('@'))
+                .filter(|_| info.current_line() > 0)
+                .map(|path| (path.to_string(), info.current_line() as u32))
+        } else {
+            None
+        };
+        state.emit(match location {
+            Some((path, line)) => crate::dap::Event::output_at("stderr", &text, &path, line),
+            None => crate::dap::Event::output("stderr", &text),
+        });
     }
+    0
+}
 
-    pub fn get_current_line(&self) -> u32 {
-        unsafe { CURRENT_LINE.load(Ordering::SeqCst) as u32 }
+/// Warning function for warnings the runtime raises itself, such as errors in `__gc`
+///
+/// `ud` is the main thread. These warnings point at bugs the program cannot
+/// otherwise see, so they are reported as important.
+#[cfg(feature = "static-lua")]
+extern "C" fn runtime_warning(ud: *mut c_void, message: *const c_char, tocont: c_int) {
+    // SAFETY: Lua calls warning functions on a thread of its own state
+    let Some(state) = (unsafe { HOOK_STATES.get(ud) }) else { return };
+    let piece = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+
+    let mut pending = state.pending_warning.lock().unwrap();
+    if pending.is_empty() && tocont == 0 && state.warning_control(&piece) {
+        return;
+    }
+    pending.push_str(&piece);
+    if tocont != 0 {
+        return;
     }
 
-    pub fn get_current_source(&self) -> Option<String> {
-        unsafe { CURRENT_SOURCE.clone() }
+    let message = std::mem::take(&mut *pending);
+    drop(pending);
+    if !state.warnings_enabled.load(Ordering::SeqCst) {
+        return;
+    }
+    if state.detached.load(Ordering::SeqCst) {
+        eprintln!("Lua warning: {}", message);
+    } else {
+        state.emit(crate::dap::Event::output("important", &format!("Lua warning: {}\n", message)));
     }
 }
 
-#[async_trait]
-impl DebugRuntime for PUCLuaRuntime {
-    async fn version(&self) -> RuntimeVersion {
-        RuntimeVersion {
-            runtime: RuntimeType::PUC,
-            version: LuaVersion::V54,
+/// Registry key of the weak table holding coroutines created by the program
+const COROUTINE_REGISTRY_KEY: &str = "wayfinder.coroutines";
+
+/// Registry key of the weak table mapping each coroutine to the thread that last resumed it
+///
+/// The main thread is recorded as `false`, since Lua 5.1 has no value for it.
+const RESUMER_REGISTRY_KEY: &str = "wayfinder.resumers";
+
+/// Wraps `coroutine.create`, `coroutine.wrap` and `coroutine.resume` so live
+/// coroutines can be listed and their stacks continued into their resumers
+///
+/// Takes the patch API, see `patches`, and returns the coroutine and resumer tables.
+const COROUTINE_TRACKING: &str = r#"
+local patches = ...
+local registry = setmetatable({}, { __mode = "k" })
+local resumers = setmetatable({}, { __mode = "kv" })
+local create, resume, running = coroutine.create, coroutine.resume, coroutine.running
+local function tracked_resume(co, ...)
+    resumers[co] = (running()) or false
+    return resume(co, ...)
+end
+patches.set(coroutine, "create", function(f)
+    local co = create(f)
+    registry[co] = true
+    return co
+end)
+patches.set(coroutine, "resume", tracked_resume)
+patches.set(coroutine, "wrap", function(f)
+    local co = coroutine.create(f)
+    local function finish(ok, ...)
+        if not ok then error((...), 0) end
+        return ...
+    end
+    return function(...) return finish(tracked_resume(co, ...)) end
+end)
+return registry, resumers
+"#;
+
+/// Registry key of the table of code loaded under `=name` chunk names
+const CHUNK_SOURCES_KEY: &str = "wayfinder.chunk_sources";
+
+/// Wraps `load` and `loadstring` to keep the code of chunks given a `=name`
+///
+/// Lua keeps the code of a string chunk as its source unless a chunk name is
+/// given; with a `=name` the code is lost, so it is captured here. Takes the
+/// patch API and returns the table of captured code by chunk name.
+const CHUNK_SOURCE_TRACKING: &str = r#"
+local patches = ...
+local sources = {}
+local function track(loader)
+    return function(chunk, chunkname, ...)
+        if type(chunk) == "string" and type(chunkname) == "string" and chunkname:sub(1, 1) == "=" then
+            sources[chunkname] = chunk
+        end
+        return loader(chunk, chunkname, ...)
+    end
+end
+patches.set(_G, "load", track(load))
+if loadstring then patches.set(_G, "loadstring", track(loadstring)) end
+return sources
+"#;
+
+/// Wraps the searchers `require` uses to report each Lua module they load
+///
+/// Takes the patch API and the function to report a chunk's source and the
+/// module's name to. The module is reported as soon as it is found, before
+/// its code runs, so breakpoints waiting for it are verified even in code
+/// that runs later. `package.loaders` is the list in Lua 5.1.
+const MODULE_TRACKING: &str = r#"
+local patches, loaded = ...
+local searchers = package and (package.searchers or package.loaders)
+local getinfo = debug and debug.getinfo
+if type(searchers) ~= "table" or not getinfo then return end
+local function report(name, loader, ...)
+    if type(loader) == "function" then
+        local info = getinfo(loader, "S")
+        if info and info.what ~= "C" then loaded(info.source, name) end
+    end
+    return loader, ...
+end
+for i = 1, #searchers do
+    local searcher = searchers[i]
+    patches.set(searchers, i, function(name, ...) return report(name, searcher(name, ...)) end)
+end
+"#;
+
+/// Lists the modules in `package.loaded` with the files `package.searchpath` finds them in
+///
+/// Returns the string keys of `package.loaded` and, at the same indices,
+/// their paths or `false`. Walks with `next`, so no `__pairs` runs;
+/// `package.searchpath` is missing before Lua 5.2.
+const LOADED_MODULES: &str = r#"
+local names, paths = {}, {}
+local loaded = package and package.loaded
+if type(loaded) ~= "table" then return names, paths end
+local searchpath, path = package.searchpath, package.path
+for name in next, loaded do
+    if type(name) == "string" then
+        names[#names + 1] = name
+        paths[#names] = searchpath and type(path) == "string" and searchpath(name, path) or false
+    end
+end
+return names, paths
+"#;
+
+/// Wraps the functions a program reads the outside world through, for record and replay
+///
+/// Takes the patch API and the functions that tell whether a logged input is
+/// left, hand it back, and log what a real call returned. While a replay has
+/// logged inputs, the program gets those instead of calling the function.
+const INPUT_LOGGING: &str = r#"
+local patches, replaying, replay, record = ...
+local function log(t, k)
+    local original = t and rawget(t, k)
+    if type(original) ~= "function" then return end
+    -- One line, so a call counts as the same number of lines recorded or replayed
+    patches.set(t, k, function(...) if replaying() then return replay() end return record(original(...)) end)
+end
+log(os, "time")
+log(os, "clock")
+log(math, "random")
+log(io, "read")
+"#;
+
+/// Whether the recording has a logged input left for the program, see `INPUT_LOGGING`
+#[cfg(feature = "static-lua")]
+extern "C" fn input_replaying(L: LuaState) -> c_int {
+    let replaying = unsafe { HOOK_STATES.get(L) }.is_some_and(|state| {
+        state.recording.lock().unwrap().as_ref().is_some_and(Recording::has_logged_input)
+    });
+    unsafe { lua_pushboolean(L, replaying as c_int) };
+    1
+}
+
+/// Returns the values of the next logged input
+#[cfg(feature = "static-lua")]
+extern "C" fn input_replay(L: LuaState) -> c_int {
+    let values = unsafe { HOOK_STATES.get(L) }
+        .and_then(|state| state.recording.lock().unwrap().as_mut().and_then(Recording::replay_input))
+        .unwrap_or_default();
+    unsafe {
+        lua_checkstack(L, values.len() as c_int);
+        for value in &values {
+            match value {
+                InputValue::Nil => lua_pushnil(L),
+                InputValue::Boolean(b) => lua_pushboolean(L, *b as c_int),
+                InputValue::Integer(n) => lua_pushinteger(L, *n),
+                InputValue::Number(n) => lua_pushnumber(L, *n),
+                InputValue::String(bytes) => lua_pushlstring(L, bytes.as_ptr() as *const c_char, bytes.len()),
+            }
         }
     }
+    values.len() as c_int
+}
 
-    async fn hot_reload(
-        &mut self,
-        module_source: &str,
-        module_name: Option<&str>,
-    ) -> Result<crate::hot_reload::HotReloadResult, RuntimeError> {
-        #[cfg(feature = "hot-reload")]
-        {
-            use crate::hot_reload::{HotReloadResult, HotReloadWarning, WarningSeverity};
-            use crate::runtime::lua_ffi::*;
-
-            // Compile the module source
-            let compile_result: Result<(), RuntimeError> = {
-                let lua_guard = self.lua.lock().unwrap();
-
-                unsafe {
-                    let source_cstr = std::ffi::CString::new(module_source)
-                        .map_err(|_| RuntimeError::Communication("Invalid source string".to_string()))?;
-
-                    if lua_guard.luaL_loadstring(source_cstr.as_ptr()) != LUA_OK as i32 {
-                        // Get the error message
-                        let error_msg = if lua_guard.lua_type(-1) == LUA_TSTRING as i32 {
-                            let c_str = lua_guard.lua_tolstring(-1, std::ptr::null_mut());
-                            if !c_str.is_null() {
-                                std::ffi::CStr::from_ptr(c_str)
-                                    .to_string_lossy()
-                                    .to_string()
-                            } else {
-                                "Unknown compilation error".to_string()
-                            }
-                        } else {
-                            "Unknown compilation error".to_string()
-                        };
-
-                        lua_guard.lua_pop(1); // Remove error message
-                        return Err(RuntimeError::Communication(format!("Compilation failed: {}", error_msg)));
-                    }
-                    Ok(())
+/// Logs its arguments, the results of a real call, and returns them unchanged
+#[cfg(feature = "static-lua")]
+extern "C" fn input_record(L: LuaState) -> c_int {
+    unsafe {
+        let count = lua_gettop(L);
+        let values = (1..=count)
+            .map(|index| match lua_type(L, index) {
+                LUA_TBOOLEAN => InputValue::Boolean(lua_toboolean(L, index) != 0),
+                LUA_TNUMBER if lua_isinteger(L, index) != 0 => InputValue::Integer(lua_tointeger(L, index)),
+                LUA_TNUMBER => InputValue::Number(lua_tonumber(L, index)),
+                LUA_TSTRING => {
+                    let mut len = 0;
+                    let ptr = lua_tolstring(L, index, &mut len);
+                    InputValue::String(std::slice::from_raw_parts(ptr as *const u8, len).to_vec())
                 }
-            };
+                _ => InputValue::Nil,
+            })
+            .collect();
+        if let Some(state) = HOOK_STATES.get(L) {
+            if let Some(recording) = state.recording.lock().unwrap().as_mut() {
+                recording.record_input(values);
+            }
+        }
+        count
+    }
+}
 
-            compile_result?;
-
-            // Execute the compiled module
-            let execute_result: Result<(), RuntimeError> = {
-                let lua_guard = self.lua.lock().unwrap();
-
-                unsafe {
-                    if lua_guard.lua_pcall(0, 1, 0) != LUA_OK as i32 {
-                        // Get the error message
-                        let error_msg = if lua_guard.lua_type(-1) == LUA_TSTRING as i32 {
-                            let c_str = lua_guard.lua_tolstring(-1, std::ptr::null_mut());
-                            if !c_str.is_null() {
-                                std::ffi::CStr::from_ptr(c_str)
-                                    .to_string_lossy()
-                                    .to_string()
-                            } else {
-                                "Unknown execution error".to_string()
-                            }
-                        } else {
-                            "Unknown execution error".to_string()
-                        };
+/// Wraps the input functions `INPUT_LOGGING` names
+///
+/// Runs unhooked, so its lines are not counted as lines of the recorded run:
+/// a breakpoint set before the program starts installs the hook early.
+#[cfg(feature = "static-lua")]
+fn install_input_logging(lua: &mut Lua) -> Result<(), String> {
+    let (mask, count) = (lua.get_hook_mask(), lua.get_hook_count());
+    lua.lua_sethook(lua_hook_callback, 0, 0);
+    let installed = (|| {
+        lua.load_string(INPUT_LOGGING)?;
+        patches::push_patch_api(lua)?;
+        lua.push_cfunction(input_replaying, 0);
+        lua.push_cfunction(input_replay, 0);
+        lua.push_cfunction(input_record, 0);
+        lua.pcall(4, 0)
+    })();
+    lua.lua_sethook(lua_hook_callback, mask, count);
+    installed.map(|_| ())
+}
 
-                        lua_guard.lua_pop(1); // Remove error message
-                        return Err(RuntimeError::Communication(format!("Execution failed: {}", error_msg)));
-                    }
+#[cfg(feature = "dynamic-lua")]
+fn install_input_logging(_lua: &mut Lua) -> Result<(), String> {
+    Err("Record and replay needs a statically linked Lua".to_string())
+}
 
-                    // Pop the result
-                    lua_guard.lua_pop(1);
-                    Ok(())
-                }
+/// Breadth-first walk over the tables reachable from `_G`
+///
+/// Returns a function taking the predicate source and the table and match
+/// limits, which returns the matches, the number of tables walked and whether
+/// a limit cut the walk short. Entries are read with `next` so `__pairs` and
+/// `__index` metamethods do not run; a predicate raising an error is a miss.
+const HEAP_SEARCH: &str = r#"
+local function describe(v)
+    if type(v) == "string" then return v end
+    local ok, text = pcall(tostring, v)
+    return ok and text or type(v)
+end
+local function key_path(path, k)
+    if type(k) == "string" and k:match("^[%a_][%w_]*$") then
+        return path .. "." .. k
+    elseif type(k) == "string" then
+        return path .. string.format("[%q]", k)
+    elseif type(k) == "number" or type(k) == "boolean" then
+        return path .. "[" .. tostring(k) .. "]"
+    end
+    return path .. "[" .. describe(k) .. "]"
+end
+return function(source, max_tables, max_results)
+    local accept = assert(load("return function(k, v) return " .. source .. " end", "=predicate"))()
+    local seen = { [_G] = true }
+    local queue, head, tail = { { _G, "_G" } }, 1, 1
+    local matches, visited = {}, 0
+    while head <= tail do
+        if visited >= max_tables then return matches, visited, true end
+        local t, path = queue[head][1], queue[head][2]
+        queue[head] = nil
+        head = head + 1
+        visited = visited + 1
+        for k, v in next, t do
+            local entry = key_path(path, k)
+            local ok, hit = pcall(accept, k, v)
+            if ok and hit then
+                matches[#matches + 1] = { path = entry, value = describe(v), type = type(v) }
+                if #matches >= max_results then return matches, visited, true end
+            end
+            if type(v) == "table" and not seen[v] then
+                seen[v] = true
+                tail = tail + 1
+                queue[tail] = { v, entry }
+            end
+        end
+    end
+    return matches, visited, false
+end
+"#;
+
+/// Runs `HEAP_SEARCH` on the given state, leaving the stack as it was
+fn search_heap_on(
+    lua: &mut Lua,
+    predicate: &str,
+    max_tables: usize,
+    max_results: usize,
+) -> Result<crate::memory::HeapSearchResult, String> {
+    let top = lua.get_top();
+    let result = (|| {
+        lua.load_string(HEAP_SEARCH)?;
+        lua.pcall(0, 1)?;
+        lua.push_string(predicate);
+        lua.push_integer(max_tables as lua_Integer);
+        lua.push_integer(max_results as lua_Integer);
+        lua.pcall(3, 3)?;
+
+        let matches_index = top + 1;
+        let mut matches = Vec::new();
+        for i in 1.. {
+            if lua.raw_get_i(matches_index, i) != LUA_TTABLE {
+                break;
+            }
+            let mut field = |name: &str| {
+                lua.get_field(-1, name);
+                let text = decode_lua_string(lua.state(), &lua.pop_bytes());
+                lua.lua_settop(-2);
+                text
             };
+            matches.push(crate::memory::HeapMatch {
+                path: field("path"),
+                value: field("value"),
+                type_name: field("type"),
+            });
+            lua.lua_settop(-2);
+        }
 
-            execute_result?;
+        Ok(crate::memory::HeapSearchResult {
+            matches,
+            tables_visited: lua.lua_tointeger(matches_index + 1) as usize,
+            truncated: lua.lua_toboolean(matches_index + 2) != 0,
+        })
+    })();
+    lua.set_top(top);
+    result
+}
 
-            // Create warnings about limitations
-            let warnings = vec![
-                HotReloadWarning {
-                    message: "State preservation not yet implemented - local variables and upvalues will be reset".to_string(),
-                    severity: WarningSeverity::Warning,
-                },
-                HotReloadWarning {
-                    message: "Module references in existing closures will not be updated".to_string(),
-                    severity: WarningSeverity::Warning,
-                }
-            ];
+/// Walk over every object reachable from `_G` and the registry
+///
+/// Returns a function taking the registry, which returns the tables,
+/// functions, userdata and threads found, the number of entries of each
+/// (table entries, or function upvalues), and the count and total length of
+/// the distinct strings. Follows table keys and values, metatables and, when
+/// the debug library is loaded, function upvalues. Entries are read with
+/// `next` so no metamethods run.
+const HEAP_SNAPSHOT: &str = r#"
+return function(registry)
+    local getupvalue = debug and debug.getupvalue
+    local getmt = debug and debug.getmetatable or getmetatable
+    local seen, objects, entries = {}, {}, {}
+    local strings, string_bytes = 0, 0
+    local function visit(v)
+        if v == nil or seen[v] then return end
+        local kind = type(v)
+        if kind == "string" then
+            seen[v] = true
+            strings = strings + 1
+            string_bytes = string_bytes + #v
+        elseif kind == "table" or kind == "function" or kind == "userdata" or kind == "thread" then
+            seen[v] = true
+            objects[#objects + 1] = v
+        end
+    end
+    visit(_G)
+    visit(registry)
+    local i = 1
+    while objects[i] ~= nil do
+        local v = objects[i]
+        local kind = type(v)
+        local count = 0
+        if kind == "table" then
+            for k, value in next, v do
+                count = count + 1
+                visit(k)
+                visit(value)
+            end
+        elseif kind == "function" and getupvalue then
+            while true do
+                local name, value = getupvalue(v, count + 1)
+                if name == nil then break end
+                count = count + 1
+                visit(value)
+            end
+        end
+        if kind == "table" or kind == "userdata" then
+            visit(getmt(v))
+        end
+        entries[i] = count
+        i = i + 1
+    end
+    return objects, entries, strings, string_bytes
+end
+"#;
+
+/// Estimated bytes of a table header, and of each of its entries
+const TABLE_SIZE: usize = 56;
+const TABLE_ENTRY_SIZE: usize = 32;
+/// Estimated bytes of a closure header, and of each of its upvalues
+const FUNCTION_SIZE: usize = 40;
+const UPVALUE_SIZE: usize = 16;
+/// Estimated bytes of a userdata header; the block itself is added to it
+const USERDATA_SIZE: usize = 40;
+/// Estimated bytes of a coroutine with its initial stack
+const THREAD_SIZE: usize = 1024;
+/// Estimated bytes of a string header; its contents are added to it
+const STRING_SIZE: usize = 24;
+
+/// Reads the collector's statistics of the given state
+fn memory_statistics_on(lua: &mut Lua) -> crate::memory::MemoryStatistics {
+    // Setting a parameter is the only way to read it, so the old value is put back
+    let pause = lua.gc(LUA_GCSETPAUSE, 0, 0);
+    lua.gc(LUA_GCSETPAUSE, pause as c_long, 0);
+    let step_mul = lua.gc(LUA_GCSETSTEPMUL, 0, 0);
+    lua.gc(LUA_GCSETSTEPMUL, step_mul as c_long, 0);
+    let (kb, bytes, running) = (lua.gc(LUA_GCCOUNT, 0, 0), lua.gc(LUA_GCCOUNTB, 0, 0), lua.gc(LUA_GCISRUNNING, 0, 0));
+
+    crate::memory::MemoryStatistics {
+        total_kb: kb as f64 + (bytes as f64 / 1024.0),
+        total_bytes: (kb * 1024 + bytes) as usize,
+        gc_pause: pause,
+        gc_step_mul: step_mul,
+        gc_running: running != 0,
+        timestamp: std::time::SystemTime::now(),
+    }
+}
 
-            Ok(HotReloadResult {
-                success: true,
-                warnings,
-                message: Some(format!("Module '{}' reloaded successfully",
-                                    module_name.unwrap_or("unnamed"))),
-            })
+/// Runs `LOADED_MODULES` on the given state, leaving the stack as it was
+///
+/// A module `package.searchpath` cannot find keeps the file `require` loaded it from.
+fn loaded_modules_on(lua: &mut Lua, registry: &ModuleRegistry) -> Result<Vec<Module>, String> {
+    let top = lua.get_top();
+    let result = (|| {
+        lua.load_string(LOADED_MODULES)?;
+        lua.pcall(0, 2)?;
+
+        let (names_index, paths_index) = (top + 1, top + 2);
+        let mut modules = Vec::new();
+        for i in 1.. {
+            if lua.raw_get_i(names_index, i) != LUA_TSTRING {
+                lua.lua_settop(-2);
+                break;
+            }
+            let name = lua.pop_string();
+            lua.lua_settop(-2);
+            let path = (lua.raw_get_i(paths_index, i) == LUA_TSTRING).then(|| lua.pop_string());
+            lua.lua_settop(-2);
+            let mut module = registry.module(&name, path);
+            module.path = module.path.map(|path| absolute_path(&path));
+            modules.push(module);
         }
+        Ok(modules)
+    })();
+    lua.set_top(top);
+    result
+}
 
-        #[cfg(not(feature = "hot-reload"))]
-        {
-            let _ = (module_source, module_name);
-            Err(RuntimeError::NotImplemented("Hot reload feature not enabled".to_string()))
+/// Runs `HEAP_SNAPSHOT` on the given state, leaving the stack as it was
+///
+/// Strings are counted and sized but not listed; before Lua 5.4 they have no
+/// address to tell them apart between snapshots.
+fn heap_snapshot_on(lua: &mut Lua, id: u64) -> Result<crate::memory::HeapSnapshot, String> {
+    use crate::memory::{HeapSnapshot, ObjectCounts, ObjectInfo};
+
+    let statistics = memory_statistics_on(lua);
+    let top = lua.get_top();
+    let result = (|| {
+        lua.load_string(HEAP_SNAPSHOT)?;
+        lua.pcall(0, 1)?;
+        lua.lua_pushvalue(LUA_REGISTRYINDEX);
+        lua.pcall(1, 4)?;
+
+        let (objects_index, entries_index) = (top + 1, top + 2);
+        let mut counts = ObjectCounts {
+            tables: 0,
+            functions: 0,
+            userdata: 0,
+            threads: 0,
+            strings: lua.lua_tointeger(top + 3) as usize,
+        };
+        let string_bytes = lua.lua_tointeger(top + 4) as usize;
+        let mut objects = Vec::new();
+        for i in 1.. {
+            let kind = lua.raw_get_i(objects_index, i);
+            if kind == LUA_TNIL {
+                lua.lua_settop(-2);
+                break;
+            }
+            lua.raw_get_i(entries_index, i);
+            let entries = lua.lua_tointeger(-1) as usize;
+            lua.lua_settop(-2);
+
+            let (type_name, size_estimate) = match kind {
+                LUA_TTABLE => {
+                    counts.tables += 1;
+                    ("table", TABLE_SIZE + entries * TABLE_ENTRY_SIZE)
+                }
+                LUA_TFUNCTION => {
+                    counts.functions += 1;
+                    ("function", FUNCTION_SIZE + entries * UPVALUE_SIZE)
+                }
+                LUA_TUSERDATA => {
+                    counts.userdata += 1;
+                    ("userdata", USERDATA_SIZE + lua.raw_len(-1))
+                }
+                _ => {
+                    counts.threads += 1;
+                    ("thread", THREAD_SIZE)
+                }
+            };
+            let address = lua.topointer(-1) as usize;
+            objects.push(ObjectInfo {
+                id: address as i64,
+                type_name: type_name.to_string(),
+                size_estimate,
+                address: format!("0x{:x}", address),
+            });
+            lua.lua_settop(-2);
         }
-    }
 
-    async fn set_breakpoint(&mut self, breakpoint: BreakpointType) -> Result<Breakpoint, RuntimeError> {
-        match breakpoint {
-            BreakpointType::Line { source, line } => {
-                let mut breakpoints = self.breakpoints.lock().unwrap();
-                breakpoints.entry(source.clone()).or_default().push(line);
+        let mut size_estimates = HashMap::new();
+        for object in &objects {
+            *size_estimates.entry(object.type_name.clone()).or_insert(0) += object.size_estimate;
+        }
+        if counts.strings > 0 {
+            size_estimates.insert("string".to_string(), counts.strings * STRING_SIZE + string_bytes);
+        }
 
-                self.install_hook();
+        Ok(HeapSnapshot {
+            id,
+            timestamp: statistics.timestamp,
+            statistics,
+            object_counts: counts,
+            objects,
+            size_estimates,
+        })
+    })();
+    lua.set_top(top);
+    result
+}
 
-                Ok(Breakpoint {
-                    id: 1,
-                    verified: true,
-                    line,
-                    message: None,
-                })
-            }
-            BreakpointType::Function { name } => Ok(Breakpoint {
-                id: 1,
-                verified: true,
-                line: 1,
-                message: Some(format!("Function breakpoint: {}", name)),
-            }),
-            BreakpointType::Exception { filter } => Ok(Breakpoint {
-                id: 1,
-                verified: true,
-                line: 0,
-                message: Some(format!("Exception breakpoint: {}", filter)),
-            }),
+/// Registry key of the metamethod breakpoint installer
+const METAMETHOD_REGISTRY_KEY: &str = "wayfinder.metamethods";
+
+/// Ids of metamethod breakpoints start here, apart from the fixed id line breakpoints report
+const METAMETHOD_BREAKPOINT_BASE: i64 = 1_000_000;
+
+/// Installs and removes trampolines around metamethods
+///
+/// A trampoline reports the hit and then does what the original metamethod
+/// would have done, so `__index` and `__newindex` tables keep working. Removed
+/// trampolines stop reporting even while another breakpoint on the same event
+/// still wraps them. Takes the patch API, see `patches`.
+const METAMETHOD_BREAKPOINTS: &str = r#"
+local patches = ...
+local installed = {}
+local api = {}
+function api.install(id, mt, event, hit)
+    if type(mt) ~= "table" then error("breakpoint target is not a table", 0) end
+    local original = rawget(mt, event)
+    local trampoline
+    if type(original) == "function" then
+        trampoline = function(...)
+            if installed[id] then hit(event) end
+            return original(...)
+        end
+    elseif event == "__index" and type(original) == "table" then
+        trampoline = function(_, k)
+            if installed[id] then hit(event) end
+            return original[k]
+        end
+    elseif event == "__newindex" and type(original) == "table" then
+        trampoline = function(_, k, v)
+            if installed[id] then hit(event) end
+            original[k] = v
+        end
+    else
+        error("breakpoint target has no " .. tostring(event) .. " metamethod", 0)
+    end
+    installed[id] = patches.set(mt, event, trampoline)
+end
+function api.uninstall(id)
+    local patch = installed[id]
+    if not patch then return end
+    installed[id] = nil
+    -- A metamethod the program replaced since is left alone
+    patches.undo(patch)
+end
+return api
+"#;
+
+/// Returns the `lua_topointer` address of the function an expression evaluates to
+fn function_address(lua: &mut Lua, expression: &str) -> Result<usize, String> {
+    let top = lua.get_top();
+    let result = (|| {
+        lua.load_string(&format!("return {}", expression))?;
+        lua.pcall(0, 1)?;
+        if !lua.is_function(-1) {
+            return Err(format!("{} is not a function", expression));
         }
-    }
+        Ok(lua.topointer(-1) as usize)
+    })();
+    lua.set_top(top);
+    result
+}
 
-    async fn remove_breakpoint(&mut self, _id: i64) -> Result<(), RuntimeError> {
-        Ok(())
+/// Pushes the metamethod breakpoint installer, loading it on first use
+fn push_metamethod_api(lua: &mut Lua) -> Result<(), String> {
+    if lua.get_field(LUA_REGISTRYINDEX, METAMETHOD_REGISTRY_KEY) == LUA_TTABLE {
+        return Ok(());
     }
+    lua.lua_settop(-2);
+
+    lua.load_string(METAMETHOD_BREAKPOINTS)?;
+    patches::push_patch_api(lua)?;
+    lua.pcall(1, 1)?;
+    lua.lua_pushvalue(-1);
+    lua.set_field(LUA_REGISTRYINDEX, METAMETHOD_REGISTRY_KEY);
+    Ok(())
+}
 
-    async fn step(&mut self, mode: StepMode) -> Result<(), RuntimeError> {
-        self.set_step(mode);
+/// Wraps the `event` metamethod of the table `target` evaluates to
+#[cfg(feature = "static-lua")]
+fn install_metamethod_breakpoint(lua: &mut Lua, id: i64, target: &str, event: &str) -> Result<(), String> {
+    let top = lua.get_top();
+    let result = (|| {
+        push_metamethod_api(lua)?;
+        lua.get_field(-1, "install");
+        lua.push_integer(id as lua_Integer);
+        lua.load_string(&format!("return {}", target))?;
+        lua.pcall(0, 1)?;
+        lua.push_string(event);
+        lua.push_cfunction(metamethod_hit, 0);
+        lua.pcall(4, 0)?;
         Ok(())
-    }
+    })();
+    lua.set_top(top);
+    result
+}
 
-    async fn continue_(&mut self) -> Result<(), RuntimeError> {
-        self.resume();
-        Ok(())
+#[cfg(feature = "dynamic-lua")]
+fn install_metamethod_breakpoint(_lua: &mut Lua, _id: i64, _target: &str, _event: &str) -> Result<(), String> {
+    Err("Metamethod breakpoints need a statically linked Lua".to_string())
+}
+
+/// Compiles a file's code without running it and returns the chunk as `lua_dump` writes it
+#[cfg(feature = "static-lua")]
+fn dump_chunk(lua: &mut Lua, path: &str, code: &[u8]) -> Result<Vec<u8>, String> {
+    unsafe extern "C" fn collect(_state: LuaState, data: *const c_void, size: size_t, dump: *mut c_void) -> c_int {
+        let dump = &mut *(dump as *mut Vec<u8>);
+        dump.extend_from_slice(std::slice::from_raw_parts(data as *const u8, size));
+        0
     }
 
-    async fn pause(&mut self) -> Result<(), RuntimeError> {
-        unsafe {
-            PAUSED.store(true, Ordering::SeqCst);
+    let name = std::ffi::CString::new(format!("@{}", path)).map_err(|e| e.to_string())?;
+    let top = lua.get_top();
+    let result = unsafe {
+        if luaL_loadbufferx(lua.state(), code.as_ptr() as *const c_char, code.len(), name.as_ptr(), c"t".as_ptr()) == LUA_OK {
+            let mut dump: Vec<u8> = Vec::new();
+            lua_dump(lua.state(), Some(collect), &mut dump as *mut Vec<u8> as *mut c_void, 0);
+            Ok(dump)
+        } else {
+            Err(lua.pop_string())
         }
+    };
+    lua.set_top(top);
+    result
+}
+
+#[cfg(feature = "dynamic-lua")]
+fn dump_chunk(_lua: &mut Lua, _path: &str, _code: &[u8]) -> Result<Vec<u8>, String> {
+    Err("Breakpoint locations need a statically linked Lua".to_string())
+}
+
+fn uninstall_metamethod_breakpoint(lua: &mut Lua, id: i64) -> Result<(), String> {
+    let top = lua.get_top();
+    let result = (|| {
+        push_metamethod_api(lua)?;
+        lua.get_field(-1, "uninstall");
+        lua.push_integer(id as lua_Integer);
+        lua.pcall(1, 0)?;
         Ok(())
-    }
+    })();
+    lua.set_top(top);
+    result
+}
 
-    async fn stack_trace(&mut self, _thread_id: Option<u64>) -> Result<Vec<Frame>, RuntimeError> {
-        let mut frames = Vec::new();
+/// Called by metamethod trampolines; stops the program like a line breakpoint
+#[cfg(feature = "static-lua")]
+extern "C" fn metamethod_hit(L: LuaState) -> c_int {
+    let Some(state) = (unsafe { HOOK_STATES.get(L) }) else { return 0 };
 
-        for level in 0..10 {
-        let lua = self.lua.lock().unwrap();
+    state.hook.paused.store(true, Ordering::SeqCst);
+    // Level 0 is this function, level 1 the trampoline
+    unsafe { record_flight(&state, L, 1, RecordKind::Stop { reason: "breakpoint".to_string() }) };
+    state.emit(crate::dap::Event::stopped("breakpoint", Some(state.stop_on_thread(L)), true));
 
-            unsafe {
-                let mut ar = DebugInfo::new();
-                let result = lua.lua_getinfo(b"nSluf\0".as_ptr() as *const i8, ar.ptr());
+    if state.program_running.load(Ordering::SeqCst) {
+        state.wait_while_paused();
+    }
+    0
+}
 
-                if result == 0 {
-                    break;
-                }
+/// Frame ids of coroutine frames are offset by the thread id times this stride
+///
+/// Main thread frames keep their stack level as id. Coroutine ids must stay
+/// below the stride so frame ids do not reach `CONTAINER_REFERENCE_BASE`.
+const FRAME_ID_STRIDE: i64 = 1000;
+
+fn frame_id(thread_id: u64, level: c_int) -> i64 {
+    if thread_id == MAIN_THREAD_ID {
+        level as i64
+    } else {
+        thread_id as i64 * FRAME_ID_STRIDE + level as i64
+    }
+}
 
-                let name = ar.name().unwrap_or("unknown").to_string();
-                let source = ar.source().map(|s| s.to_string());
+/// Splits a frame id into the thread id and stack level it refers to
+fn split_frame_id(frame_id: i64) -> (u64, c_int) {
+    let thread_id = (frame_id / FRAME_ID_STRIDE) as u64;
+    let level = (frame_id % FRAME_ID_STRIDE) as c_int;
+    if thread_id == 0 {
+        (MAIN_THREAD_ID, level)
+    } else {
+        (thread_id, level)
+    }
+}
 
-                frames.push(Frame {
-                    id: level as i64,
-                    name,
-                    source: source.map(|s| Source {
-                        name: s.clone(),
-                        path: s,
-                        source_reference: Some(0),
-                    }),
-                    line: ar.current_line() as u32,
-                    column: 1,
-                });
+/// Returns true for coroutines that are running, suspended or not yet started
+fn is_live_thread(thread: &Lua) -> bool {
+    match thread.status() {
+        LUA_YIELD => true,
+        LUA_OK => {
+            let mut ar = unsafe { std::mem::zeroed::<lua_Debug>() };
+            thread.get_stack(0, &mut ar) != 0 || thread.get_top() > 0
+        }
+        // Died with an error
+        _ => false,
+    }
+}
+
+/// Lists the main thread and the program's live coroutines, ordered by id
+fn list_threads(lua: &mut Lua) -> Vec<(u64, LuaState)> {
+    let mut threads = vec![(MAIN_THREAD_ID, lua.state())];
+    // SAFETY: the caller holds the state's lock, so nothing else runs it
+    let state = match unsafe { HOOK_STATES.get(lua.state()) } {
+        Some(state) => state,
+        None => return threads,
+    };
+
+    let top = lua.get_top();
+    if lua.get_field(LUA_REGISTRYINDEX, COROUTINE_REGISTRY_KEY) == LUA_TTABLE {
+        lua.push_nil();
+        while lua.next(-2) != 0 {
+            let thread = lua.to_thread(-2);
+            if !thread.is_null() && is_live_thread(&lua.thread_view(thread)) {
+                threads.push((state.thread_id_for(thread), thread));
             }
+            lua.lua_settop(-2);
         }
+    }
+    lua.set_top(top);
 
-        Ok(frames)
+    // Coroutines created from C are not in the registry but can still stop
+    let (stopped_id, stopped_state) = *state.stopped_thread.lock().unwrap();
+    if state.hook.is_paused() && stopped_state != 0 && !threads.iter().any(|(id, _)| *id == stopped_id) {
+        threads.push((stopped_id, stopped_state as LuaState));
     }
 
-    async fn scopes(&mut self, frame_id: i64) -> Result<Vec<Scope>, RuntimeError> {
-        Ok(vec![
-            Scope {
-                variables_reference: frame_id,
-                name: "Locals".to_string(),
-                expensive: false,
-            },
-            Scope {
-                variables_reference: -1,
-                name: "Globals".to_string(),
-                expensive: true,
-            },
-        ])
-    }
-
-    async fn variables(
-        &mut self,
-        variables_reference: i64,
-        _filter: Option<super::VariableScope>,
-    ) -> Result<Vec<super::Variable>, RuntimeError> {
-        let mut variables = Vec::new();
-        let mut lua = self.lua.lock().unwrap();
+    threads.sort_by_key(|(id, _)| *id);
+    threads
+}
 
-        if variables_reference >= 0 {
-            // Handle local variables using debug.getlocal
-            unsafe {
-                // For local variables, variables_reference represents the frame ID
-                let frame_id = variables_reference as c_int;
-                
-                // Create a debug info structure for the specified frame
-                let mut ar = std::mem::zeroed::<lua_Debug>();
-                // Get stack info for the frame
-                if lua.lua_getstack(frame_id, &mut ar) != 0 {
-                    // Enumerate local variables using lua_getlocal
-                    let mut index = 1i32;
-                    loop {
-                        // Get local variable name and value
-                        let name_ptr = lua.lua_getlocal(&mut ar, index);
-                        
-                        if name_ptr.is_null() {
-                            break; // No more local variables
-                        }
-                        
-                        // Get the local variable name
-                        let name_cstr = CStr::from_ptr(name_ptr);
-                        let name = name_cstr.to_string_lossy().to_string();
-                        
-                        // Skip special variables that start with "(" like "(temporary)"
-                        if !name.starts_with("(") {
-                            // Get the local variable value (it's on top of the stack)
-                            let value_type = lua.type_of(-1);
-                            let value_str = match value_type {
-                                0 => "nil".to_string(),
-                                1 => format!("{}", lua.pop_boolean()),
-                                3 => format!("{}", lua.pop_number()),
-                                4 => lua.pop_string(),
-                                5 => format!("table: 0x{:x}", lua.topointer(-1) as usize),
-                                6 => format!("function: 0x{:x}", lua.topointer(-1) as usize),
-                                7 => format!("userdata: 0x{:x}", lua.topointer(-1) as usize),
-                                8 => format!("thread: 0x{:x}", lua.topointer(-1) as usize),
-                                _ => format!("{}", lua.type_name(value_type)),
-                            };
-
-                            variables.push(super::Variable {
-                                name,
-                                value: value_str,
-                                type_: lua.type_name(value_type).to_string(),
-                                variables_reference: if value_type == 5 { Some(-(variables_reference * 1000 + index as i64)) } else { None },
-                                named_variables: None,
-                                indexed_variables: None,
-                            });
-                        }
-                        
-                        // Remove the value from the stack
-                        lua.lua_settop(-2);
-                        
-                        index += 1;
-                    }
-                }
-            }
-        } else if variables_reference == -1 {
-            // Handle global variables by accessing _G
-            unsafe {
-                // Push "_G" string and get the global table
-                let g_name = b"_G\0".as_ptr() as *const i8;
-                if lua.lua_getglobal(g_name) == 0 {
-                    // _G doesn't exist or is nil, remove it from stack
-                    lua.lua_settop(-2);
-                } else {
-                    // Successfully got _G table, iterate it
-                    lua.push_nil(); // First key
-                    let mut count = 0;
-                    while lua.lua_next(-2) != 0 && count < 100 {
-                        let key = lua.pop_string();
-                        let value_type = lua.type_of(-1);
-                        let value_str = match value_type {
-                            0 => "nil".to_string(),
-                            1 => format!("{}", lua.pop_boolean()),
-                            3 => format!("{}", lua.pop_number()),
-                            4 => lua.pop_string(),
-                            5 => format!("table: 0x{:x}", lua.topointer(-1) as usize),
-                            6 => format!("function: 0x{:x}", lua.topointer(-1) as usize),
-                            7 => format!("userdata: 0x{:x}", lua.topointer(-1) as usize),
-                            8 => format!("thread: 0x{:x}", lua.topointer(-1) as usize),
-                            _ => format!("{}", lua.type_name(value_type)),
-                        };
-
-                        variables.push(super::Variable {
-                            name: key,
-                            value: value_str,
-                            type_: lua.type_name(value_type).to_string(),
-                            variables_reference: if value_type == 5 { Some(-2) } else { None },
-                            named_variables: None,
-                            indexed_variables: None,
-                        });
-                        
-                        // Remove value, keep key for next iteration
-                        lua.lua_settop(-2);
-                        count += 1;
-                    }
-                    
-                    // Remove _G table from stack
-                    lua.lua_settop(-2);
-                }
-            }
-        } else if variables_reference < -1000 {
-            // Handle upvalues - negative values less than -1000 represent upvalues
-            // Format: -(frame_id * 1000 + local_index)
-            let abs_ref = -variables_reference;
-            let frame_id = (abs_ref / 1000) as c_int;
-            // let local_index = (abs_ref % 1000) as c_int;
-            
-            unsafe {
-                let mut ar = std::mem::zeroed::<lua_Debug>();
-                if lua.lua_getstack(frame_id, &mut ar) != 0 {
-                    // Get upvalues using lua_getupvalue
-                    let mut index = 1i32;
-                    loop {
-                        let name_ptr = lua.lua_getupvalue(-1, index);
-                        
-                        if name_ptr.is_null() {
-                            break; // No more upvalues
-                        }
-                        
-                        // Get the upvalue name
-                        let name_cstr = CStr::from_ptr(name_ptr);
-                        let name = name_cstr.to_string_lossy().to_string();
-                        
-                        // Get the upvalue value (it's on top of the stack)
-                        let value_type = lua.type_of(-1);
-                        let value_str = match value_type {
-                            0 => "nil".to_string(),
-                            1 => format!("{}", lua.pop_boolean()),
-                            3 => format!("{}", lua.pop_number()),
-                            4 => lua.pop_string(),
-                            5 => format!("table: 0x{:x}", lua.topointer(-1) as usize),
-                            6 => format!("function: 0x{:x}", lua.topointer(-1) as usize),
-                            7 => format!("userdata: 0x{:x}", lua.topointer(-1) as usize),
-                            8 => format!("thread: 0x{:x}", lua.topointer(-1) as usize),
-                            _ => format!("{}", lua.type_name(value_type)),
-                        };
-
-                        variables.push(super::Variable {
-                            name,
-                            value: value_str,
-                            type_: lua.type_name(value_type).to_string(),
-                            variables_reference: if value_type == 5 { Some(-(variables_reference * 100 + index as i64)) } else { None },
-                            named_variables: None,
-                            indexed_variables: None,
-                        });
-                        
-                        // Remove the value from the stack
-                        lua.lua_settop(-2);
-                        
-                        index += 1;
-                    }
-                }
-            }
-        } else if variables_reference == -2 {
-            // Handle table expansion with depth limits
-            unsafe {
-                // The table is already on the stack (placed there by the caller)
-                // Limit the number of elements we show to prevent huge expansions
-                lua.push_nil(); // First key
-                let mut count = 0;
-                while lua.lua_next(-2) != 0 && count < 50 {
-                    let key = lua.pop_string();
-                    let value_type = lua.type_of(-1);
-                    let value_str = match value_type {
-                        0 => "nil".to_string(),
-                        1 => format!("{}", lua.pop_boolean()),
-                        3 => format!("{}", lua.pop_number()),
-                        4 => lua.pop_string(),
-                        5 => format!("table: 0x{:x}", lua.topointer(-1) as usize),
-                        6 => format!("function: 0x{:x}", lua.topointer(-1) as usize),
-                        7 => format!("userdata: 0x{:x}", lua.topointer(-1) as usize),
-                        8 => format!("thread: 0x{:x}", lua.topointer(-1) as usize),
-                        _ => format!("{}", lua.type_name(value_type)),
-                    };
-
-                    variables.push(super::Variable {
-                        name: key,
-                        value: value_str,
-                        type_: lua.type_name(value_type).to_string(),
-                        variables_reference: if value_type == 5 { Some(-2) } else { None },
-                        named_variables: None,
-                        indexed_variables: None,
-                    });
-                    
-                    // Remove value, keep key for next iteration
-                    lua.lua_settop(-2);
-                    count += 1;
-                }
+/// The thread that resumed a running coroutine, as recorded by `COROUTINE_TRACKING`
+///
+/// None if the coroutine was resumed from C, or its resumer is no longer
+/// waiting on it, which leaves nothing to continue its stack into.
+fn resumer_of(lua: &mut Lua, thread: LuaState) -> Option<LuaState> {
+    let top = lua.get_top();
+    let mut resumer = None;
+    if lua.get_field(LUA_REGISTRYINDEX, RESUMER_REGISTRY_KEY) == LUA_TTABLE {
+        lua.push_nil();
+        while lua.next(-2) != 0 {
+            if lua.to_thread(-2) == thread {
+                let by = lua.to_thread(-1);
+                resumer = Some(if by.is_null() { lua.state() } else { by });
+                break;
             }
+            lua.lua_settop(-2);
         }
-
-        Ok(variables)
     }
+    lua.set_top(top);
 
-    async fn evaluate(&mut self, frame_id: i64, expression: &str) -> Result<Value, RuntimeError> {
-        let trimmed = expression.trim();
-
-        if trimmed.is_empty() {
-            return Ok(Value::Nil);
-        }
-
-        // Check if this is an assignment operation
-        let is_assignment = trimmed.contains('=') && !trimmed.contains("==") && !trimmed.contains("!=");
-        let is_dangerous_function = trimmed.contains("load") || trimmed.contains("dofile") || trimmed.contains("require");
-
-        // Apply safety checks based on configuration
-        match self.config.eval_safety {
-            EvalSafety::Strict => {
-                // In strict mode, prevent all assignments and dangerous functions
-                if is_assignment {
-                    return Err(RuntimeError::Communication(
-                        "Assignment not allowed in strict evaluation mode".to_string()
-                    ));
-                }
-                if is_dangerous_function {
-                    return Err(RuntimeError::Communication(
-                        "Dangerous function calls not allowed in strict evaluation mode".to_string()
-                    ));
-                }
-            }
-            EvalSafety::Basic => {
-                // In basic mode, warn about assignments and dangerous functions
-                if is_assignment {
-                    println!("Warning: Assignment detected in expression evaluation: {}", trimmed);
-                }
-                if is_dangerous_function {
-                    println!("Warning: Potentially dangerous function call detected: {}", trimmed);
-                }
-            }
-            EvalSafety::None => {
-                // In none mode, allow everything but still log
-                if is_assignment {
-                    println!("Info: Assignment in expression evaluation: {}", trimmed);
-                }
-                if is_dangerous_function {
-                    println!("Info: Function call detected: {}", trimmed);
-                }
-            }
-        }
-
-        // If mutation is enabled and this is an assignment, try to handle it properly
-        if self.config.evaluate_mutation && is_assignment {
-            if let Some(result) = self.handle_assignment(frame_id, trimmed).await {
-                return result;
-            }
-        }
-
-        // Execute the expression
-        let mut lua = self.lua.lock().unwrap();
-        if let Ok(_) = lua.execute(trimmed) {
-            // Convert the result on top of stack to our Value type
-            let result = Self::lua_to_value(&mut lua, -1);
-            return Ok(result);
-        }
-
-        // Handle literal values
-        match trimmed {
-            "nil" => Ok(Value::Nil),
-            "true" => Ok(Value::Boolean(true)),
-            "false" => Ok(Value::Boolean(false)),
-            s if s.parse::<f64>().is_ok() => Ok(Value::Number(s.parse().unwrap())),
-            _ => Ok(Value::String(format!("<error: {}>", expression))),
-        }
-    }
+    // A resumer is itself running, or waiting in `resume` for a coroutine it started
+    resumer.filter(|&resumer| {
+        let view = lua.thread_view(resumer);
+        resumer == lua.state() || (view.status() == LUA_OK && is_live_thread(&view))
+    })
+}
 
-    async fn run_to_location(&mut self, _source: &str, _line: u32) -> Result<(), RuntimeError> {
-        Ok(())
+fn thread_name(thread_id: u64) -> String {
+    if thread_id == MAIN_THREAD_ID {
+        "main".to_string()
+    } else {
+        format!("coroutine {}", thread_id)
     }
+}
 
-    async fn source(&mut self, _source_reference: i64) -> Result<String, RuntimeError> {
-        Err(RuntimeError::NotImplemented("source not implemented".to_string()))
+/// Name shown for a frame, following the conventions of Lua's own tracebacks
+fn frame_name(info: &DebugInfo) -> String {
+    if let Some(name) = info.name() {
+        return name.to_string();
     }
 
-    async fn get_exception_info(&mut self, _thread_id: u64) -> Result<ExceptionInfo, RuntimeError> {
-        Err(RuntimeError::NotImplemented("get_exception_info not implemented".to_string()))
+    match info.what() {
+        "main" => "main chunk".to_string(),
+        "C" => "[C]".to_string(),
+        _ => format!("function <{}:{}>", info.short_src(), info.linedefined()),
     }
+}
 
-    async fn check_data_breakpoints(&mut self, frame_id: i64) -> Result<bool, RuntimeError> {
-        // Call the internal check_watchpoints method
-        Ok(self.check_watchpoints(frame_id))
+/// Source of a frame
+///
+/// Chunks loaded from a file are opened by path. Chunks loaded from strings
+/// report their code as source, or `=name` with the code captured by
+/// `CHUNK_SOURCE_TRACKING`; their code is handed out by source reference.
+/// C functions and precompiled chunks get no source.
+fn frame_source(info: &DebugInfo, lua: &mut Lua, references: &Mutex<SourceReferences>) -> Option<Source> {
+    if info.what() == "C" {
+        return None;
     }
+    let source = info.source()?;
+    let Some(path) = source.strip_prefix('@') else {
+        let (name, code) = match source.strip_prefix('=') {
+            Some(name) => (name.to_string(), captured_chunk_source(lua, source)?),
+            // Precompiled chunks start with the bytecode signature
+            None if !source.starts_with('\x1b') => (info.short_src().to_string(), source.to_string()),
+            None => return None,
+        };
+        let reference = references.lock().unwrap().reference(&name, &code);
+        return Some(Source {
+            name,
+            path: String::new(),
+            source_reference: Some(reference),
+        });
+    };
+    let name = std::path::Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path);
+
+    Some(Source {
+        name: name.to_string(),
+        path: path.to_string(),
+        source_reference: None,
+    })
+}
 
-    async fn get_memory_statistics(&self) -> Result<crate::memory::MemoryStatistics, RuntimeError> {
-        use crate::runtime::lua_ffi::*;
-        use std::time::SystemTime;
+/// Code loaded under a `=name` chunk name, if `CHUNK_SOURCE_TRACKING` captured it
+fn captured_chunk_source(lua: &mut Lua, chunkname: &str) -> Option<String> {
+    let top = lua.get_top();
+    let code = (lua.get_field(LUA_REGISTRYINDEX, CHUNK_SOURCES_KEY) == LUA_TTABLE
+        && lua.get_field(-1, chunkname) == LUA_TSTRING)
+        .then(|| lua.pop_string());
+    lua.set_top(top);
+    code
+}
 
-        let lua = self.lua.lock().unwrap();
-        let state = lua.state();
-
-        let kb = unsafe { lua_gc(state, LUA_GCCOUNT, 0, 0) };
-        let bytes = unsafe { lua_gc(state, LUA_GCCOUNTB, 0, 0) };
-        let pause = unsafe { lua_gc(state, LUA_GCSETPAUSE, 0, 0) };
-        let step_mul = unsafe { lua_gc(state, LUA_GCSETSTEPMUL, 0, 0) };
-        let running = unsafe { lua_gc(state, LUA_GCISRUNNING, 0, 0) };
-
-        Ok(crate::memory::MemoryStatistics {
-            total_kb: kb as f64 + (bytes as f64 / 1024.0),
-            total_bytes: (kb * 1024 + bytes) as usize,
-            gc_pause: pause,
-            gc_step_mul: step_mul,
-            gc_running: running != 0,
-            timestamp: SystemTime::now(),
-        })
+/// Returns a handle to the thread a frame id belongs to, and the frame's level
+fn frame_thread(lua: &mut Lua, frame_id: i64) -> Option<(Lua, c_int)> {
+    let (thread_id, level) = split_frame_id(frame_id);
+    if thread_id == MAIN_THREAD_ID {
+        return Some((lua.thread_view(lua.state()), level));
     }
 
-    async fn force_gc(&mut self) -> Result<(), RuntimeError> {
-        use crate::runtime::lua_ffi::*;
-
-        let lua = self.lua.lock().unwrap();
-        let state = lua.state();
+    list_threads(lua)
+        .into_iter()
+        .find(|(id, _)| *id == thread_id)
+        .map(|(_, state)| (lua.thread_view(state), level))
+}
 
-        unsafe {
-            lua_gc(state, LUA_GCCOLLECT, 0, 0);
+/// The lines with code after the current one of the function at `level` of `thread`, if it is a Lua function of `path`
+///
+/// Read from the function's `activelines`, so lines with no code of their
+/// own, such as `end` or comments, are left out.
+fn later_function_lines(thread: &mut Lua, level: c_int, path: &str) -> Vec<u32> {
+    let mut ar = unsafe { std::mem::zeroed::<lua_Debug>() };
+    if thread.get_stack(level, &mut ar) == 0 || thread.get_info("SlL", &mut ar) == 0 {
+        return Vec::new();
+    }
+    let source = unsafe { ar.source.as_ref().map(|source| CStr::from_ptr(source).to_string_lossy().into_owned()) };
+
+    // `L` pushed the lines as the keys of a table, or nil for a C function
+    let mut lines = Vec::new();
+    if source.is_some_and(|source| source_matches(&source, path)) && thread.is_table(-1) {
+        thread.push_nil();
+        while thread.next(-2) != 0 {
+            thread.lua_pop(1);
+            let line = thread.lua_tointeger(-1);
+            if thread.is_number(-1) && line > lua_Integer::from(ar.currentline) {
+                lines.push(line as u32);
+            }
         }
-        Ok(())
     }
+    thread.lua_pop(1);
+    lines.sort_unstable();
+    lines
+}
 
-    async fn start_profiling(&mut self, mode: crate::profiling::ProfilingMode) -> Result<(), RuntimeError> {
-        use crate::runtime::lua_ffi::*;
+/// Raw pointer to the Lua wrapper, handed to the program thread
+struct ProgramState(*mut Lua);
 
-        let runtime_id = self as *const _ as usize;
-        CURRENT_RUNTIME_ID.with(|id| id.set(runtime_id));
+// The pointee is kept alive by the Arc moved alongside it
+unsafe impl Send for ProgramState {}
 
-        let profiler = Arc::new(Mutex::new(crate::profiling::Profiler::new(mode)));
-        PROFILER_REGISTRY.lock().unwrap().insert(runtime_id, profiler);
+/// Number of frames kept per flight recorder snapshot
+const FLIGHT_RECORD_FRAMES: c_int = 10;
 
-        let lua = self.lua.lock().unwrap();
-        let state = lua.state();
+/// Snapshots a thread's stack into the flight recorder
+///
+/// `first_level` skips frames belonging to the debugger, such as the message
+/// handler that reports uncaught errors.
+#[cfg(feature = "static-lua")]
+unsafe fn record_flight(state: &PucHookState, L: LuaState, first_level: c_int, kind: RecordKind) {
+    let mut frames = Vec::new();
+    let mut locals = None;
 
-        // Update hook mask based on profiling mode
-        match mode {
-            crate::profiling::ProfilingMode::Sampling { interval_ms } => {
-                unsafe {
-                    lua_sethook(state, lua_hook_callback, LUA_MASKCOUNT, interval_ms as i32);
-                }
-            }
-            crate::profiling::ProfilingMode::CallTrace => {
-                unsafe {
-                    lua_sethook(state, lua_hook_callback, LUA_MASKLINE | LUA_MASKCALL | LUA_MASKRET, 0);
+    for level in first_level..first_level + FLIGHT_RECORD_FRAMES {
+        let mut ar = std::mem::zeroed::<lua_Debug>();
+        if lua_getstack(L, level, &mut ar) == 0 || lua_getinfo(L, c"nSl".as_ptr(), &mut ar) == 0 {
+            break;
+        }
+
+        let c_string = |ptr: *const i8| (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_string_lossy().to_string());
+        frames.push(FrameSummary {
+            name: c_string(ar.name).unwrap_or_else(|| "unknown".to_string()),
+            source: c_string(ar.source),
+            line: ar.currentline.max(0) as u32,
+        });
+
+        // C functions such as `error` have no locals worth showing
+        if locals.is_none() && ar.currentline >= 0 {
+            let mut captured = Vec::new();
+            let mut index = 1;
+            loop {
+                let name = lua_getlocal(L, &mut ar, index);
+                if name.is_null() {
+                    break;
                 }
-            }
-            crate::profiling::ProfilingMode::LineLevel => {
-                unsafe {
-                    lua_sethook(state, lua_hook_callback, LUA_MASKLINE | LUA_MASKCALL | LUA_MASKRET, 0);
+                let name = CStr::from_ptr(name).to_string_lossy().to_string();
+                if !name.starts_with('(') {
+                    captured.push(snapshot_local(L, name));
                 }
+                lua_settop(L, -2);
+                index += 1;
             }
-            crate::profiling::ProfilingMode::Disabled => return Ok(()),
+            locals = Some(captured);
         }
-
-        Ok(())
     }
 
-    async fn stop_profiling(&mut self) -> Result<crate::profiling::ProfileData, RuntimeError> {
-        use crate::runtime::lua_ffi::*;
-
-        let runtime_id = self as *const _ as usize;
-
-        let profiler_arc = PROFILER_REGISTRY.lock().unwrap()
-            .remove(&runtime_id)
-            .ok_or(RuntimeError::Communication("No active profiler".into()))?;
-
-        // Get the profile data from the Arc<Mutex>
-        let data = {
-            let profiler_guard = profiler_arc.lock().unwrap();
-            profiler_guard.to_profile_data()
-        };
-
-        let lua = self.lua.lock().unwrap();
-        let state = lua.state();
-
-        // Reset hook to line-only mode for stepping
-        unsafe {
-            lua_sethook(state, lua_hook_callback, LUA_MASKLINE, 0);
-        }
-
-        Ok(data)
+    let thread_id = state.thread_id_for(L);
+    if let Ok(mut recorder) = state.flight_recorder.lock() {
+        recorder.record(thread_id, kind, frames, locals.unwrap_or_default());
     }
+}
 
-    async fn get_profile_snapshot(&self) -> Result<Option<crate::profiling::ProfileData>, RuntimeError> {
-        let runtime_id = self as *const _ as usize;
+#[cfg(feature = "dynamic-lua")]
+unsafe fn record_flight(_state: &PucHookState, _L: LuaState, _first_level: c_int, _kind: RecordKind) {}
+
+/// Renders the value on top of the stack for a flight recorder snapshot
+#[cfg(feature = "static-lua")]
+unsafe fn snapshot_local(L: LuaState, name: String) -> LocalSnapshot {
+    let type_ = CStr::from_ptr(lua_typename(L, lua_type(L, -1))).to_string_lossy().to_string();
+    let value = match raw_to_value(L, -1) {
+        Value::Nil => "nil".to_string(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => format!("\"{}\"", s),
+        Value::Table { length, .. } => format!("table (len={})", length),
+        _ => format!("{}: 0x{:x}", type_, lua_topointer(L, -1) as usize),
+    };
+    LocalSnapshot { name, value, type_ }
+}
 
-        let registry = PROFILER_REGISTRY.lock().unwrap();
-        if let Some(profiler_arc) = registry.get(&runtime_id) {
-            let profiler = profiler_arc.lock().unwrap();
-            // Create snapshot without finishing
-            Ok(Some(crate::profiling::ProfileData {
-                mode: profiler.mode(),
-                duration_ms: profiler.elapsed().as_secs_f64() * 1000.0,
-                functions: profiler.functions().clone(),
-                total_samples: profiler.sample_count(),
-            }))
-        } else {
-            Ok(None)
+/// Message handler for the program chunk that records uncaught errors
+///
+/// Runs before the stack unwinds, so the failing frames can still be read.
+#[cfg(feature = "static-lua")]
+extern "C" fn record_uncaught_error(L: LuaState) -> c_int {
+    unsafe {
+        let mut len = 0;
+        let ptr = luaL_tolstring(L, 1, &mut len);
+        let message = decode_lua_string(L, std::slice::from_raw_parts(ptr as *const u8, len));
+        lua_settop(L, 1);
+
+        // Level 0 is this handler
+        if let Some(state) = HOOK_STATES.get(L) {
+            record_flight(&state, L, 1, RecordKind::Error { message });
         }
     }
+    // Return the original error value unchanged
+    1
 }
 
-impl PUCLuaRuntime {
-    /// Sets a data breakpoint in the runtime
-    pub async fn set_data_breakpoint(&mut self, data_breakpoint: DataBreakpoint) -> Result<Breakpoint, RuntimeError> {
-        // Store the data breakpoint in our watchpoint manager
-        let mut watchpoint_manager = self.watchpoint_manager.write().unwrap();
-        let breakpoints = vec![data_breakpoint];
-        watchpoint_manager.set_data_breakpoints(breakpoints);
-
-        // Install hook if not already installed
-        self.install_hook();
+/// Calls the loaded program chunk, recording an uncaught error before the stack unwinds
+#[cfg(feature = "static-lua")]
+fn run_program(lua: &mut Lua) -> Result<(), String> {
+    // Stack: chunk, handler, chunk
+    lua.push_cfunction(record_uncaught_error, 0);
+    lua.lua_pushvalue(-2);
+    let handler = lua.get_top() - 1;
+    let result = match lua.lua_pcall(0, 0, handler) {
+        LUA_OK => Ok(()),
+        _ => Err(lua.pop_string()),
+    };
+    lua.set_top(handler - 2);
+    result
+}
 
-        Ok(Breakpoint {
-            id: 1,
-            verified: true,
-            line: 0,
-            message: Some("Data breakpoint set".to_string()),
-        })
-    }
+#[cfg(feature = "dynamic-lua")]
+fn run_program(lua: &mut Lua) -> Result<(), String> {
+    lua.pcall(0, 0).map(|_| ())
+}
 
-    /// Check if any watchpoints have been triggered
-    fn check_watchpoints(&self, frame_id: i64) -> bool {
-        // Get the list of watchpoint IDs and their data types first
-        let watchpoint_info: Vec<(i64, DataType)> = {
-            let watchpoint_manager = self.watchpoint_manager.read().unwrap();
-            watchpoint_manager.get_data_breakpoints()
-                .iter()
-                .map(|wp| (wp.id, wp.data_type.clone()))
-                .collect()
-        };
-        
-        // Check each watchpoint
-        for (id, data_type) in watchpoint_info {
-            // Get the current value based on the data type
-            let current_value = match &data_type {
-                DataType::Local => {
-                    // We need the name for local variables, but we don't have it here
-                    // Let's get it from the watchpoint manager
-                    let watchpoint_manager = self.watchpoint_manager.read().unwrap();
-                    if let Some(wp) = watchpoint_manager.find_data_breakpoint(id) {
-                        self.get_local_variable_value(frame_id, &wp.name)
-                    } else {
-                        None
-                    }
-                },
-                DataType::Global => {
-                    let watchpoint_manager = self.watchpoint_manager.read().unwrap();
-                    if let Some(wp) = watchpoint_manager.find_data_breakpoint(id) {
-                        self.get_global_variable_value(&wp.name)
-                    } else {
-                        None
-                    }
-                },
-                DataType::Upvalue => {
-                    let watchpoint_manager = self.watchpoint_manager.read().unwrap();
-                    if let Some(wp) = watchpoint_manager.find_data_breakpoint(id) {
-                        self.get_upvalue_value(&wp.name)
-                    } else {
-                        None
-                    }
-                },
-                DataType::UpvalueId { function_index, upvalue_index, upvalue_id: _ } => {
-                    self.get_upvalue_id_value(*function_index, *upvalue_index)
-                },
-                DataType::TableField { table_ref, field } => {
-                    self.get_table_field_value(*table_ref, field)
-                }
-            };
-            
-            // If we got a current value, check if it has changed
-            if let Some(value) = current_value {
-                // Check if the value has changed
-                let has_changed = {
-                    let watchpoint_manager = self.watchpoint_manager.read().unwrap();
-                    watchpoint_manager.has_data_breakpoint_value_changed(id, &value)
-                };
-                
-                if has_changed {
-                    // Value has changed, update the previous value
-                    let mut watchpoint_manager = self.watchpoint_manager.write().unwrap();
-                    watchpoint_manager.update_data_breakpoint_previous_value(id, value.clone());
-                    
-                    // Check access type - for now we'll assume we're monitoring writes
-                    return true; // Trigger the watchpoint
-                }
+extern "C" fn lua_hook_callback(L: LuaState, ar: *mut lua_Debug) {
+    on_hook_event(L, ar);
+
+    // Raised out here, with nothing left to drop, as the error unwinds past this frame.
+    // Code that catches it runs into it again at its next event.
+    #[cfg(feature = "static-lua")]
+    {
+        let aborting = unsafe { HOOK_STATES.get(L) }.is_some_and(|state| state.aborting.load(Ordering::SeqCst));
+        if aborting {
+            unsafe {
+                lua_pushstring(L, RESTART_ERROR.as_ptr() as *const c_char);
+                lua_error(L);
             }
         }
-        
-        false
     }
+}
 
-    /// Sets the debugger configuration
-    pub fn set_config(&mut self, config: DebuggerConfig) {
-        self.config = config;
+fn on_hook_event(_L: LuaState, ar: *mut lua_Debug) {
+    // SAFETY: hooks are called with the thread they fire on
+    let Some(state) = (unsafe { HOOK_STATES.get(_L) }) else { return };
+    // A program being restarted must not stop again on its way out
+    if state.aborting.load(Ordering::SeqCst) {
+        return;
     }
+    let hook = &state.hook;
 
-    /// Gets the debugger configuration
-    pub fn config(&self) -> &DebuggerConfig {
-        &self.config
-    }
+    unsafe {
+        if lua_getinfo(_L, b"lS\0".as_ptr() as *const i8, ar) == 0 {
+            return;
+        }
 
-    /// Check if any watchpoints have been triggered (public method)
-    pub async fn check_data_breakpoints(&self, frame_id: i64) -> Result<bool, RuntimeError> {
-        // Call the internal check_watchpoints method
-        Ok(self.check_watchpoints(frame_id))
-    }
+        state.drain_safe_point_actions();
 
-    /// Gets the current value of a local variable
-    fn get_local_variable_value(&self, frame_id: i64, variable_name: &str) -> Option<String> {
-        let mut lua = self.lua.lock().unwrap();
-        
-        // Create debug info structure for the specified frame
-        let mut ar = unsafe { std::mem::zeroed::<lua_Debug>() };
-        if lua.get_stack(frame_id as c_int, &mut ar) != 0 {
-            // Search for the variable in local scope
-            let mut index = 1i32;
-            loop {
-                // Get local variable name
-                let name_opt = lua.get_local(&mut ar, index);
-                
-                match name_opt {
-                    Some(name) => {
-                        if name == variable_name {
-                            // Found the variable, get its value
-                            // Convert the value to a string representation
-                            let value_type = lua.type_of(-1);
-                            let value_str = match value_type {
-                                0 => "nil".to_string(), // nil
-                                1 => {
-                                    // boolean
-                                    if lua.pop_boolean() {
-                                        "true".to_string()
-                                    } else {
-                                        "false".to_string()
-                                    }
-                                },
-                                3 => {
-                                    // number
-                                    lua.pop_number().to_string()
-                                },
-                                4 => {
-                                    // string
-                                    format!("\"{}\"", lua.pop_string())
-                                },
-                                5 => {
-                                    // table
-                                    format!("table:0x{:x}", lua.topointer(-1) as usize)
-                                },
-                                6 => {
-                                    // function
-                                    format!("function:0x{:x}", lua.topointer(-1) as usize)
-                                },
-                                7 => {
-                                    // userdata
-                                    format!("userdata:0x{:x}", lua.topointer(-1) as usize)
-                                },
-                                8 => {
-                                    // thread
-                                    format!("thread:0x{:x}", lua.topointer(-1) as usize)
-                                },
-                                _ => format!("unknown:{}", lua.type_name(value_type)),
-                            };
-                            
-                            // Remove the value from stack
-                            lua.set_top(-2);
-                            return Some(value_str);
-                        }
-                        
-                        // Remove the local variable value from stack
-                        lua.set_top(-2);
-                        index += 1;
-                    }
-                    None => {
-                        // No more local variables, break the loop
-                        break;
-                    }
-                }
+        let event = (*ar).event;
+        let line = (*ar).currentline as u32;
+
+        let source = {
+            let source_ptr = (*ar).source;
+            if !source_ptr.is_null() {
+                let c_str = CStr::from_ptr(source_ptr);
+                Some(c_str.to_string_lossy().to_string())
+            } else {
+                None
             }
-        }
-        
-        None
-    }
+        };
 
-    /// Gets the current value of an upvalue
-    fn get_upvalue_value(&self, variable_name: &str) -> Option<String> {
-        let mut lua = self.lua.lock().unwrap();
-        
-        // Try to get the current function (assuming it's at the top of stack)
-        let func_index = -1;
-        let mut index = 1i32;
-        loop {
-            let name_opt = lua.get_upvalue(func_index, index);
-            
-            match name_opt {
-                Some(name) => {
-                    if name == variable_name {
-                        // Found the upvalue, get its value
-                        // Convert the value to a string representation
-                        let value_type = lua.type_of(-1);
-                        let value_str = match value_type {
-                            0 => "nil".to_string(), // nil
-                            1 => {
-                                // boolean
-                                if lua.pop_boolean() {
-                                    "true".to_string()
-                                } else {
-                                    "false".to_string()
-                                }
-                            },
-                            3 => {
-                                // number
-                                lua.pop_number().to_string()
-                            },
-                            4 => {
-                                // string
-                                format!("\"{}\"", lua.pop_string())
-                            },
-                            5 => {
-                                // table
-                                format!("table:0x{:x}", lua.topointer(-1) as usize)
-                            },
-                            6 => {
-                                // function
-                                format!("function:0x{:x}", lua.topointer(-1) as usize)
-                            },
-                            7 => {
-                                // userdata
-                                format!("userdata:0x{:x}", lua.topointer(-1) as usize)
-                            },
-                            8 => {
-                                // thread
-                                format!("thread:0x{:x}", lua.topointer(-1) as usize)
-                            },
-                            _ => format!("unknown:{}", lua.type_name(value_type)),
-                        };
-                        
-                        // Remove the value from stack
-                        lua.set_top(-2);
-                        return Some(value_str);
-                    }
-                    
-                    // Remove the upvalue from stack
-                    lua.set_top(-2);
-                    index += 1;
+        // A replay runs through the lines already recorded without stopping
+        let arrived = match state.advance_recording(_L, event, source.as_deref(), line) {
+            Progress::Replaying => {
+                if let Some(source) = source.as_deref().filter(|s| hook.current_source.lock().unwrap().as_deref() != Some(*s)) {
+                    state.note_chunk(source);
                 }
-                None => {
-                    // No more upvalues, break the loop
-                    break;
+                hook.set_location(source, line);
+                return;
+            }
+            Progress::Arrived { reason } => Some(reason),
+            Progress::Live => None,
+        };
+
+        if event != LUA_HOOKLINE && event != LUA_HOOKCOUNT {
+            state.check_frame_step(_L, ar);
+        }
+        let at_function_breakpoint = !hook.is_paused()
+            && (((event == LUA_HOOKCALL || event == LUA_HOOKTAILCALL) && state.check_function_breakpoint(_L, ar))
+                || (event == LUA_HOOKLINE && state.function_entry.swap(false, Ordering::SeqCst)));
+
+        if let Some(source) = source.as_deref() {
+            // Chunks are only looked up when execution moves to another source
+            if hook.current_source.lock().unwrap().as_deref() != Some(source) {
+                state.note_chunk(source);
+                if let Some(tracker) = &state.allocations {
+                    tracker.set_source(source);
                 }
             }
         }
-        
-        None
-    }
-
-    /// Gets the current value of an upvalue identified by its ID
-    fn get_upvalue_id_value(&self, function_index: i32, upvalue_index: i32) -> Option<String> {
-        let mut lua = self.lua.lock().unwrap();
-        
-        // Get the upvalue ID to verify it's the same upvalue
-        let _upvalue_id = lua.upvalue_id(function_index, upvalue_index) as usize;
-        
-        // Get the upvalue value
-        let name_opt = lua.get_upvalue(function_index, upvalue_index);
-        
-        if name_opt.is_some() {
-            // Convert the value to a string representation
-            let value_type = lua.type_of(-1);
-            let value_str = match value_type {
-                0 => "nil".to_string(), // nil
-                1 => {
-                    // boolean
-                    if lua.pop_boolean() {
-                        "true".to_string()
-                    } else {
-                        "false".to_string()
-                    }
-                },
-                3 => {
-                    // number
-                    lua.pop_number().to_string()
-                },
-                4 => {
-                    // string
-                    format!("\"{}\"", lua.pop_string())
-                },
-                5 => {
-                    // table
-                    format!("table:0x{:x}", lua.topointer(-1) as usize)
-                },
-                6 => {
-                    // function
-                    format!("function:0x{:x}", lua.topointer(-1) as usize)
-                },
-                7 => {
-                    // userdata
-                    format!("userdata:0x{:x}", lua.topointer(-1) as usize)
-                },
-                8 => {
-                    // thread
-                    format!("thread:0x{:x}", lua.topointer(-1) as usize)
-                },
-                _ => format!("unknown:{}", lua.type_name(value_type)),
-            };
-            
-            // Remove the value from stack
-            lua.set_top(-2);
-            Some(value_str)
+        if let Some(tracker) = &state.allocations {
+            tracker.set_line(line);
+        }
+        let at_breakpoint = source.as_deref().is_some_and(|s| state.is_active_breakpoint(s, line));
+        let data_hit = if event == LUA_HOOKLINE && !hook.is_paused() {
+            state.check_data_breakpoints(_L, ar, source.as_deref(), line)
+        } else {
+            None
+        };
+        let at_run_to = (*ar).event == LUA_HOOKLINE && hook.take_run_to(source.as_deref(), line);
+        let memory_exceeded = if event == LUA_HOOKLINE && !hook.is_paused() {
+            state.check_memory_limit(_L)
         } else {
             None
+        };
+        if event == LUA_HOOKLINE {
+            state.trace_line(_L, ar, source.as_deref(), line);
         }
-    }
+        hook.set_location(source, line);
 
-    /// Creates a watched table that intercepts field access
-    fn create_watched_table(&self, table_ref: i64, field: &str) -> Result<(), RuntimeError> {
-        let _lua = self.lua.lock().unwrap();
-        
-        // This is a simplified implementation that would need to be expanded
-        // In a full implementation, this would:
-        // 1. Create a proxy table with __index and __newindex metamethods
-        // 2. Store the original table reference
-        // 3. Set up the metamethods to call back to the watchpoint system
-        // 4. Replace the original table with the proxy
-        
-        // For now, we'll just log that a table field is being watched
-        println!("Watching table field: table_ref={}, field={}", table_ref, field);
-        
-        Ok(())
-    }
+        let step_mode = StepMode::from_u32(hook.step_mode.load(Ordering::SeqCst) as u32);
+        // Call and return events only reach here while profiling or frame stepping,
+        // count events while profiling or instruction stepping
+        let step_event = if hook.instruction_step.load(Ordering::SeqCst) { LUA_HOOKCOUNT } else { LUA_HOOKLINE };
+        let repeats_instruction_stop =
+            hook.instruction_stop_line.swap(0, Ordering::SeqCst) == line as usize && event == LUA_HOOKLINE;
+        let should_step = event == step_event && !repeats_instruction_stop && hook.should_step.load(Ordering::SeqCst);
 
-    /// Gets the current value of a table field
-    fn get_table_field_value(&self, _table_ref: i64, field: &str) -> Option<String> {
-        let mut lua = self.lua.lock().unwrap();
-        
-        // Push the table onto the stack (this is simplified - in reality we'd need the actual reference)
-        // For now, we'll assume the table is accessible somehow
-        // In a full implementation, we'd need to track table references properly
-        
-        // Push the field name
-        let field_cstr = std::ffi::CString::new(field).ok()?;
-        unsafe {
-            lua.lua_pushstring(field_cstr.as_ptr());
-            
-            // Get the table field value
-            if lua.lua_gettable(-2) == 0 { // -2 would be the table index
-            // Got the field value, convert to string representation
-            let value_type = lua.type_of(-1);
-            let value_str = match value_type {
-                0 => "nil".to_string(), // nil
-                1 => {
-                    // boolean
-                    if lua.pop_boolean() {
-                        "true".to_string()
-                    } else {
-                        "false".to_string()
-                    }
-                },
-                3 => {
-                    // number
-                    lua.pop_number().to_string()
-                },
-                4 => {
-                    // string
-                    format!("\"{}\"", lua.pop_string())
-                },
-                5 => {
-                    // table
-                    format!("table:0x{:x}", lua.topointer(-1) as usize)
-                },
-                6 => {
-                    // function
-                    format!("function:0x{:x}", lua.topointer(-1) as usize)
-                },
-                7 => {
-                    // userdata
-                    format!("userdata:0x{:x}", lua.topointer(-1) as usize)
-                },
-                8 => {
-                    // thread
-                    format!("thread:0x{:x}", lua.topointer(-1) as usize)
+        let triggered_for_step = if should_step {
+            match step_mode {
+                StepMode::In => true,
+                StepMode::Over => {
+                    let depth = (*ar).linedefined as usize;
+                    depth <= hook.step_depth.load(Ordering::SeqCst)
                 },
-                _ => format!("unknown:{}", lua.type_name(value_type)),
-            };
-            
-            // Remove the value from stack
-            lua.set_top(-2);
-            Some(value_str)
+                StepMode::Out => {
+                    let depth = (*ar).linedefined as usize;
+                    depth < hook.step_depth.load(Ordering::SeqCst)
+                }
+            }
+        } else {
+            false
+        };
+
+        let watchpoint_triggered = data_hit.is_some();
+
+        let stop_reason = if let Some(reason) = arrived {
+            Some(reason)
+        } else if triggered_for_step || watchpoint_triggered {
+            hook.step_triggered.store(true, Ordering::SeqCst);
+            Some(if watchpoint_triggered { "data breakpoint" } else { "step" })
+        } else if state.stop_on_entry.swap(false, Ordering::SeqCst) {
+            Some("entry")
+        } else if (*ar).event == LUA_HOOKLINE && !hook.is_paused() && at_breakpoint {
+            Some("breakpoint")
+        } else if at_function_breakpoint {
+            Some("function breakpoint")
+        } else if at_run_to {
+            Some("goto")
+        } else if event == LUA_HOOKLINE && !hook.is_paused() && state.is_held_thread(_L) {
+            Some("pause")
+        } else if memory_exceeded.is_some() {
+            Some("memory limit")
         } else {
-            // Failed to get table field
-            lua.set_top(-2);
             None
+        };
+
+        if let Some(reason) = stop_reason {
+            hook.cancel_run_to();
+            state.frame_step.lock().unwrap().take();
+            state.held_threads.lock().unwrap().clear();
+            if event == LUA_HOOKCOUNT {
+                hook.instruction_stop_line.store(line as usize, Ordering::SeqCst);
+            }
+            hook.paused.store(true, Ordering::SeqCst);
+            record_flight(&state, _L, 0, RecordKind::Stop { reason: reason.to_string() });
+            let thread_id = Some(state.stop_on_thread(_L));
+            state.stopped_at_data_breakpoint.store(reason == "data breakpoint", Ordering::SeqCst);
+            state.emit(match (reason, memory_exceeded.as_deref(), data_hit.as_ref()) {
+                ("memory limit", Some(description), _) => {
+                    crate::dap::Event::stopped_with_description(reason, description, thread_id)
+                }
+                ("data breakpoint", _, Some(hit)) => crate::dap::Event::data_breakpoint_stopped(hit, thread_id),
+                _ => crate::dap::Event::stopped(reason, thread_id, true),
+            });
         }
-        } // End of unsafe block
-    }
 
-    /// Gets the current value of a global variable
-    fn get_global_variable_value(&self, variable_name: &str) -> Option<String> {
-        let mut lua = self.lua.lock().unwrap();
-        
-        // Push the variable name and get the global value
-        let var_name_cstr = std::ffi::CString::new(variable_name).ok()?;
-        let result = unsafe { lua.lua_getglobal(var_name_cstr.as_ptr()) };
-        
-        if result != 0 {
-            // Got the global variable, convert to string representation
-            let value_type = lua.type_of(-1);
-            let value_str = match value_type {
-                0 => "nil".to_string(), // nil
-                1 => {
-                    // boolean
-                    if lua.pop_boolean() {
-                        "true".to_string()
-                    } else {
-                        "false".to_string()
+        // Hold a launched program here until the client resumes it
+        if state.program_running.load(Ordering::SeqCst) {
+            state.wait_while_paused();
+        }
+
+        // The wall-clock sampler's timer only asks; the stack is read here, on the program's thread
+        let ticks = state.wall_clock_ticks.swap(0, Ordering::SeqCst);
+        if ticks > 0 {
+            state.take_wall_clock_sample(_L, ticks);
+        }
+
+        // Handle profiling events
+        if event == LUA_HOOKCALL || event == LUA_HOOKRET || event == LUA_HOOKCOUNT {
+            if let Ok(profiler) = state.profiler.lock() {
+                if let Some(profiler_arc) = profiler.as_ref() {
+                    if let Ok(mut profiler) = profiler_arc.lock() {
+                        // Call events reach a wall-clock profile only for function breakpoints
+                        let wall_clock = matches!(profiler.mode(), crate::profiling::ProfilingMode::WallClock { .. });
+                        match event {
+                            _ if wall_clock => {}
+                            LUA_HOOKCALL => {
+                                // Get function information for the call event
+                                let _ = lua_getinfo(_L, b"nS\0".as_ptr() as *const i8, ar);
+                                let name = get_hook_function_name(ar);
+                                let source = get_hook_source(ar);
+                                let line = (*ar).linedefined as u32;
+                                profiler.on_call(name, source, line);
+                            }
+                            LUA_HOOKRET => {
+                                profiler.on_return();
+                            }
+                            LUA_HOOKCOUNT => {
+                                profiler.on_sample();
+                            }
+                            _ => {}
+                        }
                     }
-                },
-                3 => {
-                    // number
-                    lua.pop_number().to_string()
-                },
-                4 => {
-                    // string
-                    format!("\"{}\"", lua.pop_string())
-                },
-                5 => {
-                    // table
-                    format!("table:0x{:x}", lua.topointer(-1) as usize)
-                },
-                6 => {
-                    // function
-                    format!("function:0x{:x}", lua.topointer(-1) as usize)
-                },
-                7 => {
-                    // userdata
-                    format!("userdata:0x{:x}", lua.topointer(-1) as usize)
-                },
-                8 => {
-                    // thread
-                    format!("thread:0x{:x}", lua.topointer(-1) as usize)
-                },
-                _ => format!("unknown:{}", lua.type_name(value_type)),
-            };
-            
-            // Remove the value from stack
-            lua.set_top(-2);
-            Some(value_str)
-        } else {
-            // Failed to get global variable
-            lua.set_top(-2);
-            None
+                }
+            }
         }
     }
+}
 
-    /// Handle assignment expressions when mutation is enabled
-    async fn handle_assignment(&self, frame_id: i64, expression: &str) -> Option<Result<Value, RuntimeError>> {
-        // Parse the assignment expression (e.g., "x = 10" or "y = x + 5")
-        let parts: Vec<&str> = expression.splitn(2, '=').collect();
-        if parts.len() != 2 {
-            return None;
+// Helper functions for profiling hook
+
+/// Functions listed in the profile of a stopped event
+const STOP_HOTTEST_FUNCTIONS: usize = 5;
+
+/// Deepest stack a wall-clock sample captures
+const MAX_SAMPLED_FRAMES: c_int = 200;
+
+/// Captures a thread's call stack for the wall-clock profiler, outermost frame first
+#[cfg(feature = "static-lua")]
+unsafe fn sample_stack(L: LuaState) -> Vec<crate::profiling::SampledFrame> {
+    let mut frames = Vec::new();
+    for level in 0..MAX_SAMPLED_FRAMES {
+        let mut ar = std::mem::zeroed::<lua_Debug>();
+        if lua_getstack(L, level, &mut ar) == 0 || lua_getinfo(L, c"nS".as_ptr(), &mut ar) == 0 {
+            break;
+        }
+        frames.push(crate::profiling::SampledFrame {
+            name: get_hook_function_name(&mut ar),
+            source: get_hook_source(&mut ar),
+            line_defined: ar.linedefined.max(0) as u32,
+        });
+    }
+    frames.reverse();
+    frames
+}
+
+#[cfg(feature = "dynamic-lua")]
+unsafe fn sample_stack(_L: LuaState) -> Vec<crate::profiling::SampledFrame> {
+    Vec::new()
+}
+
+/// Ticks the wall-clock sampler until the profiler is stopped or replaced
+///
+/// Time the program spends stopped, or before it starts, is not sampled.
+fn run_wall_clock_timer(
+    state: Arc<PucHookState>,
+    profiler: Arc<Mutex<crate::profiling::Profiler>>,
+    interval: Duration,
+) {
+    let _task = internals::tasks().register(TaskRole::ProfilerTimer, TaskKind::Thread, "sampling");
+    loop {
+        thread::sleep(interval);
+        let current = state.profiler.lock().unwrap().clone();
+        if !current.is_some_and(|current| Arc::ptr_eq(&current, &profiler)) {
+            break;
+        }
+        if state.program_running.load(Ordering::SeqCst) && !state.hook.is_paused() {
+            state.wall_clock_ticks.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+unsafe fn get_hook_function_name(ar: *mut lua_Debug) -> String {
+    if !(*ar).name.is_null() {
+        if let Ok(c_str) = CStr::from_ptr((*ar).name).to_str() {
+            return c_str.to_string();
+        }
+    }
+    format!("<?:{}?>", (*ar).linedefined)
+}
+
+unsafe fn get_hook_source(ar: *mut lua_Debug) -> Option<String> {
+    if !(*ar).source.is_null() {
+        if let Ok(c_str) = CStr::from_ptr((*ar).source).to_str() {
+            return Some(c_str.to_string());
         }
+    }
+    None
+}
+
+/// Name of the child under which tables and userdata show their metatable
+const METATABLE_CHILD: &str = "[metatable]";
+
+/// A table key the Variables pane can show and assign
+#[derive(Debug, Clone, PartialEq)]
+enum FieldKey {
+    Name(String),
+    Index(i64),
+}
 
-        let variable_name = parts[0].trim();
-        let value_expression = parts[1].trim();
+impl FieldKey {
+    /// Parses a name as shown by `Display`, where `[n]` is an integer key
+    fn parse(name: &str) -> Self {
+        name.strip_prefix('[')
+            .and_then(|n| n.strip_suffix(']'))
+            .and_then(|n| n.parse().ok())
+            .map(FieldKey::Index)
+            .unwrap_or_else(|| FieldKey::Name(name.to_string()))
+    }
+}
 
-        // Try to set the variable using debug.setlocal or debug.setupvalue
-        match self.set_variable_value(frame_id, variable_name, value_expression).await {
-            Ok(value) => Some(Ok(value)),
-            Err(e) => Some(Err(e)),
+impl std::fmt::Display for FieldKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldKey::Name(name) => write!(f, "{}", name),
+            FieldKey::Index(index) => write!(f, "[{}]", index),
         }
     }
+}
 
-    /// Set a variable value using debug.setlocal or debug.setupvalue
-    async fn set_variable_value(&self, frame_id: i64, variable_name: &str, value_expression: &str) -> Result<Value, RuntimeError> {
-        // First, evaluate the value expression to get the actual value
-        let value_result = {
-            let mut lua = self.lua.lock().unwrap();
-            if let Err(_) = lua.execute(value_expression) {
-                return Err(RuntimeError::Communication(
-                    format!("Failed to evaluate value expression: {}", value_expression)
-                ));
+/// Reads a table key without converting it, which would confuse `lua_next`
+fn read_key(lua: &Lua, index: c_int) -> Option<FieldKey> {
+    match lua.type_of(index) {
+        LUA_TSTRING => {
+            let mut len = 0;
+            let ptr = lua.lua_tolstring(index, &mut len);
+            let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len) };
+            Some(FieldKey::Name(decode_lua_string(lua.state(), bytes)))
+        }
+        LUA_TNUMBER => {
+            let number = lua.lua_tonumber(index);
+            (number.fract() == 0.0).then_some(FieldKey::Index(number as i64))
+        }
+        _ => None,
+    }
+}
+
+/// Renders the value on top of the stack as the Variables pane and debug console show it
+///
+/// Returns the display value, the type name and whether it can be expanded.
+fn describe_top(lua: &mut Lua, limits: DisplayLimits, format: ValueFormat) -> (String, String, bool) {
+    let value_type = lua.type_of(-1);
+    let value = value_preview::preview_top(lua, limits.max_string_length, format);
+
+    // Userdata has nothing to show but its metatable
+    let expandable = match value_type {
+        LUA_TTABLE => true,
+        LUA_TUSERDATA => {
+            let has_metatable = lua.get_metatable(-1) != 0;
+            if has_metatable {
+                lua.lua_settop(-2);
             }
-            Self::lua_to_value(&mut lua, -1)
+            has_metatable
+        }
+        _ => false,
+    };
+
+    (value, lua.type_name(value_type).to_string(), expandable)
+}
+
+/// Describes the value on top of the stack, pinning it when it can be expanded or has bytes to read
+///
+/// Expandable values report how many children each filter of the
+/// `variables` request lists, so clients can page through them. Strings and
+/// userdata get a memory reference.
+fn top_variable(lua: &mut Lua, refs: &mut VariableRefs, name: String, format: ValueFormat) -> super::Variable {
+    let (value, type_, expandable) = describe_top(lua, refs.limits(), format);
+    let container = lua.get_top();
+    let length = if lua.is_table(container) { lua.raw_len(container) } else { 0 };
+    let named = expandable.then(|| named_count(lua, container, length) as u32);
+    let has_memory = matches!(lua.type_of(container), LUA_TSTRING | LUA_TUSERDATA | LUA_TLIGHTUSERDATA);
+    let handle = (expandable || has_memory).then(|| refs.pin_top(lua));
+
+    super::Variable {
+        name,
+        value,
+        type_,
+        variables_reference: handle.filter(|_| expandable),
+        named_variables: named,
+        indexed_variables: (length > 0).then_some(length as u32),
+        memory_reference: handle.filter(|_| has_memory).map(|handle| handle.to_string()),
+    }
+}
+
+/// The bytes behind a memory reference
+enum PinnedMemory {
+    /// A string's bytes, which Lua shares and so must not change, or a full userdata's block
+    Block { address: *mut u8, len: usize, writable: bool },
+    /// A light userdata, whose extent is unknown
+    Address(u64),
+}
+
+/// Pushes the value behind a memory reference and finds its bytes
+///
+/// The pointers stay valid while the value is pinned, until the program
+/// resumes. Leaves values on the stack; callers restore the stack top.
+fn pinned_memory(lua: &mut Lua, refs: &VariableRefs, memory_reference: &str) -> Result<PinnedMemory, RuntimeError> {
+    match memory_reference.parse().ok().and_then(|handle| refs.get(handle)) {
+        Some(VariableReference::Value { slot }) if refs.push_pinned(lua, slot) => {}
+        _ => {
+            return Err(RuntimeError::VariableNotFound(format!(
+                "Memory reference {} is no longer valid",
+                memory_reference
+            )))
+        }
+    }
+    match lua.type_of(-1) {
+        LUA_TSTRING => {
+            let mut len = 0;
+            let address = lua.lua_tolstring(-1, &mut len) as *mut u8;
+            Ok(PinnedMemory::Block { address, len, writable: false })
+        }
+        LUA_TUSERDATA => Ok(PinnedMemory::Block {
+            address: lua.touserdata(-1) as *mut u8,
+            len: lua.raw_len(-1),
+            writable: true,
+        }),
+        LUA_TLIGHTUSERDATA => Ok(PinnedMemory::Address(lua.touserdata(-1) as u64)),
+        _ => Err(RuntimeError::MemoryAccess(format!(
+            "Memory reference {} is not a string or userdata",
+            memory_reference
+        ))),
+    }
+}
+
+/// Counts the children listed by name: keys outside the array part, and the metatable
+///
+/// Keys that are neither strings nor whole numbers are not listed, so they are not counted.
+fn named_count(lua: &mut Lua, container: c_int, length: usize) -> usize {
+    let mut count = 0;
+    if lua.is_table(container) {
+        lua.push_nil();
+        while lua.next(container) != 0 {
+            let named = match lua.type_of(-2) {
+                LUA_TSTRING => true,
+                LUA_TNUMBER => {
+                    let number = lua.lua_tonumber(-2);
+                    number.fract() == 0.0 && !(1.0..=length as f64).contains(&number)
+                }
+                _ => false,
+            };
+            count += usize::from(named);
+            // Remove value, keep key for next iteration
+            lua.lua_settop(-2);
+        }
+    }
+    if lua.get_metatable(container) != 0 {
+        lua.lua_settop(-2);
+        count += 1;
+    }
+    count
+}
+
+/// Lists the children of the table or userdata on top of the stack
+///
+/// Array elements come first, then the remaining keys, up to the limit's
+/// `max_children`, and the metatable. An indexed page reads its slice of the
+/// array directly; a named page reads keys up to its end, however many there
+/// are, so every key can be reached by paging.
+fn list_children(lua: &mut Lua, refs: &mut VariableRefs, page: VariablesPage) -> Vec<super::Variable> {
+    let container = lua.get_top();
+    let length = if lua.is_table(container) { lua.raw_len(container) } else { 0 };
+    let mut variables = Vec::new();
+
+    if page.filter != Some(VariablesFilter::Named) {
+        let (first, count) = match page.filter {
+            Some(VariablesFilter::Indexed) => (page.start, page.count.unwrap_or(length)),
+            _ => (0, length),
         };
+        for index in first + 1..=length.min(first.saturating_add(count)) {
+            lua.lua_rawgeti(container, index as i64);
+            variables.push(top_variable(lua, refs, FieldKey::Index(index as i64).to_string(), page.format));
+            lua.lua_settop(-2);
+        }
+    }
 
-        // Try to find and set the variable using debug API
-        {
-            let mut lua = self.lua.lock().unwrap();
-            
-            // Create debug info structure for the specified frame
+    if page.filter != Some(VariablesFilter::Indexed) {
+        if lua.is_table(container) {
+            let max_children = refs.limits().max_children;
+            let max_named = match page.filter {
+                Some(VariablesFilter::Named) => page.start.saturating_add(page.count.unwrap_or(max_children)),
+                _ => max_children,
+            };
+            let mut named = 0;
+            lua.push_nil();
+            while named < max_named && lua.next(container) != 0 {
+                match read_key(lua, -2) {
+                    // Listed with the array elements
+                    Some(FieldKey::Index(index)) if index >= 1 && index as usize <= length => {}
+                    Some(key) => {
+                        variables.push(top_variable(lua, refs, key.to_string(), page.format));
+                        named += 1;
+                    }
+                    None => {}
+                }
+                // Remove value, keep key for next iteration
+                lua.lua_settop(-2);
+            }
+            lua.set_top(container);
+        }
+
+        if lua.get_metatable(container) != 0 {
+            variables.push(top_variable(lua, refs, METATABLE_CHILD.to_string(), page.format));
+            lua.lua_settop(-2);
+        }
+    }
+
+    match page.filter {
+        Some(VariablesFilter::Indexed) => variables,
+        // Named keys such as `[0]` look like array elements, so the listing is only cut
+        _ => page.slice(variables),
+    }
+}
+
+/// Lists the children of the scope or value behind a handle
+///
+/// Leaves values on the stack; callers restore the stack top.
+fn list_variables(lua: &mut Lua, refs: &mut VariableRefs, handle: i64, page: VariablesPage) -> Vec<super::Variable> {
+    let mut variables = Vec::new();
+
+    match refs.get(handle) {
+        Some(VariableReference::Locals { frame }) => {
             let mut ar = unsafe { std::mem::zeroed::<lua_Debug>() };
-            if lua.get_stack(frame_id as c_int, &mut ar) != 0 {
-                // Search for the variable in local scope
-                let mut index = 1i32;
-                loop {
-                // Get local variable name
-                let name_opt = lua.get_local(&mut ar, index);
-                    
-                    match name_opt {
-                        Some(name) => {
-                            if name == variable_name {
-                                // Found the variable, set its value
-                                // The value is already on top of the stack from our earlier evaluation
-                                let set_result = lua.set_local(&mut ar, index);
-                                if set_result.is_some() {
-                                    if self.config.show_modifications {
-                                        println!("Modified local variable '{}' to value {:?}", variable_name, value_result);
-                                    }
-                                    return Ok(value_result);
-                                }
-                            }
-                            
-                            // Remove the local variable value from stack
-                            lua.set_top(-2);
-                            index += 1;
-                        }
-                        None => {
-                            // No more local variables, break the loop
-                            break;
+            if let Some((mut thread, level)) = frame_thread(lua, frame) {
+                let thread_top = thread.get_top();
+                if thread.get_stack(level, &mut ar) != 0 {
+                    let mut index = 1;
+                    while let Some(name) = thread.get_local(&mut ar, index) {
+                        // Skip special variables that start with "(" like "(temporary)"
+                        if !name.starts_with('(') {
+                            variables.push(top_variable(&mut thread, refs, name, page.format));
                         }
+
+                        // Remove the value from the stack
+                        thread.lua_settop(-2);
+                        index += 1;
                     }
                 }
-                
-                // If not found in locals, search upvalues
-                // Get the function at the top of the stack (current function)
-                let func_index = -1; // Assuming function is at top of stack
-                let mut index = 1i32;
-                loop {
-                    let name_opt = lua.get_upvalue(func_index, index);
-                    
-                    match name_opt {
-                        Some(name) => {
-                            if name == variable_name {
-                                // Found the upvalue, set its value
-                                // The value is already on top of the stack
-                                let set_result = lua.set_upvalue(func_index, index);
-                                if set_result.is_some() {
-                                    if self.config.show_modifications {
-                                        println!("Modified upvalue '{}' to value {:?}", variable_name, value_result);
-                                    }
-                                    return Ok(value_result);
-                                }
-                            }
-                            
-                            // Remove the upvalue from stack
-                            lua.set_top(-2);
-                            index += 1;
-                        }
-                        None => {
-                            // No more upvalues, break the loop
-                            break;
-                        }
+                thread.set_top(thread_top);
+            }
+            variables = page.apply(variables);
+        }
+        Some(VariableReference::Upvalues { frame }) => {
+            let mut ar = unsafe { std::mem::zeroed::<lua_Debug>() };
+            if let Some((mut thread, level)) = frame_thread(lua, frame) {
+                let thread_top = thread.get_top();
+                // Push the frame's function so its upvalues can be read
+                if thread.get_stack(level, &mut ar) != 0 && thread.get_info("f", &mut ar) != 0 {
+                    let mut index = 1;
+                    while let Some(name) = thread.get_upvalue(-1, index) {
+                        variables.push(top_variable(&mut thread, refs, name, page.format));
+
+                        // Remove the value from the stack
+                        thread.lua_settop(-2);
+                        index += 1;
                     }
                 }
+                thread.set_top(thread_top);
             }
+            variables = page.apply(variables);
         }
-        
-        // If not found in locals or upvalues, treat as global variable
-        let assignment_expr = format!("{} = {}", variable_name, value_expression);
-        let mut lua = self.lua.lock().unwrap();
-        if let Err(_) = lua.execute(&assignment_expr) {
-            return Err(RuntimeError::Communication(
-                format!("Failed to execute assignment: {}", assignment_expr)
-            ));
+        Some(VariableReference::Globals) => {
+            lua.lua_pushglobaltable();
+            variables = list_children(lua, refs, page);
         }
-        
-        if self.config.show_modifications {
-            println!("Modified variable '{}' to value {:?}", variable_name, value_result);
+        Some(VariableReference::Value { slot }) if refs.push_pinned(lua, slot) => {
+            variables = list_children(lua, refs, page);
         }
+        Some(VariableReference::Value { .. }) | None => {}
+    }
+
+    variables
+}
+
+/// Evaluates `value` and pushes the result twice
+///
+/// The assignment pops the copy; the original is described afterwards.
+fn push_assigned_value(lua: &mut Lua, value: &str) -> Result<(), RuntimeError> {
+    lua.load_string(&format!("return {}", value)).map_err(|message| RuntimeError::Evaluation {
+        kind: EvalErrorKind::Syntax,
+        message,
+    })?;
+    lua.pcall(0, 1).map_err(|message| RuntimeError::Evaluation {
+        kind: EvalErrorKind::Runtime,
+        message,
+    })?;
+    lua.lua_pushvalue(-1);
+    Ok(())
+}
 
-        Ok(value_result)
+/// Assigns the result of `value` to the variable `name` in a scope or table
+///
+/// Leaves values on the stack; callers restore the stack top.
+fn assign_variable(
+    lua: &mut Lua,
+    refs: &mut VariableRefs,
+    handle: i64,
+    name: &str,
+    value: &str,
+) -> Result<super::Variable, RuntimeError> {
+    match refs.get(handle) {
+        Some(VariableReference::Value { slot }) => {
+            if name == METATABLE_CHILD {
+                return Err(RuntimeError::Communication(
+                    "Metatables cannot be replaced from the Variables pane".to_string(),
+                ));
+            }
+            if !refs.push_pinned(lua, slot) || !lua.is_table(-1) {
+                return Err(RuntimeError::VariableNotFound(format!("Variable reference {} is not a table", handle)));
+            }
+            push_assigned_value(lua, value)?;
+            match FieldKey::parse(name) {
+                FieldKey::Name(field) => lua.set_field(-3, &field),
+                FieldKey::Index(index) => lua.lua_rawseti(-3, index),
+            }
+        }
+        Some(VariableReference::Globals) => {
+            push_assigned_value(lua, value)?;
+            lua.set_global(name);
+        }
+        Some(VariableReference::Locals { frame }) => assign_in_frame(lua, frame, name, value, true)?,
+        Some(VariableReference::Upvalues { frame }) => assign_in_frame(lua, frame, name, value, false)?,
+        None => {
+            return Err(RuntimeError::VariableNotFound(format!("Variable reference {} is no longer valid", handle)))
+        }
     }
+
+    Ok(top_variable(lua, refs, name.to_string(), ValueFormat::default()))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tokio::runtime::Runtime;
+/// Assigns a local of a frame or an upvalue of its function
+///
+/// With `locals` set, locals fall back to upvalues, like name resolution in
+/// Lua itself. Leaves the assigned value on top of the stack.
+fn assign_in_frame(lua: &mut Lua, frame: i64, name: &str, value: &str, locals: bool) -> Result<(), RuntimeError> {
+    let (mut thread, level) = frame_thread(lua, frame).ok_or(RuntimeError::FrameNotFound(frame))?;
+    let mut ar = unsafe { std::mem::zeroed::<lua_Debug>() };
+    if thread.get_stack(level, &mut ar) == 0 {
+        return Err(RuntimeError::FrameNotFound(frame));
+    }
+    let thread_top = thread.get_top();
+    let on_coroutine = thread.state() != lua.state();
+
+    // The last local with the name is the one in scope
+    let mut local = None;
+    if locals {
+        let mut index = 1;
+        while let Some(local_name) = thread.get_local(&mut ar, index) {
+            thread.lua_settop(-2);
+            if local_name == name {
+                local = Some(index);
+            }
+            index += 1;
+        }
+    }
+
+    // The value is evaluated on the main thread and its copy moved over
+    if let Some(index) = local {
+        push_assigned_value(lua, value)?;
+        lua.xmove(&thread, 1);
+        thread.set_local(&mut ar, index);
+    } else {
+        let upvalue = if thread.get_info("f", &mut ar) != 0 {
+            let mut found = None;
+            let mut index = 1;
+            while let Some(upvalue_name) = thread.get_upvalue(-1, index) {
+                thread.lua_settop(-2);
+                if upvalue_name == name {
+                    found = Some(index);
+                    break;
+                }
+                index += 1;
+            }
+            found
+        } else {
+            None
+        };
+
+        let index = match upvalue {
+            Some(index) => index,
+            None => {
+                if on_coroutine {
+                    thread.set_top(thread_top);
+                }
+                return Err(RuntimeError::VariableNotFound(if locals {
+                    format!("No local or upvalue named '{}'", name)
+                } else {
+                    format!("No upvalue named '{}'", name)
+                }));
+            }
+        };
+        let function = thread.get_top();
+        if let Err(e) = push_assigned_value(lua, value) {
+            if on_coroutine {
+                thread.set_top(thread_top);
+            }
+            return Err(e);
+        }
+        lua.xmove(&thread, 1);
+        thread.set_upvalue(function, index);
+    }
+
+    if on_coroutine {
+        thread.set_top(thread_top);
+    }
+    Ok(())
+}
+
+pub struct PUCLuaRuntime {
+    lua: Arc<TrackedMutex<Lua>>,
+    breakpoints: Arc<Mutex<HashMap<String, Vec<u32>>>>,
+    detailed_breakpoints: Arc<Mutex<HashMap<String, Vec<LineBreakpoint>>>>,
+    watched_variable_values: Arc<Mutex<HashMap<String, String>>>,
+    config: DebuggerConfig,
+    step_mode: Arc<Mutex<StepMode>>,
+    /// Whether a program chunk is loaded and waiting to be started
+    program_loaded: bool,
+    /// Script loaded as the program, loaded again on restart
+    program_path: Option<String>,
+    /// How a launch request loaded the program, repeated on restart
+    launch: Option<ProgramLaunch>,
+    /// Id handed to the next metamethod breakpoint
+    next_metamethod_id: i64,
+    /// Id handed to the next line, function or exception breakpoint
+    next_breakpoint_id: i64,
+    /// Location of each line breakpoint, by id
+    line_breakpoints: HashMap<i64, (String, u32)>,
+    /// Id of the last heap snapshot taken
+    last_snapshot_id: u64,
+    /// Expandable variables handed out since the program last resumed
+    variable_refs: Arc<Mutex<VariableRefs>>,
+    /// State shared with the hook, registered under this runtime's Lua state
+    hook_state: Arc<PucHookState>,
+    /// Whether a host program runs the state on its own thread, see `hosted`
+    hosted: bool,
+    /// The Lua the state runs on
+    backend: LuaBackend,
+}
+
+/// The host's side of a state debugged with `PUCLuaRuntime::hosted`
+///
+/// The debugger touches the state only when the host's thread lets it: from
+/// the hook while Lua runs, and from `poll` and `wait_for_start` between calls
+/// into Lua. Call these on the thread that runs the state.
+pub struct HostedProgram {
+    hook_state: Arc<PucHookState>,
+}
+
+impl HostedProgram {
+    /// Applies requests that came in while the host was not running Lua
+    ///
+    /// Hosts call this regularly, such as once a frame, so the debugger gets
+    /// answers while no Lua code runs.
+    pub fn poll(&self) {
+        self.hook_state.drain_safe_point_actions();
+    }
+
+    /// Blocks until a debugger attaches and finishes configuring, applying its requests meanwhile
+    pub fn wait_for_start(&self) {
+        self.hook_state.wait_for_start();
+    }
+
+    /// Whether a debugger is attached and configured
+    pub fn is_started(&self) -> bool {
+        !self.hook_state.awaiting_start.load(Ordering::SeqCst)
+    }
+}
+
+impl PUCLuaRuntime {
+    #[cfg(feature = "static-lua")]
+    pub fn new() -> Self {
+        Self::with_lua(Lua::new_tracked())
+    }
+
+    #[cfg(feature = "dynamic-lua")]
+    pub fn new_with_library(lib: crate::runtime::lua_loader::LuaLibrary) -> Self {
+        Self::with_lua(Lua::new_with_library(lib))
+    }
+
+    fn with_lua(lua: Lua) -> Self {
+        let breakpoints = Arc::new(Mutex::new(HashMap::new()));
+        let hook_state = Arc::new(PucHookState::new(
+            lua.state(),
+            breakpoints.clone(),
+            lua.allocation_tracker(),
+        ));
+        HOOK_STATES.register(lua.state(), hook_state.clone());
+        let backend = lua.backend();
+
+        Self {
+            lua: Arc::new(TrackedMutex::new("lua", lua)),
+            breakpoints,
+            detailed_breakpoints: Arc::new(Mutex::new(HashMap::new())),
+            watched_variable_values: Arc::new(Mutex::new(HashMap::new())),
+            config: DebuggerConfig::default(),
+            step_mode: Arc::new(Mutex::new(StepMode::Over)),
+            program_loaded: false,
+            program_path: None,
+            launch: None,
+            next_metamethod_id: METAMETHOD_BREAKPOINT_BASE,
+            next_breakpoint_id: 1,
+            last_snapshot_id: 0,
+            line_breakpoints: HashMap::new(),
+            variable_refs: Arc::new(Mutex::new(VariableRefs::new())),
+            hook_state,
+            hosted: false,
+            backend,
+        }
+    }
+
+    /// Debugs a state that a host program embedding Lua creates and runs itself
+    ///
+    /// Nothing is loaded or launched: the host keeps calling into Lua on its
+    /// own thread, and the hook stops it there. Starting the program installs
+    /// the hook; the returned `HostedProgram` applies the debugger's requests
+    /// while the host is not running Lua.
+    pub fn hosted(lua: Lua) -> (Self, HostedProgram) {
+        let mut runtime = Self::with_lua(lua);
+        runtime.hosted = true;
+        // The host's thread is the program thread from the start, so requests go through the queue
+        runtime.hook_state.program_running.store(true, Ordering::SeqCst);
+        runtime.hook_state.awaiting_start.store(true, Ordering::SeqCst);
+        let program = HostedProgram {
+            hook_state: runtime.hook_state.clone(),
+        };
+        (runtime, program)
+    }
+
+    /// Tracker of the state's allocator; states the runtime did not create have none
+    fn allocation_tracker(&self) -> Result<&Arc<AllocationTracker>, RuntimeError> {
+        self.hook_state.allocations.as_ref().ok_or_else(|| {
+            RuntimeError::NotImplemented("Allocation tracking needs a state created by the debugger".to_string())
+        })
+    }
+
+    fn next_breakpoint_id(&mut self) -> i64 {
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        id
+    }
+
+    /// Sets the hook mask breakpoints need, unless profiling or a frame step needs more
+    async fn sync_hook_mask(&self) -> Result<(), RuntimeError> {
+        if self.hook_state.profiler.lock().unwrap().is_some() || self.hook_state.frame_step.lock().unwrap().is_some() {
+            return Ok(());
+        }
+        let mask = self.hook_state.base_hook_mask();
+        self.with_lua_at_safe_point(move |lua| lua.lua_sethook(lua_hook_callback, mask, 0))
+            .await
+    }
+
+    fn lua_to_value(lua: &mut Lua, index: c_int) -> Value {
+        let lua_type = lua.type_of(index);
+
+        match lua_type {
+            0 => Value::Nil,
+            1 => Value::Boolean(lua.pop_boolean()),
+            2 => Value::UserData,
+            3 => Value::Number(lua.pop_number()),
+            4 => Value::String(decode_lua_string(lua.state(), &lua.pop_bytes())),
+            5 => {
+                let len = lua.len(index);
+                Value::Table {
+                    reference: 0,
+                    length: len as u32,
+                }
+            }
+            6 => Value::Function {
+                reference: 0,
+                name: None,
+            },
+            7 => Value::UserData,
+            8 => Value::Thread,
+            _ => Value::Nil,
+        }
+    }
+
+    pub fn execute_code(&self, code: &str) -> Result<Value, String> {
+        let mut lua = self.lua.lock().unwrap();
+        lua.execute(code)?;
+        Ok(Self::lua_to_value(&mut lua, -1))
+    }
+
+    pub fn load_file(&self, filename: &str) -> Result<c_int, String> {
+        let mut lua = self.lua.lock().unwrap();
+        lua.load_file(filename)
+    }
+
+    /// Runs `action` against the Lua state once it is safe to do so
+    ///
+    /// Without a running program this happens immediately. Otherwise the
+    /// action is queued for the hook and the future resolves once it has been
+    /// applied at the next line or while the program is stopped.
+    pub async fn with_lua_at_safe_point<T, F>(&self, action: F) -> Result<T, RuntimeError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Lua) -> T + Send + 'static,
+    {
+        let receiver = {
+            let mut queue = self.hook_state.safe_point_queue.lock().unwrap();
+            if !self.hook_state.program_running.load(Ordering::SeqCst) {
+                drop(queue);
+                let mut lua = self.lua.lock().unwrap();
+                return Ok(action(&mut lua));
+            }
+
+            let (reply, receiver) = tokio::sync::oneshot::channel();
+            let lua = self.lua.clone();
+            queue.push(Box::new(move || {
+                let mut lua = lua.lock().unwrap();
+                let _ = reply.send(action(&mut lua));
+            }));
+            receiver
+        };
+        self.hook_state.wake();
+
+        receiver
+            .await
+            .map_err(|_| RuntimeError::Communication("Action was dropped before it could be applied".to_string()))
+    }
+
+    /// Loads a script as the program to debug without running it
+    ///
+    /// The program starts when the client finishes configuration, so that
+    /// breakpoints set during configuration are in place for the first line.
+    pub fn load_program(&mut self, path: &str) -> Result<(), String> {
+        let mut lua = self.lua.lock().unwrap();
+
+        #[cfg(feature = "static-lua")]
+        {
+            lua.push_cfunction(print_to_output, 0);
+            patches::patch_global(&mut lua, "print")?;
+            lua.push_cfunction(warn_to_output, 0);
+            patches::patch_global(&mut lua, "warn")?;
+            unsafe { lua_setwarnf(lua.state(), Some(runtime_warning), lua.state()) };
+        }
+
+        lua.load_string(COROUTINE_TRACKING)?;
+        patches::push_patch_api(&mut lua)?;
+        lua.pcall(1, 2)?;
+        lua.set_field(LUA_REGISTRYINDEX, RESUMER_REGISTRY_KEY);
+        lua.set_field(LUA_REGISTRYINDEX, COROUTINE_REGISTRY_KEY);
+
+        lua.load_string(CHUNK_SOURCE_TRACKING)?;
+        patches::push_patch_api(&mut lua)?;
+        lua.pcall(1, 1)?;
+        lua.set_field(LUA_REGISTRYINDEX, CHUNK_SOURCES_KEY);
+
+        #[cfg(feature = "static-lua")]
+        {
+            lua.load_string(MODULE_TRACKING)?;
+            patches::push_patch_api(&mut lua)?;
+            lua.push_cfunction(module_loaded, 0);
+            lua.pcall(2, 0)?;
+        }
+
+        lua.load_file(path)?;
+        self.hook_state.chunks.lock().unwrap().register_file(&format!("@{}", path));
+        self.hook_state.flight_recorder.lock().unwrap().clear();
+        self.hook_state.execution_trace.lock().unwrap().clear();
+        self.hook_state.modules.lock().unwrap().clear();
+        self.program_loaded = true;
+        self.program_path = Some(path.to_string());
+        Ok(())
+    }
+
+    /// Loads a program the way a launch request describes it
+    ///
+    /// Like the standalone interpreter, sets the global `arg` to the script
+    /// at 0 followed by its arguments, and replaces `package.path` and
+    /// `package.cpath` when given, a `;;` in them standing for the old path.
+    /// The launch is kept, to load the program the same way on restart.
+    pub fn load_launch(&mut self, launch: &ProgramLaunch) -> Result<(), String> {
+        {
+            let mut lua = self.lua.lock().unwrap();
+            lua.get_global("package");
+            if lua.is_table(-1) {
+                for (field, value) in [("path", &launch.lua_path), ("cpath", &launch.lua_cpath)] {
+                    if let Some(value) = value {
+                        lua.get_field(-1, field);
+                        let default = if lua.is_string(-1) { lua.pop_string() } else { String::new() };
+                        lua.lua_pop(1);
+                        lua.push_string(&super::lua_paths::expand_default(value, &default));
+                        lua.set_field(-2, field);
+                    }
+                }
+            }
+            lua.lua_pop(1);
+
+            lua.create_table(launch.args.len() as c_int, 1);
+            lua.push_string(&launch.program);
+            lua.raw_set_i(-2, 0);
+            for (i, arg) in launch.args.iter().enumerate() {
+                lua.push_string(arg);
+                lua.raw_set_i(-2, i as c_int + 1);
+            }
+            lua.set_global("arg");
+        }
+
+        self.load_program(&launch.program)?;
+        self.launch = Some(launch.clone());
+        Ok(())
+    }
+
+    /// Stops the program and loads it again in a new state, as `restart` does
+    ///
+    /// Returns the new runtime, with no breakpoints set, for the caller to put
+    /// in place of this one.
+    async fn relaunch(&mut self) -> Result<Self, RuntimeError> {
+        let path = self.program_path.clone().ok_or_else(|| {
+            RuntimeError::NotImplemented("Only a program loaded by the debugger can be restarted".to_string())
+        })?;
+
+        // Unwind the running program, also out of a stop, and wait for its thread to finish with the state
+        if self.hook_state.program_running.load(Ordering::SeqCst) {
+            self.hook_state.aborting.store(true, Ordering::SeqCst);
+            self.hook_state.held_threads.lock().unwrap().clear();
+            self.clear_pause();
+            let started = std::time::Instant::now();
+            while self.hook_state.program_running.load(Ordering::SeqCst) {
+                if started.elapsed() > RESTART_TIMEOUT {
+                    return Err(RuntimeError::Communication(
+                        "The program did not stop for the restart; it may be blocked outside Lua".to_string(),
+                    ));
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        let lua = self.lua.lock().unwrap().new_like();
+        let mut fresh = Self::with_lua(lua);
+        fresh.config = self.config.clone();
+        if let Some(sender) = self.hook_state.events.lock().unwrap().clone() {
+            fresh.set_event_sender(sender);
+        }
+        fresh.set_string_encoding(*self.hook_state.string_encoding.lock().unwrap());
+        let limit_kb = self.hook_state.memory_limit_kb.load(Ordering::SeqCst);
+        fresh.set_memory_limit((limit_kb > 0).then_some(limit_kb));
+        fresh.set_execution_trace_lines(self.hook_state.execution_trace.lock().unwrap().capacity());
+        fresh.set_display_limits(self.variable_refs.lock().unwrap().limits());
+        match &self.launch {
+            Some(launch) => fresh.load_launch(launch),
+            None => fresh.load_program(&path),
+        }
+        .map_err(RuntimeError::Communication)?;
+
+        Ok(fresh)
+    }
+
+    /// Runs the recording's question against it
+    fn recorded<T>(&self, find: impl FnOnce(&Recording) -> Result<T, String>) -> Result<T, RuntimeError> {
+        if !self.is_paused() {
+            return Err(RuntimeError::NotStopped("The program must be stopped to go back".to_string()));
+        }
+        let recording = self.hook_state.recording.lock().unwrap();
+        let recording = recording.as_ref().ok_or_else(|| {
+            RuntimeError::NotImplemented("Going back needs a recorded run, see `recordReplay`".to_string())
+        })?;
+        find(recording).map_err(RuntimeError::Communication)
+    }
+
+    /// Runs the program again from the start, replaying the recording up to the line at `target`
+    ///
+    /// The new state gets the breakpoints of this one, under the same ids,
+    /// apart from metamethod breakpoints, which patched this state's
+    /// metatables. The program stops at `target` with `reason`.
+    async fn rewind(&mut self, target: u64, reason: &'static str) -> Result<(), RuntimeError> {
+        let fresh = self.relaunch().await?;
+        *fresh.breakpoints.lock().unwrap() = self.breakpoints.lock().unwrap().clone();
+        *fresh.detailed_breakpoints.lock().unwrap() = self.detailed_breakpoints.lock().unwrap().clone();
+        *fresh.hook_state.pending_breakpoints.lock().unwrap() = self.hook_state.pending_breakpoints.lock().unwrap().clone();
+        *fresh.hook_state.function_breakpoints.lock().unwrap() = self.hook_state.function_breakpoints.lock().unwrap().clone();
+        let data_breakpoints = self.hook_state.data_breakpoints.lock().unwrap().get_data_breakpoints().into_iter().cloned().collect();
+        fresh.hook_state.data_breakpoints.lock().unwrap().set_data_breakpoints(data_breakpoints);
+
+        let mut recording = self.hook_state.recording.lock().unwrap().take();
+        if let Some(recording) = recording.as_mut() {
+            recording.rewind(target, reason);
+        }
+        *fresh.hook_state.recording.lock().unwrap() = recording;
+
+        let line_breakpoints = std::mem::take(&mut self.line_breakpoints);
+        let next_breakpoint_id = self.next_breakpoint_id;
+        *self = fresh;
+        self.line_breakpoints = line_breakpoints;
+        self.next_breakpoint_id = next_breakpoint_id;
+
+        self.hook_state.emit(crate::dap::Event::continued(Some(MAIN_THREAD_ID), true));
+        self.start_program(false).await
+    }
+
+    pub fn load_string(&self, code: &str) -> Result<c_int, String> {
+        let mut lua = self.lua.lock().unwrap();
+        lua.load_string(code)
+    }
+
+    pub fn pcall(&self, nargs: c_int, nresults: c_int) -> Result<c_int, String> {
+        let mut lua = self.lua.lock().unwrap();
+        lua.pcall(nargs, nresults)
+    }
+
+    pub fn get_global(&mut self, name: &str) -> c_int {
+        let mut lua = self.lua.lock().unwrap();
+        lua.get_global(name)
+    }
+
+    pub fn set_top(&mut self, idx: c_int) {
+        let mut lua = self.lua.lock().unwrap();
+        lua.set_top(idx);
+    }
+
+    pub fn get_top(&self) -> c_int {
+        let lua = self.lua.lock().unwrap();
+        lua.get_top()
+    }
+
+    pub fn type_of(&self, idx: c_int) -> c_int {
+        let lua = self.lua.lock().unwrap();
+        lua.type_of(idx)
+    }
+
+    pub fn is_nil(&self, idx: c_int) -> bool {
+        let lua = self.lua.lock().unwrap();
+        lua.is_nil(idx)
+    }
+
+    pub fn pop_string(&mut self) -> String {
+        let mut lua = self.lua.lock().unwrap();
+        lua.pop_string()
+    }
+
+    pub fn get_local_variable(&mut self, ar: &mut DebugInfo, n: c_int) -> Option<(String, Value)> {
+        let mut lua = self.lua.lock().unwrap();
+
+        let name = unsafe {
+            let ptr = lua.lua_getlocal(ar.ptr(), n);
+            if ptr.is_null() {
+                return None;
+            }
+            let name = CStr::from_ptr(ptr).to_string_lossy().to_string();
+            lua.set_top(-2);
+            name
+        };
+
+        let value = Self::lua_to_value(&mut lua, -1);
+        Some((name, value))
+    }
+
+    pub fn get_upvalue(&mut self, func_index: c_int, n: c_int) -> Option<(String, Value)> {
+        let mut lua = self.lua.lock().unwrap();
+
+        unsafe {
+            let ptr = lua.lua_getupvalue(func_index, n);
+            if ptr.is_null() {
+                return None;
+            }
+            let name = CStr::from_ptr(ptr).to_string_lossy().to_string();
+            let value = Self::lua_to_value(&mut lua, -1);
+            lua.set_top(-2);
+            Some((name, value))
+        }
+    }
+
+    pub fn get_stack_info(&mut self, _level: i32) -> Option<DebugInfo<'static>> {
+        let lua = self.lua.lock().unwrap();
+
+        unsafe {
+            let mut ar = DebugInfo::new();
+            let result = lua.lua_getinfo(b"SnSluf\0".as_ptr() as *const i8, ar.ptr());
+
+            if result == 0 {
+                return None;
+            }
+
+            Some(ar)
+        }
+    }
+
+    fn is_breakpoint_hit(&self, source: &str, line: u32) -> bool {
+        let breakpoints = self.breakpoints.lock().unwrap();
+        if let Some(lines) = breakpoints.get(source) {
+            lines.contains(&line)
+        } else {
+            false
+        }
+    }
+
+    pub fn is_breakpoint_hit_at_current_location(&self) -> bool {
+        let (source, line) = self.get_current_location();
+
+        if let Some(ref s) = source {
+            self.is_breakpoint_hit(s, line)
+        } else {
+            false
+        }
+    }
+
+    pub fn should_remain_paused(&self) -> bool {
+        if self.is_breakpoint_hit_at_current_location() {
+            return true;
+        }
+        self.hook_state.hook.step_triggered.load(Ordering::SeqCst)
+    }
+
+    pub fn clear_step_triggered(&self) {
+        self.hook_state.hook.step_triggered.store(false, Ordering::SeqCst);
+    }
+
+    /// Sets the hook mask breakpoints and stepping need, unless a profiler has set its own
+    pub fn install_hook(&self) {
+        if self.hook_state.profiler.lock().unwrap().is_some() {
+            return;
+        }
+        let lua = self.lua.lock().unwrap();
+        unsafe {
+            lua.lua_sethook(lua_hook_callback, self.hook_state.base_hook_mask(), 0);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.hook_state.hook.is_paused()
+    }
+
+    pub fn wait_for_pause(&self, timeout_ms: u64) -> bool {
+        let start = std::time::Instant::now();
+        while !self.is_paused() {
+            if start.elapsed() > Duration::from_millis(timeout_ms) {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    pub fn handle_pause(&self) -> bool {
+        let is_breakpoint = self.is_breakpoint_hit_at_current_location();
+        let step_triggered = self.hook_state.hook.step_triggered.load(Ordering::SeqCst);
+
+        if is_breakpoint || step_triggered {
+            // Step stops are reported by the hook itself
+            if is_breakpoint && !step_triggered {
+                let (thread_id, _) = *self.hook_state.stopped_thread.lock().unwrap();
+                self.hook_state.emit(crate::dap::Event::stopped("breakpoint", Some(thread_id), true));
+            }
+            self.clear_step_triggered();
+            true
+        } else {
+            self.clear_pause();
+            self.install_hook();
+            false
+        }
+    }
+
+    pub fn clear_pause(&self) {
+        self.hook_state.hook.clear_pause();
+        self.hook_state.wake();
+    }
+
+    pub fn set_step(&self, mode: StepMode) {
+        let hook = &self.hook_state.hook;
+        hook.should_step.store(true, Ordering::SeqCst);
+        hook.instruction_step.store(false, Ordering::SeqCst);
+        hook.step_mode.store(mode.to_u32() as usize, Ordering::SeqCst);
+
+        unsafe {
+            let lua = self.lua.lock().unwrap();
+            let mut ar = DebugInfo::new();
+            // lua_getinfo reads the frame lua_getstack fills in, so there must be one
+            if lua.lua_getstack(0, ar.ptr()) != 0 && lua.lua_getinfo(c"S".as_ptr(), ar.ptr()) != 0 {
+                let depth = ar.linedefined() as usize;
+                if depth == 0 {
+                    hook.step_depth.store(0, Ordering::SeqCst);
+                } else {
+                    hook.step_depth.store(depth + 1, Ordering::SeqCst);
+                }
+            }
+        }
+        self.install_hook();
+    }
+
+    /// Sets up a step that stops at the next instruction, using a count hook with a count of 1
+    pub fn set_instruction_step(&self, mode: StepMode) {
+        self.set_step(mode);
+        self.hook_state.hook.instruction_step.store(true, Ordering::SeqCst);
+        let lua = self.lua.lock().unwrap();
+        unsafe {
+            lua.lua_sethook(lua_hook_callback, self.hook_state.base_hook_mask() | LUA_MASKCOUNT, 1);
+        }
+    }
+
+    pub fn resume(&self) {
+        self.clear_pause();
+        self.install_hook();
+    }
+
+    /// Applies the configured evaluation safety checks
+    ///
+    /// Returns whether the expression looks like an assignment.
+    fn check_expression_safety(&self, trimmed: &str) -> Result<bool, RuntimeError> {
+        // Check if this is an assignment operation
+        // Comparisons contain `=` too
+        let is_assignment = ["==", "~=", "<=", ">="]
+            .iter()
+            .fold(trimmed.to_string(), |text, operator| text.replace(operator, ""))
+            .contains('=');
+        let is_dangerous_function = trimmed.contains("load") || trimmed.contains("dofile") || trimmed.contains("require");
+
+        // Apply safety checks based on configuration
+        match self.config.eval_safety {
+            EvalSafety::Strict => {
+                // In strict mode, prevent all assignments and dangerous functions
+                if is_assignment {
+                    return Err(RuntimeError::Evaluation {
+                        kind: EvalErrorKind::Rejected,
+                        message: "Assignment not allowed in strict evaluation mode".to_string(),
+                    });
+                }
+                if is_dangerous_function {
+                    return Err(RuntimeError::Evaluation {
+                        kind: EvalErrorKind::Rejected,
+                        message: "Dangerous function calls not allowed in strict evaluation mode".to_string(),
+                    });
+                }
+            }
+            EvalSafety::Basic => {
+                // In basic mode, warn about assignments and dangerous functions
+                if is_assignment {
+                    tracing::warn!(expression = trimmed, "assignment in evaluated expression");
+                }
+                if is_dangerous_function {
+                    tracing::warn!(expression = trimmed, "potentially dangerous function call in evaluated expression");
+                }
+            }
+            EvalSafety::None => {
+                // In none mode, allow everything but still log
+                if is_assignment {
+                    tracing::info!(expression = trimmed, "assignment in evaluated expression");
+                }
+                if is_dangerous_function {
+                    tracing::info!(expression = trimmed, "function call in evaluated expression");
+                }
+            }
+        }
+
+        Ok(is_assignment)
+    }
+
+    /// Evaluates an expression at a safe point and reads its result with `read`
+    ///
+    /// With a frame, its locals and upvalues are in scope and, with mutation
+    /// enabled, assignments are stored back into it. Without one, or once the
+    /// frame is gone, only globals are.
+    async fn evaluate_with<T, F>(&self, frame_id: Option<i64>, expression: &str, read: F) -> Result<T, RuntimeError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Lua) -> T + Send + 'static,
+    {
+        let trimmed = expression.trim();
+        // An empty expression returns nothing, which reads as nil
+        let is_assignment = !trimmed.is_empty() && self.check_expression_safety(trimmed)?;
+        if trimmed.contains('\0') {
+            return Err(RuntimeError::Evaluation {
+                kind: EvalErrorKind::Syntax,
+                message: "Expression contains a NUL byte".to_string(),
+            });
+        }
+
+        // With mutation enabled an assignment runs as a statement, and what it
+        // assigned is stored back into the frame and shown as its result
+        let write_back = frame_id.is_some() && self.config.evaluate_mutation && is_assignment;
+        let code = match trimmed.split_once('=') {
+            Some((target, _)) if write_back => format!("{}\nreturn {}", trimmed, target.trim()),
+            _ => format!("return {}", trimmed),
+        };
+        if write_back && self.config.show_modifications {
+            tracing::debug!(expression = trimmed, ?frame_id, "assigning in frame");
+        }
+
+        let limits = EvaluationLimits::from_config(&self.config);
+        let evaluation = self.with_lua_at_safe_point(move |lua| {
+            let top = lua.get_top();
+            let result = match frame_id.and_then(|frame_id| frame_thread(lua, frame_id)) {
+                Some((mut thread, level)) if frame_env::has_frame(&thread, level) => {
+                    frame_env::evaluate_in_frame(lua, &mut thread, level, &code, write_back, &limits)
+                }
+                // Without the frame, as when the program is not stopped, only globals are in scope
+                _ => lua
+                    .load_string(&code)
+                    .map_err(EvaluationError::Syntax)
+                    .and_then(|_| sandbox::call(lua, &limits)),
+            }
+            .map(|()| read(lua));
+            lua.set_top(top);
+            result
+        });
+        if frame_id.is_some() {
+            return evaluation.await?.map_err(RuntimeError::from);
+        }
+
+        // Watches should not hang if the program is stuck outside Lua code
+        let timeout = GLOBAL_EVAL_TIMEOUT + limits.time.unwrap_or_default();
+        match tokio::time::timeout(timeout, evaluation).await {
+            Ok(result) => result?.map_err(RuntimeError::from),
+            Err(_) => Err(RuntimeError::Communication(
+                "Timed out waiting for the program to reach a safe point".to_string(),
+            )),
+        }
+    }
+
+    pub fn get_current_location(&self) -> (Option<String>, u32) {
+        (self.get_current_source(), self.get_current_line())
+    }
+
+    pub fn get_current_line(&self) -> u32 {
+        self.hook_state.hook.current_line()
+    }
+
+    pub fn get_current_source(&self) -> Option<String> {
+        self.hook_state.hook.current_source()
+    }
+}
+
+impl Drop for PUCLuaRuntime {
+    fn drop(&mut self) {
+        // A program still running keeps the state alive but runs unhooked from here on
+        let lua = self.lua.lock().unwrap_or_else(|e| e.into_inner());
+        HOOK_STATES.unregister(lua.state());
+        // The host goes on running the state; Lua allows setting the hook from another thread
+        if self.hosted {
+            lua.lua_sethook(lua_hook_callback, 0, 0);
+        }
+        self.clear_pause();
+    }
+}
+
+#[async_trait]
+impl DebugRuntime for PUCLuaRuntime {
+    async fn version(&self) -> RuntimeVersion {
+        RuntimeVersion {
+            runtime: RuntimeType::PUC,
+            version: self.backend.version,
+        }
+    }
+
+    async fn hot_reload(
+        &mut self,
+        module_source: &str,
+        module_name: Option<&str>,
+    ) -> Result<crate::hot_reload::HotReloadResult, RuntimeError> {
+        #[cfg(feature = "hot-reload")]
+        {
+            use crate::hot_reload::{reload_module, HotReloadResult};
+
+            // Run the new code and move the module's state over at a safe point
+            let module_source = module_source.to_string();
+            let name = module_name.map(str::to_string);
+            let transfer = self
+                .with_lua_at_safe_point(move |lua| reload_module(lua, &module_source, name.as_deref()))
+                .await?
+                .map_err(RuntimeError::Communication)?;
+            Ok(HotReloadResult::from_transfer(module_name, transfer))
+        }
+
+        #[cfg(not(feature = "hot-reload"))]
+        {
+            let _ = (module_source, module_name);
+            Err(RuntimeError::NotImplemented("Hot reload feature not enabled".to_string()))
+        }
+    }
+
+    async fn set_breakpoint(&mut self, breakpoint: BreakpointType) -> Result<Breakpoint, RuntimeError> {
+        match breakpoint {
+            BreakpointType::Line { source, line } => {
+                let id = self.next_breakpoint_id();
+                self.breakpoints.lock().unwrap().entry(source.clone()).or_default().push(line);
+                self.line_breakpoints.insert(id, (source.clone(), line));
+
+                // Resolves once the hook can see the breakpoint
+                self.sync_hook_mask().await?;
+
+                // The hook verifies it once the file is loaded
+                let verification = self.hook_state.chunks.lock().unwrap().verify(&source, line);
+                if verification == Verification::NotLoaded {
+                    self.hook_state.pending_breakpoints.lock().unwrap().insert(id, (source, line));
+                }
+
+                Ok(Breakpoint {
+                    id,
+                    verified: verification.is_verified(),
+                    line,
+                    message: verification.message(line),
+                })
+            }
+            BreakpointType::Function { name } => {
+                let id = self.next_breakpoint_id();
+                self.hook_state.function_breakpoints.lock().unwrap().insert(id, name.trim().to_string());
+                self.sync_hook_mask().await?;
+
+                Ok(Breakpoint {
+                    id,
+                    verified: true,
+                    line: 0,
+                    message: None,
+                })
+            }
+            BreakpointType::Exception { filter } => Ok(Breakpoint {
+                id: self.next_breakpoint_id(),
+                verified: true,
+                line: 0,
+                message: Some(format!("Exception breakpoint: {}", filter)),
+            }),
+            BreakpointType::Metamethod { target, event } => {
+                let id = self.next_metamethod_id;
+                self.next_metamethod_id += 1;
+
+                let message = format!("Metamethod breakpoint: {} {}", target, event);
+                self.with_lua_at_safe_point(move |lua| install_metamethod_breakpoint(lua, id, &target, &event))
+                    .await?
+                    .map_err(RuntimeError::BreakpointInvalid)?;
+
+                Ok(Breakpoint {
+                    id,
+                    verified: true,
+                    line: 0,
+                    message: Some(message),
+                })
+            }
+        }
+    }
+
+    async fn remove_breakpoint(&mut self, id: i64) -> Result<(), RuntimeError> {
+        if id < METAMETHOD_BREAKPOINT_BASE {
+            self.hook_state.pending_breakpoints.lock().unwrap().remove(&id);
+            if self.hook_state.function_breakpoints.lock().unwrap().remove(&id).is_some() {
+                self.sync_hook_mask().await?;
+            }
+            if let Some((source, line)) = self.line_breakpoints.remove(&id) {
+                let mut breakpoints = self.breakpoints.lock().unwrap();
+                if let Some(lines) = breakpoints.get_mut(&source) {
+                    // Another breakpoint may sit on the same line
+                    if let Some(index) = lines.iter().position(|&l| l == line) {
+                        lines.remove(index);
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        self.with_lua_at_safe_point(move |lua| uninstall_metamethod_breakpoint(lua, id))
+            .await?
+            .map_err(RuntimeError::Communication)
+    }
+
+    async fn step(&mut self, mode: StepMode) -> Result<(), RuntimeError> {
+        // Variable references are only valid while the program is stopped
+        self.variable_refs.lock().unwrap().clear();
+        self.set_step(mode);
+        // Announced first, so the client cannot see the next stop before it
+        self.hook_state.emit(crate::dap::Event::continued(Some(MAIN_THREAD_ID), true));
+        self.hook_state.hook.paused.store(false, Ordering::SeqCst);
+        self.hook_state.wake();
+        Ok(())
+    }
+
+    async fn step_instruction(&mut self, mode: StepMode) -> Result<(), RuntimeError> {
+        // Count events are the profiler's samples while it runs
+        if self.hook_state.profiler.lock().unwrap().is_some() {
+            return Err(RuntimeError::Communication(
+                "Instruction stepping is unavailable while profiling".to_string(),
+            ));
+        }
+        self.variable_refs.lock().unwrap().clear();
+        self.set_instruction_step(mode);
+        self.hook_state.emit(crate::dap::Event::continued(Some(MAIN_THREAD_ID), true));
+        self.hook_state.hook.paused.store(false, Ordering::SeqCst);
+        self.hook_state.wake();
+        Ok(())
+    }
+
+    async fn continue_(&mut self) -> Result<(), RuntimeError> {
+        self.variable_refs.lock().unwrap().clear();
+        self.hook_state.held_threads.lock().unwrap().clear();
+        self.hook_state.emit(crate::dap::Event::continued(Some(MAIN_THREAD_ID), true));
+        self.resume();
+        Ok(())
+    }
+
+    /// Coroutines take turns on one OS thread, so a coroutine runs alone only
+    /// until it yields or resumes another; the program stops there.
+    async fn continue_thread(&mut self, thread_id: u64) -> Result<bool, RuntimeError> {
+        let mut others = self.hook_state.known_thread_ids();
+        if !others.remove(&thread_id) {
+            return Err(RuntimeError::ThreadNotFound(thread_id));
+        }
+        self.variable_refs.lock().unwrap().clear();
+        *self.hook_state.held_threads.lock().unwrap() = others;
+        self.hook_state.emit(crate::dap::Event::continued(Some(thread_id), false));
+        self.resume();
+        Ok(false)
+    }
+
+    async fn step_frame(&mut self, function: &str, until: FrameStepTarget) -> Result<(), RuntimeError> {
+        let expression = function.trim().to_string();
+        let address = self
+            .with_lua_at_safe_point(move |lua| function_address(lua, &expression))
+            .await?
+            .map_err(RuntimeError::Communication)?;
+        *self.hook_state.frame_step.lock().unwrap() = Some((address, until));
+
+        // Call and return events are only needed until the function shows up
+        self.with_lua_at_safe_point(|lua| {
+            lua.lua_sethook(lua_hook_callback, LUA_MASKLINE | LUA_MASKCALL | LUA_MASKRET, 0)
+        })
+        .await?;
+
+        self.variable_refs.lock().unwrap().clear();
+        self.hook_state.emit(crate::dap::Event::continued(Some(MAIN_THREAD_ID), true));
+        self.clear_pause();
+        Ok(())
+    }
+
+    async fn pause(&mut self) -> Result<(), RuntimeError> {
+        self.hook_state.frame_step.lock().unwrap().take();
+        self.hook_state.hook.cancel_run_to();
+        self.hook_state.hook.paused.store(true, Ordering::SeqCst);
+        self.hook_state.emit(crate::dap::Event::stopped("pause", Some(MAIN_THREAD_ID), true));
+        Ok(())
+    }
+
+    async fn pause_thread(&mut self, thread_id: u64) -> Result<(), RuntimeError> {
+        if !self.hook_state.known_thread_ids().contains(&thread_id) {
+            return Err(RuntimeError::ThreadNotFound(thread_id));
+        }
+        self.hook_state.held_threads.lock().unwrap().insert(thread_id);
+        Ok(())
+    }
+
+    async fn stack_trace(&mut self, thread_id: Option<u64>) -> Result<Vec<Frame>, RuntimeError> {
+        let mut frames = Vec::new();
+        let mut lua = self.lua.lock().unwrap();
+
+        // Without a thread id, show the thread the program stopped on
+        let stopped_id = self.is_paused().then(|| self.hook_state.stopped_thread.lock().unwrap().0);
+        let thread_id = thread_id.or(stopped_id).unwrap_or(MAIN_THREAD_ID);
+        let thread = match frame_thread(&mut lua, frame_id(thread_id, 0)) {
+            Some((thread, _)) => thread,
+            None => return Err(RuntimeError::ThreadNotFound(thread_id)),
+        };
+
+        // The stopped coroutine's stack continues into the threads waiting on
+        // it, so code run by `await` or a scheduler shows who resumed it
+        let mut segments = vec![(thread_id, thread)];
+        if self.config.stitch_coroutine_stacks && stopped_id == Some(thread_id) {
+            while let Some(resumer) = segments.last().and_then(|(_, thread)| {
+                let state = thread.state();
+                (state != lua.state()).then(|| resumer_of(&mut lua, state)).flatten()
+            }) {
+                let resumer_id = self.hook_state.thread_id_for(resumer);
+                if segments.iter().any(|(id, _)| *id == resumer_id) {
+                    break;
+                }
+                segments.push((resumer_id, lua.thread_view(resumer)));
+            }
+        }
+
+        for (index, (thread_id, mut thread)) in segments.into_iter().enumerate() {
+            if index > 0 {
+                // Takes the id of the resumer's `resume` call, which is never shown
+                frames.push(Frame {
+                    id: frame_id(thread_id, 0),
+                    name: format!("resumed by {}", thread_name(thread_id)),
+                    source: None,
+                    line: 0,
+                    column: 0,
+                    presentation_hint: Some("label".to_string()),
+                });
+            }
+            // The resumer's top frames are `resume` and the wrappers tracking it
+            let mut in_resume = index > 0;
+
+            // Deeper levels would collide with the frame ids of the next thread
+            for level in 0..FRAME_ID_STRIDE as c_int {
+                let mut info = unsafe { DebugInfo::new() };
+                let ar = unsafe { &mut *info.ptr() };
+                if thread.get_stack(level, ar) == 0 {
+                    break;
+                }
+                if thread.get_info("nSl", ar) == 0 {
+                    continue;
+                }
+                if in_resume {
+                    if info.what() == "C" || info.source() == Some(COROUTINE_TRACKING) {
+                        continue;
+                    }
+                    in_resume = false;
+                }
+
+                let source = frame_source(&info, &mut thread, &self.hook_state.source_references);
+                frames.push(Frame {
+                    id: frame_id(thread_id, level),
+                    name: frame_name(&info),
+                    // Lua tracks lines only; frames without a source get no position
+                    line: if source.is_some() { info.current_line().max(0) as u32 } else { 0 },
+                    column: if source.is_some() { 1 } else { 0 },
+                    source,
+                    presentation_hint: None,
+                });
+            }
+        }
+
+        Ok(frames)
+    }
+
+    async fn threads(&mut self) -> Result<Vec<super::Thread>, RuntimeError> {
+        self.with_lua_at_safe_point(|lua| {
+            list_threads(lua)
+                .into_iter()
+                .map(|(id, _)| super::Thread { id, name: thread_name(id) })
+                .collect()
+        })
+        .await
+    }
+
+    async fn scopes(&mut self, frame_id: i64) -> Result<Vec<Scope>, RuntimeError> {
+        let mut lua = self.lua.lock().unwrap();
+        let mut refs = self.variable_refs.lock().unwrap();
+
+        let has_upvalues = frame_thread(&mut lua, frame_id).is_some_and(|(thread, level)| {
+            let mut info = unsafe { DebugInfo::new() };
+            let ar = unsafe { &mut *info.ptr() };
+            thread.get_stack(level, ar) != 0 && thread.get_info("u", ar) != 0 && info.nups() > 0
+        });
+
+        let mut scopes = vec![Scope {
+            variables_reference: refs.register(VariableReference::Locals { frame: frame_id }),
+            name: "Locals".to_string(),
+            expensive: false,
+        }];
+        if has_upvalues {
+            scopes.push(Scope {
+                variables_reference: refs.register(VariableReference::Upvalues { frame: frame_id }),
+                name: "Upvalues".to_string(),
+                expensive: false,
+            });
+        }
+        scopes.push(Scope {
+            variables_reference: refs.register(VariableReference::Globals),
+            name: "Globals".to_string(),
+            expensive: true,
+        });
+        Ok(scopes)
+    }
+
+    async fn variables(
+        &mut self,
+        variables_reference: i64,
+        _filter: Option<super::VariableScope>,
+    ) -> Result<Vec<super::Variable>, RuntimeError> {
+        self.variables_page(variables_reference, VariablesPage::default()).await
+    }
+
+    async fn variables_page(
+        &mut self,
+        variables_reference: i64,
+        page: VariablesPage,
+    ) -> Result<Vec<super::Variable>, RuntimeError> {
+        let mut lua = self.lua.lock().unwrap();
+        let mut refs = self.variable_refs.lock().unwrap();
+        let top = lua.get_top();
+        let variables = list_variables(&mut lua, &mut refs, variables_reference, page);
+        lua.set_top(top);
+        Ok(variables)
+    }
+
+    async fn set_variable(
+        &mut self,
+        variables_reference: i64,
+        name: &str,
+        value: &str,
+    ) -> Result<super::Variable, RuntimeError> {
+        let refs = self.variable_refs.clone();
+        let name = name.to_string();
+        let value = value.to_string();
+
+        self.with_lua_at_safe_point(move |lua| {
+            let mut refs = refs.lock().unwrap();
+            let top = lua.get_top();
+            let result = assign_variable(lua, &mut refs, variables_reference, &name, &value);
+            lua.set_top(top);
+            result
+        })
+        .await?
+    }
+
+    async fn evaluate(&mut self, frame_id: i64, expression: &str) -> Result<Value, RuntimeError> {
+        self.evaluate_with(Some(frame_id), expression, value_on_top).await
+    }
+
+    async fn evaluate_global(&mut self, expression: &str) -> Result<Value, RuntimeError> {
+        self.evaluate_with(None, expression, value_on_top).await
+    }
+
+    async fn evaluate_variable(
+        &mut self,
+        frame_id: Option<i64>,
+        expression: &str,
+        format: ValueFormat,
+    ) -> Result<super::Variable, RuntimeError> {
+        // Tables stay pinned for drill-down until the program resumes
+        let refs = self.variable_refs.clone();
+        let name = expression.trim().to_string();
+        self.evaluate_with(frame_id, expression, move |lua| top_variable(lua, &mut refs.lock().unwrap(), name, format))
+            .await
+    }
+
+    async fn evaluate_literal(&mut self, frame_id: Option<i64>, expression: &str) -> Result<String, RuntimeError> {
+        self.evaluate_with(frame_id, expression, value_preview::literal_top).await
+    }
+
+    async fn read_memory(&mut self, memory_reference: &str, offset: i64, count: usize) -> Result<MemoryRead, RuntimeError> {
+        let refs = self.variable_refs.clone();
+        let memory_reference = memory_reference.to_string();
+        self.with_lua_at_safe_point(move |lua| {
+            let refs = refs.lock().unwrap();
+            let top = lua.get_top();
+            let read = pinned_memory(lua, &refs, &memory_reference).map(|memory| match memory {
+                PinnedMemory::Block { address, len, .. } => {
+                    let bytes = unsafe { std::slice::from_raw_parts(address as *const u8, len) };
+                    memory_references::read(bytes, address as u64, offset, count)
+                }
+                PinnedMemory::Address(address) => memory_references::unreadable(address, offset, count),
+            });
+            lua.set_top(top);
+            read
+        })
+        .await?
+    }
+
+    async fn write_memory(
+        &mut self,
+        memory_reference: &str,
+        offset: i64,
+        data: Vec<u8>,
+        allow_partial: bool,
+    ) -> Result<usize, RuntimeError> {
+        let refs = self.variable_refs.clone();
+        let memory_reference = memory_reference.to_string();
+        self.with_lua_at_safe_point(move |lua| {
+            let refs = refs.lock().unwrap();
+            let top = lua.get_top();
+            let written = pinned_memory(lua, &refs, &memory_reference).and_then(|memory| match memory {
+                PinnedMemory::Block { address, len, writable: true } => {
+                    let range = memory_references::write_range(len, offset, data.len(), allow_partial)
+                        .map_err(RuntimeError::MemoryAccess)?;
+                    let written = range.len();
+                    let block = unsafe { std::slice::from_raw_parts_mut(address, len) };
+                    block[range].copy_from_slice(&data[..written]);
+                    Ok(written)
+                }
+                PinnedMemory::Block { .. } => Err(RuntimeError::MemoryAccess(
+                    "Strings cannot be written: Lua shares them between every value with the same contents".to_string(),
+                )),
+                PinnedMemory::Address(_) => Err(RuntimeError::MemoryAccess(
+                    "Light userdata cannot be written: the size of what it points at is unknown".to_string(),
+                )),
+            });
+            lua.set_top(top);
+            written
+        })
+        .await?
+    }
+
+    async fn goto_lines(&mut self, source: &str) -> Result<Vec<u32>, RuntimeError> {
+        if !self.is_paused() {
+            return Err(RuntimeError::NotStopped("The program must be stopped to find goto targets".to_string()));
+        }
+        let mut lua = self.lua.lock().unwrap();
+        let thread_id = self.hook_state.stopped_thread.lock().unwrap().0;
+        let (mut thread, level) =
+            frame_thread(&mut lua, frame_id(thread_id, 0)).ok_or(RuntimeError::ThreadNotFound(thread_id))?;
+        Ok(later_function_lines(&mut thread, level, source))
+    }
+
+    async fn run_to_location(&mut self, source: &str, line: u32) -> Result<(), RuntimeError> {
+        // A temporary breakpoint the hook clears on the next stop
+        *self.hook_state.hook.run_to.lock().unwrap() = Some((source.to_string(), line));
+        self.continue_().await
+    }
+
+    async fn source(&mut self, source_reference: i64) -> Result<String, RuntimeError> {
+        self.hook_state
+            .source_references
+            .lock()
+            .unwrap()
+            .code(source_reference)
+            .map(str::to_string)
+            .ok_or(RuntimeError::SourceNotFound(source_reference))
+    }
+
+    async fn code_lines(&mut self, source: &str) -> Result<Vec<u32>, RuntimeError> {
+        let mut code = std::fs::read(source)?;
+        // Like luaL_loadfile, skip a first line such as #!/usr/bin/lua, keeping its newline
+        if code.starts_with(b"#") {
+            let end = code.iter().position(|&b| b == b'\n').unwrap_or(code.len());
+            code.drain(..end);
+        }
+        let path = source.to_string();
+        let dump = self
+            .with_lua_at_safe_point(move |lua| dump_chunk(lua, &path, &code))
+            .await?
+            .map_err(RuntimeError::Communication)?;
+        let lines = chunk_lines::code_lines(&dump).map_err(RuntimeError::Communication)?;
+        Ok(lines.into_iter().collect())
+    }
+
+    async fn get_exception_info(&mut self, _thread_id: u64) -> Result<ExceptionInfo, RuntimeError> {
+        Err(RuntimeError::NotImplemented("get_exception_info not implemented".to_string()))
+    }
+
+    async fn search_heap(
+        &mut self,
+        predicate: &str,
+        max_tables: usize,
+        max_results: usize,
+    ) -> Result<crate::memory::HeapSearchResult, RuntimeError> {
+        let predicate = predicate.trim().to_string();
+        self.check_expression_safety(&predicate)?;
+        self.with_lua_at_safe_point(move |lua| search_heap_on(lua, &predicate, max_tables, max_results))
+            .await?
+            .map_err(RuntimeError::Communication)
+    }
+
+    async fn flight_records(&self) -> Result<Vec<FlightRecord>, RuntimeError> {
+        Ok(self.hook_state.flight_recorder.lock().unwrap().records())
+    }
+
+    async fn execution_trace(&self) -> Result<Vec<TraceEntry>, RuntimeError> {
+        Ok(self.hook_state.execution_trace.lock().unwrap().entries())
+    }
+
+    async fn modules(&mut self) -> Result<Vec<Module>, RuntimeError> {
+        let hook_state = self.hook_state.clone();
+        self.with_lua_at_safe_point(move |lua| loaded_modules_on(lua, &hook_state.modules.lock().unwrap()))
+            .await?
+            .map_err(RuntimeError::Communication)
+    }
+
+    fn set_execution_trace_lines(&mut self, lines: usize) {
+        self.hook_state.execution_trace.lock().unwrap().set_capacity(lines);
+    }
+
+    fn set_evaluation_config(&mut self, config: &DebuggerConfig) {
+        self.config.eval_safety = config.eval_safety.clone();
+        self.config.evaluate_mutation = config.evaluate_mutation;
+        self.config.evaluation_instruction_limit = config.evaluation_instruction_limit;
+        self.config.evaluation_timeout_ms = config.evaluation_timeout_ms;
+    }
+
+    fn set_display_limits(&mut self, limits: DisplayLimits) {
+        self.variable_refs.lock().unwrap().set_limits(limits);
+    }
+
+    /// Whether the program is stopped at a data breakpoint; the hook checks them as it runs
+    async fn check_data_breakpoints(&mut self, _frame_id: i64) -> Result<bool, RuntimeError> {
+        Ok(self.is_paused() && self.hook_state.stopped_at_data_breakpoint.load(Ordering::SeqCst))
+    }
+
+    async fn set_data_breakpoints(&mut self, breakpoints: Vec<DataBreakpoint>) -> Result<Vec<DataBreakpoint>, RuntimeError> {
+        let breakpoints = breakpoints
+            .into_iter()
+            .map(|mut bp| {
+                bp.verified = matches!(bp.data_type, DataType::Local | DataType::Global | DataType::Upvalue);
+                if !bp.verified {
+                    bp.message = Some("Only locals, globals and upvalues can be watched".to_string());
+                }
+                bp
+            })
+            .collect();
+        // Files may have changed since they were last scanned
+        self.hook_state.source_lines.lock().unwrap().clear();
+        Ok(self.hook_state.data_breakpoints.lock().unwrap().set_data_breakpoints(breakpoints))
+    }
+
+    async fn get_memory_statistics(&self) -> Result<crate::memory::MemoryStatistics, RuntimeError> {
+        self.with_lua_at_safe_point(memory_statistics_on).await
+    }
+
+    async fn take_heap_snapshot(&mut self) -> Result<crate::memory::HeapSnapshot, RuntimeError> {
+        self.last_snapshot_id += 1;
+        let id = self.last_snapshot_id;
+        self.with_lua_at_safe_point(move |lua| heap_snapshot_on(lua, id))
+            .await?
+            .map_err(RuntimeError::Communication)
+    }
+
+    async fn restart(&mut self) -> Result<(), RuntimeError> {
+        // Dropping the old runtime unhooks and closes the old state
+        *self = self.relaunch().await?;
+        Ok(())
+    }
+
+    async fn step_back(&mut self) -> Result<(), RuntimeError> {
+        let target = self.recorded(|recording| recording.step_back_target(recording.position()))?;
+        self.rewind(target, "step").await
+    }
+
+    async fn reverse_continue(&mut self) -> Result<(), RuntimeError> {
+        let hook_state = self.hook_state.clone();
+        let (target, reason) = self.recorded(|recording| {
+            recording.reverse_continue_target(recording.position(), |source, line| {
+                hook_state.is_active_breakpoint(source, line)
+            })
+        })?;
+        self.rewind(target, reason).await
+    }
+
+    async fn detach(&mut self) -> Result<(), RuntimeError> {
+        // Nothing may stop the program once no one is there to resume it
+        self.breakpoints.lock().unwrap().clear();
+        self.detailed_breakpoints.lock().unwrap().clear();
+        self.line_breakpoints.clear();
+        self.hook_state.data_breakpoints.lock().unwrap().clear_all_data_breakpoints();
+        self.hook_state.function_breakpoints.lock().unwrap().clear();
+        self.hook_state.pending_breakpoints.lock().unwrap().clear();
+        *self.hook_state.frame_step.lock().unwrap() = None;
+        *self.hook_state.hook.run_to.lock().unwrap() = None;
+        self.hook_state.hook.should_step.store(false, Ordering::SeqCst);
+        self.hook_state.profiler.lock().unwrap().take();
+        self.hook_state.stop_on_entry.store(false, Ordering::SeqCst);
+        self.hook_state.memory_limit_kb.store(0, Ordering::SeqCst);
+
+        // Lua's own warning function starts out off
+        self.hook_state.warnings_enabled.store(false, Ordering::SeqCst);
+        self.hook_state.detached.store(true, Ordering::SeqCst);
+
+        let restored = self
+            .with_lua_at_safe_point(|lua| {
+                lua.lua_sethook(lua_hook_callback, 0, 0);
+                let restored = patches::restore_all(lua);
+                for key in [COROUTINE_REGISTRY_KEY, RESUMER_REGISTRY_KEY, CHUNK_SOURCES_KEY, METAMETHOD_REGISTRY_KEY] {
+                    lua.push_nil();
+                    lua.set_field(LUA_REGISTRYINDEX, key);
+                }
+                restored
+            })
+            .await;
+        self.clear_pause();
+        if self.hosted {
+            self.hook_state.awaiting_start.store(true, Ordering::SeqCst);
+        }
+
+        restored?.map_err(RuntimeError::Communication)?;
+        Ok(())
+    }
+
+    async fn force_gc(&mut self) -> Result<(), RuntimeError> {
+        self.with_lua_at_safe_point(|lua| {
+            lua.gc(LUA_GCCOLLECT, 0, 0);
+        })
+        .await
+    }
+
+    async fn gc_step(&mut self, kb: u32) -> Result<bool, RuntimeError> {
+        self.with_lua_at_safe_point(move |lua| lua.gc(LUA_GCSTEP, kb as c_long, 0) != 0)
+            .await
+    }
+
+    async fn tune_gc(
+        &mut self,
+        pause: Option<i32>,
+        step_mul: Option<i32>,
+    ) -> Result<crate::memory::MemoryStatistics, RuntimeError> {
+        self.with_lua_at_safe_point(move |lua| {
+            if let Some(pause) = pause {
+                lua.gc(LUA_GCSETPAUSE, pause as c_long, 0);
+            }
+            if let Some(step_mul) = step_mul {
+                lua.gc(LUA_GCSETSTEPMUL, step_mul as c_long, 0);
+            }
+            memory_statistics_on(lua)
+        })
+        .await
+    }
+
+    async fn start_profiling(&mut self, mode: crate::profiling::ProfilingMode) -> Result<(), RuntimeError> {
+        use crate::runtime::lua_ffi::*;
+
+        let profiler = Arc::new(Mutex::new(crate::profiling::Profiler::new(mode)));
+        *self.hook_state.profiler.lock().unwrap() = Some(profiler.clone());
+        self.hook_state.wall_clock_ticks.store(0, Ordering::SeqCst);
+        *self.hook_state.profile_at_last_stop.lock().unwrap() = None;
+
+        // Update hook mask based on profiling mode
+        let (mask, count) = match mode {
+            crate::profiling::ProfilingMode::Sampling { interval_ms } => (LUA_MASKCOUNT, interval_ms as i32),
+            crate::profiling::ProfilingMode::WallClock { interval_ms } => {
+                // Samples are taken on the line events breakpoints already need
+                let hook_state = self.hook_state.clone();
+                let interval = Duration::from_millis(interval_ms.max(1) as u64);
+                thread::spawn(move || run_wall_clock_timer(hook_state, profiler, interval));
+                (self.hook_state.base_hook_mask(), 0)
+            }
+            crate::profiling::ProfilingMode::CallTrace => (LUA_MASKLINE | LUA_MASKCALL | LUA_MASKRET, 0),
+            crate::profiling::ProfilingMode::LineLevel => (LUA_MASKLINE | LUA_MASKCALL | LUA_MASKRET, 0),
+            crate::profiling::ProfilingMode::Disabled => return Ok(()),
+        };
+
+        self.with_lua_at_safe_point(move |lua| lua.lua_sethook(lua_hook_callback, mask, count))
+            .await
+    }
+
+    async fn stop_profiling(&mut self) -> Result<crate::profiling::ProfileData, RuntimeError> {
+        let profiler_arc = self.hook_state.profiler.lock().unwrap()
+            .take()
+            .ok_or(RuntimeError::Communication("No active profiler".into()))?;
+
+        // Get the profile data from the Arc<Mutex>
+        let data = {
+            let profiler_guard = profiler_arc.lock().unwrap();
+            profiler_guard.to_profile_data()
+        };
+
+        // Reset hook to line-only mode for stepping
+        self.sync_hook_mask().await?;
+
+        Ok(data)
+    }
+
+    async fn launch_program(&mut self, launch: &ProgramLaunch) -> Result<(), RuntimeError> {
+        self.load_launch(launch).map_err(RuntimeError::Communication)
+    }
+
+    fn has_program(&self) -> bool {
+        self.program_loaded
+    }
+
+    fn lua_backend(&self) -> Option<LuaBackend> {
+        Some(self.backend.clone())
+    }
+
+    async fn start_program(&mut self, stop_on_entry: bool) -> Result<(), RuntimeError> {
+        if self.hosted {
+            // The host is already running; it stops at the next line it runs
+            self.hook_state.stop_on_entry.store(stop_on_entry, Ordering::SeqCst);
+            self.hook_state.detached.store(false, Ordering::SeqCst);
+            if self.hook_state.profiler.lock().unwrap().is_none() {
+                self.install_hook();
+            }
+            self.hook_state.awaiting_start.store(false, Ordering::SeqCst);
+            self.hook_state.wake();
+            return Ok(());
+        }
+        if !std::mem::take(&mut self.program_loaded) {
+            return Ok(());
+        }
+
+        let hook_state = self.hook_state.clone();
+        if hook_state.recording.lock().unwrap().is_some() {
+            install_input_logging(&mut self.lua.lock().unwrap()).map_err(RuntimeError::Communication)?;
+        }
+        hook_state.stop_on_entry.store(stop_on_entry, Ordering::SeqCst);
+        // A profiler started before the program keeps its own mask
+        if hook_state.profiler.lock().unwrap().is_none() {
+            self.install_hook();
+        }
+        hook_state.program_running.store(true, Ordering::SeqCst);
+
+        let keep_alive = self.lua.clone();
+        let state = ProgramState(&mut *self.lua.lock().unwrap() as *mut Lua);
+        thread::spawn(move || {
+            let _keep_alive = keep_alive;
+            let _task = internals::tasks().register(TaskRole::Program, TaskKind::Thread, "running");
+            let state = state;
+            // The mutex is not held while the program runs, so requests can
+            // inspect the state while the hook has the program stopped
+            let result = unsafe { run_program(&mut *state.0) };
+            {
+                // Flip under the queue lock so no action can be queued after the final drain
+                let _queue = hook_state.safe_point_queue.lock().unwrap();
+                hook_state.program_running.store(false, Ordering::SeqCst);
+            }
+            hook_state.drain_safe_point_actions();
+
+            // A restarted program goes on in a new state; the client is not told this one ended
+            if hook_state.aborting.load(Ordering::SeqCst) {
+                return;
+            }
+            if let Err(message) = &result {
+                hook_state.emit(crate::dap::Event::output("stderr", &format!("{}\n", message)));
+                let entries = hook_state.execution_trace.lock().unwrap().entries();
+                if let Some(trace) = execution_trace::describe(&entries) {
+                    hook_state.emit(crate::dap::Event::output("stderr", &trace));
+                }
+            }
+            hook_state.emit(crate::dap::Event::exited(if result.is_ok() { 0 } else { 1 }));
+            hook_state.emit(crate::dap::Event::terminated());
+        });
+
+        Ok(())
+    }
+
+    fn set_event_sender(&mut self, sender: crate::dap::EventSender) {
+        *self.hook_state.events.lock().unwrap() = Some(sender);
+    }
+
+    fn set_string_encoding(&mut self, encoding: Option<&'static encoding_rs::Encoding>) {
+        *self.hook_state.string_encoding.lock().unwrap() = encoding;
+    }
+
+    fn set_memory_limit(&mut self, limit_kb: Option<u64>) {
+        self.hook_state.memory_limit_kb.store(limit_kb.unwrap_or(0), Ordering::SeqCst);
+        self.hook_state.over_memory_limit.store(false, Ordering::SeqCst);
+    }
+
+    fn set_stitch_coroutine_stacks(&mut self, enabled: bool) {
+        self.config.stitch_coroutine_stacks = enabled;
+    }
+
+    /// Takes effect when the program starts; a run already recorded is kept while enabled
+    fn set_record_replay(&mut self, enabled: bool) {
+        let mut recording = self.hook_state.recording.lock().unwrap();
+        match (enabled, recording.is_some()) {
+            (true, false) => *recording = Some(Recording::default()),
+            (false, true) => *recording = None,
+            _ => {}
+        }
+    }
+
+    fn lock_states(&self) -> Vec<LockState> {
+        vec![self.lua.state()]
+    }
+
+    async fn set_allocation_tracking(&mut self, enabled: bool) -> Result<(), RuntimeError> {
+        let tracker = self.allocation_tracker()?;
+        tracker.set_enabled(enabled);
+        Ok(())
+    }
+
+    async fn allocation_profile(
+        &mut self,
+        limit: usize,
+        reset: bool,
+    ) -> Result<crate::memory::AllocationProfile, RuntimeError> {
+        let tracker = self.allocation_tracker()?;
+        let profile = tracker.profile(limit);
+        if reset {
+            tracker.reset();
+        }
+        Ok(profile)
+    }
+
+    async fn get_profile_snapshot(&self) -> Result<Option<crate::profiling::ProfileData>, RuntimeError> {
+        let profiler = self.hook_state.profiler.lock().unwrap();
+        if let Some(profiler_arc) = profiler.as_ref() {
+            let profiler = profiler_arc.lock().unwrap();
+            // Create snapshot without finishing
+            Ok(Some(profiler.to_profile_data()))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl PUCLuaRuntime {
+    /// Sets the debugger configuration
+    pub fn set_config(&mut self, config: DebuggerConfig) {
+        if let Some(label) = &config.source_encoding {
+            match crate::debug::encoding::lookup(label) {
+                Ok(encoding) => DebugRuntime::set_string_encoding(self, encoding),
+                Err(e) => tracing::warn!("{}", e),
+            }
+        }
+        DebugRuntime::set_memory_limit(self, config.memory_limit_kb);
+        DebugRuntime::set_record_replay(self, config.record_replay);
+        DebugRuntime::set_execution_trace_lines(self, config.execution_trace_lines);
+        DebugRuntime::set_display_limits(self, config.display_limits());
+        self.config = config;
+    }
+
+    /// Gets the debugger configuration
+    pub fn config(&self) -> &DebuggerConfig {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        Runtime::new().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn test_runtime_creation() {
+        let runtime = PUCLuaRuntime::new();
+        assert!(!runtime.is_paused());
+    }
+
+    #[test]
+    fn test_runtimes_do_not_share_pause_state() {
+        block_on(async {
+            let mut first = PUCLuaRuntime::new();
+            let second = PUCLuaRuntime::new();
+
+            first.pause().await.unwrap();
+            assert!(first.is_paused());
+            assert!(!second.is_paused());
+        });
+    }
+
+    #[test]
+    fn test_breakpoint_storage() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+
+            runtime.set_breakpoint(BreakpointType::Line {
+                source: "test.lua".to_string(),
+                line: 10,
+            }).await.unwrap();
+
+            let breakpoints = runtime.breakpoints.lock().unwrap();
+            assert!(breakpoints.contains_key("test.lua"));
+            assert!(breakpoints["test.lua"].contains(&10));
+        });
+    }
+
+    #[test]
+    fn test_is_breakpoint_hit() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+
+            runtime.set_breakpoint(BreakpointType::Line {
+                source: "test.lua".to_string(),
+                line: 10,
+            }).await.unwrap();
+            runtime.set_breakpoint(BreakpointType::Line {
+                source: "test.lua".to_string(),
+                line: 20,
+            }).await.unwrap();
+            runtime.set_breakpoint(BreakpointType::Line {
+                source: "other.lua".to_string(),
+                line: 5,
+            }).await.unwrap();
+
+            assert!(runtime.is_breakpoint_hit("test.lua", 10));
+            assert!(runtime.is_breakpoint_hit("test.lua", 20));
+            assert!(!runtime.is_breakpoint_hit("test.lua", 15));
+            assert!(!runtime.is_breakpoint_hit("other.lua", 10));
+            assert!(runtime.is_breakpoint_hit("other.lua", 5));
+        });
+    }
+
+    #[test]
+    fn test_step_mode_conversion() {
+        assert_eq!(StepMode::Over.to_u32(), 0);
+        assert_eq!(StepMode::In.to_u32(), 1);
+        assert_eq!(StepMode::Out.to_u32(), 2);
+
+        assert_eq!(StepMode::from_u32(0), StepMode::Over);
+        assert_eq!(StepMode::from_u32(1), StepMode::In);
+        assert_eq!(StepMode::from_u32(2), StepMode::Out);
+        assert_eq!(StepMode::from_u32(99), StepMode::Out);
+    }
+
+    #[test]
+    fn test_execute_simple_code() {
+        let runtime = PUCLuaRuntime::new();
+
+        let result = runtime.execute_code("1 + 2");
+        assert!(result.is_ok());
+        let value = result.unwrap();
+        match value {
+            Value::Number(n) => assert_eq!(n, 3.0),
+            _ => panic!("Expected Number, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_execute_string_concatenation() {
+        let runtime = PUCLuaRuntime::new();
+
+        let result = runtime.execute_code("'hello' .. ' ' .. 'world'");
+        assert!(result.is_ok());
+        let value = result.unwrap();
+        match value {
+            Value::String(s) => assert_eq!(s, "hello world"),
+            _ => panic!("Expected String, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_execute_boolean() {
+        let runtime = PUCLuaRuntime::new();
+
+        let result = runtime.execute_code("true");
+        assert!(result.is_ok());
+        let value = result.unwrap();
+        match value {
+            Value::Boolean(b) => assert!(b),
+            _ => panic!("Expected Boolean, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_execute_nil() {
+        let runtime = PUCLuaRuntime::new();
+
+        let result = runtime.execute_code("nil");
+        assert!(result.is_ok());
+        let value = result.unwrap();
+        match value {
+            Value::Nil => (),
+            _ => panic!("Expected Nil, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_install_hook() {
+        let runtime = PUCLuaRuntime::new();
+        runtime.install_hook();
+    }
+
+    #[test]
+    fn test_clear_pause() {
+        let runtime = PUCLuaRuntime::new();
+        runtime.clear_pause();
+        assert!(!runtime.is_paused());
+    }
+
+    #[test]
+    fn test_set_step() {
+        let runtime = PUCLuaRuntime::new();
+        runtime.set_step(StepMode::In);
+        runtime.set_step(StepMode::Over);
+        runtime.set_step(StepMode::Out);
+    }
+
+    #[test]
+    fn test_lua_state_operations() {
+        let mut runtime = PUCLuaRuntime::new();
+        let top = runtime.get_top();
+        assert_eq!(top, 0);
+
+        runtime.set_top(5);
+        let top = runtime.get_top();
+        assert_eq!(top, 5);
+
+        runtime.set_top(0);
+        let top = runtime.get_top();
+        assert_eq!(top, 0);
+    }
+
+    #[test]
+    fn test_type_of() {
+        let mut runtime = PUCLuaRuntime::new();
+
+        runtime.execute_code("nil").ok();
+        assert_eq!(runtime.type_of(-1), 0);
+
+        runtime.execute_code("true").ok();
+        assert_eq!(runtime.type_of(-1), 1);
+
+        runtime.execute_code("123").ok();
+        assert_eq!(runtime.type_of(-1), 3);
+
+        runtime.execute_code("'hello'").ok();
+        assert_eq!(runtime.type_of(-1), 4);
+
+        runtime.set_top(0);
+    }
+
+    #[test]
+    fn test_get_global() {
+        let mut runtime = PUCLuaRuntime::new();
+
+        runtime.execute_code("myvar = 42").ok();
+        let count = runtime.get_global("myvar");
+        assert_eq!(count, 1);
+
+        let value = runtime.execute_code("myvar").ok();
+        match value {
+            Some(Value::Number(n)) => assert_eq!(n, 42.0),
+            _ => panic!("Expected Number"),
+        }
+
+        runtime.set_top(0);
+    }
+
+    #[test]
+    fn test_load_string() {
+        let runtime = PUCLuaRuntime::new();
+
+        let result = runtime.load_string("x = 10");
+        assert!(result.is_ok());
+
+        let result = runtime.pcall(0, 0);
+        assert!(result.is_ok());
+
+        let value = runtime.execute_code("x").ok();
+        match value {
+            Some(Value::Number(n)) => assert_eq!(n, 10.0),
+            _ => panic!("Expected Number"),
+        }
+    }
+
+    #[test]
+    fn test_evaluate_global_when_idle() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.execute_code("counter = 41").unwrap();
+
+            match runtime.evaluate_global("counter + 1").await {
+                Ok(Value::Number(n)) => assert_eq!(n, 42.0),
+                other => panic!("Expected Number, got {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn test_runaway_watch_is_stopped() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_config(DebuggerConfig {
+                evaluation_instruction_limit: 0,
+                evaluation_timeout_ms: 50,
+                ..DebuggerConfig::default()
+            });
+            match runtime.evaluate_global("(function() while true do end end)()").await {
+                Err(RuntimeError::EvaluationTimeout(message)) => assert!(message.contains("too long"), "{}", message),
+                other => panic!("Expected a timeout, got {:?}", other),
+            }
+            match runtime.evaluate_global("1 + 1").await {
+                Ok(Value::Number(n)) => assert_eq!(n, 2.0),
+                other => panic!("Expected Number, got {:?}", other),
+            }
+        });
+    }
+
+    #[test]
+    fn test_evaluate_global_reports_errors() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            assert!(runtime.evaluate_global("nil + 1").await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_launched_program_stops_on_entry() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("entry.lua");
+            std::fs::write(&script, "local x = 1\nlocal y = 2\n").unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.start_program(true).await.unwrap();
+
+            let stopped = events.recv().await.unwrap();
+            assert_eq!(stopped.event, "stopped");
+            assert_eq!(stopped.body.unwrap()["reason"], "entry");
+
+            runtime.continue_().await.unwrap();
+            loop {
+                if events.recv().await.unwrap().event == "terminated" {
+                    break;
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_safe_point_action_applied_while_stopped() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("safe_point.lua");
+            std::fs::write(&script, "local x = 1\n").unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.start_program(true).await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "stopped");
+
+            // The program thread is parked in the hook, which applies the action
+            let top = runtime.with_lua_at_safe_point(|lua| lua.get_top()).await.unwrap();
+            assert!(top >= 0);
+
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_step_frame_stops_on_call_and_return() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("frames.lua");
+            std::fs::write(&script, "function update(n)\n  tick = n\nend\nupdate(1)\nlocal b = 2\nupdate(2)\n").unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime
+                .set_breakpoint(BreakpointType::Line {
+                    source: script.to_str().unwrap().to_string(),
+                    line: 4,
+                })
+                .await
+                .unwrap();
+            runtime.start_program(false).await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "stopped");
+
+            async fn next_stop(events: &mut crate::dap::EventReceiver) -> String {
+                loop {
+                    let event = events.recv().await.unwrap();
+                    if event.event == "stopped" {
+                        return event.body.unwrap()["reason"].as_str().unwrap().to_string();
+                    }
+                }
+            }
+
+            runtime.step_frame("update", FrameStepTarget::NextCall).await.unwrap();
+            assert_eq!(next_stop(&mut events).await, "step");
+            assert_eq!(runtime.get_current_line(), 2);
+
+            runtime.step_frame("update", FrameStepTarget::Return).await.unwrap();
+            assert_eq!(next_stop(&mut events).await, "step");
+            assert_eq!(runtime.get_current_line(), 5);
+            assert_eq!(runtime.evaluate_global("tick").await.unwrap(), Value::Number(1.0));
+
+            assert!(runtime.step_frame("no_such_function", FrameStepTarget::NextCall).await.is_err());
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_instruction_step_stops_within_a_line() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("instructions.lua");
+            std::fs::write(
+                &script,
+                "local a = 1\nlocal s = tostring(a) .. '!'\nlocal t = tostring(s) .. '?'\nlocal b = 2\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.start_program(true).await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "stopped");
+
+            async fn next_stop(events: &mut crate::dap::EventReceiver) -> String {
+                loop {
+                    let event = events.recv().await.unwrap();
+                    if event.event == "stopped" {
+                        return event.body.unwrap()["reason"].as_str().unwrap().to_string();
+                    }
+                }
+            }
+
+            while runtime.get_current_line() != 2 {
+                runtime.step_instruction(StepMode::In).await.unwrap();
+                assert_eq!(next_stop(&mut events).await, "step");
+            }
+            let mut stops_on_line = 1;
+            while runtime.get_current_line() == 2 {
+                runtime.step_instruction(StepMode::In).await.unwrap();
+                next_stop(&mut events).await;
+                stops_on_line += 1;
+            }
+            assert!(stops_on_line >= 3, "only {} stops on line 2", stops_on_line);
+
+            // Stopped on line 3's first instruction, a line step goes on to line 4
+            assert_eq!(runtime.get_current_line(), 3);
+            runtime.step(StepMode::In).await.unwrap();
+            next_stop(&mut events).await;
+            assert_eq!(runtime.get_current_line(), 4);
+
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_run_to_location_stops_once() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("cursor.lua");
+            std::fs::write(&script, "local a = 1\nlocal b = 2\nfor i = 1, 3 do\n  a = a + i\nend\n").unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.start_program(true).await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "stopped");
+
+            runtime.run_to_location(script.to_str().unwrap(), 4).await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "continued");
+            let stopped = events.recv().await.unwrap();
+            assert_eq!(stopped.body.unwrap()["reason"], "goto");
+            assert_eq!(runtime.get_current_line(), 4);
+
+            // The loop body runs again without stopping
+            runtime.continue_().await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "continued");
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_goto_lines_are_later_lines_of_the_stopped_function() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("targets.lua");
+            std::fs::write(
+                &script,
+                "local function add(a, b)\n  local sum = a + b\n  -- done\n  return sum\nend\nprint(add(1, 2))\n",
+            )
+            .unwrap();
+            let path = script.to_str().unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(path).unwrap();
+            assert!(matches!(runtime.goto_lines(path).await, Err(RuntimeError::NotStopped(_))));
+            runtime.set_breakpoint(BreakpointType::Line { source: path.to_string(), line: 2 }).await.unwrap();
+            runtime.start_program(false).await.unwrap();
+            while events.recv().await.unwrap().event != "stopped" {}
+
+            assert_eq!(runtime.goto_lines(path).await.unwrap(), vec![4, 5]);
+            assert!(runtime.goto_lines("/elsewhere/other.lua").await.unwrap().is_empty());
+
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_recorded_run_steps_back_with_the_same_inputs() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("rewind.lua");
+            std::fs::write(&script, "seed = math.random(1, 1000000)\ncount = 0\nfor i = 1, 3 do\n  count = count + 1\nend\n").unwrap();
+            let path = script.to_str().unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.set_record_replay(true);
+            runtime.load_program(path).unwrap();
+            let breakpoint = runtime
+                .set_breakpoint(BreakpointType::Line { source: path.to_string(), line: 4 })
+                .await
+                .unwrap();
+            runtime.start_program(false).await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "stopped");
+
+            runtime.continue_().await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "continued");
+            assert_eq!(events.recv().await.unwrap().event, "stopped");
+            assert_eq!(runtime.evaluate_global("count").await.unwrap(), Value::Number(1.0));
+            let seed = runtime.evaluate_global("seed").await.unwrap();
+
+            runtime.reverse_continue().await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "continued");
+            let stopped = events.recv().await.unwrap();
+            assert_eq!(stopped.body.unwrap()["reason"], "breakpoint");
+            assert_eq!(runtime.evaluate_global("count").await.unwrap(), Value::Number(0.0));
+            assert_eq!(runtime.evaluate_global("seed").await.unwrap(), seed);
+
+            runtime.step_back().await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "continued");
+            let stopped = events.recv().await.unwrap();
+            assert_eq!(stopped.body.unwrap()["reason"], "step");
+            assert_eq!(runtime.get_current_line(), 3);
+
+            runtime.remove_breakpoint(breakpoint.id).await.unwrap();
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_step_back_needs_a_recording() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.hook_state.hook.paused.store(true, Ordering::SeqCst);
+            assert!(matches!(runtime.step_back().await, Err(RuntimeError::NotImplemented(_))));
+        });
+    }
+
+    #[test]
+    fn test_stack_trace_walks_every_level() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("nested.lua");
+            std::fs::write(&script, "local function inner()\n  return 1\nend\nlocal function outer()\n  return inner() + 1\nend\nouter()\n").unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime
+                .set_breakpoint(BreakpointType::Line {
+                    source: script.to_str().unwrap().to_string(),
+                    line: 2,
+                })
+                .await
+                .unwrap();
+            runtime.start_program(false).await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "stopped");
+
+            let frames = runtime.stack_trace(None).await.unwrap();
+            let names: Vec<&str> = frames.iter().map(|f| f.name.as_str()).collect();
+            assert_eq!(names, ["inner", "outer", "main chunk"]);
+            assert_eq!(frames[0].line, 2);
+            assert_eq!(frames[1].line, 5);
+            assert_eq!(frames[2].line, 7);
+
+            let source = frames[0].source.as_ref().unwrap();
+            assert_eq!(source.name, "nested.lua");
+            assert_eq!(source.path, script.to_str().unwrap());
+
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_breakpoint_verified_when_its_file_loads() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let module = dir.path().join("module.lua");
+            let script = dir.path().join("main.lua");
+            std::fs::write(&module, "local a = 1\nloaded = a + 1\n").unwrap();
+            std::fs::write(&script, format!("dofile({:?})\n", module.to_str().unwrap())).unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+
+            let past_end = runtime
+                .set_breakpoint(BreakpointType::Line { source: script.to_str().unwrap().to_string(), line: 5 })
+                .await
+                .unwrap();
+            assert!(!past_end.verified);
+            let pending = runtime
+                .set_breakpoint(BreakpointType::Line { source: module.to_str().unwrap().to_string(), line: 2 })
+                .await
+                .unwrap();
+            assert!(!pending.verified);
+            assert_ne!(pending.id, past_end.id);
+
+            runtime.start_program(false).await.unwrap();
+            let changed = events.recv().await.unwrap();
+            assert_eq!(changed.event, "breakpoint");
+            let body = changed.body.unwrap();
+            assert_eq!(body["breakpoint"]["id"], pending.id);
+            assert_eq!(body["breakpoint"]["verified"], true);
+
+            let stopped = events.recv().await.unwrap();
+            assert_eq!(stopped.body.unwrap()["reason"], "breakpoint");
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_breakpoint_verified_when_require_finds_its_module() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let module = dir.path().join("lazy.lua");
+            let script = dir.path().join("main.lua");
+            std::fs::write(&module, "local M = {}\nfunction M.run()\n  return 1\nend\nreturn M\n").unwrap();
+            // The searcher finds the module without running it, so only the searcher wrapper can tell
+            std::fs::write(
+                &script,
+                format!(
+                    "package.path = {:?}\nlocal loader = (package.searchers or package.loaders)[2]('lazy')\n",
+                    dir.path().join("?.lua").to_str().unwrap()
+                ),
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            let pending = runtime
+                .set_breakpoint(BreakpointType::Line { source: module.to_str().unwrap().to_string(), line: 3 })
+                .await
+                .unwrap();
+            assert!(!pending.verified);
+
+            runtime.start_program(false).await.unwrap();
+            let changed = events.recv().await.unwrap();
+            assert_eq!(changed.event, "breakpoint");
+            let body = changed.body.unwrap();
+            assert_eq!(body["breakpoint"]["id"], pending.id);
+            assert_eq!(body["breakpoint"]["verified"], true);
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_required_modules_are_announced_and_listed() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let module = dir.path().join("util.lua");
+            let script = dir.path().join("main.lua");
+            std::fs::write(&module, "return { answer = 42 }\n").unwrap();
+            std::fs::write(
+                &script,
+                format!("package.path = {:?}\nlocal util = require('util')\n", dir.path().join("?.lua").to_str().unwrap()),
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.start_program(false).await.unwrap();
+
+            let mut announced = None;
+            loop {
+                let event = events.recv().await.unwrap();
+                match event.event.as_str() {
+                    "module" => announced = event.body,
+                    "terminated" => break,
+                    _ => {}
+                }
+            }
+            let announced = announced.expect("no module event");
+            assert_eq!(announced["reason"], "new");
+            assert_eq!(announced["module"]["name"], "util");
+            assert_eq!(announced["module"]["path"], module.to_str().unwrap());
+
+            let modules = runtime.modules().await.unwrap();
+            let util = modules.iter().find(|m| m.name == "util").unwrap();
+            assert_eq!(util.path.as_deref(), module.to_str());
+            assert!(util.load_time_ms.is_some());
+            // Standard libraries are listed without a file or load time
+            let string = modules.iter().find(|m| m.name == "string").unwrap();
+            assert_eq!((string.path.as_deref(), string.load_time_ms), (None, None));
+        });
+    }
+
+    #[test]
+    fn test_function_breakpoints_stop_on_entry() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("functions.lua");
+            std::fs::write(
+                &script,
+                "local M = {}\nfunction M.update() return 1 end\npackage.loaded.game = M\n\
+                 local function helper() return 2 end\nhelper()\nM.update()\nprint('done')\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.set_breakpoint(BreakpointType::Function { name: "helper".to_string() }).await.unwrap();
+            runtime.set_breakpoint(BreakpointType::Function { name: "game.update".to_string() }).await.unwrap();
+            runtime.start_program(false).await.unwrap();
+
+            for line in [4, 2] {
+                let stopped = loop {
+                    let event = events.recv().await.unwrap();
+                    if event.event == "stopped" {
+                        break event;
+                    }
+                };
+                assert_eq!(stopped.body.unwrap()["reason"], "function breakpoint");
+                let frames = runtime.stack_trace(None).await.unwrap();
+                assert_eq!(frames[0].line, line);
+                runtime.continue_().await.unwrap();
+            }
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_hosted_state_stops_on_the_host_thread() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("host.lua");
+            std::fs::write(&script, "local speed = 3\nresult = speed * 2\n").unwrap();
+
+            let host = Lua::new();
+            let (mut runtime, program) = PUCLuaRuntime::hosted(unsafe { Lua::from_raw(host.state()) });
+            let (sender, mut events) = crate::dap::event_channel();
+            runtime.set_event_sender(sender);
+
+            let path = script.to_str().unwrap().to_string();
+            let host_thread = thread::spawn(move || {
+                let mut host = host;
+                program.wait_for_start();
+                assert!(program.is_started());
+                host.execute_file(&path).unwrap();
+                host
+            });
+
+            // Applied by the host while it waits for the start
+            runtime
+                .set_breakpoint(BreakpointType::Line {
+                    source: script.to_str().unwrap().to_string(),
+                    line: 2,
+                })
+                .await
+                .unwrap();
+            runtime.start_program(false).await.unwrap();
+
+            while events.recv().await.unwrap().event != "stopped" {}
+            assert_eq!(runtime.stack_trace(None).await.unwrap()[0].line, 2);
+            runtime.continue_().await.unwrap();
+
+            let mut host = host_thread.join().unwrap();
+            // The runtime unhooks the state it does not own before the host closes it
+            drop(runtime);
+            host.execute("assert(result == 6 and debug.gethook() == nil)").unwrap();
+        });
+    }
+
+    #[test]
+    fn test_code_lines() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("lines.lua");
+            std::fs::write(&script, "#!/usr/bin/env lua\nlocal function add(a, b)\n\n  return a + b\nend\n-- done\nprint(add(1, 2))\n")
+                .unwrap();
+
+            let mut runtime = PUCLuaRuntime::new();
+            let lines = runtime.code_lines(script.to_str().unwrap()).await.unwrap();
+            // Lines of the nested function count too; blank lines and comments do not
+            assert!(lines.contains(&4) && lines.contains(&7), "{:?}", lines);
+            assert!(!lines.contains(&3) && !lines.contains(&6), "{:?}", lines);
+
+            std::fs::write(&script, "print(").unwrap();
+            assert!(runtime.code_lines(script.to_str().unwrap()).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_data_breakpoints_stop_on_writes_and_reads() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("watch.lua");
+            std::fs::write(&script, "score = 0\nlocal hp = 10\nlocal double = hp * 2\nscore = double\nprint(score)\n")
+                .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+
+            let watch = |id, data_id, access_type| {
+                let (data_type, name) = DataType::from_data_id(data_id);
+                DataBreakpoint {
+                    id,
+                    name: name.to_string(),
+                    condition: None,
+                    hit_condition: None,
+                    verified: false,
+                    message: None,
+                    hit_count: 0,
+                    data_type,
+                    access_type,
+                    previous_value: None,
+                }
+            };
+            let field = DataBreakpoint {
+                data_type: DataType::TableField { table_ref: 1, field: "x".to_string() },
+                ..watch(3, "x", AccessType::Write)
+            };
+            let set = runtime
+                .set_data_breakpoints(vec![watch(1, "global:score", AccessType::Write), watch(2, "hp", AccessType::Read), field])
+                .await
+                .unwrap();
+            let verified: Vec<bool> = set.iter().map(|bp| bp.verified).collect();
+            assert_eq!(verified, [true, true, false]);
+
+            runtime.start_program(false).await.unwrap();
+            let mut hits = Vec::new();
+            loop {
+                let event = events.recv().await.unwrap();
+                match event.event.as_str() {
+                    "stopped" => {
+                        let body = event.body.unwrap();
+                        assert_eq!(body["reason"], "data breakpoint");
+                        assert!(runtime.check_data_breakpoints(0).await.unwrap());
+                        let line = runtime.stack_trace(None).await.unwrap()[0].line;
+                        let hit = &body["dataBreakpoint"];
+                        hits.push(format!(
+                            "{} {} {} {} -> {}",
+                            line,
+                            hit["name"].as_str().unwrap(),
+                            hit["accessType"].as_str().unwrap(),
+                            hit["oldValue"].as_str().unwrap(),
+                            hit["newValue"].as_str().unwrap()
+                        ));
+                        runtime.continue_().await.unwrap();
+                    }
+                    "terminated" => break,
+                    _ => {}
+                }
+            }
+            assert_eq!(hits, ["2 score write nil -> 0", "3 hp read 10 -> 10", "5 score write 0 -> 20"]);
+        });
+    }
+
+    #[test]
+    fn test_warnings_become_output_events() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("warnings.lua");
+            std::fs::write(
+                &script,
+                "warn('low ', 'fuel')\nwarn('@off')\nwarn('hidden')\nwarn('@on')\n\
+                 setmetatable({}, { __gc = function() error('boom') end })\ncollectgarbage()\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.start_program(false).await.unwrap();
+
+            let mut outputs = Vec::new();
+            loop {
+                let event = events.recv().await.unwrap();
+                match event.event.as_str() {
+                    "output" => outputs.push(event.body.unwrap()),
+                    "terminated" => break,
+                    _ => {}
+                }
+            }
 
-    fn block_on<F: std::future::Future>(future: F) -> F::Output {
-        Runtime::new().unwrap().block_on(future)
+            assert_eq!(outputs.len(), 2);
+            assert_eq!(outputs[0]["category"], "stderr");
+            assert_eq!(outputs[0]["output"], "Lua warning: low fuel\n");
+            assert_eq!(outputs[0]["source"]["path"], script.to_str().unwrap());
+            assert_eq!(outputs[0]["line"], 1);
+            assert_eq!(outputs[1]["category"], "important");
+            assert!(outputs[1]["output"].as_str().unwrap().contains("boom"));
+        });
     }
 
     #[test]
-    fn test_runtime_creation() {
-        let runtime = PUCLuaRuntime::new();
-        assert!(!runtime.is_paused());
+    fn test_wall_clock_profile_samples_stacks() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("busy.lua");
+            std::fs::write(
+                &script,
+                "local function spin()\n  local stop = os.clock() + 0.1\n  while os.clock() < stop do end\nend\nspin()\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime
+                .start_profiling(crate::profiling::ProfilingMode::WallClock { interval_ms: 1 })
+                .await
+                .unwrap();
+            runtime.start_program(false).await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+
+            let profile = runtime.stop_profiling().await.unwrap();
+            assert!(profile.total_samples > 0);
+            assert!(profile.functions["spin"].self_time_ms > 0.0);
+            assert!(profile.stacks.iter().any(|stack| stack.frames.last().map(String::as_str) == Some("spin")));
+        });
     }
 
     #[test]
-    fn test_breakpoint_storage() {
+    fn test_stops_when_memory_limit_exceeded() {
         block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("memory.lua");
+            std::fs::write(
+                &script,
+                "local t = {}\nfor i = 1, 200000 do\n  t[i] = { i }\nend\nt = nil\ncollectgarbage()\nlocal done = true\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
             let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            DebugRuntime::set_memory_limit(&mut runtime, Some(1024));
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.start_program(false).await.unwrap();
+
+            let stopped = loop {
+                let event = events.recv().await.unwrap();
+                if event.event == "stopped" {
+                    break event.body.unwrap();
+                }
+            };
+            assert_eq!(stopped["reason"], "memory limit");
+            assert!(stopped["description"].as_str().unwrap().contains("exceeds the limit of 1024 KB"));
 
-            runtime.set_breakpoint(BreakpointType::Line {
-                source: "test.lua".to_string(),
-                line: 10,
-            }).await.unwrap();
-
-            let breakpoints = runtime.breakpoints.lock().unwrap();
-            assert!(breakpoints.contains_key("test.lua"));
-            assert!(breakpoints["test.lua"].contains(&10));
+            // Usage stays over the limit while the loop runs, which is one stop
+            runtime.continue_().await.unwrap();
+            loop {
+                match events.recv().await.unwrap().event.as_str() {
+                    "stopped" => panic!("stopped twice for the same excursion"),
+                    "terminated" => break,
+                    _ => {}
+                }
+            }
         });
     }
 
     #[test]
-    fn test_is_breakpoint_hit() {
+    fn test_source_of_string_chunks() {
         block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("chunks.lua");
+            std::fs::write(
+                &script,
+                "local named = load('local y = 2\\nreturn y', '=generated')\nnamed()\n\
+                 local anonymous = load('return 3')\nanonymous()\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
             let mut runtime = PUCLuaRuntime::new();
-
-            runtime.set_breakpoint(BreakpointType::Line {
-                source: "test.lua".to_string(),
-                line: 10,
-            }).await.unwrap();
-            runtime.set_breakpoint(BreakpointType::Line {
-                source: "test.lua".to_string(),
-                line: 20,
-            }).await.unwrap();
-            runtime.set_breakpoint(BreakpointType::Line {
-                source: "other.lua".to_string(),
-                line: 5,
-            }).await.unwrap();
-
-            assert!(runtime.is_breakpoint_hit("test.lua", 10));
-            assert!(runtime.is_breakpoint_hit("test.lua", 20));
-            assert!(!runtime.is_breakpoint_hit("test.lua", 15));
-            assert!(!runtime.is_breakpoint_hit("other.lua", 10));
-            assert!(runtime.is_breakpoint_hit("other.lua", 5));
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.set_breakpoint(BreakpointType::Function { name: "named".to_string() }).await.unwrap();
+            runtime.set_breakpoint(BreakpointType::Function { name: "anonymous".to_string() }).await.unwrap();
+            runtime.start_program(false).await.unwrap();
+
+            for (name, code) in [("generated", "local y = 2\nreturn y"), ("[string \"return 3\"]", "return 3")] {
+                while events.recv().await.unwrap().event != "stopped" {}
+                let frames = runtime.stack_trace(None).await.unwrap();
+                let source = frames[0].source.clone().unwrap();
+                assert_eq!(source.name, name);
+                assert!(source.path.is_empty());
+                let reference = source.source_reference.unwrap();
+                assert_eq!(runtime.source(reference).await.unwrap(), code);
+                runtime.continue_().await.unwrap();
+            }
+            while events.recv().await.unwrap().event != "terminated" {}
         });
     }
 
     #[test]
-    fn test_step_mode_conversion() {
-        assert_eq!(StepMode::Over.to_u32(), 0);
-        assert_eq!(StepMode::In.to_u32(), 1);
-        assert_eq!(StepMode::Out.to_u32(), 2);
+    fn test_breakpoint_blocks_program_until_continue() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("blocking.lua");
+            std::fs::write(&script, "reached = 1\nreached = 2\n").unwrap();
 
-        assert_eq!(StepMode::from_u32(0), StepMode::Over);
-        assert_eq!(StepMode::from_u32(1), StepMode::In);
-        assert_eq!(StepMode::from_u32(2), StepMode::Out);
-        assert_eq!(StepMode::from_u32(99), StepMode::Out);
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime
+                .set_breakpoint(BreakpointType::Line {
+                    source: script.to_str().unwrap().to_string(),
+                    line: 2,
+                })
+                .await
+                .unwrap();
+            runtime.start_program(false).await.unwrap();
+
+            let stopped = events.recv().await.unwrap();
+            assert_eq!(stopped.body.unwrap()["reason"], "breakpoint");
+
+            // Line 2 has not run, however long the client takes
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(runtime.is_paused());
+            assert_eq!(runtime.evaluate_global("reached").await.unwrap(), Value::Number(1.0));
+
+            runtime.continue_().await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "continued");
+            while events.recv().await.unwrap().event != "terminated" {}
+            assert_eq!(runtime.evaluate_global("reached").await.unwrap(), Value::Number(2.0));
+        });
     }
 
     #[test]
-    fn test_execute_simple_code() {
-        let runtime = PUCLuaRuntime::new();
+    fn test_search_heap_returns_paths() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime
+                .execute_code("world = { enemies = { { name = 'Imp' }, { name = 'Boss' } } }\nworld.alias = world")
+                .unwrap();
+
+            let result = runtime
+                .search_heap("type(v) == 'table' and v.name == 'Boss'", 100, 10)
+                .await
+                .unwrap();
+            assert_eq!(result.matches.len(), 1);
+            assert_eq!(result.matches[0].path, "_G.world.enemies[2]");
+            assert_eq!(result.matches[0].type_name, "table");
+            assert!(!result.truncated);
+
+            let limited = runtime.search_heap("true", 1, 100).await.unwrap();
+            assert!(limited.truncated);
+            assert_eq!(limited.tables_visited, 1);
+        });
+    }
 
-        let result = runtime.execute_code("1 + 2");
-        assert!(result.is_ok());
-        let value = result.unwrap();
-        match value {
-            Value::Number(n) => assert_eq!(n, 3.0),
-            _ => panic!("Expected Number, got {:?}", value),
-        }
+    #[test]
+    fn test_heap_snapshot_counts_reachable_objects() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.execute_code("world = { name = 'overworld' }\nworld.self = world").unwrap();
+            let before = runtime.take_heap_snapshot().await.unwrap();
+
+            runtime
+                .execute_code("world.enemies = {}\nfor i = 1, 100 do world.enemies[i] = { id = 'enemy' .. i } end")
+                .unwrap();
+            let after = runtime.take_heap_snapshot().await.unwrap();
+
+            assert_eq!((before.id, after.id), (1, 2));
+            assert_eq!(after.object_counts.tables, before.object_counts.tables + 101);
+            assert!(after.object_counts.strings >= before.object_counts.strings + 100);
+            assert!(after.object_counts.functions > 0);
+            let counts = &after.object_counts;
+            assert_eq!(after.objects.len(), counts.tables + counts.functions + counts.userdata + counts.threads);
+            assert!(after.size_estimates["table"] > before.size_estimates["table"]);
+        });
     }
 
     #[test]
-    fn test_execute_string_concatenation() {
-        let runtime = PUCLuaRuntime::new();
+    fn test_allocations_are_charged_to_source_lines() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("allocations.lua");
+            std::fs::write(
+                &script,
+                "local kept = {}\nfor i = 1, 1000 do\n  kept[i] = { i, i * 2 }\nend\nlocal done = true\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.set_allocation_tracking(true).await.unwrap();
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.start_program(false).await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+
+            let profile = runtime.allocation_profile(3, true).await.unwrap();
+            let top = &profile.sites[0];
+            assert!(top.source.ends_with("allocations.lua"));
+            assert_eq!(top.line, 3);
+            assert!(top.allocations >= 1000);
+            assert!(top.live_bytes > 0);
+            assert!(profile.live_bytes >= top.live_bytes);
+            assert!(runtime.allocation_profile(3, false).await.unwrap().sites.is_empty());
+        });
+    }
 
-        let result = runtime.execute_code("'hello' .. ' ' .. 'world'");
-        assert!(result.is_ok());
-        let value = result.unwrap();
-        match value {
-            Value::String(s) => assert_eq!(s, "hello world"),
-            _ => panic!("Expected String, got {:?}", value),
-        }
+    #[test]
+    fn test_metamethod_breakpoint_stops_and_uninstalls() {
+        block_on(async {
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime
+                .execute_code("EnemyMT = { __index = { hp = 10 } }\nboss = setmetatable({}, EnemyMT)")
+                .unwrap();
+
+            let breakpoint = runtime
+                .set_breakpoint(BreakpointType::Metamethod {
+                    target: "EnemyMT".to_string(),
+                    event: "__index".to_string(),
+                })
+                .await
+                .unwrap();
+            assert!(breakpoint.id >= METAMETHOD_BREAKPOINT_BASE);
+
+            // The original __index table still answers
+            assert_eq!(runtime.evaluate_global("boss.hp").await.unwrap(), Value::Number(10.0));
+            let stopped = events.recv().await.unwrap();
+            assert_eq!(stopped.body.unwrap()["reason"], "breakpoint");
+            runtime.clear_pause();
+
+            runtime.remove_breakpoint(breakpoint.id).await.unwrap();
+            assert_eq!(
+                runtime.evaluate_global("type(rawget(EnemyMT, '__index'))").await.unwrap(),
+                Value::String("table".to_string())
+            );
+            assert!(runtime
+                .set_breakpoint(BreakpointType::Metamethod {
+                    target: "EnemyMT".to_string(),
+                    event: "__call".to_string(),
+                })
+                .await
+                .is_err());
+        });
     }
 
     #[test]
-    fn test_execute_boolean() {
-        let runtime = PUCLuaRuntime::new();
+    fn test_detach_restores_the_state() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("empty.lua");
+            std::fs::write(&script, "").unwrap();
 
-        let result = runtime.execute_code("true");
-        assert!(result.is_ok());
-        let value = result.unwrap();
-        match value {
-            Value::Boolean(b) => assert!(b),
-            _ => panic!("Expected Boolean, got {:?}", value),
-        }
+            let mut runtime = PUCLuaRuntime::new();
+            runtime
+                .execute_code(
+                    "EnemyMT = { __index = function() return 1 end }\n\
+                     before = { print, warn, load, coroutine.create, coroutine.wrap, EnemyMT.__index }",
+                )
+                .unwrap();
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime
+                .set_breakpoint(BreakpointType::Metamethod {
+                    target: "EnemyMT".to_string(),
+                    event: "__index".to_string(),
+                })
+                .await
+                .unwrap();
+            runtime.set_breakpoint(BreakpointType::Function { name: "print".to_string() }).await.unwrap();
+            assert_eq!(runtime.evaluate_global("print == before[1]").await.unwrap(), Value::Boolean(false));
+
+            runtime.detach().await.unwrap();
+
+            // Expressions run on a thread of their own, so the main thread's hook is asked for
+            let same = "print == before[1] and warn == before[2] and load == before[3] \
+                        and coroutine.create == before[4] and coroutine.wrap == before[5] \
+                        and rawget(EnemyMT, '__index') == before[6] \
+                        and debug.gethook(debug.getregistry()[1]) == nil";
+            assert_eq!(runtime.evaluate_global(same).await.unwrap(), Value::Boolean(true));
+            assert_eq!(
+                runtime.evaluate_global("debug.getregistry()['wayfinder.patches']").await.unwrap(),
+                Value::Nil
+            );
+        });
     }
 
     #[test]
-    fn test_execute_nil() {
-        let runtime = PUCLuaRuntime::new();
+    fn test_set_global_and_table_field() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.execute_code("config = { speed = 1 }").unwrap();
+            let scopes = runtime.scopes(0).await.unwrap();
+            let globals = scopes.iter().find(|s| s.name == "Globals").unwrap().variables_reference;
 
-        let result = runtime.execute_code("nil");
-        assert!(result.is_ok());
-        let value = result.unwrap();
-        match value {
-            Value::Nil => (),
-            _ => panic!("Expected Nil, got {:?}", value),
-        }
+            let variable = runtime.set_variable(globals, "lives", "2 + 1").await.unwrap();
+            assert_eq!(variable.value, "3");
+
+            let globals = runtime.variables(globals, None).await.unwrap();
+            let config = globals.iter().find(|v| v.name == "config").unwrap();
+            let reference = config.variables_reference.unwrap();
+            runtime.set_variable(reference, "speed", "5").await.unwrap();
+
+            match runtime.evaluate_global("config.speed + lives").await {
+                Ok(Value::Number(n)) => assert_eq!(n, 8.0),
+                other => panic!("Expected Number, got {:?}", other),
+            }
+        });
     }
 
     #[test]
-    fn test_install_hook() {
-        let runtime = PUCLuaRuntime::new();
-        runtime.install_hook();
+    fn test_nested_tables_expand_and_page() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime
+                .execute_code("world = setmetatable({ player = { inventory = { 'sword', 'shield', 'torch' } } }, { __name = 'World' })")
+                .unwrap();
+            let scopes = runtime.scopes(0).await.unwrap();
+            let globals = scopes.iter().find(|s| s.name == "Globals").unwrap().variables_reference;
+
+            let child = |variables: &[Variable], name: &str| {
+                variables.iter().find(|v| v.name == name).cloned().unwrap()
+            };
+            let world = child(&runtime.variables(globals, None).await.unwrap(), "world");
+            let fields = runtime.variables(world.variables_reference.unwrap(), None).await.unwrap();
+            let metatable = child(&fields, "[metatable]");
+            let player = child(&fields, "player");
+            let inventory = child(&runtime.variables(player.variables_reference.unwrap(), None).await.unwrap(), "inventory");
+            assert_eq!(inventory.indexed_variables, Some(3));
+
+            let page = VariablesPage {
+                filter: Some(VariablesFilter::Indexed),
+                start: 1,
+                count: Some(2),
+                ..VariablesPage::default()
+            };
+            let items = runtime.variables_page(inventory.variables_reference.unwrap(), page).await.unwrap();
+            let names: Vec<_> = items.iter().map(|v| (v.name.as_str(), v.value.as_str())).collect();
+            assert_eq!(names, [("[2]", "\"shield\""), ("[3]", "\"torch\"")]);
+
+            let meta_fields = runtime.variables(metatable.variables_reference.unwrap(), None).await.unwrap();
+            assert_eq!(child(&meta_fields, "__name").value, "\"World\"");
+
+            // Handles stay valid until the program resumes
+            let again = runtime.variables(player.variables_reference.unwrap(), None).await.unwrap();
+            assert_eq!(again.len(), 1);
+        });
     }
 
     #[test]
-    fn test_clear_pause() {
-        let runtime = PUCLuaRuntime::new();
-        runtime.clear_pause();
-        assert!(!runtime.is_paused());
+    fn test_huge_tables_page_by_filter() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime
+                .execute_code(
+                    "grid = setmetatable({ width = 100, height = 50, [0] = 'origin' }, {}) \
+                     for i = 1, 5000 do grid[i] = i * 2 end",
+                )
+                .unwrap();
+            let grid = runtime.evaluate_variable(None, "grid", ValueFormat::default()).await.unwrap();
+            assert_eq!(grid.indexed_variables, Some(5000));
+            assert_eq!(grid.named_variables, Some(4));
+            let reference = grid.variables_reference.unwrap();
+
+            let indexed = VariablesPage {
+                filter: Some(VariablesFilter::Indexed),
+                start: 4000,
+                count: Some(3),
+                ..VariablesPage::default()
+            };
+            let items = runtime.variables_page(reference, indexed).await.unwrap();
+            let items: Vec<_> = items.iter().map(|v| (v.name.as_str(), v.value.as_str())).collect();
+            assert_eq!(items, [("[4001]", "8002"), ("[4002]", "8004"), ("[4003]", "8006")]);
+
+            // Named pages reach past the listing limit and end with the metatable
+            let named = |start, count| VariablesPage {
+                filter: Some(VariablesFilter::Named),
+                start,
+                count,
+                ..VariablesPage::default()
+            };
+            let all = runtime.variables_page(reference, named(0, None)).await.unwrap();
+            let names: Vec<_> = all.iter().map(|v| v.name.clone()).collect();
+            assert_eq!(names.len(), 4);
+            assert!(names.contains(&"[0]".to_string()), "{:?}", names);
+            assert_eq!(names[3], METATABLE_CHILD);
+            let page = runtime.variables_page(reference, named(1, Some(2))).await.unwrap();
+            assert_eq!(page.iter().map(|v| &v.name).collect::<Vec<_>>(), names[1..3].iter().collect::<Vec<_>>());
+
+            runtime.set_display_limits(DisplayLimits { max_children: 1, ..DisplayLimits::default() });
+            let page = runtime.variables_page(reference, named(2, Some(2))).await.unwrap();
+            assert_eq!(page.iter().map(|v| &v.name).collect::<Vec<_>>(), names[2..4].iter().collect::<Vec<_>>());
+        });
     }
 
     #[test]
-    fn test_set_step() {
-        let runtime = PUCLuaRuntime::new();
-        runtime.set_step(StepMode::In);
-        runtime.set_step(StepMode::Over);
-        runtime.set_step(StepMode::Out);
+    fn test_evaluate_previews_tables_for_drill_down() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.execute_code("player = { name = 'Ada', pos = { x = 1, y = 2 }, items = { 'sword' } }").unwrap();
+            runtime.execute_code("player.self = player").unwrap();
+
+            let pos = runtime.evaluate_variable(None, "player.pos", ValueFormat::default()).await.unwrap();
+            assert!(pos.value == "{x = 1, y = 2}" || pos.value == "{y = 2, x = 1}", "{}", pos.value);
+            assert_eq!(pos.type_, "table");
+            let fields = runtime.variables(pos.variables_reference.unwrap(), None).await.unwrap();
+            assert_eq!(fields.len(), 2);
+
+            let player = runtime.evaluate_variable(None, "player", ValueFormat::default()).await.unwrap();
+            assert!(player.value.contains("name = \"Ada\""), "{}", player.value);
+            assert!(player.value.contains("items = {\"sword\"}"), "{}", player.value);
+            assert!(player.value.contains("self = <cycle>"), "{}", player.value);
+
+            let name = runtime.evaluate_variable(None, "player.name .. '\\n'", ValueFormat::default()).await.unwrap();
+            assert_eq!(name.value, "\"Ada\\n\"");
+            assert_eq!(name.variables_reference, None);
+        });
     }
 
     #[test]
-    fn test_lua_state_operations() {
-        let mut runtime = PUCLuaRuntime::new();
-        let top = runtime.get_top();
-        assert_eq!(top, 0);
+    fn test_variables_and_evaluation_follow_the_value_format() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.execute_code("flags = { mask = 255, ratio = 2 / 3 }").unwrap();
+            let hex = ValueFormat { hex: true, precision: Some(2) };
 
-        runtime.set_top(5);
-        let top = runtime.get_top();
-        assert_eq!(top, 5);
+            let flags = runtime.evaluate_variable(None, "flags", hex).await.unwrap();
+            assert!(flags.value.contains("mask = 0xff") && flags.value.contains("ratio = 0.67"), "{}", flags.value);
 
-        runtime.set_top(0);
-        let top = runtime.get_top();
-        assert_eq!(top, 0);
+            let page = VariablesPage { format: hex, ..VariablesPage::default() };
+            let fields = runtime.variables_page(flags.variables_reference.unwrap(), page).await.unwrap();
+            let mask = fields.iter().find(|v| v.name == "mask").unwrap();
+            assert_eq!(mask.value, "0xff");
+
+            let plain = runtime.evaluate_variable(None, "flags.mask", ValueFormat::default()).await.unwrap();
+            assert_eq!(plain.value, "255");
+        });
     }
 
     #[test]
-    fn test_type_of() {
-        let mut runtime = PUCLuaRuntime::new();
-
-        runtime.execute_code("nil").ok();
-        assert_eq!(runtime.type_of(-1), 0);
+    fn test_clipboard_literals_paste_back() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.execute_code("level = { name = 'cave\\n', spawns = { { 1, 2 }, { 3, 4 } } }").unwrap();
+
+            let literal = runtime.evaluate_literal(None, "level.spawns").await.unwrap();
+            assert_eq!(literal, "{{1, 2}, {3, 4}}");
+            let literal = runtime.evaluate_literal(None, "level").await.unwrap();
+            runtime.execute_code(&format!("copy = {}", literal)).unwrap();
+            match runtime.evaluate_global("copy.name == level.name and copy.spawns[2][1]").await {
+                Ok(Value::Number(n)) => assert_eq!(n, 3.0),
+                other => panic!("Expected Number, got {:?}", other),
+            }
+        });
+    }
 
-        runtime.execute_code("true").ok();
-        assert_eq!(runtime.type_of(-1), 1);
+    #[test]
+    fn test_strings_are_readable_but_not_writable_memory() {
+        block_on(async {
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.execute_code("blob = '\\137PNG\\0\\r\\n'; level = {}").unwrap();
+
+            let blob = runtime.evaluate_variable(None, "blob", ValueFormat::default()).await.unwrap();
+            assert_eq!(blob.variables_reference, None);
+            let reference = blob.memory_reference.expect("strings have a memory reference");
+            let read = runtime.read_memory(&reference, 1, 10).await.unwrap();
+            assert_eq!((read.data.as_slice(), read.unreadable_bytes), (&b"PNG\0\r\n"[..], 4));
+
+            let error = runtime.write_memory(&reference, 0, b"JPG".to_vec(), false).await.unwrap_err();
+            assert_eq!(error.code(), crate::runtime::error_code::MEMORY_ACCESS);
+            let level = runtime.evaluate_variable(None, "level", ValueFormat::default()).await.unwrap();
+            assert_eq!(level.memory_reference, None);
+            assert!(runtime.read_memory("999", 0, 1).await.is_err());
+        });
+    }
 
-        runtime.execute_code("123").ok();
-        assert_eq!(runtime.type_of(-1), 3);
+    #[test]
+    fn test_set_local_while_stopped() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("set_local.lua");
+            std::fs::write(&script, "local x = 1\nresult = x\n").unwrap();
 
-        runtime.execute_code("'hello'").ok();
-        assert_eq!(runtime.type_of(-1), 4);
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime
+                .set_breakpoint(BreakpointType::Line {
+                    source: script.to_str().unwrap().to_string(),
+                    line: 2,
+                })
+                .await
+                .unwrap();
+            runtime.start_program(false).await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "stopped");
+
+            let locals = runtime.scopes(0).await.unwrap()[0].variables_reference;
+            let variable = runtime.set_variable(locals, "x", "41").await.unwrap();
+            assert_eq!(variable.type_, "number");
+            let missing = runtime.set_variable(locals, "missing", "1").await.unwrap_err();
+            assert_eq!(missing.code(), crate::runtime::error_code::VARIABLE_NOT_FOUND);
+
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+
+            match runtime.evaluate_global("result").await {
+                Ok(Value::Number(n)) => assert_eq!(n, 41.0),
+                other => panic!("Expected Number, got {:?}", other),
+            }
+        });
+    }
 
-        runtime.set_top(0);
+    #[test]
+    fn test_evaluate_sees_the_frames_variables() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("frame_eval.lua");
+            std::fs::write(
+                &script,
+                "score = 5\nshadowed = 'global'\nlocal hp = 10\nlocal shadowed = nil\nresult = hp\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_config(DebuggerConfig {
+                evaluate_mutation: true,
+                ..DebuggerConfig::default()
+            });
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime
+                .set_breakpoint(BreakpointType::Line {
+                    source: script.to_str().unwrap().to_string(),
+                    line: 5,
+                })
+                .await
+                .unwrap();
+            runtime.start_program(false).await.unwrap();
+            assert_eq!(events.recv().await.unwrap().event, "stopped");
+
+            assert_eq!(runtime.evaluate(0, "hp * 2").await.unwrap(), Value::Number(20.0));
+            assert_eq!(runtime.evaluate(0, "score").await.unwrap(), Value::Number(5.0));
+            // A nil local still hides the global
+            assert_eq!(runtime.evaluate(0, "shadowed").await.unwrap(), Value::Nil);
+            let syntax = runtime.evaluate(0, "hp +").await.unwrap_err();
+            assert!(matches!(syntax, RuntimeError::Evaluation { kind: EvalErrorKind::Syntax, .. }), "{}", syntax);
+            assert!(!syntax.show_user());
+            assert_eq!(runtime.evaluate(0, "hp = hp + 1").await.unwrap(), Value::Number(11.0));
+
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+            assert_eq!(runtime.evaluate_global("result").await.unwrap(), Value::Number(11.0));
+        });
     }
 
     #[test]
-    fn test_get_global() {
-        let mut runtime = PUCLuaRuntime::new();
+    fn test_stops_report_hottest_functions_while_profiling() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("frames.lua");
+            std::fs::write(
+                &script,
+                "local function work()\n  local s = 0\n  for i = 1, 100000 do s = s + i end\n  return s\nend\n\
+                 work()\nlocal first = true\nwork()\nlocal second = true\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            for line in [7, 9] {
+                runtime
+                    .set_breakpoint(BreakpointType::Line {
+                        source: script.to_str().unwrap().to_string(),
+                        line,
+                    })
+                    .await
+                    .unwrap();
+            }
+            runtime.start_profiling(crate::profiling::ProfilingMode::CallTrace).await.unwrap();
+            runtime.start_program(false).await.unwrap();
+
+            for _ in 0..2 {
+                let stopped = loop {
+                    let event = events.recv().await.unwrap();
+                    if event.event == "stopped" {
+                        break event.body.unwrap();
+                    }
+                };
+                let profile = &stopped["profile"];
+                assert!(profile["sinceLastStopMs"].as_f64().unwrap() > 0.0);
+                let work = &profile["hottestFunctions"][0];
+                assert_eq!(work["name"], "work");
+                // Only the call since the previous stop counts
+                assert_eq!(work["callCount"], 1);
+                runtime.continue_().await.unwrap();
+            }
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
 
-        runtime.execute_code("myvar = 42").ok();
-        let count = runtime.get_global("myvar");
-        assert_eq!(count, 1);
+    #[test]
+    fn test_uncaught_error_is_recorded() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("uncaught.lua");
+            std::fs::write(&script, "local answer = 42\nerror('boom')\n").unwrap();
 
-        let value = runtime.execute_code("myvar").ok();
-        match value {
-            Some(Value::Number(n)) => assert_eq!(n, 42.0),
-            _ => panic!("Expected Number"),
-        }
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.start_program(false).await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
 
-        runtime.set_top(0);
+            let records = runtime.flight_records().await.unwrap();
+            let record = records
+                .iter()
+                .find(|r| matches!(&r.kind, RecordKind::Error { message } if message.contains("boom")))
+                .expect("error was not recorded");
+            assert!(record.frames.iter().any(|f| f.line == 2));
+            assert!(record.locals.iter().any(|l| l.name == "answer" && l.value == "42"));
+        });
     }
 
     #[test]
-    fn test_load_string() {
-        let runtime = PUCLuaRuntime::new();
+    fn test_uncaught_error_prints_execution_trace() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("trace.lua");
+            std::fs::write(
+                &script,
+                "local function check(n)\n  local limit = 3\n  return n < limit\nend\nif not check(5) then\n  error('too big')\nend\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime.start_program(false).await.unwrap();
 
-        let result = runtime.load_string("x = 10");
-        assert!(result.is_ok());
+            let mut outputs = Vec::new();
+            loop {
+                let event = events.recv().await.unwrap();
+                match event.event.as_str() {
+                    "output" => outputs.push(event.body.unwrap()["output"].as_str().unwrap().to_string()),
+                    "terminated" => break,
+                    _ => {}
+                }
+            }
 
-        let result = runtime.pcall(0, 0);
-        assert!(result.is_ok());
+            // The trace shows the call to check, which had returned by the time of the error
+            let entries = runtime.execution_trace().await.unwrap();
+            assert!(entries.iter().any(|e| e.line == 3 && e.function == "check"));
+            let last = entries.last().unwrap();
+            assert_eq!((last.line, last.function.as_str()), (6, "main chunk"));
+            let trace = outputs.iter().find(|o| o.starts_with("How the program got here")).expect("no trace printed");
+            assert!(trace.contains("trace.lua:3 in check"));
+        });
+    }
 
-        let value = runtime.execute_code("x").ok();
-        match value {
-            Some(Value::Number(n)) => assert_eq!(n, 10.0),
-            _ => panic!("Expected Number"),
-        }
+    #[test]
+    fn test_threads_include_stopped_coroutine() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("coroutines.lua");
+            std::fs::write(
+                &script,
+                "local co = coroutine.create(function(a)\n  local inside = a\n  coroutine.yield(inside)\nend)\ncoroutine.resume(co, 5)\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime
+                .set_breakpoint(BreakpointType::Line {
+                    source: script.to_str().unwrap().to_string(),
+                    line: 3,
+                })
+                .await
+                .unwrap();
+            runtime.start_program(false).await.unwrap();
+
+            let stopped = events.recv().await.unwrap();
+            assert_eq!(stopped.event, "stopped");
+            let thread_id = stopped.body.unwrap()["threadId"].as_u64().unwrap();
+            assert_ne!(thread_id, MAIN_THREAD_ID);
+
+            let threads = runtime.threads().await.unwrap();
+            assert_eq!(threads[0].id, MAIN_THREAD_ID);
+            assert!(threads.iter().any(|thread| thread.id == thread_id));
+
+            let frames = runtime.stack_trace(Some(thread_id)).await.unwrap();
+            assert_eq!(frames[0].line, 3);
+            let scopes = runtime.scopes(frames[0].id).await.unwrap();
+            let locals = runtime.variables(scopes[0].variables_reference, None).await.unwrap();
+            assert!(locals.iter().any(|v| v.name == "inside" && v.value == "5"));
+
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_coroutine_stack_continues_into_resumers() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("stitched.lua");
+            std::fs::write(
+                &script,
+                "local function inner()\n  coroutine.yield(1)\nend\nlocal function outer()\n  local step = coroutine.wrap(inner)\n  step()\nend\nlocal co = coroutine.create(outer)\ncoroutine.resume(co)\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime
+                .set_breakpoint(BreakpointType::Line {
+                    source: script.to_str().unwrap().to_string(),
+                    line: 2,
+                })
+                .await
+                .unwrap();
+            runtime.start_program(false).await.unwrap();
+
+            let stopped = events.recv().await.unwrap();
+            assert_eq!(stopped.event, "stopped");
+            let thread_id = stopped.body.unwrap()["threadId"].as_u64().unwrap();
+
+            let frames = runtime.stack_trace(Some(thread_id)).await.unwrap();
+            let lines: Vec<_> = frames.iter().map(|frame| frame.line).collect();
+            assert_eq!(lines[..5], [2, 0, 6, 0, 9]);
+            let (outer_id, _) = split_frame_id(frames[2].id);
+            assert_ne!(outer_id, thread_id);
+            assert_eq!(frames[1].name, format!("resumed by coroutine {}", outer_id));
+            assert_eq!(frames[1].presentation_hint.as_deref(), Some("label"));
+            assert_eq!((frames[3].name.as_str(), frames[4].name.as_str()), ("resumed by main", "main chunk"));
+            // Resumer frames keep their own ids, so their variables can be inspected
+            let scopes = runtime.scopes(frames[2].id).await.unwrap();
+            let locals = runtime.variables(scopes[0].variables_reference, None).await.unwrap();
+            assert!(locals.iter().any(|v| v.name == "step"));
+
+            runtime.set_stitch_coroutine_stacks(false);
+            let frames = runtime.stack_trace(Some(thread_id)).await.unwrap();
+            assert_eq!(frames.iter().map(|frame| frame.line).collect::<Vec<_>>(), vec![2]);
+
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
+    }
+
+    #[test]
+    fn test_continue_thread_stops_when_another_thread_runs() {
+        block_on(async {
+            let dir = tempfile::tempdir().unwrap();
+            let script = dir.path().join("single_thread.lua");
+            std::fs::write(
+                &script,
+                "local co = coroutine.create(function()\n  for i = 1, 2 do\n    coroutine.yield(i)\n  end\nend)\ncoroutine.resume(co)\nlocal after = 1\n",
+            )
+            .unwrap();
+
+            let (sender, mut events) = crate::dap::event_channel();
+            let mut runtime = PUCLuaRuntime::new();
+            runtime.set_event_sender(sender);
+            runtime.load_program(script.to_str().unwrap()).unwrap();
+            runtime
+                .set_breakpoint(BreakpointType::Line {
+                    source: script.to_str().unwrap().to_string(),
+                    line: 3,
+                })
+                .await
+                .unwrap();
+            runtime.start_program(false).await.unwrap();
+            let thread_id = events.recv().await.unwrap().body.unwrap()["threadId"].as_u64().unwrap();
+            assert!(matches!(runtime.pause_thread(999).await, Err(RuntimeError::ThreadNotFound(999))));
+
+            // The coroutine runs alone until it yields back to the main thread
+            assert!(!runtime.continue_thread(thread_id).await.unwrap());
+            let continued = events.recv().await.unwrap().body.unwrap();
+            assert_eq!((continued["threadId"].as_u64(), continued["allThreadsContinued"].as_bool()), (Some(thread_id), Some(false)));
+            let stopped = events.recv().await.unwrap().body.unwrap();
+            assert_eq!(stopped["reason"], "pause");
+            assert_eq!(stopped["threadId"], MAIN_THREAD_ID);
+            assert_eq!(runtime.get_current_line(), 7);
+
+            runtime.continue_().await.unwrap();
+            while events.recv().await.unwrap().event != "terminated" {}
+        });
     }
 }
@@ -5,9 +5,7 @@
 //! and can perform FFI operations needed for hot reloading.
 
 use crate::hot_reload::service::{HotReloadService, HotReloadResult};
-use crate::hot_reload::{HotReloadError, HotReloadWarning, WarningSeverity};
-use crate::runtime::puc_lua::PUCLuaRuntime;
-use crate::runtime::lua_ffi::*;
+use crate::hot_reload::{reload_module, HotReloadError};
 use crate::runtime::lua_state::Lua;
 use async_trait::async_trait;
 use std::sync::{Arc, Mutex};
@@ -23,77 +21,6 @@ impl PUCLuaHotReloadService {
     pub fn new(lua: Arc<Mutex<Lua>>) -> Self {
         Self { lua }
     }
-    
-    /// Compile new module source
-    fn compile_module(&mut self, source: &str) -> Result<(), HotReloadError> {
-        let lua_guard = self.lua.lock().unwrap();
-        let lua_state = lua_guard.state();
-        
-        unsafe {
-            let source_cstr = std::ffi::CString::new(source)
-                .map_err(|_| HotReloadError::CompilationError("Invalid source string".to_string()))?;
-
-            if luaL_loadstring(lua_state, source_cstr.as_ptr()) != LUA_OK as i32 {
-                // Get the error message
-                let error_msg = if lua_type(lua_state, -1) == LUA_TSTRING as i32 {
-                    let c_str = lua_tolstring(lua_state, -1, std::ptr::null_mut());
-                    if !c_str.is_null() {
-                        std::ffi::CStr::from_ptr(c_str)
-                            .to_string_lossy()
-                            .to_string()
-                    } else {
-                        "Unknown compilation error".to_string()
-                    }
-                } else {
-                    "Unknown compilation error".to_string()
-                };
-
-                lua_pop(lua_state, 1); // Remove error message
-                return Err(HotReloadError::CompilationError(error_msg));
-            }
-        }
-
-        Ok(())
-    }
-
-    /// Execute compiled module to get new module table
-    fn execute_module(&mut self) -> Result<i64, HotReloadError> {
-        let lua_guard = self.lua.lock().unwrap();
-        let lua_state = lua_guard.state();
-        
-        unsafe {
-            // Execute the compiled chunk
-            if lua_pcall(lua_state, 0, 1, 0) != LUA_OK as i32 {
-                // Get the error message
-                let error_msg = if lua_type(lua_state, -1) == LUA_TSTRING as i32 {
-                    let c_str = lua_tolstring(lua_state, -1, std::ptr::null_mut());
-                    if !c_str.is_null() {
-                        std::ffi::CStr::from_ptr(c_str)
-                            .to_string_lossy()
-                            .to_string()
-                    } else {
-                        "Unknown execution error".to_string()
-                    }
-                } else {
-                    "Unknown execution error".to_string()
-                };
-
-                lua_pop(lua_state, 1); // Remove error message
-                return Err(HotReloadError::CompilationError(error_msg));
-            }
-
-            // The result should be on top of the stack
-            // Create a reference to it
-            let module_ref = luaL_ref(lua_state, LUA_REGISTRYINDEX);
-            Ok(module_ref as i64)
-        }
-    }
-
-    /// Call new module chunk (compile + execute)
-    fn call_module_chunk(&mut self, chunk: &str) -> Result<i64, HotReloadError> {
-        self.compile_module(chunk)?;
-        self.execute_module()
-    }
 }
 
 #[async_trait]
@@ -103,30 +30,12 @@ impl HotReloadService for PUCLuaHotReloadService {
         module_source: &str,
         module_name: Option<&str>,
     ) -> Result<HotReloadResult, HotReloadError> {
-        // In a real implementation, we would:
-        // 1. Capture the current state
-        // 2. Compile and execute the new module
-        // 3. Restore the state
-        // 4. Update references
-        
-        // For now, we'll just compile and execute as a placeholder
-        self.compile_module(module_source)?;
-        
-        let warnings = vec![
-            HotReloadWarning {
-                message: "State preservation not yet implemented".to_string(),
-                severity: WarningSeverity::Warning,
-            }
-        ];
-        
-        Ok(HotReloadResult {
-            success: true,
-            warnings,
-            message: Some(format!("Module '{}' compiled successfully", 
-                                module_name.unwrap_or("unnamed"))),
-        })
+        let mut lua = self.lua.lock().unwrap();
+        let transfer =
+            reload_module(&mut lua, module_source, module_name).map_err(HotReloadError::CompilationError)?;
+        Ok(HotReloadResult::from_transfer(module_name, transfer))
     }
-    
+
     fn is_supported(&self) -> bool {
         true
     }
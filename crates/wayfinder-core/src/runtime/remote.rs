@@ -0,0 +1,478 @@
+//! Runtime proxied to a debug agent over a socket
+//!
+//! `RemoteRuntime` forwards every `DebugRuntime` call to a
+//! [`DebugAgent`](crate::agent::DebugAgent) running inside another process and
+//! relays the agent's events to the local event sender.
+
+use super::{
+    error_code, BreakpointType, DebugRuntime, ExceptionInfo, Frame, FrameStepTarget, RuntimeError, RuntimeVersion,
+    Scope, StepMode, Thread, Value, Variable, VariableScope, VariablesPage,
+};
+use crate::agent::{method, params, AGENT_VERSION};
+use crate::dap::transport::DapTransport;
+use crate::dap::{Event, EventSender, Message, ProtocolMessage, ResponseError};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+
+type Reply = std::result::Result<JsonValue, ResponseError>;
+
+/// A request waiting to be written to the agent
+struct PendingRequest {
+    method: &'static str,
+    params: JsonValue,
+    reply: oneshot::Sender<Reply>,
+}
+
+/// Runtime living in another process, reached through its debug agent
+pub struct RemoteRuntime {
+    requests: mpsc::UnboundedSender<PendingRequest>,
+    events: Arc<Mutex<Option<EventSender>>>,
+    version: RuntimeVersion,
+}
+
+impl RemoteRuntime {
+    /// Takes over a connected transport and performs the attach handshake
+    pub async fn connect<R, W>(transport: DapTransport<R, W>) -> Result<Self, RuntimeError>
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (requests, outgoing) = mpsc::unbounded_channel();
+        let events = Arc::new(Mutex::new(None));
+        tokio::spawn(run_connection(transport, outgoing, events.clone()));
+
+        let mut runtime = Self {
+            requests,
+            events,
+            // Replaced by the agent's answer below
+            version: RuntimeVersion {
+                runtime: super::RuntimeType::PUC,
+                version: super::LuaVersion::V54,
+            },
+        };
+        // The protocol between the two changes between versions
+        let attached: JsonValue = runtime.call(method::ATTACH, json!({})).await?;
+        let found = attached.get("agentVersion").and_then(|v| v.as_str()).unwrap_or("unknown");
+        if found != AGENT_VERSION {
+            return Err(RuntimeError::VersionMismatch {
+                expected: AGENT_VERSION.to_string(),
+                found: found.to_string(),
+            });
+        }
+        runtime.version = serde_json::from_value(attached)
+            .map_err(|e| RuntimeError::Communication(format!("Invalid {} response: {}", method::ATTACH, e)))?;
+        Ok(runtime)
+    }
+
+    /// Connects to an agent listening on a TCP address
+    pub async fn connect_tcp(address: &str) -> Result<Self, RuntimeError> {
+        let stream = tokio::net::TcpStream::connect(address).await?;
+        Self::connect(DapTransport::tcp(stream)).await
+    }
+
+    /// Connects to an agent listening on a Unix socket
+    #[cfg(unix)]
+    pub async fn connect_unix(path: &std::path::Path) -> Result<Self, RuntimeError> {
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        Self::connect(DapTransport::unix(stream)).await
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &'static str, params: JsonValue) -> Result<T, RuntimeError> {
+        let (reply, response) = oneshot::channel();
+        self.requests
+            .send(PendingRequest { method, params, reply })
+            .map_err(|_| disconnected())?;
+
+        let result = response.await.map_err(|_| disconnected())?;
+        match result {
+            Ok(value) => serde_json::from_value(value)
+                .map_err(|e| RuntimeError::Communication(format!("Invalid {} response: {}", method, e))),
+            Err(error) if error.code == error_code::NOT_IMPLEMENTED => Err(RuntimeError::NotImplemented(error.message)),
+            Err(error) => Err(RuntimeError::Remote {
+                code: error.code,
+                message: error.message,
+            }),
+        }
+    }
+}
+
+fn disconnected() -> RuntimeError {
+    RuntimeError::Communication("Debug agent disconnected".to_string())
+}
+
+/// Owns the transport: writes queued requests, routes responses and events
+async fn run_connection<R, W>(
+    mut transport: DapTransport<R, W>,
+    mut outgoing: mpsc::UnboundedReceiver<PendingRequest>,
+    events: Arc<Mutex<Option<EventSender>>>,
+) where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let _task = crate::internals::tasks().register(
+        crate::internals::TaskRole::RemoteConnection,
+        crate::internals::TaskKind::Task,
+        "connected",
+    );
+    let mut in_flight: HashMap<u64, oneshot::Sender<Reply>> = HashMap::new();
+    let mut next_id = 1u64;
+
+    loop {
+        tokio::select! {
+            request = outgoing.recv() => {
+                // The runtime was dropped
+                let Some(request) = request else { return };
+                let id = next_id;
+                next_id += 1;
+
+                let message = ProtocolMessage::Request(Message::new(id, request.method, request.params));
+                if transport.write_protocol_message(&message).await.is_err() {
+                    break;
+                }
+                in_flight.insert(id, request.reply);
+            }
+            message = transport.read_protocol_message() => match message {
+                Ok(Some(ProtocolMessage::Response(response))) => {
+                    if let Some(reply) = in_flight.remove(&response.request_seq) {
+                        let _ = reply.send(response.result);
+                    }
+                }
+                Ok(Some(ProtocolMessage::Event(event))) => {
+                    if let Some(sender) = events.lock().unwrap().as_ref() {
+                        let _ = sender.send(event);
+                    }
+                }
+                // The agent never sends requests
+                Ok(Some(ProtocolMessage::Request(_))) => {}
+                Ok(None) | Err(_) => break,
+            },
+        }
+    }
+
+    // Dropping the in-flight replies fails their callers with "disconnected"
+    drop(in_flight);
+    if let Some(sender) = events.lock().unwrap().as_ref() {
+        let _ = sender.send(Event::terminated());
+    }
+}
+
+#[async_trait::async_trait]
+impl DebugRuntime for RemoteRuntime {
+    async fn version(&self) -> RuntimeVersion {
+        self.version.clone()
+    }
+
+    async fn set_breakpoint(&mut self, breakpoint: BreakpointType) -> Result<super::Breakpoint, RuntimeError> {
+        self.call(method::SET_BREAKPOINT, params(&[("breakpoint", json!(breakpoint))])).await
+    }
+
+    async fn remove_breakpoint(&mut self, id: i64) -> Result<(), RuntimeError> {
+        self.call(method::REMOVE_BREAKPOINT, params(&[("id", json!(id))])).await
+    }
+
+    async fn step(&mut self, mode: StepMode) -> Result<(), RuntimeError> {
+        self.call(method::STEP, params(&[("mode", json!(mode))])).await
+    }
+
+    async fn step_instruction(&mut self, mode: StepMode) -> Result<(), RuntimeError> {
+        self.call(method::STEP_INSTRUCTION, params(&[("mode", json!(mode))])).await
+    }
+
+    async fn continue_(&mut self) -> Result<(), RuntimeError> {
+        self.call(method::CONTINUE, json!({})).await
+    }
+
+    async fn step_frame(&mut self, function: &str, until: FrameStepTarget) -> Result<(), RuntimeError> {
+        self.call(
+            method::STEP_FRAME,
+            params(&[("function", json!(function)), ("until", json!(until))]),
+        )
+        .await
+    }
+
+    async fn pause(&mut self) -> Result<(), RuntimeError> {
+        self.call(method::PAUSE, json!({})).await
+    }
+
+    async fn pause_thread(&mut self, thread_id: u64) -> Result<(), RuntimeError> {
+        self.call(method::PAUSE_THREAD, params(&[("threadId", json!(thread_id))])).await
+    }
+
+    async fn continue_thread(&mut self, thread_id: u64) -> Result<bool, RuntimeError> {
+        self.call(method::CONTINUE_THREAD, params(&[("threadId", json!(thread_id))])).await
+    }
+
+    async fn threads(&mut self) -> Result<Vec<Thread>, RuntimeError> {
+        self.call(method::THREADS, json!({})).await
+    }
+
+    async fn stack_trace(&mut self, thread_id: Option<u64>) -> Result<Vec<Frame>, RuntimeError> {
+        self.call(method::STACK_TRACE, params(&[("threadId", json!(thread_id))])).await
+    }
+
+    async fn scopes(&mut self, frame_id: i64) -> Result<Vec<Scope>, RuntimeError> {
+        self.call(method::SCOPES, params(&[("frameId", json!(frame_id))])).await
+    }
+
+    async fn variables(
+        &mut self,
+        variables_reference: i64,
+        filter: Option<VariableScope>,
+    ) -> Result<Vec<Variable>, RuntimeError> {
+        self.call(
+            method::VARIABLES,
+            params(&[("variablesReference", json!(variables_reference)), ("filter", json!(filter))]),
+        )
+        .await
+    }
+
+    async fn variables_page(&mut self, variables_reference: i64, page: VariablesPage) -> Result<Vec<Variable>, RuntimeError> {
+        self.call(
+            method::VARIABLES,
+            params(&[("variablesReference", json!(variables_reference)), ("page", json!(page))]),
+        )
+        .await
+    }
+
+    async fn evaluate(&mut self, frame_id: i64, expression: &str) -> Result<Value, RuntimeError> {
+        self.call(
+            method::EVALUATE,
+            params(&[("frameId", json!(frame_id)), ("expression", json!(expression))]),
+        )
+        .await
+    }
+
+    async fn set_variable(
+        &mut self,
+        variables_reference: i64,
+        name: &str,
+        value: &str,
+    ) -> Result<Variable, RuntimeError> {
+        self.call(
+            method::SET_VARIABLE,
+            params(&[
+                ("variablesReference", json!(variables_reference)),
+                ("name", json!(name)),
+                ("value", json!(value)),
+            ]),
+        )
+        .await
+    }
+
+    async fn evaluate_global(&mut self, expression: &str) -> Result<Value, RuntimeError> {
+        self.call(method::EVALUATE_GLOBAL, params(&[("expression", json!(expression))])).await
+    }
+
+    async fn evaluate_literal(&mut self, frame_id: Option<i64>, expression: &str) -> Result<String, RuntimeError> {
+        self.call(
+            method::EVALUATE_LITERAL,
+            params(&[("frameId", json!(frame_id)), ("expression", json!(expression))]),
+        )
+        .await
+    }
+
+    async fn goto_lines(&mut self, source: &str) -> Result<Vec<u32>, RuntimeError> {
+        self.call(method::GOTO_LINES, params(&[("source", json!(source))])).await
+    }
+
+    async fn read_memory(
+        &mut self,
+        memory_reference: &str,
+        offset: i64,
+        count: usize,
+    ) -> Result<crate::debug::memory_references::MemoryRead, RuntimeError> {
+        self.call(
+            method::READ_MEMORY,
+            params(&[("memoryReference", json!(memory_reference)), ("offset", json!(offset)), ("count", json!(count))]),
+        )
+        .await
+    }
+
+    async fn write_memory(
+        &mut self,
+        memory_reference: &str,
+        offset: i64,
+        data: Vec<u8>,
+        allow_partial: bool,
+    ) -> Result<usize, RuntimeError> {
+        self.call(
+            method::WRITE_MEMORY,
+            params(&[
+                ("memoryReference", json!(memory_reference)),
+                ("offset", json!(offset)),
+                ("data", json!(data)),
+                ("allowPartial", json!(allow_partial)),
+            ]),
+        )
+        .await
+    }
+
+    async fn run_to_location(&mut self, source: &str, line: u32) -> Result<(), RuntimeError> {
+        self.call(
+            method::RUN_TO_LOCATION,
+            params(&[("source", json!(source)), ("line", json!(line))]),
+        )
+        .await
+    }
+
+    async fn source(&mut self, source_reference: i64) -> Result<String, RuntimeError> {
+        self.call(method::SOURCE, params(&[("sourceReference", json!(source_reference))])).await
+    }
+
+    async fn check_data_breakpoints(&mut self, frame_id: i64) -> Result<bool, RuntimeError> {
+        self.call(method::CHECK_DATA_BREAKPOINTS, params(&[("frameId", json!(frame_id))])).await
+    }
+
+    async fn get_exception_info(&mut self, thread_id: u64) -> Result<ExceptionInfo, RuntimeError> {
+        self.call(method::EXCEPTION_INFO, params(&[("threadId", json!(thread_id))])).await
+    }
+
+    async fn get_memory_statistics(&self) -> Result<crate::memory::MemoryStatistics, RuntimeError> {
+        self.call(method::MEMORY_STATISTICS, json!({})).await
+    }
+
+    async fn force_gc(&mut self) -> Result<(), RuntimeError> {
+        self.call(method::FORCE_GC, json!({})).await
+    }
+
+    async fn gc_step(&mut self, kb: u32) -> Result<bool, RuntimeError> {
+        self.call(method::GC_STEP, params(&[("kb", json!(kb))])).await
+    }
+
+    async fn tune_gc(
+        &mut self,
+        pause: Option<i32>,
+        step_mul: Option<i32>,
+    ) -> Result<crate::memory::MemoryStatistics, RuntimeError> {
+        self.call(method::TUNE_GC, params(&[("pause", json!(pause)), ("stepMul", json!(step_mul))]))
+            .await
+    }
+
+    async fn take_heap_snapshot(&mut self) -> Result<crate::memory::HeapSnapshot, RuntimeError> {
+        self.call(method::HEAP_SNAPSHOT, json!({})).await
+    }
+
+    async fn set_allocation_tracking(&mut self, enabled: bool) -> Result<(), RuntimeError> {
+        self.call(method::SET_ALLOCATION_TRACKING, params(&[("enabled", json!(enabled))]))
+            .await
+    }
+
+    async fn allocation_profile(
+        &mut self,
+        limit: usize,
+        reset: bool,
+    ) -> Result<crate::memory::AllocationProfile, RuntimeError> {
+        self.call(method::ALLOCATION_PROFILE, params(&[("limit", json!(limit)), ("reset", json!(reset))]))
+            .await
+    }
+
+    async fn detach(&mut self) -> Result<(), RuntimeError> {
+        self.call(method::DETACH, json!({})).await
+    }
+
+    async fn flight_records(&self) -> Result<Vec<crate::debug::flight_recorder::FlightRecord>, RuntimeError> {
+        self.call(method::FLIGHT_RECORDS, json!({})).await
+    }
+
+    async fn modules(&mut self) -> Result<Vec<crate::debug::modules::Module>, RuntimeError> {
+        self.call(method::MODULES, json!({})).await
+    }
+
+    async fn search_heap(
+        &mut self,
+        predicate: &str,
+        max_tables: usize,
+        max_results: usize,
+    ) -> Result<crate::memory::HeapSearchResult, RuntimeError> {
+        self.call(
+            method::SEARCH_HEAP,
+            params(&[
+                ("predicate", json!(predicate)),
+                ("maxTables", json!(max_tables)),
+                ("maxResults", json!(max_results)),
+            ]),
+        )
+        .await
+    }
+
+    async fn start_program(&mut self, stop_on_entry: bool) -> Result<(), RuntimeError> {
+        self.call(method::START, params(&[("stopOnEntry", json!(stop_on_entry))])).await
+    }
+
+    fn set_event_sender(&mut self, sender: EventSender) {
+        *self.events.lock().unwrap() = Some(sender);
+    }
+
+    fn set_string_encoding(&mut self, encoding: Option<&'static encoding_rs::Encoding>) {
+        // Strings are decoded inside the target, so the agent applies this.
+        // Nobody waits for the reply; a lost connection shows up on the next call.
+        let (reply, _) = oneshot::channel();
+        let _ = self.requests.send(PendingRequest {
+            method: method::SET_STRING_ENCODING,
+            params: params(&[("encoding", json!(encoding.map(|e| e.name())))]),
+            reply,
+        });
+    }
+
+    fn set_memory_limit(&mut self, limit_kb: Option<u64>) {
+        let (reply, _) = oneshot::channel();
+        let _ = self.requests.send(PendingRequest {
+            method: method::SET_MEMORY_LIMIT,
+            params: params(&[("limitKb", json!(limit_kb))]),
+            reply,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::DebugAgent;
+    use crate::runtime::mock::MockRuntime;
+    use tokio::io::BufReader;
+
+    async fn connected() -> RemoteRuntime {
+        let (client, server) = tokio::io::duplex(4096);
+        let (server_read, server_write) = tokio::io::split(server);
+        tokio::spawn(async move {
+            let mut agent = DebugAgent::new(MockRuntime::new());
+            let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+            let _ = agent.serve(&mut transport).await;
+        });
+
+        let (client_read, client_write) = tokio::io::split(client);
+        RemoteRuntime::connect(DapTransport::new(BufReader::new(client_read), client_write))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_calls_round_trip_through_agent() {
+        let mut runtime = connected().await;
+        assert_eq!(runtime.version().await.version, super::super::LuaVersion::V54);
+
+        let breakpoint = runtime
+            .set_breakpoint(BreakpointType::Line {
+                source: "main.lua".to_string(),
+                line: 12,
+            })
+            .await
+            .unwrap();
+        assert!(breakpoint.verified);
+        assert_eq!(breakpoint.line, 12);
+
+        let frames = runtime.stack_trace(None).await.unwrap();
+        let local = MockRuntime::new().stack_trace(None).await.unwrap();
+        assert_eq!(frames, local);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_operations_stay_unsupported() {
+        let mut runtime = connected().await;
+        assert!(matches!(runtime.force_gc().await, Err(RuntimeError::NotImplemented(_))));
+    }
+}
@@ -0,0 +1,224 @@
+//! Limits on code the debugger runs for the client
+//!
+//! A watch or console expression such as `while true do end` would never
+//! return, and it runs while the program is stopped, so it would hang the
+//! program and every request after it. Expressions are called on a thread of
+//! their own with a count hook that stops them once they run past an
+//! instruction budget or a deadline. The hook has to live on a new thread:
+//! expressions run from inside the debug hook, where Lua does not call hooks
+//! on the program's own thread.
+//!
+//! Only static builds can resume a thread; dynamic builds call expressions
+//! directly, without limits.
+
+use super::lua_ffi::*;
+use super::lua_state::Lua;
+use super::{EvalErrorKind, RuntimeError};
+use crate::config::DebuggerConfig;
+use std::time::Duration;
+
+/// Instructions run between checks of the budget
+#[cfg_attr(not(feature = "static-lua"), allow(dead_code))]
+const CHECK_INTERVAL: c_int = 1000;
+
+/// How long and how far an expression may run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvaluationLimits {
+    /// Instructions, counted a thousand at a time; None for no limit
+    pub instructions: Option<u64>,
+    /// Wall-clock time; None for no limit
+    pub time: Option<Duration>,
+}
+
+impl EvaluationLimits {
+    /// No limits at all
+    pub const NONE: Self = Self {
+        instructions: None,
+        time: None,
+    };
+
+    /// The limits configured, where 0 is no limit
+    pub fn from_config(config: &DebuggerConfig) -> Self {
+        Self {
+            instructions: Some(config.evaluation_instruction_limit).filter(|&limit| limit > 0),
+            time: Some(config.evaluation_timeout_ms)
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
+        }
+    }
+}
+
+/// Why an expression did not produce a value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvaluationError {
+    /// It did not compile
+    Syntax(String),
+    /// It raised an error
+    Failed(String),
+    /// It ran past its limits and was stopped
+    LimitExceeded(String),
+}
+
+impl From<EvaluationError> for RuntimeError {
+    fn from(error: EvaluationError) -> Self {
+        match error {
+            EvaluationError::Syntax(message) => RuntimeError::Evaluation {
+                kind: EvalErrorKind::Syntax,
+                message,
+            },
+            EvaluationError::Failed(message) => RuntimeError::Evaluation {
+                kind: EvalErrorKind::Runtime,
+                message,
+            },
+            EvaluationError::LimitExceeded(message) => RuntimeError::EvaluationTimeout(message),
+        }
+    }
+}
+
+/// Calls the function on top of `lua`'s stack without arguments, within `limits`
+///
+/// Leaves the function's first result on top of the stack, along with the
+/// thread it ran on; callers restore the stack top.
+pub fn call(lua: &mut Lua, limits: &EvaluationLimits) -> Result<(), EvaluationError> {
+    call_with_arguments(lua, 0, limits)
+}
+
+/// Calls a function with the `arguments` values above it on `lua`'s stack, within `limits`
+///
+/// Leaves the function's first result on top of the stack, along with the
+/// thread it ran on; callers restore the stack top.
+#[cfg(feature = "static-lua")]
+pub fn call_with_arguments(lua: &mut Lua, arguments: c_int, limits: &EvaluationLimits) -> Result<(), EvaluationError> {
+    use std::cell::RefCell;
+    use std::ffi::CStr;
+    use std::time::Instant;
+
+    /// What is left of the budget of the expression running on this OS thread
+    struct Budget {
+        instructions_left: Option<u64>,
+        deadline: Option<Instant>,
+        limit: Option<String>,
+    }
+
+    thread_local! {
+        static BUDGET: RefCell<Option<Budget>> = const { RefCell::new(None) };
+    }
+
+    /// Stops the expression once it is over budget
+    extern "C" fn count_hook(L: LuaState, _ar: *mut lua_Debug) {
+        let exceeded = BUDGET.with(|budget| {
+            let mut budget = budget.borrow_mut();
+            let budget = budget.as_mut()?;
+            if let Some(left) = &mut budget.instructions_left {
+                *left = left.saturating_sub(CHECK_INTERVAL as u64);
+                if *left == 0 {
+                    budget.limit = Some("it ran too many instructions".to_string());
+                }
+            }
+            if budget.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                budget.limit = Some("it ran too long".to_string());
+            }
+            budget.limit.is_some().then_some(())
+        });
+        // Raised with nothing left to drop, as the error unwinds past this frame
+        if exceeded.is_some() {
+            unsafe {
+                lua_pushstring(L, c"expression stopped by the debugger".as_ptr());
+                lua_error(L);
+            }
+        }
+    }
+
+    unsafe {
+        let L = lua.state();
+        let thread = lua_newthread(L);
+        for _ in 0..=arguments {
+            lua_pushvalue(L, -(arguments + 2));
+        }
+        lua_xmove(L, thread, arguments + 1);
+        lua_sethook(thread, count_hook, LUA_MASKCOUNT, CHECK_INTERVAL);
+
+        let previous = BUDGET.with(|budget| {
+            budget.replace(Some(Budget {
+                instructions_left: limits.instructions,
+                deadline: limits.time.map(|time| Instant::now() + time),
+                limit: None,
+            }))
+        });
+        let mut results = 0;
+        let status = lua_resume(thread, L, arguments, &mut results);
+        let budget = BUDGET.with(|budget| budget.replace(previous));
+
+        match status {
+            LUA_OK => {
+                if results == 0 {
+                    lua_pushnil(L);
+                } else {
+                    lua_settop(thread, lua_gettop(thread) - results + 1);
+                    lua_xmove(thread, L, 1);
+                }
+                Ok(())
+            }
+            LUA_YIELD => Err(EvaluationError::Failed("Expressions cannot yield".to_string())),
+            _ => {
+                if let Some(limit) = budget.and_then(|budget| budget.limit) {
+                    return Err(EvaluationError::LimitExceeded(format!("Stopped the expression because {}", limit)));
+                }
+                let message = lua_tolstring(thread, -1, std::ptr::null_mut());
+                Err(EvaluationError::Failed(if message.is_null() {
+                    "Evaluation failed".to_string()
+                } else {
+                    CStr::from_ptr(message).to_string_lossy().into_owned()
+                }))
+            }
+        }
+    }
+}
+
+/// Calls a function with the `arguments` values above it on `lua`'s stack
+///
+/// Leaves the function's first result on top of the stack.
+#[cfg(feature = "dynamic-lua")]
+pub fn call_with_arguments(lua: &mut Lua, arguments: c_int, _limits: &EvaluationLimits) -> Result<(), EvaluationError> {
+    lua.pcall(arguments, 1).map(|_| ()).map_err(EvaluationError::Failed)
+}
+
+#[cfg(all(test, feature = "static-lua"))]
+mod tests {
+    use super::*;
+
+    fn evaluate(lua: &mut Lua, code: &str, limits: &EvaluationLimits) -> Result<Option<f64>, EvaluationError> {
+        let top = lua.get_top();
+        lua.load_string(code).map_err(EvaluationError::Failed)?;
+        let result = call(lua, limits).map(|()| lua.is_number(-1).then(|| lua.pop_number()));
+        lua.set_top(top);
+        result
+    }
+
+    #[test]
+    fn test_runaway_expressions_are_stopped() {
+        let mut lua = Lua::new();
+        let by_instructions = EvaluationLimits {
+            instructions: Some(100_000),
+            time: None,
+        };
+        let by_time = EvaluationLimits {
+            instructions: None,
+            time: Some(Duration::from_millis(50)),
+        };
+
+        assert_eq!(evaluate(&mut lua, "return 6 * 7", &by_instructions), Ok(Some(42.0)));
+        for limits in [by_instructions, by_time] {
+            match evaluate(&mut lua, "while true do end", &limits) {
+                Err(EvaluationError::LimitExceeded(message)) => assert!(message.starts_with("Stopped"), "{}", message),
+                other => panic!("Expected the loop to be stopped, got {:?}", other),
+            }
+        }
+        match evaluate(&mut lua, "error('boom')", &EvaluationLimits::NONE) {
+            Err(EvaluationError::Failed(message)) => assert!(message.contains("boom"), "{}", message),
+            other => panic!("Expected the error, got {:?}", other),
+        }
+        // The state is usable afterwards
+        assert_eq!(evaluate(&mut lua, "return 1 + 1", &EvaluationLimits::NONE), Ok(Some(2.0)));
+    }
+}
@@ -0,0 +1,429 @@
+//! One-line previews of Lua values for the Variables pane and the debug console
+//!
+//! A table shows its first fields, as in `{x = 1, y = {…}, n = 3}`, rather
+//! than its address, so most values can be read without expanding them.
+//! Previews nest `MAX_DEPTH` tables deep and list `MAX_FIELDS` fields of each;
+//! a table already being previewed shows as `<cycle>`. Values whose
+//! metatable has `__tostring` show what it returns, run within limits so a
+//! slow one cannot hang the stopped program. Strings are quoted with Lua's
+//! escapes and cut after the configured number of characters.
+//!
+//! For the clipboard, values are written out whole as Lua literals instead,
+//! which paste back into code or the debug console.
+
+use super::lua_ffi::*;
+use super::lua_state::Lua;
+use super::puc_lua::decode_lua_string;
+use super::sandbox::{self, EvaluationLimits};
+use super::ValueFormat;
+use crate::debug::completions::is_identifier;
+use std::time::Duration;
+
+/// Tables previewed inside one another before the rest show as `{…}`
+const MAX_DEPTH: usize = 2;
+
+/// Fields previewed per table before the rest show as `…`
+const MAX_FIELDS: usize = 8;
+
+/// Characters of a table's preview after which its remaining fields are left out
+const MAX_PREVIEW_LENGTH: usize = 120;
+
+/// Tables written inside one another in a literal before the rest show as a comment
+const MAX_LITERAL_DEPTH: usize = 100;
+
+/// How long and how far a `__tostring` metamethod may run
+const TOSTRING_LIMITS: EvaluationLimits = EvaluationLimits {
+    instructions: Some(100_000),
+    time: Some(Duration::from_millis(100)),
+};
+
+/// Previews the value on top of the stack, leaving the stack as it was
+///
+/// Strings are cut after `max_string_length` characters; numbers are shown in `format`.
+pub fn preview_top(lua: &mut Lua, max_string_length: usize, format: ValueFormat) -> String {
+    let index = lua.get_top();
+    preview(lua, index, 0, &mut Vec::new(), max_string_length, format)
+}
+
+/// Previews the value at the absolute `index`, `depth` tables deep
+///
+/// `visiting` holds the tables being previewed around this one.
+fn preview(
+    lua: &mut Lua,
+    index: c_int,
+    depth: usize,
+    visiting: &mut Vec<usize>,
+    max_string_length: usize,
+    format: ValueFormat,
+) -> String {
+    let value_type = lua.type_of(index);
+    match value_type {
+        LUA_TNIL => "nil".to_string(),
+        LUA_TBOOLEAN => (lua.lua_toboolean(index) != 0).to_string(),
+        LUA_TNUMBER => format.format_number(lua.lua_tonumber(index)),
+        LUA_TSTRING => quote(&string_at(lua, index), max_string_length),
+        LUA_TTABLE => tostring_result(lua, index)
+            .unwrap_or_else(|| preview_table(lua, index, depth, visiting, max_string_length, format)),
+        LUA_TUSERDATA => {
+            tostring_result(lua, index).unwrap_or_else(|| format!("userdata: 0x{:x}", lua.topointer(index) as usize))
+        }
+        _ => format!("{}: 0x{:x}", lua.type_name(value_type), lua.topointer(index) as usize),
+    }
+}
+
+/// Previews the table at the absolute `index`: array elements first, then the other fields
+fn preview_table(
+    lua: &mut Lua,
+    index: c_int,
+    depth: usize,
+    visiting: &mut Vec<usize>,
+    max_string_length: usize,
+    format: ValueFormat,
+) -> String {
+    let pointer = lua.topointer(index) as usize;
+    if visiting.contains(&pointer) {
+        return "<cycle>".to_string();
+    }
+    let length = lua.raw_len(index);
+    if depth >= MAX_DEPTH {
+        let top = lua.get_top();
+        lua.push_nil();
+        let empty = lua.next(index) == 0;
+        lua.set_top(top);
+        return if empty { "{}" } else { "{…}" }.to_string();
+    }
+
+    visiting.push(pointer);
+    let mut fields: Vec<String> = Vec::new();
+    let mut preview_length = 0;
+    let full = |fields: &[String], preview_length: usize| {
+        fields.len() >= MAX_FIELDS || preview_length >= MAX_PREVIEW_LENGTH
+    };
+    let mut truncated = false;
+
+    for element in 1..=length {
+        if full(&fields, preview_length) {
+            truncated = true;
+            break;
+        }
+        lua.lua_rawgeti(index, element as i64);
+        let value = preview(lua, lua.get_top(), depth + 1, visiting, max_string_length, format);
+        lua.lua_settop(-2);
+        preview_length += value.chars().count() + 2;
+        fields.push(value);
+    }
+
+    if !truncated {
+        let top = lua.get_top();
+        lua.push_nil();
+        while lua.next(index) != 0 {
+            let key = top + 1;
+            if !is_array_key(lua, key, length) {
+                if full(&fields, preview_length) {
+                    truncated = true;
+                    break;
+                }
+                let field = format!(
+                    "{} = {}",
+                    preview_key(lua, key, visiting, max_string_length, format),
+                    preview(lua, key + 1, depth + 1, visiting, max_string_length, format)
+                );
+                preview_length += field.chars().count() + 2;
+                fields.push(field);
+            }
+            // Remove value, keep key for next iteration
+            lua.lua_settop(-2);
+        }
+        lua.set_top(top);
+    }
+    visiting.pop();
+
+    if truncated {
+        fields.push("…".to_string());
+    }
+    format!("{{{}}}", fields.join(", "))
+}
+
+/// Writes the value on top of the stack as a Lua literal, leaving the stack as it was
+///
+/// Nothing is left out: every field of every nested table is written, raw,
+/// and strings are not cut. Values a literal cannot hold, such as functions
+/// and tables that contain themselves, are written as `nil` followed by a
+/// comment saying what they were; fields keyed by one are left out.
+pub fn literal_top(lua: &mut Lua) -> String {
+    let index = lua.get_top();
+    literal(lua, index, &mut Vec::new())
+}
+
+/// The literal of the value at the absolute `index`
+///
+/// `visiting` holds the tables being written around this one.
+fn literal(lua: &mut Lua, index: c_int, visiting: &mut Vec<usize>) -> String {
+    let value_type = lua.type_of(index);
+    match value_type {
+        LUA_TNIL => "nil".to_string(),
+        LUA_TBOOLEAN => (lua.lua_toboolean(index) != 0).to_string(),
+        LUA_TNUMBER => number_literal(lua.lua_tonumber(index)),
+        LUA_TSTRING => quote(&string_at(lua, index), usize::MAX),
+        LUA_TTABLE => table_literal(lua, index, visiting),
+        _ => format!("nil --[[{}: 0x{:x}]]", lua.type_name(value_type), lua.topointer(index) as usize),
+    }
+}
+
+/// A number as Lua reads it back, infinities and NaN included
+fn number_literal(number: f64) -> String {
+    if number.is_nan() {
+        "0/0".to_string()
+    } else if number.is_infinite() {
+        if number > 0.0 { "math.huge" } else { "-math.huge" }.to_string()
+    } else {
+        number.to_string()
+    }
+}
+
+/// The literal of the table at the absolute `index`: array elements first, then the other fields
+fn table_literal(lua: &mut Lua, index: c_int, visiting: &mut Vec<usize>) -> String {
+    let pointer = lua.topointer(index) as usize;
+    if visiting.contains(&pointer) {
+        return "nil --[[<cycle>]]".to_string();
+    }
+    if visiting.len() >= MAX_LITERAL_DEPTH || !lua.check_stack(4) {
+        return "nil --[[nested too deep]]".to_string();
+    }
+
+    visiting.push(pointer);
+    let length = lua.raw_len(index);
+    let mut fields: Vec<String> = Vec::new();
+    for element in 1..=length {
+        lua.lua_rawgeti(index, element as i64);
+        fields.push(literal(lua, lua.get_top(), visiting));
+        lua.lua_settop(-2);
+    }
+
+    let top = lua.get_top();
+    lua.push_nil();
+    while lua.next(index) != 0 {
+        let key = top + 1;
+        if !is_array_key(lua, key, length) {
+            if let Some(key_literal) = literal_key(lua, key, visiting) {
+                fields.push(format!("{} = {}", key_literal, literal(lua, key + 1, visiting)));
+            }
+        }
+        // Remove value, keep key for next iteration
+        lua.lua_settop(-2);
+    }
+    lua.set_top(top);
+    visiting.pop();
+
+    format!("{{{}}}", fields.join(", "))
+}
+
+/// A table key as a table constructor writes it; none for keys a literal cannot hold
+fn literal_key(lua: &mut Lua, index: c_int, visiting: &mut Vec<usize>) -> Option<String> {
+    if lua.type_of(index) == LUA_TSTRING {
+        let name = string_at(lua, index);
+        if is_identifier(&name) {
+            return Some(name);
+        }
+    }
+    let key = literal(lua, index, visiting);
+    (!key.starts_with("nil")).then(|| format!("[{}]", key))
+}
+
+/// Whether the key at `index` is one of the array elements already previewed
+fn is_array_key(lua: &Lua, index: c_int, length: usize) -> bool {
+    if lua.type_of(index) != LUA_TNUMBER {
+        return false;
+    }
+    let number = lua.lua_tonumber(index);
+    number.fract() == 0.0 && number >= 1.0 && number <= length as f64
+}
+
+/// Previews a table key as it would be written in a table constructor
+///
+/// Keys are read without converting them, which would confuse `lua_next`.
+fn preview_key(
+    lua: &mut Lua,
+    index: c_int,
+    visiting: &mut Vec<usize>,
+    max_string_length: usize,
+    format: ValueFormat,
+) -> String {
+    if lua.type_of(index) == LUA_TSTRING {
+        let name = string_at(lua, index);
+        if is_identifier(&name) {
+            return name;
+        }
+        return format!("[{}]", quote(&name, max_string_length));
+    }
+    // Tables used as keys show only as `{…}`
+    format!("[{}]", preview(lua, index, MAX_DEPTH, visiting, max_string_length, format))
+}
+
+/// What the `__tostring` metamethod of the value at `index` returns, if it has one that succeeds
+fn tostring_result(lua: &mut Lua, index: c_int) -> Option<String> {
+    let top = lua.get_top();
+    let result = (|| {
+        if lua.get_metatable(index) == 0 {
+            return None;
+        }
+        lua.push_string("__tostring");
+        lua.raw_get(-2);
+        if !lua.is_function(-1) {
+            return None;
+        }
+        lua.lua_pushvalue(index);
+        sandbox::call_with_arguments(lua, 1, &TOSTRING_LIMITS).ok()?;
+        (lua.type_of(-1) == LUA_TSTRING).then(|| string_at(lua, -1))
+    })();
+    lua.set_top(top);
+    result
+}
+
+/// The string at `index`, decoded for display
+fn string_at(lua: &Lua, index: c_int) -> String {
+    let mut len = 0;
+    let ptr = lua.lua_tolstring(index, &mut len);
+    let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len) };
+    decode_lua_string(lua.state(), bytes)
+}
+
+/// Quotes a string with the escapes Lua reads back, cut after `max_length` characters
+fn quote(text: &str, max_length: usize) -> String {
+    let mut quoted = String::with_capacity(text.len().min(max_length) + 2);
+    quoted.push('"');
+    for (count, c) in text.chars().enumerate() {
+        if count == max_length {
+            quoted.push('…');
+            break;
+        }
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            // Three-digit decimal escapes, which every Lua version reads
+            c if c.is_control() && (c as u32) < 256 => quoted.push_str(&format!("\\{:03}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(all(test, feature = "static-lua"))]
+mod tests {
+    use super::*;
+
+    const MAX_STRING_LENGTH: usize = 200;
+
+    fn preview_of(lua: &mut Lua, code: &str) -> String {
+        preview_formatted(lua, code, ValueFormat::default())
+    }
+
+    fn preview_formatted(lua: &mut Lua, code: &str, format: ValueFormat) -> String {
+        lua.load_string(&format!("return {}", code)).unwrap();
+        lua.pcall(0, 1).unwrap();
+        let top = lua.get_top();
+        let preview = preview_top(lua, MAX_STRING_LENGTH, format);
+        assert_eq!(lua.get_top(), top, "the preview of {} left the stack changed", code);
+        lua.set_top(top - 1);
+        preview
+    }
+
+    #[test]
+    fn test_tables_preview_their_fields() {
+        let mut lua = Lua::new();
+        assert_eq!(preview_of(&mut lua, "{}"), "{}");
+        assert_eq!(preview_of(&mut lua, "{1, 'two', true}"), "{1, \"two\", true}");
+        assert_eq!(preview_of(&mut lua, "{x = 1}"), "{x = 1}");
+        assert_eq!(preview_of(&mut lua, "{10, ['a b'] = 2}"), "{10, [\"a b\"] = 2}");
+        assert_eq!(preview_of(&mut lua, "{y = {z = {}}}"), "{y = {z = {}}}");
+        assert_eq!(preview_of(&mut lua, "{y = {z = {1}}}"), "{y = {z = {…}}}");
+        assert_eq!(preview_of(&mut lua, "{1, 2, 3, 4, 5, 6, 7, 8, 9, 10}"), "{1, 2, 3, 4, 5, 6, 7, 8, …}");
+    }
+
+    #[test]
+    fn test_cycles_and_tostring() {
+        let mut lua = Lua::new();
+        lua.execute("cyclic = {}; cyclic.self = cyclic").unwrap();
+        assert_eq!(preview_of(&mut lua, "cyclic"), "{self = <cycle>}");
+
+        lua.execute("Point = { __tostring = function(p) return '(' .. p.x .. ', ' .. p.y .. ')' end }")
+            .unwrap();
+        assert_eq!(preview_of(&mut lua, "{at = setmetatable({x = 1, y = 2}, Point)}"), "{at = (1, 2)}");
+        // A failing or runaway metamethod falls back to the fields
+        assert_eq!(
+            preview_of(&mut lua, "setmetatable({x = 1}, { __tostring = function() error('no') end })"),
+            "{x = 1}"
+        );
+        assert_eq!(
+            preview_of(&mut lua, "setmetatable({x = 1}, { __tostring = function() while true do end end })"),
+            "{x = 1}"
+        );
+    }
+
+    #[test]
+    fn test_strings_are_escaped_and_cut() {
+        let mut lua = Lua::new();
+        assert_eq!(preview_of(&mut lua, r#""say \"hi\"\n\1""#), r#""say \"hi\"\n\001""#);
+        let long = preview_of(&mut lua, "string.rep('a', 500)");
+        assert_eq!(long.chars().count(), MAX_STRING_LENGTH + 3);
+        assert!(long.ends_with("a…\""));
+    }
+
+    #[test]
+    fn test_numbers_follow_the_format() {
+        let mut lua = Lua::new();
+        let hex = ValueFormat { hex: true, precision: Some(2) };
+        assert_eq!(preview_formatted(&mut lua, "255", hex), "0xff");
+        assert_eq!(preview_formatted(&mut lua, "{-16, 1/3, x = 10}", hex), "{-0x10, 0.33, x = 0xa}");
+        assert_eq!(preview_formatted(&mut lua, "math.pi", ValueFormat { hex: false, precision: Some(3) }), "3.142");
+        assert_eq!(preview_of(&mut lua, "math.huge"), "inf");
+    }
+
+    fn literal_of(lua: &mut Lua, code: &str) -> String {
+        lua.load_string(&format!("return {}", code)).unwrap();
+        lua.pcall(0, 1).unwrap();
+        let top = lua.get_top();
+        let literal = literal_top(lua);
+        assert_eq!(lua.get_top(), top, "the literal of {} left the stack changed", code);
+        lua.set_top(top - 1);
+        literal
+    }
+
+    #[test]
+    fn test_literals_hold_whole_values() {
+        let mut lua = Lua::new();
+        assert_eq!(
+            literal_of(&mut lua, "{1, 'two', {x = 0.5}, {['a b'] = true}, {[3] = 'c'}}"),
+            "{1, \"two\", {x = 0.5}, {[\"a b\"] = true}, {[3] = \"c\"}}"
+        );
+        assert_eq!(literal_of(&mut lua, "{1/0, -1/0}"), "{math.huge, -math.huge}");
+
+        // Strings and tables are not cut, and __tostring is not used
+        let long = literal_of(&mut lua, "string.rep('a', 500)");
+        assert_eq!(long.len(), 502);
+        let wide = literal_of(
+            &mut lua,
+            "setmetatable({1, 2, 3, 4, 5, 6, 7, 8, 9, 10}, { __tostring = function() return 'x' end })",
+        );
+        assert_eq!(wide, "{1, 2, 3, 4, 5, 6, 7, 8, 9, 10}");
+    }
+
+    #[test]
+    fn test_literals_mark_what_they_cannot_hold() {
+        let mut lua = Lua::new();
+        lua.execute("cyclic = { n = 1 }; cyclic.self = cyclic; cyclic[print] = 2").unwrap();
+        let literal = literal_of(&mut lua, "cyclic");
+        assert!(literal.contains("self = nil --[[<cycle>]]"), "{}", literal);
+        assert!(!literal.contains("= 2"), "{}", literal);
+        assert!(literal_of(&mut lua, "{print}").starts_with("{nil --[[function: 0x"));
+
+        // What is written reads back into the same shape
+        lua.execute(&format!("copy = {}", literal)).unwrap();
+        assert_eq!(literal_of(&mut lua, "copy.n"), "1");
+        assert_eq!(literal_of(&mut lua, "copy.self"), "nil");
+    }
+}
@@ -0,0 +1,142 @@
+//! Handles behind the `variablesReference` values given to the client
+//!
+//! DAP names every expandable scope and value with a positive integer and
+//! expects it to keep working for as long as the program stays stopped. Each
+//! handle maps to a scope of a frame, the globals, or a table or userdata
+//! pinned with `luaL_ref` in a registry table, so nested values expand to the
+//! same object however deep they sit. Resuming the program releases them all.
+
+use super::lua_ffi::*;
+use super::lua_state::Lua;
+use super::DisplayLimits;
+use libc::c_int;
+
+/// Registry key of the table pinning values handed out during the current stop
+pub const PINNED_VALUES_KEY: &str = "wayfinder.variables";
+
+/// What a handle stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariableReference {
+    Locals { frame: i64 },
+    Upvalues { frame: i64 },
+    Globals,
+    /// A value pinned in the `PINNED_VALUES_KEY` table
+    Value { slot: c_int },
+}
+
+/// Handles handed out since the program last stopped
+#[derive(Debug, Default)]
+pub struct VariableRefs {
+    references: Vec<VariableReference>,
+    /// Whether the pinned values table was created during this stop
+    pinned_table: bool,
+    /// How much of the values behind the handles is shown; kept across stops
+    limits: DisplayLimits,
+}
+
+impl VariableRefs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the handle of a reference, reusing the handle of an equal scope
+    pub fn register(&mut self, reference: VariableReference) -> i64 {
+        let existing = match reference {
+            VariableReference::Value { .. } => None,
+            _ => self.references.iter().position(|r| *r == reference),
+        };
+        let index = existing.unwrap_or_else(|| {
+            self.references.push(reference);
+            self.references.len() - 1
+        });
+        // Zero tells the client a variable cannot be expanded
+        index as i64 + 1
+    }
+
+    pub fn get(&self, handle: i64) -> Option<VariableReference> {
+        let index = usize::try_from(handle).ok()?.checked_sub(1)?;
+        self.references.get(index).copied()
+    }
+
+    /// Pins the value on top of the stack and returns its handle
+    ///
+    /// The value stays on the stack.
+    pub fn pin_top(&mut self, lua: &mut Lua) -> i64 {
+        // Replacing the table drops whatever the previous stop pinned
+        if !self.pinned_table {
+            lua.create_table(0, 0);
+            lua.set_field(LUA_REGISTRYINDEX, PINNED_VALUES_KEY);
+            self.pinned_table = true;
+        }
+
+        lua.get_field(LUA_REGISTRYINDEX, PINNED_VALUES_KEY);
+        lua.lua_pushvalue(-2);
+        let slot = lua.luaL_ref(-2);
+        lua.lua_settop(-2);
+        self.register(VariableReference::Value { slot })
+    }
+
+    /// Pushes a pinned value
+    ///
+    /// Leaves the pinned values table below it; callers restore the stack top.
+    pub fn push_pinned(&self, lua: &mut Lua, slot: c_int) -> bool {
+        if !self.pinned_table || lua.get_field(LUA_REGISTRYINDEX, PINNED_VALUES_KEY) != LUA_TTABLE {
+            return false;
+        }
+        lua.lua_rawgeti(-1, slot as i64) != LUA_TNIL
+    }
+
+    pub fn limits(&self) -> DisplayLimits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: DisplayLimits) {
+        self.limits = limits;
+    }
+
+    /// Invalidates every handle, once the values they point at may have changed
+    pub fn clear(&mut self) {
+        self.references.clear();
+        self.pinned_table = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scopes_keep_their_handle() {
+        let mut refs = VariableRefs::new();
+        let locals = refs.register(VariableReference::Locals { frame: 0 });
+        let globals = refs.register(VariableReference::Globals);
+        assert_eq!(locals, 1);
+        assert_eq!(refs.register(VariableReference::Locals { frame: 0 }), locals);
+        assert_eq!(refs.get(globals), Some(VariableReference::Globals));
+        assert_eq!(refs.get(0), None);
+
+        refs.clear();
+        assert_eq!(refs.get(locals), None);
+    }
+
+    #[cfg(feature = "static-lua")]
+    #[test]
+    fn test_pinned_values_expire_with_the_stop() {
+        let mut lua = Lua::new();
+        let mut refs = VariableRefs::new();
+        lua.execute("pinned = { 10 }").unwrap();
+        lua.get_global("pinned");
+        let handle = refs.pin_top(&mut lua);
+        lua.set_top(0);
+
+        let Some(VariableReference::Value { slot }) = refs.get(handle) else {
+            panic!("Expected a pinned value");
+        };
+        assert!(refs.push_pinned(&mut lua, slot));
+        assert_eq!(lua.lua_rawgeti(-1, 1), LUA_TNUMBER);
+        lua.set_top(0);
+
+        refs.clear();
+        assert!(!refs.push_pinned(&mut lua, slot));
+    }
+}
@@ -0,0 +1,203 @@
+//! Where Lua DLLs are installed on Windows
+//!
+//! Windows has no standard library directory, so dynamic builds look where
+//! the usual distributions put Lua: the directories named by `LUA_DEV` (Lua
+//! for Windows) and `LUA_DIR`, the install locations of Lua entries in the
+//! registry's list of installed programs, vcpkg, Scoop, MSYS2, `Program
+//! Files`, and finally PATH. MSVC builds name the DLL `lua54.dll` or
+//! `lua5.4.dll`; MinGW builds often add a `lib` prefix, so the names a
+//! MinGW build of Wayfinder was more likely linked against come first.
+//! vcpkg's port names it just `lua.dll`, which the loader only takes once
+//! its exports show the version.
+
+use super::LuaVersion;
+use std::path::{Path, PathBuf};
+
+/// vcpkg triplets of the DLLs searched for, by how common they are
+const VCPKG_TRIPLETS: &[&str] = &["x64-windows", "x64-mingw-dynamic", "x86-windows", "arm64-windows"];
+
+/// MSYS2 environments that ship Lua
+const MSYS2_ENVIRONMENTS: &[&str] = &["ucrt64", "mingw64", "clang64", "mingw32"];
+
+/// The file names of a version's DLL, the likeliest first
+///
+/// `mingw` puts the `lib` prefixed names of MinGW builds first.
+pub fn dll_names(version: LuaVersion, mingw: bool) -> Vec<String> {
+    let dotted = version.to_string();
+    let compact = dotted.replace('.', "");
+    let msvc = [format!("lua{}.dll", compact), format!("lua{}.dll", dotted)];
+    let gnu = [format!("liblua{}.dll", compact), format!("liblua{}.dll", dotted)];
+    let mut names: Vec<String> = if mingw {
+        gnu.into_iter().chain(msvc).collect()
+    } else {
+        msvc.into_iter().chain(gnu).collect()
+    };
+    names.push("lua.dll".to_string());
+    names
+}
+
+/// The directories to search for a version's DLL, in order
+///
+/// `env` reads environment variables and `installed` holds the install
+/// locations the registry lists for Lua, both passed in so the order can be
+/// checked on any platform.
+pub fn search_dirs(version: LuaVersion, env: impl Fn(&str) -> Option<String>, installed: &[PathBuf]) -> Vec<PathBuf> {
+    let dotted = version.to_string();
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let mut with_bin = |dirs: &mut Vec<PathBuf>, dir: PathBuf| {
+        dirs.push(dir.join("bin"));
+        dirs.push(dir);
+    };
+
+    for variable in ["LUA_DEV", "LUA_DIR"] {
+        if let Some(dir) = env(variable) {
+            with_bin(&mut dirs, PathBuf::from(dir));
+        }
+    }
+    for dir in installed {
+        with_bin(&mut dirs, dir.clone());
+    }
+    if let Some(root) = env("VCPKG_ROOT") {
+        for triplet in VCPKG_TRIPLETS {
+            dirs.push(Path::new(&root).join("installed").join(triplet).join("bin"));
+        }
+    }
+    if let Some(profile) = env("USERPROFILE") {
+        dirs.push(Path::new(&profile).join(r"scoop\apps\lua\current"));
+    }
+    let msys2 = env("MSYS2_ROOT").unwrap_or_else(|| r"C:\msys64".to_string());
+    for environment in MSYS2_ENVIRONMENTS {
+        dirs.push(Path::new(&msys2).join(environment).join("bin"));
+    }
+    for variable in ["ProgramFiles", "ProgramFiles(x86)"] {
+        if let Some(program_files) = env(variable) {
+            let lua = Path::new(&program_files).join("Lua");
+            with_bin(&mut dirs, lua.join(&dotted));
+            with_bin(&mut dirs, lua);
+        }
+    }
+    if let Some(path) = env("PATH") {
+        dirs.extend(std::env::split_paths(&path));
+    }
+
+    let mut unique: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        if !unique.contains(&dir) {
+            unique.push(dir);
+        }
+    }
+    unique
+}
+
+/// The files to try for a version's DLL, every name in every directory
+pub fn candidates(version: LuaVersion, dirs: &[PathBuf], mingw: bool) -> Vec<PathBuf> {
+    let names = dll_names(version, mingw);
+    dirs.iter().flat_map(|dir| names.iter().map(move |name| dir.join(name))).collect()
+}
+
+/// Install locations of the programs named Lua in the registry's list of installed programs
+///
+/// Read with `reg.exe`, which every Windows has; installers such as Lua for
+/// Windows and the LuaBinaries MSI register there.
+#[cfg(windows)]
+pub fn registry_install_dirs() -> Vec<PathBuf> {
+    const UNINSTALL_KEYS: &[&str] = &[
+        r"HKLM\SOFTWARE\Microsoft\Windows\CurrentVersion\Uninstall",
+        r"HKLM\SOFTWARE\WOW6432Node\Microsoft\Windows\CurrentVersion\Uninstall",
+        r"HKCU\SOFTWARE\Microsoft\Windows\CurrentVersion\Uninstall",
+    ];
+    let query = |arguments: &[&str]| {
+        std::process::Command::new("reg")
+            .arg("query")
+            .args(arguments)
+            .output()
+            .ok()
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+            .unwrap_or_default()
+    };
+
+    let mut dirs = Vec::new();
+    for key in UNINSTALL_KEYS {
+        let programs = parse_reg_query(&query(&[key, "/s", "/f", "Lua", "/d"]));
+        for (program, _, name) in programs.iter().filter(|(_, value, _)| value == "DisplayName") {
+            if !name.starts_with("Lua") {
+                continue;
+            }
+            let locations = parse_reg_query(&query(&[program, "/v", "InstallLocation"]));
+            dirs.extend(locations.into_iter().filter(|(_, _, dir)| !dir.is_empty()).map(|(_, _, dir)| PathBuf::from(dir)));
+        }
+    }
+    dirs
+}
+
+/// The values in the output of `reg query`, as (key, value name, data)
+///
+/// Keys are printed on lines of their own, and each of their values on an
+/// indented line as name, type and data separated by four spaces.
+pub fn parse_reg_query(output: &str) -> Vec<(String, String, String)> {
+    let mut values = Vec::new();
+    let mut key = None;
+    for line in output.lines() {
+        if line.starts_with("HKEY_") {
+            key = Some(line.trim().to_string());
+            continue;
+        }
+        let Some(key) = &key else {
+            continue;
+        };
+        let mut fields = line.trim_start().splitn(3, "    ");
+        if let (Some(name), Some(kind), Some(data)) = (fields.next(), fields.next(), fields.next()) {
+            if kind.starts_with("REG_") {
+                values.push((key.clone(), name.to_string(), data.trim().to_string()));
+            }
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_msvc_and_mingw_names() {
+        assert_eq!(
+            dll_names(LuaVersion::V54, false),
+            ["lua54.dll", "lua5.4.dll", "liblua54.dll", "liblua5.4.dll", "lua.dll"]
+        );
+        assert_eq!(dll_names(LuaVersion::V51, true)[..2], ["liblua51.dll", "liblua5.1.dll"]);
+    }
+
+    #[test]
+    fn test_search_dirs_follow_the_usual_installs() {
+        let env = |name: &str| match name {
+            "LUA_DEV" => Some(r"C:\Program Files (x86)\Lua\5.1".to_string()),
+            "VCPKG_ROOT" => Some(r"C:\vcpkg".to_string()),
+            "ProgramFiles" => Some(r"C:\Program Files".to_string()),
+            _ => None,
+        };
+        let dirs = search_dirs(LuaVersion::V54, env, &[PathBuf::from(r"D:\Tools\Lua")]);
+        let position = |dir: &Path| dirs.iter().position(|d| d == dir).unwrap_or_else(|| panic!("{:?} missing", dir));
+
+        let lua_dev = position(Path::new(r"C:\Program Files (x86)\Lua\5.1"));
+        let registered = position(Path::new(r"D:\Tools\Lua"));
+        let vcpkg = position(&Path::new(r"C:\vcpkg").join("installed").join("x64-windows").join("bin"));
+        let program_files = position(&Path::new(r"C:\Program Files").join("Lua").join("5.4"));
+        assert!(lua_dev < registered && registered < vcpkg && vcpkg < program_files);
+        assert!(dirs.contains(&Path::new(r"C:\msys64").join("ucrt64").join("bin")));
+    }
+
+    #[test]
+    fn test_reg_query_output_is_read() {
+        let output = "\r\n\
+HKEY_LOCAL_MACHINE\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall\\LuaForWindows_is1\r\n    \
+DisplayName    REG_SZ    Lua for Windows 5.1.5-52\r\n    \
+InstallLocation    REG_SZ    C:\\Program Files (x86)\\Lua\\5.1\\\r\n\r\n\
+End of search: 1 match(es) found.\r\n";
+        let values = parse_reg_query(output);
+        assert_eq!(values.len(), 2);
+        assert!(values[0].0.ends_with("LuaForWindows_is1"));
+        assert_eq!((values[0].1.as_str(), values[0].2.as_str()), ("DisplayName", "Lua for Windows 5.1.5-52"));
+        assert_eq!(values[1].2, "C:\\Program Files (x86)\\Lua\\5.1\\");
+    }
+}
@@ -0,0 +1,125 @@
+//! Encodings for profiles, heap snapshots and other large exports
+//!
+//! Plain JSON is what viewers open directly. Snapshots of a big heap run to
+//! hundreds of megabytes as JSON, so they can also be written as
+//! gzip-compressed JSON or as MessagePack, which is both smaller and faster
+//! to read back.
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Serializer {
+    #[default]
+    Json,
+    /// JSON compressed with gzip
+    JsonGzip,
+    MessagePack,
+}
+
+impl FromStr for Serializer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Serializer::Json),
+            "json.gz" | "jsonGzip" | "gzip" => Ok(Serializer::JsonGzip),
+            "msgpack" | "messagePack" => Ok(Serializer::MessagePack),
+            _ => Err(format!("Unknown serializer: {} (expected json, json.gz or msgpack)", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Serializer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Serializer::Json => write!(f, "json"),
+            Serializer::JsonGzip => write!(f, "json.gz"),
+            Serializer::MessagePack => write!(f, "msgpack"),
+        }
+    }
+}
+
+impl Serializer {
+    /// Serializer implied by a file name's extension, such as `profile.json.gz`
+    pub fn for_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Serializer::Json),
+            "gz" => Some(Serializer::JsonGzip),
+            "msgpack" | "mpk" => Some(Serializer::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// Whether the output is text that can be embedded in a response
+    pub fn is_text(&self) -> bool {
+        *self == Serializer::Json
+    }
+
+    pub fn to_vec<T: Serialize + ?Sized>(&self, value: &T) -> io::Result<Vec<u8>> {
+        match self {
+            Serializer::Json => Ok(serde_json::to_vec(value)?),
+            Serializer::JsonGzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                serde_json::to_writer(&mut encoder, value)?;
+                encoder.finish()
+            }
+            Serializer::MessagePack => {
+                rmp_serde::to_vec_named(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+        }
+    }
+
+    pub fn from_slice<T: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<T> {
+        match self {
+            Serializer::Json => Ok(serde_json::from_slice(bytes)?),
+            Serializer::JsonGzip => {
+                let mut json = Vec::new();
+                GzDecoder::new(bytes).read_to_end(&mut json)?;
+                Ok(serde_json::from_slice(&json)?)
+            }
+            Serializer::MessagePack => {
+                rmp_serde::from_slice(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+        }
+    }
+
+    /// Writes a value to a file
+    pub fn write<T: Serialize + ?Sized>(&self, value: &T, path: &Path) -> io::Result<()> {
+        let bytes = self.to_vec(value)?;
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_round_trip() {
+        let value = json!({ "functions": [{ "name": "update", "selfTimeMs": 1.5 }], "totalSamples": 3 });
+        for serializer in [Serializer::Json, Serializer::JsonGzip, Serializer::MessagePack] {
+            let bytes = serializer.to_vec(&value).unwrap();
+            let read: serde_json::Value = serializer.from_slice(&bytes).unwrap();
+            assert_eq!(read, value, "{}", serializer);
+        }
+    }
+
+    #[test]
+    fn test_serializer_for_path() {
+        assert_eq!(Serializer::for_path(Path::new("heap.json")), Some(Serializer::Json));
+        assert_eq!(Serializer::for_path(Path::new("heap.json.gz")), Some(Serializer::JsonGzip));
+        assert_eq!(Serializer::for_path(Path::new("heap.msgpack")), Some(Serializer::MessagePack));
+        assert_eq!(Serializer::for_path(Path::new("heap")), None);
+        assert_eq!("json.gz".parse::<Serializer>(), Ok(Serializer::JsonGzip));
+    }
+}
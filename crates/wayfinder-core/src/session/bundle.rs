@@ -0,0 +1,200 @@
+//! Session artifact bundles for bug reports
+//!
+//! A bundle is a zip archive of everything useful for reproducing a problem:
+//! the recent protocol trace, the program's output, the breakpoints, the
+//! configuration, the last profile and the last uncaught error. Source code
+//! reaches the trace through `source` responses, evaluated expressions and
+//! breakpoint conditions; redaction blanks those so a bundle can be attached
+//! to a public issue.
+
+use super::trace::{Direction, TraceEntry};
+use crate::config::DebuggerConfig;
+use crate::debug::flight_recorder::FlightRecord;
+use crate::debug::inventory::BreakpointInventory;
+use crate::profiling::ProfileData;
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Fields whose values are source code: source contents, evaluated expressions and breakpoint code
+const SOURCE_FIELDS: &[&str] = &["content", "expression", "condition", "logMessage", "log_message"];
+
+/// Value redacted fields are replaced with
+pub const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BundleOptions {
+    /// Blank out source code wherever it appears
+    pub redact_sources: bool,
+}
+
+/// What goes into a bundle; artifacts the session does not have are left out
+#[derive(Debug, Clone, Default)]
+pub struct SessionBundle {
+    pub trace: Vec<TraceEntry>,
+    pub config: Option<DebuggerConfig>,
+    pub breakpoints: Option<BreakpointInventory>,
+    pub profile: Option<ProfileData>,
+    /// The most recent uncaught error
+    pub crash: Option<FlightRecord>,
+}
+
+impl SessionBundle {
+    /// The program's output, taken from the output events in the trace
+    pub fn output_log(&self) -> String {
+        self.trace
+            .iter()
+            .filter(|entry| entry.direction == Direction::Sent && entry.message["event"] == "output")
+            .filter_map(|entry| {
+                let body = &entry.message["body"];
+                let output = body["output"].as_str()?;
+                let category = body["category"].as_str().unwrap_or("console");
+                Some(format!("[{}] {}", category, output.trim_end_matches('\n')))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Writes the bundle as a zip archive and returns the names of the files in it
+    pub fn write(&self, path: &Path, options: BundleOptions) -> io::Result<Vec<String>> {
+        let mut files: Vec<(&str, Vec<u8>)> = Vec::new();
+
+        let mut trace = String::new();
+        for entry in &self.trace {
+            trace.push_str(&serde_json::to_string(&prepare(entry, options)?)?);
+            trace.push('\n');
+        }
+        files.push(("trace.jsonl", trace.into_bytes()));
+        files.push(("output.log", self.output_log().into_bytes()));
+        if let Some(config) = &self.config {
+            files.push(("config.json", pretty(config, options)?));
+        }
+        if let Some(breakpoints) = &self.breakpoints {
+            files.push(("breakpoints.json", pretty(breakpoints, options)?));
+        }
+        if let Some(profile) = &self.profile {
+            files.push(("profile.json", pretty(profile, options)?));
+        }
+        if let Some(crash) = &self.crash {
+            files.push(("crash.json", pretty(crash, options)?));
+        }
+
+        let mut names: Vec<String> = files.iter().map(|(name, _)| name.to_string()).collect();
+        let created_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let manifest = json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "createdMs": created_ms,
+            "redactedSources": options.redact_sources,
+            "files": names,
+        });
+        files.push(("manifest.json", serde_json::to_vec_pretty(&manifest)?));
+        names.push("manifest.json".to_string());
+
+        let mut zip = ZipWriter::new(std::fs::File::create(path)?);
+        let file_options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, bytes) in files {
+            zip.start_file(name, file_options)?;
+            zip.write_all(&bytes)?;
+        }
+        zip.finish()?;
+        Ok(names)
+    }
+}
+
+/// Serializes a value, redacting it when asked to
+fn prepare<T: Serialize>(value: &T, options: BundleOptions) -> io::Result<JsonValue> {
+    let mut value = serde_json::to_value(value)?;
+    if options.redact_sources {
+        redact_sources(&mut value);
+    }
+    Ok(value)
+}
+
+fn pretty<T: Serialize>(value: &T, options: BundleOptions) -> io::Result<Vec<u8>> {
+    Ok(serde_json::to_vec_pretty(&prepare(value, options)?)?)
+}
+
+/// Replaces every string held by a source code field, at any depth
+pub fn redact_sources(value: &mut JsonValue) {
+    match value {
+        JsonValue::Object(object) => {
+            for (key, field) in object.iter_mut() {
+                if SOURCE_FIELDS.contains(&key.as_str()) && field.is_string() {
+                    *field = JsonValue::String(REDACTED.to_string());
+                } else {
+                    redact_sources(field);
+                }
+            }
+        }
+        JsonValue::Array(items) => items.iter_mut().for_each(redact_sources),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn entry(direction: Direction, message: JsonValue) -> TraceEntry {
+        TraceEntry {
+            timestamp_ms: 0,
+            direction,
+            message,
+        }
+    }
+
+    fn bundle() -> SessionBundle {
+        SessionBundle {
+            trace: vec![
+                entry(Direction::Received, json!({ "command": "evaluate", "arguments": { "expression": "secret()" } })),
+                entry(Direction::Sent, json!({ "id": 2, "result": { "content": "local key = 42" } })),
+                entry(
+                    Direction::Sent,
+                    json!({ "type": "event", "event": "output", "body": { "category": "stdout", "output": "hi\n" } }),
+                ),
+            ],
+            config: Some(DebuggerConfig::default()),
+            ..SessionBundle::default()
+        }
+    }
+
+    #[test]
+    fn test_output_log_collects_output_events() {
+        assert_eq!(bundle().output_log(), "[stdout] hi");
+    }
+
+    #[test]
+    fn test_redact_sources() {
+        let mut value = json!({ "arguments": { "expression": "x + 1", "frameId": 1 }, "lines": [{ "condition": "x > 2" }] });
+        redact_sources(&mut value);
+        assert_eq!(value["arguments"]["expression"], REDACTED);
+        assert_eq!(value["arguments"]["frameId"], 1);
+        assert_eq!(value["lines"][0]["condition"], REDACTED);
+    }
+
+    #[test]
+    fn test_write_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.zip");
+        let names = bundle()
+            .write(&path, BundleOptions { redact_sources: true })
+            .unwrap();
+        assert_eq!(names, vec!["trace.jsonl", "output.log", "config.json", "manifest.json"]);
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let mut trace = String::new();
+        archive.by_name("trace.jsonl").unwrap().read_to_string(&mut trace).unwrap();
+        assert_eq!(trace.lines().count(), 3);
+        assert!(!trace.contains("secret()"));
+        assert!(!trace.contains("local key"));
+        assert!(trace.contains("hi\\n"));
+    }
+}
@@ -0,0 +1,324 @@
+//! What the connected client said about itself in `initialize`
+//!
+//! Clients differ in how they number positions and name files: VS Code
+//! counts lines and columns from 1 and sends paths, while other clients may
+//! count from 0 or send `file://` URIs. The handlers work with 1-based lines
+//! and columns and plain paths only, so messages are converted on their way
+//! in and out, as the source mapping does for TypeScript. Responses also
+//! leave out what the client said it cannot use.
+
+use crate::dap::Event;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+/// How the client names files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathFormat {
+    #[default]
+    Path,
+    Uri,
+}
+
+/// Arguments of the initialize request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientCapabilities {
+    #[serde(rename = "clientID", skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Human-readable name, such as `Visual Studio Code`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+    #[serde(rename = "adapterID", skip_serializing_if = "Option::is_none")]
+    pub adapter_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(default = "counts_from_one")]
+    pub lines_start_at1: bool,
+    #[serde(default = "counts_from_one")]
+    pub columns_start_at1: bool,
+    #[serde(default)]
+    pub path_format: PathFormat,
+    #[serde(default)]
+    pub supports_variable_type: bool,
+    /// Whether the client pages through `indexedVariables` and `namedVariables`
+    #[serde(default)]
+    pub supports_variable_paging: bool,
+    /// Whether the adapter can ask the client to run the program in a terminal
+    #[serde(default)]
+    pub supports_run_in_terminal_request: bool,
+    #[serde(default)]
+    pub supports_memory_references: bool,
+    #[serde(default)]
+    pub supports_progress_reporting: bool,
+    #[serde(default)]
+    pub supports_invalidated_event: bool,
+    #[serde(default)]
+    pub supports_memory_event: bool,
+    #[serde(default)]
+    pub supports_args_can_be_interpreted_by_shell: bool,
+    #[serde(default)]
+    pub supports_start_debugging_request: bool,
+}
+
+fn counts_from_one() -> bool {
+    true
+}
+
+impl Default for ClientCapabilities {
+    /// A client that sent nothing: positions count from 1, files are paths and nothing optional is supported
+    fn default() -> Self {
+        Self {
+            client_id: None,
+            client_name: None,
+            adapter_id: None,
+            locale: None,
+            lines_start_at1: true,
+            columns_start_at1: true,
+            path_format: PathFormat::Path,
+            supports_variable_type: false,
+            supports_variable_paging: false,
+            supports_run_in_terminal_request: false,
+            supports_memory_references: false,
+            supports_progress_reporting: false,
+            supports_invalidated_event: false,
+            supports_memory_event: false,
+            supports_args_can_be_interpreted_by_shell: false,
+            supports_start_debugging_request: false,
+        }
+    }
+}
+
+impl ClientCapabilities {
+    /// Reads the arguments of an initialize request, ignoring the ones not described here
+    pub fn from_request(params: &JsonValue) -> Result<Self, String> {
+        if params.is_null() {
+            return Ok(Self::default());
+        }
+        Self::deserialize(params).map_err(|e| format!("Invalid initialize arguments: {}", e))
+    }
+
+    /// The client's name, or its id when it gave none
+    pub fn name(&self) -> &str {
+        self.client_name.as_deref().or(self.client_id.as_deref()).unwrap_or("unknown client")
+    }
+
+    /// Whether the client uses the positions and paths the handlers do
+    fn is_native(&self) -> bool {
+        self.lines_start_at1 && self.columns_start_at1 && self.path_format == PathFormat::Path
+    }
+
+    /// Converts the positions and paths in a request's arguments to 1-based lines and columns and paths
+    pub fn translate_request(&self, arguments: &mut JsonValue) {
+        if !self.is_native() {
+            self.convert(arguments, true);
+        }
+    }
+
+    /// Converts a response's positions and paths for the client and drops what it cannot use
+    pub fn translate_response(&self, command: &str, response: &mut JsonValue) {
+        let Some(result) = response.get_mut("result") else { return };
+        if !self.is_native() {
+            self.convert(result, false);
+            // Where a completion starts is a column too
+            if command == "completions" && !self.columns_start_at1 {
+                if let Some(targets) = result["targets"].as_array_mut() {
+                    for target in targets {
+                        shift(&mut target["start"], false);
+                    }
+                }
+            }
+        }
+        let mut unsupported = Vec::new();
+        if !self.supports_variable_paging {
+            unsupported.extend(["indexedVariables", "namedVariables"]);
+        }
+        if !self.supports_memory_references {
+            unsupported.push("memoryReference");
+        }
+        if !unsupported.is_empty() {
+            if let Some(variables) = result.get_mut("variables").and_then(|v| v.as_array_mut()) {
+                variables.iter_mut().for_each(|variable| remove_fields(variable, &unsupported));
+            }
+            if matches!(command, "setVariable" | "setExpression" | "evaluate") {
+                remove_fields(result, &unsupported);
+            }
+        }
+    }
+
+    /// Converts the positions and paths an event carries for the client
+    pub fn translate_event(&self, event: &mut Event) {
+        if let Some(body) = event.body.as_mut().filter(|_| !self.is_native()) {
+            self.convert(body, false);
+        }
+    }
+
+    /// Converts every line, column and source path in a message, `incoming` from the client
+    fn convert(&self, value: &mut JsonValue, incoming: bool) {
+        match value {
+            JsonValue::Object(fields) => {
+                for (key, field) in fields.iter_mut() {
+                    match key.as_str() {
+                        "line" | "endLine" if !self.lines_start_at1 => shift(field, incoming),
+                        "lines" if !self.lines_start_at1 => {
+                            if let Some(lines) = field.as_array_mut() {
+                                lines.iter_mut().for_each(|line| shift(line, incoming));
+                            }
+                        }
+                        "column" | "endColumn" if !self.columns_start_at1 => shift(field, incoming),
+                        "source" => {
+                            self.convert_path(field, incoming);
+                            self.convert(field, incoming);
+                        }
+                        "sources" => {
+                            if let Some(sources) = field.as_array_mut() {
+                                sources.iter_mut().for_each(|source| self.convert_path(source, incoming));
+                            }
+                        }
+                        _ => self.convert(field, incoming),
+                    }
+                }
+            }
+            JsonValue::Array(items) => items.iter_mut().for_each(|item| self.convert(item, incoming)),
+            _ => {}
+        }
+    }
+
+    fn convert_path(&self, source: &mut JsonValue, incoming: bool) {
+        if self.path_format != PathFormat::Uri {
+            return;
+        }
+        let Some(path) = source.get("path").and_then(|path| path.as_str()) else { return };
+        let converted = if incoming { uri_to_path(path) } else { Some(path_to_uri(path)) };
+        if let Some(converted) = converted {
+            source["path"] = json!(converted);
+        }
+    }
+}
+
+/// Moves a position between the client's 0-based numbering and the handlers' 1-based one
+fn shift(position: &mut JsonValue, incoming: bool) {
+    let Some(number) = position.as_u64() else { return };
+    *position = json!(if incoming { number + 1 } else { number.saturating_sub(1) });
+}
+
+fn remove_fields(variable: &mut JsonValue, fields: &[&str]) {
+    if let Some(variable) = variable.as_object_mut() {
+        for field in fields {
+            variable.remove(*field);
+        }
+    }
+}
+
+/// The path of a `file://` URI; None for other schemes
+fn uri_to_path(uri: &str) -> Option<String> {
+    let rest = uri.strip_prefix("file://")?;
+    // An authority, usually empty, comes before the path
+    let path = &rest[rest.find('/').unwrap_or(rest.len())..];
+    let path = percent_decode(path);
+    // Windows drive letters come as /C:/...
+    let bytes = path.as_bytes();
+    if bytes.len() >= 3 && bytes[0] == b'/' && bytes[1].is_ascii_alphabetic() && bytes[2] == b':' {
+        return Some(path[1..].to_string());
+    }
+    Some(path)
+}
+
+fn path_to_uri(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let mut uri = String::from("file://");
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~:".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zero_based_uri_client() {
+        let client = ClientCapabilities::from_request(&json!({
+            "clientID": "neovim",
+            "adapterID": "wayfinder",
+            "linesStartAt1": false,
+            "columnsStartAt1": false,
+            "pathFormat": "uri"
+        }))
+        .unwrap();
+        assert_eq!(client.name(), "neovim");
+
+        let mut arguments = json!({
+            "source": { "path": "file:///home/me/my%20game/main.lua" },
+            "breakpoints": [{ "line": 0 }, { "line": 9, "column": 4 }]
+        });
+        client.translate_request(&mut arguments);
+        assert_eq!(arguments["source"]["path"], "/home/me/my game/main.lua");
+        assert_eq!(arguments["breakpoints"], json!([{ "line": 1 }, { "line": 10, "column": 5 }]));
+
+        let mut response = json!({ "result": { "stackFrames": [
+            { "id": 1, "name": "main", "line": 10, "column": 1, "source": { "name": "main.lua", "path": "/home/me/my game/main.lua" } }
+        ] } });
+        client.translate_response("stackTrace", &mut response);
+        let frame = &response["result"]["stackFrames"][0];
+        assert_eq!((frame["line"].clone(), frame["column"].clone()), (json!(9), json!(0)));
+        assert_eq!(frame["source"]["path"], "file:///home/me/my%20game/main.lua");
+
+        let mut event = Event::output_at("console", "hi\n", "/game/main.lua", 3);
+        client.translate_event(&mut event);
+        assert_eq!(event.body.unwrap()["line"], 2);
+    }
+
+    #[test]
+    fn test_defaults_and_gated_features() {
+        let client = ClientCapabilities::from_request(&json!({ "adapterID": "wayfinder" })).unwrap();
+        assert!(client.lines_start_at1 && client.columns_start_at1);
+        assert_eq!(client.path_format, PathFormat::Path);
+        assert!(!client.supports_run_in_terminal_request);
+
+        let mut response = json!({ "result": { "variables": [
+            { "name": "items", "value": "table", "variablesReference": 3, "indexedVariables": 200, "line": 4,
+              "memoryReference": "3" }
+        ] } });
+        client.translate_response("variables", &mut response);
+        assert_eq!(
+            response["result"]["variables"][0],
+            json!({ "name": "items", "value": "table", "variablesReference": 3, "line": 4 })
+        );
+
+        assert!(ClientCapabilities::from_request(&json!({ "pathFormat": "url" })).is_err());
+        assert_eq!(uri_to_path("file:///C:/game/main.lua").as_deref(), Some("C:/game/main.lua"));
+        assert_eq!(uri_to_path("https://example.com/main.lua"), None);
+    }
+}
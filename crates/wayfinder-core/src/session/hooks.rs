@@ -0,0 +1,123 @@
+//! Callbacks embedders run on session events
+//!
+//! Lets a host react to the session, such as capturing the game window on
+//! every stop, without driving the DAP loop itself. Callbacks run on the
+//! server's task as events pass through to the client, so they should return
+//! quickly.
+
+use crate::dap::Event;
+use crate::runtime::Breakpoint;
+
+/// Why the program stopped, as reported to the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoppedInfo {
+    pub reason: String,
+    pub thread_id: Option<u64>,
+}
+
+type Callback<T> = Box<dyn FnMut(&T) + Send>;
+type OutputCallback = Box<dyn FnMut(&str, &str) + Send>;
+
+/// Callbacks registered on a `DapServer`
+#[derive(Default)]
+pub struct SessionHooks {
+    stopped: Vec<Callback<StoppedInfo>>,
+    breakpoint_bound: Vec<Callback<Breakpoint>>,
+    output: Vec<OutputCallback>,
+}
+
+impl SessionHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_stopped(&mut self, callback: impl FnMut(&StoppedInfo) + Send + 'static) {
+        self.stopped.push(Box::new(callback));
+    }
+
+    pub fn on_breakpoint_bound(&mut self, callback: impl FnMut(&Breakpoint) + Send + 'static) {
+        self.breakpoint_bound.push(Box::new(callback));
+    }
+
+    /// Registers a callback taking the output category and text
+    pub fn on_output(&mut self, callback: impl FnMut(&str, &str) + Send + 'static) {
+        self.output.push(Box::new(callback));
+    }
+
+    /// Runs the callbacks for a breakpoint that is verified, as set or once its source loads
+    pub fn breakpoint_bound(&mut self, breakpoint: &Breakpoint) {
+        for callback in &mut self.breakpoint_bound {
+            callback(breakpoint);
+        }
+    }
+
+    /// Runs the callbacks an event on its way to the client concerns
+    pub fn dispatch(&mut self, event: &Event) {
+        let Some(body) = &event.body else { return };
+
+        match event.event.as_str() {
+            "stopped" => {
+                let info = StoppedInfo {
+                    reason: body.get("reason").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+                    thread_id: body.get("threadId").and_then(|v| v.as_u64()),
+                };
+                for callback in &mut self.stopped {
+                    callback(&info);
+                }
+            }
+            "breakpoint" => {
+                let Some(breakpoint) = body.get("breakpoint") else { return };
+                let breakpoint = Breakpoint {
+                    id: breakpoint.get("id").and_then(|v| v.as_i64()).unwrap_or_default(),
+                    verified: breakpoint.get("verified").and_then(|v| v.as_bool()).unwrap_or(false),
+                    line: breakpoint.get("line").and_then(|v| v.as_u64()).unwrap_or_default() as u32,
+                    message: breakpoint.get("message").and_then(|v| v.as_str()).map(str::to_string),
+                };
+                if breakpoint.verified {
+                    self.breakpoint_bound(&breakpoint);
+                }
+            }
+            "output" => {
+                let category = body.get("category").and_then(|v| v.as_str()).unwrap_or("console");
+                let output = body.get("output").and_then(|v| v.as_str()).unwrap_or_default();
+                for callback in &mut self.output {
+                    callback(category, output);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_dispatch_runs_matching_callbacks() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut hooks = SessionHooks::new();
+
+        let stops = seen.clone();
+        hooks.on_stopped(move |info| stops.lock().unwrap().push(format!("stopped {}", info.reason)));
+        let bound = seen.clone();
+        hooks.on_breakpoint_bound(move |bp| bound.lock().unwrap().push(format!("bound {}", bp.id)));
+        let output = seen.clone();
+        hooks.on_output(move |category, text| output.lock().unwrap().push(format!("{} {}", category, text)));
+
+        hooks.dispatch(&Event::stopped("breakpoint", Some(1), true));
+        hooks.dispatch(&Event::breakpoint(
+            "changed",
+            Breakpoint { id: 4, verified: false, line: 2, message: None },
+        ));
+        hooks.dispatch(&Event::breakpoint(
+            "changed",
+            Breakpoint { id: 5, verified: true, line: 2, message: None },
+        ));
+        hooks.dispatch(&Event::output("stdout", "hello"));
+        hooks.dispatch(&Event::terminated());
+
+        assert_eq!(*seen.lock().unwrap(), vec!["stopped breakpoint", "bound 5", "stdout hello"]);
+    }
+}
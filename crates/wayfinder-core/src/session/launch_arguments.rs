@@ -0,0 +1,198 @@
+//! Typed arguments of the launch request
+//!
+//! A launch request names the program to debug and how to run it. The
+//! arguments read here are merged with defaults, such as those from
+//! `wayfinder.yaml`, before anything uses them. Settings that only tune the
+//! debugger (`sourceEncoding`, `memoryLimitKb`, ...) are read from the merged
+//! request into [`SessionArguments`], which attach requests share.
+
+use super::rewrite_rules::RewriteRule;
+use crate::debug::source_paths::SourcePathConfig;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchArguments {
+    /// Script to load; a runtime that already has a program keeps it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+    /// Arguments the script finds in `arg`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Directory the program runs in, and where source maps are searched for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Environment variables set before the program loads
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Lua version to run the program under, such as `lua5.1`
+    #[serde(alias = "runtimeVersion", skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<String>,
+    /// Lua library to load instead of searching for the one of `runtime`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lua_library_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_on_entry: Option<bool>,
+    /// Whether to debug TypeScript sources through TSTL source maps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_maps: Option<bool>,
+    /// Template for `package.path`, see [`lua_paths`](crate::runtime::lua_paths)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lua_path: Option<String>,
+    /// Template for `package.cpath`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lua_cpath: Option<String>,
+    /// Directories `require` searches after the program's own
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_roots: Vec<String>,
+    /// Where the program's stdio goes: `internalConsole`, `integratedTerminal` or `externalTerminal`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub console: Option<String>,
+}
+
+impl LaunchArguments {
+    /// Reads the arguments of a launch request, ignoring the ones not described here
+    pub fn from_request(params: &JsonValue) -> Result<Self, String> {
+        if params.is_null() {
+            return Ok(Self::default());
+        }
+        Self::deserialize(params).map_err(|e| format!("Invalid launch arguments: {}", e))
+    }
+
+    /// Fills what the request left unset from `defaults`
+    ///
+    /// Environment variables are merged, the request's winning over the defaults'.
+    pub fn with_defaults(self, defaults: &LaunchArguments) -> Self {
+        let mut env = defaults.env.clone();
+        env.extend(self.env);
+        Self {
+            program: self.program.or_else(|| defaults.program.clone()),
+            args: if self.args.is_empty() { defaults.args.clone() } else { self.args },
+            cwd: self.cwd.or_else(|| defaults.cwd.clone()),
+            env,
+            runtime: self.runtime.or_else(|| defaults.runtime.clone()),
+            lua_library_path: self.lua_library_path.or_else(|| defaults.lua_library_path.clone()),
+            stop_on_entry: self.stop_on_entry.or(defaults.stop_on_entry),
+            source_maps: self.source_maps.or(defaults.source_maps),
+            lua_path: self.lua_path.or_else(|| defaults.lua_path.clone()),
+            lua_cpath: self.lua_cpath.or_else(|| defaults.lua_cpath.clone()),
+            source_roots: if self.source_roots.is_empty() { defaults.source_roots.clone() } else { self.source_roots },
+            console: self.console.or_else(|| defaults.console.clone()),
+        }
+    }
+
+    /// The request with these arguments written over it, for the settings read from it directly
+    pub fn overlay(&self, params: &JsonValue) -> JsonValue {
+        let mut overlaid = match params {
+            JsonValue::Object(_) => params.clone(),
+            _ => JsonValue::Object(Default::default()),
+        };
+        if let Ok(JsonValue::Object(fields)) = serde_json::to_value(self) {
+            for (key, value) in fields {
+                overlaid[key] = value;
+            }
+        }
+        overlaid
+    }
+}
+
+/// Arguments of launch and attach requests that tune the debugger rather than start a program
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionArguments {
+    #[serde(default)]
+    pub stop_on_entry: Option<bool>,
+    /// Where source maps are searched for, and what relative source paths are taken against
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// Encoding of source files, as a WHATWG label such as `windows-1252`
+    #[serde(default)]
+    pub source_encoding: Option<String>,
+    /// Memory the program may allocate; 0 removes the limit
+    #[serde(default)]
+    pub memory_limit_kb: Option<u64>,
+    #[serde(default)]
+    pub stitch_coroutine_stacks: Option<bool>,
+    /// Only read at launch, since recording starts with the program
+    #[serde(default)]
+    pub record_replay: Option<bool>,
+    #[serde(default)]
+    pub unsafe_memory_writes: Option<bool>,
+    /// Lines the execution trace keeps
+    #[serde(default)]
+    pub trace_lines: Option<usize>,
+    /// Breakpoint file to watch; `cwd`'s default file without it
+    #[serde(default)]
+    pub breakpoints_file: Option<String>,
+    #[serde(default)]
+    pub source_maps: Option<bool>,
+    #[serde(flatten)]
+    pub source_paths: SourcePathConfig,
+    /// Replaces the rules in effect when given
+    #[serde(default)]
+    pub rewrite_rules: Option<Vec<RewriteRule>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_request_over_defaults() {
+        let defaults = LaunchArguments {
+            cwd: Some("/game".to_string()),
+            env: HashMap::from([("MODE".to_string(), "dev".to_string()), ("SEED".to_string(), "1".to_string())]),
+            runtime: Some("lua5.4".to_string()),
+            lua_library_path: Some("/opt/lua/liblua5.4.so".to_string()),
+            stop_on_entry: Some(true),
+            ..Default::default()
+        };
+        let params = json!({
+            "program": "main.lua",
+            "args": ["--level", "2"],
+            "env": { "SEED": "7" },
+            "runtimeVersion": "lua5.1",
+            "luaPath": "./?.lua",
+            "memoryLimitKb": 1024
+        });
+
+        let arguments = LaunchArguments::from_request(&params).unwrap().with_defaults(&defaults);
+        assert_eq!(arguments.program.as_deref(), Some("main.lua"));
+        assert_eq!(arguments.args, vec!["--level", "2"]);
+        assert_eq!(arguments.cwd.as_deref(), Some("/game"));
+        assert_eq!(arguments.env["SEED"], "7");
+        assert_eq!(arguments.env["MODE"], "dev");
+        assert_eq!(arguments.runtime.as_deref(), Some("lua5.1"));
+        assert_eq!(arguments.lua_library_path.as_deref(), Some("/opt/lua/liblua5.4.so"));
+        assert_eq!(arguments.stop_on_entry, Some(true));
+
+        let overlaid = arguments.overlay(&params);
+        assert_eq!(overlaid["cwd"], "/game");
+        assert_eq!(overlaid["stopOnEntry"], true);
+        assert_eq!(overlaid["memoryLimitKb"], 1024);
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        let error = LaunchArguments::from_request(&json!({ "args": "--level 2" })).unwrap_err();
+        assert!(error.starts_with("Invalid launch arguments"));
+        assert_eq!(LaunchArguments::from_request(&JsonValue::Null), Ok(LaunchArguments::default()));
+    }
+
+    #[test]
+    fn test_session_arguments() {
+        let params = json!({ "program": "main.lua", "memoryLimitKb": 1024, "sourceRoot": "src", "traceLines": 50 });
+        let arguments = SessionArguments::deserialize(&params).unwrap();
+        assert_eq!(arguments.memory_limit_kb, Some(1024));
+        assert_eq!(arguments.trace_lines, Some(50));
+        assert_eq!(arguments.source_paths.source_root.as_deref(), Some("src"));
+        assert_eq!(arguments.rewrite_rules, None);
+
+        assert!(SessionArguments::deserialize(&json!({ "memoryLimitKb": "big" })).is_err());
+        assert!(SessionArguments::deserialize(&json!({ "stitchCoroutineStacks": "yes" })).is_err());
+        assert!(SessionArguments::deserialize(&json!({ "rewriteRules": { "command": "next" } })).is_err());
+    }
+}
@@ -1,18 +1,71 @@
-use super::config::DebuggerConfig;
+pub mod bundle;
+pub mod client;
+pub mod hooks;
+pub mod launch_arguments;
+pub mod output_capture;
+pub mod panics;
+pub mod process_tree;
+pub mod replay;
+pub mod rewrite_rules;
+pub mod source_mapping;
+pub mod terminal;
+pub mod trace;
+
+use super::config::{DebuggerConfig, DebuggerSettings};
+use super::dap::arguments::{self, SourceBreakpoint};
+use super::dap::transport::{self as dap_transport, DapTransport};
+use super::dap::{event_channel, Event, EventReceiver, EventSender, Response};
+use super::debug::breakpoint_file::{self, BreakpointFileWatcher};
 use super::debug::breakpoints::BreakpointManager;
-use super::debug::conditions::ConditionEvaluator;
+use super::debug::completions;
+use super::debug::conditions::{self, ConditionEvaluator};
 use super::debug::hit_conditions;
+use super::debug::inventory::{BreakpointInventory, InventoryScope};
 use super::debug::logpoints::LogpointEvaluator;
-use super::debug::watchpoints::WatchpointManager;
-use super::hot_reload::WarningSeverity;
-use super::runtime::{BreakpointType, DebugRuntime, Frame, Scope, StepMode, Variable, Value};
+use super::debug::side_effects::EvaluationContext;
+use super::debug::watches::{WatchManager, WatchStatus};
+use super::debug::watchpoints::{AccessType, DataBreakpoint, DataType, WatchpointManager};
+use super::hot_reload::watcher::{self as hot_reload_watcher, FileWatcher, WatcherConfig};
+use super::hot_reload::{FieldOutcome, FieldPreservation, HotReloadResult, WarningSeverity};
+use super::internals::{self, LockState, TaskKind, TaskRole};
+use client::ClientCapabilities;
+use hooks::{SessionHooks, StoppedInfo};
+use launch_arguments::{LaunchArguments, SessionArguments};
+use output_capture::OutputCapture;
+use rewrite_rules::RewriteRules;
+use super::runtime::lua_paths::{self, LuaPathConfig, LuaPaths};
+use source_mapping::SourceMapping;
+use terminal::{PendingTerminalLaunch, TerminalLauncher};
+use super::debug::source_paths::PathResolver;
+use trace::{Direction, ProtocolTrace};
+use super::runtime::{
+    error_code, BreakpointType, DebugRuntime, Frame, FrameStepTarget, RuntimeError, Scope, StepGranularity, StepMode, Thread,
+    Value, ValueFormat, Variable, VariablesPage,
+};
+use base64::Engine;
 use serde_json::{json, Value as JsonValue};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncWrite};
+use tokio::sync::Notify;
+use tracing::Instrument;
 
 pub struct DebugSession<R: DebugRuntime> {
     runtime: R,
     breakpoint_manager: BreakpointManager,
     watchpoint_manager: WatchpointManager,
+    watch_manager: WatchManager,
     config: DebuggerConfig,
+    /// Channel to the DAP server for events raised by the session
+    events: Option<EventSender>,
+    /// Runtime ids of the line breakpoints set in each source
+    line_breakpoints: HashMap<String, Vec<i64>>,
+    /// Runtime ids of the function breakpoints
+    function_breakpoints: Vec<i64>,
+    /// Runtime ids of the installed metamethod breakpoints
+    metamethod_breakpoints: Vec<i64>,
+    /// Locations handed out as goto targets; a target id is its index plus one
+    goto_targets: Vec<(String, u32)>,
 }
 
 impl<R: DebugRuntime> DebugSession<R> {
@@ -21,10 +74,34 @@ impl<R: DebugRuntime> DebugSession<R> {
             runtime,
             breakpoint_manager: BreakpointManager::new(),
             watchpoint_manager: WatchpointManager::new(),
+            watch_manager: WatchManager::new(),
             config: DebuggerConfig::default(),
+            events: None,
+            line_breakpoints: HashMap::new(),
+            function_breakpoints: Vec::new(),
+            metamethod_breakpoints: Vec::new(),
+            goto_targets: Vec::new(),
         }
     }
 
+    /// Connects the session and its runtime to the DAP server's event channel
+    pub fn set_event_sender(&mut self, sender: EventSender) {
+        self.runtime.set_event_sender(sender.clone());
+        self.events = Some(sender);
+    }
+
+    /// Sends an event to the client, if a server is attached
+    pub fn emit(&self, event: Event) {
+        if let Some(sender) = &self.events {
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Starts the loaded program, which runs until something stops it
+    pub async fn start_program(&mut self, stop_on_entry: bool) -> Result<(), super::runtime::RuntimeError> {
+        self.runtime.start_program(stop_on_entry).await
+    }
+
     pub async fn run(&mut self) -> Result<(), super::runtime::RuntimeError> {
         self.runtime.continue_().await
     }
@@ -33,31 +110,122 @@ impl<R: DebugRuntime> DebugSession<R> {
         self.runtime.step(mode).await
     }
 
+    /// Steps by `granularity`, or by the configured `step_granularity` without one
+    pub async fn step_by(
+        &mut self,
+        mode: StepMode,
+        granularity: Option<StepGranularity>,
+    ) -> Result<(), super::runtime::RuntimeError> {
+        match granularity.unwrap_or(self.config.step_granularity) {
+            StepGranularity::Instruction => self.runtime.step_instruction(mode).await,
+            StepGranularity::Statement | StepGranularity::Line => self.runtime.step(mode).await,
+        }
+    }
+
+    /// Goes back one line by replaying the recorded run, see `time_travel`
+    pub async fn step_back(&mut self) -> Result<(), super::runtime::RuntimeError> {
+        self.runtime.step_back().await
+    }
+
+    /// Goes back to the previous breakpoint by replaying the recorded run
+    pub async fn reverse_continue(&mut self) -> Result<(), super::runtime::RuntimeError> {
+        self.runtime.reverse_continue().await
+    }
+
+    pub async fn step_frame(&mut self, function: &str, until: FrameStepTarget) -> Result<(), super::runtime::RuntimeError> {
+        self.runtime.step_frame(function, until).await
+    }
+
+    /// Resumes until the given location is reached or something else stops the program
+    pub async fn run_to_location(&mut self, source: &str, line: u32) -> Result<(), super::runtime::RuntimeError> {
+        self.runtime.run_to_location(source, line).await
+    }
+
+    /// Returns the goto target id for a location, reusing ids handed out before
+    pub fn goto_target_id(&mut self, source: &str, line: u32) -> i64 {
+        let position = self.goto_targets.iter().position(|(s, l)| s == source && *l == line);
+        let index = position.unwrap_or_else(|| {
+            self.goto_targets.push((source.to_string(), line));
+            self.goto_targets.len() - 1
+        });
+        index as i64 + 1
+    }
+
+    pub fn goto_target(&self, id: i64) -> Option<(String, u32)> {
+        let index = usize::try_from(id.checked_sub(1)?).ok()?;
+        self.goto_targets.get(index).cloned()
+    }
+
+    pub async fn threads(&mut self) -> Result<Vec<Thread>, super::runtime::RuntimeError> {
+        self.runtime.threads().await
+    }
+
     pub async fn stack_trace(&mut self, thread_id: Option<u64>) -> Result<Vec<Frame>, super::runtime::RuntimeError> {
-        self.runtime.stack_trace(thread_id).await
+        let frames = self.runtime.stack_trace(thread_id).await?;
+        if self.config.collapse_lualib_frames {
+            Ok(crate::debug::lualib::collapse_lualib_frames(frames))
+        } else {
+            Ok(frames)
+        }
     }
 
     pub async fn scopes(&mut self, frame_id: i64) -> Result<Vec<Scope>, super::runtime::RuntimeError> {
         self.runtime.scopes(frame_id).await
     }
 
-    pub async fn variables(&mut self, variables_reference: i64) -> Result<Vec<Variable>, super::runtime::RuntimeError> {
-        self.runtime.variables(variables_reference, None).await
+    pub async fn variables(
+        &mut self,
+        variables_reference: i64,
+        page: VariablesPage,
+    ) -> Result<Vec<Variable>, super::runtime::RuntimeError> {
+        let page = VariablesPage { format: page.format.or(self.config.value_format()), ..page };
+        self.runtime.variables_page(variables_reference, page).await
     }
 
-    pub async fn evaluate(&mut self, frame_id: i64, expression: &str) -> Result<Value, super::runtime::RuntimeError> {
-        // If mutation is enabled, we might want to track what changes
-        if self.config.evaluate_mutation {
-            // In a full implementation, we would:
-            // 1. Check if the expression is an assignment
-            // 2. If so, track what variable is being modified
-            // 3. Optionally show the modification in the UI
-            // 4. Apply safety checks based on config
-        }
-        
+    /// Evaluates an expression in a frame
+    ///
+    /// In hover and watch `context`s an expression that calls a function or
+    /// assigns is rejected, whatever the evaluation safety setting.
+    pub async fn evaluate(
+        &mut self,
+        frame_id: i64,
+        expression: &str,
+        context: EvaluationContext,
+    ) -> Result<Value, super::runtime::RuntimeError> {
+        context.check(expression)?;
         self.runtime.evaluate(frame_id, expression).await
     }
 
+    /// Evaluates an expression against the globals, without needing a stopped frame
+    pub async fn evaluate_global(&mut self, expression: &str) -> Result<Value, super::runtime::RuntimeError> {
+        self.runtime.evaluate_global(expression).await
+    }
+
+    /// Evaluates an expression as the debug console shows it, in a frame or against the globals
+    ///
+    /// Read-only `context`s are checked as for `evaluate`. Numbers are shown
+    /// in `format`, with the configured precision when it sets none.
+    pub async fn evaluate_variable(
+        &mut self,
+        frame_id: Option<i64>,
+        expression: &str,
+        context: EvaluationContext,
+        format: ValueFormat,
+    ) -> Result<Variable, super::runtime::RuntimeError> {
+        context.check(expression)?;
+        let format = format.or(self.config.value_format());
+        self.runtime.evaluate_variable(frame_id, expression, format).await
+    }
+
+    /// Evaluates an expression into a Lua literal that pastes back into code
+    pub async fn evaluate_literal(
+        &mut self,
+        frame_id: Option<i64>,
+        expression: &str,
+    ) -> Result<String, super::runtime::RuntimeError> {
+        self.runtime.evaluate_literal(frame_id, expression).await
+    }
+
     pub async fn set_breakpoint(&mut self, source: &str, line: u32) -> Result<super::debug::breakpoints::LineBreakpoint, super::runtime::RuntimeError> {
         let bp = self
             .runtime
@@ -66,12 +234,14 @@ impl<R: DebugRuntime> DebugSession<R> {
                 line,
             })
             .await?;
+        self.line_breakpoints.entry(source.to_string()).or_default().push(bp.id);
         
-        // Create and store the breakpoint in our manager
+        // Create and store the breakpoint in our manager, at the line the runtime moved it to
         let line_bp = super::debug::breakpoints::LineBreakpoint {
             id: bp.id,
             source: source.to_string(),
-            line,
+            line: bp.line,
+            column: None,
             condition: None,
             log_message: None,
             hit_condition: None,
@@ -83,7 +253,19 @@ impl<R: DebugRuntime> DebugSession<R> {
         Ok(line_bp)
     }
 
+    /// Removes the runtime's line breakpoints in a source before a new set replaces them
+    pub async fn clear_line_breakpoints(&mut self, source: &str) {
+        for id in self.line_breakpoints.remove(source).unwrap_or_default() {
+            let _ = self.runtime.remove_breakpoint(id).await;
+        }
+    }
+
     pub async fn remove_breakpoint(&mut self, id: i64) -> Result<(), super::runtime::RuntimeError> {
+        for ids in self.line_breakpoints.values_mut() {
+            ids.retain(|&bp_id| bp_id != id);
+        }
+        self.function_breakpoints.retain(|&bp_id| bp_id != id);
+        self.breakpoint_manager.remove_breakpoint(id);
         self.runtime.remove_breakpoint(id).await
     }
 
@@ -91,6 +273,15 @@ impl<R: DebugRuntime> DebugSession<R> {
         self.runtime.pause().await
     }
 
+    pub async fn pause_thread(&mut self, thread_id: u64) -> Result<(), super::runtime::RuntimeError> {
+        self.runtime.pause_thread(thread_id).await
+    }
+
+    /// Resumes one thread, returning whether every thread was continued after all
+    pub async fn run_thread(&mut self, thread_id: u64) -> Result<bool, super::runtime::RuntimeError> {
+        self.runtime.continue_thread(thread_id).await
+    }
+
     /// Checks if the runtime is paused and handles breakpoint conditions if so
     pub async fn check_pause_state(&mut self) -> Result<Option<String>, super::runtime::RuntimeError> {
         // This would be implemented to check the runtime's pause state
@@ -106,6 +297,7 @@ impl<R: DebugRuntime> DebugSession<R> {
                 name: name.to_string(),
             })
             .await?;
+        self.function_breakpoints.push(bp.id);
         
         // Create and store the breakpoint in our manager
         let func_bp = super::debug::breakpoints::FunctionBreakpoint {
@@ -122,6 +314,13 @@ impl<R: DebugRuntime> DebugSession<R> {
         Ok(func_bp)
     }
 
+    /// Removes the runtime's function breakpoints before a new set replaces them
+    pub async fn clear_function_breakpoints(&mut self) {
+        for id in std::mem::take(&mut self.function_breakpoints) {
+            let _ = self.runtime.remove_breakpoint(id).await;
+        }
+    }
+
     pub async fn set_exception_breakpoint(&mut self, filter: &str) -> Result<(), super::runtime::RuntimeError> {
         let _bp = self
             .runtime
@@ -132,6 +331,101 @@ impl<R: DebugRuntime> DebugSession<R> {
         Ok(())
     }
     
+    /// Replaces all metamethod breakpoints, given as (target, event) pairs
+    ///
+    /// The previous trampolines are uninstalled first so the metatables are
+    /// left as the program set them up.
+    pub async fn set_metamethod_breakpoints(
+        &mut self,
+        breakpoints: &[(String, String)],
+    ) -> Vec<Result<super::runtime::Breakpoint, super::runtime::RuntimeError>> {
+        for id in std::mem::take(&mut self.metamethod_breakpoints) {
+            let _ = self.runtime.remove_breakpoint(id).await;
+        }
+
+        let mut results = Vec::new();
+        for (target, event) in breakpoints {
+            let result = self
+                .runtime
+                .set_breakpoint(BreakpointType::Metamethod {
+                    target: target.clone(),
+                    event: event.clone(),
+                })
+                .await;
+            if let Ok(bp) = &result {
+                self.metamethod_breakpoints.push(bp.id);
+            }
+            results.push(result);
+        }
+        results
+    }
+
+    /// Runs the program again from the start with the breakpoints the client set
+    ///
+    /// The runtime discards the program's state and loads it again. Every
+    /// line, function and exception breakpoint in the manager is then set in
+    /// the new state, with hit counts starting over; metamethod breakpoints
+    /// are dropped with the metatables they patched. Returns the breakpoints
+    /// as the runtime set them, line breakpoints with their source.
+    pub async fn restart(&mut self) -> Result<Vec<(Option<String>, super::runtime::Breakpoint)>, super::runtime::RuntimeError> {
+        self.runtime.restart().await?;
+        self.reapply_breakpoints().await
+    }
+
+    /// Moves the session to another runtime, such as the agent of a program started elsewhere
+    ///
+    /// The breakpoints are set again in the new runtime and returned as for `restart`.
+    pub async fn replace_runtime(
+        &mut self,
+        mut runtime: R,
+    ) -> Result<Vec<(Option<String>, super::runtime::Breakpoint)>, super::runtime::RuntimeError> {
+        if let Some(sender) = &self.events {
+            runtime.set_event_sender(sender.clone());
+        }
+        self.runtime = runtime;
+        let config = self.config.clone();
+        self.set_config(config);
+        self.reapply_breakpoints().await
+    }
+
+    /// Sets every breakpoint the manager holds in the runtime again, which has none of them
+    async fn reapply_breakpoints(
+        &mut self,
+    ) -> Result<Vec<(Option<String>, super::runtime::Breakpoint)>, super::runtime::RuntimeError> {
+        self.line_breakpoints.clear();
+        self.function_breakpoints.clear();
+        self.metamethod_breakpoints.clear();
+        self.goto_targets.clear();
+        self.breakpoint_manager.reset_hit_counts();
+
+        let mut restored = Vec::new();
+        let lines: Vec<(String, u32)> = self
+            .breakpoint_manager
+            .get_all_line_breakpoints()
+            .into_iter()
+            .map(|bp| (bp.source.clone(), bp.line))
+            .collect();
+        for (source, line) in lines {
+            let bp = self.set_breakpoint(&source, line).await?;
+            self.breakpoint_manager.update_line_breakpoint(&source, line, bp.id, bp.verified, bp.message.clone());
+            let breakpoint = super::runtime::Breakpoint { id: bp.id, verified: bp.verified, line: bp.line, message: bp.message };
+            restored.push((Some(source), breakpoint));
+        }
+
+        let names: Vec<String> = self.breakpoint_manager.get_function_breakpoints().iter().map(|bp| bp.name.clone()).collect();
+        for name in names {
+            let bp = self.set_function_breakpoint(&name).await?;
+            self.breakpoint_manager.update_function_breakpoint(&name, bp.id, bp.verified, bp.message.clone());
+            let breakpoint = super::runtime::Breakpoint { id: bp.id, verified: bp.verified, line: 0, message: bp.message };
+            restored.push((None, breakpoint));
+        }
+
+        for filter in self.breakpoint_manager.get_exception_breakpoints().clone() {
+            self.set_exception_breakpoint(&filter).await?;
+        }
+        Ok(restored)
+    }
+
     pub fn breakpoint_manager(&mut self) -> &mut BreakpointManager {
         &mut self.breakpoint_manager
     }
@@ -140,14 +434,132 @@ impl<R: DebugRuntime> DebugSession<R> {
         &mut self.watchpoint_manager
     }
 
+    pub fn watch_manager(&mut self) -> &mut WatchManager {
+        &mut self.watch_manager
+    }
+
+    pub fn lock_states(&self) -> Vec<LockState> {
+        self.runtime.lock_states()
+    }
+
+    /// Variables that can complete an identifier in a frame, innermost scope first
+    ///
+    /// Without a base these are the frame's scopes; with a dotted path such
+    /// as `player.inventory`, the fields of the table it leads to. The path is
+    /// followed through the scopes' children, so no metamethods run.
+    pub async fn completion_candidates(&mut self, frame_id: i64, base: Option<&str>) -> Vec<(Vec<Variable>, &'static str)> {
+        let mut scopes = Vec::new();
+        for scope in self.scopes(frame_id).await.unwrap_or_default() {
+            if let Ok(variables) = self.variables(scope.variables_reference, VariablesPage::default()).await {
+                scopes.push(variables);
+            }
+        }
+        let Some(base) = base else {
+            return scopes.into_iter().map(|variables| (variables, "variable")).collect();
+        };
+
+        let mut segments = base.split('.');
+        let first = segments.next().unwrap_or_default();
+        let mut reference = scopes.iter().flatten().find(|v| v.name == first).and_then(|v| v.variables_reference);
+        for segment in segments {
+            let Some(parent) = reference.filter(|&r| r > 0) else { break };
+            let children = self.variables(parent, VariablesPage::default()).await.unwrap_or_default();
+            reference = children.iter().find(|v| v.name == segment).and_then(|v| v.variables_reference);
+        }
+        match reference.filter(|&r| r > 0) {
+            Some(table) => vec![(self.variables(table, VariablesPage::default()).await.unwrap_or_default(), "field")],
+            None => Vec::new(),
+        }
+    }
+
+    /// Evaluates the watches not yet evaluated at this stop, in the stopped thread's top frame
+    pub async fn refresh_watches(&mut self) {
+        let stale = self.watch_manager.stale();
+        if stale.is_empty() {
+            return;
+        }
+        let frame_id = match self.stack_trace(self.watch_manager.stopped_thread()).await {
+            Ok(frames) => match frames.first() {
+                Some(frame) => frame.id,
+                None => return,
+            },
+            Err(_) => return,
+        };
+        for expression in stale {
+            let value = match self.evaluate_variable(Some(frame_id), &expression, EvaluationContext::Watch, ValueFormat::default()).await {
+                Ok(variable) => variable.value,
+                Err(e) => format!("<error: {}>", e),
+            };
+            self.watch_manager.record(&expression, value);
+        }
+    }
+
+    /// Lists the breakpoints, watchpoints and exception filters the session has registered
+    pub fn inventory(&self, scope: InventoryScope) -> BreakpointInventory {
+        BreakpointInventory::collect(&self.breakpoint_manager, &self.watchpoint_manager, scope)
+    }
+
     pub fn set_config(&mut self, config: DebuggerConfig) {
         self.config = config;
+        self.runtime.set_string_encoding(self.source_encoding());
+        self.runtime.set_memory_limit(self.config.memory_limit_kb);
+        self.runtime.set_stitch_coroutine_stacks(self.config.stitch_coroutine_stacks);
+        self.runtime.set_record_replay(self.config.record_replay);
+        self.runtime.set_execution_trace_lines(self.config.execution_trace_lines);
+        self.runtime.set_evaluation_config(&self.config);
+        self.runtime.set_display_limits(self.config.display_limits());
+    }
+
+    /// Encoding configured for sources, `None` to auto-detect
+    ///
+    /// Unknown labels are rejected when the client sets them, so they fall
+    /// back to auto-detection here.
+    fn source_encoding(&self) -> Option<&'static encoding_rs::Encoding> {
+        self.config
+            .source_encoding
+            .as_deref()
+            .and_then(|label| crate::debug::encoding::lookup(label).ok().flatten())
+    }
+
+    /// Reads a source file and decodes it with the configured encoding
+    pub fn read_source_file(&self, path: &str) -> std::io::Result<String> {
+        let bytes = std::fs::read(path)?;
+        Ok(crate::debug::encoding::decode(&bytes, self.source_encoding()))
     }
 
     pub fn config(&self) -> &DebuggerConfig {
         &self.config
     }
 
+    /// Replaces the data breakpoints, returning them as the runtime verified them
+    pub async fn set_data_breakpoints(&mut self, breakpoints: Vec<DataBreakpoint>) -> Vec<DataBreakpoint> {
+        let stored = self.watchpoint_manager.set_data_breakpoints(breakpoints);
+        let verified = match self.runtime.set_data_breakpoints(stored.clone()).await {
+            Ok(verified) => verified,
+            Err(e) => stored
+                .into_iter()
+                .map(|mut bp| {
+                    bp.verified = false;
+                    bp.message = Some(e.to_string());
+                    bp
+                })
+                .collect(),
+        };
+        // Ids are kept, so this only records what the runtime verified
+        self.watchpoint_manager.set_data_breakpoints(verified)
+    }
+
+    /// The lines from `line` to `end_line` of a source that a breakpoint can stop on
+    pub async fn breakpoint_locations(
+        &mut self,
+        source: &str,
+        line: u32,
+        end_line: u32,
+    ) -> Result<Vec<u32>, super::runtime::RuntimeError> {
+        let lines = self.runtime.code_lines(source).await?;
+        Ok(lines.into_iter().filter(|l| (line..=end_line).contains(l)).collect())
+    }
+
     /// Check if any watchpoints have been triggered
     pub async fn check_watchpoints(&mut self, frame_id: i64) -> Result<bool, super::runtime::RuntimeError> {
         // Call the runtime's check_watchpoints method
@@ -155,229 +567,399 @@ impl<R: DebugRuntime> DebugSession<R> {
     }
 
     /// Checks if we should stop at a line breakpoint based on its conditions
-    pub async fn should_stop_at_line_breakpoint(&mut self, source: &str, line: u32) -> Result<bool, super::runtime::RuntimeError> {
-        // Get the breakpoint ID first to avoid borrow conflicts
-        let breakpoint_id = {
-            if let Some(breakpoint) = self.breakpoint_manager.find_line_breakpoint(source, line) {
-                Some(breakpoint.id)
-            } else {
-                None
+    ///
+    /// `source` is the path the runtime reports for the location and
+    /// `frame_id` the frame the breakpoint was hit in, where its condition and
+    /// log message are evaluated. Log messages and evaluation warnings reach
+    /// the client as console output attributed to the breakpoint. A logpoint
+    /// never stops; its condition and hit condition decide whether it logs.
+    pub async fn should_stop_at_line_breakpoint(&mut self, source: &str, line: u32, frame_id: i64) -> Result<bool, super::runtime::RuntimeError> {
+        // A breakpoint the session does not know of, such as one the runtime set itself, always stops
+        let Some(key) = self.breakpoint_manager.line_breakpoint_source(source, line) else { return Ok(true) };
+        let Some(breakpoint) = self.breakpoint_manager.find_line_breakpoint(&key, line) else { return Ok(true) };
+        let condition = breakpoint.condition.clone().filter(|c| !c.trim().is_empty());
+        let hit_condition = breakpoint.hit_condition.clone().filter(|c| !c.trim().is_empty());
+        let log_message = breakpoint.log_message.clone().filter(|m| !m.is_empty());
+        let output = |text: String| Event::output_at("console", &text, &key, line);
+
+        if let Some(condition) = &condition {
+            match ConditionEvaluator::evaluate_condition(&mut self.runtime, frame_id, condition).await {
+                Ok(true) => {}
+                Ok(false) => return Ok(false),
+                // A condition that fails to evaluate stops, so the problem gets noticed
+                Err(e) => self.emit(output(format!("Warning: condition `{}` failed to evaluate: {}\n", condition, e))),
             }
-        };
-        
-        if let Some(_id) = breakpoint_id {
-            // Get the breakpoint information first to avoid borrow conflicts
-            let breakpoint_info = {
-                if let Some(breakpoint) = self.breakpoint_manager.find_line_breakpoint(source, line) {
-                    Some((
-                        breakpoint.hit_condition.clone(),
-                        breakpoint.log_message.clone(),
-                        breakpoint.condition.clone(),
-                    ))
-                } else {
-                    None
-                }
-            };
-            
-            if let Some((hit_condition, log_message, condition)) = breakpoint_info {
-                // First check hit conditions
-                if let Some(ref hit_condition_str) = hit_condition {
-                    if !hit_condition_str.trim().is_empty() {
-                        // Increment hit count first
-                        self.breakpoint_manager.increment_line_breakpoint_hit_count(source, line);
-                        
-                        // Get the current hit count
-                        if let Some(hit_count) = self.breakpoint_manager.get_line_breakpoint_hit_count(source, line) {
-                            match hit_conditions::evaluate_hit_condition(hit_condition_str, hit_count) {
-                                Ok(should_hit) => {
-                                    if !should_hit {
-                                        return Ok(false); // Don't stop, hit condition not met
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("Warning: Hit condition evaluation failed for breakpoint at {}:{}: {}", source, line, e);
-                                    // If hit condition evaluation fails, we still break but log the error
-                                }
-                            }
-                        }
-                    }
-                } else {
-                    // No hit condition, just increment the count
-                    self.breakpoint_manager.increment_line_breakpoint_hit_count(source, line);
-                }
-
-                // Handle logpoints
-                if let Some(log_message) = &log_message {
-                    if !log_message.is_empty() {
-                        // Process the logpoint message
-                        match LogpointEvaluator::process_logpoint(&mut self.runtime, 0, log_message).await {
-                            Ok(message) => {
-                                // In a real implementation, we would send this as a DAP output event
-                                println!("Logpoint: {}", message);
-                            }
-                            Err(e) => {
-                                eprintln!("Warning: Logpoint evaluation failed: {}", e);
-                            }
-                        }
-                        
-                        // If it's only a logpoint (no condition), don't stop
-                        if condition.is_none() && hit_condition.is_none() {
-                            return Ok(false);
-                        }
-                    }
-                }
+        }
 
-                // Check conditional breakpoint
-                if let Some(condition_str) = &condition {
-                    if !condition_str.trim().is_empty() {
-                        match ConditionEvaluator::should_break(&mut self.runtime, 0, Some(condition_str)).await {
-                            Ok(should_break) => return Ok(should_break),
-                            Err(e) => {
-                                eprintln!("Warning: Condition evaluation failed for breakpoint at {}:{}: {}", source, line, e);
-                                // If condition evaluation fails, we still break but log the error
-                                return Ok(true);
-                            }
-                        }
-                    }
-                }
+        self.breakpoint_manager.increment_line_breakpoint_hit_count(&key, line);
+        if let Some(hit_condition) = &hit_condition {
+            let hit_count = self.breakpoint_manager.get_line_breakpoint_hit_count(&key, line).unwrap_or(0);
+            match hit_conditions::evaluate_hit_condition(hit_condition, hit_count) {
+                Ok(true) => {}
+                Ok(false) => return Ok(false),
+                Err(e) => self.emit(output(format!("Warning: hit condition `{}` is invalid: {}\n", hit_condition, e))),
             }
-            
-            // No condition or empty condition means always break
-            Ok(true)
-        } else {
-            // No breakpoint found - shouldn't happen in normal operation
-            Ok(false)
         }
+
+        let Some(log_message) = log_message else { return Ok(true) };
+        match LogpointEvaluator::process_logpoint(&mut self.runtime, frame_id, &log_message).await {
+            Ok(message) => self.emit(output(format!("{}\n", message))),
+            Err(e) => self.emit(output(format!("Warning: logpoint failed: {}\n", e))),
+        }
+        Ok(false)
     }
 
     /// Checks if we should stop at a function breakpoint based on its conditions
-    pub async fn should_stop_at_function_breakpoint(&mut self, name: &str) -> Result<bool, super::runtime::RuntimeError> {
-        // Get the breakpoint ID first to avoid borrow conflicts
-        let breakpoint_id = {
-            if let Some(breakpoint) = self.breakpoint_manager.find_function_breakpoint(name) {
-                Some(breakpoint.id)
-            } else {
-                None
+    ///
+    /// Works like `should_stop_at_line_breakpoint`, with output that is not
+    /// attributed to a source.
+    pub async fn should_stop_at_function_breakpoint(&mut self, name: &str, frame_id: i64) -> Result<bool, super::runtime::RuntimeError> {
+        let Some(breakpoint) = self.breakpoint_manager.find_function_breakpoint(name) else { return Ok(true) };
+        let condition = breakpoint.condition.clone().filter(|c| !c.trim().is_empty());
+        let hit_condition = breakpoint.hit_condition.clone().filter(|c| !c.trim().is_empty());
+        let log_message = breakpoint.log_message.clone().filter(|m| !m.is_empty());
+        let output = |text: String| Event::output("console", &text);
+
+        if let Some(condition) = &condition {
+            match ConditionEvaluator::evaluate_condition(&mut self.runtime, frame_id, condition).await {
+                Ok(true) => {}
+                Ok(false) => return Ok(false),
+                Err(e) => self.emit(output(format!(
+                    "Warning: condition `{}` of function breakpoint '{}' failed to evaluate: {}\n",
+                    condition, name, e
+                ))),
+            }
+        }
+
+        self.breakpoint_manager.increment_function_breakpoint_hit_count(name);
+        if let Some(hit_condition) = &hit_condition {
+            let hit_count = self.breakpoint_manager.get_function_breakpoint_hit_count(name).unwrap_or(0);
+            match hit_conditions::evaluate_hit_condition(hit_condition, hit_count) {
+                Ok(true) => {}
+                Ok(false) => return Ok(false),
+                Err(e) => self.emit(output(format!(
+                    "Warning: hit condition `{}` of function breakpoint '{}' is invalid: {}\n",
+                    hit_condition, name, e
+                ))),
             }
+        }
+
+        let Some(log_message) = log_message else { return Ok(true) };
+        match LogpointEvaluator::process_logpoint(&mut self.runtime, frame_id, &log_message).await {
+            Ok(message) => self.emit(output(format!("{}\n", message))),
+            Err(e) => self.emit(output(format!("Warning: logpoint failed: {}\n", e))),
+        }
+        Ok(false)
+    }
+
+    /// Checks if we should stop at a data breakpoint based on its hit condition
+    pub fn should_stop_at_data_breakpoint(&mut self, id: i64) -> bool {
+        let Some(breakpoint) = self.watchpoint_manager.find_data_breakpoint(id) else { return true };
+        let hit_condition = breakpoint.hit_condition.clone().filter(|c| !c.trim().is_empty());
+        let name = breakpoint.name.clone();
+
+        self.watchpoint_manager.increment_data_breakpoint_hit_count(id);
+        let Some(hit_condition) = hit_condition else { return true };
+        let hit_count = self.watchpoint_manager.get_data_breakpoint_hit_count(id).unwrap_or(0);
+        self.hit_condition_met(&hit_condition, hit_count, &format!("data breakpoint '{}'", name))
+    }
+
+    /// Checks if we should stop at an exception based on the conditions of the filters it matched
+    ///
+    /// `filter` is the filter the runtime stopped for. Without one, the
+    /// exception counts against every active filter and stops if any of them
+    /// says to. A filter's condition is evaluated in `frame_id`, the frame
+    /// that raised the error, with the error `message` bound to `message`;
+    /// only exceptions that meet it count towards the hit condition.
+    pub async fn should_stop_at_exception(&mut self, filter: Option<&str>, message: &str, frame_id: Option<i64>) -> bool {
+        let filters = match filter {
+            Some(filter) => vec![filter.to_string()],
+            None => self.breakpoint_manager.get_exception_breakpoints().clone(),
         };
-        
-        if let Some(_id) = breakpoint_id {
-            // Get the breakpoint information first to avoid borrow conflicts
-            let breakpoint_info = {
-                if let Some(breakpoint) = self.breakpoint_manager.find_function_breakpoint(name) {
-                    Some((
-                        breakpoint.hit_condition.clone(),
-                        breakpoint.log_message.clone(),
-                        breakpoint.condition.clone(),
-                    ))
-                } else {
-                    None
-                }
-            };
-            
-            if let Some((hit_condition, log_message, condition)) = breakpoint_info {
-                // Handle logpoints (function breakpoints don't typically have log messages, but we'll support it)
-                if let Some(log_message_str) = &log_message {
-                    if !log_message_str.is_empty() {
-                        // Process the logpoint message
-                        match LogpointEvaluator::process_logpoint(&mut self.runtime, 0, log_message_str).await {
-                            Ok(message) => {
-                                // In a real implementation, we would send this as a DAP output event
-                                println!("Logpoint: {}", message);
-                            }
-                            Err(e) => {
-                                eprintln!("Warning: Logpoint evaluation failed: {}", e);
-                            }
-                        }
-                        
-                        // If it's only a logpoint (no condition), don't stop
-                        if condition.is_none() && hit_condition.is_none() {
-                            return Ok(false);
-                        }
-                    }
-                }
+        if filters.is_empty() {
+            return true;
+        }
 
-                // First check hit conditions
-                if let Some(ref hit_condition_str) = hit_condition {
-                    if !hit_condition_str.trim().is_empty() {
-                        // Increment hit count first
-                        self.breakpoint_manager.increment_function_breakpoint_hit_count(name);
-                        
-                        // Get the current hit count
-                        if let Some(hit_count) = self.breakpoint_manager.get_function_breakpoint_hit_count(name) {
-                            match hit_conditions::evaluate_hit_condition(hit_condition_str, hit_count) {
-                                Ok(should_hit) => {
-                                    if !should_hit {
-                                        return Ok(false); // Don't stop, hit condition not met
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("Warning: Hit condition evaluation failed for function breakpoint '{}': {}", name, e);
-                                    // If hit condition evaluation fails, we still break but log the error
-                                }
-                            }
-                        }
-                    }
-                } else {
-                    // No hit condition, just increment the count
-                    self.breakpoint_manager.increment_function_breakpoint_hit_count(name);
+        let mut stop = false;
+        for filter in filters {
+            let condition = self.breakpoint_manager.get_exception_condition(&filter).cloned();
+            if let (Some(condition), Some(frame_id)) = (condition, frame_id) {
+                let expression = conditions::exception_condition(&condition, message);
+                match ConditionEvaluator::evaluate_condition(&mut self.runtime, frame_id, &expression).await {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    // A condition that fails to evaluate stops, so the problem gets noticed
+                    Err(e) => self.emit(Event::output(
+                        "console",
+                        &format!("Warning: condition `{}` of exception filter '{}' failed to evaluate: {}\n", condition, filter, e),
+                    )),
                 }
+            }
 
-                // Check conditional breakpoint
-                if let Some(condition_str) = &condition {
-                    if !condition_str.trim().is_empty() {
-                        match ConditionEvaluator::should_break(&mut self.runtime, 0, Some(condition_str)).await {
-                            Ok(should_break) => return Ok(should_break),
-                            Err(e) => {
-                                eprintln!("Warning: Condition evaluation failed for function breakpoint '{}': {}", name, e);
-                                // If condition evaluation fails, we still break but log the error
-                                return Ok(true);
-                            }
-                        }
-                    }
+            let hit_count = self.breakpoint_manager.increment_exception_hit_count(&filter);
+            stop |= match self.breakpoint_manager.get_exception_hit_condition(&filter).cloned() {
+                Some(hit_condition) => {
+                    self.hit_condition_met(&hit_condition, hit_count, &format!("exception filter '{}'", filter))
                 }
-                
-                // No condition or empty condition means always break
-                Ok(true)
-            } else {
-                // No breakpoint found - shouldn't happen in normal operation
-                Ok(false)
-            }
-        } else {
-            // No breakpoint found - shouldn't happen in normal operation
-            Ok(false)
+                None => true,
+            };
         }
+        stop
     }
 
+    /// Evaluates a hit condition; an invalid one is reported and stops, so the problem gets noticed
+    fn hit_condition_met(&self, hit_condition: &str, hit_count: usize, what: &str) -> bool {
+        match hit_conditions::evaluate_hit_condition(hit_condition, hit_count) {
+            Ok(met) => met,
+            Err(e) => {
+                self.emit(Event::output(
+                    "console",
+                    &format!("Warning: hit condition `{}` of {} is invalid: {}\n", hit_condition, what, e),
+                ));
+                true
+            }
+        }
+    }
 }
 
 pub struct DapServer<R: DebugRuntime> {
     session: Option<DebugSession<R>>,
     process_handle: Option<tokio::process::Child>,
+    /// Reads the stdout and stderr of the debuggee process
+    output: Option<OutputCapture>,
     is_running: bool,
+    /// Events queued by request handlers, flushed after each response
+    pending_events: Vec<Event>,
+    /// Sender handed to the session and runtime for asynchronous events
+    event_tx: EventSender,
+    /// Events raised outside of request handling, drained by the event loop
+    event_rx: EventReceiver,
+    /// Rewrites of messages from `rewriteRules`
+    rewrite_rules: RewriteRules,
+    /// Whether the program should stop before its first line
+    stop_on_entry: bool,
+    /// Callbacks registered by the embedder
+    hooks: SessionHooks,
+    /// Profile from the last `profiling/stop`, kept for export
+    last_profile: Option<crate::profiling::ProfileData>,
+    /// Recent messages exchanged with the client, for session bundles
+    trace: ProtocolTrace,
+    /// Breakpoint file applied whenever it changes
+    breakpoint_file: Option<BreakpointFileWatcher>,
+    /// Files whose changes are hot reloaded into the running program
+    hot_reload_watcher: Option<FileWatcher>,
+    /// Arguments of the launch request, applied again on restart; None for attached sessions
+    launch_arguments: Option<JsonValue>,
+    /// Translation between TypeScript sources and the Lua TSTL compiled them to; None when disabled
+    source_mapping: Option<SourceMapping>,
+    /// Launch arguments used where a launch request leaves them unset
+    launch_defaults: LaunchArguments,
+    /// Creates the runtime for the Lua version a launch request asks for
+    runtime_factory: Option<RuntimeFactory<R>>,
+    /// What the client said about itself in `initialize`
+    client: ClientCapabilities,
+    /// Runs launched programs in the client's terminal under a debug agent
+    terminal_launcher: Option<Box<dyn TerminalLauncher<R>>>,
+    /// Launch waiting for the client to run the program in its terminal
+    terminal_launch: Option<PendingTerminalLaunch>,
+    /// Requests to send to the client, such as `runInTerminal`, with their arguments
+    client_requests: Vec<(String, JsonValue)>,
+    /// Reads the debugger settings from the embedder's settings file, for reloading them
+    settings_loader: Option<SettingsLoader>,
+    /// Changes the filter of the adapter's diagnostics, which the embedder installed
+    log_level_handler: Option<LogLevelHandler>,
+    /// Notified to reload the settings from outside the event loop, as on SIGHUP
+    settings_reload: Arc<Notify>,
+    /// Notified to end the session from outside the event loop, as on SIGTERM
+    shutdown: Arc<Notify>,
+    /// What happens to the debuggee process when the session is shut down that way
+    on_shutdown: OnShutdown,
+}
+
+/// Creates a runtime for the `runtime` and `luaLibraryPath` of a launch request, the default for neither
+pub type RuntimeFactory<R> = Box<dyn Fn(&LaunchArguments) -> Result<R, String> + Send>;
+
+/// Reads the debugger settings again, such as from wayfinder.yaml
+pub type SettingsLoader = Box<dyn Fn() -> Result<DebuggerSettings, String> + Send>;
+
+/// Sets the `tracing` filter of the adapter's diagnostics
+pub type LogLevelHandler = Box<dyn Fn(&str) -> Result<(), String> + Send>;
+
+/// What a shut down adapter does with the debuggee process it started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OnShutdown {
+    /// Kill it, as a `disconnect` does
+    #[default]
+    Terminate,
+    /// Leave it running on its own
+    Detach,
 }
 
 impl<R: DebugRuntime> DapServer<R> {
     pub fn new() -> Self {
+        let (event_tx, event_rx) = event_channel();
         Self { 
             session: None,
             process_handle: None,
+            output: None,
             is_running: false,
+            pending_events: Vec::new(),
+            event_tx,
+            event_rx,
+            rewrite_rules: RewriteRules::default(),
+            stop_on_entry: false,
+            hooks: SessionHooks::new(),
+            last_profile: None,
+            trace: ProtocolTrace::default(),
+            breakpoint_file: None,
+            hot_reload_watcher: None,
+            launch_arguments: None,
+            source_mapping: Some(SourceMapping::new(std::env::current_dir().ok(), PathResolver::default())),
+            launch_defaults: LaunchArguments::default(),
+            runtime_factory: None,
+            client: ClientCapabilities::default(),
+            terminal_launcher: None,
+            terminal_launch: None,
+            client_requests: Vec::new(),
+            settings_loader: None,
+            log_level_handler: None,
+            settings_reload: Arc::new(Notify::new()),
+            shutdown: Arc::new(Notify::new()),
+            on_shutdown: OnShutdown::default(),
         }
     }
 
     pub fn set_runtime(&mut self, runtime: R) {
-        self.session = Some(DebugSession::new(runtime));
+        let mut session = DebugSession::new(runtime);
+        session.set_event_sender(self.event_tx.clone());
+        self.session = Some(session);
+    }
+
+    /// Returns a sender for delivering events to the client asynchronously
+    pub fn event_sender(&self) -> EventSender {
+        self.event_tx.clone()
+    }
+
+    /// Sets the default for `stopOnEntry`, which a launch request may override
+    pub fn set_stop_on_entry(&mut self, stop_on_entry: bool) {
+        self.stop_on_entry = stop_on_entry;
+    }
+
+    /// Sets the rewrite rules, which a launch or attach request may replace
+    pub fn set_rewrite_rules(&mut self, rules: RewriteRules) {
+        self.rewrite_rules = rules;
+    }
+
+    /// Sets the launch arguments used where a launch request leaves them unset
+    pub fn set_launch_defaults(&mut self, defaults: LaunchArguments) {
+        self.launch_defaults = defaults;
+    }
+
+    /// Lets launch requests pick the Lua version, with a fresh runtime from `factory`
+    pub fn set_runtime_factory(&mut self, factory: impl Fn(&LaunchArguments) -> Result<R, String> + Send + 'static) {
+        self.runtime_factory = Some(Box::new(factory));
+    }
+
+    /// Lets `wayfinder/configure` requests with `reload` and the reload trigger read the settings through `loader`
+    pub fn set_settings_loader(&mut self, loader: impl Fn() -> Result<DebuggerSettings, String> + Send + 'static) {
+        self.settings_loader = Some(Box::new(loader));
+    }
+
+    /// Lets settings change the adapter's log level, which `handler` applies
+    pub fn set_log_level_handler(&mut self, handler: impl Fn(&str) -> Result<(), String> + Send + 'static) {
+        self.log_level_handler = Some(Box::new(handler));
+    }
+
+    /// Returns a handle that makes the running event loop reload the settings when notified
+    ///
+    /// Embedders notify it on SIGHUP. The outcome is reported in the console.
+    pub fn settings_reload_trigger(&self) -> Arc<Notify> {
+        self.settings_reload.clone()
+    }
+
+    /// Returns a handle that makes the running event loop end the session when notified
+    ///
+    /// Embedders notify it on SIGINT or SIGTERM. The events already raised are
+    /// sent, the debuggee process is dealt with as [`set_on_shutdown`] says,
+    /// and the client gets a `terminated` event before the loop returns.
+    ///
+    /// [`set_on_shutdown`]: DapServer::set_on_shutdown
+    pub fn shutdown_trigger(&self) -> Arc<Notify> {
+        self.shutdown.clone()
+    }
+
+    /// Sets what a shutdown through [`shutdown_trigger`](DapServer::shutdown_trigger) does with the debuggee process
+    pub fn set_on_shutdown(&mut self, on_shutdown: OnShutdown) {
+        self.on_shutdown = on_shutdown;
+    }
+
+    /// Applies debugger settings to the session, and their log level through the log level handler
+    ///
+    /// Invalid settings change nothing.
+    pub fn configure(&mut self, settings: &DebuggerSettings) -> Result<(), String> {
+        let session = self.session.as_mut().ok_or_else(|| "No debug session".to_string())?;
+        let mut config = session.config().clone();
+        settings.apply(&mut config)?;
+        if let Some(level) = &settings.log_level {
+            match &self.log_level_handler {
+                Some(handler) => handler(level)?,
+                None => return Err("The log level cannot be changed in this adapter".to_string()),
+            }
+        }
+        session.set_config(config);
+        Ok(())
+    }
+
+    /// Reads the settings file again and applies it, reporting the outcome in the console
+    fn reload_settings(&mut self) {
+        let outcome = self.load_settings().and_then(|settings| self.configure(&settings));
+        let message = match outcome {
+            Ok(()) => "Reloaded the debugger settings\n".to_string(),
+            Err(e) => format!("Could not reload the debugger settings: {}\n", e),
+        };
+        self.queue_event(Event::output("console", &message));
+    }
+
+    fn load_settings(&self) -> Result<DebuggerSettings, String> {
+        match &self.settings_loader {
+            Some(loader) => loader(),
+            None => Err("There is no settings file to reload".to_string()),
+        }
+    }
+
+    /// Lets launch requests run the program in the client's terminal, attached through `launcher`
+    pub fn set_terminal_launcher(&mut self, launcher: impl TerminalLauncher<R> + 'static) {
+        self.terminal_launcher = Some(Box::new(launcher));
+    }
+
+    /// Runs a callback every time the program stops
+    pub fn on_stopped(&mut self, callback: impl FnMut(&StoppedInfo) + Send + 'static) -> &mut Self {
+        self.hooks.on_stopped(callback);
+        self
+    }
+
+    /// Runs a callback when a breakpoint is verified, whether as it is set or once its source loads
+    pub fn on_breakpoint_bound(&mut self, callback: impl FnMut(&super::runtime::Breakpoint) + Send + 'static) -> &mut Self {
+        self.hooks.on_breakpoint_bound(callback);
+        self
+    }
+
+    /// Runs a callback with the category and text of everything the program outputs
+    pub fn on_output(&mut self, callback: impl FnMut(&str, &str) + Send + 'static) -> &mut Self {
+        self.hooks.on_output(callback);
+        self
     }
 
-    pub fn set_process(&mut self, process: tokio::process::Child) {
+    /// Watches the debuggee process, sending what it writes to piped stdout and stderr as output events
+    pub fn set_process(&mut self, mut process: tokio::process::Child) {
+        self.output = OutputCapture::start(&mut process);
         self.process_handle = Some(process);
     }
 
     pub async fn terminate_process(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(mut process) = self.process_handle.take() {
-            // Try graceful termination first
-            process.kill().await?;
+            process_tree::kill_tree(&mut process).await?;
             let _ = process.wait().await;
         }
         self.is_running = false;
@@ -388,34 +970,87 @@ impl<R: DebugRuntime> DapServer<R> {
         self.is_running
     }
 
+    /// Queues an event to be sent to the client after the current response
+    pub fn queue_event(&mut self, event: Event) {
+        self.pending_events.push(event);
+    }
+
+    /// Takes all queued events, leaving the queue empty
+    pub fn take_pending_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.pending_events)
+    }
+
+    /// Takes the requests to send to the client, as command and arguments
+    pub fn take_client_requests(&mut self) -> Vec<(String, JsonValue)> {
+        std::mem::take(&mut self.client_requests)
+    }
+
+    /// Handles the client's response to a request the server sent it
+    ///
+    /// Returns the response to a request of the client's that was waiting for it, if any.
+    pub async fn handle_client_response(&mut self, response: &JsonValue) -> Option<JsonValue> {
+        match response.get("command").and_then(|c| c.as_str()) {
+            Some("runInTerminal") => self.finish_terminal_launch(response).await,
+            _ => None,
+        }
+    }
+
     pub async fn handle_request(&mut self, method: &str, params: &JsonValue, id: u64) -> Option<JsonValue> {
         match method {
-            "initialize" => Some(self.handle_initialize(id)),
+            "initialize" => self.handle_initialize(id, params),
             "launch" => self.handle_launch(id, params).await,
+            "restart" => self.handle_restart(id, params).await,
             "attach" => self.handle_attach(id, params),
             "disconnect" => self.handle_disconnect(id).await,
             "setBreakpoints" => self.handle_set_breakpoints(id, params).await,
+            "breakpointLocations" => self.handle_breakpoint_locations(id, params).await,
             "setFunctionBreakpoints" => self.handle_set_function_breakpoints(id, params).await,
             "setExceptionBreakpoints" => self.handle_set_exception_breakpoints(id, params).await,
             "setDataBreakpoints" => self.handle_set_data_breakpoints(id, params).await,
-            "configurationDone" => self.handle_configuration_done(id),
-            "continue" => self.handle_continue(id).await,
-            "next" => self.handle_next(id).await,
-            "stepIn" => self.handle_step_in(id).await,
-            "stepOut" => self.handle_step_out(id).await,
-            "pause" => self.handle_pause(id).await,
+            "setMetamethodBreakpoints" => self.handle_set_metamethod_breakpoints(id, params).await,
+            "configurationDone" => self.handle_configuration_done(id).await,
+            "continue" => self.handle_continue(id, params).await,
+            "runToLocation" => self.handle_run_to_location(id, params).await,
+            "gotoTargets" => self.handle_goto_targets(id, params).await,
+            "goto" => self.handle_goto(id, params).await,
+            "next" => self.handle_next(id, params).await,
+            "stepIn" => self.handle_step_in(id, params).await,
+            "stepOut" => self.handle_step_out(id, params).await,
+            "stepFrame" => self.handle_step_frame(id, params).await,
+            "stepBack" => self.handle_step_back(id).await,
+            "reverseContinue" => self.handle_reverse_continue(id).await,
+            "pause" => self.handle_pause(id, params).await,
+            "threads" => self.handle_threads(id).await,
             "stackTrace" => self.handle_stack_trace(id, params).await,
             "scopes" => self.handle_scopes(id, params).await,
             "variables" => self.handle_variables(id, params).await,
+            "setVariable" => self.handle_set_variable(id, params).await,
             "evaluate" => self.handle_evaluate(id, params).await,
+            "completions" => self.handle_completions(id, params).await,
             "source" => self.handle_source(id, params).await,
             "exceptionInfo" => self.handle_exception_info(id, params).await,
-            "memoryStatistics" => self.handle_memory_statistics(id).await,
+            "modules" => self.handle_modules(id, params).await,
+            "readMemory" => self.handle_read_memory(id, params).await,
+            "writeMemory" => self.handle_write_memory(id, params).await,
+            "memoryStatistics" | "wayfinder/memory/stats" => self.handle_memory_statistics(id).await,
             "forceGC" => self.handle_force_gc(id).await,
-            "profiling/start" => self.handle_profiling_start(id, params).await,
-            "profiling/stop" => self.handle_profiling_stop(id).await,
-            "profiling/snapshot" => self.handle_profiling_snapshot(id).await,
-            "hotReload" => self.handle_hot_reload(id, params).await,
+            "wayfinder/memory/gc" => self.handle_memory_gc(id, params).await,
+            "wayfinder/memory/tune" => self.handle_memory_tune(id, params).await,
+            "wayfinder/memory/allocations" => self.handle_memory_allocations(id, params).await,
+            "flightRecorder" => self.handle_flight_recorder(id).await,
+            "wayfinder/trace" => self.handle_execution_trace(id).await,
+            "wayfinder/configure" => self.handle_configure(id, params),
+            "wayfinder/watches" => self.handle_watches(id, params),
+            "wayfinder/internals" => Some(self.handle_internals(id)),
+            "wayfinder/bundleSession" => self.handle_bundle_session(id, params).await,
+            "heapSearch" => self.handle_heap_search(id, params).await,
+            "heapSnapshot" => self.handle_heap_snapshot(id, params).await,
+            "profiling/start" | "wayfinder/startProfiling" => self.handle_profiling_start(id, params).await,
+            "profiling/stop" | "wayfinder/stopProfiling" => self.handle_profiling_stop(id).await,
+            "profiling/snapshot" | "wayfinder/profileSnapshot" => self.handle_profiling_snapshot(id).await,
+            "wayfinder/profile/export" => self.handle_profile_export(id, params).await,
+            "wayfinder/hotReload" | "hotReload" => self.handle_hot_reload(id, params).await,
+            "breakpointInventory" => self.handle_breakpoint_inventory(id, params),
             _ => Some(self.error_response(id, -32600, format!("Unknown method: {}", method))),
         }
     }
@@ -429,12 +1064,19 @@ impl<R: DebugRuntime> DapServer<R> {
             "supportsHitBreakpoints": true,
             "supportsLogBreakpoints": true,
             "supportsEvaluateForHovers": true,
-            "supportsStepBack": false,
-            "supportsSetVariable": false,
+            "supportsStepBack": true,
+            "supportsSteppingGranularity": true,
+            "supportsSetVariable": true,
             "supportsRestartFrame": false,
-            "supportsGotoTargetsRequest": false,
-            "supportsCompletionsRequest": false,
-            "supportsModulesRequest": false,
+            "supportsRestartRequest": true,
+            "supportsGotoTargetsRequest": true,
+            "supportsCompletionsRequest": true,
+            "supportsBreakpointLocationsRequest": true,
+            "supportsModulesRequest": true,
+            "supportsValueFormattingOptions": true,
+            "supportsClipboardContext": true,
+            "supportsReadMemoryRequest": true,
+            "supportsWriteMemoryRequest": true,
             "supportsTerminateDebuggee": true,
             "supportsDelayedStackTraceLoading": true,
             "supportsDataBreakpoints": true,
@@ -461,127 +1103,702 @@ impl<R: DebugRuntime> DapServer<R> {
         })
     }
 
-    fn handle_initialize(&self, id: u64) -> JsonValue {
-        json!({
+    fn handle_initialize(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        self.client = match ClientCapabilities::from_request(params) {
+            Ok(client) => client,
+            Err(message) => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message)),
+        };
+        tracing::info!(
+            client = self.client.name(),
+            lines_start_at1 = self.client.lines_start_at1,
+            columns_start_at1 = self.client.columns_start_at1,
+            "client connected"
+        );
+        // The initialized event must follow the initialize response
+        self.queue_event(Event::initialized());
+        let mut capabilities = Self::capabilities();
+        // Which Lua programs run on, so clients can tell a fallback from the library they meant
+        if let Some(backend) = self.session.as_ref().and_then(|session| session.runtime.lua_backend()) {
+            capabilities["luaRuntime"] = json!({
+                "mode": backend.mode,
+                "version": backend.version.to_string(),
+                "library": backend.library,
+            });
+        }
+        Some(json!({
             "id": id,
-            "result": Self::capabilities()
-        })
+            "result": capabilities
+        }))
     }
 
-    async fn handle_launch(&mut self, id: u64, _params: &JsonValue) -> Option<JsonValue> {
-        if let Some(session) = &mut self.session {
-            let _ = session.runtime.step(StepMode::In).await.ok();
+    /// What the client said about itself in `initialize`
+    pub fn client(&self) -> &ClientCapabilities {
+        &self.client
+    }
+
+    async fn handle_launch(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let arguments = match LaunchArguments::from_request(params) {
+            Ok(arguments) => arguments.with_defaults(&self.launch_defaults),
+            Err(message) => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message)),
+        };
+        if let Some(kind) = terminal::terminal_kind(arguments.console.as_deref()) {
+            match self.request_terminal_launch(id, kind, &arguments, params) {
+                // Answered once the program's agent is connected
+                Ok(()) => return None,
+                Err(message) => self.queue_event(Event::output(
+                    "console",
+                    &format!("Running the program in the debug console: {}\n", message),
+                )),
+            }
+        }
+        if let Err(message) = self.load_launched_program(&arguments).await {
+            return Some(self.error_response(id, error_code::LAUNCH_FAILED, message));
+        }
+        // The program itself starts on configurationDone
+        if let Err(message) = self.apply_launch_arguments(&arguments.overlay(params)) {
+            return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message));
         }
         Some(json!({ "id": id, "result": {} }))
     }
 
-    fn handle_attach(&mut self, id: u64, _params: &JsonValue) -> Option<JsonValue> {
-        Some(json!({ "id": id, "result": {} }))
+    /// Asks the client to run the launched program in a terminal, under a debug agent
+    fn request_terminal_launch(
+        &mut self,
+        id: u64,
+        kind: &str,
+        arguments: &LaunchArguments,
+        params: &JsonValue,
+    ) -> Result<(), String> {
+        if !self.client.supports_run_in_terminal_request {
+            return Err(format!("{} cannot run programs in a terminal", self.client.name()));
+        }
+        let Some(launcher) = &self.terminal_launcher else {
+            return Err("this server cannot run programs in a terminal".to_string());
+        };
+        if arguments.program.is_none() {
+            return Err("no program to run".to_string());
+        }
+
+        let address = terminal::free_local_address().map_err(|e| format!("no port for the debug agent: {}", e))?;
+        let command = launcher.command(&address, arguments)?;
+        self.client_requests
+            .push(("runInTerminal".to_string(), terminal::run_in_terminal_arguments(kind, command, arguments)));
+        self.terminal_launch = Some(PendingTerminalLaunch {
+            launch_id: id,
+            arguments: arguments.clone(),
+            params: params.clone(),
+            address,
+        });
+        Ok(())
     }
 
-    async fn handle_disconnect(&mut self, id: u64) -> Option<JsonValue> {
-        // Terminate the debuggee process if it's running
-        if let Err(e) = self.terminate_process().await {
-            eprintln!("Error terminating process: {}", e);
+    /// Attaches to the agent of the program the client ran in a terminal and answers the launch
+    ///
+    /// Breakpoints set while the client started the program move to the agent's runtime.
+    async fn finish_terminal_launch(&mut self, response: &JsonValue) -> Option<JsonValue> {
+        let pending = self.terminal_launch.take()?;
+        let id = pending.launch_id;
+        if response.get("success").and_then(|v| v.as_bool()) != Some(true) {
+            let reason = response.get("message").and_then(|v| v.as_str()).unwrap_or("no reason given");
+            return Some(self.error_response(id, error_code::LAUNCH_FAILED, format!("The client could not run the program: {}", reason)));
+        }
+        let launcher = self.terminal_launcher.as_deref()?;
+        let runtime = match terminal::connect_to_agent(launcher, &pending.address).await {
+            Ok(runtime) => runtime,
+            Err(message) => return Some(self.error_response(id, error_code::LAUNCH_FAILED, message)),
+        };
+
+        let removed = self.runtime_breakpoint_ids();
+        match &mut self.session {
+            Some(session) => match session.replace_runtime(runtime).await {
+                Ok(restored) => self.queue_replaced_breakpoints(removed, restored),
+                Err(e) => return Some(self.runtime_error_response(id, "Failed to set breakpoints", &e)),
+            },
+            None => self.set_runtime(runtime),
+        }
+        if let Err(message) = self.apply_launch_arguments(&pending.arguments.overlay(&pending.params)) {
+            return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message));
         }
-        
-        // Clean up the session
-        self.session = None;
-        self.is_running = false;
-        
         Some(json!({ "id": id, "result": {} }))
     }
 
-    async fn handle_set_breakpoints(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
-        let session = match &mut self.session {
-            Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+    /// Loads the program a launch request names, in a runtime for the Lua version it asks for
+    ///
+    /// A runtime that loaded its program before the session started keeps
+    /// it, unless the request asks for another Lua version. The program runs
+    /// in this process, so `cwd` and `env` change the adapter's own. Its
+    /// `package.path` searches the program's directory and `sourceRoots`
+    /// before the `luaPath` template.
+    async fn load_launched_program(&mut self, arguments: &LaunchArguments) -> Result<(), String> {
+        let Some(program) = &arguments.program else { return Ok(()) };
+        let asks_for_lua = arguments.runtime.is_some() || arguments.lua_library_path.is_some();
+        let fresh = match &self.runtime_factory {
+            Some(factory) if asks_for_lua || self.session.is_none() => Some(factory(arguments)?),
+            _ => None,
         };
+        if let Some(runtime) = fresh {
+            if let Some(backend) = runtime.lua_backend() {
+                tracing::info!("Running {} on {}", program, backend);
+            }
+            self.set_runtime(runtime);
+        }
 
-        let source = params.get("source")?.get("path")?.as_str()?;
-        let breakpoints = params.get("breakpoints")?.as_array()?;
-
-        // Convert DAP breakpoints to our internal format
-        let mut line_breakpoints = Vec::new();
-        for bp in breakpoints {
-            let line = bp.get("line")?.as_u64()? as u32;
-            line_breakpoints.push(super::debug::breakpoints::LineBreakpoint {
-                id: 0, // Will be assigned by BreakpointManager
-                source: source.to_string(),
-                line,
-                condition: bp.get("condition").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                log_message: bp.get("logMessage").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                hit_condition: bp.get("hitCondition").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                verified: false, // Will be set by runtime
-                message: None,
-                hit_count: 0,
-            });
+        let Some(session) = &mut self.session else { return Err("No debug session".to_string()) };
+        if session.runtime.has_program() {
+            return Ok(());
+        }
+        if let Some(cwd) = &arguments.cwd {
+            std::env::set_current_dir(cwd).map_err(|e| format!("Cannot change to {}: {}", cwd, e))?;
+        }
+        for (key, value) in &arguments.env {
+            std::env::set_var(key, value);
         }
 
-        // Store breakpoints in manager
+        let cwd = std::env::current_dir().map_err(|e| format!("Cannot read the working directory: {}", e))?;
+        let script = cwd.join(program);
+        let path_config = LuaPathConfig {
+            lua_path: arguments.lua_path.clone(),
+            lua_cpath: arguments.lua_cpath.clone(),
+            source_roots: arguments.source_roots.iter().map(std::path::PathBuf::from).collect(),
+        };
+        let paths = LuaPaths::derive(&path_config, script.parent().unwrap_or(&cwd), &cwd);
+        let launch = super::runtime::ProgramLaunch {
+            program: program.clone(),
+            args: arguments.args.clone(),
+            lua_path: Some(paths.path),
+            lua_cpath: Some(paths.cpath),
+        };
+        session
+            .runtime
+            .launch_program(&launch)
+            .await
+            .map_err(|e| format!("Failed to load {}: {}", program, e))
+    }
+
+    /// Applies and remembers the arguments of a launch request
+    fn apply_launch_arguments(&mut self, params: &JsonValue) -> Result<(), String> {
+        let args: SessionArguments = arguments::parse("launch", params)?;
+        if let Some(stop_on_entry) = args.stop_on_entry {
+            self.stop_on_entry = stop_on_entry;
+        }
+        self.apply_source_encoding(&args)?;
+        self.apply_rewrite_rules(&args);
+        self.apply_memory_limit(&args);
+        self.apply_coroutine_stacks(&args);
+        self.apply_record_replay(&args);
+        self.apply_unsafe_memory_writes(&args);
+        self.apply_execution_trace_lines(&args);
+        self.apply_breakpoint_file(&args);
+        self.apply_source_maps(&args);
+        self.launch_arguments = Some(params.clone());
+        Ok(())
+    }
+
+    /// Runs a launched program again from the start, in a new Lua state
+    ///
+    /// The launch arguments are applied again, or the ones the request
+    /// carries in `arguments`, and the client's breakpoints are set in the
+    /// new state; their runtime ids change, so clients get `removed` and
+    /// `new` breakpoint events. Like after `launch`, the program starts on
+    /// the `configurationDone` that follows the `initialized` event.
+    async fn handle_restart(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        if self.session.is_none() {
+            return Some(self.no_session_response(id));
+        }
+        let arguments = match params.get("arguments") {
+            // New arguments take the defaults as the launch request did
+            Some(arguments) => match LaunchArguments::from_request(arguments) {
+                Ok(typed) => typed.with_defaults(&self.launch_defaults).overlay(arguments),
+                Err(message) => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message)),
+            },
+            None => match self.launch_arguments.clone() {
+                Some(arguments) => arguments,
+                None => return Some(self.error_response(id, error_code::NOT_AVAILABLE, "Only launched programs can be restarted".to_string())),
+            },
+        };
+
+        let removed = self.runtime_breakpoint_ids();
+        let session = self.session.as_mut()?;
+        let restored = match session.restart().await {
+            Ok(restored) => restored,
+            Err(e) => return Some(self.runtime_error_response(id, "Restart failed", &e)),
+        };
+        self.is_running = false;
+        if let Err(message) = self.apply_launch_arguments(&arguments) {
+            return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message));
+        }
+
+        self.queue_replaced_breakpoints(removed, restored);
+        self.queue_event(Event::initialized());
+        Some(json!({ "id": id, "result": {} }))
+    }
+
+    /// Ids of the line and function breakpoints set in the runtime
+    fn runtime_breakpoint_ids(&self) -> Vec<i64> {
+        let Some(session) = &self.session else { return Vec::new() };
+        session
+            .line_breakpoints
+            .values()
+            .flatten()
+            .chain(&session.function_breakpoints)
+            .copied()
+            .collect()
+    }
+
+    /// Tells the client the breakpoints `removed` were replaced by `restored`, as a new runtime set them
+    fn queue_replaced_breakpoints(
+        &mut self,
+        removed: Vec<i64>,
+        restored: Vec<(Option<String>, super::runtime::Breakpoint)>,
+    ) {
+        for id in removed {
+            let breakpoint = super::runtime::Breakpoint {
+                id,
+                verified: false,
+                line: 0,
+                message: None,
+            };
+            self.queue_event(Event::breakpoint("removed", breakpoint));
+        }
+        for (source, breakpoint) in restored {
+            self.queue_event(match source {
+                Some(source) => Event::breakpoint_in("new", breakpoint, &source),
+                None => Event::breakpoint("new", breakpoint),
+            });
+        }
+    }
+
+    fn handle_attach(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        // The runtime is connected to the target before the DAP session starts
+        if self.session.is_none() {
+            return Some(self.no_session_response(id));
+        }
+        let args: SessionArguments = match self.parse_arguments(id, "attach", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        if let Err(message) = self.apply_source_encoding(&args) {
+            return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message));
+        }
+        self.apply_rewrite_rules(&args);
+        self.apply_memory_limit(&args);
+        self.apply_coroutine_stacks(&args);
+        self.apply_breakpoint_file(&args);
+        self.apply_source_maps(&args);
+        Some(json!({ "id": id, "result": {} }))
+    }
+
+    /// Applies the `rewriteRules` launch/attach argument, if given
+    fn apply_rewrite_rules(&mut self, args: &SessionArguments) {
+        if let Some(rules) = &args.rewrite_rules {
+            self.rewrite_rules = RewriteRules::new(rules.clone());
+        }
+    }
+
+    /// Applies the `sourceMaps` launch/attach argument and the source path settings
+    ///
+    /// `cwd` is searched for the maps of TypeScript sources, and relative
+    /// `sourceRoot` and `tsconfig` paths are taken against it.
+    fn apply_source_maps(&mut self, args: &SessionArguments) {
+        if args.source_maps == Some(false) {
+            self.source_mapping = None;
+            return;
+        }
+        let root = args
+            .cwd
+            .as_ref()
+            .map(std::path::PathBuf::from)
+            .or_else(|| std::env::current_dir().ok());
+        let base = root.clone().unwrap_or_default();
+        let (resolver, error) = PathResolver::new(&args.source_paths, &base);
+        if let Some(error) = error {
+            self.queue_event(Event::output("console", &format!("Ignoring tsconfig {}\n", error)));
+        }
+        self.source_mapping = Some(SourceMapping::new(root, resolver));
+    }
+
+    /// Applies the `sourceEncoding` launch/attach argument, if given
+    fn apply_source_encoding(&mut self, args: &SessionArguments) -> Result<(), String> {
+        let label = match &args.source_encoding {
+            Some(label) => label,
+            None => return Ok(()),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+
+        crate::debug::encoding::lookup(label)?;
+        let mut config = session.config().clone();
+        config.source_encoding = Some(label.clone());
+        session.set_config(config);
+        Ok(())
+    }
+
+    /// Applies the `memoryLimitKb` launch/attach argument, if given; 0 removes the limit
+    fn apply_memory_limit(&mut self, args: &SessionArguments) {
+        let limit_kb = match args.memory_limit_kb {
+            Some(limit_kb) => limit_kb,
+            None => return,
+        };
+        if let Some(session) = &mut self.session {
+            let mut config = session.config().clone();
+            config.memory_limit_kb = (limit_kb > 0).then_some(limit_kb);
+            session.set_config(config);
+        }
+    }
+
+    /// Applies the `stitchCoroutineStacks` launch/attach argument, if given
+    fn apply_coroutine_stacks(&mut self, args: &SessionArguments) {
+        let stitch = match args.stitch_coroutine_stacks {
+            Some(stitch) => stitch,
+            None => return,
+        };
+        if let Some(session) = &mut self.session {
+            let mut config = session.config().clone();
+            config.stitch_coroutine_stacks = stitch;
+            session.set_config(config);
+        }
+    }
+
+    /// Applies the `recordReplay` launch argument, if given
+    ///
+    /// Recording starts with the program, so it is only read at launch.
+    fn apply_record_replay(&mut self, args: &SessionArguments) {
+        let record = match args.record_replay {
+            Some(record) => record,
+            None => return,
+        };
+        if let Some(session) = &mut self.session {
+            let mut config = session.config().clone();
+            config.record_replay = record;
+            session.set_config(config);
+        }
+    }
+
+    /// Applies the `unsafeMemoryWrites` launch argument, if given
+    fn apply_unsafe_memory_writes(&mut self, args: &SessionArguments) {
+        let allowed = match args.unsafe_memory_writes {
+            Some(allowed) => allowed,
+            None => return,
+        };
+        if let Some(session) = &mut self.session {
+            let mut config = session.config().clone();
+            config.unsafe_memory_writes = allowed;
+            session.set_config(config);
+        }
+    }
+
+    /// Applies the `traceLines` launch argument, if given
+    fn apply_execution_trace_lines(&mut self, args: &SessionArguments) {
+        let lines = match args.trace_lines {
+            Some(lines) => lines,
+            None => return,
+        };
+        if let Some(session) = &mut self.session {
+            let mut config = session.config().clone();
+            config.execution_trace_lines = lines;
+            session.set_config(config);
+        }
+    }
+
+    async fn handle_disconnect(&mut self, id: u64) -> Option<JsonValue> {
+        // Terminate the debuggee process if it's running
+        if let Err(e) = self.terminate_process().await {
+            tracing::error!("error terminating process: {}", e);
+        }
+
+        // A program that keeps running, such as an embedding host, is left as it was before attaching
+        if let Some(session) = &mut self.session {
+            if let Err(e) = session.runtime.detach().await {
+                tracing::error!("error detaching from the program: {}", e);
+            }
+        }
+
+        // Clean up the session
+        self.session = None;
+        self.is_running = false;
+        
+        Some(json!({ "id": id, "result": {} }))
+    }
+
+    async fn handle_set_breakpoints(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::SetBreakpointsArguments = match self.parse_arguments(id, "setBreakpoints", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        if self.session.is_none() {
+            return Some(self.no_session_response(id));
+        }
+
+        let source = match args.source.path() {
+            Ok(path) => path,
+            Err(message) => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message)),
+        };
+        // Every request carries the full set for the source; an empty or
+        // missing list clears it
+        let line_breakpoints = line_breakpoints_from(source, &args.breakpoints);
+        // The runtime stops on lines; a column is kept for the client as long as the line is
+        let requested: Vec<(u32, Option<u32>)> = line_breakpoints.iter().map(|bp| (bp.line, bp.column)).collect();
+
+        let results: Vec<JsonValue> = self
+            .replace_line_breakpoints(source, line_breakpoints)
+            .await?
+            .into_iter()
+            .zip(requested)
+            .map(|(bp, (line, column))| {
+                let mut result = json!({
+                    "id": bp.id,
+                    "verified": bp.verified,
+                    "line": bp.line,
+                    "message": bp.message
+                });
+                if let Some(column) = column.filter(|_| bp.line == line) {
+                    result["column"] = json!(column);
+                }
+                result
+            })
+            .collect();
+
+        Some(json!({
+            "id": id,
+            "result": { "breakpoints": results }
+        }))
+    }
+
+    /// Lists the lines of a range a breakpoint can be set on, for editors to show as targets
+    async fn handle_breakpoint_locations(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::BreakpointLocationsArguments = match self.parse_arguments(id, "breakpointLocations", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let source = match args.source.path() {
+            Ok(path) => path,
+            Err(message) => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message)),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        match session.breakpoint_locations(source, args.line, args.end_line.unwrap_or(args.line)).await {
+            Ok(lines) => {
+                let locations: Vec<JsonValue> = lines.into_iter().map(|line| json!({ "line": line })).collect();
+                Some(json!({
+                    "id": id,
+                    "result": { "breakpoints": locations }
+                }))
+            }
+            Err(e) => Some(self.runtime_error_response(id, "Failed to get breakpoint locations", &e)),
+        }
+    }
+
+    /// Gives a source exactly the given line breakpoints, in the manager and the runtime
+    ///
+    /// Returns each breakpoint as the runtime set it; None without a session.
+    async fn replace_line_breakpoints(
+        &mut self,
+        source: &str,
+        line_breakpoints: Vec<super::debug::breakpoints::LineBreakpoint>,
+    ) -> Option<Vec<super::runtime::Breakpoint>> {
+        let session = self.session.as_mut()?;
+
+        // Store breakpoints in manager
         let stored_breakpoints = session.breakpoint_manager().set_line_breakpoints(source.to_string(), line_breakpoints);
 
-        // Set breakpoints in runtime
+        // Set breakpoints in runtime, replacing the ones from the last request for this source
+        session.clear_line_breakpoints(source).await;
         let mut results = Vec::new();
         for bp in &stored_breakpoints {
             match session.set_breakpoint(&bp.source, bp.line).await {
-                Ok(runtime_bp) => {
-                    results.push(json!({
-                        "id": runtime_bp.id,
-                        "verified": runtime_bp.verified,
-                        "line": runtime_bp.line,
-                        "message": runtime_bp.message
-                    }));
+                Ok(line_bp) => {
+                    session.breakpoint_manager().update_line_breakpoint(
+                        &bp.source,
+                        bp.line,
+                        line_bp.id,
+                        line_bp.verified,
+                        line_bp.message.clone(),
+                    );
+                    let runtime_bp = super::runtime::Breakpoint {
+                        id: line_bp.id,
+                        verified: line_bp.verified,
+                        line: line_bp.line,
+                        message: line_bp.message,
+                    };
+                    if runtime_bp.verified {
+                        self.hooks.breakpoint_bound(&runtime_bp);
+                    }
+                    results.push(runtime_bp);
                 }
                 Err(_) => {
-                    results.push(json!({
-                        "id": bp.id,
-                        "verified": false,
-                        "line": bp.line,
-                        "message": "Failed to set breakpoint"
-                    }));
+                    results.push(super::runtime::Breakpoint {
+                        id: bp.id,
+                        verified: false,
+                        line: bp.line,
+                        message: Some("Failed to set breakpoint".to_string()),
+                    });
                 }
             }
         }
+        Some(results)
+    }
 
-        Some(json!({
-            "id": id,
-            "result": { "breakpoints": results }
-        }))
+    /// Starts applying a breakpoint file whenever it changes, replacing any file watched before
+    pub fn watch_breakpoint_file(&mut self, path: impl Into<std::path::PathBuf>) {
+        self.breakpoint_file = Some(BreakpointFileWatcher::new(path));
+    }
+
+    /// Watches the `breakpointsFile` launch/attach argument, or the default file in `cwd`
+    fn apply_breakpoint_file(&mut self, args: &SessionArguments) {
+        let path = match &args.breakpoints_file {
+            Some(path) => std::path::PathBuf::from(path),
+            None => {
+                let cwd = args.cwd.as_ref().map(std::path::PathBuf::from);
+                match cwd.or_else(|| std::env::current_dir().ok()) {
+                    Some(cwd) => cwd.join(breakpoint_file::DEFAULT_FILE_NAME),
+                    None => return,
+                }
+            }
+        };
+        self.watch_breakpoint_file(path);
+    }
+
+    /// Applies the watched breakpoint file if it changed since the last check
+    ///
+    /// Clients learn about the change through breakpoint events: `removed`
+    /// for every breakpoint the file replaced and `new` for the ones it set.
+    async fn sync_breakpoint_file(&mut self) {
+        let Some(changes) = self.breakpoint_file.as_mut().and_then(|watcher| watcher.poll()) else {
+            return;
+        };
+        let changes = match changes {
+            Ok(changes) => changes,
+            Err(message) => {
+                self.queue_event(Event::output("console", &format!("Ignoring breakpoint file {}\n", message)));
+                return;
+            }
+        };
+
+        for (source, breakpoints) in changes {
+            let line_breakpoints = line_breakpoints_from(&source, &breakpoints);
+            let removed = match &self.session {
+                Some(session) => session.line_breakpoints.get(&source).cloned().unwrap_or_default(),
+                None => return,
+            };
+            for id in removed {
+                let breakpoint = super::runtime::Breakpoint {
+                    id,
+                    verified: false,
+                    line: 0,
+                    message: None,
+                };
+                self.queue_event(Event::breakpoint("removed", breakpoint));
+            }
+            for breakpoint in self.replace_line_breakpoints(&source, line_breakpoints).await.unwrap_or_default() {
+                self.queue_event(Event::breakpoint_in("new", breakpoint, &source));
+            }
+        }
+    }
+
+    /// Starts hot reloading the files `config` watches whenever they change
+    pub fn watch_for_hot_reload(&mut self, config: WatcherConfig) {
+        self.hot_reload_watcher = Some(FileWatcher::new(config));
+    }
+
+    /// Reloads the modules whose files changed since the last check
+    ///
+    /// Only modules the program has required are reloaded, so editing the
+    /// main script or a file nothing uses runs nothing. Changes to watched
+    /// files that are not Lua run the build command. Each reload, or its
+    /// failure, is summarized on the console.
+    async fn sync_hot_reload(&mut self) {
+        let Some(watcher) = self.hot_reload_watcher.as_mut() else { return };
+        let changed = watcher.poll(std::time::Instant::now());
+        let config = watcher.config().clone();
+        // Changes made while nothing runs are taken as the starting point
+        if changed.is_empty() || !self.is_running {
+            return;
+        }
+
+        let (lua_files, others): (Vec<_>, Vec<_>) =
+            changed.into_iter().partition(|path| path.extension().is_some_and(|ext| ext == "lua"));
+        if let (Some(command), Some(first)) = (&config.build_command, others.first()) {
+            let message = match run_build_command(command, &config.root).await {
+                Ok(()) => format!("Hot reload: rebuilt after {} changed\n", first.display()),
+                Err(e) => format!("[error] Hot reload: `{}` failed: {}\n", command, e),
+            };
+            self.queue_event(Event::output("console", &message));
+        }
+
+        for path in lua_files {
+            let Some(name) = config.module_name(&path) else { continue };
+            let Some(session) = self.session.as_mut() else { return };
+            let quoted = name.replace('\\', "\\\\").replace('"', "\\\"");
+            let loaded = session.runtime.evaluate_global(&format!("package.loaded[\"{}\"] ~= nil", quoted)).await;
+            if !matches!(loaded, Ok(Value::Boolean(true))) {
+                continue;
+            }
+            let source = match std::fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(e) => {
+                    let message = format!("[error] Hot reload: cannot read {}: {}\n", path.display(), e);
+                    self.queue_event(Event::output("console", &message));
+                    continue;
+                }
+            };
+
+            let lines = match session.runtime.hot_reload(&source, Some(&name)).await {
+                Ok(result) => hot_reload_summary(&path, &name, &result),
+                Err(e) => vec![format!("[error] Hot reload of {} failed: {}\n", path.display(), e)],
+            };
+            for line in lines {
+                self.queue_event(Event::output("console", &line));
+            }
+        }
     }
 
     async fn handle_set_function_breakpoints(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::SetFunctionBreakpointsArguments =
+            match self.parse_arguments(id, "setFunctionBreakpoints", params) {
+                Ok(args) => args,
+                Err(response) => return Some(response),
+            };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        let breakpoints = params.get("breakpoints")?.as_array()?;
-
         // Convert DAP breakpoints to our internal format
-        let mut func_breakpoints = Vec::new();
-        for bp in breakpoints {
-            let name = bp.get("name")?.as_str()?;
-            func_breakpoints.push(super::debug::breakpoints::FunctionBreakpoint {
+        let func_breakpoints = args
+            .breakpoints
+            .into_iter()
+            .map(|bp| super::debug::breakpoints::FunctionBreakpoint {
                 id: 0, // Will be assigned by BreakpointManager
-                name: name.to_string(),
-                condition: bp.get("condition").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                log_message: bp.get("logMessage").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                hit_condition: bp.get("hitCondition").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                name: bp.name,
+                condition: bp.condition,
+                log_message: bp.log_message,
+                hit_condition: bp.hit_condition,
                 verified: false, // Will be set by runtime
                 message: None,
                 hit_count: 0,
-            });
-        }
+            })
+            .collect();
 
         // Store breakpoints in manager
         let stored_breakpoints = session.breakpoint_manager().set_function_breakpoints(func_breakpoints);
 
-        // Set breakpoints in runtime
+        // Set breakpoints in runtime, replacing the ones from the last request
+        session.clear_function_breakpoints().await;
         let mut results = Vec::new();
         for bp in &stored_breakpoints {
             match session.set_function_breakpoint(&bp.name).await {
                 Ok(runtime_bp) => {
+                    session.breakpoint_manager().update_function_breakpoint(
+                        &bp.name,
+                        runtime_bp.id,
+                        runtime_bp.verified,
+                        runtime_bp.message.clone(),
+                    );
                     results.push(json!({
                         "id": runtime_bp.id,
                         "verified": runtime_bp.verified,
@@ -605,19 +1822,37 @@ impl<R: DebugRuntime> DapServer<R> {
     }
 
     async fn handle_set_exception_breakpoints(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::SetExceptionBreakpointsArguments =
+            match self.parse_arguments(id, "setExceptionBreakpoints", params) {
+                Ok(args) => args,
+                Err(response) => return Some(response),
+            };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        let filters = params.get("filters")?.as_array()?;
-        let filter_strings: Vec<String> = filters.iter()
-            .filter_map(|f| f.as_str())
-            .map(|s| s.to_string())
-            .collect();
+        let mut filter_strings = args.filters;
+
+        // Filters given with options may carry a condition and a hit condition
+        let mut conditions = HashMap::new();
+        let mut hit_conditions = HashMap::new();
+        for options in args.filter_options {
+            if !filter_strings.contains(&options.filter_id) {
+                filter_strings.push(options.filter_id.clone());
+            }
+            if let Some(condition) = options.condition.filter(|c| !c.trim().is_empty()) {
+                conditions.insert(options.filter_id.clone(), condition);
+            }
+            if let Some(hit_condition) = options.hit_condition {
+                hit_conditions.insert(options.filter_id, hit_condition);
+            }
+        }
 
         // Store exception filters in manager
         session.breakpoint_manager().set_exception_breakpoints(filter_strings.clone());
+        session.breakpoint_manager().set_exception_conditions(conditions);
+        session.breakpoint_manager().set_exception_hit_conditions(hit_conditions);
 
         // Set exception breakpoints in runtime
         let mut results = Vec::new();
@@ -644,45 +1879,85 @@ impl<R: DebugRuntime> DapServer<R> {
         }))
     }
 
-    async fn handle_set_data_breakpoints(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+    async fn handle_set_metamethod_breakpoints(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::SetMetamethodBreakpointsArguments =
+            match self.parse_arguments(id, "setMetamethodBreakpoints", params) {
+                Ok(args) => args,
+                Err(response) => return Some(response),
+            };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        let breakpoints = params.get("breakpoints")?.as_array()?;
+        let requested: Vec<(String, String)> = args.breakpoints.into_iter().map(|bp| (bp.target, bp.event)).collect();
+
+        let results: Vec<JsonValue> = session
+            .set_metamethod_breakpoints(&requested)
+            .await
+            .into_iter()
+            .map(|result| match result {
+                Ok(bp) => json!({
+                    "id": bp.id,
+                    "verified": bp.verified,
+                    "message": bp.message
+                }),
+                Err(e) => json!({
+                    "verified": false,
+                    "message": format!("Failed to set metamethod breakpoint: {}", e)
+                }),
+            })
+            .collect();
+
+        Some(json!({
+            "id": id,
+            "result": { "breakpoints": results }
+        }))
+    }
+
+    async fn handle_set_data_breakpoints(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::SetDataBreakpointsArguments = match self.parse_arguments(id, "setDataBreakpoints", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        if self.session.is_none() {
+            return Some(self.no_session_response(id));
+        }
 
-        // Convert DAP data breakpoints to our internal format
         let mut data_breakpoints = Vec::new();
-        for bp in breakpoints {
-            let name = bp.get("label")?.as_str()?.to_string();
-            data_breakpoints.push(super::debug::watchpoints::DataBreakpoint {
+        for bp in args.breakpoints {
+            let Some(access_type) = AccessType::from_dap(bp.access_type.as_deref()) else {
+                let message = format!("Unknown access type: {}", bp.access_type.unwrap_or_default());
+                return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message));
+            };
+            let (data_type, name) = DataType::from_data_id(&bp.data_id);
+            data_breakpoints.push(DataBreakpoint {
                 id: 0, // Will be assigned by WatchpointManager
-                name,
-                condition: bp.get("condition").and_then(|v| v.as_str()).map(|s| s.to_string()),
-                hit_condition: bp.get("hitCondition").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                name: name.to_string(),
+                condition: bp.condition,
+                hit_condition: bp.hit_condition,
                 verified: false, // Will be set by runtime
                 message: None,
                 hit_count: 0,
-                data_type: super::debug::watchpoints::DataType::Local, // Default for now
-                access_type: super::debug::watchpoints::AccessType::ReadWrite, // Default for now
+                data_type,
+                access_type,
                 previous_value: None,
             });
         }
 
-        // Store data breakpoints in manager
-        let stored_breakpoints = session.watchpoint_manager().set_data_breakpoints(data_breakpoints);
-
-        // In a full implementation, we would set data breakpoints in the runtime
-        // For now, we'll just return successful results
-        let mut results = Vec::new();
-        for bp in &stored_breakpoints {
-            results.push(json!({
-                "id": bp.id,
-                "verified": true,
-                "message": format!("Data breakpoint set for {}", bp.name)
-            }));
-        }
+        let session = self.session.as_mut()?;
+        let results: Vec<JsonValue> = session
+            .set_data_breakpoints(data_breakpoints)
+            .await
+            .iter()
+            .map(|bp| {
+                json!({
+                    "id": bp.id,
+                    "verified": bp.verified,
+                    "message": bp.message
+                })
+            })
+            .collect();
 
         Some(json!({
             "id": id,
@@ -690,130 +1965,607 @@ impl<R: DebugRuntime> DapServer<R> {
         }))
     }
 
-    fn handle_configuration_done(&self, id: u64) -> Option<JsonValue> {
+    async fn handle_configuration_done(&mut self, id: u64) -> Option<JsonValue> {
+        let stop_on_entry = self.stop_on_entry;
+        if let Some(session) = &mut self.session {
+            if let Err(e) = session.start_program(stop_on_entry).await {
+                return Some(self.runtime_error_response(id, "Failed to start program", &e));
+            }
+            self.is_running = true;
+        }
         Some(json!({ "id": id, "result": {} }))
     }
 
-    async fn handle_continue(&mut self, id: u64) -> Option<JsonValue> {
+    /// Resumes the program, or with `singleThread` only the thread `threadId`
+    async fn handle_continue(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::ExecutionArguments = match self.parse_arguments(id, "continue", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        match session.run().await {
+        let result = match args.single_thread() {
+            Some(thread_id) => session.run_thread(thread_id).await,
+            None => session.run().await.map(|()| true),
+        };
+        match result {
+            Ok(all_threads_continued) => {
+                Some(json!({ "id": id, "result": { "allThreadsContinued": all_threads_continued } }))
+            }
+            Err(e) => Some(self.runtime_error_response(id, "Continue failed", &e)),
+        }
+    }
+
+    /// Steps one game frame: runs until a per-frame function is next called or returns
+    async fn handle_step_frame(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::StepFrameArguments = match self.parse_arguments(id, "stepFrame", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        match session.step_frame(&args.function, args.until).await {
+            Ok(()) => Some(json!({ "id": id, "result": { "allThreadsContinued": true } })),
+            Err(e) => Some(self.runtime_error_response(id, "Frame step failed", &e)),
+        }
+    }
+
+    async fn handle_run_to_location(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::LocationArguments = match self.parse_arguments(id, "runToLocation", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let source = match args.source.path() {
+            Ok(path) => path,
+            Err(message) => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message)),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        match session.run_to_location(source, args.line).await {
             Ok(()) => Some(json!({ "id": id, "result": { "allThreadsContinued": true } })),
-            Err(e) => Some(self.error_response(id, -1, format!("Continue failed: {}", e))),
+            Err(e) => Some(self.runtime_error_response(id, "Run to location failed", &e)),
+        }
+    }
+
+    /// Offers the requested line as a goto target if the stopped function has code on it later on
+    ///
+    /// Lua cannot move the instruction pointer, so `goto` runs to the target
+    /// instead, which only reaches lines after the one the function is
+    /// stopped on. When the runtime cannot tell which lines the stopped
+    /// function has, or the program is not stopped, any line is offered.
+    async fn handle_goto_targets(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::LocationArguments = match self.parse_arguments(id, "gotoTargets", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let source = match args.source.path() {
+            Ok(path) => path,
+            Err(message) => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message)),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        let line = args.line;
+        match session.runtime.goto_lines(source).await {
+            Ok(lines) if !lines.contains(&line) => return Some(json!({ "id": id, "result": { "targets": [] } })),
+            Ok(_) | Err(RuntimeError::NotImplemented(_) | RuntimeError::NotStopped(_)) => {}
+            Err(e) => return Some(self.runtime_error_response(id, "Goto targets failed", &e)),
+        }
+        let target_id = session.goto_target_id(source, line);
+
+        Some(json!({
+            "id": id,
+            "result": {
+                "targets": [{
+                    "id": target_id,
+                    "label": format!("Run to line {}", line),
+                    "line": line
+                }]
+            }
+        }))
+    }
+
+    async fn handle_goto(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::GotoArguments = match self.parse_arguments(id, "goto", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        let target_id = args.target_id;
+        let (source, line) = match session.goto_target(target_id) {
+            Some(target) => target,
+            None => return Some(self.error_response(id, error_code::GOTO_TARGET_NOT_FOUND, format!("Unknown goto target: {}", target_id))),
+        };
+
+        match session.run_to_location(&source, line).await {
+            Ok(()) => Some(json!({ "id": id, "result": {} })),
+            Err(e) => Some(self.runtime_error_response(id, "Goto failed", &e)),
         }
     }
 
     async fn handle_profiling_start(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
         use crate::profiling::ProfilingMode;
 
+        let args: arguments::ProfilingStartArguments = match self.parse_arguments(id, "profiling/start", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        let mode = params.get("mode").and_then(|v| v.as_str()).unwrap_or("sampling");
-        let profiling_mode = match mode {
-            "sampling" => {
-                let interval = params.get("intervalMs")
-                    .and_then(|v| v.as_u64())
-                    .unwrap_or(10) as u32;
-                ProfilingMode::Sampling { interval_ms: interval }
-            }
+        let interval = args.interval_ms.unwrap_or(10);
+        let profiling_mode = match args.mode.as_deref().unwrap_or("sampling") {
+            "sampling" => ProfilingMode::Sampling { interval_ms: interval },
+            "wallClock" => ProfilingMode::WallClock { interval_ms: interval.max(1) },
             "callTrace" => ProfilingMode::CallTrace,
             "lineLevel" => ProfilingMode::LineLevel,
-            _ => return Some(self.error_response(id, -1, "Invalid profiling mode".to_string())),
+            _ => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, "Invalid profiling mode".to_string())),
         };
 
+        // Restarting would silently throw away what was collected so far
+        if matches!(session.runtime.get_profile_snapshot().await, Ok(Some(_))) {
+            return Some(self.error_response(id, error_code::NOT_AVAILABLE, "Profiling is already running".to_string()));
+        }
+
         match session.runtime.start_profiling(profiling_mode).await {
             Ok(_) => Some(json!({
                 "id": id,
                 "result": { "started": true }
             })),
-            Err(e) => Some(self.error_response(id, -1, format!("Failed to start profiling: {}", e))),
+            Err(e) => Some(self.runtime_error_response(id, "Failed to start profiling", &e)),
         }
     }
 
     async fn handle_profiling_stop(&mut self, id: u64) -> Option<JsonValue> {
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
         match session.runtime.stop_profiling().await {
-            Ok(data) => Some(json!({
+            Ok(data) => {
+                let response = json!({
+                    "id": id,
+                    "result": profile_json(&data)
+                });
+                self.last_profile = Some(data);
+                Some(response)
+            }
+            Err(e) => Some(self.runtime_error_response(id, "Failed to stop profiling", &e)),
+        }
+    }
+
+    /// Exports the running profile, or the last stopped one, as speedscope or Chrome trace JSON
+    ///
+    /// Writes the file when `path` is given; otherwise the document is returned
+    /// in the response.
+    async fn handle_profile_export(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        use crate::profiling::export::{self, ExportFormat};
+
+        let args: arguments::ExportArguments = match self.parse_arguments(id, "wayfinder/profile/export", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let format = match &args.format {
+            Some(format) => match format.parse::<ExportFormat>() {
+                Ok(format) => format,
+                Err(e) => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, e)),
+            },
+            None => ExportFormat::default(),
+        };
+
+        let path = args.path.as_deref();
+        let serializer = match serializer_param(args.serializer.as_deref(), path) {
+            Ok(serializer) => serializer,
+            Err(message) => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message)),
+        };
+
+        let snapshot = match &self.session {
+            Some(session) => session.runtime.get_profile_snapshot().await.ok().flatten(),
+            None => None,
+        };
+        let Some(data) = snapshot.as_ref().or(self.last_profile.as_ref()) else {
+            return Some(self.error_response(id, error_code::NOT_AVAILABLE, "No profile to export".to_string()));
+        };
+
+        match path {
+            Some(path) => match export::write_profile(data, format, serializer, std::path::Path::new(path)) {
+                Ok(()) => Some(json!({
+                    "id": id,
+                    "result": { "path": path, "format": format, "serializer": serializer }
+                })),
+                Err(e) => Some(self.error_response(id, error_code::IO, format!("Failed to write profile: {}", e))),
+            },
+            None => Some(json!({
                 "id": id,
-                "result": {
-                    "durationMs": data.duration_ms,
-                    "totalSamples": data.total_samples,
-                    "functions": data.functions.iter().map(|(name, profile)| {
-                        json!({
-                            "name": name,
-                            "callCount": profile.call_count,
-                            "totalTimeMs": profile.total_time_ms,
-                            "selfTimeMs": profile.self_time_ms,
-                        })
-                    }).collect::<Vec<_>>()
-                }
+                "result": { "format": format, "content": export::export(data, format, "profile") }
             })),
-            Err(e) => Some(self.error_response(id, -1, format!("Failed to stop profiling: {}", e))),
         }
     }
 
     async fn handle_profiling_snapshot(&mut self, id: u64) -> Option<JsonValue> {
         let session = match &self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
         match session.runtime.get_profile_snapshot().await {
             Ok(Some(data)) => Some(json!({
                 "id": id,
-                "result": {
-                    "durationMs": data.duration_ms,
-                    "totalSamples": data.total_samples,
-                    "functions": data.functions.iter().map(|(name, profile)| {
-                        json!({
-                            "name": name,
-                            "callCount": profile.call_count,
-                            "totalTimeMs": profile.total_time_ms,
-                            "selfTimeMs": profile.self_time_ms,
-                        })
-                    }).collect::<Vec<_>>()
-                }
+                "result": profile_json(&data)
             })),
-            Ok(None) => Some(self.error_response(id, -1, "No active profiler".to_string())),
-            Err(e) => Some(self.error_response(id, -1, format!("Failed to get profile snapshot: {}", e))),
+            Ok(None) => Some(self.error_response(id, error_code::NOT_AVAILABLE, "No active profiler".to_string())),
+            Err(e) => Some(self.runtime_error_response(id, "Failed to get profile snapshot", &e)),
         }
     }
 
     async fn handle_memory_statistics(&mut self, id: u64) -> Option<JsonValue> {
         let session = match &self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
+        };
+
+        match session.runtime.get_memory_statistics().await {
+            Ok(stats) => Some(json!({
+                "id": id,
+                "result": memory_statistics_json(&stats)
+            })),
+            Err(e) => Some(self.runtime_error_response(id, "Failed to get memory statistics", &e)),
+        }
+    }
+
+    /// Runs a full collection, or with `"mode": "step"` an incremental step of `stepKb` kilobytes
+    async fn handle_memory_gc(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::MemoryGcArguments = match self.parse_arguments(id, "wayfinder/memory/gc", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        let cycle_finished = match args.mode.as_deref().unwrap_or("collect") {
+            "collect" => session.runtime.force_gc().await.map(|_| true),
+            "step" => session.runtime.gc_step(args.step_kb).await,
+            other => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, format!("Unknown GC mode: {}", other))),
+        };
+        let cycle_finished = match cycle_finished {
+            Ok(finished) => finished,
+            Err(e) => return Some(self.runtime_error_response(id, "Failed to collect garbage", &e)),
         };
 
         match session.runtime.get_memory_statistics().await {
+            Ok(stats) => {
+                let mut result = memory_statistics_json(&stats);
+                result["cycleFinished"] = json!(cycle_finished);
+                Some(json!({ "id": id, "result": result }))
+            }
+            Err(e) => Some(self.runtime_error_response(id, "Failed to get memory statistics", &e)),
+        }
+    }
+
+    /// Sets the collector's `pause` and `stepMul`, either of which may be left out
+    async fn handle_memory_tune(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::MemoryTuneArguments = match self.parse_arguments(id, "wayfinder/memory/tune", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let (pause, step_mul) = (args.pause, args.step_mul);
+        if pause.is_none() && step_mul.is_none() {
+            return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, "Missing pause or stepMul".to_string()));
+        }
+        if pause.into_iter().chain(step_mul).any(|value| value < 0) {
+            return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, "GC settings cannot be negative".to_string()));
+        }
+
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        match session.runtime.tune_gc(pause, step_mul).await {
             Ok(stats) => Some(json!({
+                "id": id,
+                "result": memory_statistics_json(&stats)
+            })),
+            Err(e) => Some(self.runtime_error_response(id, "Failed to tune the GC", &e)),
+        }
+    }
+
+    /// Returns the source lines that allocated the most memory still in use
+    ///
+    /// `track` starts or stops tracking first, `limit` caps the number of lines
+    /// and `reset` clears the counts once they are returned.
+    async fn handle_memory_allocations(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::MemoryAllocationsArguments = match self.parse_arguments(id, "wayfinder/memory/allocations", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        if let Some(track) = args.track {
+            if let Err(e) = session.runtime.set_allocation_tracking(track).await {
+                return Some(self.runtime_error_response(id, "Failed to track allocations", &e));
+            }
+        }
+        let limit = args.limit.unwrap_or(crate::memory::DEFAULT_ALLOCATION_SITES);
+
+        match session.runtime.allocation_profile(limit, args.reset).await {
+            Ok(profile) => Some(json!({ "id": id, "result": profile })),
+            Err(e) => Some(self.runtime_error_response(id, "Failed to get allocations", &e)),
+        }
+    }
+
+    /// Writes the session's artifacts to a zip archive at `path` for a bug report
+    ///
+    /// With `redactSources`, source code is blanked out of everything in it.
+    async fn handle_bundle_session(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::BundleSessionArguments = match self.parse_arguments(id, "wayfinder/bundleSession", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let path = std::path::PathBuf::from(args.path);
+        let options = bundle::BundleOptions {
+            redact_sources: args.redact_sources,
+        };
+
+        let mut contents = bundle::SessionBundle {
+            trace: self.trace.entries(),
+            profile: self.last_profile.clone(),
+            ..bundle::SessionBundle::default()
+        };
+        if let Some(session) = &self.session {
+            contents.config = Some(session.config().clone());
+            contents.breakpoints = Some(session.inventory(InventoryScope::All));
+            if contents.profile.is_none() {
+                contents.profile = session.runtime.get_profile_snapshot().await.ok().flatten();
+            }
+            // Runtimes without a flight recorder simply have no crash to report
+            contents.crash = session.runtime.flight_records().await.ok().and_then(|records| {
+                records
+                    .into_iter()
+                    .rev()
+                    .find(|record| matches!(record.kind, crate::debug::flight_recorder::RecordKind::Error { .. }))
+            });
+        }
+
+        match contents.write(&path, options) {
+            Ok(files) => Some(json!({
+                "id": id,
+                "result": { "path": path.display().to_string(), "files": files }
+            })),
+            Err(e) => Some(self.error_response(id, error_code::IO, format!("Failed to write {}: {}", path.display(), e))),
+        }
+    }
+
+    async fn handle_flight_recorder(&mut self, id: u64) -> Option<JsonValue> {
+        let session = match &self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        match session.runtime.flight_records().await {
+            Ok(records) => Some(json!({ "id": id, "result": { "records": records } })),
+            Err(e) => Some(self.runtime_error_response(id, "Failed to get flight records", &e)),
+        }
+    }
+
+    /// Lists the modules in `package.loaded`, sorted by name and paged
+    async fn handle_modules(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::ModulesArguments = match self.parse_arguments(id, "modules", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        match session.runtime.modules().await {
+            Ok(modules) => {
+                let total_modules = modules.len();
+                let modules = crate::debug::modules::page(modules, args.start_module, args.module_count);
+                Some(json!({
+                    "id": id,
+                    "result": { "modules": modules, "totalModules": total_modules }
+                }))
+            }
+            Err(e) => Some(self.runtime_error_response(id, "Failed to list modules", &e)),
+        }
+    }
+
+    /// Reads the bytes behind a string's or userdata's `memoryReference`
+    async fn handle_read_memory(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::ReadMemoryArguments = match self.parse_arguments(id, "readMemory", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        match session.runtime.read_memory(&args.memory_reference, args.offset, args.count).await {
+            Ok(read) => {
+                let mut result = json!({
+                    "address": format!("0x{:x}", read.address),
+                    "unreadableBytes": read.unreadable_bytes
+                });
+                if !read.data.is_empty() {
+                    result["data"] = json!(base64::engine::general_purpose::STANDARD.encode(&read.data));
+                }
+                Some(json!({ "id": id, "result": result }))
+            }
+            Err(e) => Some(self.runtime_error_response(id, "Failed to read memory", &e)),
+        }
+    }
+
+    /// Writes into the block of a full userdata
+    ///
+    /// Refused unless `unsafe_memory_writes` is set.
+    async fn handle_write_memory(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::WriteMemoryArguments = match self.parse_arguments(id, "writeMemory", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let data = match base64::engine::general_purpose::STANDARD.decode(&args.data) {
+            Ok(data) => data,
+            Err(e) => {
+                return Some(self.error_response(
+                    id,
+                    arguments::INVALID_ARGUMENTS,
+                    format!("Invalid writeMemory arguments: data is not valid base64: {}", e),
+                ))
+            }
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+        if !session.config().unsafe_memory_writes {
+            let error = RuntimeError::MemoryAccess("memory writes are off; set unsafeMemoryWrites to allow them".to_string());
+            return Some(self.runtime_error_response(id, "Failed to write memory", &error));
+        }
+
+        match session.runtime.write_memory(&args.memory_reference, args.offset, data, args.allow_partial).await {
+            Ok(written) => {
+                let mut result = json!({ "bytesWritten": written });
+                if args.allow_partial {
+                    result["offset"] = json!(args.offset);
+                }
+                Some(json!({ "id": id, "result": result }))
+            }
+            Err(e) => Some(self.runtime_error_response(id, "Failed to write memory", &e)),
+        }
+    }
+
+    async fn handle_execution_trace(&mut self, id: u64) -> Option<JsonValue> {
+        let session = match &self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        match session.runtime.execution_trace().await {
+            Ok(entries) => Some(json!({ "id": id, "result": { "entries": entries } })),
+            Err(e) => Some(self.runtime_error_response(id, "Failed to get execution trace", &e)),
+        }
+    }
+
+    /// Changes the debugger's settings mid-session
+    ///
+    /// With `reload`, the settings file is read again and the request's own
+    /// settings are applied over it. The response holds the settings now in
+    /// effect.
+    fn handle_configure(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::ConfigureArguments = match self.parse_arguments(id, "wayfinder/configure", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let mut settings = args.settings;
+        if args.reload {
+            match self.load_settings() {
+                Ok(loaded) => settings = settings.or(loaded),
+                Err(message) if self.settings_loader.is_none() => {
+                    return Some(self.error_response(id, error_code::NOT_AVAILABLE, message))
+                }
+                Err(message) => return Some(self.error_response(id, error_code::IO, message)),
+            }
+        }
+        if let Err(message) = self.configure(&settings) {
+            return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message));
+        }
+
+        let session = self.session.as_ref()?;
+        Some(json!({ "id": id, "result": DebuggerSettings::of(session.config()) }))
+    }
+
+    async fn handle_heap_search(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        use crate::memory::{DEFAULT_SEARCH_MAX_RESULTS, DEFAULT_SEARCH_MAX_TABLES};
+
+        let args: arguments::HeapSearchArguments = match self.parse_arguments(id, "heapSearch", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        if args.predicate.trim().is_empty() {
+            return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, "Missing predicate".to_string()));
+        }
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        let max_tables = args.max_tables.unwrap_or(DEFAULT_SEARCH_MAX_TABLES);
+        let max_results = args.max_results.unwrap_or(DEFAULT_SEARCH_MAX_RESULTS);
+
+        match session.runtime.search_heap(&args.predicate, max_tables, max_results).await {
+            Ok(result) => Some(json!({ "id": id, "result": result })),
+            Err(e) => Some(self.runtime_error_response(id, "Heap search failed", &e)),
+        }
+    }
+
+    async fn handle_heap_snapshot(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::ExportArguments = match self.parse_arguments(id, "heapSnapshot", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let path = args.path.as_deref();
+        let serializer = match serializer_param(args.serializer.as_deref(), path) {
+            Ok(serializer) => serializer,
+            Err(message) => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, message)),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        let snapshot = match session.runtime.take_heap_snapshot().await {
+            Ok(snapshot) => snapshot,
+            Err(e) => return Some(self.runtime_error_response(id, "Failed to take heap snapshot", &e)),
+        };
+        let Some(path) = path else {
+            return Some(json!({ "id": id, "result": snapshot }));
+        };
+
+        // Only the summary is returned when the objects go to a file
+        match serializer.write(&snapshot, std::path::Path::new(path)) {
+            Ok(()) => Some(json!({
                 "id": id,
                 "result": {
-                    "totalKB": stats.total_kb,
-                    "totalBytes": stats.total_bytes,
-                    "gcPause": stats.gc_pause,
-                    "gcStepMul": stats.gc_step_mul,
-                    "gcRunning": stats.gc_running,
+                    "id": snapshot.id,
+                    "path": path,
+                    "serializer": serializer,
+                    "objectCounts": snapshot.object_counts,
                 }
             })),
-            Err(e) => Some(self.error_response(id, -1, format!("Failed to get memory statistics: {}", e))),
+            Err(e) => Some(self.error_response(id, error_code::IO, format!("Failed to write heap snapshot: {}", e))),
         }
     }
 
     async fn handle_force_gc(&mut self, id: u64) -> Option<JsonValue> {
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
         match session.runtime.force_gc().await {
@@ -821,134 +2573,254 @@ impl<R: DebugRuntime> DapServer<R> {
                 "id": id,
                 "result": { "success": true }
             })),
-            Err(e) => Some(self.error_response(id, -1, format!("Failed to force GC: {}", e))),
+            Err(e) => Some(self.runtime_error_response(id, "Failed to force GC", &e)),
         }
     }
 
+    /// Reloads a module, so editors can reload the file being edited
+    ///
+    /// The module is named by `module`, by the file it was loaded from as
+    /// `path`, or both; `package.path` gives the one from the other. The file
+    /// is read unless `source` carries unsaved text. The response describes
+    /// what became of the module's fields along with any warnings, which are
+    /// also summarized on the console.
     async fn handle_hot_reload(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::HotReloadArguments = match self.parse_arguments(id, "wayfinder/hotReload", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let module = args.module.as_deref();
+        let path = args.path.as_ref().map(std::path::PathBuf::from);
+        let source = args.source.as_deref();
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        // Extract the module source from parameters
-        let module_source = match params.get("source") {
-            Some(source) => match source.as_str() {
-                Some(s) => s,
-                None => return Some(self.error_response(id, -1, "Source must be a string".to_string())),
+        let (module, path) = if module.is_some() && path.is_some() {
+            (module.map(str::to_string), path)
+        } else if module.is_none() && path.is_none() && source.is_some() {
+            (None, None)
+        } else {
+            let search_path = match session.runtime.evaluate_global("package.path").await {
+                Ok(Value::String(search_path)) => search_path,
+                _ => String::new(),
+            };
+            let cwd = std::env::current_dir().unwrap_or_default();
+            match (module, path) {
+                (Some(module), None) => match lua_paths::search_module(&search_path, module, &cwd) {
+                    Some(path) => (Some(module.to_string()), Some(path)),
+                    None if source.is_some() => (Some(module.to_string()), None),
+                    None => {
+                        let message = format!("Module '{}' is not found on package.path", module);
+                        return Some(self.error_response(id, error_code::MODULE_NOT_FOUND, message));
+                    }
+                },
+                (None, Some(path)) => match lua_paths::module_for_file(&search_path, &path, &cwd) {
+                    Some(module) => (Some(module), Some(path)),
+                    None => {
+                        let message = format!("{} is not a module on package.path", path.display());
+                        return Some(self.error_response(id, error_code::MODULE_NOT_FOUND, message));
+                    }
+                },
+                _ => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, "Missing module or path".to_string())),
+            }
+        };
+
+        let source = match (source, &path) {
+            (Some(source), _) => source.to_string(),
+            (None, Some(path)) => match std::fs::read_to_string(path) {
+                Ok(source) => source,
+                Err(e) => return Some(self.error_response(id, error_code::IO, format!("Cannot read {}: {}", path.display(), e))),
             },
-            None => return Some(self.error_response(id, -1, "Missing source parameter".to_string())),
+            (None, None) => return Some(self.error_response(id, arguments::INVALID_ARGUMENTS, "Missing module or path".to_string())),
         };
 
-        // Extract optional module name
-        let module_name = params.get("name").and_then(|n| n.as_str());
+        let result = match session.runtime.hot_reload(&source, module.as_deref()).await {
+            Ok(result) => result,
+            Err(e) => return Some(self.runtime_error_response(id, "Hot reload failed", &e)),
+        };
+        let name = module.as_deref().unwrap_or("unnamed");
+        let shown = path.clone().unwrap_or_else(|| std::path::PathBuf::from("unsaved source"));
+        for line in hot_reload_summary(&shown, name, &result) {
+            self.queue_event(Event::output("console", &line));
+        }
 
-        // Perform the hot reload operation directly through the runtime
-        match session.runtime.hot_reload(module_source, module_name).await {
-            Ok(result) => {
-                // Send warnings as output events
-                for warning in &result.warnings {
-                    let severity = match warning.severity {
-                        WarningSeverity::Info => "info",
-                        WarningSeverity::Warning => "warning",
-                        WarningSeverity::Error => "error",
-                    };
+        let warnings: Vec<JsonValue> = result
+            .warnings
+            .iter()
+            .map(|w| json!({ "message": w.message, "severity": severity_name(&w.severity) }))
+            .collect();
+        let fields: Vec<JsonValue> = result.fields.iter().map(field_json).collect();
+        Some(json!({
+            "id": id,
+            "result": {
+                "success": result.success,
+                "module": module,
+                "path": path.map(|path| path.display().to_string()),
+                "message": result.message,
+                "warnings": warnings,
+                "fields": fields,
+            }
+        }))
+    }
 
-                    // In a real implementation, we would send this as a DAP output event
-                    // For now, we'll just print it
-                    println!("[{}] Hot reload: {}", severity, warning.message);
-                }
+    async fn handle_next(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::ExecutionArguments = match self.parse_arguments(id, "next", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
 
-                // Return success response
-                Some(json!({
-                    "seq": 0,
-                    "type": "response",
-                    "request_seq": id,
-                    "command": "hotReload",
-                    "success": result.success,
-                    "body": {
-                        "message": result.message,
-                        "warnings": result.warnings.len(),
-                    }
-                }))
-            }
-            Err(e) => Some(self.error_response(id, -1, format!("Hot reload failed: {}", e))),
+        match session.step_by(StepMode::Over, args.granularity).await {
+            Ok(()) => Some(json!({ "id": id, "result": {} })),
+            Err(e) => Some(self.runtime_error_response(id, "Step over failed", &e)),
+        }
+    }
+
+    async fn handle_step_in(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::ExecutionArguments = match self.parse_arguments(id, "stepIn", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        match session.step_by(StepMode::In, args.granularity).await {
+            Ok(()) => Some(json!({ "id": id, "result": {} })),
+            Err(e) => Some(self.runtime_error_response(id, "Step in failed", &e)),
+        }
+    }
+
+    /// Goes back one line; only launched programs recorded with `recordReplay` can
+    async fn handle_step_back(&mut self, id: u64) -> Option<JsonValue> {
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        match session.step_back().await {
+            Ok(()) => Some(json!({ "id": id, "result": {} })),
+            Err(e) => Some(self.runtime_error_response(id, "Step back failed", &e)),
         }
     }
 
-    async fn handle_next(&mut self, id: u64) -> Option<JsonValue> {
+    async fn handle_reverse_continue(&mut self, id: u64) -> Option<JsonValue> {
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        match session.step(StepMode::Over).await {
+        match session.reverse_continue().await {
             Ok(()) => Some(json!({ "id": id, "result": {} })),
-            Err(e) => Some(self.error_response(id, -1, format!("Step over failed: {}", e))),
+            Err(e) => Some(self.runtime_error_response(id, "Reverse continue failed", &e)),
         }
     }
 
-    async fn handle_step_in(&mut self, id: u64) -> Option<JsonValue> {
+    async fn handle_step_out(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::ExecutionArguments = match self.parse_arguments(id, "stepOut", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        match session.step(StepMode::In).await {
+        match session.step_by(StepMode::Out, args.granularity).await {
             Ok(()) => Some(json!({ "id": id, "result": {} })),
-            Err(e) => Some(self.error_response(id, -1, format!("Step in failed: {}", e))),
+            Err(e) => Some(self.runtime_error_response(id, "Step out failed", &e)),
         }
     }
 
-    async fn handle_step_out(&mut self, id: u64) -> Option<JsonValue> {
+    /// Pauses the program, or with `singleThread` stops it when the thread `threadId` next runs
+    ///
+    /// Clients send the selected thread with every pause, so only `singleThread`,
+    /// borrowed from the execution requests, makes the pause thread-targeted.
+    async fn handle_pause(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::ExecutionArguments = match self.parse_arguments(id, "pause", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        match session.step(StepMode::Out).await {
+        let result = match args.single_thread() {
+            Some(thread_id) => session.pause_thread(thread_id).await,
+            None => session.pause().await,
+        };
+        match result {
             Ok(()) => Some(json!({ "id": id, "result": {} })),
-            Err(e) => Some(self.error_response(id, -1, format!("Step out failed: {}", e))),
+            Err(e) => Some(self.runtime_error_response(id, "Pause failed", &e)),
         }
     }
 
-    async fn handle_pause(&mut self, id: u64) -> Option<JsonValue> {
+    async fn handle_threads(&mut self, id: u64) -> Option<JsonValue> {
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        match session.pause().await {
-            Ok(()) => Some(json!({ "id": id, "result": {} })),
-            Err(e) => Some(self.error_response(id, -1, format!("Pause failed: {}", e))),
+        match session.threads().await {
+            Ok(threads) => {
+                let threads: Vec<JsonValue> = threads
+                    .into_iter()
+                    .map(|thread| json!({ "id": thread.id, "name": thread.name }))
+                    .collect();
+                Some(json!({ "id": id, "result": { "threads": threads } }))
+            }
+            Err(e) => Some(self.runtime_error_response(id, "Threads failed", &e)),
         }
     }
 
     async fn handle_stack_trace(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::StackTraceArguments = match self.parse_arguments(id, "stackTrace", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        let thread_id = params.get("threadId").and_then(|v| v.as_u64());
+        // Clients page through deep stacks with startFrame and levels; 0 levels means all
+        let levels = if args.levels > 0 { args.levels } else { usize::MAX };
 
-        match session.stack_trace(thread_id).await {
+        match session.stack_trace(args.thread_id).await {
             Ok(frames) => {
+                let total_frames = frames.len();
                 let stack_frames: Vec<JsonValue> = frames
                     .into_iter()
+                    .skip(args.start_frame)
+                    .take(levels)
                     .map(|frame| {
+                        let mut name = frame.name;
+                        if args.format.module {
+                            if let Some(source) = &frame.source {
+                                name = format!("{} [{}]", name, source.name);
+                            }
+                        }
+                        if args.format.line {
+                            name = format!("{} Line {}", name, frame.line);
+                        }
                         let mut obj = json!({
                             "id": frame.id,
-                            "name": frame.name,
+                            "name": name,
                             "line": frame.line,
                             "column": frame.column,
                         });
-                        if let Some(source) = frame.source {
-                            obj["source"] = json!({
-                                "name": source.name,
-                                "path": source.path,
-                                "sourceReference": source.source_reference.unwrap_or(0)
-                            });
+                        if let Some(source) = &frame.source {
+                            obj["source"] = source_json(source);
+                        }
+                        if let Some(hint) = &frame.presentation_hint {
+                            obj["presentationHint"] = json!(hint);
                         }
                         obj
                     })
@@ -958,23 +2830,25 @@ impl<R: DebugRuntime> DapServer<R> {
                     "id": id,
                     "result": {
                         "stackFrames": stack_frames,
-                        "totalFrames": stack_frames.len()
+                        "totalFrames": total_frames
                     }
                 }))
             }
-            Err(e) => Some(self.error_response(id, -1, format!("Stack trace failed: {}", e))),
+            Err(e) => Some(self.runtime_error_response(id, "Stack trace failed", &e)),
         }
     }
 
     async fn handle_scopes(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::ScopesArguments = match self.parse_arguments(id, "scopes", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        let frame_id = params.get("frameId")?.as_i64()?;
-
-        match session.scopes(frame_id).await {
+        match session.scopes(args.frame_id).await {
             Ok(scopes) => {
                 let scope_objects: Vec<JsonValue> = scopes
                     .into_iter()
@@ -992,19 +2866,29 @@ impl<R: DebugRuntime> DapServer<R> {
                     "result": { "scopes": scope_objects }
                 }))
             }
-            Err(e) => Some(self.error_response(id, -1, format!("Scopes failed: {}", e))),
+            Err(e) => Some(self.runtime_error_response(id, "Scopes failed", &e)),
         }
     }
 
     async fn handle_variables(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::VariablesArguments = match self.parse_arguments(id, "variables", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        let variables_reference = params.get("variablesReference")?.as_i64()?;
+        let page = VariablesPage {
+            filter: args.filter,
+            start: args.start,
+            // A count of 0 asks for all children
+            count: Some(args.count).filter(|&n| n > 0),
+            format: args.format,
+        };
 
-        match session.variables(variables_reference).await {
+        match session.variables(args.variables_reference, page).await {
             Ok(variables) => {
                 let var_objects: Vec<JsonValue> = variables
                     .into_iter()
@@ -1023,6 +2907,9 @@ impl<R: DebugRuntime> DapServer<R> {
                         if let Some(indexed) = v.indexed_variables {
                             obj["indexedVariables"] = indexed.into();
                         }
+                        if let Some(memory_reference) = v.memory_reference {
+                            obj["memoryReference"] = memory_reference.into();
+                        }
                         obj
                     })
                     .collect();
@@ -1032,65 +2919,293 @@ impl<R: DebugRuntime> DapServer<R> {
                     "result": { "variables": var_objects }
                 }))
             }
-            Err(e) => Some(self.error_response(id, -1, format!("Variables failed: {}", e))),
+            Err(e) => Some(self.runtime_error_response(id, "Variables failed", &e)),
         }
     }
 
-    async fn handle_evaluate(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+    async fn handle_set_variable(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::SetVariableArguments = match self.parse_arguments(id, "setVariable", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        let expression = params.get("expression")?.as_str()?;
-        let frame_id = params.get("frameId").and_then(|v| v.as_i64()).unwrap_or(0);
+        match session.runtime.set_variable(args.variables_reference, &args.name, &args.value).await {
+            Ok(variable) => {
+                let mut result = json!({
+                    "value": variable.value,
+                    "type": variable.type_,
+                    "variablesReference": variable.variables_reference.unwrap_or(0)
+                });
+                if let Some(named) = variable.named_variables {
+                    result["namedVariables"] = named.into();
+                }
+                if let Some(indexed) = variable.indexed_variables {
+                    result["indexedVariables"] = indexed.into();
+                }
+                if let Some(memory_reference) = variable.memory_reference {
+                    result["memoryReference"] = memory_reference.into();
+                }
+                Some(json!({ "id": id, "result": result }))
+            }
+            Err(e) => Some(self.runtime_error_response(id, "Set variable failed", &e)),
+        }
+    }
+
+    async fn handle_evaluate(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::EvaluateArguments = match self.parse_arguments(id, "evaluate", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
 
-        match session.evaluate(frame_id, expression).await {
-            Ok(value) => {
-                let (value_str, type_str) = match value {
-                    Value::Nil => ("nil".to_string(), "nil".to_string()),
-                    Value::Boolean(b) => (b.to_string(), "boolean".to_string()),
-                    Value::Number(n) => (n.to_string(), "number".to_string()),
-                    Value::String(s) => (format!("\"{}\"", s), "string".to_string()),
-                    Value::Table { reference, length } => {
-                        (format!("table (ref={}, len={})", reference, length), "table".to_string())
+        let expression = args.expression.as_str();
+        let context = args.context.as_deref();
+        // Debug console commands start with a dot, which no Lua expression does
+        if context == Some("repl") && expression.trim_start().starts_with('.') {
+            return Some(match InventoryScope::from_command(expression) {
+                Some(scope) => json!({
+                    "id": id,
+                    "result": {
+                        "result": session.inventory(scope).render(scope),
+                        "variablesReference": 0
                     }
-                    Value::Function { reference, name } => (
-                        format!("function (ref={}, name={})", reference, name.unwrap_or_default()),
-                        "function".to_string(),
-                    ),
-                    Value::UserData => ("userdata".to_string(), "userdata".to_string()),
-                    Value::Thread => ("thread".to_string(), "thread".to_string()),
-                };
+                }),
+                None => self.error_response(
+                    id,
+                    -1,
+                    format!("Unknown command: {} (available: {})", expression.trim(), InventoryScope::COMMANDS),
+                ),
+            });
+        }
+        // Copied values are whole literals rather than previews
+        if context == Some("clipboard") {
+            return Some(match session.evaluate_literal(args.frame_id, expression).await {
+                Ok(literal) => json!({
+                    "id": id,
+                    "result": { "result": literal, "variablesReference": 0 }
+                }),
+                Err(e) => self.runtime_error_response(id, "Evaluate failed", &e),
+            });
+        }
+        // Without a frame the expression is evaluated against the globals,
+        // which also works while the program is running
+        let result = session
+            .evaluate_variable(args.frame_id, expression, EvaluationContext::from_request(context), args.format)
+            .await;
+
+        // Watches remember their value at each stop so the pane can show what changed
+        let watch = if context == Some("watch") {
+            let value = match &result {
+                Ok(variable) => variable.value.clone(),
+                Err(e) => format!("<error: {}>", e),
+            };
+            Some(session.watch_manager().record(expression, value).clone())
+        } else {
+            None
+        };
 
-                Some(json!({
+        match result {
+            Ok(variable) => {
+                let mut response = json!({
                     "id": id,
                     "result": {
-                        "result": value_str,
-                        "type": type_str
+                        "result": variable.value,
+                        "type": variable.type_,
+                        "variablesReference": variable.variables_reference.unwrap_or(0)
                     }
-                }))
+                });
+                if let Some(named) = variable.named_variables {
+                    response["result"]["namedVariables"] = named.into();
+                }
+                if let Some(indexed) = variable.indexed_variables {
+                    response["result"]["indexedVariables"] = indexed.into();
+                }
+                if let Some(memory_reference) = variable.memory_reference {
+                    response["result"]["memoryReference"] = memory_reference.into();
+                }
+                if let Some(watch) = watch {
+                    let status = watch.status();
+                    response["result"]["watchStatus"] = json!(status);
+                    if status == WatchStatus::Changed {
+                        response["result"]["previousValue"] = json!(watch.previous_value);
+                    }
+                }
+                Some(response)
             }
-            Err(e) => Some(self.error_response(id, -1, format!("Evaluate failed: {}", e))),
+            Err(e) => Some(self.runtime_error_response(id, "Evaluate failed", &e)),
         }
     }
 
-    async fn handle_source(&mut self, id: u64, _params: &JsonValue) -> Option<JsonValue> {
-        Some(json!({
+    /// Completes the identifier before the cursor in the debug console
+    ///
+    /// Candidates come from the frame's scopes, and after `a.b.` or `a.b:`
+    /// from the fields of that table. Without a frame only keywords match.
+    async fn handle_completions(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::CompletionsArguments = match self.parse_arguments(id, "completions", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        let text = args.text.as_str();
+        // Columns start at 1; the cursor defaults to the end of the text
+        let column = args.column.unwrap_or(text.chars().count() + 1).clamp(1, text.chars().count() + 1);
+        let frame_id = args.frame_id;
+        let Some(context) = completions::context_at(text, column.saturating_sub(1)) else {
+            return Some(json!({ "id": id, "result": { "targets": [] } }));
+        };
+
+        let groups = match frame_id {
+            Some(frame_id) => session.completion_candidates(frame_id, context.base.as_deref()).await,
+            // Variables can only be listed while stopped in a frame
+            None => Vec::new(),
+        };
+
+        // The completion replaces the prefix, which starts this many columns in
+        let start = column - context.prefix.chars().count();
+        let length = context.prefix.chars().count();
+        let targets: Vec<JsonValue> = completions::complete(&context, &groups)
+            .into_iter()
+            .map(|item| {
+                json!({
+                    "label": item.label,
+                    "text": item.label,
+                    "type": item.item_type,
+                    "start": start,
+                    "length": length
+                })
+            })
+            .collect();
+        Some(json!({ "id": id, "result": { "targets": targets } }))
+    }
+
+    /// Lists the live tasks and threads and the hold state of the Lua state's locks
+    fn handle_internals(&self, id: u64) -> JsonValue {
+        let locks = self.session.as_ref().map(|s| s.lock_states()).unwrap_or_default();
+        json!({
             "id": id,
             "result": {
-                "content": "-- Source code not available"
+                "tasks": internals::tasks().tasks(),
+                "locks": locks,
             }
+        })
+    }
+
+    /// Lists the watch expressions, after adding those in `add` and removing those in `remove`
+    fn handle_watches(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::WatchesArguments = match self.parse_arguments(id, "wayfinder/watches", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        for expression in &args.add {
+            session.watch_manager().add(expression);
+        }
+        for expression in &args.remove {
+            session.watch_manager().remove(expression);
+        }
+
+        let watches: Vec<JsonValue> = session
+            .watch_manager()
+            .watches()
+            .iter()
+            .map(|watch| {
+                let mut entry = serde_json::to_value(watch).unwrap_or(JsonValue::Null);
+                entry["status"] = json!(watch.status());
+                entry
+            })
+            .collect();
+        Some(json!({ "id": id, "result": { "watches": watches } }))
+    }
+
+    /// Lists what the session has registered, for clients to check what the server thinks is set
+    fn handle_breakpoint_inventory(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::BreakpointInventoryArguments = match self.parse_arguments(id, "breakpointInventory", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        let scope = args.scope;
+        let inventory = session.inventory(scope);
+        let table = inventory.render(scope);
+        let mut result = serde_json::to_value(inventory).unwrap_or(JsonValue::Null);
+        result["table"] = json!(table);
+
+        Some(json!({
+            "id": id,
+            "result": result
         }))
     }
 
+    async fn handle_source(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::SourceArguments = match self.parse_arguments(id, "source", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
+        let session = match &mut self.session {
+            Some(s) => s,
+            None => return Some(self.no_session_response(id)),
+        };
+
+        let source_reference = args
+            .source_reference
+            .or_else(|| args.source.as_ref().and_then(|source| source.source_reference))
+            .unwrap_or(0);
+        let path = args.source.as_ref().and_then(|source| source.path.as_deref());
+
+        // A reference of 0 means the client should load the source by path
+        let content = match (source_reference, path) {
+            (0, Some(path)) => session
+                .read_source_file(path)
+                .map_err(|e| (error_code::IO, format!("Failed to read {}: {}", path, e))),
+            (0, None) => Err((arguments::INVALID_ARGUMENTS, "No source reference or path given".to_string())),
+            (reference, _) => match session.runtime.source(reference).await {
+                Ok(content) => Ok(content),
+                Err(e) => return Some(self.runtime_error_response(id, "Failed to get source", &e)),
+            },
+        };
+
+        match content {
+            Ok(content) => Some(json!({
+                "id": id,
+                "result": {
+                    "content": content,
+                    "mimeType": "text/x-lua"
+                }
+            })),
+            Err((code, message)) => Some(self.error_response(id, code, message)),
+        }
+    }
+
     async fn handle_exception_info(&mut self, id: u64, params: &JsonValue) -> Option<JsonValue> {
+        let args: arguments::ExceptionInfoArguments = match self.parse_arguments(id, "exceptionInfo", params) {
+            Ok(args) => args,
+            Err(response) => return Some(response),
+        };
         let session = match &mut self.session {
             Some(s) => s,
-            None => return Some(self.error_response(id, -1, "No debug session".to_string())),
+            None => return Some(self.no_session_response(id)),
         };
 
-        let thread_id = params.get("threadId").and_then(|v| v.as_u64()).unwrap_or(0);
+        let thread_id = args.thread_id.unwrap_or(0);
 
         match session.runtime.get_exception_info(thread_id).await {
             Ok(exception_info) => {
@@ -1114,12 +3229,8 @@ impl<R: DebugRuntime> DapServer<R> {
                                 "line": frame.line,
                                 "column": frame.column,
                             });
-                            if let Some(source) = frame.source {
-                                frame_obj["source"] = json!({
-                                    "name": source.name,
-                                    "path": source.path,
-                                    "sourceReference": source.source_reference.unwrap_or(0)
-                                });
+                            if let Some(source) = &frame.source {
+                                frame_obj["source"] = source_json(source);
                             }
                             frame_obj
                         })
@@ -1135,30 +3246,619 @@ impl<R: DebugRuntime> DapServer<R> {
 
                 Some(result)
             }
-            Err(e) => Some(self.error_response(id, -1, format!("Exception info failed: {}", e))),
+            Err(e) => Some(self.runtime_error_response(id, "Exception info failed", &e)),
+        }
+    }
+
+    /// Handles a request, answering with an error if its handler panics
+    ///
+    /// The panic and its backtrace are also shown in the debug console, and
+    /// the session carries on.
+    async fn handle_request_catching(&mut self, method: &str, params: &JsonValue, id: u64) -> Option<JsonValue> {
+        match panics::catch_unwind(self.handle_request(method, params, id)).await {
+            Ok(response) => response,
+            Err(report) => {
+                tracing::error!("{} request panicked: {}", method, report.message);
+                self.queue_event(Event::output("stderr", &format!("{}\n", report)));
+                Some(json!({
+                    "id": id,
+                    "error": {
+                        "code": panics::INTERNAL_ERROR,
+                        "message": format!("Internal error handling {}: {}", method, report.message),
+                        "showUser": true
+                    }
+                }))
+            }
         }
     }
 
-    pub async fn run_event_loop(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        // This would typically be implemented with a transport layer
-        // For now, we'll just indicate that the event loop is ready
-        println!("DAP server event loop started");
+    /// Runs the DAP message loop over the given transport
+    ///
+    /// Requests are dispatched through `handle_request`, whose `{"id", "result"}`
+    /// or `{"id", "error"}` answers go out as DAP responses, and any events
+    /// queued while handling a request are written after its response. The
+    /// loop ends when the client disconnects or closes the stream.
+    pub async fn run_event_loop<Rd, Wr>(
+        &mut self,
+        transport: &mut DapTransport<Rd, Wr>,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        Rd: AsyncBufRead + Unpin,
+        Wr: AsyncWrite + Unpin,
+    {
+        let mut breakpoint_file_poll = tokio::time::interval(breakpoint_file::POLL_INTERVAL);
+        let mut hot_reload_poll = tokio::time::interval(hot_reload_watcher::POLL_INTERVAL);
+        let settings_reload = self.settings_reload.clone();
+        let shutdown = self.shutdown.clone();
+        let task = internals::tasks().register(TaskRole::Transport, TaskKind::Task, "idle");
+        panics::install_hook();
+        loop {
+            let message = tokio::select! {
+                // Reading is cancel-safe, so an event arriving mid-message loses nothing
+                result = transport.read_message() => match result {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    // The body was framed, so the next message can still be read
+                    Err(e) if dap_transport::is_invalid_json(&e) => {
+                        tracing::warn!("ignoring a DAP message that is not JSON: {}", e);
+                        let answer = json!({
+                            "id": 0,
+                            "error": { "code": dap_transport::INVALID_JSON, "message": format!("Invalid JSON: {}", e) }
+                        });
+                        self.send_response(transport, "", &answer).await?;
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::error!("error reading DAP message: {}", e);
+                        break;
+                    }
+                },
+                Some(event) = self.event_rx.recv() => {
+                    if !self.breakpoint_stop_wanted(&event).await {
+                        continue;
+                    }
+                    if let Some(trace) = self.exception_trace(&event).await {
+                        self.observe_event(&trace);
+                        self.send_event(transport, trace).await?;
+                    }
+                    self.observe_event(&event);
+                    self.send_event(transport, event).await?;
+                    self.refresh_watches().await;
+                    continue;
+                }
+                _ = breakpoint_file_poll.tick(), if self.breakpoint_file.is_some() => {
+                    self.sync_breakpoint_file().await;
+                    for event in self.take_pending_events() {
+                        self.observe_event(&event);
+                        self.send_event(transport, event).await?;
+                    }
+                    continue;
+                }
+                _ = hot_reload_poll.tick(), if self.hot_reload_watcher.is_some() => {
+                    self.sync_hot_reload().await;
+                    for event in self.take_pending_events() {
+                        self.observe_event(&event);
+                        self.send_event(transport, event).await?;
+                    }
+                    continue;
+                }
+                _ = shutdown.notified() => {
+                    self.shut_down(transport).await?;
+                    break;
+                }
+                _ = settings_reload.notified() => {
+                    self.reload_settings();
+                    for event in self.take_pending_events() {
+                        self.observe_event(&event);
+                        self.send_event(transport, event).await?;
+                    }
+                    continue;
+                }
+                Some(event) = output_capture::next_output(&mut self.output) => {
+                    self.observe_event(&event);
+                    self.send_event(transport, event).await?;
+                    continue;
+                }
+                status = wait_for_exit(&mut self.process_handle) => {
+                    self.process_handle = None;
+                    self.is_running = false;
+                    // Everything the program wrote comes before the news that it ended
+                    if let Some(mut output) = self.output.take() {
+                        for event in output.drain(output_capture::DRAIN_TIMEOUT).await {
+                            self.observe_event(&event);
+                            self.send_event(transport, event).await?;
+                        }
+                    }
+                    let exit_code = status.ok().and_then(|s| s.code()).unwrap_or(-1);
+                    for event in [Event::exited(exit_code), Event::terminated()] {
+                        self.trace_event(&event);
+                        transport.write_event(&event).await?;
+                    }
+                    continue;
+                }
+            };
+
+            self.trace.record(Direction::Received, &message);
+
+            if message.get("type").and_then(|t| t.as_str()) == Some("response") {
+                if let Some(response) = self.handle_client_response(&message).await {
+                    // Only a launch waits on the client
+                    self.send_response(transport, "launch", &response).await?;
+                }
+                for event in self.take_pending_events() {
+                    self.observe_event(&event);
+                    self.send_event(transport, event).await?;
+                }
+                continue;
+            }
+
+            // Accept both the DAP field names and the JSON-RPC style ones
+            let method = message
+                .get("command")
+                .or_else(|| message.get("method"))
+                .and_then(|m| m.as_str())
+                .unwrap_or("")
+                .to_string();
+            let mut params = message
+                .get("arguments")
+                .or_else(|| message.get("params"))
+                .cloned()
+                .unwrap_or(JsonValue::Null);
+            let id = message
+                .get("seq")
+                .or_else(|| message.get("id"))
+                .and_then(|i| i.as_u64())
+                .unwrap_or(0);
+
+            task.set_activity(&format!("handling {}", method));
+            self.client.translate_request(&mut params);
+            self.rewrite_rules.translate_request(&method, &mut params);
+            let mut typed_expression = None;
+            if let Some(mapping) = &mut self.source_mapping {
+                mapping.translate_request(&method, id, &mut params);
+                typed_expression = mapping.take_typed_expression(id);
+            }
+            let span = tracing::debug_span!("request", command = %method, seq = id);
+            let mut response = self.handle_request_catching(&method, &params, id).instrument(span.clone()).await;
+            // A console expression rewritten from TypeScript that fails is evaluated as typed
+            if let Some(expression) = typed_expression {
+                if response.as_ref().is_some_and(|response| response.get("error").is_some()) {
+                    params["expression"] = json!(expression);
+                    response = self.handle_request_catching(&method, &params, id).instrument(span.clone()).await;
+                }
+            }
+            if let Some(error) = response.as_ref().and_then(|response| response.get("error")) {
+                tracing::debug!(parent: &span, error = %error["message"], "request failed");
+            }
+            if let Some(mut response) = response {
+                if let Some(mapping) = &mut self.source_mapping {
+                    mapping.translate_response(&method, id, &mut response);
+                }
+                self.rewrite_rules.translate_response(&method, &mut response);
+                self.client.translate_response(&method, &mut response);
+                self.send_response(transport, &method, &response).await?;
+            }
+            task.set_activity("idle");
+
+            for (command, arguments) in self.take_client_requests() {
+                transport.write_request(&command, &arguments).await?;
+            }
+            for event in self.take_pending_events() {
+                self.observe_event(&event);
+                self.send_event(transport, event).await?;
+            }
+            while let Ok(event) = self.event_rx.try_recv() {
+                if !self.breakpoint_stop_wanted(&event).await {
+                    continue;
+                }
+                if let Some(trace) = self.exception_trace(&event).await {
+                    self.observe_event(&trace);
+                    self.send_event(transport, trace).await?;
+                }
+                self.observe_event(&event);
+                self.send_event(transport, event).await?;
+            }
+            self.refresh_watches().await;
+
+            if method == "disconnect" || method == "terminate" {
+                break;
+            }
+        }
+
         Ok(())
     }
 
+    /// Ends the session for a shutdown of the adapter
+    ///
+    /// Unlike a `disconnect`, the client did not ask for it, so it is told with
+    /// a `terminated` event.
+    async fn shut_down<Rd, Wr>(&mut self, transport: &mut DapTransport<Rd, Wr>) -> std::io::Result<()>
+    where
+        Rd: AsyncBufRead + Unpin,
+        Wr: AsyncWrite + Unpin,
+    {
+        tracing::info!("Shutting down the debug session");
+        let mut events = self.take_pending_events();
+        while let Ok(event) = self.event_rx.try_recv() {
+            events.push(event);
+        }
+        for event in events {
+            self.observe_event(&event);
+            self.send_event(transport, event).await?;
+        }
+
+        match self.on_shutdown {
+            OnShutdown::Terminate => {
+                if let Err(e) = self.terminate_process().await {
+                    tracing::error!("error terminating process: {}", e);
+                }
+                // What it wrote before it was killed still reaches the client
+                if let Some(mut output) = self.output.take() {
+                    for event in output.drain(output_capture::DRAIN_TIMEOUT).await {
+                        self.observe_event(&event);
+                        self.send_event(transport, event).await?;
+                    }
+                }
+            }
+            // Dropping the handle neither kills nor waits for the process
+            OnShutdown::Detach => {
+                self.process_handle = None;
+                self.is_running = false;
+            }
+        }
+        if let Some(session) = &mut self.session {
+            if let Err(e) = session.runtime.detach().await {
+                tracing::error!("error detaching from the program: {}", e);
+            }
+        }
+        self.session = None;
+
+        let event = Event::terminated();
+        self.trace_event(&event);
+        transport.write_event(&event).await
+    }
+
+    /// Sends a handler's answer to a `command` request as a DAP response
+    async fn send_response<Rd, Wr>(
+        &mut self,
+        transport: &mut DapTransport<Rd, Wr>,
+        command: &str,
+        answer: &JsonValue,
+    ) -> std::io::Result<()>
+    where
+        Rd: AsyncBufRead + Unpin,
+        Wr: AsyncWrite + Unpin,
+    {
+        let response = Response::from_handler(command, answer);
+        let mut message = response.to_json(0);
+        if let Some(message) = message.as_object_mut() {
+            message.remove("seq");
+        }
+        self.trace.record(Direction::Sent, &message);
+        transport.write_response(&response).await
+    }
+
+    /// Sends an event to the client, with locations in mapped Lua files translated to their sources
+    async fn send_event<Rd, Wr>(&mut self, transport: &mut DapTransport<Rd, Wr>, mut event: Event) -> std::io::Result<()>
+    where
+        Rd: AsyncBufRead + Unpin,
+        Wr: AsyncWrite + Unpin,
+    {
+        if let Some(mapping) = &mut self.source_mapping {
+            mapping.translate_event(&mut event);
+        }
+        self.rewrite_rules.translate_event(&mut event);
+        self.client.translate_event(&mut event);
+        transport.write_event(&event).await
+    }
+
+    /// Applies the condition, hit condition and log message of the breakpoint a stop is at
+    ///
+    /// Runtimes stop at every breakpoint line, data breakpoint change and
+    /// exception; when the breakpoint says not to stop there, the program is
+    /// resumed and the stop never reaches the client.
+    async fn breakpoint_stop_wanted(&mut self, event: &Event) -> bool {
+        let Some(body) = event.body.as_ref().filter(|_| event.event == "stopped") else { return true };
+        let Some(session) = &mut self.session else { return true };
+        let wanted = match body["reason"].as_str() {
+            Some("breakpoint") => Self::line_breakpoint_stop_wanted(session, body).await,
+            Some("data breakpoint") => match body["hitBreakpointIds"][0].as_i64() {
+                Some(id) => session.should_stop_at_data_breakpoint(id),
+                None => true,
+            },
+            Some("exception") => Self::exception_stop_wanted(session, body).await,
+            _ => true,
+        };
+        if wanted {
+            return true;
+        }
+
+        if let Err(e) = session.run().await {
+            session.emit(Event::output("console", &format!("Warning: failed to resume past breakpoint: {}\n", e)));
+            return true;
+        }
+        false
+    }
+
+    /// Console output listing the lines run before a stop at an exception
+    ///
+    /// The stack trace only shows the calls still active; the execution trace
+    /// also shows the ones that returned on the way to the error.
+    async fn exception_trace(&mut self, event: &Event) -> Option<Event> {
+        let body = event.body.as_ref().filter(|_| event.event == "stopped")?;
+        if body["reason"].as_str() != Some("exception") {
+            return None;
+        }
+        let entries = self.session.as_mut()?.runtime.execution_trace().await.ok()?;
+        let text = crate::debug::execution_trace::describe(&entries)?;
+        Some(Event::output("console", &text))
+    }
+
+    /// Whether the exception filters a stop at an exception matched want to stop
+    ///
+    /// The error message is the stop's `text`, or its description.
+    async fn exception_stop_wanted(session: &mut DebugSession<R>, body: &JsonValue) -> bool {
+        let message = body["text"].as_str().or_else(|| body["description"].as_str()).unwrap_or_default().to_string();
+        let frame_id = match session.stack_trace(body["threadId"].as_u64()).await {
+            Ok(frames) => frames.first().map(|frame| frame.id),
+            Err(_) => None,
+        };
+        session.should_stop_at_exception(body["exceptionFilter"].as_str(), &message, frame_id).await
+    }
+
+    /// Whether the line breakpoint at the top frame of a stop wants to stop
+    async fn line_breakpoint_stop_wanted(session: &mut DebugSession<R>, body: &JsonValue) -> bool {
+        let frame = match session.stack_trace(body["threadId"].as_u64()).await {
+            Ok(frames) => frames.into_iter().next(),
+            Err(_) => None,
+        };
+        let Some((path, line, frame_id)) = frame.and_then(|frame| {
+            let path = frame.source?.path;
+            (!path.is_empty()).then_some((path, frame.line, frame.id))
+        }) else {
+            return true;
+        };
+
+        !matches!(session.should_stop_at_line_breakpoint(&path, line, frame_id).await, Ok(false))
+    }
+
+    /// Runs the embedder's callbacks and keeps the session's view of its
+    /// breakpoints in step with events on their way to the client
+    fn observe_event(&mut self, event: &Event) {
+        self.trace_event(event);
+        self.hooks.dispatch(event);
+        if event.event == "stopped" {
+            if let Some(session) = &mut self.session {
+                let thread_id = event.body.as_ref().and_then(|body| body["threadId"].as_u64());
+                session.watch_manager().on_stop(thread_id);
+            }
+        }
+        if event.event != "breakpoint" {
+            return;
+        }
+        let (Some(session), Some(breakpoint)) = (
+            &mut self.session,
+            event.body.as_ref().and_then(|body| body.get("breakpoint")),
+        ) else {
+            return;
+        };
+        if let Some(bp_id) = breakpoint.get("id").and_then(|v| v.as_i64()) {
+            let verified = breakpoint.get("verified").and_then(|v| v.as_bool()).unwrap_or(false);
+            let message = breakpoint.get("message").and_then(|v| v.as_str()).map(str::to_string);
+            session.breakpoint_manager().set_verified(bp_id, verified, message);
+        }
+    }
+
+    /// Evaluates the watches at a new stop, so their values are kept even for stops the client skips past
+    async fn refresh_watches(&mut self) {
+        if let Some(session) = &mut self.session {
+            session.refresh_watches().await;
+        }
+    }
+
+    /// Records an event in the protocol trace as the transport writes it, less the sequence number
+    fn trace_event(&mut self, event: &Event) {
+        let mut message = event.to_json(0);
+        if let Some(message) = message.as_object_mut() {
+            message.remove("seq");
+        }
+        self.trace.record(Direction::Sent, &message);
+    }
+
+    /// Deserializes a request's arguments, or returns the error response to send instead
+    fn parse_arguments<T: serde::de::DeserializeOwned>(&self, id: u64, command: &str, params: &JsonValue) -> Result<T, JsonValue> {
+        arguments::parse(command, params).map_err(|message| self.error_response(id, arguments::INVALID_ARGUMENTS, message))
+    }
+
+    fn no_session_response(&self, id: u64) -> JsonValue {
+        self.error_response(id, error_code::NO_SESSION, "No debug session".to_string())
+    }
+
     fn error_response(&self, id: u64, code: i32, message: String) -> JsonValue {
         json!({
             "id": id,
             "error": {
                 "code": code,
-                "message": message
+                "message": message,
+                "showUser": error_code::shows_user(code)
+            }
+        })
+    }
+
+    /// Error response for a failed runtime call, with the error's stable code
+    ///
+    /// `showUser` tells the client whether the message is worth showing, as
+    /// opposed to a stale id it can ignore or an evaluation error it shows in place.
+    fn runtime_error_response(&self, id: u64, context: &str, error: &RuntimeError) -> JsonValue {
+        json!({
+            "id": id,
+            "error": {
+                "code": error.code(),
+                "message": format!("{}: {}", context, error),
+                "showUser": error.show_user()
             }
         })
     }
 }
 
+/// DAP `Source` object for a frame's source
+///
+/// Chunks without a file have no path; the client fetches their code with a
+/// `source` request for the reference instead.
+fn source_json(source: &super::runtime::Source) -> JsonValue {
+    let mut obj = json!({
+        "name": source.name,
+        "sourceReference": source.source_reference.unwrap_or(0)
+    });
+    if !source.path.is_empty() {
+        obj["path"] = json!(source.path);
+    }
+    obj
+}
+
+/// Converts DAP `SourceBreakpoint` objects for a source
+fn line_breakpoints_from(source: &str, breakpoints: &[SourceBreakpoint]) -> Vec<crate::debug::breakpoints::LineBreakpoint> {
+    breakpoints
+        .iter()
+        .map(|bp| crate::debug::breakpoints::LineBreakpoint {
+            id: 0, // Will be assigned by BreakpointManager
+            source: source.to_string(),
+            line: bp.line,
+            column: bp.column,
+            condition: bp.condition.clone(),
+            log_message: bp.log_message.clone(),
+            hit_condition: bp.hit_condition.clone(),
+            verified: false, // Will be set by runtime
+            message: None,
+            hit_count: 0,
+        })
+        .collect()
+}
+
+/// Result of the memory requests
+fn memory_statistics_json(stats: &crate::memory::MemoryStatistics) -> JsonValue {
+    json!({
+        "totalKB": stats.total_kb,
+        "totalBytes": stats.total_bytes,
+        "gcPause": stats.gc_pause,
+        "gcStepMul": stats.gc_step_mul,
+        "gcRunning": stats.gc_running,
+    })
+}
+
+/// Reads the `serializer` argument of an export request
+///
+/// Defaults to the one the file extension implies, or JSON. Only JSON can be
+/// returned in the response itself, so other serializers need a `path`.
+fn serializer_param(name: Option<&str>, path: Option<&str>) -> Result<crate::serializer::Serializer, String> {
+    use crate::serializer::Serializer;
+
+    let serializer = match name {
+        Some(name) => name.parse::<Serializer>()?,
+        None => path.and_then(|p| Serializer::for_path(std::path::Path::new(p))).unwrap_or_default(),
+    };
+    if path.is_none() && !serializer.is_text() {
+        return Err(format!("The {} serializer needs a path to write to", serializer));
+    }
+    Ok(serializer)
+}
+
+/// Result of the profiling requests, with functions hottest first
+fn profile_json(data: &crate::profiling::ProfileData) -> JsonValue {
+    use crate::profiling::ProfilingMode;
+
+    let mode = match data.mode {
+        ProfilingMode::Sampling { .. } => "sampling",
+        ProfilingMode::WallClock { .. } => "wallClock",
+        ProfilingMode::CallTrace => "callTrace",
+        ProfilingMode::LineLevel => "lineLevel",
+        ProfilingMode::Disabled => "disabled",
+    };
+    let mut functions: Vec<_> = data.functions.values().collect();
+    functions.sort_by(|a, b| b.total_time_ms.total_cmp(&a.total_time_ms).then_with(|| a.name.cmp(&b.name)));
+
+    json!({
+        "mode": mode,
+        "durationMs": data.duration_ms,
+        "totalSamples": data.total_samples,
+        "functions": functions.iter().map(|profile| {
+            json!({
+                "name": profile.name,
+                "source": profile.source,
+                "line": profile.line_defined,
+                "callCount": profile.call_count,
+                "totalTimeMs": profile.total_time_ms,
+                "selfTimeMs": profile.self_time_ms,
+            })
+        }).collect::<Vec<_>>(),
+        "stacks": data.stacks,
+    })
+}
+
 impl<R: DebugRuntime> Default for DapServer<R> {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+/// Waits for the debuggee to exit, or forever if there is none
+async fn wait_for_exit(
+    process: &mut Option<tokio::process::Child>,
+) -> std::io::Result<std::process::ExitStatus> {
+    match process {
+        Some(child) => child.wait().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Console lines summarizing a reload of module `name` from `path`
+fn hot_reload_summary(path: &std::path::Path, name: &str, result: &HotReloadResult) -> Vec<String> {
+    let message = result.message.clone().unwrap_or_else(|| format!("Module '{}' reloaded", name));
+    let mut lines = vec![format!("Hot reload: {} ({})\n", message, path.display())];
+    for warning in &result.warnings {
+        lines.push(format!("[{}] Hot reload: {}\n", severity_name(&warning.severity), warning.message));
+    }
+    lines
+}
+
+fn severity_name(severity: &WarningSeverity) -> &'static str {
+    match severity {
+        WarningSeverity::Info => "info",
+        WarningSeverity::Warning => "warning",
+        WarningSeverity::Error => "error",
+    }
+}
+
+/// What became of a field of a reloaded module, for the `wayfinder/hotReload` response
+fn field_json(field: &FieldPreservation) -> JsonValue {
+    let (outcome, detail) = match &field.outcome {
+        FieldOutcome::Preserved => ("preserved", None),
+        FieldOutcome::Replaced => ("replaced", None),
+        FieldOutcome::Added => ("added", None),
+        FieldOutcome::Removed => ("removed", None),
+        FieldOutcome::Lost(reason) => ("lost", Some(reason)),
+    };
+    json!({ "field": field.field, "outcome": outcome, "detail": detail })
+}
+
+/// Runs a hot reload build command in the shell, in `dir`
+///
+/// Fails with the end of what the command wrote if it exits unsuccessfully.
+async fn run_build_command(command: &str, dir: &std::path::Path) -> Result<(), String> {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let output = tokio::process::Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .current_dir(dir)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let mut written = String::from_utf8_lossy(&output.stderr).into_owned();
+    written.push_str(&String::from_utf8_lossy(&output.stdout));
+    let lines: Vec<&str> = written.trim().lines().collect();
+    let tail = lines[lines.len().saturating_sub(5)..].join("\n");
+    Err(format!("{}\n{}", output.status, tail).trim_end().to_string())
+}
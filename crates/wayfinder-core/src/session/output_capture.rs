@@ -0,0 +1,233 @@
+//! Output of a debuggee running as a child process
+//!
+//! The child's stdout and stderr are read on tasks of their own and turned
+//! into `output` events. Text is sent a line or more at a time; a partial
+//! line is held back until the rest arrives, or sent on its own after a short
+//! pause so prompts show up. The tasks hand events over through a bounded
+//! channel, so a burst the client cannot keep up with stops the reading, and
+//! the child blocks on its full pipe instead of the adapter buffering it all.
+
+use crate::dap::Event;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Child;
+use tokio::sync::mpsc;
+
+/// Most bytes read, or held back as a partial line, at a time
+pub const CHUNK_SIZE: usize = 8192;
+
+/// Output events waiting for the event loop before the readers stop reading
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Time a partial line waits for the rest before it is sent as it is
+const PARTIAL_LINE_DELAY: Duration = Duration::from_millis(50);
+
+/// Time given to the output still buffered once the child has exited
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Reads a child's stdout and stderr into output events
+pub struct OutputCapture {
+    events: mpsc::Receiver<Event>,
+}
+
+impl OutputCapture {
+    /// Starts reading the streams the child was spawned with piped; None if neither was
+    pub fn start(child: &mut Child) -> Option<Self> {
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        if stdout.is_none() && stderr.is_none() {
+            return None;
+        }
+        let (sender, events) = mpsc::channel(CHANNEL_CAPACITY);
+        if let Some(stdout) = stdout {
+            tokio::spawn(forward(stdout, "stdout", sender.clone()));
+        }
+        if let Some(stderr) = stderr {
+            tokio::spawn(forward(stderr, "stderr", sender));
+        }
+        Some(Self { events })
+    }
+
+    /// The next output event; None once both streams are closed
+    pub async fn next(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+
+    /// The output still to come, until both streams close or `timeout` passes
+    ///
+    /// A child's own children can keep its streams open after it exits, so
+    /// this does not wait for them past the timeout.
+    pub async fn drain(&mut self, timeout: Duration) -> Vec<Event> {
+        let mut events = Vec::new();
+        let _ = tokio::time::timeout(timeout, async {
+            while let Some(event) = self.events.recv().await {
+                events.push(event);
+            }
+        })
+        .await;
+        events
+    }
+}
+
+/// The next output of `capture`, or never if there is none
+pub async fn next_output(capture: &mut Option<OutputCapture>) -> Option<Event> {
+    match capture {
+        Some(capture) => capture.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sends what `reader` yields as output events of `category` until it closes or nobody listens
+async fn forward<Rd: AsyncRead + Unpin>(mut reader: Rd, category: &'static str, events: mpsc::Sender<Event>) {
+    let mut chunker = LineChunker::default();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = if chunker.has_partial_line() {
+            // Reading is cancel-safe, so giving up on it loses nothing
+            match tokio::time::timeout(PARTIAL_LINE_DELAY, reader.read(&mut buffer)).await {
+                Ok(read) => read,
+                Err(_) => {
+                    if let Some(text) = chunker.flush() {
+                        if events.send(Event::output(category, &text)).await.is_err() {
+                            return;
+                        }
+                    }
+                    continue;
+                }
+            }
+        } else {
+            reader.read(&mut buffer).await
+        };
+        let count = match read {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(e) => {
+                tracing::debug!("error reading the debuggee's {}: {}", category, e);
+                break;
+            }
+        };
+        for text in chunker.push(&buffer[..count]) {
+            if events.send(Event::output(category, &text)).await.is_err() {
+                return;
+            }
+        }
+    }
+    if let Some(text) = chunker.flush() {
+        let _ = events.send(Event::output(category, &text)).await;
+    }
+}
+
+/// Splits a stream of bytes into text ending at line breaks
+#[derive(Debug, Default)]
+struct LineChunker {
+    /// Bytes after the last line break
+    partial: Vec<u8>,
+}
+
+impl LineChunker {
+    fn has_partial_line(&self) -> bool {
+        !self.partial.is_empty()
+    }
+
+    /// Adds bytes read, returning the text complete so far
+    ///
+    /// A line longer than `CHUNK_SIZE` is sent in pieces, cut between characters.
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.partial.extend_from_slice(bytes);
+        let mut texts = Vec::new();
+        if let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') {
+            let rest = self.partial.split_off(end + 1);
+            texts.push(String::from_utf8_lossy(&self.partial).into_owned());
+            self.partial = rest;
+        }
+        while self.partial.len() >= CHUNK_SIZE {
+            let end = character_boundary(&self.partial[..CHUNK_SIZE]);
+            let rest = self.partial.split_off(end);
+            texts.push(String::from_utf8_lossy(&self.partial).into_owned());
+            self.partial = rest;
+        }
+        texts
+    }
+
+    /// The partial line, if any
+    fn flush(&mut self) -> Option<String> {
+        if self.partial.is_empty() {
+            return None;
+        }
+        let text = String::from_utf8_lossy(&self.partial).into_owned();
+        self.partial.clear();
+        Some(text)
+    }
+}
+
+/// Where to cut `bytes` so a UTF-8 character split by the end stays whole
+fn character_boundary(bytes: &[u8]) -> usize {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => bytes.len(),
+        // An incomplete character at the end, rather than invalid bytes
+        Err(e) if e.error_len().is_none() && e.valid_up_to() > 0 => e.valid_up_to(),
+        Err(_) => bytes.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn texts(events: &[Event]) -> Vec<(String, String)> {
+        events
+            .iter()
+            .map(|event| {
+                let body = event.body.as_ref().unwrap();
+                (body["category"].as_str().unwrap().to_string(), body["output"].as_str().unwrap().to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_partial_lines_wait_for_the_rest() {
+        let mut chunker = LineChunker::default();
+        assert!(chunker.push(b"loading").is_empty());
+        assert_eq!(chunker.push(b" level 1\nscore: "), ["loading level 1\n"]);
+        assert_eq!(chunker.flush().as_deref(), Some("score: "));
+        assert_eq!(chunker.flush(), None);
+    }
+
+    #[test]
+    fn test_long_lines_are_cut_between_characters() {
+        let mut chunker = LineChunker::default();
+        let mut line = vec![b'a'; CHUNK_SIZE - 1];
+        line.extend_from_slice("é".as_bytes());
+        let texts = chunker.push(&line);
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].len(), CHUNK_SIZE - 1);
+        assert_eq!(chunker.flush().as_deref(), Some("é"));
+    }
+
+    #[tokio::test]
+    async fn test_forward_sends_everything_before_closing() {
+        let (mut writer, reader) = tokio::io::duplex(64);
+        let (sender, receiver) = mpsc::channel(1);
+        let mut capture = OutputCapture { events: receiver };
+        let task = tokio::spawn(forward(reader, "stderr", sender));
+
+        // Far more than the pipe and channel hold, read as the events are taken
+        let burst = "x".repeat(100) + "\n";
+        let writing = tokio::spawn(async move {
+            for _ in 0..200 {
+                writer.write_all(burst.as_bytes()).await.unwrap();
+            }
+            writer.write_all(b"bye").await.unwrap();
+        });
+        let events = capture.drain(Duration::from_secs(5)).await;
+        writing.await.unwrap();
+        task.await.unwrap();
+
+        let texts = texts(&events);
+        assert!(texts.iter().all(|(category, _)| category == "stderr"));
+        let output: String = texts.into_iter().map(|(_, text)| text).collect();
+        assert_eq!(output.len(), 200 * 101 + 3);
+        assert!(output.ends_with("x\nbye"));
+    }
+}
@@ -0,0 +1,135 @@
+//! Panics in request handlers, caught so that one bad request does not end the session
+//!
+//! A handler that panics is answered with an error response and the panic,
+//! with its backtrace, is shown in the client's debug console. The adapter
+//! keeps serving requests afterwards; what the handler was changing when it
+//! panicked may be left half done, so later requests can fail too, but the
+//! client can still read the error and disconnect cleanly instead of seeing
+//! the connection drop. `--abort-on-panic` turns this off for development.
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::task::{Context, Poll};
+
+/// Error code of a response to a request whose handler panicked
+pub const INTERNAL_ERROR: i32 = -32603;
+
+static HOOK: Once = Once::new();
+static ABORT_ON_PANIC: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The last panic on this thread, recorded by the hook while the stack is still there
+    static LAST_PANIC: RefCell<Option<PanicReport>> = const { RefCell::new(None) };
+}
+
+/// What a caught panic said, and where
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicReport {
+    pub message: String,
+    /// Where it panicked, as `file:line:column`
+    pub location: Option<String>,
+    pub backtrace: String,
+}
+
+impl fmt::Display for PanicReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => writeln!(f, "Wayfinder panicked at {}: {}", location, self.message)?,
+            None => writeln!(f, "Wayfinder panicked: {}", self.message)?,
+        }
+        write!(f, "{}", self.backtrace)
+    }
+}
+
+/// Makes panics abort the process where they happen instead of being caught
+///
+/// For development: a debugger or core dump then sees the panicking frame.
+pub fn set_abort_on_panic(abort: bool) {
+    ABORT_ON_PANIC.store(abort, Ordering::Relaxed);
+}
+
+/// Installs the panic hook that records backtraces for [`catch_unwind`], once per process
+///
+/// The hook runs the one it replaces too, so panics are still printed to stderr.
+pub fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let report = PanicReport {
+                message: payload_message(info.payload()),
+                location: info.location().map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column())),
+                backtrace: Backtrace::force_capture().to_string(),
+            };
+            LAST_PANIC.with(|last| *last.borrow_mut() = Some(report));
+            previous(info);
+            if ABORT_ON_PANIC.load(Ordering::Relaxed) {
+                std::process::abort();
+            }
+        }));
+    });
+}
+
+/// Runs `future`, turning a panic while it is polled into an error
+pub fn catch_unwind<F: Future>(future: F) -> CatchUnwind<F> {
+    CatchUnwind { future: Box::pin(future) }
+}
+
+/// Future of [`catch_unwind`]
+pub struct CatchUnwind<F> {
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, PanicReport>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.future.as_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => {
+                // Without the hook there is no backtrace, but the message is still in the payload
+                let report = LAST_PANIC.with(|last| last.borrow_mut().take()).unwrap_or_else(|| PanicReport {
+                    message: payload_message(payload.as_ref()),
+                    location: None,
+                    backtrace: String::new(),
+                });
+                Poll::Ready(Err(report))
+            }
+        }
+    }
+}
+
+/// The message a panic was raised with, when it was a string
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panics_become_reports() {
+        install_hook();
+        assert_eq!(catch_unwind(async { 7 }).await, Ok(7));
+
+        let report = catch_unwind(async { panic!("bad frame {}", 3) }).await.unwrap_err();
+        assert_eq!(report.message, "bad frame 3");
+        assert!(report.location.as_deref().unwrap().contains("panics.rs"));
+        assert!(report.to_string().starts_with("Wayfinder panicked at "));
+    }
+}
@@ -0,0 +1,65 @@
+//! Stopping a debuggee together with the processes it started
+//!
+//! Killing only the debuggee leaves what it spawned running, still holding
+//! its stdout and stderr, so output capture never sees them close. Debuggees
+//! are started in a process group of their own instead, and stopping one
+//! ends the whole group: on Unix with SIGKILL sent to the group, on Windows,
+//! where children do not die with their parent, with `taskkill /T` over the
+//! process tree. The group also keeps Ctrl+C in Wayfinder's console from
+//! reaching the debuggee, which is stopped as `onShutdown` says instead.
+
+use tokio::process::{Child, Command};
+
+/// `CREATE_NEW_PROCESS_GROUP`, from `winbase.h`
+#[cfg(windows)]
+const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
+/// Makes the process `command` starts lead a process group of its own
+pub fn spawn_as_group(command: &mut Command) -> &mut Command {
+    #[cfg(unix)]
+    command.process_group(0);
+    #[cfg(windows)]
+    command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    command
+}
+
+/// Kills `child` and every process it started, then waits for it
+///
+/// A child that was not started with [`spawn_as_group`] leads no group, so
+/// on Unix only it is killed.
+pub async fn kill_tree(child: &mut Child) -> std::io::Result<()> {
+    if let Some(pid) = child.id() {
+        if kill_group(pid).await {
+            let _ = child.wait().await;
+            return Ok(());
+        }
+    }
+    child.kill().await
+}
+
+/// Kills the group `pid` leads, returning whether there was one
+#[cfg(unix)]
+async fn kill_group(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: kill only sends a signal; a negative pid names the process group
+    unsafe { libc::getpgid(pid) == pid && libc::kill(-pid, libc::SIGKILL) == 0 }
+}
+
+/// Kills the tree under `pid` with `taskkill`, returning whether it succeeded
+#[cfg(windows)]
+async fn kill_group(pid: u32) -> bool {
+    Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .await
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn kill_group(_pid: u32) -> bool {
+    false
+}
@@ -0,0 +1,225 @@
+//! Replaying recorded DAP sessions
+//!
+//! A recording made with a [`TraceRecorder`](super::trace::TraceRecorder)
+//! holds the requests a client sent and everything the adapter answered.
+//! Replaying sends the same requests, in the same order, to a fresh server
+//! and compares the answers, so a problem seen from an editor can be
+//! reproduced without it.
+//!
+//! The recorded timing is not followed. A request is sent once the one
+//! before it has been answered and the server has sent as many of the
+//! events that change the program's state (`stopped`, `terminated`, ...) as
+//! the client had seen when it sent the request, which is what a client
+//! waits on. Results hold references and paths that differ from run to run,
+//! so they are not compared field by field: a request diverges when it
+//! failed in one run and succeeded in the other, or failed differently.
+
+use super::trace::{Direction, TraceEntry};
+use super::DapServer;
+use crate::dap::transport::DapTransport;
+use crate::dap::{Message, Response};
+use crate::runtime::DebugRuntime;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncWrite, BufReader};
+
+/// Events a client waits for before its next request
+const STATE_EVENTS: &[&str] = &["initialized", "stopped", "continued", "exited", "terminated"];
+
+/// How long to wait for a response or event before giving up on it
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request answered differently than in the recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub seq: u64,
+    pub command: String,
+    /// What the recorded client got: `success`, `error: <message>` or `no response`
+    pub recorded: String,
+    pub replayed: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// Number of requests sent
+    pub requests: usize,
+    pub divergences: Vec<Divergence>,
+    /// State events the server never sent, with how many were recorded and how many came
+    pub missing_events: Vec<(String, usize, usize)>,
+    /// The replayed session, in the format of a recording
+    pub transcript: Vec<TraceEntry>,
+}
+
+impl ReplayReport {
+    /// Whether the server answered every request as it did in the recording
+    pub fn matches(&self) -> bool {
+        self.divergences.is_empty() && self.missing_events.is_empty()
+    }
+}
+
+/// Replays the requests of a recording against `server`
+///
+/// Fails only if the server's message loop does; requests answered
+/// differently are reported, not treated as errors.
+pub async fn replay<R: DebugRuntime>(
+    server: &mut DapServer<R>,
+    recording: &[TraceEntry],
+    timeout: Duration,
+) -> Result<ReplayReport, String> {
+    let (client, server_end) = tokio::io::duplex(64 * 1024);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let transport = DapTransport::new(BufReader::new(server_read), server_write);
+    let client = Client {
+        transport: DapTransport::new(BufReader::new(client_read), client_write),
+        timeout,
+        seen: HashMap::new(),
+        transcript: Vec::new(),
+    };
+
+    let served = async move {
+        // Owned here so the client reads the end of the stream when the loop finishes
+        let mut transport = transport;
+        server.run_event_loop(&mut transport).await.map_err(|e| e.to_string())
+    };
+    let (served, report) = tokio::join!(served, drive(client, recording));
+    served?;
+    Ok(report)
+}
+
+/// Sends the recorded requests, each after what its client had waited for
+async fn drive<Rd, Wr>(mut client: Client<Rd, Wr>, recording: &[TraceEntry]) -> ReplayReport
+where
+    Rd: AsyncBufRead + Unpin,
+    Wr: AsyncWrite + Unpin,
+{
+    let recorded_responses: HashMap<u64, Response> = recording
+        .iter()
+        .filter(|entry| entry.direction == Direction::Sent)
+        .filter_map(|entry| Response::from_json(&entry.message))
+        .map(|response| (response.request_seq, response))
+        .collect();
+
+    let mut report = ReplayReport::default();
+    let mut expected: HashMap<&str, usize> = HashMap::new();
+    for entry in recording {
+        if entry.direction == Direction::Sent {
+            if let Some(event) = entry.message["event"].as_str() {
+                if let Some(event) = STATE_EVENTS.iter().find(|name| **name == event) {
+                    *expected.entry(*event).or_default() += 1;
+                }
+            }
+            continue;
+        }
+
+        client.wait_for_events(&expected).await;
+        let Some(Message { id: seq, method: command, .. }) = Message::from_json(&entry.message) else {
+            // Not a request, so nothing answers it
+            client.send(&entry.message).await;
+            continue;
+        };
+        report.requests += 1;
+        let replayed = client.request(&entry.message, seq).await;
+        let recorded = outcome(recorded_responses.get(&seq));
+        let replayed = outcome(replayed.as_ref());
+        if recorded != replayed {
+            report.divergences.push(Divergence { seq, command, recorded, replayed });
+        }
+    }
+
+    client.wait_for_events(&expected).await;
+    let mut missing: Vec<_> = expected
+        .iter()
+        .filter_map(|(event, &count)| {
+            let seen = client.seen(event);
+            (seen < count).then(|| (event.to_string(), count, seen))
+        })
+        .collect();
+    missing.sort();
+    report.missing_events = missing;
+    report.transcript = client.transcript;
+    report
+}
+
+fn outcome(response: Option<&Response>) -> String {
+    match response.map(|response| &response.result) {
+        None => "no response".to_string(),
+        Some(Ok(_)) => "success".to_string(),
+        Some(Err(error)) => format!("error: {}", error.message),
+    }
+}
+
+/// The replaying end of the connection
+struct Client<Rd, Wr> {
+    transport: DapTransport<Rd, Wr>,
+    timeout: Duration,
+    /// State events received so far, by name
+    seen: HashMap<String, usize>,
+    transcript: Vec<TraceEntry>,
+}
+
+impl<Rd, Wr> Client<Rd, Wr>
+where
+    Rd: AsyncBufRead + Unpin,
+    Wr: AsyncWrite + Unpin,
+{
+    fn seen(&self, event: &str) -> usize {
+        self.seen.get(event).copied().unwrap_or(0)
+    }
+
+    async fn send(&mut self, message: &JsonValue) -> bool {
+        self.transcript.push(TraceEntry::now(Direction::Received, message));
+        match self.transport.write_message(message).await {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("replay could not send a message: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Sends a request and waits for its response
+    async fn request(&mut self, message: &JsonValue, seq: u64) -> Option<Response> {
+        if !self.send(message).await {
+            return None;
+        }
+        while let Some(received) = self.receive().await {
+            match Response::from_json(&received) {
+                Some(response) if response.request_seq == seq => return Some(response),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Reads until as many of each state event as expected have come, or nothing more does
+    async fn wait_for_events(&mut self, expected: &HashMap<&str, usize>) {
+        while expected.iter().any(|(event, &count)| self.seen(event) < count) {
+            if self.receive().await.is_none() {
+                return;
+            }
+        }
+    }
+
+    /// The next message from the server, None once it stops sending
+    async fn receive(&mut self) -> Option<JsonValue> {
+        let message = match tokio::time::timeout(self.timeout, self.transport.read_message()).await {
+            Ok(Ok(Some(message))) => message,
+            Ok(Ok(None)) => return None,
+            Ok(Err(e)) => {
+                tracing::warn!("replay could not read a message: {}", e);
+                return None;
+            }
+            Err(_) => {
+                tracing::debug!("replay timed out waiting for the server");
+                return None;
+            }
+        };
+        if let Some(event) = message["event"].as_str() {
+            *self.seen.entry(event.to_string()).or_default() += 1;
+        }
+        self.transcript.push(TraceEntry::now(Direction::Sent, &message));
+        Some(message)
+    }
+}
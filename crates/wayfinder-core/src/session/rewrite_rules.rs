@@ -0,0 +1,183 @@
+//! Rewrites of protocol messages for clients and engines with quirks
+//!
+//! Rules come from `rewriteRules` in `wayfinder.yaml` or the launch and
+//! attach requests. Each applies to one request, or to every request and
+//! event: source path prefixes are swapped on the way in and back on the way
+//! out, arguments a request leaves unset are filled in, and capabilities are
+//! hidden from the `initialize` response. Rules apply in order to requests
+//! and in reverse to responses and events, so a later rule sees what an
+//! earlier one wrote.
+
+use crate::dap::Event;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+
+/// A rewrite of the messages of one request, or of every message
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RewriteRule {
+    /// Request the rule applies to; every request, and events, when unset or `*`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Source path prefixes swapped in requests, and swapped back in responses and events
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path_prefixes: Vec<PathPrefix>,
+    /// Arguments set on the request where it leaves them unset
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub default_arguments: Map<String, JsonValue>,
+    /// Capabilities the `initialize` response reports as unsupported
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mask_capabilities: Vec<String>,
+}
+
+/// Source paths starting with `from` in requests start with `to` in what the handlers see
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathPrefix {
+    pub from: String,
+    pub to: String,
+}
+
+impl RewriteRule {
+    fn applies_to(&self, command: &str) -> bool {
+        self.command.as_deref().is_none_or(|wanted| wanted == "*" || wanted == command)
+    }
+
+    fn applies_to_events(&self) -> bool {
+        self.command.as_deref().is_none_or(|wanted| wanted == "*")
+    }
+}
+
+/// The configured rewrite rules, applied in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RewriteRules {
+    rules: Vec<RewriteRule>,
+}
+
+impl RewriteRules {
+    pub fn new(rules: Vec<RewriteRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Fills in default arguments and swaps source path prefixes in a request
+    pub fn translate_request(&self, command: &str, arguments: &mut JsonValue) {
+        for rule in self.rules.iter().filter(|rule| rule.applies_to(command)) {
+            if !rule.default_arguments.is_empty() {
+                if arguments.is_null() {
+                    *arguments = JsonValue::Object(Map::new());
+                }
+                if let Some(arguments) = arguments.as_object_mut() {
+                    for (key, value) in &rule.default_arguments {
+                        arguments.entry(key.clone()).or_insert_with(|| value.clone());
+                    }
+                }
+            }
+            for prefix in &rule.path_prefixes {
+                swap_source_prefix(arguments, &prefix.from, &prefix.to);
+            }
+        }
+    }
+
+    /// Swaps source path prefixes back in a response, and masks capabilities in the `initialize` one
+    pub fn translate_response(&self, command: &str, response: &mut JsonValue) {
+        for rule in self.rules.iter().rev().filter(|rule| rule.applies_to(command)) {
+            for prefix in &rule.path_prefixes {
+                swap_source_prefix(&mut response["result"], &prefix.to, &prefix.from);
+            }
+            if command == "initialize" {
+                if let Some(capabilities) = response["result"].as_object_mut() {
+                    for capability in &rule.mask_capabilities {
+                        capabilities.insert(capability.clone(), json!(false));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Swaps source path prefixes back in an event, for the rules that apply to every message
+    pub fn translate_event(&self, event: &mut Event) {
+        let Some(body) = event.body.as_mut() else { return };
+        for rule in self.rules.iter().rev().filter(|rule| rule.applies_to_events()) {
+            for prefix in &rule.path_prefixes {
+                swap_source_prefix(body, &prefix.to, &prefix.from);
+            }
+        }
+    }
+}
+
+/// Replaces `from` with `to` at the start of the path of every source in `value`
+///
+/// Sources are the `source` objects of frames, breakpoints and output, and
+/// the `sources` of `loadedSources`. The prefix must end at a path
+/// separator, so `/game` does not match `/games`.
+fn swap_source_prefix(value: &mut JsonValue, from: &str, to: &str) {
+    match value {
+        JsonValue::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                match (key.as_str(), field) {
+                    ("source", source) => swap_path(source, from, to),
+                    ("sources", JsonValue::Array(sources)) => sources.iter_mut().for_each(|source| swap_path(source, from, to)),
+                    (_, field) => swap_source_prefix(field, from, to),
+                }
+            }
+        }
+        JsonValue::Array(items) => {
+            for item in items {
+                swap_source_prefix(item, from, to);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn swap_path(source: &mut JsonValue, from: &str, to: &str) {
+    let Some(JsonValue::String(path)) = source.get_mut("path") else { return };
+    let Some(rest) = path.strip_prefix(from) else { return };
+    if from.ends_with(['/', '\\']) || rest.is_empty() || rest.starts_with(['/', '\\']) {
+        *path = format!("{}{}", to, rest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_rules() {
+        let rules: Vec<RewriteRule> = serde_json::from_value(json!([
+            { "pathPrefixes": [{ "from": "/home/dev/game", "to": "/srv/game" }] },
+            { "command": "evaluate", "defaultArguments": { "context": "repl" } },
+            { "command": "initialize", "maskCapabilities": ["supportsStepBack"] }
+        ]))
+        .unwrap();
+        let rules = RewriteRules::new(rules);
+
+        let mut arguments = json!({ "source": { "path": "/home/dev/game/main.lua" }, "breakpoints": [{ "line": 3 }] });
+        rules.translate_request("setBreakpoints", &mut arguments);
+        assert_eq!(arguments["source"]["path"], "/srv/game/main.lua");
+        let mut arguments = json!({ "source": { "path": "/home/dev/games/main.lua" } });
+        rules.translate_request("setBreakpoints", &mut arguments);
+        assert_eq!(arguments["source"]["path"], "/home/dev/games/main.lua");
+
+        let mut arguments = json!({ "expression": "x", "context": "hover" });
+        rules.translate_request("evaluate", &mut arguments);
+        assert_eq!(arguments["context"], "hover");
+        let mut arguments = JsonValue::Null;
+        rules.translate_request("evaluate", &mut arguments);
+        assert_eq!(arguments, json!({ "context": "repl" }));
+
+        let mut response = json!({ "result": { "stackFrames": [{ "id": 0, "source": { "path": "/srv/game/lib/util.lua" } }] } });
+        rules.translate_response("stackTrace", &mut response);
+        assert_eq!(response["result"]["stackFrames"][0]["source"]["path"], "/home/dev/game/lib/util.lua");
+        let mut response = json!({ "result": { "sources": [{ "name": "main.lua", "path": "/srv/game/main.lua" }] } });
+        rules.translate_response("loadedSources", &mut response);
+        assert_eq!(response["result"]["sources"][0]["path"], "/home/dev/game/main.lua");
+
+        let mut response = json!({ "result": { "supportsStepBack": true, "supportsRestartRequest": true } });
+        rules.translate_response("initialize", &mut response);
+        assert_eq!(response["result"], json!({ "supportsStepBack": false, "supportsRestartRequest": true }));
+
+        let mut event = Event::new("output", Some(json!({ "output": "x=1\n", "source": { "path": "/srv/game/main.lua" }, "line": 1 })));
+        rules.translate_event(&mut event);
+        assert_eq!(event.body.unwrap()["source"]["path"], "/home/dev/game/main.lua");
+    }
+}
@@ -0,0 +1,430 @@
+//! Rewrites protocol messages through TSTL source maps
+//!
+//! The client works with TypeScript sources while the runtime only knows the
+//! Lua that TSTL generated from them. Requests are rewritten on their way in
+//! and responses and events on their way out, so the handlers in between see
+//! nothing but Lua: `setBreakpoints` and `breakpointLocations` on a
+//! TypeScript file move to its Lua file, and stack frames, breakpoints and output locations in mapped Lua
+//! files point back into TypeScript. Expressions evaluated in frames of
+//! mapped Lua files are rewritten from TypeScript syntax, keeping the
+//! expression as typed to fall back on. Messages about sources without a map
+//! pass through untouched.
+
+use crate::dap::Event;
+use crate::debug::source_maps::SourceMapStore;
+use crate::debug::source_paths::PathResolver;
+use crate::debug::ts_expressions;
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Extensions of sources TSTL compiles, whose maps are searched for on first use
+const TYPESCRIPT_EXTENSIONS: &[&str] = &["ts", "tsx"];
+
+/// A `setBreakpoints` request moved from a TypeScript source to its Lua file
+#[derive(Debug)]
+struct MovedBreakpoints {
+    source: PathBuf,
+    lua_file: PathBuf,
+    /// Each requested TypeScript position with the Lua line it went to, None where no code was generated
+    lines: Vec<(u32, Option<u32>, Option<u32>)>,
+}
+
+/// A TypeScript position a breakpoint can be set on if its Lua line has code
+#[derive(Debug)]
+struct CandidateLocation {
+    line: u32,
+    column: u32,
+    generated_line: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct SourceMapping {
+    store: SourceMapStore,
+    /// Directory searched for Lua files when a TypeScript source has no loaded map yet
+    root: Option<PathBuf>,
+    /// Breakpoint requests awaiting their response, by request id
+    moved: HashMap<u64, MovedBreakpoints>,
+    /// Candidates of each `breakpointLocations` request awaiting its response, by request id
+    candidate_locations: HashMap<u64, Vec<CandidateLocation>>,
+    /// Lua file of each stack frame last reported in a mapped file, by frame id
+    mapped_frames: HashMap<i64, PathBuf>,
+    /// Expressions as typed, by the id of the evaluate request they were rewritten in
+    typed_expressions: HashMap<u64, String>,
+}
+
+impl SourceMapping {
+    pub fn new(root: Option<PathBuf>, resolver: PathResolver) -> Self {
+        Self {
+            store: SourceMapStore::with_resolver(resolver),
+            root,
+            ..Self::default()
+        }
+    }
+
+    /// Rewrites the arguments of a request about a TypeScript source to its Lua file
+    ///
+    /// An `evaluate` in a frame of a mapped Lua file has its expression
+    /// rewritten from TypeScript syntax.
+    pub fn translate_request(&mut self, command: &str, id: u64, arguments: &mut JsonValue) {
+        if command == "evaluate" {
+            self.translate_expression(id, arguments);
+            return;
+        }
+        if command == "breakpointLocations" {
+            self.translate_locations_request(id, arguments);
+            return;
+        }
+        if command != "setBreakpoints" {
+            return;
+        }
+        let Some(source) = arguments["source"]["path"].as_str().map(PathBuf::from) else { return };
+        let Some(lua_file) = self.lua_file_for(&source) else { return };
+
+        let requested = arguments["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut lines = Vec::new();
+        let mut breakpoints = Vec::new();
+        for mut breakpoint in requested {
+            let Some(line) = breakpoint["line"].as_u64().map(|line| line as u32) else { continue };
+            let requested_column = breakpoint["column"].as_u64().map(|column| column as u32);
+            let column = requested_column.unwrap_or(1);
+            let generated = self.store.to_generated(&source, line, column).map(|(_, position)| position.line);
+            if let Some(generated) = generated {
+                breakpoint["line"] = json!(generated);
+                if let Some(breakpoint) = breakpoint.as_object_mut() {
+                    breakpoint.remove("column");
+                }
+                breakpoints.push(breakpoint);
+            }
+            lines.push((line, requested_column, generated));
+        }
+
+        arguments["source"] = json!({ "path": lua_file.display().to_string() });
+        arguments["breakpoints"] = json!(breakpoints);
+        self.moved.insert(id, MovedBreakpoints { source, lua_file, lines });
+    }
+
+    /// Rewrites positions in mapped Lua files in a response to their TypeScript sources
+    pub fn translate_response(&mut self, command: &str, id: u64, response: &mut JsonValue) {
+        match command {
+            "setBreakpoints" => {
+                if let Some(moved) = self.moved.remove(&id) {
+                    self.restore_breakpoints(moved, &mut response["result"]["breakpoints"]);
+                }
+            }
+            "breakpointLocations" => {
+                let Some(candidates) = self.candidate_locations.remove(&id) else { return };
+                let Some(lines) = response["result"]["breakpoints"].as_array() else { return };
+                let lines: Vec<u64> = lines.iter().filter_map(|location| location["line"].as_u64()).collect();
+                let locations: Vec<JsonValue> = candidates
+                    .into_iter()
+                    .filter(|candidate| lines.contains(&(candidate.generated_line as u64)))
+                    .map(|candidate| json!({ "line": candidate.line, "column": candidate.column }))
+                    .collect();
+                response["result"]["breakpoints"] = json!(locations);
+            }
+            "stackTrace" => {
+                if let Some(frames) = response["result"]["stackFrames"].as_array_mut() {
+                    for frame in frames {
+                        let lua_file = frame["source"]["path"].as_str().map(PathBuf::from);
+                        let Some(id) = frame["id"].as_i64() else { continue };
+                        match lua_file.filter(|_| self.translate_location(frame)) {
+                            Some(lua_file) => self.mapped_frames.insert(id, lua_file),
+                            None => self.mapped_frames.remove(&id),
+                        };
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Rewrites the location an event carries, if it is in a mapped Lua file
+    pub fn translate_event(&mut self, event: &mut Event) {
+        let Some(body) = event.body.as_mut() else { return };
+        match event.event.as_str() {
+            "breakpoint" => {
+                self.translate_location(&mut body["breakpoint"]);
+            }
+            "output" => {
+                self.translate_location(body);
+            }
+            _ => {}
+        }
+    }
+
+    /// The expression an evaluate request was rewritten from, to evaluate as typed if the rewrite fails
+    pub fn take_typed_expression(&mut self, id: u64) -> Option<String> {
+        self.typed_expressions.remove(&id)
+    }
+
+    /// Rewrites the expression of an evaluate request in a frame of a mapped Lua file
+    fn translate_expression(&mut self, id: u64, arguments: &mut JsonValue) {
+        let Some(frame_id) = arguments["frameId"].as_i64() else { return };
+        let Some(lua_file) = self.mapped_frames.get(&frame_id) else { return };
+        let Some(expression) = arguments["expression"].as_str() else { return };
+        // Debug console commands are not expressions
+        if expression.trim_start().starts_with('.') {
+            return;
+        }
+
+        let renames = self.store.renamed_identifiers(lua_file);
+        if let Some(translated) = ts_expressions::translate(expression, &renames) {
+            self.typed_expressions.insert(id, expression.to_string());
+            arguments["expression"] = json!(translated);
+        }
+    }
+
+    /// Moves a `breakpointLocations` request to the Lua lines a TypeScript range compiled to
+    ///
+    /// Every mapping in the range is a candidate, so a line with several
+    /// statements offers a location for each; the response keeps those whose
+    /// Lua line has code.
+    fn translate_locations_request(&mut self, id: u64, arguments: &mut JsonValue) {
+        let Some(source) = arguments["source"]["path"].as_str().map(PathBuf::from) else { return };
+        let Some(line) = arguments["line"].as_u64().map(|line| line as u32) else { return };
+        let end_line = arguments["endLine"].as_u64().map_or(line, |line| line as u32);
+        let Some(lua_file) = self.lua_file_for(&source) else { return };
+
+        let mut candidates: Vec<CandidateLocation> = Vec::new();
+        for (original, generated) in self.store.mappings_in(&lua_file, &source, line, end_line) {
+            let repeated = candidates.last().is_some_and(|last| (last.line, last.column) == (original.line, original.column));
+            if !repeated {
+                candidates.push(CandidateLocation {
+                    line: original.line,
+                    column: original.column,
+                    generated_line: generated.line,
+                });
+            }
+        }
+        let generated_lines = candidates.iter().map(|candidate| candidate.generated_line);
+
+        arguments["source"] = json!({ "path": lua_file.display().to_string() });
+        arguments["line"] = json!(generated_lines.clone().min().unwrap_or(line));
+        arguments["endLine"] = json!(generated_lines.max().unwrap_or(line));
+        if let Some(arguments) = arguments.as_object_mut() {
+            arguments.remove("column");
+            arguments.remove("endColumn");
+        }
+        self.candidate_locations.insert(id, candidates);
+    }
+
+    /// Maps the `source` and `line` of a stack frame, breakpoint or output body back to TypeScript
+    ///
+    /// Returns whether the location was in a mapped Lua file.
+    fn translate_location(&mut self, located: &mut JsonValue) -> bool {
+        let Some(path) = located["source"]["path"].as_str().map(PathBuf::from) else { return false };
+        let Some(line) = located["line"].as_u64().filter(|&line| line > 0) else { return false };
+        let column = located["column"].as_u64().unwrap_or(1) as u32;
+        let Some(original) = self.store.to_original(&path, line as u32, column) else { return false };
+
+        located["source"] = source_json(&original.source);
+        located["line"] = json!(original.line);
+        if located.get("column").is_some() {
+            located["column"] = json!(original.column);
+        }
+        true
+    }
+
+    /// Puts a moved request's breakpoints back in TypeScript, in the order they were requested
+    fn restore_breakpoints(&mut self, moved: MovedBreakpoints, results: &mut JsonValue) {
+        let mut set = results.as_array().cloned().unwrap_or_default().into_iter();
+        let restored = moved
+            .lines
+            .iter()
+            .map(|&(line, column, generated)| {
+                let Some(generated) = generated else {
+                    return json!({ "verified": false, "line": line, "message": "No code was generated for this line" });
+                };
+                let Some(mut breakpoint) = set.next() else { return json!({ "verified": false, "line": line }) };
+                // A runtime that moved the breakpoint moved it in Lua
+                match breakpoint["line"].as_u64() {
+                    Some(actual) if actual as u32 != generated => {
+                        let line = self
+                            .store
+                            .to_original(&moved.lua_file, actual as u32, 1)
+                            .filter(|original| original.source == moved.source)
+                            .map_or(line, |original| original.line);
+                        breakpoint["line"] = json!(line);
+                    }
+                    _ => {
+                        breakpoint["line"] = json!(line);
+                        if let Some(column) = column {
+                            breakpoint["column"] = json!(column);
+                        }
+                    }
+                }
+                breakpoint["source"] = source_json(&moved.source);
+                breakpoint
+            })
+            .collect();
+        *results = JsonValue::Array(restored);
+    }
+
+    /// The Lua file a TypeScript source compiled to, searching the root for its map on first use
+    fn lua_file_for(&mut self, source: &Path) -> Option<PathBuf> {
+        if let Some(lua_file) = self.store.lua_file_for(source) {
+            return Some(lua_file);
+        }
+        let extension = source.extension()?.to_str()?;
+        if !TYPESCRIPT_EXTENSIONS.contains(&extension) {
+            return None;
+        }
+        // The source may have been compiled since the last search
+        self.store.load_dir(self.root.as_ref()?);
+        self.store.lua_file_for(source)
+    }
+}
+
+fn source_json(path: &Path) -> JsonValue {
+    json!({
+        "name": path.file_name().map(|name| name.to_string_lossy().to_string()),
+        "path": path.display().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::source_paths::SourcePathConfig;
+    use serde::Deserialize;
+
+    /// `main.lua` compiled from `main.ts`: Lua lines 1 and 2 come from TypeScript lines 2 and 4
+    fn project() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let lua_file = dir.path().join("main.lua");
+        std::fs::write(&lua_file, "local x = 1\nprint(x)\n--# sourceMappingURL=main.lua.map\n").unwrap();
+        std::fs::write(
+            dir.path().join("main.lua.map"),
+            r#"{ "version": 3, "sources": ["main.ts"], "names": [], "mappings": "AACA;AAEA" }"#,
+        )
+        .unwrap();
+        let source = dir.path().join("main.ts");
+        (dir, lua_file, source)
+    }
+
+    #[test]
+    fn test_breakpoints_move_to_lua_and_back() {
+        let (dir, lua_file, source) = project();
+        let mut mapping = SourceMapping::new(Some(dir.path().to_path_buf()), PathResolver::default());
+
+        let mut arguments = json!({
+            "source": { "path": source.display().to_string() },
+            "breakpoints": [{ "line": 4 }, { "line": 3 }, { "line": 2, "condition": "x > 0" }]
+        });
+        mapping.translate_request("setBreakpoints", 7, &mut arguments);
+        assert_eq!(arguments["source"]["path"], lua_file.display().to_string());
+        assert_eq!(arguments["breakpoints"], json!([{ "line": 2 }, { "line": 1, "condition": "x > 0" }]));
+
+        let mut response = json!({ "id": 7, "result": { "breakpoints": [
+            { "id": 1, "verified": true, "line": 2 },
+            { "id": 2, "verified": true, "line": 1 }
+        ] } });
+        mapping.translate_response("setBreakpoints", 7, &mut response);
+        let breakpoints = response["result"]["breakpoints"].as_array().unwrap();
+        let lines: Vec<_> = breakpoints.iter().map(|bp| (bp["line"].clone(), bp["verified"].clone())).collect();
+        assert_eq!(lines, vec![(json!(4), json!(true)), (json!(3), json!(false)), (json!(2), json!(true))]);
+        assert_eq!(breakpoints[0]["source"]["path"], source.display().to_string());
+    }
+
+    #[test]
+    fn test_breakpoint_locations_and_columns() {
+        let (dir, lua_file, source) = project();
+        let mut mapping = SourceMapping::new(Some(dir.path().to_path_buf()), PathResolver::default());
+
+        let mut arguments = json!({ "source": { "path": source.display().to_string() }, "line": 1, "endLine": 4 });
+        mapping.translate_request("breakpointLocations", 3, &mut arguments);
+        assert_eq!(arguments, json!({ "source": { "path": lua_file.display().to_string() }, "line": 1, "endLine": 2 }));
+        // Only Lua line 2 has code
+        let mut response = json!({ "id": 3, "result": { "breakpoints": [{ "line": 2 }] } });
+        mapping.translate_response("breakpointLocations", 3, &mut response);
+        assert_eq!(response["result"]["breakpoints"], json!([{ "line": 4, "column": 1 }]));
+
+        let mut arguments = json!({ "source": { "path": source.display().to_string() }, "breakpoints": [{ "line": 4, "column": 1 }] });
+        mapping.translate_request("setBreakpoints", 4, &mut arguments);
+        assert_eq!(arguments["breakpoints"], json!([{ "line": 2 }]));
+        let mut response = json!({ "id": 4, "result": { "breakpoints": [{ "id": 1, "verified": true, "line": 2 }] } });
+        mapping.translate_response("setBreakpoints", 4, &mut response);
+        let breakpoint = &response["result"]["breakpoints"][0];
+        assert_eq!((breakpoint["line"].clone(), breakpoint["column"].clone()), (json!(4), json!(1)));
+    }
+
+    #[test]
+    fn test_frames_and_events_map_back_and_others_pass_through() {
+        let (dir, lua_file, source) = project();
+        let mut mapping = SourceMapping::new(Some(dir.path().to_path_buf()), PathResolver::default());
+
+        let plain = dir.path().join("plain.lua").display().to_string();
+        let mut response = json!({ "result": { "stackFrames": [
+            { "id": 0, "name": "main", "line": 2, "column": 1, "source": { "path": lua_file.display().to_string() } },
+            { "id": 1, "name": "helper", "line": 9, "column": 1, "source": { "path": plain } }
+        ] } });
+        mapping.translate_response("stackTrace", 1, &mut response);
+        let frames = &response["result"]["stackFrames"];
+        assert_eq!((frames[0]["source"]["name"].clone(), frames[0]["line"].clone()), (json!("main.ts"), json!(4)));
+        assert_eq!((frames[1]["source"]["path"].clone(), frames[1]["line"].clone()), (json!(plain), json!(9)));
+
+        let mut event = Event::output_at("console", "x=1\n", &lua_file.display().to_string(), 1);
+        mapping.translate_event(&mut event);
+        let body = event.body.unwrap();
+        assert_eq!((body["source"]["path"].clone(), body["line"].clone()), (json!(source.display().to_string()), json!(2)));
+
+        let mut arguments = json!({ "source": { "path": plain }, "breakpoints": [{ "line": 3 }] });
+        let untouched = arguments.clone();
+        mapping.translate_request("setBreakpoints", 2, &mut arguments);
+        assert_eq!(arguments, untouched);
+    }
+
+    #[test]
+    fn test_expressions_in_mapped_frames_are_rewritten() {
+        let (dir, lua_file, _) = project();
+        let mut mapping = SourceMapping::new(Some(dir.path().to_path_buf()), PathResolver::default());
+        let plain = dir.path().join("plain.lua").display().to_string();
+        let mut response = json!({ "result": { "stackFrames": [
+            { "id": 0, "name": "main", "line": 2, "column": 1, "source": { "path": lua_file.display().to_string() } },
+            { "id": 1, "name": "helper", "line": 9, "column": 1, "source": { "path": plain } }
+        ] } });
+        mapping.translate_response("stackTrace", 1, &mut response);
+
+        let mut arguments = json!({ "expression": "this.target?.hp", "frameId": 0 });
+        mapping.translate_request("evaluate", 2, &mut arguments);
+        assert_eq!(arguments["expression"], "(self.target and self.target.hp)");
+        assert_eq!(mapping.take_typed_expression(2).as_deref(), Some("this.target?.hp"));
+
+        let mut arguments = json!({ "expression": "this.target?.hp", "frameId": 1 });
+        mapping.translate_request("evaluate", 3, &mut arguments);
+        assert_eq!(arguments["expression"], "this.target?.hp");
+        assert_eq!(mapping.take_typed_expression(3), None);
+    }
+
+    #[test]
+    fn test_breakpoints_follow_tsconfig_out_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let dist = dir.path().join("dist");
+        std::fs::create_dir(&dist).unwrap();
+        std::fs::write(dist.join("main.lua"), "local x = 1\n--# sourceMappingURL=main.lua.map\n").unwrap();
+        std::fs::write(
+            dist.join("main.lua.map"),
+            r#"{ "version": 3, "sourceRoot": "/build/src", "sources": ["main.ts"], "names": [], "mappings": "AAAA" }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("tsconfig.json"),
+            r#"{ "compilerOptions": { "rootDir": "src", "outDir": "dist" } }"#,
+        )
+        .unwrap();
+
+        let arguments = json!({ "sourceRoot": "src" });
+        let (resolver, error) = PathResolver::new(&SourcePathConfig::deserialize(&arguments).unwrap(), dir.path());
+        assert_eq!(error, None);
+        // No search root, so the Lua file can only come from the tsconfig
+        let mut mapping = SourceMapping::new(None, resolver);
+
+        let mut arguments = json!({
+            "source": { "path": dir.path().join("src/main.ts").display().to_string() },
+            "breakpoints": [{ "line": 1 }]
+        });
+        mapping.translate_request("setBreakpoints", 1, &mut arguments);
+        assert_eq!(arguments["source"]["path"], dist.join("main.lua").display().to_string());
+    }
+}
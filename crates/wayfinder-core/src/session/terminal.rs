@@ -0,0 +1,83 @@
+//! Running the launched program in the client's terminal
+//!
+//! A program that reads stdin or draws to the terminal cannot share the
+//! adapter's stdio. When a launch request has a `console` of
+//! `integratedTerminal` or `externalTerminal` and the client supports it, the
+//! server sends the client a `runInTerminal` request for a command that runs
+//! the program under a debug agent, attaches to that agent once the client
+//! reports the command started, and only then answers the launch.
+
+use super::launch_arguments::LaunchArguments;
+use serde_json::{json, Value as JsonValue};
+use std::time::Duration;
+
+/// Time the program's agent has to start listening after the client runs its command
+pub const AGENT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay between attempts to connect to the agent
+const AGENT_CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How an embedder runs programs under a debug agent and attaches to them
+#[async_trait::async_trait]
+pub trait TerminalLauncher<R>: Send + Sync {
+    /// Command line that runs the launched program under an agent listening on the TCP `address`
+    fn command(&self, address: &str, arguments: &LaunchArguments) -> Result<Vec<String>, String>;
+
+    /// Connects to the agent listening on `address`
+    async fn connect(&self, address: &str) -> Result<R, String>;
+}
+
+/// A launch waiting for the client to run its program in a terminal
+#[derive(Debug)]
+pub(crate) struct PendingTerminalLaunch {
+    /// Id of the launch request, answered once the agent is connected
+    pub launch_id: u64,
+    pub arguments: LaunchArguments,
+    /// The launch request's arguments, for the settings read from it directly
+    pub params: JsonValue,
+    pub address: String,
+}
+
+/// The `kind` of `runInTerminal` for a launch's `console`; None to run in the debug console
+pub fn terminal_kind(console: Option<&str>) -> Option<&'static str> {
+    match console {
+        Some("integratedTerminal") => Some("integrated"),
+        Some("externalTerminal") => Some("external"),
+        _ => None,
+    }
+}
+
+/// A local TCP address nothing listens on, for the program's agent
+pub fn free_local_address() -> std::io::Result<String> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.to_string())
+}
+
+/// Arguments of the `runInTerminal` request for a launch
+pub fn run_in_terminal_arguments(kind: &str, command: Vec<String>, arguments: &LaunchArguments) -> JsonValue {
+    let cwd = arguments.cwd.clone().or_else(|| {
+        std::env::current_dir().ok().map(|dir| dir.display().to_string())
+    });
+    let program = arguments.program.as_deref().unwrap_or("Lua");
+    json!({
+        "kind": kind,
+        "title": format!("Wayfinder: {}", program),
+        "cwd": cwd.unwrap_or_default(),
+        "args": command,
+        "env": arguments.env,
+    })
+}
+
+/// Connects to the agent, retrying while it starts, until `AGENT_CONNECT_TIMEOUT` passes
+pub(crate) async fn connect_to_agent<R>(launcher: &dyn TerminalLauncher<R>, address: &str) -> Result<R, String> {
+    let deadline = tokio::time::Instant::now() + AGENT_CONNECT_TIMEOUT;
+    loop {
+        match launcher.connect(address).await {
+            Ok(runtime) => return Ok(runtime),
+            Err(e) if tokio::time::Instant::now() + AGENT_CONNECT_RETRY_INTERVAL >= deadline => {
+                return Err(format!("The program's debug agent at {} did not answer: {}", address, e));
+            }
+            Err(_) => tokio::time::sleep(AGENT_CONNECT_RETRY_INTERVAL).await,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Trace of the most recent DAP messages
+//!
+//! The server keeps the last requests, responses and events it exchanged with
+//! the client, so a bug report can show what led up to a problem without
+//! anyone having to turn on logging and reproduce it.
+//!
+//! A [`TraceRecorder`] keeps everything instead, appending each message to a
+//! JSONL file as one [`TraceEntry`] per line. Such a recording can be read
+//! back with [`read_recording`] and replayed against a fresh server, see
+//! [`replay`](super::replay).
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of messages kept before the oldest is dropped
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+    /// From the client to the debugger
+    Received,
+    /// From the debugger to the client
+    Sent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceEntry {
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub direction: Direction,
+    pub message: JsonValue,
+}
+
+impl TraceEntry {
+    /// An entry for a message exchanged just now
+    pub fn now(direction: Direction, message: &JsonValue) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self {
+            timestamp_ms,
+            direction,
+            message: message.clone(),
+        }
+    }
+}
+
+/// Ring buffer of the most recent messages
+#[derive(Debug)]
+pub struct ProtocolTrace {
+    capacity: usize,
+    entries: VecDeque<TraceEntry>,
+}
+
+impl ProtocolTrace {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity.min(DEFAULT_CAPACITY)),
+        }
+    }
+
+    /// Stores a message, dropping the oldest one when full
+    pub fn record(&mut self, direction: Direction, message: &JsonValue) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TraceEntry::now(direction, message));
+    }
+
+    /// Returns the kept messages, oldest first
+    pub fn entries(&self) -> Vec<TraceEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+impl Default for ProtocolTrace {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// Appends every message to a JSONL file
+///
+/// Each line is flushed as it is written, so a recording survives the
+/// adapter crashing. A write that fails is logged once and turns the
+/// recorder off rather than failing the session.
+#[derive(Debug)]
+pub struct TraceRecorder {
+    writer: Option<BufWriter<File>>,
+}
+
+impl TraceRecorder {
+    /// Creates or truncates the recording at `path`
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            writer: Some(BufWriter::new(File::create(path)?)),
+        })
+    }
+
+    pub fn record(&mut self, direction: Direction, message: &JsonValue) {
+        let Some(writer) = &mut self.writer else { return };
+        let written = serde_json::to_writer(&mut *writer, &TraceEntry::now(direction, message))
+            .map_err(std::io::Error::from)
+            .and_then(|_| writer.write_all(b"\n"))
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            tracing::warn!("stopped recording the DAP trace: {}", e);
+            self.writer = None;
+        }
+    }
+}
+
+/// Reads a recording written by a [`TraceRecorder`], skipping blank lines
+pub fn read_recording(path: &Path) -> Result<Vec<TraceEntry>, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line)
+            .map_err(|e| format!("{}:{}: not a trace entry: {}", path.display(), number + 1, e))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Writes entries in the format of a recording, such as a replay's transcript
+pub fn write_recording(path: &Path, entries: &[TraceEntry]) -> Result<(), String> {
+    let write = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for entry in entries {
+            serde_json::to_writer(&mut writer, entry)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    };
+    write().map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_oldest_messages_are_dropped() {
+        let mut trace = ProtocolTrace::new(2);
+        trace.record(Direction::Received, &json!({ "command": "initialize" }));
+        trace.record(Direction::Sent, &json!({ "id": 1, "result": {} }));
+        trace.record(Direction::Sent, &json!({ "event": "initialized" }));
+
+        let entries = trace.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].direction, Direction::Sent);
+        assert_eq!(entries[1].message["event"], "initialized");
+    }
+
+    #[test]
+    fn test_recording_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let mut recorder = TraceRecorder::create(&path).unwrap();
+        recorder.record(Direction::Received, &json!({ "seq": 1, "command": "initialize" }));
+        recorder.record(Direction::Sent, &json!({ "id": 1, "result": {} }));
+        drop(recorder);
+
+        let entries = read_recording(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].direction, Direction::Received);
+        assert_eq!(entries[1].message, json!({ "id": 1, "result": {} }));
+
+        std::fs::write(&path, "{\"timestampMs\":1}\n").unwrap();
+        assert!(read_recording(&path).unwrap_err().contains(":1:"));
+    }
+}
@@ -73,6 +73,7 @@ async fn test_conditional_breakpoints() {
         id: 0,
         source: "/test/script.lua".to_string(),
         line: 10,
+        column: None,
         condition: Some("x > 5".to_string()),
         log_message: None,
         hit_condition: None,
@@ -100,6 +101,7 @@ async fn test_logpoint_breakpoints() {
         id: 0,
         source: "/test/script.lua".to_string(),
         line: 15,
+        column: None,
         condition: None,
         log_message: Some("Value of x is {x}".to_string()),
         hit_condition: None,
@@ -127,6 +129,7 @@ async fn test_hit_condition_breakpoints() {
         id: 0,
         source: "/test/script.lua".to_string(),
         line: 20,
+        column: None,
         condition: None,
         log_message: None,
         hit_condition: Some(">= 3".to_string()),
@@ -0,0 +1,571 @@
+//! End-to-end DAP flows against a scripted runtime
+//!
+//! These tests drive `DapServer::handle_request` through whole sessions
+//! with a `MockRuntime` scripted for each scenario, so the handlers are
+//! checked together without a Lua interpreter.
+
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashMap;
+use wayfinder_core::config::DebuggerSettings;
+use wayfinder_core::dap::arguments::INVALID_ARGUMENTS;
+use wayfinder_core::dap::{event_channel, EventReceiver};
+use wayfinder_core::debug::conditions::exception_condition;
+use wayfinder_core::debug::modules::Module;
+use wayfinder_core::debug::watchpoints::{AccessType, DataBreakpoint, DataType};
+use wayfinder_core::runtime::mock::{self, MockBreakpointOutcome, MockRuntime, MockStop, MOCK_THREAD_ID};
+use wayfinder_core::runtime::{error_code, DebugRuntime, Value, Variable};
+use wayfinder_core::session::launch_arguments::LaunchArguments;
+use wayfinder_core::session::terminal::TerminalLauncher;
+use wayfinder_core::session::{DapServer, DebugSession};
+
+/// A server with a scripted runtime, and the events the runtime raises
+struct Harness {
+    server: DapServer<MockRuntime>,
+    /// Handle on the server's runtime, to script it and check what it was asked
+    runtime: MockRuntime,
+    events: EventReceiver,
+    seq: u64,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let runtime = MockRuntime::new();
+        let mut server: DapServer<MockRuntime> = DapServer::new();
+        server.set_runtime(runtime.clone());
+
+        // Clones share their state, so this redirects the server's runtime's events here
+        let (event_tx, events) = event_channel();
+        let mut handle = runtime.clone();
+        handle.set_event_sender(event_tx);
+
+        Self {
+            server,
+            runtime,
+            events,
+            seq: 0,
+        }
+    }
+
+    /// Sends a request and returns its response, which must answer it
+    async fn request(&mut self, command: &str, arguments: JsonValue) -> JsonValue {
+        self.seq += 1;
+        let response = self
+            .server
+            .handle_request(command, &arguments, self.seq)
+            .await
+            .unwrap_or_else(|| panic!("{} got no response", command));
+        assert_eq!(response["id"], self.seq, "{}", response);
+        response
+    }
+
+    /// Sends a request that must succeed and returns its result
+    async fn success(&mut self, command: &str, arguments: JsonValue) -> JsonValue {
+        let response = self.request(command, arguments).await;
+        assert!(response.get("error").is_none(), "{} failed: {}", command, response);
+        response["result"].clone()
+    }
+
+    /// The names of the events raised since the last call, with their bodies
+    fn events(&mut self) -> Vec<(String, JsonValue)> {
+        let mut events = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            events.push((event.event, event.body.unwrap_or_default()));
+        }
+        events
+    }
+}
+
+#[tokio::test]
+async fn test_breakpoint_stop_inspect_and_continue() {
+    let mut harness = Harness::new();
+    harness.runtime.queue_breakpoint_outcome(MockBreakpointOutcome {
+        verified: true,
+        line: Some(13),
+        message: None,
+    });
+    harness.runtime.queue_breakpoint_outcome(MockBreakpointOutcome {
+        verified: false,
+        line: None,
+        message: Some("No code at line 40".to_string()),
+    });
+    harness.runtime.queue_stop(MockStop::new(
+        "breakpoint",
+        vec![
+            mock::frame(7, "update", "/game/player.lua", 13),
+            mock::frame(8, "main chunk", "/game/main.lua", 3),
+        ],
+    ));
+    harness.runtime.set_variables(7, vec![mock::variable("hp", "12", "number")]);
+    harness.runtime.set_evaluation("hp * 2", Value::Number(24.0));
+
+    let capabilities = harness.success("initialize", json!({ "adapterID": "wayfinder" })).await;
+    assert_eq!(capabilities["supportsConfigurationDoneRequest"], true);
+
+    harness.success("launch", json!({ "program": "/game/main.lua", "args": ["--fast"] })).await;
+    let launched = harness.runtime.launched_program().expect("the program was launched");
+    assert_eq!(launched.program, "/game/main.lua");
+    assert_eq!(launched.args, vec!["--fast"]);
+
+    let result = harness
+        .success(
+            "setBreakpoints",
+            json!({ "source": { "path": "/game/player.lua" }, "breakpoints": [{ "line": 12 }, { "line": 40 }] }),
+        )
+        .await;
+    let breakpoints = result["breakpoints"].as_array().unwrap();
+    assert_eq!((breakpoints[0]["verified"].clone(), breakpoints[0]["line"].clone()), (json!(true), json!(13)));
+    assert_eq!(breakpoints[1]["verified"], false);
+    assert_eq!(breakpoints[1]["message"], "No code at line 40");
+    assert_eq!(harness.runtime.breakpoint_lines("/game/player.lua"), vec![12, 40]);
+
+    harness.success("configurationDone", json!({})).await;
+    let events = harness.events();
+    assert_eq!(events.len(), 1, "{:?}", events);
+    assert_eq!(events[0].0, "stopped");
+    assert_eq!(events[0].1["reason"], "breakpoint");
+    assert_eq!(events[0].1["threadId"], MOCK_THREAD_ID);
+
+    let trace = harness.success("stackTrace", json!({ "threadId": MOCK_THREAD_ID })).await;
+    assert_eq!(trace["totalFrames"], 2);
+    assert_eq!(trace["stackFrames"][0]["name"], "update");
+    assert_eq!(trace["stackFrames"][0]["line"], 13);
+    assert_eq!(trace["stackFrames"][1]["source"]["path"], "/game/main.lua");
+
+    let scopes = harness.success("scopes", json!({ "frameId": 7 })).await;
+    let locals = scopes["scopes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|scope| scope["name"] == "Locals")
+        .expect("a Locals scope")["variablesReference"]
+        .clone();
+    let variables = harness.success("variables", json!({ "variablesReference": locals })).await;
+    assert_eq!(variables["variables"][0]["name"], "hp");
+    assert_eq!(variables["variables"][0]["value"], "12");
+
+    let evaluated = harness.success("evaluate", json!({ "expression": "hp * 2", "frameId": 7 })).await;
+    assert_eq!(evaluated["result"], "24");
+
+    harness.success("continue", json!({ "threadId": MOCK_THREAD_ID })).await;
+    let events: Vec<String> = harness.events().into_iter().map(|(name, _)| name).collect();
+    assert_eq!(events, ["exited", "terminated"]);
+    assert_eq!(harness.runtime.resumes(), 2);
+
+    harness.success("disconnect", json!({})).await;
+}
+
+#[tokio::test]
+async fn test_steps_stop_where_scripted() {
+    let mut harness = Harness::new();
+    harness.runtime.queue_stop(MockStop::new("entry", vec![mock::frame(1, "main chunk", "/game/main.lua", 1)]));
+    harness.runtime.queue_stop(MockStop::new("step", vec![mock::frame(1, "main chunk", "/game/main.lua", 2)]));
+
+    harness.success("initialize", json!({})).await;
+    harness.success("launch", json!({ "program": "/game/main.lua", "stopOnEntry": true })).await;
+    harness.success("configurationDone", json!({})).await;
+    assert_eq!(harness.events()[0].1["reason"], "entry");
+
+    harness.success("next", json!({ "threadId": MOCK_THREAD_ID })).await;
+    assert_eq!(harness.events()[0].1["reason"], "step");
+    let trace = harness.success("stackTrace", json!({ "threadId": MOCK_THREAD_ID })).await;
+    assert_eq!(trace["stackFrames"][0]["line"], 2);
+}
+
+#[tokio::test]
+async fn test_data_breakpoints_reach_the_runtime() {
+    let mut harness = Harness::new();
+    harness.success("initialize", json!({})).await;
+    harness.success("launch", json!({ "program": "/game/main.lua" })).await;
+
+    let result = harness
+        .success(
+            "setDataBreakpoints",
+            json!({ "breakpoints": [{ "dataId": "global:score" }, { "dataId": "hp", "accessType": "readWrite" }] }),
+        )
+        .await;
+    let verified: Vec<&JsonValue> = result["breakpoints"].as_array().unwrap().iter().map(|bp| &bp["verified"]).collect();
+    assert_eq!(verified, [true, true]);
+
+    let watched = harness.runtime.data_breakpoints();
+    assert_eq!((watched[0].name.as_str(), &watched[0].data_type), ("score", &DataType::Global));
+    assert_eq!(watched[0].access_type, AccessType::Write);
+    assert_eq!((watched[1].name.as_str(), &watched[1].data_type), ("hp", &DataType::Local));
+    assert_eq!(watched[1].access_type, AccessType::ReadWrite);
+
+    let response = harness
+        .request("setDataBreakpoints", json!({ "breakpoints": [{ "dataId": "hp", "accessType": "execute" }] }))
+        .await;
+    assert_eq!(response["error"]["message"], "Unknown access type: execute");
+}
+
+#[tokio::test]
+async fn test_hit_conditions_of_data_breakpoints_and_exceptions() {
+    let runtime = MockRuntime::new();
+    let mut session = DebugSession::new(runtime.clone());
+    let watched = session
+        .set_data_breakpoints(vec![DataBreakpoint {
+            id: 0,
+            name: "score".to_string(),
+            condition: None,
+            hit_condition: Some("% 3".to_string()),
+            verified: false,
+            message: None,
+            hit_count: 0,
+            data_type: DataType::Global,
+            access_type: AccessType::Write,
+            previous_value: None,
+        }])
+        .await;
+    let stops: Vec<bool> = (0..6).map(|_| session.should_stop_at_data_breakpoint(watched[0].id)).collect();
+    assert_eq!(stops, [false, false, true, false, false, true]);
+
+    session.breakpoint_manager().set_exception_breakpoints(vec!["all".to_string(), "uncaught".to_string()]);
+    session
+        .breakpoint_manager()
+        .set_exception_hit_conditions(HashMap::from([("all".to_string(), ">= 2".to_string())]));
+    assert!(!session.should_stop_at_exception(Some("all"), "boom", Some(0)).await);
+    assert!(session.should_stop_at_exception(Some("all"), "boom", Some(0)).await);
+    // Without a filter, the one without a hit condition stops
+    assert!(session.should_stop_at_exception(None, "boom", Some(0)).await);
+
+    // An exception that fails its filter's condition neither stops nor counts as a hit
+    let condition = "message:find('fatal')";
+    session
+        .breakpoint_manager()
+        .set_exception_conditions(HashMap::from([("uncaught".to_string(), condition.to_string())]));
+    runtime.set_evaluation(&exception_condition(condition, "boom"), Value::Boolean(false));
+    runtime.set_evaluation(&exception_condition(condition, "fatal: boom"), Value::Boolean(true));
+    assert!(!session.should_stop_at_exception(Some("uncaught"), "boom", Some(0)).await);
+    assert!(session.should_stop_at_exception(Some("uncaught"), "fatal: boom", Some(0)).await);
+}
+
+#[tokio::test]
+async fn test_requests_fail_without_a_session() {
+    let mut server: DapServer<MockRuntime> = DapServer::new();
+    for command in ["setBreakpoints", "stackTrace", "continue"] {
+        let response = server
+            .handle_request(command, &json!({ "source": { "path": "/game/main.lua" } }), 1)
+            .await
+            .unwrap();
+        assert!(response["error"]["message"].is_string(), "{} {}", command, response);
+        assert_eq!(response["error"]["code"], error_code::NO_SESSION, "{}", command);
+    }
+}
+
+/// Attaches to a scripted runtime in place of a program's agent
+struct MockLauncher(MockRuntime);
+
+#[async_trait::async_trait]
+impl TerminalLauncher<MockRuntime> for MockLauncher {
+    fn command(&self, address: &str, arguments: &LaunchArguments) -> Result<Vec<String>, String> {
+        let program = arguments.program.clone().unwrap_or_default();
+        Ok(vec!["wayfinder".to_string(), "agent".to_string(), "--listen".to_string(), address.to_string(), program])
+    }
+
+    async fn connect(&self, _address: &str) -> Result<MockRuntime, String> {
+        Ok(self.0.clone())
+    }
+}
+
+#[tokio::test]
+async fn test_launch_in_the_clients_terminal() {
+    let mut harness = Harness::new();
+    let agent = MockRuntime::new();
+    harness.server.set_terminal_launcher(MockLauncher(agent.clone()));
+
+    harness
+        .success("initialize", json!({ "adapterID": "wayfinder", "supportsRunInTerminalRequest": true }))
+        .await;
+    let launch = json!({ "program": "/game/main.lua", "console": "integratedTerminal", "cwd": "/game" });
+    assert!(harness.server.handle_request("launch", &launch, 10).await.is_none(), "answered before the agent connected");
+    assert!(harness.runtime.launched_program().is_none());
+
+    let requests = harness.server.take_client_requests();
+    assert_eq!(requests.len(), 1);
+    let (command, arguments) = &requests[0];
+    assert_eq!(command, "runInTerminal");
+    assert_eq!((arguments["kind"].clone(), arguments["cwd"].clone()), (json!("integrated"), json!("/game")));
+    assert_eq!(arguments["args"][1], "agent");
+    assert_eq!(arguments["args"][4], "/game/main.lua");
+
+    // Breakpoints set while the client starts the program go to the agent
+    harness
+        .success("setBreakpoints", json!({ "source": { "path": "/game/main.lua" }, "breakpoints": [{ "line": 4 }] }))
+        .await;
+    harness.server.take_pending_events();
+    let response = harness
+        .server
+        .handle_client_response(&json!({ "type": "response", "command": "runInTerminal", "success": true, "body": {} }))
+        .await
+        .expect("the launch is answered");
+    assert_eq!(response, json!({ "id": 10, "result": {} }));
+    assert_eq!(agent.breakpoint_lines("/game/main.lua"), vec![4]);
+    let events: Vec<String> = harness.server.take_pending_events().into_iter().map(|event| event.event).collect();
+    assert_eq!(events, ["breakpoint", "breakpoint"]);
+
+    harness.success("configurationDone", json!({})).await;
+    assert_eq!(agent.resumes(), 1);
+}
+
+#[tokio::test]
+async fn test_terminal_launch_falls_back_to_the_debug_console() {
+    let mut harness = Harness::new();
+    harness.success("initialize", json!({ "adapterID": "wayfinder" })).await;
+    harness
+        .success("launch", json!({ "program": "/game/main.lua", "console": "integratedTerminal" }))
+        .await;
+    assert!(harness.server.take_client_requests().is_empty());
+    assert_eq!(harness.runtime.launched_program().unwrap().program, "/game/main.lua");
+}
+
+#[tokio::test]
+async fn test_settings_of_the_wrong_type_fail_launch_and_attach() {
+    let mut harness = Harness::new();
+    harness.success("initialize", json!({ "adapterID": "wayfinder" })).await;
+    let response = harness
+        .request("launch", json!({ "program": "/game/main.lua", "memoryLimitKb": "big" }))
+        .await;
+    assert_eq!(response["error"]["code"], INVALID_ARGUMENTS, "{}", response);
+    assert!(response["error"]["message"].as_str().unwrap().contains("launch"), "{}", response);
+
+    let response = harness.request("attach", json!({ "stitchCoroutineStacks": "yes" })).await;
+    assert_eq!(response["error"]["code"], INVALID_ARGUMENTS, "{}", response);
+    harness.success("attach", json!({ "stitchCoroutineStacks": true })).await;
+}
+
+#[tokio::test]
+async fn test_custom_requests_reject_arguments_of_the_wrong_type() {
+    let mut harness = Harness::new();
+    harness.success("initialize", json!({ "adapterID": "wayfinder" })).await;
+    for (command, arguments) in [
+        ("wayfinder/memory/allocations", json!({ "track": "yes" })),
+        ("wayfinder/memory/gc", json!({ "mode": "step", "stepKb": -4 })),
+        ("wayfinder/watches", json!({ "add": "player.hp" })),
+        ("breakpointInventory", json!({ "scope": "everything" })),
+        ("exceptionInfo", json!({ "threadId": "main" })),
+    ] {
+        let response = harness.request(command, arguments).await;
+        assert_eq!(response["error"]["code"], INVALID_ARGUMENTS, "{} {}", command, response);
+    }
+    let watches = harness.success("wayfinder/watches", json!({ "add": ["player.hp"] })).await;
+    assert_eq!(watches["watches"].as_array().map(Vec::len), Some(1));
+}
+
+#[tokio::test]
+async fn test_configure_changes_settings_mid_session() {
+    let mut harness = Harness::new();
+    let levels = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let set_levels = levels.clone();
+    harness.server.set_log_level_handler(move |level| {
+        set_levels.lock().unwrap().push(level.to_string());
+        Ok(())
+    });
+    harness.server.set_settings_loader(|| {
+        Ok(DebuggerSettings {
+            eval_safety: Some("none".to_string()),
+            max_string_length: Some(80),
+            log_level: Some("debug".to_string()),
+            ..Default::default()
+        })
+    });
+    harness.success("initialize", json!({})).await;
+    harness.success("launch", json!({ "program": "/game/main.lua" })).await;
+
+    let settings = harness
+        .success("wayfinder/configure", json!({ "evalSafety": "strict", "maxVariableChildren": 20 }))
+        .await;
+    assert_eq!(settings["evalSafety"], "strict");
+    assert_eq!(settings["maxVariableChildren"], 20);
+    assert_eq!(settings["stepGranularity"], "line");
+
+    // The request's own settings win over the reloaded file's
+    let settings = harness
+        .success("wayfinder/configure", json!({ "reload": true, "maxStringLength": 30 }))
+        .await;
+    assert_eq!(settings["evalSafety"], "none");
+    assert_eq!(settings["maxStringLength"], 30);
+    assert_eq!(settings["maxVariableChildren"], 20);
+    assert_eq!(*levels.lock().unwrap(), ["debug"]);
+
+    let response = harness
+        .request("wayfinder/configure", json!({ "evalSafety": "paranoid", "maxStringLength": 5 }))
+        .await;
+    assert!(response["error"]["message"].as_str().unwrap().contains("paranoid"));
+    let settings = harness.success("wayfinder/configure", json!({})).await;
+    assert_eq!(settings["maxStringLength"], 30);
+}
+
+#[tokio::test]
+async fn test_modules_are_listed_by_name_in_pages() {
+    let mut harness = Harness::new();
+    let module = |name: &str, path: Option<&str>| Module {
+        id: name.to_string(),
+        name: name.to_string(),
+        path: path.map(str::to_string),
+        load_time_ms: path.map(|_| 1_700_000_000_000),
+    };
+    harness.runtime.set_modules(vec![
+        module("string", None),
+        module("ui.menu", Some("/game/ui/menu.lua")),
+        module("player", Some("/game/player.lua")),
+    ]);
+    let capabilities = harness.success("initialize", json!({})).await;
+    assert_eq!(capabilities["supportsModulesRequest"], true);
+    harness.success("launch", json!({ "program": "/game/main.lua" })).await;
+
+    let result = harness.success("modules", json!({ "startModule": 1, "moduleCount": 1 })).await;
+    assert_eq!(result["totalModules"], 3);
+    assert_eq!(result["modules"], json!([{ "id": "string", "name": "string" }]));
+
+    let result = harness.success("modules", json!({})).await;
+    let modules = result["modules"].as_array().unwrap();
+    assert_eq!(modules[0]["path"], "/game/player.lua");
+    assert_eq!(modules[2]["loadTimeMs"], 1_700_000_000_000u64);
+}
+
+#[tokio::test]
+async fn test_goto_targets_are_lines_of_the_stopped_function() {
+    let mut harness = Harness::new();
+    harness.success("initialize", json!({})).await;
+    harness.success("launch", json!({ "program": "/game/main.lua" })).await;
+
+    // A runtime that cannot tell offers any line
+    let location = json!({ "source": { "path": "/game/main.lua" }, "line": 7 });
+    let result = harness.success("gotoTargets", location.clone()).await;
+    assert_eq!(result["targets"][0]["line"], 7);
+
+    harness.runtime.set_goto_lines(vec![2, 4, 5]);
+    let result = harness.success("gotoTargets", location).await;
+    assert_eq!(result["targets"], json!([]));
+    let result = harness.success("gotoTargets", json!({ "source": { "path": "/game/main.lua" }, "line": 4 })).await;
+    assert_eq!(result["targets"][0]["line"], 4);
+
+    // A target from before the program moved on is stale, not worth showing
+    let response = harness.request("goto", json!({ "threadId": MOCK_THREAD_ID, "targetId": 999 })).await;
+    assert_eq!(response["error"]["code"], error_code::GOTO_TARGET_NOT_FOUND);
+    assert_eq!(response["error"]["showUser"], false);
+}
+
+#[tokio::test]
+async fn test_hover_and_watch_never_call_or_assign() {
+    let mut harness = Harness::new();
+    harness.runtime.set_evaluation("save()", Value::Boolean(true));
+    harness.runtime.set_evaluation("hp", Value::Number(12.0));
+    harness.success("initialize", json!({})).await;
+    harness.success("launch", json!({ "program": "/game/main.lua" })).await;
+
+    for context in ["hover", "watch"] {
+        for expression in ["save()", "hp = 0"] {
+            let response = harness
+                .request("evaluate", json!({ "expression": expression, "frameId": 0, "context": context }))
+                .await;
+            let message = response["error"]["message"].as_str().unwrap_or_default();
+            assert!(message.contains(context), "{} in {}: {}", expression, context, response);
+        }
+    }
+    let result = harness.success("evaluate", json!({ "expression": "hp", "frameId": 0, "context": "hover" })).await;
+    assert_eq!(result["result"], "12");
+
+    // The console keeps the configured safety level
+    let result = harness.success("evaluate", json!({ "expression": "save()", "frameId": 0, "context": "repl" })).await;
+    assert_eq!(result["result"], "true");
+}
+
+#[tokio::test]
+async fn test_values_and_frames_follow_the_requested_format() {
+    let mut harness = Harness::new();
+    harness.runtime.set_evaluation("mask", Value::Number(255.0));
+    harness.runtime.set_evaluation("ratio", Value::Number(2.0 / 3.0));
+    let capabilities = harness.success("initialize", json!({})).await;
+    assert_eq!(capabilities["supportsValueFormattingOptions"], true);
+    harness.success("launch", json!({ "program": "/game/main.lua" })).await;
+
+    let evaluate = |expression: &str, format: JsonValue| json!({ "expression": expression, "frameId": 0, "format": format });
+    let result = harness.success("evaluate", evaluate("mask", json!({ "hex": true }))).await;
+    assert_eq!(result["result"], "0xff");
+    let result = harness.success("evaluate", evaluate("mask", json!({}))).await;
+    assert_eq!(result["result"], "255");
+
+    // The configured precision applies unless the request asks for another
+    harness.success("wayfinder/configure", json!({ "floatPrecision": 3 })).await;
+    let result = harness.success("evaluate", evaluate("ratio", json!({}))).await;
+    assert_eq!(result["result"], "0.667");
+    let result = harness.success("evaluate", evaluate("ratio", json!({ "precision": 1 }))).await;
+    assert_eq!(result["result"], "0.7");
+
+    let result = harness.success("stackTrace", json!({ "format": { "line": true, "module": true } })).await;
+    assert_eq!(result["stackFrames"][0]["name"], "main [main.lua] Line 1");
+}
+
+#[tokio::test]
+async fn test_clipboard_evaluation_copies_literals() {
+    let mut harness = Harness::new();
+    harness.runtime.set_evaluation("title", Value::String("Cave".to_string()));
+    let capabilities = harness.success("initialize", json!({})).await;
+    assert_eq!(capabilities["supportsClipboardContext"], true);
+    harness.success("launch", json!({ "program": "/game/main.lua" })).await;
+
+    let result = harness
+        .success("evaluate", json!({ "expression": "title", "frameId": 0, "context": "clipboard" }))
+        .await;
+    assert_eq!(result["result"], "\"Cave\"");
+    assert_eq!(result["variablesReference"], 0);
+}
+
+#[tokio::test]
+async fn test_memory_is_read_freely_but_written_only_when_allowed() {
+    let mut harness = Harness::new();
+    harness.runtime.set_memory("5", b"\x89PNG\r\n".to_vec());
+    let capabilities = harness.success("initialize", json!({ "supportsMemoryReferences": true })).await;
+    assert_eq!(capabilities["supportsReadMemoryRequest"], true);
+    harness.success("launch", json!({ "program": "/game/main.lua" })).await;
+
+    let read = harness.success("readMemory", json!({ "memoryReference": "5", "offset": 1, "count": 8 })).await;
+    assert_eq!(read["address"], "0x1");
+    assert_eq!(read["data"], "UE5HDQo=");
+    assert_eq!(read["unreadableBytes"], 3);
+
+    let write = json!({ "memoryReference": "5", "offset": 1, "data": "SlBH" });
+    let refused = harness.request("writeMemory", write.clone()).await;
+    assert_eq!(refused["error"]["code"], 2007);
+    assert_eq!(harness.runtime.memory("5").unwrap(), b"\x89PNG\r\n");
+
+    let mut harness = Harness::new();
+    harness.runtime.set_memory("5", b"\x89PNG\r\n".to_vec());
+    harness.success("initialize", json!({})).await;
+    harness.success("launch", json!({ "program": "/game/main.lua", "unsafeMemoryWrites": true })).await;
+    assert_eq!(harness.success("writeMemory", write).await["bytesWritten"], 3);
+    assert_eq!(harness.runtime.memory("5").unwrap(), b"\x89JPG\r\n");
+}
+
+#[tokio::test]
+async fn test_huge_tables_are_fetched_a_page_at_a_time() {
+    let mut harness = Harness::new();
+    let child = |name: String| Variable {
+        name,
+        value: "0".to_string(),
+        type_: "number".to_string(),
+        variables_reference: None,
+        named_variables: None,
+        indexed_variables: None,
+        memory_reference: None,
+    };
+    let mut children: Vec<Variable> = (1..=3000).map(|i| child(format!("[{}]", i))).collect();
+    children.push(child("size".to_string()));
+    children.push(child("[metatable]".to_string()));
+    harness.runtime.set_variables(7, children);
+    harness.success("initialize", json!({ "supportsVariablePaging": true })).await;
+    harness.success("launch", json!({ "program": "/game/main.lua" })).await;
+
+    let names = |result: JsonValue| -> Vec<String> {
+        result["variables"].as_array().unwrap().iter().map(|v| v["name"].as_str().unwrap().to_string()).collect()
+    };
+    let page = json!({ "variablesReference": 7, "filter": "indexed", "start": 2000, "count": 2 });
+    assert_eq!(names(harness.success("variables", page).await), ["[2001]", "[2002]"]);
+    let page = json!({ "variablesReference": 7, "filter": "named" });
+    assert_eq!(names(harness.success("variables", page).await), ["size", "[metatable]"]);
+
+    let result = harness.success("variables", json!({ "variablesReference": 0 })).await;
+    let player = result["variables"].as_array().unwrap().iter().find(|v| v["name"] == "player").cloned().unwrap();
+    assert_eq!(player["namedVariables"], 2);
+}
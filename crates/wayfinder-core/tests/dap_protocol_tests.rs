@@ -7,12 +7,12 @@ use wayfinder_core::session::DapServer;
 use serde_json::json;
 
 /// Test that the initialize request returns correct capabilities
-#[test]
-fn test_initialize_request() {
+#[tokio::test]
+async fn test_initialize_request() {
     let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
     
     let params = json!({});
-    let response = server.handle_initialize(1);
+    let response = server.handle_request("initialize", &params, 1).await.unwrap();
     
     // Check that we got a response
     assert_eq!(response["id"], 1);
@@ -27,6 +27,22 @@ fn test_initialize_request() {
     assert!(capabilities["supportsEvaluateForHovers"].as_bool().unwrap_or(false));
 }
 
+/// Test that initialize reports the Lua programs run on
+#[tokio::test]
+async fn test_initialize_reports_the_lua_backend() {
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(PUCLuaRuntime::new());
+
+    let response = server
+        .handle_request("initialize", &json!({ "adapterID": "wayfinder" }), 1)
+        .await
+        .unwrap();
+    let backend = &response["result"]["luaRuntime"];
+    assert_eq!(backend["mode"], "static");
+    assert_eq!(backend["version"], "5.4");
+    assert!(backend["library"].is_null());
+}
+
 /// Test that we can handle launch requests
 #[tokio::test]
 async fn test_launch_request() {
@@ -36,17 +52,29 @@ async fn test_launch_request() {
     let runtime = PUCLuaRuntime::new();
     server.set_runtime(runtime);
     
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("test.lua");
+    std::fs::write(&script, "local x = 1\n").unwrap();
     let params = json!({
         "noDebug": false,
-        "program": "test.lua"
+        "program": script.display().to_string()
     });
     
-    let response = server.handle_launch(1, &params).await;
+    let response = server.handle_request("launch", &params, 1).await;
     assert!(response.is_some());
     
     let response = response.unwrap();
     assert_eq!(response["id"], 1);
     assert!(response["result"].as_object().is_some());
+
+    // A program that cannot be loaded fails the launch
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(PUCLuaRuntime::new());
+    let response = server
+        .handle_request("launch", &json!({ "program": dir.path().join("missing.lua").display().to_string() }), 2)
+        .await
+        .unwrap();
+    assert!(response["error"]["message"].as_str().unwrap().contains("missing.lua"));
 }
 
 /// Test that we can handle setBreakpoints requests
@@ -74,7 +102,7 @@ async fn test_set_breakpoints_request() {
     });
     
     // This might fail if there's no actual script, but we're testing the protocol handling
-    let response = server.handle_set_breakpoints(1, &params).await;
+    let response = server.handle_request("setBreakpoints", &params, 1).await;
     // We just check that we got a response (even if it's an error)
     assert!(response.is_some());
 }
@@ -100,7 +128,7 @@ async fn test_set_function_breakpoints_request() {
         ]
     });
     
-    let response = server.handle_set_function_breakpoints(1, &params).await;
+    let response = server.handle_request("setFunctionBreakpoints", &params, 1).await;
     assert!(response.is_some());
 }
 
@@ -117,7 +145,7 @@ async fn test_set_exception_breakpoints_request() {
         "filters": ["raised", "uncaught"]
     });
     
-    let response = server.handle_set_exception_breakpoints(1, &params).await;
+    let response = server.handle_request("setExceptionBreakpoints", &params, 1).await;
     assert!(response.is_some());
 }
 
@@ -134,7 +162,7 @@ async fn test_stack_trace_request() {
         "threadId": 1
     });
     
-    let response = server.handle_stack_trace(1, &params).await;
+    let response = server.handle_request("stackTrace", &params, 1).await;
     // Should get a response (might be error if no debug session)
     assert!(response.is_some());
 }
@@ -152,7 +180,7 @@ async fn test_scopes_request() {
         "frameId": 1
     });
     
-    let response = server.handle_scopes(1, &params).await;
+    let response = server.handle_request("scopes", &params, 1).await;
     assert!(response.is_some());
 }
 
@@ -169,7 +197,7 @@ async fn test_variables_request() {
         "variablesReference": 1
     });
     
-    let response = server.handle_variables(1, &params).await;
+    let response = server.handle_request("variables", &params, 1).await;
     assert!(response.is_some());
 }
 
@@ -187,6 +215,962 @@ async fn test_evaluate_request() {
         "frameId": 1
     });
     
-    let response = server.handle_evaluate(1, &params).await;
+    let response = server.handle_request("evaluate", &params, 1).await;
     assert!(response.is_some());
-}
\ No newline at end of file
+}
+
+/// Test that sources in a configured legacy encoding are served as UTF-8
+#[tokio::test]
+async fn test_source_request_decodes_configured_encoding() {
+    let mut server: DapServer<wayfinder_core::runtime::mock::MockRuntime> = DapServer::new();
+    server.set_runtime(wayfinder_core::runtime::mock::MockRuntime::new());
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("main.lua");
+    // "-- 日本" saved as Shift-JIS
+    std::fs::write(&path, b"-- \x93\xfa\x96\x7b\n").unwrap();
+
+    let response = server
+        .handle_request("launch", &json!({ "sourceEncoding": "shift_jis" }), 1)
+        .await
+        .unwrap();
+    assert!(response.get("error").is_none());
+
+    let params = json!({ "source": { "path": path.to_str().unwrap() } });
+    let response = server.handle_request("source", &params, 2).await.unwrap();
+    assert_eq!(response["result"]["content"], "-- \u{65e5}\u{672c}\n");
+
+    let response = server
+        .handle_request("launch", &json!({ "sourceEncoding": "klingon" }), 3)
+        .await
+        .unwrap();
+    assert!(response["error"]["message"].as_str().unwrap().contains("Unknown encoding"));
+}
+
+/// Test that failed requests carry the error's stable code and whether to show it
+#[tokio::test]
+async fn test_runtime_errors_carry_codes() {
+    use wayfinder_core::runtime::error_code;
+
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(PUCLuaRuntime::new());
+
+    let response = server.handle_request("evaluate", &json!({ "expression": "1 +" }), 1).await.unwrap();
+    assert_eq!(response["error"]["code"], error_code::EVAL_SYNTAX);
+    assert_eq!(response["error"]["showUser"], false);
+
+    let response = server.handle_request("source", &json!({ "sourceReference": 42 }), 2).await.unwrap();
+    assert_eq!(response["error"]["code"], error_code::SOURCE_NOT_FOUND);
+    assert!(response["error"]["message"].as_str().unwrap().contains("Unknown source reference 42"));
+}
+
+/// Test that a request with malformed arguments is answered with an error instead of dropped
+#[tokio::test]
+async fn test_malformed_arguments_get_error_response() {
+    use wayfinder_core::dap::arguments::INVALID_ARGUMENTS;
+
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(PUCLuaRuntime::new());
+
+    let response = server.handle_request("scopes", &json!({}), 1).await.unwrap();
+    assert_eq!(response["error"]["code"], INVALID_ARGUMENTS);
+    assert!(response["error"]["message"].as_str().unwrap().contains("frameId"));
+
+    let params = json!({ "source": { "path": "main.lua" }, "breakpoints": [{ "line": "ten" }] });
+    let response = server.handle_request("setBreakpoints", &params, 2).await.unwrap();
+    assert_eq!(response["error"]["code"], INVALID_ARGUMENTS);
+
+    let response = server.handle_request("setBreakpoints", &json!({ "source": {} }), 3).await.unwrap();
+    assert_eq!(response["error"]["code"], INVALID_ARGUMENTS);
+}
+
+/// Test that state changes raised by the runtime reach the client as events
+#[tokio::test]
+async fn test_pause_emits_stopped_event() {
+    use tokio::io::BufReader;
+    use wayfinder_core::dap::transport::DapTransport;
+
+    let (client, server_end) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut client = DapTransport::new(BufReader::new(client_read), client_write);
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+
+    let server_task = tokio::spawn(async move {
+        let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+        server.set_runtime(PUCLuaRuntime::new());
+        server.run_event_loop(&mut transport).await.unwrap();
+    });
+
+    client
+        .write_message(&json!({ "seq": 1, "type": "request", "command": "pause" }))
+        .await
+        .unwrap();
+
+    let response = client.read_message().await.unwrap().unwrap();
+    assert_eq!(response["type"], "response");
+    assert_eq!(response["request_seq"], 1);
+    assert_eq!(response["command"], "pause");
+    assert_eq!(response["success"], true);
+    assert_eq!(response["seq"], 1);
+
+    let event = client.read_message().await.unwrap().unwrap();
+    assert_eq!(event["type"], "event");
+    assert_eq!(event["seq"], 2);
+    assert_eq!(event["event"], "stopped");
+    assert_eq!(event["body"]["reason"], "pause");
+
+    client
+        .write_message(&json!({ "seq": 2, "type": "request", "command": "disconnect" }))
+        .await
+        .unwrap();
+    server_task.await.unwrap();
+}
+
+/// Test that a message that is not JSON is answered with an error and the session goes on
+#[tokio::test]
+async fn test_invalid_json_gets_error_response() {
+    use tokio::io::{AsyncWriteExt, BufReader};
+    use wayfinder_core::dap::transport::{DapTransport, INVALID_JSON};
+
+    let (client, server_end) = tokio::io::duplex(4096);
+    let (client_read, mut client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut client = DapTransport::new(BufReader::new(client_read), Vec::new());
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+
+    let server_task = tokio::spawn(async move {
+        let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+        server.set_runtime(PUCLuaRuntime::new());
+        server.run_event_loop(&mut transport).await.unwrap();
+    });
+
+    client_write.write_all(b"Content-Length: 9\r\n\r\n{not json").await.unwrap();
+    let response = client.read_message().await.unwrap().unwrap();
+    assert_eq!(response["success"], false);
+    assert_eq!(response["body"]["error"]["id"], INVALID_JSON);
+
+    let disconnect = r#"{"seq":2,"type":"request","command":"disconnect"}"#;
+    let frame = format!("Content-Length: {}\r\n\r\n{}", disconnect.len(), disconnect);
+    client_write.write_all(frame.as_bytes()).await.unwrap();
+    let response = client.read_message().await.unwrap().unwrap();
+    assert_eq!(response["command"], "disconnect");
+    server_task.await.unwrap();
+}
+
+/// Test that goto targets resolve back to the requested location
+#[tokio::test]
+async fn test_goto_targets_request() {
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(PUCLuaRuntime::new());
+
+    let params = json!({ "source": { "path": "main.lua" }, "line": 12 });
+    let response = server.handle_request("gotoTargets", &params, 1).await.unwrap();
+    let target = &response["result"]["targets"][0];
+    assert_eq!(target["line"], 12);
+
+    let again = server.handle_request("gotoTargets", &params, 2).await.unwrap();
+    assert_eq!(again["result"]["targets"][0]["id"], target["id"]);
+
+    let response = server
+        .handle_request("goto", &json!({ "targetId": target["id"] }), 3)
+        .await
+        .unwrap();
+    assert!(response.get("error").is_none());
+
+    let response = server
+        .handle_request("goto", &json!({ "targetId": 99 }), 4)
+        .await
+        .unwrap();
+    assert!(response.get("error").is_some());
+}
+
+/// Test that stackTrace pages with startFrame and levels but reports the full depth
+#[tokio::test]
+async fn test_stack_trace_paging() {
+    let mut server: DapServer<wayfinder_core::runtime::mock::MockRuntime> = DapServer::new();
+    server.set_runtime(wayfinder_core::runtime::mock::MockRuntime::new());
+
+    let response = server
+        .handle_request("stackTrace", &json!({ "threadId": 1, "startFrame": 0, "levels": 1 }), 1)
+        .await
+        .unwrap();
+    assert_eq!(response["result"]["stackFrames"].as_array().unwrap().len(), 1);
+    assert_eq!(response["result"]["totalFrames"], 1);
+
+    let response = server
+        .handle_request("stackTrace", &json!({ "threadId": 1, "startFrame": 1 }), 2)
+        .await
+        .unwrap();
+    assert!(response["result"]["stackFrames"].as_array().unwrap().is_empty());
+    assert_eq!(response["result"]["totalFrames"], 1);
+}
+
+/// Test that the breakpoint inventory lists what was set, as a request and a console command
+#[tokio::test]
+async fn test_breakpoint_inventory() {
+    let mut server: DapServer<wayfinder_core::runtime::mock::MockRuntime> = DapServer::new();
+    server.set_runtime(wayfinder_core::runtime::mock::MockRuntime::new());
+
+    server
+        .handle_request(
+            "setBreakpoints",
+            &json!({ "source": { "path": "/game/main.lua" }, "breakpoints": [{ "line": 7, "condition": "hp < 0" }] }),
+            1,
+        )
+        .await
+        .unwrap();
+
+    let response = server
+        .handle_request("breakpointInventory", &json!({ "scope": "breakpoints" }), 2)
+        .await
+        .unwrap();
+    let breakpoints = response["result"]["breakpoints"].as_array().unwrap();
+    assert_eq!(breakpoints.len(), 1);
+    assert_eq!(breakpoints[0]["location"], "/game/main.lua:7");
+    assert_eq!(breakpoints[0]["verified"], true);
+    assert!(response["result"]["table"].as_str().unwrap().contains("if hp < 0"));
+
+    let response = server
+        .handle_request("evaluate", &json!({ "expression": ".exceptions", "context": "repl" }), 3)
+        .await
+        .unwrap();
+    assert_eq!(response["result"]["result"], "Exception filters: none");
+
+    let response = server
+        .handle_request("evaluate", &json!({ "expression": ".frames", "context": "repl" }), 4)
+        .await
+        .unwrap();
+    assert!(response["error"]["message"].as_str().unwrap().contains(".breakpoints"));
+}
+
+/// Test that setBreakpoints replaces the source's breakpoints and an empty list clears them
+#[tokio::test]
+async fn test_set_breakpoints_replaces_previous_set() {
+    let runtime = wayfinder_core::runtime::mock::MockRuntime::new();
+    let mut server: DapServer<wayfinder_core::runtime::mock::MockRuntime> = DapServer::new();
+    server.set_runtime(runtime.clone());
+
+    let set = |lines: &[u32]| {
+        json!({
+            "source": { "path": "/game/main.lua" },
+            "breakpoints": lines.iter().map(|line| json!({ "line": line })).collect::<Vec<_>>(),
+        })
+    };
+
+    server.handle_request("setBreakpoints", &set(&[3, 7]), 1).await.unwrap();
+    assert_eq!(runtime.breakpoint_lines("/game/main.lua"), vec![3, 7]);
+
+    let response = server.handle_request("setBreakpoints", &set(&[7]), 2).await.unwrap();
+    assert_eq!(response["result"]["breakpoints"].as_array().unwrap().len(), 1);
+    assert_eq!(runtime.breakpoint_lines("/game/main.lua"), vec![7]);
+
+    server.handle_request("setBreakpoints", &set(&[]), 3).await.unwrap();
+    assert!(runtime.breakpoint_lines("/game/main.lua").is_empty());
+
+    let response = server.handle_request("breakpointInventory", &json!({}), 4).await.unwrap();
+    assert!(response["result"]["breakpoints"].as_array().unwrap().is_empty());
+}
+
+/// Test that profiling can be started, inspected and stopped mid-session
+#[tokio::test]
+async fn test_profiling_requests() {
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(PUCLuaRuntime::new());
+
+    let response = server
+        .handle_request("wayfinder/startProfiling", &json!({ "mode": "callTrace" }), 1)
+        .await
+        .unwrap();
+    assert_eq!(response["result"]["started"], true);
+
+    let response = server.handle_request("wayfinder/startProfiling", &json!({}), 2).await.unwrap();
+    assert!(response["error"]["message"].as_str().unwrap().contains("already running"));
+
+    let response = server.handle_request("wayfinder/profileSnapshot", &json!({}), 3).await.unwrap();
+    assert_eq!(response["result"]["mode"], "callTrace");
+
+    let response = server.handle_request("wayfinder/stopProfiling", &json!({}), 4).await.unwrap();
+    assert!(response["result"]["functions"].is_array());
+    assert!(response["result"]["stacks"].is_array());
+
+    let response = server.handle_request("wayfinder/profileSnapshot", &json!({}), 5).await.unwrap();
+    assert_eq!(response["error"]["message"], "No active profiler");
+}
+
+/// Test that the collector can be inspected, run and tuned through custom requests
+#[tokio::test]
+async fn test_memory_requests() {
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(PUCLuaRuntime::new());
+
+    // Lua 5.4 stores the pause in steps of 4, so the one it reports is rounded down
+    let response = server.handle_request("wayfinder/memory/tune", &json!({ "pause": 150 }), 1).await.unwrap();
+    let pause = response["result"]["gcPause"].as_i64().unwrap();
+    assert_eq!(pause % 4, 0);
+    assert!((147..=150).contains(&pause));
+
+    // Reading the settings leaves them as they are
+    let response = server.handle_request("wayfinder/memory/stats", &json!({}), 2).await.unwrap();
+    assert_eq!(response["result"]["gcPause"], pause);
+    let step_mul = response["result"]["gcStepMul"].clone();
+    let response = server.handle_request("wayfinder/memory/stats", &json!({}), 3).await.unwrap();
+    assert_eq!(response["result"]["gcStepMul"], step_mul);
+
+    let response = server.handle_request("wayfinder/memory/gc", &json!({}), 4).await.unwrap();
+    assert_eq!(response["result"]["cycleFinished"], true);
+    assert!(response["result"]["totalKB"].as_f64().unwrap() > 0.0);
+
+    let response = server
+        .handle_request("wayfinder/memory/gc", &json!({ "mode": "step", "stepKb": 1 }), 5)
+        .await
+        .unwrap();
+    assert!(response["result"]["cycleFinished"].is_boolean());
+
+    let response = server.handle_request("wayfinder/memory/tune", &json!({}), 6).await.unwrap();
+    assert_eq!(response["error"]["message"], "Missing pause or stepMul");
+}
+
+/// Test that edits to the breakpoint file reach the client as breakpoint events
+#[tokio::test]
+async fn test_breakpoint_file_is_synchronized() {
+    use tokio::io::BufReader;
+    use wayfinder_core::dap::transport::DapTransport;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join(".wayfinder-breakpoints.json");
+    let game = dir.path().join("game.lua").display().to_string();
+
+    let (client, server_end) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut client = DapTransport::new(BufReader::new(client_read), client_write);
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+
+    let watched = file.clone();
+    let server_task = tokio::spawn(async move {
+        let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+        server.set_runtime(PUCLuaRuntime::new());
+        server.watch_breakpoint_file(watched);
+        server.run_event_loop(&mut transport).await.unwrap();
+    });
+
+    std::fs::write(&file, r#"{ "breakpoints": { "game.lua": [{ "line": 4 }] } }"#).unwrap();
+    let event = client.read_message().await.unwrap().unwrap();
+    assert_eq!(event["event"], "breakpoint");
+    assert_eq!(event["body"]["reason"], "new");
+    assert_eq!(event["body"]["breakpoint"]["line"], 4);
+    assert_eq!(event["body"]["breakpoint"]["source"]["path"], game.as_str());
+    let id = event["body"]["breakpoint"]["id"].clone();
+
+    std::fs::write(&file, r#"{ "breakpoints": {} }"#).unwrap();
+    let event = client.read_message().await.unwrap().unwrap();
+    assert_eq!(event["body"]["reason"], "removed");
+    assert_eq!(event["body"]["breakpoint"]["id"], id);
+
+    client
+        .write_message(&json!({ "seq": 1, "type": "request", "command": "disconnect" }))
+        .await
+        .unwrap();
+    server_task.await.unwrap();
+}
+
+/// Test that saving a required module reloads it into the running program
+#[cfg(feature = "hot-reload")]
+#[tokio::test]
+async fn test_changed_modules_are_hot_reloaded() {
+    use tokio::io::BufReader;
+    use wayfinder_core::dap::transport::DapTransport;
+    use wayfinder_core::hot_reload::WatcherConfig;
+
+    let dir = tempfile::tempdir().unwrap();
+    let main = dir.path().join("main.lua");
+    let counter = dir.path().join("counter.lua");
+    std::fs::write(&counter, "local M = { hits = 0 }\nfunction M.describe() return 'old' end\nreturn M\n").unwrap();
+    std::fs::write(&main, "counter = require('counter')\ncounter.hits = 5\n").unwrap();
+
+    let (client, server_end) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut client = DapTransport::new(BufReader::new(client_read), client_write);
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+
+    let config = WatcherConfig {
+        debounce: std::time::Duration::ZERO,
+        ..WatcherConfig::new(dir.path())
+    };
+    let server_task = tokio::spawn(async move {
+        let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+        server.set_runtime(PUCLuaRuntime::new());
+        server.watch_for_hot_reload(config);
+        server.run_event_loop(&mut transport).await.unwrap();
+    });
+
+    let requests = [
+        json!({ "seq": 1, "type": "request", "command": "launch", "arguments": { "program": main.display().to_string() } }),
+        json!({ "seq": 2, "type": "request", "command": "configurationDone" }),
+    ];
+    for request in requests {
+        let id = request["seq"].clone();
+        client.write_message(&request).await.unwrap();
+        while client.read_message().await.unwrap().unwrap()["request_seq"] != id {}
+    }
+
+    // Editing the main script runs nothing, as it is no module
+    std::fs::write(&main, "counter = require('counter')\ncounter.hits = 0\n").unwrap();
+    std::fs::write(&counter, "local M = { hits = 0 }\nfunction M.describe() return 'new' end\nreturn M\n").unwrap();
+    let summary = loop {
+        let message = client.read_message().await.unwrap().unwrap();
+        let output = message["body"]["output"].as_str().unwrap_or_default().to_string();
+        if output.contains("Hot reload") {
+            break output;
+        }
+    };
+    assert!(summary.contains("Module 'counter' reloaded"), "{}", summary);
+    assert!(summary.contains("counter.lua"), "{}", summary);
+
+    let evaluate = json!({
+        "seq": 3, "type": "request", "command": "evaluate",
+        "arguments": { "expression": "counter.describe() .. counter.hits" }
+    });
+    client.write_message(&evaluate).await.unwrap();
+    let response = loop {
+        let message = client.read_message().await.unwrap().unwrap();
+        if message["request_seq"] == 3 {
+            break message;
+        }
+    };
+    assert_eq!(response["body"]["result"], "\"new5\"");
+
+    client
+        .write_message(&json!({ "seq": 4, "type": "request", "command": "disconnect" }))
+        .await
+        .unwrap();
+    server_task.await.unwrap();
+}
+
+/// Test that editors can reload a module by its name or its file
+#[cfg(feature = "hot-reload")]
+#[tokio::test]
+async fn test_hot_reload_request() {
+    let dir = tempfile::tempdir().unwrap();
+    let main = dir.path().join("main.lua");
+    let counter = dir.path().join("counter.lua");
+    std::fs::write(&counter, "local M = { hits = 0 }\nfunction M.describe() return 'old' end\nreturn M\n").unwrap();
+    std::fs::write(&main, "counter = require('counter')\ncounter.hits = 5\n").unwrap();
+
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(PUCLuaRuntime::new());
+    server.handle_request("launch", &json!({ "program": main.display().to_string() }), 1).await.unwrap();
+    server.handle_request("configurationDone", &json!({}), 2).await.unwrap();
+
+    std::fs::write(&counter, "local M = { hits = 0 }\nfunction M.describe() return 'new' end\nreturn M\n").unwrap();
+    let response = server
+        .handle_request("wayfinder/hotReload", &json!({ "path": counter.display().to_string() }), 3)
+        .await
+        .unwrap();
+    let result = &response["result"];
+    assert_eq!(result["success"], true, "{}", response);
+    assert_eq!(result["module"], "counter");
+    let hits = result["fields"].as_array().unwrap().iter().find(|f| f["field"] == "hits").unwrap();
+    assert_eq!(hits["outcome"], "preserved");
+    let events = server.take_pending_events();
+    let output = |e: &wayfinder_core::Event| e.body.as_ref().and_then(|b| b["output"].as_str()).map(str::to_string);
+    assert!(events.iter().filter_map(output).any(|line| line.contains("Hot reload")));
+
+    let response = server
+        .handle_request("wayfinder/hotReload", &json!({ "module": "counter" }), 4)
+        .await
+        .unwrap();
+    assert!(response["result"]["path"].as_str().unwrap().ends_with("counter.lua"), "{}", response);
+
+    let response = server
+        .handle_request("wayfinder/hotReload", &json!({ "module": "missing" }), 5)
+        .await
+        .unwrap();
+    assert_eq!(response["error"]["message"], "Module 'missing' is not found on package.path");
+}
+
+/// Test that watch evaluations report whether the value changed since the previous stop
+#[tokio::test]
+async fn test_watch_status_across_stops() {
+    use tokio::io::BufReader;
+    use wayfinder_core::dap::transport::DapTransport;
+
+    let (client, server_end) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut client = DapTransport::new(BufReader::new(client_read), client_write);
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+
+    let server_task = tokio::spawn(async move {
+        let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+        server.set_runtime(PUCLuaRuntime::new());
+        server.run_event_loop(&mut transport).await.unwrap();
+    });
+
+    let mut seq = 0;
+    let mut request = |command: &str, arguments: serde_json::Value| {
+        seq += 1;
+        (seq, json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments }))
+    };
+    let watch = json!({ "expression": "counter", "context": "watch" });
+    let mut statuses = Vec::new();
+    for counter in [1, 1, 2] {
+        for (command, arguments) in [
+            ("evaluate", json!({ "expression": format!("(function() counter = {} end)()", counter) })),
+            ("pause", json!({})),
+            ("evaluate", watch.clone()),
+        ] {
+            let is_watch = arguments == watch;
+            let (id, message) = request(command, arguments);
+            client.write_message(&message).await.unwrap();
+            // Events such as stopped arrive between the responses
+            let response = loop {
+                let message = client.read_message().await.unwrap().unwrap();
+                if message["request_seq"] == id {
+                    break message;
+                }
+            };
+            if is_watch {
+                statuses.push(response["body"]["watchStatus"].clone());
+            }
+        }
+    }
+    assert_eq!(statuses, vec![json!("new"), json!("unchanged"), json!("changed")]);
+
+    let (_, message) = request("disconnect", json!({}));
+    client.write_message(&message).await.unwrap();
+    server_task.await.unwrap();
+}
+
+/// Test that the debug console completes globals and table fields
+#[tokio::test]
+async fn test_completions_request() {
+    let mut server: DapServer<wayfinder_core::runtime::mock::MockRuntime> = DapServer::new();
+    server.set_runtime(wayfinder_core::runtime::mock::MockRuntime::new());
+    assert_eq!(server.handle_request("initialize", &json!({}), 1).await.unwrap()["result"]["supportsCompletionsRequest"], true);
+
+    let response = server
+        .handle_request("completions", &json!({ "text": "pla", "column": 4, "frameId": 0 }), 2)
+        .await
+        .unwrap();
+    let targets = response["result"]["targets"].as_array().unwrap();
+    assert_eq!(targets[0]["label"], "player");
+    assert_eq!(targets[0]["type"], "variable");
+    assert_eq!(targets[0]["start"], 1);
+    assert_eq!(targets[0]["length"], 3);
+
+    let response = server
+        .handle_request("completions", &json!({ "text": "print(player.h", "frameId": 0 }), 3)
+        .await
+        .unwrap();
+    let labels: Vec<_> = response["result"]["targets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["label"].as_str().unwrap())
+        .collect();
+    assert_eq!(labels, vec!["heal", "hp"]);
+
+    let response = server
+        .handle_request("completions", &json!({ "text": "player:", "frameId": 0 }), 4)
+        .await
+        .unwrap();
+    assert_eq!(response["result"]["targets"].as_array().unwrap().len(), 1);
+    assert_eq!(response["result"]["targets"][0]["type"], "function");
+
+    // Without a frame only keywords are offered
+    let response = server
+        .handle_request("completions", &json!({ "text": "re" }), 5)
+        .await
+        .unwrap();
+    let labels: Vec<_> = response["result"]["targets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["label"].as_str().unwrap())
+        .collect();
+    assert_eq!(labels, vec!["repeat", "return"]);
+}
+
+/// Test that the internals request reports the Lua lock without holding it
+#[tokio::test]
+async fn test_internals_request() {
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(PUCLuaRuntime::new());
+    server.handle_request("evaluate", &json!({ "expression": "1 + 1" }), 1).await.unwrap();
+
+    let response = server.handle_request("wayfinder/internals", &json!({}), 2).await.unwrap();
+    let locks = response["result"]["locks"].as_array().unwrap();
+    assert_eq!(locks.len(), 1);
+    assert_eq!(locks[0]["name"], "lua");
+    assert!(locks[0]["holder"].is_null());
+    assert!(locks[0]["acquisitions"].as_u64().unwrap() > 0);
+    assert!(response["result"]["tasks"].is_array());
+}
+
+/// Test that logpoints log through output events in the breakpoint's frame without stopping
+#[tokio::test]
+async fn test_logpoint_output_events() {
+    use tokio::io::BufReader;
+    use wayfinder_core::dap::transport::DapTransport;
+
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("logpoints.lua");
+    std::fs::write(&script, "local function heal(hp)\n  hp = hp + 3\n  return hp\nend\nheal(4)\n").unwrap();
+    let path = script.display().to_string();
+
+    let (client, server_end) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut client = DapTransport::new(BufReader::new(client_read), client_write);
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+
+    let program = path.clone();
+    let server_task = tokio::spawn(async move {
+        let mut runtime = PUCLuaRuntime::new();
+        runtime.load_program(&program).unwrap();
+        let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+        server.set_runtime(runtime);
+        server.run_event_loop(&mut transport).await.unwrap();
+    });
+
+    for (seq, command, arguments) in [
+        (
+            1,
+            "setBreakpoints",
+            json!({
+                "source": { "path": path },
+                "breakpoints": [
+                    { "line": 3, "logMessage": "hp={hp} table={ ({hp})[1] } bad={nope.x}" },
+                    { "line": 2, "condition": "hp > 10" }
+                ]
+            }),
+        ),
+        (2, "configurationDone", json!({})),
+    ] {
+        client
+            .write_message(&json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments }))
+            .await
+            .unwrap();
+    }
+
+    let mut outputs = Vec::new();
+    loop {
+        let message = client.read_message().await.unwrap().unwrap();
+        assert_ne!(message["event"], "stopped", "a logpoint or false condition stopped the program");
+        match message["event"].as_str() {
+            Some("output") if message["body"]["category"] == "console" => outputs.push(message["body"].clone()),
+            Some("terminated") => break,
+            _ => {}
+        }
+    }
+    assert_eq!(outputs.len(), 1);
+    let output = outputs[0]["output"].as_str().unwrap();
+    assert!(output.starts_with("hp=7 table=7 bad=<error:"), "{}", output);
+    assert_eq!(outputs[0]["source"]["path"], path.as_str());
+    assert_eq!(outputs[0]["line"], 3);
+
+    client
+        .write_message(&json!({ "seq": 3, "type": "request", "command": "disconnect" }))
+        .await
+        .unwrap();
+    server_task.await.unwrap();
+}
+
+/// Test that a restart runs the program again from the start with its breakpoints
+#[tokio::test]
+async fn test_restart_request() {
+    use tokio::io::BufReader;
+    use wayfinder_core::dap::transport::DapTransport;
+
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("restart.lua");
+    std::fs::write(&script, "counter = (counter or 0) + 1\nlocal done = true\n").unwrap();
+    let path = script.display().to_string();
+
+    let (client, server_end) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut client = DapTransport::new(BufReader::new(client_read), client_write);
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+
+    let program = path.clone();
+    let server_task = tokio::spawn(async move {
+        let mut runtime = PUCLuaRuntime::new();
+        runtime.load_program(&program).unwrap();
+        let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+        server.set_runtime(runtime);
+        server.run_event_loop(&mut transport).await.unwrap();
+    });
+
+    let mut seq = 0;
+    let mut send = |command: &str, arguments: serde_json::Value| {
+        seq += 1;
+        json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments })
+    };
+    for message in [
+        send("launch", json!({ "program": path })),
+        send("setBreakpoints", json!({ "source": { "path": path }, "breakpoints": [{ "line": 2 }] })),
+        send("configurationDone", json!({})),
+    ] {
+        client.write_message(&message).await.unwrap();
+    }
+
+    // The program must not end while it is being restarted
+    macro_rules! wait_for {
+        ($event:expr) => {
+            loop {
+                let message = client.read_message().await.unwrap().unwrap();
+                assert_ne!(message["event"], "terminated", "the program ended while waiting for {}", $event);
+                if message["event"] == $event {
+                    break message;
+                }
+            }
+        };
+    }
+    let stopped = wait_for!("stopped");
+    assert_eq!(stopped["body"]["reason"], "breakpoint");
+
+    client.write_message(&send("restart", json!({}))).await.unwrap();
+    wait_for!("initialized");
+    client.write_message(&send("configurationDone", json!({}))).await.unwrap();
+    let stopped = wait_for!("stopped");
+    assert_eq!(stopped["body"]["reason"], "breakpoint");
+
+    // The new state starts without the globals of the first run
+    client
+        .write_message(&send("evaluate", json!({ "expression": "counter", "context": "repl" })))
+        .await
+        .unwrap();
+    let response = loop {
+        let message = client.read_message().await.unwrap().unwrap();
+        if message["type"] == "response" {
+            break message;
+        }
+    };
+    assert_eq!(response["body"]["result"], "1");
+
+    client.write_message(&send("disconnect", json!({}))).await.unwrap();
+    server_task.await.unwrap();
+}
+
+/// Test that a session recorded at the transport replays against a fresh server
+#[tokio::test]
+async fn test_recorded_session_replays() {
+    use tokio::io::BufReader;
+    use wayfinder_core::dap::transport::DapTransport;
+    use wayfinder_core::session::replay;
+    use wayfinder_core::session::trace::{self, Direction, TraceRecorder};
+
+    let dir = tempfile::tempdir().unwrap();
+    let recording = dir.path().join("session.jsonl");
+
+    let (client, server_end) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut client = DapTransport::new(BufReader::new(client_read), client_write);
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+    transport.set_recorder(TraceRecorder::create(&recording).unwrap());
+
+    let server_task = tokio::spawn(async move {
+        let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+        server.set_runtime(PUCLuaRuntime::new());
+        server.run_event_loop(&mut transport).await.unwrap();
+    });
+
+    let requests = [
+        json!({ "seq": 1, "type": "request", "command": "pause" }),
+        json!({ "seq": 2, "type": "request", "command": "threads" }),
+        json!({ "seq": 3, "type": "request", "command": "goto", "arguments": { "targetId": 99 } }),
+    ];
+    for request in &requests {
+        client.write_message(request).await.unwrap();
+        while client.read_message().await.unwrap().unwrap()["request_seq"] != request["seq"] {}
+    }
+    client
+        .write_message(&json!({ "seq": 4, "type": "request", "command": "disconnect" }))
+        .await
+        .unwrap();
+    server_task.await.unwrap();
+
+    let mut entries = trace::read_recording(&recording).unwrap();
+    assert_eq!(entries.iter().filter(|entry| entry.direction == Direction::Received).count(), 4);
+    assert!(entries.iter().any(|entry| entry.message["event"] == "stopped"));
+
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(PUCLuaRuntime::new());
+    let report = replay::replay(&mut server, &entries, replay::DEFAULT_TIMEOUT).await.unwrap();
+    assert_eq!(report.requests, 4);
+    assert!(report.matches(), "{:?}", report.divergences);
+    assert!(report.transcript.iter().any(|entry| entry.message["event"] == "stopped"));
+
+    // A recording where goto succeeded no longer matches
+    let goto = entries
+        .iter_mut()
+        .find(|entry| entry.direction == Direction::Sent && entry.message["request_seq"] == 3)
+        .unwrap();
+    goto.message["success"] = json!(true);
+    let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+    server.set_runtime(PUCLuaRuntime::new());
+    let report = replay::replay(&mut server, &entries, replay::DEFAULT_TIMEOUT).await.unwrap();
+    assert_eq!(report.divergences.len(), 1);
+    assert_eq!((report.divergences[0].seq, report.divergences[0].command.as_str()), (3, "goto"));
+    assert_eq!(report.divergences[0].recorded, "success");
+}
+
+/// Test that a launch request loads its program with its arguments, search paths and defaults
+#[tokio::test]
+async fn test_launch_arguments_reach_the_program() {
+    use tokio::io::BufReader;
+    use wayfinder_core::dap::transport::DapTransport;
+    use wayfinder_core::session::launch_arguments::LaunchArguments;
+
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("args.lua");
+    std::fs::write(&script, "print(arg[1], arg[2], package.path, os.getenv(\"WAYFINDER_TEST_MODE\"))\n").unwrap();
+    let path = script.display().to_string();
+
+    let (client, server_end) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut client = DapTransport::new(BufReader::new(client_read), client_write);
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+
+    let server_task = tokio::spawn(async move {
+        let mut server: DapServer<PUCLuaRuntime> = DapServer::new();
+        server.set_runtime_factory(|_| Ok(PUCLuaRuntime::new()));
+        server.set_launch_defaults(LaunchArguments {
+            env: std::collections::HashMap::from([("WAYFINDER_TEST_MODE".to_string(), "replay".to_string())]),
+            ..Default::default()
+        });
+        server.run_event_loop(&mut transport).await.unwrap();
+    });
+
+    for message in [
+        json!({ "seq": 1, "type": "request", "command": "launch", "arguments": {
+            "program": path, "args": ["fast", "7"], "luaPath": "./lib/?.lua"
+        } }),
+        json!({ "seq": 2, "type": "request", "command": "configurationDone" }),
+    ] {
+        client.write_message(&message).await.unwrap();
+    }
+
+    let output = loop {
+        let message = client.read_message().await.unwrap().unwrap();
+        assert_ne!(message["success"], false, "{}", message);
+        if message["event"] == "output" && message["body"]["category"] == "stdout" {
+            break message["body"]["output"].as_str().unwrap().to_string();
+        }
+    };
+    let dir = dir.path().display();
+    assert_eq!(output, format!("fast\t7\t{0}/?.lua;{0}/?/init.lua;./lib/?.lua\treplay\n", dir));
+
+    client
+        .write_message(&json!({ "seq": 3, "type": "request", "command": "disconnect" }))
+        .await
+        .unwrap();
+    server_task.await.unwrap();
+}
+
+/// Test that a panicking handler is answered with an error and the session carries on
+#[tokio::test]
+async fn test_panicking_handlers_do_not_end_the_session() {
+    use tokio::io::BufReader;
+    use wayfinder_core::dap::transport::DapTransport;
+    use wayfinder_core::runtime::mock::MockRuntime;
+
+    let (client, server_end) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut client = DapTransport::new(BufReader::new(client_read), client_write);
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+
+    let runtime = MockRuntime::new();
+    runtime.panic_on_next_modules("module table is corrupt");
+    let server_task = tokio::spawn(async move {
+        let mut server: DapServer<MockRuntime> = DapServer::new();
+        server.set_runtime(runtime);
+        server.run_event_loop(&mut transport).await.unwrap();
+    });
+
+    let mut responses = Vec::new();
+    let mut panic_output = None;
+    for (seq, command) in [(1, "launch"), (2, "modules"), (3, "modules")] {
+        let arguments = json!({ "program": "/game/main.lua" });
+        client
+            .write_message(&json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments }))
+            .await
+            .unwrap();
+        loop {
+            let message = client.read_message().await.unwrap().unwrap();
+            if message["event"] == "output" && message["body"]["category"] == "stderr" {
+                panic_output = message["body"]["output"].as_str().map(str::to_string);
+            }
+            if message["type"] == "response" {
+                responses.push(message);
+                break;
+            }
+        }
+    }
+
+    assert_eq!(responses[0]["success"], true);
+    assert_eq!(responses[1]["success"], false);
+    assert_eq!(responses[1]["body"]["error"]["id"], -32603);
+    assert_eq!(responses[1]["message"], "Internal error handling modules: module table is corrupt");
+    assert_eq!(responses[2]["success"], true, "{}", responses[2]);
+
+    // The panic follows its response, with the backtrace, in the debug console
+    let panic_output = panic_output.expect("the panic is shown in the debug console");
+    assert!(panic_output.starts_with("Wayfinder panicked at "), "{}", panic_output);
+    assert!(panic_output.contains("module table is corrupt"));
+
+    client
+        .write_message(&json!({ "seq": 4, "type": "request", "command": "disconnect" }))
+        .await
+        .unwrap();
+    server_task.await.unwrap();
+}
+
+/// Test that a shutdown from outside the loop sends what was raised and ends the session
+#[tokio::test]
+async fn test_shutdown_trigger_ends_the_session() {
+    use tokio::io::BufReader;
+    use wayfinder_core::dap::transport::DapTransport;
+    use wayfinder_core::runtime::mock::MockRuntime;
+
+    let (client, server_end) = tokio::io::duplex(4096);
+    let (client_read, client_write) = tokio::io::split(client);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    let mut client = DapTransport::new(BufReader::new(client_read), client_write);
+    let mut transport = DapTransport::new(BufReader::new(server_read), server_write);
+
+    let mut server: DapServer<MockRuntime> = DapServer::new();
+    server.set_runtime(MockRuntime::new());
+    let shutdown = server.shutdown_trigger();
+    let server_task = tokio::spawn(async move {
+        server.run_event_loop(&mut transport).await.unwrap();
+    });
+
+    client
+        .write_message(&json!({ "seq": 1, "type": "request", "command": "launch", "arguments": { "program": "/game/main.lua" } }))
+        .await
+        .unwrap();
+    loop {
+        if client.read_message().await.unwrap().unwrap()["type"] == "response" {
+            break;
+        }
+    }
+
+    shutdown.notify_one();
+    loop {
+        let message = client.read_message().await.unwrap().unwrap();
+        assert_ne!(message["type"], "response", "{}", message);
+        if message["event"] == "terminated" {
+            break;
+        }
+    }
+    // The loop returns without waiting for a disconnect
+    server_task.await.unwrap();
+}
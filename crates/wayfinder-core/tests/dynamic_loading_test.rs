@@ -67,6 +67,36 @@ mod dynamic_loading_tests {
         }
     }
 
+    #[test]
+    fn test_library_override_must_be_the_asked_version() {
+        use std::path::Path;
+
+        // The name tells the version, so nothing is opened
+        let error = LuaLibrary::load_override(Path::new("/nonexistent/liblua5.1.so"), Some(LuaVersion::V54))
+            .err()
+            .expect("a Lua 5.1 library cannot stand in for Lua 5.4");
+        assert!(error.to_string().contains("is Lua 5.1, not Lua 5.4"), "{}", error);
+
+        let error = LuaLibrary::load_override(Path::new("/nonexistent/liblua5.4.so"), None).err().unwrap();
+        assert!(error.to_string().contains("/nonexistent/liblua5.4.so"), "{}", error);
+    }
+
+    #[cfg(feature = "embedded-lua")]
+    #[test]
+    fn test_embedded_lua_runs() {
+        let lib = LuaLibrary::load_embedded().expect("the linked Lua 5.4 should load");
+        assert_eq!((lib.version(), lib.path()), (LuaVersion::V54, None));
+
+        unsafe {
+            let state = lib.lual_newstate();
+            lib.lual_openlibs(state);
+            assert_eq!(lib.lual_loadstring(state, b"return #_VERSION + math.floor(2.5)\0".as_ptr() as *const i8), 0);
+            assert_eq!(lib.lua_pcall(state, 0, 1, 0), 0);
+            assert_eq!(lib.lua_tointeger(state, -1), 9);
+            lib.lua_close(state);
+        }
+    }
+
     #[test]
     fn test_create_lua_state_all_versions() {
         let versions = [
@@ -225,6 +255,25 @@ mod dynamic_loading_tests {
     }
 }
 
+#[cfg(all(windows, feature = "dynamic-lua"))]
+mod windows_loading_tests {
+    use wayfinder_core::runtime::lua_loader::LuaLibrary;
+    use wayfinder_core::runtime::LuaVersion;
+
+    #[test]
+    fn test_windows_search_tries_msvc_and_mingw_names() {
+        // CI runners without Lua installed still see every name tried
+        match LuaLibrary::load(LuaVersion::V54) {
+            Ok(lib) => assert_eq!(lib.version(), LuaVersion::V54),
+            Err(e) => {
+                let message = e.to_string();
+                assert!(message.contains("lua54.dll"), "{}", message);
+                assert!(message.contains("liblua5.4.dll"), "{}", message);
+            }
+        }
+    }
+}
+
 #[cfg(feature = "static-lua")]
 mod static_mode_notice {
     #[test]
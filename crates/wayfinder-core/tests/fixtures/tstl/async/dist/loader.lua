@@ -0,0 +1,17 @@
+local ____lualib = require("lualib_bundle")
+local __TS__AsyncAwaiter = ____lualib.__TS__AsyncAwaiter
+local __TS__Await = ____lualib.__TS__Await
+local ____exports = {}
+local ____net = require("net")
+local fetchLevel = ____net.fetchLevel
+function ____exports.loadLevel(id)
+    return __TS__AsyncAwaiter(function(____awaiter_resolve)
+        local level = __TS__Await(fetchLevel(id))
+        if level == nil then
+            return ____awaiter_resolve(nil, 0)
+        end
+        return ____awaiter_resolve(nil, level.size)
+    end)
+end
+return ____exports
+--# sourceMappingURL=loader.lua.map
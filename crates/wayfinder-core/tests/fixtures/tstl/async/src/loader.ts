@@ -0,0 +1,9 @@
+import { fetchLevel } from "./net";
+
+export async function loadLevel(id: number): Promise<number> {
+    const level = await fetchLevel(id);
+    if (level === undefined) {
+        return 0;
+    }
+    return level.size;
+}
@@ -0,0 +1,41 @@
+local ____modules = {}
+local ____moduleCache = {}
+local ____originalRequire = require
+local function require(file, ...)
+    if ____moduleCache[file] then
+        return ____moduleCache[file].value
+    end
+    if ____modules[file] then
+        local module = ____modules[file]
+        ____moduleCache[file] = { value = module(file, ...) }
+        return ____moduleCache[file].value
+    else
+        if ____originalRequire then
+            return ____originalRequire(file)
+        else
+            error("module '" .. file .. "' not found")
+        end
+    end
+end
+____modules = {
+["util"] = function(...) 
+local ____exports = {}
+function ____exports.clamp(self, value, low, high)
+    return math.min(
+        math.max(value, low),
+        high
+    )
+end
+return ____exports
+ end,
+["main"] = function(...) 
+local ____exports = {}
+local ____util = require("util")
+local clamp = ____util.clamp
+local _24speed = clamp(nil, 12, 0, 10)
+print(_24speed)
+return ____exports
+ end,
+}
+return require("main", ...)
+--# sourceMappingURL=bundle.lua.map
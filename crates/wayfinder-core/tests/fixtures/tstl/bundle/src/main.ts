@@ -0,0 +1,4 @@
+import { clamp } from "./util";
+
+const $speed = clamp(12, 0, 10);
+print($speed);
@@ -0,0 +1,21 @@
+local ____lualib = require("lualib_bundle")
+local __TS__Class = ____lualib.__TS__Class
+local ____exports = {}
+____exports.Player = __TS__Class()
+local Player = ____exports.Player
+Player.name = "Player"
+function Player.prototype.____constructor(self, name)
+    self.name = name
+    self.hp = 100
+end
+function Player.prototype.hit(self, damage)
+    self.hp = self.hp - damage
+    return self.hp <= 0
+end
+function Player.prototype.stop(self)
+    local ____end = self.hp
+    self.hp = 0
+    return ____end
+end
+return ____exports
+--# sourceMappingURL=player.lua.map
@@ -0,0 +1,15 @@
+export class Player {
+    private hp = 100;
+    constructor(public name: string) {}
+
+    hit(damage: number): boolean {
+        this.hp -= damage;
+        return this.hp <= 0;
+    }
+
+    stop(): number {
+        const end = this.hp;
+        this.hp = 0;
+        return end;
+    }
+}
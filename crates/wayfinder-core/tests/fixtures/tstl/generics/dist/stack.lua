@@ -0,0 +1,21 @@
+local ____lualib = require("lualib_bundle")
+local __TS__Class = ____lualib.__TS__Class
+local ____exports = {}
+____exports.Stack = __TS__Class()
+local Stack = ____exports.Stack
+Stack.name = "Stack"
+function Stack.prototype.____constructor(self)
+    self.items = {}
+end
+function Stack.prototype.push(self, item)
+    local ____self_items_0 = self.items
+    ____self_items_0[#____self_items_0 + 1] = item
+end
+function Stack.prototype.peek(self)
+    return self.items[#self.items]
+end
+function ____exports.first(self, list)
+    return list[1]
+end
+return ____exports
+--# sourceMappingURL=stack.lua.map
@@ -0,0 +1,13 @@
+--[[ Generated with https://github.com/TypeScriptToLua/TypeScriptToLua ]]
+require("lualib_bundle");
+local ____exports = {}
+function ____exports.countOf(self, items, name)
+    for ____, item in ipairs(items) do
+        if item.name == name then
+            return item.count
+        end
+    end
+    return 0
+end
+return ____exports
+--# sourceMappingURL=data:application/json;base64,eyJ2ZXJzaW9uIjozLCJzb3VyY2VSb290IjoiLi4vc3JjLyIsInNvdXJjZXMiOlsiaW52ZW50b3J5LnRzIl0sIm5hbWVzIjpbImNvdW50T2YiLCJpdGVtcyIsIm5hbWUiLCJpdGVtIl0sIm1hcHBpbmdzIjoiOzs7QUFFQSxxQkFBZ0JBLGNBQVFDLE9BQWVDO0lBQ25DLFVBQVdDO1FBQ1A7WUFDSTtRQUNKO0lBQ0o7SUFDQTtBQUNKIn0=
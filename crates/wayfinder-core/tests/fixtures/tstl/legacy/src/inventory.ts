@@ -0,0 +1,10 @@
+interface Item { name: string; count: number }
+
+export function countOf(items: Item[], name: string): number {
+    for (const item of items) {
+        if (item.name === name) {
+            return item.count;
+        }
+    }
+    return 0;
+}
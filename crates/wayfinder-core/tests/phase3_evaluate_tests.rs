@@ -20,6 +20,7 @@ fn test_custom_config() {
         evaluate_mutation: true,
         show_modifications: false,
         eval_safety: EvalSafety::Strict,
+        ..Default::default()
     };
 
     assert!(config.evaluate_mutation);
@@ -59,6 +60,7 @@ fn test_mutation_enabled_config() {
         evaluate_mutation: true,
         show_modifications: true,
         eval_safety: EvalSafety::Basic,
+        ..Default::default()
     };
 
     assert!(config.evaluate_mutation);
@@ -93,6 +93,7 @@ fn test_configuration_integration() {
         evaluate_mutation: true,
         show_modifications: true,
         eval_safety: EvalSafety::Basic,
+        ..Default::default()
     };
     
     assert!(config.evaluate_mutation);
@@ -123,6 +124,7 @@ async fn test_session_phase3_integration() {
         evaluate_mutation: true,
         show_modifications: false,
         eval_safety: EvalSafety::Strict,
+        ..Default::default()
     };
     session.set_config(new_config);
     let config = session.config();
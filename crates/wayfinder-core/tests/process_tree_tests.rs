@@ -0,0 +1,56 @@
+//! Stopping a debuggee together with the processes it started
+//!
+//! Each test starts a shell that leaves a long-running child holding its
+//! stdout. Only when the whole tree is killed does stdout reach its end.
+
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use wayfinder_core::session::process_tree;
+
+/// A shell that starts a child which outlives it by far, sharing its stdout
+fn shell_with_child() -> Command {
+    #[cfg(unix)]
+    {
+        let mut command = Command::new("sh");
+        command.args(["-c", "sleep 30 & wait"]);
+        command
+    }
+    #[cfg(windows)]
+    {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "ping -n 30 127.0.0.1 > NUL & ping -n 30 127.0.0.1 > NUL"]);
+        command
+    }
+}
+
+#[cfg(any(unix, windows))]
+#[tokio::test]
+async fn test_kill_tree_ends_the_children_too() {
+    let mut command = shell_with_child();
+    command.stdout(Stdio::piped()).stdin(Stdio::null());
+    process_tree::spawn_as_group(&mut command);
+    let mut child = command.spawn().expect("the shell should start");
+    let mut stdout = child.stdout.take().unwrap();
+
+    // Give the shell time to start its child
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    process_tree::kill_tree(&mut child).await.unwrap();
+
+    let mut rest = Vec::new();
+    let closed = tokio::time::timeout(Duration::from_secs(10), stdout.read_to_end(&mut rest)).await;
+    assert!(closed.is_ok(), "a child of the killed process still holds its stdout");
+}
+
+#[cfg(any(unix, windows))]
+#[tokio::test]
+async fn test_kill_tree_stops_a_process_without_a_group() {
+    let mut command = shell_with_child();
+    command.stdout(Stdio::null()).stdin(Stdio::null());
+    let mut child = command.spawn().expect("the shell should start");
+
+    process_tree::kill_tree(&mut child).await.unwrap();
+    let status = tokio::time::timeout(Duration::from_secs(10), child.wait()).await;
+    assert!(status.is_ok_and(|status| status.is_ok_and(|status| !status.success())));
+}
@@ -0,0 +1,139 @@
+//! Real TSTL output run through the source map and expression translators
+//!
+//! `tests/fixtures/tstl` holds what TypeScriptToLua generates for common
+//! code, with the maps it wrote: a class, an async function, generics, a
+//! `luaBundle` of two modules, and the output of a 0.x release with its map
+//! inlined under a `sourceRoot`. Each case checks that lines map both ways
+//! and which names TSTL changed, so heuristics in the translators cannot
+//! drift from what TSTL actually emits.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use wayfinder_core::debug::source_maps::SourceMapStore;
+use wayfinder_core::debug::ts_expressions;
+
+fn fixture(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tstl").join(path)
+}
+
+/// Checks that each TypeScript line goes to its Lua line and back
+fn assert_round_trips(store: &mut SourceMapStore, lua_file: &Path, source: &Path, lines: &[(u32, u32)]) {
+    for &(ts_line, lua_line) in lines {
+        let (file, generated) = store
+            .to_generated(source, ts_line, 1)
+            .unwrap_or_else(|| panic!("{}:{} has no Lua", source.display(), ts_line));
+        assert_eq!((file.as_path(), generated.line), (lua_file, lua_line), "{}:{}", source.display(), ts_line);
+
+        let original = store
+            .to_original(lua_file, lua_line, 1)
+            .unwrap_or_else(|| panic!("{}:{} has no source", lua_file.display(), lua_line));
+        assert_eq!((original.source.as_path(), original.line), (source, ts_line), "{}:{}", lua_file.display(), lua_line);
+    }
+}
+
+fn translate(expression: &str, renames: &HashMap<String, String>) -> String {
+    ts_expressions::translate(expression, renames).unwrap_or_else(|| expression.to_string())
+}
+
+#[test]
+fn test_class() {
+    let (lua_file, source) = (fixture("classes/dist/player.lua"), fixture("classes/src/player.ts"));
+    let mut store = SourceMapStore::new();
+    store.load_dir(&fixture("classes"));
+
+    // The class, a field initializer moved into the constructor, and each method
+    assert_round_trips(&mut store, &lua_file, &source, &[(1, 4), (2, 9), (3, 7), (5, 11), (6, 12), (7, 13), (11, 16), (13, 18)]);
+    assert_eq!(store.to_generated(&source, 4, 1), None);
+    let local = store.to_original(&lua_file, 16, 11).unwrap();
+    assert_eq!((local.line, local.column, local.name.as_deref()), (11, 15, Some("end")));
+
+    let renames = store.renamed_identifiers(&lua_file);
+    assert_eq!(renames, HashMap::from([("end".to_string(), "____end".to_string())]));
+    assert_eq!(translate("end * 2", &renames), "____end * 2");
+    assert_eq!(translate("this.hp <= 0 && this.name !== null", &renames), "self.hp <= 0 and self.name ~= nil");
+}
+
+#[test]
+fn test_async_function() {
+    let (lua_file, source) = (fixture("async/dist/loader.lua"), fixture("async/src/loader.ts"));
+    let mut store = SourceMapStore::new();
+    store.load_dir(&fixture("async"));
+
+    assert_round_trips(&mut store, &lua_file, &source, &[(1, 5), (3, 7), (4, 9), (5, 10), (6, 11), (8, 13), (9, 14)]);
+    // The awaiter wrapping the body belongs to the function's declaration
+    assert_eq!(store.to_original(&lua_file, 8, 5).map(|original| original.line), Some(3));
+
+    let renames = store.renamed_identifiers(&lua_file);
+    assert!(renames.is_empty());
+    assert_eq!(translate("level?.size", &renames), "(level and level.size)");
+    assert_eq!(translate("level === undefined", &renames), "level == nil");
+}
+
+#[test]
+fn test_generics() {
+    let (lua_file, source) = (fixture("generics/dist/stack.lua"), fixture("generics/src/stack.ts"));
+    let mut store = SourceMapStore::new();
+    store.load_dir(&fixture("generics"));
+
+    assert_round_trips(&mut store, &lua_file, &source, &[(1, 4), (2, 8), (4, 10), (5, 11), (9, 15), (14, 18)]);
+    // `push` compiles to two Lua lines, both from the same TypeScript line
+    assert_eq!(store.to_original(&lua_file, 11, 1).map(|original| original.line), Some(5));
+    assert_eq!(store.to_original(&lua_file, 12, 1).map(|original| original.line), Some(5));
+
+    let renames = store.renamed_identifiers(&lua_file);
+    assert!(renames.is_empty());
+    assert_eq!(translate("list[0]", &renames), "list[1]");
+    assert_eq!(translate("this.items[0]", &renames), "self.items[1]");
+}
+
+#[test]
+fn test_bundle_of_modules() {
+    let lua_file = fixture("bundle/dist/bundle.lua");
+    let (main, util) = (fixture("bundle/src/main.ts"), fixture("bundle/src/util.ts"));
+    let mut store = SourceMapStore::new();
+    let map = store.for_lua_file(&lua_file).unwrap().unwrap();
+    assert_eq!(map.sources(), [util.clone(), main.clone()]);
+
+    assert_round_trips(&mut store, &lua_file, &util, &[(1, 23), (2, 24), (3, 28)]);
+    assert_round_trips(&mut store, &lua_file, &main, &[(1, 33), (3, 35), (4, 36)]);
+    // The bundle's module loader has no source
+    assert_eq!(store.to_original(&lua_file, 5, 1), None);
+    let argument = store.to_original(&lua_file, 26, 9).unwrap();
+    assert_eq!((argument.source, argument.line, argument.column), (util, 2, 43));
+
+    let renames = store.renamed_identifiers(&lua_file);
+    assert_eq!(renames, HashMap::from([("$speed".to_string(), "_24speed".to_string())]));
+    assert_eq!(translate("$speed * 2", &renames), "_24speed * 2");
+}
+
+#[test]
+fn test_legacy_inline_map_with_source_root() {
+    let (lua_file, source) = (fixture("legacy/out/inventory.lua"), fixture("legacy/src/inventory.ts"));
+    let mut store = SourceMapStore::new();
+    store.load_dir(&fixture("legacy"));
+
+    assert_round_trips(&mut store, &lua_file, &source, &[(3, 4), (4, 5), (5, 6), (6, 7), (9, 10), (10, 11)]);
+    // Types compile to nothing
+    assert_eq!(store.to_generated(&source, 1, 1), None);
+
+    let renames = store.renamed_identifiers(&lua_file);
+    assert!(renames.is_empty());
+    assert_eq!(translate("item.name === name", &renames), "item.name == name");
+}
+
+#[test]
+fn test_sources_find_their_lua_files() {
+    let mut store = SourceMapStore::new();
+    store.load_dir(&fixture(""));
+
+    for (source, lua_file) in [
+        ("classes/src/player.ts", "classes/dist/player.lua"),
+        ("async/src/loader.ts", "async/dist/loader.lua"),
+        ("generics/src/stack.ts", "generics/dist/stack.lua"),
+        ("bundle/src/main.ts", "bundle/dist/bundle.lua"),
+        ("bundle/src/util.ts", "bundle/dist/bundle.lua"),
+        ("legacy/src/inventory.ts", "legacy/out/inventory.lua"),
+    ] {
+        assert_eq!(store.lua_file_for(&fixture(source)), Some(fixture(lua_file)), "{}", source);
+    }
+}
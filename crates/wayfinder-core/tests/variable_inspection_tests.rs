@@ -80,6 +80,7 @@ fn test_variable_value_representation() {
         variables_reference: None,
         named_variables: None,
         indexed_variables: None,
+        memory_reference: None,
     };
     assert_eq!(nil_var.value, "nil");
     assert_eq!(nil_var.type_, "nil");
@@ -92,6 +93,7 @@ fn test_variable_value_representation() {
         variables_reference: None,
         named_variables: None,
         indexed_variables: None,
+        memory_reference: None,
     };
     assert_eq!(bool_var.value, "true");
     assert_eq!(bool_var.type_, "boolean");
@@ -104,6 +106,7 @@ fn test_variable_value_representation() {
         variables_reference: None,
         named_variables: None,
         indexed_variables: None,
+        memory_reference: None,
     };
     assert_eq!(num_var.value, "3.14");
     assert_eq!(num_var.type_, "number");
@@ -116,6 +119,7 @@ fn test_variable_value_representation() {
         variables_reference: None,
         named_variables: None,
         indexed_variables: None,
+        memory_reference: None,
     };
     assert_eq!(str_var.value, "\"hello\"");
     assert_eq!(str_var.type_, "string");
@@ -128,6 +132,7 @@ fn test_variable_value_representation() {
         variables_reference: Some(123),
         named_variables: Some(3),
         indexed_variables: Some(0),
+        memory_reference: None,
     };
     assert_eq!(table_var.value, "table (3 elements)");
     assert_eq!(table_var.type_, "table");